@@ -0,0 +1,62 @@
+//! Decodes sharded bitmap index entries the way `Reader::read_bitmap_index`
+//! does, once with a fresh `Vec` per shard and once with a pooled scratch
+//! buffer, and prints the time per shard of each.
+//!
+//! ```text
+//! cargo bench --bench buf_pool
+//! ```
+
+use ethers_db::{
+    bitmap::{decode_roaring, decode_roaring_into, encode_roaring},
+    pool::BufPool,
+};
+use std::{hint::black_box, time::Instant};
+
+static SHARD_BUFS: BufPool<u32> = BufPool::new(16, 1 << 16);
+
+const SHARDS: u32 = 64;
+const CALLS: usize = 200;
+const RUNS: usize = 5;
+
+/// Returns the best time per shard of `RUNS` runs of `CALLS` lookups, in ns.
+fn bench<F: FnMut(&[Vec<u8>]) -> usize>(shards: &[Vec<u8>], mut lookup: F) -> f64 {
+    (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            for _ in 0..CALLS {
+                black_box(lookup(black_box(shards)));
+            }
+            start.elapsed().as_nanos() as f64 / (CALLS * shards.len()) as f64
+        })
+        .fold(f64::INFINITY, f64::min)
+}
+
+fn main() {
+    // shards of a busy address's index: a few thousand blocks each, mostly
+    // in array containers
+    let shards: Vec<Vec<u8>> = (0..SHARDS)
+        .map(|i| {
+            let base = i * 100_000;
+            encode_roaring(&(0..2_000).map(|j| base + 37 * j).collect::<Vec<_>>())
+        })
+        .collect();
+
+    let fresh = bench(&shards, |shards| {
+        let mut out = vec![];
+        for shard in shards {
+            out.extend(decode_roaring(shard).unwrap());
+        }
+        out.len()
+    });
+    let pooled = bench(&shards, |shards| {
+        let mut out = vec![];
+        let mut shard = SHARD_BUFS.get();
+        for v in shards {
+            decode_roaring_into(v, &mut shard).unwrap();
+            out.extend(shard.drain(..));
+        }
+        out.len()
+    });
+    println!("fresh vec per shard: {:>8.0} ns/shard", fresh);
+    println!("pooled shard buffer: {:>8.0} ns/shard", pooled);
+}
@@ -7,16 +7,23 @@ import "C"
 import "runtime/cgo"
 import (
 	"context"
+	"encoding/binary"
+	"math/big"
+	"path/filepath"
 	// llog "log"
 
 	"github.com/holiman/uint256"
+	"github.com/ledgerwatch/erigon-lib/compress"
 	"github.com/ledgerwatch/erigon-lib/kv"
 	"github.com/ledgerwatch/erigon-lib/kv/mdbx"
+	"github.com/ledgerwatch/erigon-lib/recsplit"
 	"github.com/ledgerwatch/erigon/common"
 	"github.com/ledgerwatch/erigon/core/rawdb"
 	"github.com/ledgerwatch/erigon/core/state"
 	"github.com/ledgerwatch/erigon/core/types"
 	"github.com/ledgerwatch/erigon/core/types/accounts"
+	"github.com/ledgerwatch/erigon/crypto"
+	"github.com/ledgerwatch/erigon/eth/stagedsync/stages"
 	"github.com/ledgerwatch/erigon/rlp"
 	"github.com/ledgerwatch/log/v3"
 )
@@ -243,6 +250,53 @@ func PutStorage(dbPtr C.uintptr_t, address []byte, key []byte, val []byte) (exit
 	return 1
 }
 
+// Stores code under its hash, and the hash as the code of the address's
+// incarnation
+//export PutCode
+func PutCode(dbPtr C.uintptr_t, address []byte, incarnation uint64, code []byte) (exit int) {
+	db := cgo.Handle(dbPtr).Value().(kv.RwDB)
+
+	tx, closer, err := begin(db)
+	if err != nil {
+		log.Error("tx begin", err)
+		return -1
+	}
+	defer closer(&err)
+
+	w := state.NewPlainStateWriterNoHistory(tx)
+	code = common.CopyBytes(code)
+	err = w.UpdateAccountCode(common.BytesToAddress(address), incarnation, crypto.Keccak256Hash(code), code)
+	if err != nil {
+		log.Error("UpdateAccountCode", err)
+		return -1
+	}
+
+	return 1
+}
+
+// Deletes the account as SELFDESTRUCT does, recording incarnation in the
+// IncarnationMap. Its storage is left under the old incarnation.
+//export DeleteAccount
+func DeleteAccount(dbPtr C.uintptr_t, address []byte, incarnation uint64) (exit int) {
+	db := cgo.Handle(dbPtr).Value().(kv.RwDB)
+
+	tx, closer, err := begin(db)
+	if err != nil {
+		log.Error("tx begin", err)
+		return -1
+	}
+	defer closer(&err)
+
+	w := state.NewPlainStateWriterNoHistory(tx)
+	err = w.DeleteAccount(common.BytesToAddress(address), &accounts.Account{Incarnation: incarnation})
+	if err != nil {
+		log.Error("DeleteAccount", err)
+		return -1
+	}
+
+	return 1
+}
+
 //export PutHeadHeaderHash
 func PutHeadHeaderHash(dbPtr C.uintptr_t, hash []byte) (exit int) {
 	db := cgo.Handle(dbPtr).Value().(kv.RwDB)
@@ -329,6 +383,494 @@ func PutCanonicalHash(dbPtr C.uintptr_t, hash []byte, num uint64) (exit int) {
 	return 1
 }
 
+// config is the json encoded chain config, stored under the genesis hash
+//export PutChainConfig
+func PutChainConfig(dbPtr C.uintptr_t, genesis []byte, config []byte) (exit int) {
+	db := cgo.Handle(dbPtr).Value().(kv.RwDB)
+
+	tx, closer, err := begin(db)
+	if err != nil {
+		log.Error("tx begin", err)
+		return -1
+	}
+	defer closer(&err)
+
+	if err = tx.Put(kv.ConfigTable, common.CopyBytes(genesis), common.CopyBytes(config)); err != nil {
+		log.Error("failed to store Config entry", "err", err)
+		return -1
+	}
+
+	return 1
+}
+
+//export PutStageProgress
+func PutStageProgress(dbPtr C.uintptr_t, stage string, num uint64) (exit int) {
+	db := cgo.Handle(dbPtr).Value().(kv.RwDB)
+
+	tx, closer, err := begin(db)
+	if err != nil {
+		log.Error("tx begin", err)
+		return -1
+	}
+	defer closer(&err)
+
+	err = stages.SaveStageProgress(tx, stages.SyncStage(stage), num)
+	if err != nil {
+		log.Error("SaveStageProgress", err)
+		return -1
+	}
+
+	return 1
+}
+
+//export PutForkchoice
+func PutForkchoice(dbPtr C.uintptr_t, safe []byte, finalized []byte) (exit int) {
+	db := cgo.Handle(dbPtr).Value().(kv.RwDB)
+
+	tx, closer, err := begin(db)
+	if err != nil {
+		log.Error("tx begin", err)
+		return -1
+	}
+	defer closer(&err)
+
+	err = rawdb.WriteForkchoiceSafe(tx, common.BytesToHash(safe))
+	if err != nil {
+		log.Error("WriteForkchoiceSafe", err)
+		return -1
+	}
+
+	err = rawdb.WriteForkchoiceFinalized(tx, common.BytesToHash(finalized))
+	if err != nil {
+		log.Error("WriteForkchoiceFinalized", err)
+		return -1
+	}
+
+	return 1
+}
+
+//export PutIssuance
+func PutIssuance(dbPtr C.uintptr_t, num uint64, totalIssued []byte, totalBurnt []byte) (exit int) {
+	db := cgo.Handle(dbPtr).Value().(kv.RwDB)
+
+	tx, closer, err := begin(db)
+	if err != nil {
+		log.Error("tx begin", err)
+		return -1
+	}
+	defer closer(&err)
+
+	err = rawdb.WriteTotalIssued(tx, num, new(big.Int).SetBytes(totalIssued))
+	if err != nil {
+		log.Error("WriteTotalIssued", err)
+		return -1
+	}
+
+	err = rawdb.WriteTotalBurnt(tx, num, new(big.Int).SetBytes(totalBurnt))
+	if err != nil {
+		log.Error("WriteTotalBurnt", err)
+		return -1
+	}
+
+	return 1
+}
+
+// receipts is the cbor encoded list of the block's receipts
+//export PutReceipts
+func PutReceipts(dbPtr C.uintptr_t, num uint64, receipts []byte) (exit int) {
+	db := cgo.Handle(dbPtr).Value().(kv.RwDB)
+
+	tx, closer, err := begin(db)
+	if err != nil {
+		log.Error("tx begin", err)
+		return -1
+	}
+	defer closer(&err)
+
+	key := make([]byte, 8)
+	binary.BigEndian.PutUint64(key, num)
+	if err = tx.Put(kv.Receipts, key, receipts); err != nil {
+		log.Error("failed to store Receipts entry", "err", err)
+		return -1
+	}
+
+	return 1
+}
+
+// receipt is the rlp encoded receipt of the block's state-sync tx, which is
+// indexed under txHash
+//export PutBorReceipt
+func PutBorReceipt(dbPtr C.uintptr_t, num uint64, txHash []byte, receipt []byte) (exit int) {
+	db := cgo.Handle(dbPtr).Value().(kv.RwDB)
+
+	tx, closer, err := begin(db)
+	if err != nil {
+		log.Error("tx begin", err)
+		return -1
+	}
+	defer closer(&err)
+
+	key := make([]byte, 8)
+	binary.BigEndian.PutUint64(key, num)
+	if err = tx.Put(kv.BorReceipts, key, receipt); err != nil {
+		log.Error("failed to store BorReceipts entry", "err", err)
+		return -1
+	}
+	if err = tx.Put(kv.BorTxLookup, txHash, new(big.Int).SetUint64(num).Bytes()); err != nil {
+		log.Error("failed to store BorTxLookup entry", "err", err)
+		return -1
+	}
+
+	return 1
+}
+
+// proof is the AuRa epoch transition proof of the block, written to the
+// PendingEpoch table if pending, and to the Epoch table otherwise
+//export PutEpoch
+func PutEpoch(dbPtr C.uintptr_t, num uint64, hash []byte, proof []byte, pending bool) (exit int) {
+	db := cgo.Handle(dbPtr).Value().(kv.RwDB)
+
+	tx, closer, err := begin(db)
+	if err != nil {
+		log.Error("tx begin", err)
+		return -1
+	}
+	defer closer(&err)
+
+	table := kv.Epoch
+	if pending {
+		table = kv.PendingEpoch
+	}
+	key := make([]byte, 8, 40)
+	binary.BigEndian.PutUint64(key, num)
+	key = append(key, hash...)
+	if err = tx.Put(table, key, proof); err != nil {
+		log.Error("failed to store epoch", "table", table, "err", err)
+		return -1
+	}
+
+	return 1
+}
+
+// logs is the cbor encoded list of the logs emitted by one transaction
+//export PutLogs
+func PutLogs(dbPtr C.uintptr_t, num uint64, txIdx uint32, logs []byte) (exit int) {
+	db := cgo.Handle(dbPtr).Value().(kv.RwDB)
+
+	tx, closer, err := begin(db)
+	if err != nil {
+		log.Error("tx begin", err)
+		return -1
+	}
+	defer closer(&err)
+
+	key := make([]byte, 12)
+	binary.BigEndian.PutUint64(key, num)
+	binary.BigEndian.PutUint32(key[8:], txIdx)
+	if err = tx.Put(kv.Log, key, logs); err != nil {
+		log.Error("failed to store Log entry", "err", err)
+		return -1
+	}
+
+	return 1
+}
+
+// traces are the block's CallTraceSet values, each an address followed by a
+// flags byte
+//export PutCallTraces
+func PutCallTraces(dbPtr C.uintptr_t, num uint64, traces [][]byte) (exit int) {
+	db := cgo.Handle(dbPtr).Value().(kv.RwDB)
+
+	tx, closer, err := begin(db)
+	if err != nil {
+		log.Error("tx begin", err)
+		return -1
+	}
+	defer closer(&err)
+
+	key := make([]byte, 8)
+	binary.BigEndian.PutUint64(key, num)
+	for _, trace := range traces {
+		if err = tx.Put(kv.CallTraceSet, key, trace); err != nil {
+			log.Error("failed to store CallTraceSet entry", "err", err)
+			return -1
+		}
+	}
+
+	return 1
+}
+
+// acct is the account before block num in Erigon's storage encoding, empty if
+// the account didn't exist
+//export PutAccountChange
+func PutAccountChange(dbPtr C.uintptr_t, num uint64, address []byte, acct []byte) (exit int) {
+	db := cgo.Handle(dbPtr).Value().(kv.RwDB)
+
+	tx, closer, err := begin(db)
+	if err != nil {
+		log.Error("tx begin", err)
+		return -1
+	}
+	defer closer(&err)
+
+	key := make([]byte, 8)
+	binary.BigEndian.PutUint64(key, num)
+	val := append(common.CopyBytes(address), acct...)
+	if err = tx.Put(kv.AccountChangeSet, key, val); err != nil {
+		log.Error("failed to store AccountChangeSet entry", "err", err)
+		return -1
+	}
+
+	return 1
+}
+
+// val is the value of the slot before block num, zero if it wasn't set
+//export PutStorageChange
+func PutStorageChange(dbPtr C.uintptr_t, num uint64, address []byte, incarnation uint64, slot []byte, val []byte) (exit int) {
+	db := cgo.Handle(dbPtr).Value().(kv.RwDB)
+
+	tx, closer, err := begin(db)
+	if err != nil {
+		log.Error("tx begin", err)
+		return -1
+	}
+	defer closer(&err)
+
+	key := make([]byte, 8+20+8)
+	binary.BigEndian.PutUint64(key, num)
+	copy(key[8:], address)
+	binary.BigEndian.PutUint64(key[28:], incarnation)
+	v := append(common.CopyBytes(slot), new(uint256.Int).SetBytes(val).Bytes()...)
+	if err = tx.Put(kv.StorageChangeSet, key, v); err != nil {
+		log.Error("failed to store StorageChangeSet entry", "err", err)
+		return -1
+	}
+
+	return 1
+}
+
+// bitmap is the roaring64 encoded set of blocks in which the account changed,
+// stored as the address's last shard
+//export PutAccountHistory
+func PutAccountHistory(dbPtr C.uintptr_t, address []byte, bitmap []byte) (exit int) {
+	db := cgo.Handle(dbPtr).Value().(kv.RwDB)
+
+	tx, closer, err := begin(db)
+	if err != nil {
+		log.Error("tx begin", err)
+		return -1
+	}
+	defer closer(&err)
+
+	key := make([]byte, len(address)+8)
+	copy(key, address)
+	binary.BigEndian.PutUint64(key[len(address):], ^uint64(0))
+	if err = tx.Put(kv.AccountsHistory, key, bitmap); err != nil {
+		log.Error("failed to store AccountsHistory entry", "err", err)
+		return -1
+	}
+
+	return 1
+}
+
+// bitmap is the roaring64 encoded set of blocks in which the slot changed,
+// stored as the address and slot's last shard
+//export PutStorageHistory
+func PutStorageHistory(dbPtr C.uintptr_t, address []byte, slot []byte, bitmap []byte) (exit int) {
+	db := cgo.Handle(dbPtr).Value().(kv.RwDB)
+
+	tx, closer, err := begin(db)
+	if err != nil {
+		log.Error("tx begin", err)
+		return -1
+	}
+	defer closer(&err)
+
+	key := make([]byte, len(address)+len(slot)+8)
+	copy(key, address)
+	copy(key[len(address):], slot)
+	binary.BigEndian.PutUint64(key[len(address)+len(slot):], ^uint64(0))
+	if err = tx.Put(kv.StorageHistory, key, bitmap); err != nil {
+		log.Error("failed to store StorageHistory entry", "err", err)
+		return -1
+	}
+
+	return 1
+}
+
+//export PutLogAddressIndex
+func PutLogAddressIndex(dbPtr C.uintptr_t, address []byte, bitmap []byte) (exit int) {
+	return putLogIndex(dbPtr, kv.LogAddressIndex, address, bitmap)
+}
+
+//export PutLogTopicIndex
+func PutLogTopicIndex(dbPtr C.uintptr_t, topic []byte, bitmap []byte) (exit int) {
+	return putLogIndex(dbPtr, kv.LogTopicIndex, topic, bitmap)
+}
+
+// putLogIndex stores bitmap as the last shard of key in one of the 32-bit log
+// indices
+func putLogIndex(dbPtr C.uintptr_t, table string, k []byte, bitmap []byte) (exit int) {
+	db := cgo.Handle(dbPtr).Value().(kv.RwDB)
+
+	tx, closer, err := begin(db)
+	if err != nil {
+		log.Error("tx begin", err)
+		return -1
+	}
+	defer closer(&err)
+
+	key := make([]byte, len(k)+4)
+	copy(key, k)
+	binary.BigEndian.PutUint32(key[len(k):], ^uint32(0))
+	if err = tx.Put(table, key, bitmap); err != nil {
+		log.Error("failed to store log index entry", "table", table, "err", err)
+		return -1
+	}
+
+	return 1
+}
+
+// acct is the account in Erigon's storage encoding, stored under the keccak
+// of the address
+//export PutHashedAccount
+func PutHashedAccount(dbPtr C.uintptr_t, address []byte, acct []byte) (exit int) {
+	db := cgo.Handle(dbPtr).Value().(kv.RwDB)
+
+	tx, closer, err := begin(db)
+	if err != nil {
+		log.Error("tx begin", err)
+		return -1
+	}
+	defer closer(&err)
+
+	if err = tx.Put(kv.HashedAccounts, crypto.Keccak256(address), common.CopyBytes(acct)); err != nil {
+		log.Error("failed to store HashedAccount entry", "err", err)
+		return -1
+	}
+
+	return 1
+}
+
+// Stores the slot under keccak(address) ++ incarnation, with the value
+// prefixed by keccak(key) and stripped of leading zeros
+//export PutHashedStorage
+func PutHashedStorage(dbPtr C.uintptr_t, address []byte, incarnation uint64, key []byte, val []byte) (exit int) {
+	db := cgo.Handle(dbPtr).Value().(kv.RwDB)
+
+	tx, closer, err := begin(db)
+	if err != nil {
+		log.Error("tx begin", err)
+		return -1
+	}
+	defer closer(&err)
+
+	k := make([]byte, 40)
+	copy(k, crypto.Keccak256(address))
+	binary.BigEndian.PutUint64(k[32:], incarnation)
+	v := append(crypto.Keccak256(key), common.TrimLeftZeroes(common.CopyBytes(val))...)
+	if err = tx.Put(kv.HashedStorage, k, v); err != nil {
+		log.Error("failed to store HashedStorage entry", "err", err)
+		return -1
+	}
+
+	return 1
+}
+
+// Writes the snapshot segment `path` of `words`, compressed as Erigon does,
+// and the enumerated recsplit index beside it mapping `keys[i]` to the offset
+// of `words[i]`. The words are numbered from baseDataId, the first block or
+// tx number of the segment.
+//export WriteSegment
+func WriteSegment(path string, words [][]byte, keys [][]byte, baseDataId uint64) (exit int) {
+	ctx := context.Background()
+	tmpDir := filepath.Dir(path)
+	c, err := compress.NewCompressor(ctx, "WriteSegment", path, tmpDir, compress.MinPatternScore, 1)
+	if err != nil {
+		log.Error("NewCompressor", err)
+		return -1
+	}
+	defer c.Close()
+	for _, word := range words {
+		if err = c.AddWord(word); err != nil {
+			log.Error("AddWord", err)
+			return -1
+		}
+	}
+	if err = c.Compress(); err != nil {
+		log.Error("Compress", err)
+		return -1
+	}
+
+	d, err := compress.NewDecompressor(path)
+	if err != nil {
+		log.Error("NewDecompressor", err)
+		return -1
+	}
+	defer d.Close()
+	rs, err := newRecSplit(path, len(keys), true, baseDataId)
+	if err != nil {
+		log.Error("NewRecSplit", err)
+		return -1
+	}
+	defer rs.Close()
+	g := d.MakeGetter()
+	var word []byte
+	var offset uint64
+	for i := 0; g.HasNext(); i++ {
+		if err = rs.AddKey(keys[i], offset); err != nil {
+			log.Error("AddKey", err)
+			return -1
+		}
+		word, offset = g.Next(word[:0])
+	}
+	if err = rs.Build(); err != nil {
+		log.Error("Build", err)
+		return -1
+	}
+	return 1
+}
+
+// Writes the recsplit index `path` mapping each of `txHashes` to the block in
+// `blockNums` at the same position, as Erigon's transactions-to-block index
+// does.
+//export WriteTxToBlockIndex
+func WriteTxToBlockIndex(path string, txHashes [][]byte, blockNums []uint64, baseDataId uint64) (exit int) {
+	rs, err := newRecSplit(path, len(txHashes), false, baseDataId)
+	if err != nil {
+		log.Error("NewRecSplit", err)
+		return -1
+	}
+	defer rs.Close()
+	for i, hash := range txHashes {
+		if err = rs.AddKey(hash, blockNums[i]); err != nil {
+			log.Error("AddKey", err)
+			return -1
+		}
+	}
+	if err = rs.Build(); err != nil {
+		log.Error("Build", err)
+		return -1
+	}
+	return 1
+}
+
+// The recsplit parameters of Erigon's snapshot indices
+func newRecSplit(path string, keyCount int, enums bool, baseDataId uint64) (*recsplit.RecSplit, error) {
+	return recsplit.NewRecSplit(recsplit.RecSplitArgs{
+		KeyCount:   keyCount,
+		Enums:      enums,
+		BucketSize: 2000,
+		LeafSize:   8,
+		TmpDir:     filepath.Dir(path),
+		IndexFile:  path,
+		BaseDataID: baseDataId,
+		StartSeed: []uint64{0x106393c187cae21a, 0x6453cec3f7376937, 0x643e521ddbd2be98, 0x3740c6412f6572cb, 0x717d47562f1ce470, 0x4cd6eb4c63befb7c, 0x9bfd8c5e18c8da73,
+			0x082f20e10092a9a3, 0x2ada2ce68d21defc, 0xe33cb4f3e7c6466b, 0x3980be458c509c59, 0xc466fd9584828e8c, 0x45f0aabe1a61ede6, 0xf6e7b8b33ad9b98d,
+			0x4ef95e25f4b4983d, 0x81175195173b92d3, 0x4e50927d8dd15978, 0x1ea2099d1fafae7f, 0x425c8a06fbaaa815, 0xcd4216006c74052a},
+	})
+}
+
 func begin(db kv.RwDB) (tx kv.RwTx, closer func(*error), err error) {
 	ctx := context.Background()
 	tx, err = db.BeginRw(ctx)
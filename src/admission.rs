@@ -0,0 +1,183 @@
+//! Admission control for heavy queries. A client configured with an
+//! `AdmissionConfig` bounds the number of scans (`get_logs`, withdrawal totals,
+//! warming) running at once, so that a burst of them can't take every read
+//! slot and core from the point lookups served alongside them. Queries over
+//! the limit wait in a queue, and are rejected if the queue is full or they
+//! wait too long.
+
+use anyhow::Result;
+use std::{
+    sync::{Condvar, Mutex},
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+
+/// Returned (wrapped in an `anyhow::Error`) when a heavy query isn't admitted.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejected {
+    #[error("too many queries queued")]
+    QueueFull,
+    #[error("timed out waiting to run query")]
+    QueueTimeout,
+}
+
+/// How many heavy queries may run and wait at once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdmissionConfig {
+    /// The number of heavy queries which may run at once.
+    pub max_running: usize,
+    /// The number of heavy queries which may wait for a slot. `None` is unbounded.
+    pub max_queued: Option<usize>,
+    /// How long a query waits for a slot before it's rejected. `None` waits
+    /// indefinitely.
+    pub queue_timeout: Option<Duration>,
+}
+
+impl Default for AdmissionConfig {
+    fn default() -> Self {
+        Self {
+            max_running: 4,
+            max_queued: Some(64),
+            queue_timeout: Some(Duration::from_secs(10)),
+        }
+    }
+}
+
+/// A snapshot of the admission queue.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AdmissionMetrics {
+    pub running: usize,
+    pub queued: usize,
+    /// The number of queries rejected so far.
+    pub rejected: u64,
+}
+
+/// The slots shared by a client and its clones.
+#[derive(Debug)]
+pub struct Admission {
+    config: AdmissionConfig,
+    state: Mutex<AdmissionMetrics>,
+    freed: Condvar,
+}
+
+impl Admission {
+    pub fn new(config: AdmissionConfig) -> Self {
+        Self {
+            config,
+            state: Default::default(),
+            freed: Condvar::new(),
+        }
+    }
+
+    pub fn config(&self) -> AdmissionConfig {
+        self.config
+    }
+
+    pub fn metrics(&self) -> AdmissionMetrics {
+        *self.state.lock().unwrap()
+    }
+
+    /// Blocks until a slot is free, returning a permit which holds the slot
+    /// until it's dropped. The wait blocks the thread, so async callers run
+    /// admitted reads with `spawn_blocking`, as `DbMiddleware` does.
+    pub fn acquire(&self) -> Result<Permit<'_>> {
+        let max_running = self.config.max_running.max(1);
+        let mut state = self.state.lock().unwrap();
+        // queries only skip the queue if it's empty, so waiters are served first
+        if state.queued == 0 && state.running < max_running {
+            state.running += 1;
+            return Ok(Permit(self));
+        }
+        if self
+            .config
+            .max_queued
+            .map_or(false, |max| state.queued >= max)
+        {
+            state.rejected += 1;
+            return Err(Rejected::QueueFull.into());
+        }
+
+        let deadline = self.config.queue_timeout.map(|t| Instant::now() + t);
+        state.queued += 1;
+        while state.running >= max_running {
+            state = match deadline {
+                None => self.freed.wait(state).unwrap(),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        state.queued -= 1;
+                        state.rejected += 1;
+                        return Err(Rejected::QueueTimeout.into());
+                    }
+                    self.freed.wait_timeout(state, deadline - now).unwrap().0
+                }
+            };
+        }
+        state.queued -= 1;
+        state.running += 1;
+        Ok(Permit(self))
+    }
+}
+
+/// A running heavy query's slot, freed on drop.
+#[derive(Debug)]
+pub struct Permit<'a>(&'a Admission);
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        self.0.state.lock().unwrap().running -= 1;
+        self.0.freed.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn admission(max_queued: Option<usize>, queue_timeout: Option<Duration>) -> Admission {
+        Admission::new(AdmissionConfig {
+            max_running: 1,
+            max_queued,
+            queue_timeout,
+        })
+    }
+
+    #[test]
+    fn test_admission_rejects() {
+        let adm = admission(Some(0), None);
+        let permit = adm.acquire().unwrap();
+        let err = adm.acquire().unwrap_err();
+        assert_eq!(err.downcast_ref(), Some(&Rejected::QueueFull));
+        drop(permit);
+        let _permit = adm.acquire().unwrap();
+
+        let adm = admission(None, Some(Duration::from_millis(10)));
+        let _permit = adm.acquire().unwrap();
+        let err = adm.acquire().unwrap_err();
+        assert_eq!(err.downcast_ref(), Some(&Rejected::QueueTimeout));
+        assert_eq!(
+            adm.metrics(),
+            AdmissionMetrics {
+                running: 1,
+                queued: 0,
+                rejected: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn test_admission_queues() {
+        let adm = admission(None, Some(Duration::from_secs(60)));
+        let permit = adm.acquire().unwrap();
+        thread::scope(|s| {
+            let waiter = s.spawn(|| adm.acquire().map(drop));
+            while adm.metrics().queued == 0 {
+                thread::yield_now();
+            }
+            drop(permit);
+            waiter.join().unwrap().unwrap();
+        });
+        assert_eq!(adm.metrics(), AdmissionMetrics::default());
+    }
+}
@@ -0,0 +1,251 @@
+//! Scans an account's history for changes that ordinary transactions don't
+//! explain, for debugging stuck transactions and surprising contract state.
+//!
+//! The account history index lists the blocks which changed the account, and
+//! the account changesets hold its state going into each of them. The state
+//! coming out of a block is therefore the state going into the next change,
+//! or the current state after the last one.
+
+use anyhow::{format_err, Result};
+use ethers::types::{Address, U64};
+use mdbx::EnvironmentKind;
+use serde::Serialize;
+
+use crate::{
+    builder::PrunedData,
+    client::{Client, STATE_TABLES},
+    models::Account,
+    reader::{Reader, EMPTY_CODEHASH},
+    tables,
+    types::{BlockNum, HeaderKey},
+};
+
+/// A change to an account which ordinary transactions don't explain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum AnomalyKind {
+    /// The nonce of an account without code didn't rise by the number of
+    /// transactions it sent in the block.
+    NonceGap { expected: u64, found: u64 },
+    /// The nonce fell, which happens when an account is destroyed and
+    /// recreated.
+    NonceReset { from: u64, to: u64 },
+    /// The account was deleted, by a self-destruct or as an empty account.
+    Destroyed,
+    /// A contract was deployed again at the address after being destroyed.
+    Redeployed {
+        from_incarnation: u64,
+        to_incarnation: u64,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Anomaly {
+    pub block_number: U64,
+    #[serde(flatten)]
+    pub kind: AnomalyKind,
+}
+
+/// The result of `Client::audit_account`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountAudit {
+    pub address: Address,
+    /// The number of blocks in the account's history.
+    pub blocks_scanned: u64,
+    /// The anomalies found, oldest first.
+    pub anomalies: Vec<Anomaly>,
+}
+
+impl<E: EnvironmentKind> Client<E> {
+    /// Walks the history of `address` and reports nonce gaps and resets,
+    /// deletions and redeployments. Blocks are only read when the nonce of an
+    /// account without code changes, to count the transactions it sent.
+    ///
+    /// Only the history still in the db is scanned, so a node which prunes
+    /// history reports anomalies in the retained blocks only.
+    pub fn audit_account(&self, address: Address) -> Result<AccountAudit> {
+        self.ensure_unpruned("audit_account", PrunedData::History)?;
+        let _permit = self.admit()?;
+        let _slow = self.slow_read("audit_account", STATE_TABLES, || format!("{:?}", address));
+        let mut dbtx = self.reader()?;
+        let history = dbtx.read_bitmap_index64(tables::AccountHistory, address.as_bytes())?;
+
+        // the state going into each change, then the current state
+        let mut states = Vec::with_capacity(history.len() + 1);
+        for num in &history {
+            let before = dbtx
+                .read_account_before(address, BlockNum(*num))?
+                .ok_or_else(|| format_err!("no changeset for {:?} in block {}", address, num))?;
+            states.push(before);
+        }
+        states.push(dbtx.read_account_data(address)?);
+
+        let mut audit = AccountAudit {
+            address,
+            blocks_scanned: history.len().try_into()?,
+            anomalies: vec![],
+        };
+        let mut last_incarnation = 0;
+        for (num, pair) in history.iter().zip(states.windows(2)) {
+            let num = BlockNum(*num);
+            let (before, after) = (&pair[0], &pair[1]);
+            let mut flag = |kind| {
+                audit.anomalies.push(Anomaly {
+                    block_number: num.into(),
+                    kind,
+                })
+            };
+
+            let deleted = *after == Account::default();
+            if *before != Account::default() && deleted {
+                flag(AnomalyKind::Destroyed);
+            }
+            last_incarnation = last_incarnation.max(before.incarnation);
+            if last_incarnation > 0 && after.incarnation > last_incarnation {
+                flag(AnomalyKind::Redeployed {
+                    from_incarnation: last_incarnation,
+                    to_incarnation: after.incarnation,
+                });
+            }
+            last_incarnation = last_incarnation.max(after.incarnation);
+
+            // contracts' nonces count their creations, not transactions
+            if deleted || has_code(after) || after.nonce == before.nonce {
+                continue;
+            }
+            if after.nonce < before.nonce {
+                flag(AnomalyKind::NonceReset {
+                    from: before.nonce,
+                    to: after.nonce,
+                });
+                continue;
+            }
+            let expected = before.nonce + count_sent(self, &mut dbtx, address, num)?;
+            if after.nonce != expected {
+                flag(AnomalyKind::NonceGap {
+                    expected,
+                    found: after.nonce,
+                });
+            }
+        }
+        Ok(audit)
+    }
+}
+
+fn has_code(acct: &Account) -> bool {
+    !acct.codehash.is_zero() && acct.codehash != *EMPTY_CODEHASH
+}
+
+/// Counts the transactions `address` sent in the canonical block `num`.
+fn count_sent<E: EnvironmentKind>(
+    client: &Client<E>,
+    dbtx: &mut Reader<'_, mdbx::RO, E>,
+    address: Address,
+    num: BlockNum,
+) -> Result<u64> {
+    let key = HeaderKey::new(num, dbtx.read_canonical_hash(num)?);
+    let body = dbtx.read_body_for_storage(key)?;
+    let msgs = dbtx.read_transactions(body.base_tx_id.into(), body.tx_amount.try_into()?)?;
+    let senders = client.read_block_senders(dbtx, key, &msgs)?;
+    Ok(senders
+        .iter()
+        .filter(|s| **s == address)
+        .count()
+        .try_into()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::{
+        chain::ChainBuilder,
+        rand::{rand_legacy, sign},
+        TMP_DIR,
+    };
+    use akula::models::MessageWithSignature;
+    use ethers::{core::k256::ecdsa::SigningKey, types::H256};
+    use rand::thread_rng;
+
+    #[test]
+    fn test_audit_account() -> Result<()> {
+        let mut rng = thread_rng();
+        let message = rand_legacy(&mut rng);
+        let signature = sign(SigningKey::random(&mut rng), message.hash().as_bytes());
+        let tx = MessageWithSignature { message, signature };
+        let eoa = tx.recover_sender()?;
+        let nonce = |nonce| Account {
+            nonce,
+            balance: 1.into(),
+            ..Default::default()
+        };
+
+        let contract = Address::repeat_byte(0xcc);
+        let deployed = |incarnation| Account {
+            nonce: 1,
+            incarnation,
+            codehash: H256::repeat_byte(0xdd),
+            ..Default::default()
+        };
+
+        let chain = ChainBuilder::new()
+            .start(5)
+            // sends its first tx
+            .block(|b| b.tx(tx.clone()).account_change(eoa, None))
+            .block(|b| b.account_change(contract, None))
+            // the nonce skips ahead without a tx
+            .block(|b| b.account_change(eoa, Some(nonce(1))))
+            .block(|b| b.account_change(contract, Some(deployed(1))))
+            .block(|b| {
+                b.account_change(eoa, Some(nonce(3)))
+                    .account_change(contract, None)
+                    .account(eoa, nonce(0))
+                    .account(contract, deployed(2))
+            })
+            .write(TMP_DIR.clone())?;
+        let db = Client::<mdbx::NoWriteMap>::open_new(chain.path.clone())?;
+
+        let audit = db.audit_account(eoa)?;
+        assert_eq!(audit.blocks_scanned, 3);
+        assert_eq!(
+            audit.anomalies,
+            vec![
+                Anomaly {
+                    block_number: 7.into(),
+                    kind: AnomalyKind::NonceGap {
+                        expected: 1,
+                        found: 3,
+                    },
+                },
+                Anomaly {
+                    block_number: 9.into(),
+                    kind: AnomalyKind::NonceReset { from: 3, to: 0 },
+                },
+            ]
+        );
+
+        let audit = db.audit_account(contract)?;
+        assert_eq!(
+            audit.anomalies,
+            vec![
+                Anomaly {
+                    block_number: 8.into(),
+                    kind: AnomalyKind::Destroyed,
+                },
+                Anomaly {
+                    block_number: 9.into(),
+                    kind: AnomalyKind::Redeployed {
+                        from_incarnation: 1,
+                        to_incarnation: 2,
+                    },
+                },
+            ]
+        );
+
+        let quiet = db.audit_account(Address::repeat_byte(0xee))?;
+        assert_eq!(quiet.blocks_scanned, 0);
+        assert!(quiet.anomalies.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,298 @@
+//! AuRa epoch transitions and validator sets, as used by Gnosis chain. At each
+//! transition Erigon stores a proof of the validator set taking effect: empty
+//! for a fixed list from the chain spec, the validator set contract's address
+//! for the first transition of a contract set, and otherwise the header and
+//! receipts of the block whose `InitiateChange` log signalled the new set.
+
+use anyhow::{format_err, Result};
+use ethers::{
+    abi::{self, ParamType},
+    types::{Address, BlockId, Bytes, H256, U64},
+    utils::keccak256,
+};
+use fastrlp::Header;
+use mdbx::EnvironmentKind;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+
+use crate::{
+    client::{get_header_key, Client},
+    tables,
+};
+
+// The tables read by the epoch lookups, as reported in the slow read log
+const EPOCH_TABLES: &[&str] = &[
+    tables::CanonicalHeader::const_db_name(),
+    tables::HeaderNumber::const_db_name(),
+    tables::Epoch::const_db_name(),
+    tables::PendingEpoch::const_db_name(),
+];
+
+/// The topic of `InitiateChange(bytes32 indexed parentHash, address[] newSet)`.
+static INITIATE_CHANGE: Lazy<H256> =
+    Lazy::new(|| keccak256("InitiateChange(bytes32,address[])").into());
+
+/// An AuRa epoch transition and its proof, as stored.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EpochTransition {
+    pub block_number: U64,
+    pub block_hash: H256,
+    pub proof: Bytes,
+}
+
+/// Where the validators of an epoch come from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ValidatorSource {
+    /// A fixed list given in the chain spec rather than the db.
+    Spec,
+    /// The first set of a validator set contract, which is only known by
+    /// calling the contract as of the transition.
+    Contract(Address),
+    /// The set signalled by the contract's `InitiateChange` log.
+    List(Vec<Address>),
+}
+
+/// The result of `Client::get_validators_at`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EpochValidators {
+    /// The transition starting the epoch the block is in.
+    pub transition: U64,
+    pub transition_hash: H256,
+    pub validators: ValidatorSource,
+}
+
+impl<E: EnvironmentKind> Client<E> {
+    /// Returns the last canonical epoch transition at or before `block`, or
+    /// `None` if there is none.
+    pub fn get_epoch_transition<T: Into<BlockId> + Send + Sync>(
+        &self,
+        block: T,
+    ) -> Result<Option<EpochTransition>> {
+        let block = block.into();
+        let _slow = self.slow_read("get_epoch_transition", EPOCH_TABLES, || {
+            format!("{:?}", block)
+        });
+        let mut dbtx = self.reader()?;
+        let num = get_header_key(&mut dbtx, block)?.num;
+        Ok(dbtx
+            .read_epoch_transition_at(num)?
+            .map(|(key, proof)| EpochTransition {
+                block_number: key.num.into(),
+                block_hash: key.hash,
+                proof: proof.into(),
+            }))
+    }
+
+    /// Returns the proof of the transition signalled in `block` which is still
+    /// waiting to be finalized, if any.
+    pub fn get_pending_epoch_transition<T: Into<BlockId> + Send + Sync>(
+        &self,
+        block: T,
+    ) -> Result<Option<EpochTransition>> {
+        let block = block.into();
+        let _slow = self.slow_read("get_pending_epoch_transition", EPOCH_TABLES, || {
+            format!("{:?}", block)
+        });
+        let mut dbtx = self.reader()?;
+        let key = get_header_key(&mut dbtx, block)?;
+        Ok(dbtx.read_pending_epoch(key)?.map(|proof| EpochTransition {
+            block_number: key.num.into(),
+            block_hash: key.hash,
+            proof: proof.into(),
+        }))
+    }
+
+    /// Returns the validator set in effect at `block`, decoded from the proof
+    /// of the last epoch transition at or before it, or `None` if the db has
+    /// no such transition.
+    ///
+    /// ```ignore
+    /// if let Some(epoch) = client.get_validators_at(25_000_000u64)? {
+    ///     println!("{:?} since block {}", epoch.validators, epoch.transition);
+    /// }
+    /// ```
+    pub fn get_validators_at<T: Into<BlockId> + Send + Sync>(
+        &self,
+        block: T,
+    ) -> Result<Option<EpochValidators>> {
+        let transition = match self.get_epoch_transition(block)? {
+            Some(transition) => transition,
+            None => return Ok(None),
+        };
+        let validators = decode_validators(&transition.proof).map_err(|e| {
+            format_err!(
+                "bad epoch proof at block {}: {}",
+                transition.block_number,
+                e
+            )
+        })?;
+        Ok(Some(EpochValidators {
+            transition: transition.block_number,
+            transition_hash: transition.block_hash,
+            validators,
+        }))
+    }
+}
+
+/// Decodes the validator set proven by an epoch transition proof.
+fn decode_validators(proof: &[u8]) -> Result<ValidatorSource> {
+    if proof.is_empty() {
+        return Ok(ValidatorSource::Spec);
+    }
+    let mut items = list(&mut &*proof)?;
+    match item(&mut items)? {
+        // the first proof of a contract set: address ++ header
+        (false, addr) if addr.len() == 20 => {
+            Ok(ValidatorSource::Contract(Address::from_slice(addr)))
+        }
+        // header ++ receipts
+        (true, _) => {
+            let mut receipts = list(&mut items)?;
+            let mut validators = None;
+            while !receipts.is_empty() {
+                let mut receipt = match item(&mut receipts)? {
+                    (true, payload) => payload,
+                    // typed receipts are wrapped in a string, after the type
+                    (false, typed) => list(&mut typed.get(1..).unwrap_or_default())?,
+                };
+                // status or state root, cumulative gas used and bloom
+                for _ in 0..3 {
+                    item(&mut receipt)?;
+                }
+                let mut logs = list(&mut receipt)?;
+                while !logs.is_empty() {
+                    let mut log = list(&mut logs)?;
+                    let _address = item(&mut log)?;
+                    let mut topics = list(&mut log)?;
+                    let (_, topic) = item(&mut topics)?;
+                    if topic == INITIATE_CHANGE.as_bytes() {
+                        let (_, data) = item(&mut log)?;
+                        validators = Some(decode_new_set(data)?);
+                    }
+                }
+            }
+            validators
+                .map(ValidatorSource::List)
+                .ok_or_else(|| format_err!("no InitiateChange log"))
+        }
+        _ => Err(format_err!("unknown proof format")),
+    }
+}
+
+/// Decodes the `address[] newSet` data of an `InitiateChange` log.
+fn decode_new_set(data: &[u8]) -> Result<Vec<Address>> {
+    let tokens = abi::decode(&[ParamType::Array(Box::new(ParamType::Address))], data)?;
+    tokens
+        .into_iter()
+        .next()
+        .and_then(|token| token.into_array())
+        .ok_or_else(|| format_err!("bad InitiateChange data"))?
+        .into_iter()
+        .map(|token| {
+            token
+                .into_address()
+                .ok_or_else(|| format_err!("bad InitiateChange address"))
+        })
+        .collect()
+}
+
+/// Splits the item at the front of `buf` off of `buf`, returning whether it
+/// is a list, and its payload.
+fn item<'a>(buf: &mut &'a [u8]) -> Result<(bool, &'a [u8])> {
+    let h = Header::decode(buf).map_err(|e| format_err!("{}", e))?;
+    anyhow::ensure!(buf.len() >= h.payload_length, "rlp input too short");
+    let (payload, rest) = buf.split_at(h.payload_length);
+    *buf = rest;
+    Ok((h.list, payload))
+}
+
+/// Like `item`, for an item which must be a list.
+fn list<'a>(buf: &mut &'a [u8]) -> Result<&'a [u8]> {
+    match item(buf)? {
+        (true, payload) => Ok(payload),
+        (false, _) => Err(format_err!("expected an rlp list")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::{chain::ChainBuilder, TMP_DIR};
+    use ethers::abi::Token;
+    use fastrlp::Encodable;
+
+    fn rlp_list(items: &[Vec<u8>]) -> Vec<u8> {
+        let payload = items.concat();
+        let mut out = vec![];
+        Header {
+            list: true,
+            payload_length: payload.len(),
+        }
+        .encode(&mut out);
+        out.extend(payload);
+        out
+    }
+
+    fn rlp_bytes(b: &[u8]) -> Vec<u8> {
+        let mut out = vec![];
+        bytes::Bytes::copy_from_slice(b).encode(&mut out);
+        out
+    }
+
+    /// The proof of a transition to `validators`, signalled by a typed receipt.
+    fn change_proof(validators: &[Address]) -> Vec<u8> {
+        let data = abi::encode(&[Token::Array(
+            validators.iter().copied().map(Token::Address).collect(),
+        )]);
+        let log = rlp_list(&[
+            rlp_bytes(Address::repeat_byte(0xaa).as_bytes()),
+            rlp_list(&[
+                rlp_bytes(INITIATE_CHANGE.as_bytes()),
+                rlp_bytes(H256::repeat_byte(0x01).as_bytes()),
+            ]),
+            rlp_bytes(&data),
+        ]);
+        let receipt = rlp_list(&[
+            rlp_bytes(&[1]),
+            rlp_bytes(&[0x52, 0x08]),
+            rlp_bytes(&[0; 256]),
+            rlp_list(&[log]),
+        ]);
+        let typed = [&[2u8][..], &receipt].concat();
+        // a stand-in for the header
+        rlp_list(&[rlp_list(&[]), rlp_list(&[rlp_bytes(&typed)])])
+    }
+
+    #[test]
+    fn test_get_validators_at() -> Result<()> {
+        let contract = Address::repeat_byte(0xcc);
+        let validators = vec![Address::repeat_byte(0x01), Address::repeat_byte(0x02)];
+        let first = rlp_list(&[rlp_bytes(contract.as_bytes()), rlp_list(&[])]);
+        let chain = ChainBuilder::new()
+            .block(|b| b.epoch(first, false))
+            .block(|b| b.epoch(change_proof(&validators), true))
+            .block(|b| b.epoch(change_proof(&validators), false))
+            .block(|b| b)
+            .write(TMP_DIR.clone())?;
+        let db = Client::<mdbx::NoWriteMap>::open_new(chain.path.clone())?;
+
+        let epoch = db.get_validators_at(2u64)?.unwrap();
+        assert_eq!(epoch.transition, 1.into());
+        assert_eq!(epoch.validators, ValidatorSource::Contract(contract));
+        // pending transitions don't take effect
+        assert!(db.get_pending_epoch_transition(2u64)?.is_some());
+
+        let epoch = db.get_validators_at(4u64)?.unwrap();
+        assert_eq!(epoch.transition, 3.into());
+        assert_eq!(epoch.transition_hash, chain.hash(2));
+        assert_eq!(epoch.validators, ValidatorSource::List(validators));
+        assert_eq!(db.get_pending_epoch_transition(4u64)?, None);
+
+        assert_eq!(decode_validators(&[])?, ValidatorSource::Spec);
+        assert!(decode_validators(&rlp_list(&[rlp_list(&[]), rlp_list(&[])])).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,118 @@
+//! Balance time series for charting an address over a range of blocks.
+//!
+//! Rather than reading the balance at every block, the series is built from
+//! the blocks in the account history index: the balance only changes in those
+//! blocks, and the changeset of each holds the balance going into it.
+
+use anyhow::Result;
+use ethers::types::{Address, BlockNumber, U256, U64};
+use mdbx::EnvironmentKind;
+use serde::Serialize;
+
+use crate::{
+    builder::PrunedData,
+    client::{res_block_number, Client, STATE_TABLES},
+    tables,
+    types::BlockNum,
+};
+
+/// The balance of an address after a block.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BalancePoint {
+    pub block_number: U64,
+    pub balance: U256,
+}
+
+impl<E: EnvironmentKind> Client<E> {
+    /// Returns the balance of `address` after block `from`, followed by the
+    /// balance after each block in `from + 1..=to` which changed it. Blocks
+    /// which left the balance unchanged are omitted, so the series is a step
+    /// function over the range.
+    pub fn get_balance_history<T: Into<BlockNumber>>(
+        &self,
+        address: Address,
+        from: T,
+        to: T,
+    ) -> Result<Vec<BalancePoint>> {
+        let _permit = self.admit()?;
+        let (from, to) = (from.into(), to.into());
+        let _slow = self.slow_read("get_balance_history", STATE_TABLES, || {
+            format!("{:?} {:?}..={:?}", address, from, to)
+        });
+        let mut dbtx = self.reader()?;
+        let head = res_block_number(&mut dbtx, BlockNumber::Latest)?;
+        let from = res_block_number(&mut dbtx, from)?;
+        let to = res_block_number(&mut dbtx, to)?;
+        anyhow::ensure!(from <= to, "invalid block range: {} > {}", from, to);
+        anyhow::ensure!(to <= head, "block {} is past the head {}", to, head);
+        if from < head {
+            self.ensure_unpruned("get_balance_history", PrunedData::History)?;
+        }
+
+        let mut points = vec![BalancePoint {
+            block_number: from.into(),
+            balance: dbtx.state_at(from).account(address)?.balance,
+        }];
+        let changes = dbtx
+            .read_bitmap_index64(tables::AccountHistory, address.as_bytes())?
+            .into_iter()
+            .filter(|num| *num > *from)
+            .collect::<Vec<_>>();
+        // the balance coming out of a change is the one going into the next
+        for num in changes.iter().take_while(|num| **num <= *to) {
+            dbtx.budget().check()?;
+            let after = dbtx.state_at(BlockNum(*num)).account(address)?;
+            if after.balance != points[points.len() - 1].balance {
+                points.push(BalancePoint {
+                    block_number: (*num).into(),
+                    balance: after.balance,
+                });
+            }
+        }
+        Ok(points)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::Account,
+        test::{chain::ChainBuilder, TMP_DIR},
+    };
+
+    #[test]
+    fn test_get_balance_history() -> Result<()> {
+        let who = Address::repeat_byte(0x42);
+        let balance = |wei: u64| Account::new().balance(wei.into());
+        let point = |num: u64, wei: u64| BalancePoint {
+            block_number: num.into(),
+            balance: wei.into(),
+        };
+        // funded in block 1, only its nonce changes in block 3
+        let chain = ChainBuilder::new()
+            .block(|b| b.account_change(who, None))
+            .block(|b| b)
+            .block(|b| b.account_change(who, Some(balance(5))))
+            .block(|b| b.account_change(who, Some(balance(5).nonce(1))))
+            .block(|b| b.account_change(who, Some(balance(7).nonce(1))))
+            .block(|b| b.account(who, balance(2).nonce(1)))
+            .write(TMP_DIR.clone())?;
+        let db = Client::<mdbx::NoWriteMap>::open_new(chain.path.clone())?;
+
+        assert_eq!(
+            db.get_balance_history(who, 0u64, 6)?,
+            vec![point(0, 0), point(1, 5), point(4, 7), point(5, 2)]
+        );
+        assert_eq!(
+            db.get_balance_history(who, 2u64, 4)?,
+            vec![point(2, 5), point(4, 7)]
+        );
+        assert_eq!(db.get_balance_history(who, 5u64, 5)?, vec![point(5, 2)]);
+        assert_eq!(db.get_balance_history(who, 6u64, 6)?, vec![point(6, 2)]);
+        assert!(db.get_balance_history(who, 3u64, 2).is_err());
+        assert!(db.get_balance_history(who, 0u64, 7).is_err());
+        Ok(())
+    }
+}
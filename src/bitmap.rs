@@ -0,0 +1,331 @@
+//! Coding for the roaring bitmaps Erigon uses in its index tables.
+//!
+//! Only the portable serialization format written by
+//! github.com/RoaringBitmap/roaring is supported. Bitmaps are encoded without
+//! run containers, which every reader of the format accepts.
+
+use anyhow::{format_err, Result};
+use bytes::{Buf, BufMut};
+
+const SERIAL_COOKIE_NO_RUNCONTAINER: u32 = 12346;
+const SERIAL_COOKIE: u16 = 12347;
+const NO_OFFSET_THRESHOLD: usize = 4;
+const MAX_ARRAY_CARDINALITY: usize = 4096;
+const BITSET_WORDS: usize = 1024;
+
+/// Decodes a serialized 32-bit roaring bitmap into its sorted values.
+pub fn decode_roaring(buf: &[u8]) -> Result<Vec<u32>> {
+    let mut out = vec![];
+    decode_roaring_into(buf, &mut out)?;
+    Ok(out)
+}
+
+/// Decodes a serialized 32-bit roaring bitmap, appending its sorted values to `out`.
+pub fn decode_roaring_into(mut buf: &[u8], out: &mut Vec<u32>) -> Result<()> {
+    read_roaring(&mut buf, out)
+}
+
+/// Decodes a serialized 64-bit roaring bitmap into its sorted values. The
+/// portable format is a count of buckets, each a 32-bit high key followed by
+/// a serialized 32-bit bitmap of the low bits.
+pub fn decode_roaring64(mut buf: &[u8]) -> Result<Vec<u64>> {
+    let n_buckets = read_u64(&mut buf)?;
+    let mut out = vec![];
+    let mut lows = vec![];
+    for _ in 0..n_buckets {
+        let high = (read_u32(&mut buf)? as u64) << 32;
+        read_roaring(&mut buf, &mut lows)?;
+        out.extend(lows.drain(..).map(|low| high | low as u64));
+    }
+    Ok(out)
+}
+
+/// Serializes a 32-bit roaring bitmap holding `values`, which must be sorted
+/// and distinct.
+pub fn encode_roaring(values: &[u32]) -> Vec<u8> {
+    let mut out = vec![];
+    write_roaring(&mut out, values);
+    out
+}
+
+/// Serializes a 64-bit roaring bitmap holding `values`, which must be sorted
+/// and distinct.
+pub fn encode_roaring64(values: &[u64]) -> Vec<u8> {
+    let buckets = group_by_high(values, |v| (v >> 32) as u32);
+    let mut out = vec![];
+    out.put_u64_le(buckets.len() as u64);
+    let mut lows = vec![];
+    for (high, bucket) in buckets {
+        out.put_u32_le(high);
+        lows.clear();
+        lows.extend(bucket.iter().map(|v| *v as u32));
+        write_roaring(&mut out, &lows);
+    }
+    out
+}
+
+/// Appends one serialized 32-bit roaring bitmap to `out`.
+fn write_roaring(out: &mut Vec<u8>, values: &[u32]) {
+    debug_assert!(values.windows(2).all(|w| w[0] < w[1]));
+    let containers = group_by_high(values, |v| (v >> 16) as u16);
+
+    out.put_u32_le(SERIAL_COOKIE_NO_RUNCONTAINER);
+    out.put_u32_le(containers.len() as u32);
+    for (key, lows) in &containers {
+        out.put_u16_le(*key);
+        out.put_u16_le((lows.len() - 1) as u16);
+    }
+    // offsets are from the start of this bitmap
+    let mut offset = 8 + containers.len() * 8;
+    for (_, lows) in &containers {
+        out.put_u32_le(offset as u32);
+        offset += container_len(lows.len());
+    }
+
+    for (_, lows) in containers {
+        if lows.len() > MAX_ARRAY_CARDINALITY {
+            let mut words = [0u64; BITSET_WORDS];
+            for v in lows {
+                let low = *v as u16 as usize;
+                words[low / 64] |= 1u64 << (low % 64);
+            }
+            words.iter().for_each(|w| out.put_u64_le(*w));
+        } else {
+            lows.iter().for_each(|v| out.put_u16_le(*v as u16));
+        }
+    }
+}
+
+fn container_len(cardinality: usize) -> usize {
+    if cardinality > MAX_ARRAY_CARDINALITY {
+        BITSET_WORDS * 8
+    } else {
+        cardinality * 2
+    }
+}
+
+/// Splits sorted `values` into runs sharing the same `high` key.
+fn group_by_high<T: Copy, K: PartialEq>(values: &[T], high: impl Fn(T) -> K) -> Vec<(K, &[T])> {
+    let mut out = vec![];
+    let mut rest = values;
+    while let Some(first) = rest.first() {
+        let key = high(*first);
+        let n = rest.iter().take_while(|v| high(**v) == key).count();
+        let (group, tail) = rest.split_at(n);
+        out.push((key, group));
+        rest = tail;
+    }
+    out
+}
+
+/// Reads one serialized 32-bit roaring bitmap from the front of `buf` into `out`,
+/// leaving `buf` at the first byte after it.
+fn read_roaring(buf: &mut &[u8], out: &mut Vec<u32>) -> Result<()> {
+    let cookie = read_u32(buf)?;
+    let (size, run_flags) = if cookie & 0xffff == SERIAL_COOKIE as u32 {
+        let size = (cookie >> 16) as usize + 1;
+        let flags = take(buf, (size + 7) / 8)?.to_vec();
+        (size, Some(flags))
+    } else if cookie == SERIAL_COOKIE_NO_RUNCONTAINER {
+        (read_u32(buf)? as usize, None)
+    } else {
+        return Err(format_err!("bad roaring cookie: {}", cookie));
+    };
+
+    let mut headers = Vec::with_capacity(size);
+    for _ in 0..size {
+        let key = read_u16(buf)?;
+        let cardinality = read_u16(buf)? as usize + 1;
+        headers.push((key, cardinality));
+    }
+
+    // Container offsets are redundant when reading sequentially
+    if run_flags.is_none() || size >= NO_OFFSET_THRESHOLD {
+        take(buf, size * 4)?;
+    }
+
+    for (i, (key, cardinality)) in headers.into_iter().enumerate() {
+        let high = (key as u32) << 16;
+        let is_run = run_flags
+            .as_ref()
+            .map_or(false, |flags| flags[i / 8] & (1 << (i % 8)) != 0);
+        if is_run {
+            let n_runs = read_u16(buf)?;
+            for _ in 0..n_runs {
+                let start = read_u16(buf)? as u32;
+                let len = read_u16(buf)? as u32;
+                out.extend((start..=start + len).map(|low| high | low));
+            }
+        } else if cardinality > MAX_ARRAY_CARDINALITY {
+            for word_idx in 0..BITSET_WORDS {
+                let mut word = read_u64(buf)?;
+                while word != 0 {
+                    let bit = word.trailing_zeros();
+                    out.push(high | (word_idx as u32 * 64 + bit));
+                    word &= word - 1;
+                }
+            }
+        } else {
+            for _ in 0..cardinality {
+                out.push(high | read_u16(buf)? as u32);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns the sorted union of two sorted sets.
+pub fn union<T: Ord + Copy>(a: &[T], b: &[T]) -> Vec<T> {
+    let mut out = Vec::with_capacity(a.len() + b.len());
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            std::cmp::Ordering::Less => {
+                out.push(a[i]);
+                i += 1;
+            }
+            std::cmp::Ordering::Greater => {
+                out.push(b[j]);
+                j += 1;
+            }
+            std::cmp::Ordering::Equal => {
+                out.push(a[i]);
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    out.extend_from_slice(&a[i..]);
+    out.extend_from_slice(&b[j..]);
+    out
+}
+
+/// Returns the sorted intersection of two sorted sets.
+pub fn intersect<T: Ord + Copy>(a: &[T], b: &[T]) -> Vec<T> {
+    let mut out = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            std::cmp::Ordering::Less => i += 1,
+            std::cmp::Ordering::Greater => j += 1,
+            std::cmp::Ordering::Equal => {
+                out.push(a[i]);
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    out
+}
+
+fn take<'a>(buf: &mut &'a [u8], n: usize) -> Result<&'a [u8]> {
+    if buf.len() < n {
+        return Err(format_err!("unexpected end of roaring bitmap"));
+    }
+    let (head, tail) = buf.split_at(n);
+    *buf = tail;
+    Ok(head)
+}
+
+fn read_u16(buf: &mut &[u8]) -> Result<u16> {
+    Ok(take(buf, 2)?.get_u16_le())
+}
+
+fn read_u32(buf: &mut &[u8]) -> Result<u32> {
+    Ok(take(buf, 4)?.get_u32_le())
+}
+
+fn read_u64(buf: &mut &[u8]) -> Result<u64> {
+    Ok(take(buf, 8)?.get_u64_le())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_decode_array_container() -> Result<()> {
+        // {1, 5, 65537}: two array containers without runs
+        let mut buf = vec![];
+        buf.extend(SERIAL_COOKIE_NO_RUNCONTAINER.to_le_bytes());
+        buf.extend(2u32.to_le_bytes());
+        buf.extend([0u16.to_le_bytes(), 1u16.to_le_bytes()].concat());
+        buf.extend([1u16.to_le_bytes(), 0u16.to_le_bytes()].concat());
+        buf.extend([0u8; 8]); // offsets
+        buf.extend([1u16.to_le_bytes(), 5u16.to_le_bytes()].concat());
+        buf.extend(1u16.to_le_bytes());
+        assert_eq!(decode_roaring(&buf)?, vec![1, 5, 65537]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_run_container() -> Result<()> {
+        // {10, 11, 12}: one run container, no offsets since size < 4
+        let mut buf = vec![];
+        buf.extend((SERIAL_COOKIE as u32).to_le_bytes());
+        buf.push(1); // run flags
+        buf.extend([0u16.to_le_bytes(), 2u16.to_le_bytes()].concat());
+        buf.extend(1u16.to_le_bytes());
+        buf.extend([10u16.to_le_bytes(), 2u16.to_le_bytes()].concat());
+        assert_eq!(decode_roaring(&buf)?, vec![10, 11, 12]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_roaring64() -> Result<()> {
+        // {2^32 + 7}: one bucket holding a single array container
+        let mut buf = vec![];
+        buf.extend(1u64.to_le_bytes());
+        buf.extend(1u32.to_le_bytes());
+        buf.extend(SERIAL_COOKIE_NO_RUNCONTAINER.to_le_bytes());
+        buf.extend(1u32.to_le_bytes());
+        buf.extend([0u16.to_le_bytes(), 0u16.to_le_bytes()].concat());
+        buf.extend([0u8; 4]); // offsets
+        buf.extend(7u16.to_le_bytes());
+        assert_eq!(decode_roaring64(&buf)?, vec![(1 << 32) + 7]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_ops() {
+        assert_eq!(union(&[1, 3, 5], &[2, 3]), vec![1, 2, 3, 5]);
+        assert_eq!(intersect(&[1, 3, 5], &[2, 3, 5]), vec![3, 5]);
+    }
+
+    #[test]
+    fn test_encode_bitset_container() -> Result<()> {
+        // one dense container, one sparse
+        let values: Vec<u32> = (0..5000).chain([70000, 70001]).collect();
+        let enc = encode_roaring(&values);
+        assert_eq!(enc.len(), 8 + 2 * 8 + BITSET_WORDS * 8 + 2 * 2);
+        assert_eq!(decode_roaring(&enc)?, values);
+        Ok(())
+    }
+
+    proptest! {
+        #[test]
+        fn prop_roaring_roundtrip(values in prop::collection::btree_set(any::<u32>(), 0..256)) {
+            let values: Vec<_> = values.into_iter().collect();
+            prop_assert_eq!(decode_roaring(&encode_roaring(&values)).unwrap(), values);
+        }
+
+        #[test]
+        fn prop_roaring_dense_roundtrip(start in any::<u32>(), len in 0..20_000u32, step in 1..4u32) {
+            let values: Vec<_> = (0..len).filter_map(|i| start.checked_add(i * step)).collect();
+            prop_assert_eq!(decode_roaring(&encode_roaring(&values)).unwrap(), values);
+        }
+
+        #[test]
+        fn prop_roaring64_roundtrip(
+            // few distinct high bits, so that buckets hold several values
+            values in prop::collection::btree_set((0..4u64, any::<u32>()), 0..256)
+        ) {
+            let values: Vec<_> = values
+                .into_iter()
+                .map(|(high, low)| high << 32 | low as u64)
+                .collect();
+            prop_assert_eq!(decode_roaring64(&encode_roaring64(&values)).unwrap(), values);
+        }
+    }
+}
@@ -0,0 +1,212 @@
+//! Polygon state-sync transactions. Bor commits the state syncs of a block in
+//! a synthetic transaction after its regular ones, which Erigon doesn't store
+//! with the block's transactions: its receipt is kept in the BorReceipt table,
+//! and its hash, derived from the block, is indexed in BorTxLookup.
+
+use anyhow::Result;
+use ethers::{
+    types::{Log, Transaction, TransactionReceipt, H256},
+    utils::keccak256,
+};
+use mdbx::{EnvironmentKind, TransactionKind};
+
+use crate::{client::Client, reader::Reader, receipts::accrue, types::HeaderKey};
+
+const BOR_RECEIPT_PREFIX: &[u8] = b"matic-bor-receipt-";
+
+/// Returns the hash of the state-sync transaction of the block `key`, as
+/// derived by Bor: the keccak of its receipt key, `"matic-bor-receipt-"` ++
+/// the block number ++ the block hash.
+pub fn state_sync_tx_hash(key: HeaderKey) -> H256 {
+    let mut enc = BOR_RECEIPT_PREFIX.to_vec();
+    enc.extend(key.num.to_be_bytes());
+    enc.extend(key.hash.as_bytes());
+    keccak256(enc).into()
+}
+
+impl<E: EnvironmentKind> Client<E> {
+    /// Returns the canonical block of the state-sync transaction `hash`, or
+    /// `None` if `hash` isn't one.
+    pub(crate) fn find_state_sync_block<TX: TransactionKind>(
+        &self,
+        dbtx: &mut Reader<'_, TX, E>,
+        hash: H256,
+    ) -> Result<Option<HeaderKey>> {
+        let num = match dbtx.find_state_sync_block_number(hash)? {
+            Some(num) => num,
+            None => return Ok(None),
+        };
+        let key = HeaderKey::new(num, dbtx.read_canonical_hash(num)?);
+        // the lookup may still point at a block which was reorged out
+        if state_sync_tx_hash(key) != hash {
+            return Ok(None);
+        }
+        Ok(Some(key))
+    }
+
+    /// Returns the state-sync transaction of the block `key`, indexed after
+    /// the block's regular transactions. It has no sender, recipient or gas.
+    pub(crate) fn state_sync_transaction<TX: TransactionKind>(
+        &self,
+        dbtx: &mut Reader<'_, TX, E>,
+        key: HeaderKey,
+    ) -> Result<Transaction> {
+        let (_, tx_amt) = dbtx.read_body_tx_range(key)?;
+        Ok(Transaction {
+            hash: state_sync_tx_hash(key),
+            block_hash: Some(key.hash),
+            block_number: Some(key.num.into()),
+            transaction_index: Some(tx_amt.into()),
+            gas_price: Some(0.into()),
+            transaction_type: Some(0.into()),
+            ..Default::default()
+        })
+    }
+
+    /// Returns the receipt of the state-sync transaction of the block `key`,
+    /// or `None` if its receipt isn't stored. Its logs are indexed after the
+    /// logs of the block's regular transactions.
+    pub(crate) fn state_sync_receipt<TX: TransactionKind>(
+        &self,
+        dbtx: &mut Reader<'_, TX, E>,
+        key: HeaderKey,
+    ) -> Result<Option<TransactionReceipt>> {
+        let stored = match dbtx.read_bor_receipt(key.num)? {
+            Some(stored) => stored,
+            None => return Ok(None),
+        };
+        let tx = self.state_sync_transaction(dbtx, key)?;
+        let first_log_index = dbtx
+            .read_block_logs(key.num)?
+            .iter()
+            .map(|(_, logs)| logs.len())
+            .sum::<usize>();
+
+        let mut receipt = TransactionReceipt {
+            transaction_hash: tx.hash,
+            transaction_index: tx.transaction_index.unwrap_or_default(),
+            block_hash: Some(key.hash),
+            block_number: Some(key.num.into()),
+            cumulative_gas_used: stored.cumulative_gas_used.into(),
+            gas_used: Some(0.into()),
+            status: Some(stored.status.into()),
+            transaction_type: tx.transaction_type,
+            effective_gas_price: Some(0.into()),
+            ..Default::default()
+        };
+        for (tx_log_idx, log) in stored.logs.into_iter().enumerate() {
+            accrue(&mut receipt.logs_bloom.0, log.address.as_bytes());
+            for topic in &log.topics {
+                accrue(&mut receipt.logs_bloom.0, topic.as_bytes());
+            }
+            receipt.logs.push(Log {
+                address: log.address,
+                topics: log.topics,
+                data: log.data.into(),
+                block_hash: Some(key.hash),
+                block_number: Some(key.num.into()),
+                transaction_hash: Some(tx.hash),
+                transaction_index: tx.transaction_index,
+                log_index: Some((first_log_index + tx_log_idx).into()),
+                transaction_log_index: Some(tx_log_idx.into()),
+                removed: Some(false),
+                ..Default::default()
+            });
+        }
+        Ok(Some(receipt))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        builder::SystemTxs,
+        client::Either,
+        models::{Log as DbLog, Receipt},
+        test::{chain::ChainBuilder, rand::rand_vec, TMP_DIR},
+    };
+    use akula::models::MessageWithSignature;
+    use ethers::types::Address;
+    use rand::thread_rng;
+
+    #[test]
+    fn test_state_sync_tx_hash() {
+        let hash = H256::repeat_byte(0xab);
+        let preimage = [
+            &b"matic-bor-receipt-"[..],
+            &[0, 0, 0, 0, 0x01, 0x7d, 0x78, 0x40],
+            hash.as_bytes(),
+        ]
+        .concat();
+        assert_eq!(
+            state_sync_tx_hash(HeaderKey::new(25_000_000, hash)),
+            H256(keccak256(preimage))
+        );
+    }
+
+    #[test]
+    fn test_state_sync_tx() -> Result<()> {
+        let mut rng = thread_rng();
+        let txs: Vec<MessageWithSignature> = rand_vec(&mut rng, 1);
+        let log = DbLog {
+            address: Address::repeat_byte(0x11),
+            topics: vec![H256::repeat_byte(0x22)],
+            data: vec![0xab; 4].into(),
+        };
+        let chain = ChainBuilder::new()
+            .block(|b| {
+                b.txs(txs)
+                    .receipt(
+                        Receipt {
+                            status: 1,
+                            cumulative_gas_used: 21_000,
+                            ..Default::default()
+                        },
+                        vec![log.clone()],
+                    )
+                    .state_sync(vec![log.clone(), log])
+            })
+            .block(|b| b)
+            .write(TMP_DIR.clone())?;
+        let db = Client::<mdbx::NoWriteMap>::open_new(chain.path.clone())?;
+        let key = HeaderKey::new(1, chain.hash(0));
+        let hash = state_sync_tx_hash(key);
+
+        let tx = db.get_transaction(hash)?.unwrap();
+        assert_eq!(tx.hash, hash);
+        assert_eq!(tx.block_hash, Some(key.hash));
+        assert_eq!(tx.transaction_index, Some(1.into()));
+
+        let receipt = match db.get_transaction_receipt(hash)? {
+            Either::Right(Some(receipt)) => receipt,
+            _ => panic!("missing state-sync receipt"),
+        };
+        assert_eq!(receipt.status, Some(1.into()));
+        assert_eq!(receipt.cumulative_gas_used, 21_000.into());
+        assert_eq!(receipt.logs.len(), 2);
+        // after the log of the block's one regular tx
+        assert_eq!(receipt.logs[0].log_index, Some(1.into()));
+        assert_eq!(receipt.logs[1].transaction_log_index, Some(1.into()));
+
+        // block 2 had no state syncs
+        let block2 = state_sync_tx_hash(HeaderKey::new(2, chain.hash(1)));
+        assert!(db.get_transaction(block2).is_err());
+
+        // the polygon preset leaves them out
+        let db = Client::<mdbx::NoWriteMap>::builder()
+            .path(chain.path.clone())
+            .system_txs(SystemTxs::polygon())
+            .build()?;
+        assert_eq!(db.get_transaction(hash)?, None);
+        assert!(matches!(
+            db.get_transaction_receipt(hash)?,
+            Either::Right(None)
+        ));
+        assert_eq!(
+            db.get_block_with_txs(key.hash)?.unwrap().transactions.len(),
+            1
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,159 @@
+use anyhow::Result;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+
+/// Returned (wrapped in an `anyhow::Error`) when a read is interrupted by its budget.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interrupted {
+    #[error("read deadline exceeded")]
+    Timeout,
+    #[error("read cancelled")]
+    Cancelled,
+}
+
+/// A shareable flag used to cancel in-flight reads from another thread or task.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed)
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// An optional deadline and cancellation token checked between the items of
+/// long cursor walks. The default budget is unbounded.
+#[derive(Debug, Clone, Default)]
+pub struct ReadBudget {
+    deadline: Option<Instant>,
+    cancel: Option<CancelToken>,
+}
+
+impl ReadBudget {
+    pub fn unbounded() -> Self {
+        Self::default()
+    }
+
+    /// A budget which expires `timeout` from now.
+    pub fn timeout(timeout: Duration) -> Self {
+        Self::default().deadline(Instant::now() + timeout)
+    }
+
+    pub fn deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    pub fn cancel_token(mut self, token: CancelToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    pub fn is_unbounded(&self) -> bool {
+        self.deadline.is_none() && self.cancel.is_none()
+    }
+
+    /// Returns this budget, or if it's unbounded and `timeout` is set, a
+    /// budget which expires `timeout` from now.
+    pub fn or_timeout(&self, timeout: Option<Duration>) -> Self {
+        match timeout {
+            Some(timeout) if self.is_unbounded() => Self::timeout(timeout),
+            _ => self.clone(),
+        }
+    }
+
+    /// Returns an error if the budget has been cancelled or its deadline has passed.
+    pub fn check(&self) -> Result<()> {
+        if matches!(&self.cancel, Some(cancel) if cancel.is_cancelled()) {
+            return Err(Interrupted::Cancelled.into());
+        }
+        if self.deadline.map_or(false, |d| Instant::now() >= d) {
+            return Err(Interrupted::Timeout.into());
+        }
+        Ok(())
+    }
+}
+
+/// Iterator adapter which checks a `ReadBudget` before yielding each item. Once
+/// the budget is exhausted, a single `Interrupted` error is yielded and the
+/// iterator is fused.
+pub struct Budgeted<I> {
+    inner: I,
+    budget: ReadBudget,
+    done: bool,
+}
+
+impl<I> Budgeted<I> {
+    pub fn new(inner: I, budget: ReadBudget) -> Self {
+        Self {
+            inner,
+            budget,
+            done: false,
+        }
+    }
+}
+
+impl<T, I: Iterator<Item = Result<T>>> Iterator for Budgeted<I> {
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        if let Err(e) = self.budget.check() {
+            self.done = true;
+            return Some(Err(e));
+        }
+        self.inner.next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_budgeted_cancel() {
+        let token = CancelToken::new();
+        let budget = ReadBudget::unbounded().cancel_token(token.clone());
+        let mut it = Budgeted::new((0..).map(Ok::<_, anyhow::Error>), budget);
+
+        assert_eq!(it.next().unwrap().unwrap(), 0);
+        token.cancel();
+        let err = it.next().unwrap().unwrap_err();
+        assert_eq!(err.downcast_ref(), Some(&Interrupted::Cancelled));
+        assert!(it.next().is_none());
+    }
+
+    #[test]
+    fn test_budgeted_timeout() {
+        let budget = ReadBudget::timeout(Duration::ZERO);
+        let mut it = Budgeted::new((0..).map(Ok::<_, anyhow::Error>), budget);
+        let err = it.next().unwrap().unwrap_err();
+        assert_eq!(err.downcast_ref(), Some(&Interrupted::Timeout));
+    }
+
+    #[test]
+    fn test_or_timeout() {
+        let timeout = Some(Duration::from_secs(60));
+        assert!(ReadBudget::unbounded().or_timeout(None).is_unbounded());
+        assert!(!ReadBudget::unbounded().or_timeout(timeout).is_unbounded());
+        // a bounded budget is kept as is
+        let expired = ReadBudget::timeout(Duration::ZERO).or_timeout(timeout);
+        assert!(expired.check().is_err());
+    }
+}
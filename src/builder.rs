@@ -0,0 +1,563 @@
+use akula::kv::mdbx::MdbxEnvironment;
+use anyhow::{format_err, Result};
+use ethers::types::{Address, Chain, H160};
+use mdbx::EnvironmentKind;
+use std::{marker::PhantomData, path::PathBuf, sync::Arc, time::Duration};
+use thiserror::Error;
+
+use crate::{
+    admission::AdmissionConfig,
+    cache::CacheConfig,
+    client::Client,
+    codec::{CustomTxDecoder, CustomTxTypes},
+    output::{label_addresses, AddressLabeler, OutputConfig},
+    prefetch::PrefetchConfig,
+    receipts::StatusBackfill,
+    snapshot::{SnapshotInventory, SnapshotTxIndex},
+    utils::open_db,
+};
+
+/// How the environment is expected to be shared with other processes.
+///
+/// Supported concurrent access:
+///
+/// | Erigon                     | `Offline`                   | `LiveNode` |
+/// |----------------------------|-----------------------------|------------|
+/// | not running                | yes                         | yes        |
+/// | running                    | only with compatible flags  | yes        |
+/// | running in exclusive mode  | no                          | no         |
+///
+/// Both modes open the environment read-only and check that the lock file can
+/// be shared. `LiveNode` additionally sets `MDBX_ACCEDE`, so the flags chosen by
+/// the running node are adopted instead of causing the open to fail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenMode {
+    Offline,
+    LiveNode,
+}
+
+impl Default for OpenMode {
+    fn default() -> Self {
+        Self::LiveNode
+    }
+}
+
+/// Options passed through to mdbx when opening the environment.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OpenOptions {
+    pub mode: OpenMode,
+    /// Maximum number of concurrent read transactions. Uses the mdbx default if unset.
+    pub max_readers: Option<u64>,
+}
+
+impl OpenOptions {
+    pub fn live_node() -> Self {
+        Self {
+            mode: OpenMode::LiveNode,
+            ..Default::default()
+        }
+    }
+
+    pub fn offline() -> Self {
+        Self {
+            mode: OpenMode::Offline,
+            ..Default::default()
+        }
+    }
+}
+
+/// Where the middleware sends a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Route {
+    Db,
+    Inner,
+}
+
+/// Default request routing used by `DbMiddleware`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Routing {
+    /// Where to send state reads at a specific (historical) block.
+    pub historical_state: Route,
+    /// Retry a request against the inner provider if the db read fails.
+    pub fallback_on_error: bool,
+}
+
+impl Default for Routing {
+    fn default() -> Self {
+        Self {
+            historical_state: Route::Inner,
+            fallback_on_error: false,
+        }
+    }
+}
+
+/// Optional behaviour which can be toggled per client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Features {
+    /// Recover transaction senders from their signatures when they are missing
+    /// from the db. If disabled, a missing sender is an error.
+    pub recover_senders: bool,
+    /// Treat the Execution stage progress as the head when it is behind the
+    /// headers, so "latest" never refers to a block without executed state.
+    /// Useful when reading from a node which is still syncing.
+    pub execution_safe_head: bool,
+    /// Check every header, block body and receipt list read against the
+    /// commitments in its block header before returning it: the header's hash
+    /// against its key, and the transactions and receipts against their roots.
+    /// Meant for datadirs copied from untrusted sources, at the cost of reading
+    /// a whole block to return any one of its transactions or receipts.
+    ///
+    /// Logs returned by `get_logs` and log filters are read from the log
+    /// tables alone and are not verified.
+    pub verify: bool,
+}
+
+impl Default for Features {
+    fn default() -> Self {
+        Self {
+            recover_senders: true,
+            execution_safe_head: false,
+            verify: false,
+        }
+    }
+}
+
+/// Data a node may prune, named after Erigon's `--prune` flags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrunedData {
+    /// `h`: account and storage history, for state at past blocks.
+    History,
+    /// `r`: receipts and logs.
+    Receipts,
+    /// `t`: the transaction hash index.
+    TxLookup,
+    /// `c`: the call trace index.
+    CallTraces,
+}
+
+impl std::fmt::Display for PrunedData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::History => "state history",
+            Self::Receipts => "receipts",
+            Self::TxLookup => "the transaction hash index",
+            Self::CallTraces => "call traces",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Returned (wrapped in an `anyhow::Error`) by reads disabled by the client's
+/// `PrunedProfile`. `DbMiddleware` sends them to the inner provider.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("{method} reads {data}, which the node prunes")]
+pub struct Pruned {
+    pub method: &'static str,
+    pub data: PrunedData,
+}
+
+/// Which data the node prunes. A pruned node keeps only the most recent
+/// blocks' worth, so rather than serving some blocks and failing on others,
+/// the client disables the reads which need it.
+///
+/// A full node, Erigon's `--prune=hrtc`, serves:
+///
+/// | Read                                         | Served from    |
+/// |----------------------------------------------|----------------|
+/// | headers, blocks, uncles, withdrawals         | the db         |
+/// | latest balances, nonces, code and storage    | the db         |
+/// | state at past blocks                         | inner provider |
+/// | receipts, logs and log filters               | inner provider |
+/// | transactions, receipts and proofs by hash    | inner provider |
+/// | `gas_profile`, `block_fee_breakdown`         | disabled       |
+/// | `audit_account`                              | disabled       |
+///
+/// Reads from the inner provider are made by `DbMiddleware`; called on the
+/// `Client`, they fail with `Pruned`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PrunedProfile {
+    pub history: bool,
+    pub receipts: bool,
+    pub tx_lookup: bool,
+    pub call_traces: bool,
+}
+
+impl Default for PrunedProfile {
+    fn default() -> Self {
+        Self::full_node()
+    }
+}
+
+impl PrunedProfile {
+    /// Erigon's `--prune=hrtc`, which prunes everything it can.
+    pub fn full_node() -> Self {
+        Self {
+            history: true,
+            receipts: true,
+            tx_lookup: true,
+            call_traces: true,
+        }
+    }
+
+    pub fn prunes(&self, data: PrunedData) -> bool {
+        match data {
+            PrunedData::History => self.history,
+            PrunedData::Receipts => self.receipts,
+            PrunedData::TxLookup => self.tx_lookup,
+            PrunedData::CallTraces => self.call_traces,
+        }
+    }
+}
+
+// 0xdeaddeaddeaddeaddeaddeaddeaddeaddead0001
+const OP_L1_ATTRIBUTES_DEPOSITOR: Address = H160([
+    0xde, 0xad, 0xde, 0xad, 0xde, 0xad, 0xde, 0xad, 0xde, 0xad, 0xde, 0xad, 0xde, 0xad, 0xde, 0xad,
+    0xde, 0xad, 0x00, 0x01,
+]);
+
+/// Which transactions are system transactions, and whether reads return them.
+///
+/// System transactions are identified by their sender. Excluded ones are left
+/// out of block transaction lists, transaction counts, receipts and single
+/// transaction lookups; the remaining transactions keep their index in the
+/// block. Logs are not filtered.
+///
+/// Polygon's state-sync transactions aren't stored with the block's
+/// transactions and have no sender, so they are matched by `state_syncs`
+/// instead. They are only reached by hash, so excluding them leaves their
+/// transaction and receipt lookups empty.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SystemTxs {
+    pub senders: Vec<Address>,
+    pub state_syncs: bool,
+    pub exclude: bool,
+}
+
+impl SystemTxs {
+    /// Excludes the OP-stack L1 attributes deposits. User deposits have
+    /// ordinary senders and are kept. Deposits are decoded by
+    /// `DepositTxDecoder`, registered with `custom_tx_type`.
+    pub fn optimism() -> Self {
+        Self {
+            senders: vec![OP_L1_ATTRIBUTES_DEPOSITOR],
+            state_syncs: false,
+            exclude: true,
+        }
+    }
+
+    /// Excludes Polygon's state-sync transactions.
+    pub fn polygon() -> Self {
+        Self {
+            state_syncs: true,
+            exclude: true,
+            ..Default::default()
+        }
+    }
+
+    /// Returns whether the transactions of `sender` are left out of reads.
+    pub fn excludes(&self, sender: &Address) -> bool {
+        self.exclude && self.senders.contains(sender)
+    }
+
+    /// Returns whether Polygon's state-sync transactions are left out of reads.
+    pub fn excludes_state_syncs(&self) -> bool {
+        self.exclude && self.state_syncs
+    }
+
+    /// Returns whether any transaction can be left out by its sender, in
+    /// which case reads need every sender.
+    pub fn is_filtering(&self) -> bool {
+        self.exclude && !self.senders.is_empty()
+    }
+}
+
+/// Settings shared by every clone of a `Client`.
+#[derive(Debug, Clone, Default)]
+pub struct ClientOptions {
+    pub chain: Option<Chain>,
+    pub routing: Routing,
+    pub features: Features,
+    /// Maximum number of threads used to scan the blocks of a wide `get_logs`
+    /// filter. Zero or one scans on the calling thread.
+    pub log_parallelism: usize,
+    /// Reads taking at least this long are logged with `tracing` at the warn
+    /// level, under the `ethers_db::slow_read` target. Lazy iterators such as
+    /// `stream_logs` and `dump_state_at` aren't timed, as they read after
+    /// returning; their collecting and paging counterparts are.
+    pub slow_read_threshold: Option<Duration>,
+    /// The time each read transaction may spend in long cursor walks, counted
+    /// from when its reader is opened. See `ReadBudget::timeout`.
+    pub read_timeout: Option<Duration>,
+    /// How the serving layers format their JSON output.
+    pub output: OutputConfig,
+    /// Labels the addresses in the serving layers' output. See `format_output`.
+    pub labeler: Option<Arc<dyn AddressLabeler>>,
+    pub system_txs: SystemTxs,
+    /// Decoders for transaction types the built-in decoding rejects, used by
+    /// block and transaction reads.
+    pub custom_txs: CustomTxTypes,
+    /// Derives a status for pre-Byzantium receipts, which only carry a state
+    /// root. `None` leaves their status unset.
+    pub status_backfill: Option<Arc<dyn StatusBackfill>>,
+    /// Disables the reads needing data the node prunes. `None` for an archive
+    /// node.
+    pub pruned: Option<PrunedProfile>,
+}
+
+impl ClientOptions {
+    /// Formats the JSON `value` as the serving layers do: rewritten by the
+    /// `OutputConfig`, with labels attached by the `labeler`. Exporters writing
+    /// JSON can call it to match.
+    pub fn format_output(&self, value: &mut serde_json::Value) {
+        self.output.apply(value);
+        if let Some(labeler) = &self.labeler {
+            label_addresses(labeler.as_ref(), value);
+        }
+    }
+}
+
+/// Configures and opens a `Client`.
+#[derive(Debug, Clone)]
+pub struct ClientBuilder<E: EnvironmentKind> {
+    chaindata: Option<PathBuf>,
+    snapshots: Option<PathBuf>,
+    txpool: Option<PathBuf>,
+    #[cfg(feature = "sqlite")]
+    userdata: Option<PathBuf>,
+    cache: CacheConfig,
+    open: OpenOptions,
+    prefetch: Option<PrefetchConfig>,
+    admission: Option<AdmissionConfig>,
+    read_trace: Option<usize>,
+    options: ClientOptions,
+    _env: PhantomData<E>,
+}
+
+impl<E: EnvironmentKind> Default for ClientBuilder<E> {
+    fn default() -> Self {
+        Self {
+            chaindata: None,
+            snapshots: None,
+            txpool: None,
+            #[cfg(feature = "sqlite")]
+            userdata: None,
+            cache: Default::default(),
+            open: Default::default(),
+            prefetch: None,
+            admission: None,
+            read_trace: None,
+            options: Default::default(),
+            _env: PhantomData,
+        }
+    }
+}
+
+impl<E: EnvironmentKind> ClientBuilder<E> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Path to the chaindata directory (the directory containing `mdbx.dat`).
+    pub fn path<P: Into<PathBuf>>(mut self, chaindata: P) -> Self {
+        self.chaindata = Some(chaindata.into());
+        self
+    }
+
+    /// Path to an Erigon datadir. The chaindata lives at `<datadir>/chaindata`,
+    /// snapshots at `<datadir>/snapshots` and the txpool db at `<datadir>/txpool`,
+    /// each only if the directory exists.
+    pub fn datadir<P: Into<PathBuf>>(mut self, datadir: P) -> Self {
+        let datadir = datadir.into();
+        let snapshots = datadir.join("snapshots");
+        if snapshots.is_dir() {
+            self.snapshots = Some(snapshots);
+        }
+        let txpool = datadir.join("txpool");
+        if txpool.is_dir() {
+            self.txpool = Some(txpool);
+        }
+        self.chaindata = Some(datadir.join("chaindata"));
+        self
+    }
+
+    /// Path to a directory of snapshot files, whose transaction hash indices are
+    /// used for lookups that miss the TxLookup table.
+    pub fn snapshots<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.snapshots = Some(dir.into());
+        self
+    }
+
+    /// Path to the txpool db directory, which is opened with the same open
+    /// options as the chaindata.
+    pub fn txpool<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.txpool = Some(dir.into());
+        self
+    }
+
+    /// Path to the SQLite file of the userdata store, created if missing. See
+    /// `Client::userdata`.
+    #[cfg(feature = "sqlite")]
+    pub fn userdata<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.userdata = Some(path.into());
+        self
+    }
+
+    pub fn chain(mut self, chain: Chain) -> Self {
+        self.options.chain = Some(chain);
+        self
+    }
+
+    pub fn cache_config(mut self, config: CacheConfig) -> Self {
+        self.cache = config;
+        self
+    }
+
+    pub fn open_options(mut self, open: OpenOptions) -> Self {
+        self.open = open;
+        self
+    }
+
+    pub fn routing(mut self, routing: Routing) -> Self {
+        self.options.routing = routing;
+        self
+    }
+
+    pub fn features(mut self, features: Features) -> Self {
+        self.options.features = features;
+        self
+    }
+
+    pub fn log_parallelism(mut self, threads: usize) -> Self {
+        self.options.log_parallelism = threads;
+        self
+    }
+
+    pub fn slow_read_threshold(mut self, threshold: Duration) -> Self {
+        self.options.slow_read_threshold = Some(threshold);
+        self
+    }
+
+    /// Leaves system transactions out of reads. See `SystemTxs`.
+    pub fn system_txs(mut self, system_txs: SystemTxs) -> Self {
+        self.options.system_txs = system_txs;
+        self
+    }
+
+    /// Matches the JSON written by the serving layers to a node's RPC output,
+    /// e.g. `OutputConfig::geth()`.
+    pub fn output(mut self, config: OutputConfig) -> Self {
+        self.options.output = config;
+        self
+    }
+
+    /// Decodes the transactions of type `tx_type` with `decoder`, so blocks
+    /// holding them can be read. See `CustomTxDecoder`.
+    pub fn custom_tx_type<D: CustomTxDecoder + 'static>(mut self, tx_type: u8, decoder: D) -> Self {
+        self.options.custom_txs.register(tx_type, decoder);
+        self
+    }
+
+    /// Attaches labels from `labeler` to the addresses in the serving layers'
+    /// output, e.g. the client's `UserData`.
+    pub fn labeler<L: AddressLabeler + 'static>(mut self, labeler: L) -> Self {
+        self.options.labeler = Some(Arc::new(labeler));
+        self
+    }
+
+    /// Fills in the status of pre-Byzantium receipts with `backfill`, so they
+    /// have the same fields as later ones.
+    pub fn status_backfill<B: StatusBackfill + 'static>(mut self, backfill: B) -> Self {
+        self.options.status_backfill = Some(Arc::new(backfill));
+        self
+    }
+
+    /// Disables the reads needing data a pruned node doesn't keep, so they
+    /// fail up front and `DbMiddleware` delegates them. See `PrunedProfile`.
+    pub fn pruned(mut self, profile: PrunedProfile) -> Self {
+        self.options.pruned = Some(profile);
+        self
+    }
+
+    /// Bounds the cursor walks of each read transaction to `timeout` from when
+    /// its reader is opened. For deadlines or cancellation tokens spanning a
+    /// whole request, see `Client::with_budget`.
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.options.read_timeout = Some(timeout);
+        self
+    }
+
+    /// Reads ahead of sequential block and transaction scans on a background
+    /// thread. Disabled by default.
+    pub fn prefetch(mut self, config: PrefetchConfig) -> Self {
+        self.prefetch = Some(config);
+        self
+    }
+
+    /// Limits the number of heavy queries (`get_logs`, withdrawal totals and
+    /// warming) running at once. Unlimited by default.
+    pub fn admission(mut self, config: AdmissionConfig) -> Self {
+        self.admission = Some(config);
+        self
+    }
+
+    /// Records the last `capacity` table reads, for `Client::last_read_trace`.
+    /// Disabled by default.
+    pub fn read_trace(mut self, capacity: usize) -> Self {
+        self.read_trace = Some(capacity);
+        self
+    }
+
+    /// Opens the environment and returns the configured client.
+    pub fn build(self) -> Result<Client<E>> {
+        let chaindata = self
+            .chaindata
+            .clone()
+            .ok_or_else(|| format_err!("ClientBuilder requires a path or datadir"))?;
+        let env = open_db(chaindata, &self.open)?;
+        let snapshots = match &self.snapshots {
+            Some(dir) => {
+                for seg in SnapshotInventory::open(dir)?.missing() {
+                    tracing::warn!(segment = %seg.name, "snapshot segment missing from disk");
+                }
+                Some(Arc::new(SnapshotTxIndex::open(dir)?))
+            }
+            None => None,
+        };
+        let txpool = match &self.txpool {
+            Some(dir) => Some(Arc::new(open_db(dir.clone(), &self.open)?)),
+            None => None,
+        };
+        #[cfg(feature = "sqlite")]
+        let userdata = match &self.userdata {
+            Some(path) => Some(crate::userdata::UserData::open(path)?),
+            None => None,
+        };
+        let prefetch = self.prefetch;
+        let client = self
+            .configure(env)
+            .with_snapshots(snapshots)
+            .with_txpool(txpool);
+        #[cfg(feature = "sqlite")]
+        let client = client.with_userdata(userdata);
+        match prefetch {
+            Some(config) => client.with_prefetch(config),
+            None => Ok(client),
+        }
+    }
+
+    /// Returns a client configured by this builder around an already open environment.
+    /// The path and open options are ignored.
+    pub fn configure(self, env: MdbxEnvironment<E>) -> Client<E> {
+        let client = Client::new(env)
+            .with_cache_config(self.cache)
+            .with_options(Arc::new(self.options));
+        let client = match self.read_trace {
+            Some(capacity) => client.with_read_trace(capacity),
+            None => client,
+        };
+        match self.admission {
+            Some(config) => client.with_admission(config),
+            None => client,
+        }
+    }
+}
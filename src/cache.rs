@@ -0,0 +1,356 @@
+use akula::models as ak_models;
+use ethers::types::{Address, TransactionReceipt, H256};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::Hash,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::types::{BlockNum, HeaderKey};
+
+const MIB: usize = 1 << 20;
+
+/// Memory budget for the caches kept by a `Client`. A budget of 0 disables the
+/// corresponding cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheConfig {
+    /// Bytes of contract code, keyed by codehash.
+    pub code_bytes: usize,
+    /// Number of decoded block headers.
+    pub header_entries: usize,
+    /// Approximate bytes of decoded block receipts.
+    pub receipt_bytes: usize,
+    /// Number of keys recently found missing: unknown transaction hashes and
+    /// addresses without an account.
+    pub missing_entries: usize,
+    /// How long a key found missing is remembered, at most. Every entry is
+    /// also dropped when the head or the Execution or TxLookup stages move.
+    pub missing_ttl: Duration,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            code_bytes: 32 * MIB,
+            header_entries: 1024,
+            receipt_bytes: 16 * MIB,
+            missing_entries: 4096,
+            missing_ttl: Duration::from_secs(30),
+        }
+    }
+}
+
+impl CacheConfig {
+    /// A config with every cache disabled.
+    pub fn disabled() -> Self {
+        Self {
+            code_bytes: 0,
+            header_entries: 0,
+            receipt_bytes: 0,
+            missing_entries: 0,
+            missing_ttl: Duration::ZERO,
+        }
+    }
+}
+
+/// Current usage of a single cache, in the cache's unit (bytes or entries).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheUsage {
+    pub used: usize,
+    pub capacity: usize,
+    pub entries: usize,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// A snapshot of the usage of every cache kept by a `Client`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheMetrics {
+    pub code: CacheUsage,
+    pub headers: CacheUsage,
+    pub receipts: CacheUsage,
+    pub missing: CacheUsage,
+}
+
+impl CacheMetrics {
+    /// Total bytes held by the byte-bounded caches.
+    pub fn total_bytes(&self) -> usize {
+        self.code.used + self.receipts.used
+    }
+}
+
+/// A least-recently-used map bounded by the total weight of its entries.
+#[derive(Debug)]
+pub(crate) struct Lru<K, V> {
+    capacity: usize,
+    used: usize,
+    tick: u64,
+    entries: HashMap<K, (V, usize, u64)>,
+    order: BTreeMap<u64, K>,
+    hits: u64,
+    misses: u64,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> Lru<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            used: 0,
+            tick: 0,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    pub fn get(&mut self, key: &K) -> Option<V> {
+        match self.entries.get_mut(key) {
+            Some((val, _, last_used)) => {
+                self.order.remove(last_used);
+                self.tick += 1;
+                *last_used = self.tick;
+                self.order.insert(self.tick, key.clone());
+                self.hits += 1;
+                Some(val.clone())
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Inserts `val`, evicting the least recently used entries until it fits.
+    /// Values heavier than the whole cache are not stored.
+    pub fn insert(&mut self, key: K, val: V, weight: usize) {
+        if weight > self.capacity {
+            return;
+        }
+        if let Some((_, old_weight, last_used)) = self.entries.remove(&key) {
+            self.order.remove(&last_used);
+            self.used -= old_weight;
+        }
+        while self.used + weight > self.capacity {
+            let oldest = match self.order.keys().next() {
+                Some(tick) => *tick,
+                None => break,
+            };
+            if let Some(k) = self.order.remove(&oldest) {
+                if let Some((_, w, _)) = self.entries.remove(&k) {
+                    self.used -= w;
+                }
+            }
+        }
+        self.tick += 1;
+        self.used += weight;
+        self.order.insert(self.tick, key.clone());
+        self.entries.insert(key, (val, weight, self.tick));
+    }
+
+    pub fn remove(&mut self, key: &K) {
+        if let Some((_, weight, last_used)) = self.entries.remove(key) {
+            self.order.remove(&last_used);
+            self.used -= weight;
+        }
+    }
+
+    /// Drops every entry, keeping the hit and miss counts.
+    pub fn clear(&mut self) {
+        self.used = 0;
+        self.entries.clear();
+        self.order.clear();
+    }
+
+    pub fn usage(&self) -> CacheUsage {
+        CacheUsage {
+            used: self.used,
+            capacity: self.capacity,
+            entries: self.entries.len(),
+            hits: self.hits,
+            misses: self.misses,
+        }
+    }
+}
+
+/// A key found missing from the db.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum MissingKey {
+    Tx(H256),
+    Account(Address),
+}
+
+/// How far the db has synced. Headers are written before their blocks are
+/// executed and their transactions indexed, so a key missing at one head can
+/// appear without the head moving, once the Execution or TxLookup stage
+/// catches up.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct SyncProgress {
+    pub head: H256,
+    pub execution: Option<BlockNum>,
+    pub tx_lookup: Option<BlockNum>,
+}
+
+/// Keys recently found missing, so repeated lookups of garbage input don't
+/// reach the db. A new block, a reorg or a stage catching up can add any of
+/// them, so the entries only hold while the sync progress they were found
+/// missing at is unchanged.
+#[derive(Debug)]
+pub(crate) struct MissingKeys {
+    ttl: Duration,
+    progress: SyncProgress,
+    entries: Lru<MissingKey, Instant>,
+}
+
+impl MissingKeys {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            ttl,
+            progress: SyncProgress::default(),
+            entries: Lru::new(capacity),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.entries.capacity > 0 && !self.ttl.is_zero()
+    }
+
+    /// Returns whether `key` was found missing at `progress` within the TTL.
+    pub fn contains(&mut self, key: &MissingKey, progress: SyncProgress) -> bool {
+        self.set_progress(progress);
+        match self.entries.get(key) {
+            Some(found) if found.elapsed() < self.ttl => true,
+            Some(_) => {
+                self.entries.remove(key);
+                false
+            }
+            None => false,
+        }
+    }
+
+    pub fn insert(&mut self, key: MissingKey, progress: SyncProgress) {
+        self.set_progress(progress);
+        self.entries.insert(key, Instant::now(), 1);
+    }
+
+    fn set_progress(&mut self, progress: SyncProgress) {
+        if progress != self.progress {
+            self.entries.clear();
+            self.progress = progress;
+        }
+    }
+
+    pub fn usage(&self) -> CacheUsage {
+        self.entries.usage()
+    }
+}
+
+/// The caches shared by every clone of a `Client`. Cached values are keyed by
+/// content hash or (number, hash) pairs, so they never need to be invalidated.
+/// Missing keys are the exception, see `MissingKeys`.
+#[derive(Debug)]
+pub(crate) struct Caches {
+    pub code: Mutex<Lru<H256, bytes::Bytes>>,
+    pub headers: Mutex<Lru<HeaderKey, ak_models::BlockHeader>>,
+    pub receipts: Mutex<Lru<HeaderKey, Arc<Vec<TransactionReceipt>>>>,
+    pub missing: Mutex<MissingKeys>,
+}
+
+impl Caches {
+    pub fn new(config: CacheConfig) -> Self {
+        Self {
+            code: Mutex::new(Lru::new(config.code_bytes)),
+            headers: Mutex::new(Lru::new(config.header_entries)),
+            receipts: Mutex::new(Lru::new(config.receipt_bytes)),
+            missing: Mutex::new(MissingKeys::new(config.missing_entries, config.missing_ttl)),
+        }
+    }
+
+    pub fn metrics(&self) -> CacheMetrics {
+        CacheMetrics {
+            code: usage(&self.code),
+            headers: usage(&self.headers),
+            receipts: usage(&self.receipts),
+            missing: self.missing.lock().map(|c| c.usage()).unwrap_or_default(),
+        }
+    }
+}
+
+impl Default for Caches {
+    fn default() -> Self {
+        Self::new(CacheConfig::default())
+    }
+}
+
+fn usage<K: Hash + Eq + Clone, V: Clone>(cache: &Mutex<Lru<K, V>>) -> CacheUsage {
+    cache.lock().map(|c| c.usage()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lru_eviction() {
+        let mut lru = Lru::new(10);
+        lru.insert(1, "a", 4);
+        lru.insert(2, "b", 4);
+        // touch 1 so 2 is evicted first
+        assert_eq!(lru.get(&1), Some("a"));
+        lru.insert(3, "c", 4);
+
+        assert_eq!(lru.get(&2), None);
+        assert_eq!(lru.get(&1), Some("a"));
+        assert_eq!(lru.get(&3), Some("c"));
+        let usage = lru.usage();
+        assert_eq!(usage.used, 8);
+        assert_eq!(usage.hits, 3);
+        assert_eq!(usage.misses, 1);
+
+        // too heavy to cache at all
+        lru.insert(4, "d", 11);
+        assert_eq!(lru.get(&4), None);
+    }
+
+    #[test]
+    fn test_missing_keys() {
+        let head = SyncProgress {
+            head: H256::repeat_byte(1),
+            ..Default::default()
+        };
+        let next = SyncProgress {
+            head: H256::repeat_byte(2),
+            ..head
+        };
+        let executed = SyncProgress {
+            execution: Some(BlockNum(1)),
+            ..head
+        };
+        let key = MissingKey::Tx(H256::repeat_byte(0xaa));
+        let mut missing = MissingKeys::new(2, Duration::from_secs(60));
+        assert!(missing.is_enabled());
+        assert!(!missing.contains(&key, head));
+        missing.insert(key, head);
+        assert!(missing.contains(&key, head));
+        assert!(!missing.contains(&MissingKey::Account(Address::zero()), head));
+
+        // a new head drops every entry
+        assert!(!missing.contains(&key, next));
+        missing.insert(key, next);
+        assert!(!missing.contains(&key, head));
+
+        // as does a stage catching up to the same head
+        missing.insert(key, head);
+        assert!(!missing.contains(&key, executed));
+
+        // so do expired entries
+        let mut missing = MissingKeys::new(2, Duration::from_millis(10));
+        missing.insert(key, head);
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(!missing.contains(&key, head));
+        assert_eq!(missing.usage().entries, 0);
+        assert!(!MissingKeys::new(0, Duration::from_secs(1)).is_enabled());
+    }
+}
@@ -0,0 +1,266 @@
+//! A minimal CBOR codec for the values Erigon stores with ugorji/go/codec,
+//! such as receipts and logs.
+
+use anyhow::{format_err, Result};
+use bytes::{Buf, BufMut};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Uint(u64),
+    /// A negative integer `-1 - n`, stored as `n`.
+    Neg(u64),
+    Bytes(Vec<u8>),
+    Text(String),
+    Array(Vec<Value>),
+    Map(Vec<(Value, Value)>),
+    Bool(bool),
+    Null,
+}
+
+impl Value {
+    pub fn as_u64(&self) -> Result<u64> {
+        match self {
+            Value::Uint(n) => Ok(*n),
+            Value::Null => Ok(0),
+            _ => Err(format_err!("expected cbor uint, got {:?}", self)),
+        }
+    }
+
+    pub fn as_bytes(&self) -> Result<&[u8]> {
+        match self {
+            Value::Bytes(b) => Ok(b),
+            Value::Null => Ok(&[]),
+            _ => Err(format_err!("expected cbor bytes, got {:?}", self)),
+        }
+    }
+
+    pub fn as_array(&self) -> Result<&[Value]> {
+        match self {
+            Value::Array(a) => Ok(a),
+            Value::Null => Ok(&[]),
+            _ => Err(format_err!("expected cbor array, got {:?}", self)),
+        }
+    }
+
+    /// Returns a field of a struct encoded either as an array (`toarray`) or as
+    /// a map keyed by its `codec:"n"` tag. `idx` is the 0-based field index and
+    /// `tag` the codec tag.
+    pub fn field(&self, idx: usize, tag: &str) -> Option<&Value> {
+        match self {
+            Value::Array(a) => a.get(idx),
+            Value::Map(m) => m.iter().find_map(|(k, v)| match k {
+                Value::Text(t) if t == tag => Some(v),
+                Value::Uint(n) if n.to_string() == tag => Some(v),
+                _ => None,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Decodes one CBOR item from the front of `buf`.
+pub fn decode(buf: &mut &[u8]) -> Result<Value> {
+    if !buf.has_remaining() {
+        return Err(format_err!("unexpected end of cbor"));
+    }
+    let initial = buf.get_u8();
+    let major = initial >> 5;
+    let info = initial & 0x1f;
+
+    if major == 7 {
+        return match info {
+            20 => Ok(Value::Bool(false)),
+            21 => Ok(Value::Bool(true)),
+            22 | 23 => Ok(Value::Null),
+            _ => Err(format_err!("unsupported cbor simple value: {}", info)),
+        };
+    }
+
+    let arg = read_arg(buf, info)?;
+    match major {
+        0 => Ok(Value::Uint(arg)),
+        1 => Ok(Value::Neg(arg)),
+        2 => Ok(Value::Bytes(take(buf, arg)?.to_vec())),
+        3 => Ok(Value::Text(String::from_utf8(take(buf, arg)?.to_vec())?)),
+        4 => (0..arg)
+            .map(|_| decode(buf))
+            .collect::<Result<Vec<_>>>()
+            .map(Value::Array),
+        5 => (0..arg)
+            .map(|_| Ok((decode(buf)?, decode(buf)?)))
+            .collect::<Result<Vec<_>>>()
+            .map(Value::Map),
+        // tags carry no information we need
+        6 => decode(buf),
+        _ => unreachable!(),
+    }
+}
+
+/// Splits a CBOR array into the encodings of its items, without decoding the
+/// array as a whole.
+pub fn split_array(mut buf: &[u8]) -> Result<Vec<&[u8]>> {
+    if !buf.has_remaining() {
+        return Err(format_err!("unexpected end of cbor"));
+    }
+    let initial = buf.get_u8();
+    if initial >> 5 != 4 {
+        return Err(format_err!(
+            "expected cbor array, got major type {}",
+            initial >> 5
+        ));
+    }
+    let len = read_arg(&mut buf, initial & 0x1f)?;
+    let mut items = vec![];
+    for _ in 0..len {
+        let start = buf;
+        decode(&mut buf)?;
+        items.push(&start[..start.len() - buf.len()]);
+    }
+    Ok(items)
+}
+
+/// Appends the encoding of `val` to `out`, using the shortest argument encodings.
+pub fn encode(val: &Value, out: &mut Vec<u8>) {
+    match val {
+        Value::Uint(n) => write_head(out, 0, *n),
+        Value::Neg(n) => write_head(out, 1, *n),
+        Value::Bytes(b) => {
+            write_head(out, 2, b.len() as u64);
+            out.extend_from_slice(b);
+        }
+        Value::Text(t) => {
+            write_head(out, 3, t.len() as u64);
+            out.extend_from_slice(t.as_bytes());
+        }
+        Value::Array(a) => {
+            write_head(out, 4, a.len() as u64);
+            a.iter().for_each(|v| encode(v, out));
+        }
+        Value::Map(m) => {
+            write_head(out, 5, m.len() as u64);
+            for (k, v) in m {
+                encode(k, out);
+                encode(v, out);
+            }
+        }
+        Value::Bool(b) => out.push(0xf4 | *b as u8),
+        Value::Null => out.push(0xf6),
+    }
+}
+
+fn write_head(out: &mut Vec<u8>, major: u8, arg: u64) {
+    let major = major << 5;
+    match arg {
+        0..=23 => out.push(major | arg as u8),
+        24..=0xff => {
+            out.push(major | 24);
+            out.push(arg as u8);
+        }
+        0x100..=0xffff => {
+            out.push(major | 25);
+            out.put_u16(arg as u16);
+        }
+        0x1_0000..=0xffff_ffff => {
+            out.push(major | 26);
+            out.put_u32(arg as u32);
+        }
+        _ => {
+            out.push(major | 27);
+            out.put_u64(arg);
+        }
+    }
+}
+
+fn read_arg(buf: &mut &[u8], info: u8) -> Result<u64> {
+    let need = match info {
+        0..=23 => return Ok(info as u64),
+        24 => 1,
+        25 => 2,
+        26 => 4,
+        27 => 8,
+        _ => return Err(format_err!("unsupported cbor length encoding: {}", info)),
+    };
+    if buf.remaining() < need {
+        return Err(format_err!("unexpected end of cbor"));
+    }
+    Ok(buf.get_uint(need))
+}
+
+fn take<'a>(buf: &mut &'a [u8], n: u64) -> Result<&'a [u8]> {
+    let n = usize::try_from(n)?;
+    if buf.len() < n {
+        return Err(format_err!("unexpected end of cbor"));
+    }
+    let (head, tail) = buf.split_at(n);
+    *buf = tail;
+    Ok(head)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn value() -> impl Strategy<Value = Value> {
+        let leaf = prop_oneof![
+            any::<u64>().prop_map(Value::Uint),
+            any::<u64>().prop_map(Value::Neg),
+            any::<Vec<u8>>().prop_map(Value::Bytes),
+            ".*".prop_map(Value::Text),
+            any::<bool>().prop_map(Value::Bool),
+            Just(Value::Null),
+        ];
+        leaf.prop_recursive(4, 64, 8, |inner| {
+            prop_oneof![
+                prop::collection::vec(inner.clone(), 0..8).prop_map(Value::Array),
+                prop::collection::vec((inner.clone(), inner), 0..8).prop_map(Value::Map),
+            ]
+        })
+    }
+
+    #[test]
+    fn test_decode() -> Result<()> {
+        // [1, h'abcd', {"2": 500}]
+        let enc = hex::decode("830142abcda1613219 01f4".replace(' ', ""))?;
+        let val = decode(&mut &enc[..])?;
+        assert_eq!(val.field(0, "1"), Some(&Value::Uint(1)));
+        assert_eq!(val.field(1, "2").unwrap().as_bytes()?, &[0xab, 0xcd]);
+        assert_eq!(
+            val.field(2, "3").unwrap().field(0, "2"),
+            Some(&Value::Uint(500))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_array() -> Result<()> {
+        let enc = hex::decode("830142abcda161321901f4")?;
+        let items = split_array(&enc)?;
+        assert_eq!(items, vec![&enc[1..2], &enc[2..5], &enc[5..]]);
+        assert!(split_array(&enc[5..]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_encode() {
+        let val = Value::Array(vec![
+            Value::Uint(1),
+            Value::Bytes(vec![0xab, 0xcd]),
+            Value::Map(vec![(Value::Text("2".into()), Value::Uint(500))]),
+        ]);
+        let mut enc = vec![];
+        encode(&val, &mut enc);
+        assert_eq!(hex::encode(enc), "830142abcda161321901f4");
+    }
+
+    proptest! {
+        #[test]
+        fn prop_cbor_roundtrip(val in value()) {
+            let mut enc = vec![];
+            encode(&val, &mut enc);
+            let mut buf = &enc[..];
+            prop_assert_eq!(decode(&mut buf).unwrap(), val);
+            prop_assert!(buf.is_empty());
+        }
+    }
+}
@@ -1,65 +1,652 @@
 use akula::{
     kv::{mdbx::MdbxEnvironment, tables as ak_tables},
-    models as ak_models,
+    models::{self as ak_models, MessageWithSignature},
 };
 use anyhow::{format_err, Result};
-use ethers::core::types::{
-    Address, Block, BlockId, BlockNumber as EthersBlockNumber, TxHash, H256, U256, U64,
+use ethers::{
+    abi::RawLog,
+    contract::{EthEvent, LogMeta},
+    core::types::{
+        Address, Block, BlockId, BlockNumber as EthersBlockNumber, Filter, FilterBlockOption, Log,
+        TxHash, H256, U256, U64,
+    },
+    utils::keccak256,
 };
 use mdbx::{EnvironmentKind, TransactionKind};
-use std::path::PathBuf;
-
-use crate::reader::Reader;
-use crate::utils::{open_db, BlockCast, MsgCast};
-
-// TODO:
-// - receipts
-// - historical data
-// - logs
-// - delegate to inner when data may not be in the db but erigon would reconstruct it
+use once_cell::sync::OnceCell;
+use std::{
+    ops::{Bound, RangeBounds},
+    path::PathBuf,
+    sync::Arc,
+};
+use thiserror::Error;
+
+use crate::{
+    admission::{Admission, AdmissionConfig, AdmissionMetrics, Permit},
+    budget::ReadBudget,
+    builder::{ClientBuilder, ClientOptions, Pruned, PrunedData},
+    cache::{CacheConfig, CacheMetrics, Caches, MissingKey, SyncProgress},
+    codec::{recover_senders, BlockCast, BlockFields, MsgCast, StoredTx},
+    convert,
+    filters::Filters,
+    logs::{self, LogStream},
+    models::Account,
+    page::{paginate, Page, PageCursor},
+    pool::WorkerPool,
+    prefetch::{Prefetch, PrefetchConfig},
+    reader::{OwnedReader, Reader, EXECUTION_STAGE, TX_LOOKUP_STAGE},
+    readtrace::{ReadRecord, ReadTrace},
+    slowlog::SlowRead,
+    snapshot::{BlockRange, SnapshotInventory, SnapshotTxIndex},
+    tables, trie,
+    types::{BlockNum, HeaderKey, TxId},
+    utils::{BlockAssembler, FullTxs, TxHashes},
+};
 
+/// A read-only handle to an Erigon chaindata environment.
+///
+/// The environment is opened once and shared behind an `Arc`, so cloning a
+/// `Client` is cheap and every clone reads from the same environment. `Client`
+/// is `Send + Sync`, and each method begins its own short-lived read-only
+/// transaction, so a single `Client` can be shared freely across threads.
+/// Transactions themselves (`Reader`) are bound to the thread that opened them
+/// and should not be held across await points; use `OwnedReader` when a
+/// `'static` handle is needed.
 #[derive(Debug)]
-pub struct Client<E: EnvironmentKind>(MdbxEnvironment<E>);
+pub struct Client<E: EnvironmentKind> {
+    env: Arc<MdbxEnvironment<E>>,
+    pub(crate) caches: Arc<Caches>,
+    pub(crate) filters: Arc<Filters>,
+    options: Arc<ClientOptions>,
+    snapshots: Option<Arc<SnapshotTxIndex>>,
+    txpool: Option<Arc<MdbxEnvironment<E>>>,
+    budget: ReadBudget,
+    prefetch: Option<Prefetch<E>>,
+    /// Threads scanning the shards of wide `get_logs` filters, started by the
+    /// first such call and shared with every clone.
+    log_workers: Arc<OnceCell<WorkerPool>>,
+    admission: Option<Arc<Admission>>,
+    read_trace: Option<ReadTrace>,
+    #[cfg(feature = "sqlite")]
+    pub(crate) userdata: Option<crate::userdata::UserData>,
+}
+
+impl<E: EnvironmentKind> Clone for Client<E> {
+    fn clone(&self) -> Self {
+        Self {
+            env: Arc::clone(&self.env),
+            caches: Arc::clone(&self.caches),
+            filters: Arc::clone(&self.filters),
+            options: Arc::clone(&self.options),
+            snapshots: self.snapshots.clone(),
+            txpool: self.txpool.clone(),
+            budget: self.budget.clone(),
+            prefetch: self.prefetch.clone(),
+            log_workers: Arc::clone(&self.log_workers),
+            admission: self.admission.clone(),
+            read_trace: self.read_trace.clone(),
+            #[cfg(feature = "sqlite")]
+            userdata: self.userdata.clone(),
+        }
+    }
+}
 
 impl<E: EnvironmentKind> Client<E> {
     pub fn new(db: MdbxEnvironment<E>) -> Self {
-        Self(db)
+        Self {
+            env: Arc::new(db),
+            caches: Default::default(),
+            filters: Default::default(),
+            options: Default::default(),
+            snapshots: None,
+            txpool: None,
+            budget: ReadBudget::default(),
+            prefetch: None,
+            log_workers: Default::default(),
+            admission: None,
+            read_trace: None,
+            #[cfg(feature = "sqlite")]
+            userdata: None,
+        }
+    }
+
+    pub fn builder() -> ClientBuilder<E> {
+        ClientBuilder::new()
     }
 
+    /// Opens the chaindata directory with the default configuration. Use
+    /// `Client::builder` to configure the client.
     pub fn open_new(chaindata_dir: PathBuf) -> Result<Self> {
-        let db = open_db(chaindata_dir)?;
-        Ok(Self(db))
+        Self::builder().path(chaindata_dir).build()
+    }
+
+    pub(crate) fn with_options(mut self, options: Arc<ClientOptions>) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn options(&self) -> &ClientOptions {
+        &self.options
+    }
+
+    pub(crate) fn with_snapshots(mut self, snapshots: Option<Arc<SnapshotTxIndex>>) -> Self {
+        self.snapshots = snapshots;
+        self
+    }
+
+    pub(crate) fn with_txpool(mut self, txpool: Option<Arc<MdbxEnvironment<E>>>) -> Self {
+        self.txpool = txpool;
+        self
+    }
+
+    #[cfg(feature = "sqlite")]
+    pub(crate) fn with_userdata(mut self, userdata: Option<crate::userdata::UserData>) -> Self {
+        self.userdata = userdata;
+        self
+    }
+
+    /// Starts a thread which reads ahead of sequential block and transaction
+    /// scans, shared by this client and the clones made after this call.
+    pub fn with_prefetch(mut self, config: PrefetchConfig) -> Result<Self> {
+        self.prefetch = Some(Prefetch::spawn(Arc::clone(&self.env), config)?);
+        Ok(self)
+    }
+
+    /// Returns the number of bytes read ahead of sequential scans, if readahead
+    /// is enabled.
+    pub fn prefetched_bytes(&self) -> Option<u64> {
+        self.prefetch.as_ref().map(Prefetch::touched_bytes)
+    }
+
+    /// Returns the pool which scans the shards of wide `get_logs` filters,
+    /// starting its `threads` threads on first use.
+    pub(crate) fn log_workers(&self, threads: usize) -> Result<&WorkerPool> {
+        self.log_workers
+            .get_or_try_init(|| WorkerPool::spawn("ethers-db-logs", threads))
+    }
+
+    /// Limits the number of heavy queries running at once across this client
+    /// and the clones made after this call. Queries over the limit queue, and
+    /// fail with `Rejected` if they can't be admitted.
+    pub fn with_admission(mut self, config: AdmissionConfig) -> Self {
+        self.admission = Some(Arc::new(Admission::new(config)));
+        self
+    }
+
+    /// Returns the state of the heavy query queue, if admission control is enabled.
+    pub fn admission_metrics(&self) -> Option<AdmissionMetrics> {
+        self.admission.as_deref().map(Admission::metrics)
+    }
+
+    /// Records the tables and keys read by this client and the clones made
+    /// after this call, keeping the last `capacity` reads. Reads served from
+    /// the client's caches don't reach the db and aren't recorded.
+    pub fn with_read_trace(mut self, capacity: usize) -> Self {
+        self.read_trace = Some(ReadTrace::new(capacity));
+        self
+    }
+
+    /// Returns the reads made by the most recent read transaction, if read
+    /// tracing is enabled. Most calls read in a single transaction, so after a
+    /// call returns these are its reads, unless another thread has begun a
+    /// transaction since. Use `trace_reads` to trace a call alone.
+    ///
+    /// ```ignore
+    /// let client = client.with_read_trace(4096);
+    /// client.get_block(n)?;
+    /// for read in client.last_read_trace().unwrap_or_default() {
+    ///     println!("{} {:?}", read.table, read.key.map(hex::encode));
+    /// }
+    /// ```
+    pub fn last_read_trace(&self) -> Option<Vec<ReadRecord>> {
+        self.read_trace.as_ref().map(ReadTrace::last)
+    }
+
+    /// Returns the trace of this client's reads, if enabled.
+    pub fn read_trace(&self) -> Option<&ReadTrace> {
+        self.read_trace.as_ref()
+    }
+
+    /// Runs `f` on a clone of this client which traces its reads alone, and
+    /// returns them, oldest first, along with its result. Unlike
+    /// `last_read_trace`, the reads of concurrent calls aren't mixed in, and
+    /// calls reading in several transactions are traced in full. Reads served
+    /// from the caches aren't recorded; see `dry_run`.
+    ///
+    /// ```ignore
+    /// let (block, reads) = client.trace_reads(|db| db.get_block(n));
+    /// ```
+    pub fn trace_reads<R>(&self, f: impl FnOnce(&Self) -> R) -> (R, Vec<ReadRecord>) {
+        let trace = ReadTrace::new(usize::MAX);
+        let client = Self {
+            read_trace: Some(trace.clone()),
+            ..self.clone()
+        };
+        let out = f(&client);
+        (out, trace.all())
+    }
+
+    /// Like `trace_reads`, but runs `f` with the caches disabled and its own
+    /// filters, so every read it makes reaches the db and is recorded, and it
+    /// leaves this client's caches and installed filters as they were.
+    pub fn dry_run<R>(&self, f: impl FnOnce(&Self) -> R) -> (R, Vec<ReadRecord>) {
+        let client = Self {
+            caches: Arc::new(Caches::new(CacheConfig::disabled())),
+            filters: Default::default(),
+            ..self.clone()
+        };
+        client.trace_reads(f)
+    }
+
+    /// Waits for a heavy query slot, which is held until the permit is dropped.
+    pub(crate) fn admit(&self) -> Result<Option<Permit<'_>>> {
+        self.admission
+            .as_deref()
+            .map(Admission::acquire)
+            .transpose()
+    }
+
+    /// Fails with `Pruned` if the client's `PrunedProfile` prunes `data`, which
+    /// `method` reads.
+    pub(crate) fn ensure_unpruned(&self, method: &'static str, data: PrunedData) -> Result<()> {
+        match self.options.pruned {
+            Some(profile) if profile.prunes(data) => Err(Pruned { method, data }.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Resolves `range` to inclusive block bounds, or `None` if it is empty. An
+    /// unbounded end is the current head.
+    pub(crate) fn resolve_range<R: RangeBounds<u64>>(
+        &self,
+        range: R,
+    ) -> Result<Option<(u64, u64)>> {
+        let from = match range.start_bound() {
+            Bound::Included(n) => *n,
+            Bound::Excluded(n) => n.saturating_add(1),
+            Bound::Unbounded => 0,
+        };
+        let to = match range.end_bound() {
+            Bound::Included(n) => *n,
+            Bound::Excluded(0) => return Ok(None),
+            Bound::Excluded(n) => n - 1,
+            Bound::Unbounded => self.get_block_number()?.as_u64(),
+        };
+        Ok((from <= to).then(|| (from, to)))
+    }
+
+    /// Returns a reader over the txpool db, if the client was opened with one.
+    pub fn txpool_reader(&self) -> Result<Reader<'_, mdbx::RO, E>> {
+        let txpool = self
+            .txpool
+            .as_ref()
+            .ok_or_else(|| format_err!("client was opened without a txpool db"))?;
+        Ok(Reader::new(txpool.begin()?)
+            .with_budget(self.budget.or_timeout(self.options.read_timeout))
+            .with_read_trace(self.read_trace.as_ref()))
+    }
+
+    /// Replaces this client's caches with empty caches bounded by `config`.
+    /// Clones made before this call keep the previous caches.
+    pub fn with_cache_config(mut self, config: CacheConfig) -> Self {
+        self.caches = Arc::new(Caches::new(config));
+        self
+    }
+
+    /// Returns the current usage of the caches shared by this client and its clones.
+    pub fn cache_metrics(&self) -> CacheMetrics {
+        self.caches.metrics()
     }
 
     pub fn reader(&self) -> Result<Reader<'_, mdbx::RO, E>> {
-        Ok(Reader::new(self.0.begin()?))
+        Ok(Reader::new(self.env.begin()?)
+            .with_budget(self.budget.or_timeout(self.options.read_timeout))
+            .with_execution_cap(self.options.features.execution_safe_head)
+            .with_prefetch(self.prefetch.clone())
+            .with_read_trace(self.read_trace.as_ref()))
+    }
+
+    /// Returns a `'static` reader handle which shares this client's environment.
+    pub fn owned_reader(&self) -> OwnedReader<E> {
+        OwnedReader::new(Arc::clone(&self.env))
+            .with_budget(self.budget.clone())
+            .with_read_timeout(self.options.read_timeout)
+            .with_execution_cap(self.options.features.execution_safe_head)
+            .with_read_trace(self.read_trace.clone())
+    }
+
+    /// Returns a clone of this client whose cursor walks are bounded by `budget`.
+    /// A bounded budget replaces the client's `read_timeout`. Intended for
+    /// enforcing per-request deadlines, e.g.
+    /// `client.with_budget(ReadBudget::timeout(dur)).get_block(n)`.
+    pub fn with_budget(&self, budget: ReadBudget) -> Self {
+        Self {
+            budget,
+            ..self.clone()
+        }
+    }
+
+    /// Returns the code for `codehash`, consulting the code cache first.
+    fn read_code_cached<TX: TransactionKind>(
+        &self,
+        dbtx: &mut Reader<'_, TX, E>,
+        codehash: H256,
+    ) -> Result<bytes::Bytes> {
+        if let Some(code) = self.caches.code.lock().unwrap().get(&codehash) {
+            return Ok(code);
+        }
+        let code = dbtx.read_code(codehash)?;
+        let len = code.len();
+        self.caches
+            .code
+            .lock()
+            .unwrap()
+            .insert(codehash, code.clone(), len);
+        Ok(code)
+    }
+
+    /// Returns whether `key` was recently found missing, at the current sync
+    /// progress.
+    fn known_missing<TX: TransactionKind>(
+        &self,
+        dbtx: &mut Reader<'_, TX, E>,
+        key: MissingKey,
+    ) -> bool {
+        if !self.caches.missing.lock().unwrap().is_enabled() {
+            return false;
+        }
+        match sync_progress(dbtx) {
+            Ok(progress) => self.caches.missing.lock().unwrap().contains(&key, progress),
+            Err(_) => false,
+        }
+    }
+
+    /// Remembers that `key` is missing at the current sync progress.
+    pub(crate) fn record_missing<TX: TransactionKind>(
+        &self,
+        dbtx: &mut Reader<'_, TX, E>,
+        key: MissingKey,
+    ) {
+        if !self.caches.missing.lock().unwrap().is_enabled() {
+            return;
+        }
+        if let Ok(progress) = sync_progress(dbtx) {
+            self.caches.missing.lock().unwrap().insert(key, progress);
+        }
+    }
+
+    /// Returns the current account of `who`, or the empty account if it has
+    /// none, skipping the db for addresses recently found without one.
+    fn read_account_cached<TX: TransactionKind>(
+        &self,
+        dbtx: &mut Reader<'_, TX, E>,
+        who: Address,
+    ) -> Result<Account> {
+        let key = MissingKey::Account(who);
+        if self.known_missing(dbtx, key) {
+            return Ok(Account::default());
+        }
+        match dbtx.read_account(who)? {
+            Some(acct) => Ok(acct),
+            None => {
+                self.record_missing(dbtx, key);
+                Ok(Account::default())
+            }
+        }
+    }
+
+    /// Returns the block of the transaction `hash` from TxLookup, or `None` if
+    /// it isn't there, skipping the db for hashes recently found missing. Misses
+    /// are only recorded by the caller, once it has also searched elsewhere.
+    pub(crate) fn find_transaction_block_number_cached<TX: TransactionKind>(
+        &self,
+        dbtx: &mut Reader<'_, TX, E>,
+        hash: H256,
+    ) -> Result<Option<BlockNum>> {
+        if self.known_missing(dbtx, MissingKey::Tx(hash)) {
+            return Ok(None);
+        }
+        dbtx.find_transaction_block_number(hash)
+    }
+
+    /// Returns whether each of `msgs`, the transactions of the block `key`, is
+    /// left out by the client's `SystemTxs`.
+    pub(crate) fn excluded_txs<TX: TransactionKind>(
+        &self,
+        dbtx: &mut Reader<'_, TX, E>,
+        key: HeaderKey,
+        msgs: &[MessageWithSignature],
+    ) -> Result<Vec<bool>> {
+        let system_txs = &self.options.system_txs;
+        if !system_txs.is_filtering() {
+            return Ok(vec![false; msgs.len()]);
+        }
+        let senders = self.read_block_senders(dbtx, key, msgs)?;
+        Ok(senders.iter().map(|s| system_txs.excludes(s)).collect())
+    }
+
+    /// Returns the sender of each of `msgs`, the transactions of the block
+    /// `key`, recovering the ones the db is missing from their signatures.
+    pub(crate) fn read_block_senders<TX: TransactionKind>(
+        &self,
+        dbtx: &mut Reader<'_, TX, E>,
+        key: HeaderKey,
+        msgs: &[MessageWithSignature],
+    ) -> Result<Vec<Address>> {
+        let known = dbtx.read_senders(key)?;
+        self.recover_block_senders(key.num, msgs, &known)
+    }
+
+    /// Returns the sender of each of `msgs`, taken from `known` or, where it
+    /// is zero or missing, recovered from the signature. Fails if any must be
+    /// recovered and the client's sender recovery is disabled.
+    pub(crate) fn recover_block_senders(
+        &self,
+        num: BlockNum,
+        msgs: &[MessageWithSignature],
+        known: &[Address],
+    ) -> Result<Vec<Address>> {
+        let missing =
+            known.len() < msgs.len() || known.iter().take(msgs.len()).any(|s| s.is_zero());
+        if missing && !self.options.features.recover_senders {
+            return Err(format_err!(
+                "Missing senders for block {} and sender recovery is disabled",
+                num
+            ));
+        }
+        recover_senders(msgs, known)
+    }
+
+    /// Returns the header for `key`, consulting the header cache first.
+    pub(crate) fn read_header_cached<TX: TransactionKind>(
+        &self,
+        dbtx: &mut Reader<'_, TX, E>,
+        key: HeaderKey,
+    ) -> Result<ak_models::BlockHeader> {
+        if let Some(header) = self.caches.headers.lock().unwrap().get(&key) {
+            return Ok(header);
+        }
+        let header = dbtx.read_header(key)?;
+        if self.options.features.verify {
+            anyhow::ensure!(
+                header.hash() == key.hash,
+                "header of block {} hashes to {:?}, not {:?}",
+                key.num,
+                header.hash(),
+                key.hash
+            );
+        }
+        self.caches
+            .headers
+            .lock()
+            .unwrap()
+            .insert(key, header.clone(), 1);
+        Ok(header)
+    }
+
+    /// Checks the `tx_amt` stored transactions from `base_tx_id`, the
+    /// transactions of the block `key`, against the header's transactions
+    /// root, if the client verifies reads.
+    pub(crate) fn verify_transactions<TX: TransactionKind>(
+        &self,
+        dbtx: &mut Reader<'_, TX, E>,
+        key: HeaderKey,
+        header: &ak_models::BlockHeader,
+        base_tx_id: TxId,
+        tx_amt: usize,
+    ) -> Result<()> {
+        if !self.options.features.verify {
+            return Ok(());
+        }
+        let root = trie::ordered_trie_root(&dbtx.read_raw_transactions(base_tx_id, tx_amt)?);
+        anyhow::ensure!(
+            root == header.transactions_root,
+            "transactions root mismatch in block {}: {:?} != {:?}",
+            key.num,
+            root,
+            header.transactions_root
+        );
+        Ok(())
     }
 }
 
+// The tables read by the client's methods, as reported in the slow read log
+pub(crate) const HEADER_TABLES: &[&str] = &[
+    tables::CanonicalHeader::const_db_name(),
+    tables::HeaderNumber::const_db_name(),
+    tables::Header::const_db_name(),
+    tables::BlockBody::const_db_name(),
+];
+pub(crate) const STATE_TABLES: &[&str] = &[
+    tables::PlainState::const_db_name(),
+    tables::PlainContractCode::const_db_name(),
+    tables::Code::const_db_name(),
+    tables::IncarnationMap::const_db_name(),
+    tables::AccountHistory::const_db_name(),
+    tables::AccountChangeSet::const_db_name(),
+    tables::StorageHistory::const_db_name(),
+    tables::StorageChangeSet::const_db_name(),
+];
+pub(crate) const BLOCK_TABLES: &[&str] = &[
+    tables::CanonicalHeader::const_db_name(),
+    tables::HeaderNumber::const_db_name(),
+    tables::Header::const_db_name(),
+    tables::BlockBody::const_db_name(),
+    tables::BlockTransaction::const_db_name(),
+    tables::TxSender::const_db_name(),
+];
+pub(crate) const TX_TABLES: &[&str] = &[
+    tables::BlockTransactionLookup::const_db_name(),
+    tables::CanonicalHeader::const_db_name(),
+    tables::BlockBody::const_db_name(),
+    tables::BlockTransaction::const_db_name(),
+    tables::TxSender::const_db_name(),
+];
+pub(crate) const LOG_TABLES: &[&str] = &[
+    tables::LogAddressIndex::const_db_name(),
+    tables::LogTopicIndex::const_db_name(),
+    tables::TransactionLog::const_db_name(),
+    tables::CanonicalHeader::const_db_name(),
+    tables::BlockBody::const_db_name(),
+    tables::BlockTransaction::const_db_name(),
+];
+const AVAILABLE_TABLES: &[&str] = &[
+    tables::Header::const_db_name(),
+    tables::BlockBody::const_db_name(),
+    tables::Receipt::const_db_name(),
+    tables::AccountChangeSet::const_db_name(),
+];
+
 // Synchronous middleware methods
 impl<E: EnvironmentKind> Client<E> {
     pub fn get_block_number(&self) -> Result<U64> {
+        let _slow = self.slow_read("get_block_number", HEADER_TABLES, String::new);
+        let mut dbtx = self.reader()?;
+        Ok(dbtx.read_head_block_number()?.into())
+    }
+
+    /// Returns the number of the last canonical block with a timestamp at or
+    /// before `timestamp`, in seconds. `None` if every block is later.
+    pub fn get_block_number_by_timestamp(&self, timestamp: u64) -> Result<Option<U64>> {
+        let _slow = self.slow_read("get_block_number_by_timestamp", HEADER_TABLES, || {
+            timestamp.to_string()
+        });
         let mut dbtx = self.reader()?;
-        Ok(dbtx.read_head_block_number()?.0.into())
+        Ok(dbtx.read_block_number_at_time(timestamp)?.map(Into::into))
+    }
+
+    /// Returns the block a state read at `block` has to read the history at,
+    /// or `None` for the latest state, which is read from the plain state.
+    /// Fails if `method` needs history the client's `PrunedProfile` prunes.
+    pub(crate) fn historical_block<TX: TransactionKind>(
+        &self,
+        dbtx: &mut Reader<'_, TX, E>,
+        block: Option<BlockId>,
+        method: &'static str,
+    ) -> Result<Option<BlockNum>> {
+        let id = match block {
+            Some(id) => id,
+            None => return Ok(None),
+        };
+        let key = get_header_key(dbtx, id)?;
+        if key.num == dbtx.read_head_block_number()? {
+            return Ok(None);
+        }
+        self.ensure_unpruned(method, PrunedData::History)?;
+        Ok(Some(key.num))
+    }
+
+    /// Returns the account `from` as of `block`, through a `StateAt` view
+    /// before the head.
+    fn read_account_at_block<TX: TransactionKind>(
+        &self,
+        dbtx: &mut Reader<'_, TX, E>,
+        from: Address,
+        block: Option<BlockId>,
+        method: &'static str,
+    ) -> Result<Account> {
+        match self.historical_block(dbtx, block, method)? {
+            Some(num) => dbtx.state_at(num).account(from),
+            None => self.read_account_cached(dbtx, from),
+        }
     }
 
+    /// Returns the balance of `from` at `block`.
     pub fn get_balance(&self, from: Address, block: Option<BlockId>) -> Result<U256> {
-        assert!(block.is_none(), "no history handling yet");
+        let _slow = self.slow_read("get_balance", STATE_TABLES, || {
+            format!("{:?} at {:?}", from, block)
+        });
         let mut dbtx = self.reader()?;
-        Ok(dbtx.read_account_data(from)?.balance)
+        Ok(self
+            .read_account_at_block(&mut dbtx, from, block, "get_balance")?
+            .balance)
     }
 
+    /// Returns the code of `from` at `block`. A contract since destroyed or
+    /// redeployed returns the code it had then.
     pub fn get_code(&self, from: Address, block: Option<BlockId>) -> Result<ethers::types::Bytes> {
-        assert!(block.is_none(), "no history handling yet");
+        let _slow = self.slow_read("get_code", STATE_TABLES, || {
+            format!("{:?} at {:?}", from, block)
+        });
         let mut dbtx = self.reader()?;
-        let data = dbtx.read_account_data(from)?;
-        dbtx.read_code(data.codehash).map(From::from)
+        let code = match self.historical_block(&mut dbtx, block, "get_code")? {
+            Some(num) => dbtx.state_at(num).code(from)?,
+            None => {
+                let acct = self.read_account_cached(&mut dbtx, from)?;
+                self.read_code_cached(&mut dbtx, acct.codehash)?
+            }
+        };
+        Ok(code.into())
     }
 
+    /// Returns the nonce of `from` at `block`.
     pub fn get_transaction_count(&self, from: Address, block: Option<BlockId>) -> Result<U256> {
-        assert!(block.is_none(), "no history handling yet");
+        let _slow = self.slow_read("get_transaction_count", STATE_TABLES, || {
+            format!("{:?} at {:?}", from, block)
+        });
         let mut dbtx = self.reader()?;
-        Ok(dbtx.read_account_data(from)?.nonce.into())
+        let acct = self.read_account_at_block(&mut dbtx, from, block, "get_transaction_count")?;
+        Ok(acct.nonce.into())
     }
 
     pub fn get_transaction<T: Send + Sync + Into<TxHash>>(
@@ -67,40 +654,293 @@ impl<E: EnvironmentKind> Client<E> {
         transaction_hash: T,
     ) -> Result<Option<ethers::types::Transaction>> {
         let hash = transaction_hash.into();
+        self.ensure_unpruned("get_transaction", PrunedData::TxLookup)?;
+        let _slow = self.slow_read("get_transaction", TX_TABLES, || format!("{:?}", hash));
 
         let mut dbtx = self.reader()?;
-        let block_num = dbtx.read_transaction_block_number(hash)?;
+        let block_num = match self.find_transaction_block_number_cached(&mut dbtx, hash)? {
+            Some(num) => num,
+            None => {
+                #[cfg(feature = "polygon")]
+                if let Some(key) = self.find_state_sync_block(&mut dbtx, hash)? {
+                    if self.options().system_txs.excludes_state_syncs() {
+                        return Ok(None);
+                    }
+                    return self.state_sync_transaction(&mut dbtx, key).map(Some);
+                }
+                // Transactions in frozen segments aren't in TxLookup
+                let res = match &self.snapshots {
+                    Some(snapshots) => self.find_snapshot_transaction(&mut dbtx, snapshots, hash),
+                    None => Err(NotFound::Tx(hash).into()),
+                };
+                if res.is_err() {
+                    self.record_missing(&mut dbtx, MissingKey::Tx(hash));
+                }
+                return res;
+            }
+        };
+        self.find_transaction(&mut dbtx, block_num, hash)
+    }
+
+    /// Searches the canonical block `block_num` for the transaction `hash`.
+    fn find_transaction<TX: TransactionKind>(
+        &self,
+        dbtx: &mut Reader<'_, TX, E>,
+        block_num: BlockNum,
+        hash: H256,
+    ) -> Result<Option<ethers::types::Transaction>> {
         let block_hash = dbtx.read_canonical_hash(block_num)?;
-        let body = dbtx.read_body_for_storage((block_num, block_hash))?;
+        let key = HeaderKey::new(block_num, block_hash);
+        let body = dbtx.read_body_for_storage(key)?;
+        let tx_amt = body.tx_amount.try_into()?;
+        if self.options.features.verify {
+            let header = self.read_header_cached(dbtx, key)?;
+            self.verify_transactions(dbtx, key, &header, body.base_tx_id.into(), tx_amt)?;
+        }
+        for (idx, raw) in dbtx
+            .read_raw_transactions(body.base_tx_id.into(), tx_amt)?
+            .iter()
+            .enumerate()
+        {
+            // only txs cast by a custom decoder may hash otherwise
+            let custom = self.options.custom_txs.handles(raw);
+            if !custom && H256::from(keccak256(raw)) != hash {
+                continue;
+            }
+            let mut tx = match self
+                .options
+                .custom_txs
+                .decode(raw, key.num, key.hash, idx)?
+            {
+                StoredTx::Msg(msg) => MsgCast::new(&msg).cast(key.num, key.hash, idx)?,
+                StoredTx::Custom(tx) => tx,
+            };
+            if tx.hash == hash {
+                // a decoder may leave the sender to the one stored
+                if tx.from.is_zero() {
+                    tx.from = dbtx
+                        .read_senders(key)?
+                        .get(idx)
+                        .copied()
+                        .unwrap_or_default();
+                }
+                if self.options.system_txs.excludes(&tx.from) {
+                    return Ok(None);
+                }
+                return Ok(Some(tx));
+            }
+        }
+        Err(format_err!(
+            "No transaction hash {} in block {}",
+            hash,
+            block_num
+        ))
+    }
 
-        let (msg, idx) = dbtx
-            .try_stream_transactions(*body.base_tx_id, body.tx_amount.try_into()?)?
-            .zip(0..)
-            .find(|(msg, _i)| msg.hash() == hash)
-            .ok_or_else(|| format_err!("No transaction hash {} in block {}", hash, block_num))?;
+    /// Returns the proof of inclusion of the transaction `hash` in its block's
+    /// transactions trie. Errors if the trie built from the stored transactions
+    /// doesn't match the root in the block header. Receipt proofs are not
+    /// supported, as the receipts are not stored in full.
+    pub fn get_transaction_proof<T: Send + Sync + Into<TxHash>>(
+        &self,
+        transaction_hash: T,
+    ) -> Result<TransactionProof> {
+        let hash = transaction_hash.into();
+        self.ensure_unpruned("get_transaction_proof", PrunedData::TxLookup)?;
+        let _slow = self.slow_read("get_transaction_proof", TX_TABLES, || format!("{:?}", hash));
+        let mut dbtx = self.reader()?;
+        let block_number = dbtx.read_transaction_block_number(hash)?;
+        let block_hash = dbtx.read_canonical_hash(block_number)?;
+        let header_key = HeaderKey::new(block_number, block_hash);
+        let header = self.read_header_cached(&mut dbtx, header_key)?;
+        let body = dbtx.read_body_for_storage(header_key)?;
+
+        let txs = dbtx.read_raw_transactions(body.base_tx_id.into(), body.tx_amount.try_into()?)?;
+        let index = txs
+            .iter()
+            .position(|tx| H256::from(keccak256(tx)) == hash)
+            .ok_or_else(|| format_err!("No transaction hash {} in block {}", hash, block_number))?;
+        let (root, proof) = trie::ordered_trie_proof(&txs, Some(index));
+        anyhow::ensure!(
+            root == header.transactions_root,
+            "transactions root mismatch in block {}: {:?} != {:?}",
+            block_number,
+            root,
+            header.transactions_root
+        );
+        Ok(TransactionProof {
+            block_hash,
+            block_number: block_number.into(),
+            transaction_index: index.into(),
+            transactions_root: root,
+            proof: proof.into_iter().map(Into::into).collect(),
+        })
+    }
 
-        Ok(Some(MsgCast::new(&msg).cast(block_num, block_hash, idx)))
+    /// Looks up `hash` in the snapshot indices, checking each candidate block.
+    /// Candidates are read from the snapshot segments, or from the db when
+    /// their segments aren't on disk.
+    fn find_snapshot_transaction<TX: TransactionKind>(
+        &self,
+        dbtx: &mut Reader<'_, TX, E>,
+        snapshots: &SnapshotTxIndex,
+        hash: H256,
+    ) -> Result<Option<ethers::types::Transaction>> {
+        for num in snapshots.candidate_blocks(hash.as_bytes())? {
+            let frozen = match snapshots.read_block_transactions(num)? {
+                Some(txs) => txs,
+                None => match self.find_transaction(dbtx, BlockNum(num), hash) {
+                    Ok(Some(tx)) => return Ok(Some(tx)),
+                    _ => continue,
+                },
+            };
+            let (idx, (msg, sender)) = match frozen
+                .iter()
+                .enumerate()
+                .find(|(_, (msg, _))| msg.hash() == hash)
+            {
+                Some(found) => found,
+                None => continue,
+            };
+            let block_hash = match snapshots.read_block_hash(num)? {
+                Some(block_hash) => block_hash,
+                None => dbtx.read_canonical_hash(BlockNum(num))?,
+            };
+            let tx =
+                MsgCast::new(msg)
+                    .maybe_signer(*sender)
+                    .cast(BlockNum(num), block_hash, idx)?;
+            if self.options.system_txs.excludes(&tx.from) {
+                return Ok(None);
+            }
+            return Ok(Some(tx));
+        }
+        Err(NotFound::Tx(hash).into())
     }
 
+    /// Returns the value of the storage slot `location` of `from` at `block`,
+    /// read at the account's incarnation as of the block, as Erigon's
+    /// rpcdaemon does.
     pub fn get_storage_at(
         &self,
         from: Address,
         location: H256,
         block: Option<BlockId>,
     ) -> Result<H256> {
-        assert!(block.is_none(), "no history handling yet");
+        let _slow = self.slow_read("get_storage_at", STATE_TABLES, || {
+            format!("{:?} {:?} at {:?}", from, location, block)
+        });
+        let mut dbtx = self.reader()?;
+        match self.historical_block(&mut dbtx, block, "get_storage_at")? {
+            Some(num) => dbtx.state_at(num).storage(from, location),
+            None => {
+                let acct = self.read_account_cached(&mut dbtx, from)?;
+                dbtx.read_account_storage(from, acct.incarnation, location)
+                    .map_err(From::from)
+            }
+        }
+    }
+
+    /// Returns the number of storage slots currently set for `from`, without
+    /// reading its storage into memory. Zero for accounts without storage.
+    pub fn storage_slot_count(&self, from: Address) -> Result<u64> {
+        let _slow = self.slow_read("storage_slot_count", STATE_TABLES, || format!("{:?}", from));
         let mut dbtx = self.reader()?;
         let acct = dbtx.read_account_data(from)?;
-        dbtx.read_account_storage(from, acct.incarnation, location)
-            .map_err(From::from)
+        dbtx.count_account_storage(from, acct.incarnation)
+    }
+
+    /// Returns a page of at most `limit` (slot, value) pairs from the current
+    /// storage of `from`. Pass the previous page's cursor to resume.
+    pub fn get_storage_range(
+        &self,
+        from: Address,
+        cursor: Option<&PageCursor>,
+        limit: usize,
+    ) -> Result<Page<(H256, H256)>> {
+        let _slow = self.slow_read("get_storage_range", STATE_TABLES, || format!("{:?}", from));
+        let mut dbtx = self.reader()?;
+        let incarnation = dbtx.read_account_data(from)?.incarnation;
+        read_storage_page(&mut dbtx, from, incarnation, cursor, limit)
+    }
+
+    /// Lists every incarnation of `from`, oldest first: the ones destroyed by
+    /// SELFDESTRUCT, up to the last recorded in the IncarnationMap, and the
+    /// current one if it's a contract.
+    pub fn get_incarnations(&self, from: Address) -> Result<Vec<Incarnation>> {
+        let _slow = self.slow_read("get_incarnations", STATE_TABLES, || format!("{:?}", from));
+        let mut dbtx = self.reader()?;
+        let current = dbtx.read_account(from)?.map(|acct| acct.incarnation);
+        let last = dbtx.read_last_incarnation(from)?.max(current.unwrap_or(0));
+        (1..=last)
+            .map(|incarnation| {
+                Ok(Incarnation {
+                    incarnation,
+                    codehash: dbtx.read_contract_codehash(from, incarnation)?,
+                    current: current == Some(incarnation),
+                })
+            })
+            .collect()
+    }
+
+    /// Like `get_storage_range`, but reads the storage of the given
+    /// incarnation of `from`. Erigon leaves the storage of a destroyed
+    /// incarnation in place, so its state at destruction can be inspected.
+    pub fn get_incarnation_storage_range(
+        &self,
+        from: Address,
+        incarnation: u64,
+        cursor: Option<&PageCursor>,
+        limit: usize,
+    ) -> Result<Page<(H256, H256)>> {
+        let _slow = self.slow_read("get_incarnation_storage_range", STATE_TABLES, || {
+            format!("{:?} incarnation {}", from, incarnation)
+        });
+        read_storage_page(&mut self.reader()?, from, incarnation, cursor, limit)
+    }
+
+    /// Returns the header of a block without reading its body. The returned
+    /// block has no transactions and no uncle hashes.
+    pub fn get_header<T: Into<BlockId> + Send + Sync>(
+        &self,
+        block_hash_or_number: T,
+    ) -> Result<Option<Block<()>>> {
+        let id = block_hash_or_number.into();
+        let _slow = self.slow_read("get_header", HEADER_TABLES, || format!("{:?}", id));
+        let mut dbtx = self.reader()?;
+        let header_key = get_header_key(&mut dbtx, id)?;
+        let HeaderKey {
+            num: block_num,
+            hash: block_hash,
+        } = header_key;
+        let header = self.read_header_cached(&mut dbtx, header_key)?;
+        Ok(Some(BlockCast(&header).cast_header(block_num, block_hash)))
+    }
+
+    /// Returns the headers of every block stored at height `num`, including
+    /// side-chain blocks which Erigon has retained. The canonical block, if
+    /// any, comes first. The returned blocks have no transactions or uncles.
+    pub fn get_blocks_at_height<T: Into<BlockNum>>(&self, num: T) -> Result<Vec<Block<()>>> {
+        let num = num.into();
+        let _slow = self.slow_read("get_blocks_at_height", HEADER_TABLES, || num.to_string());
+        let mut dbtx = self.reader()?;
+        let canonical = dbtx.find_canonical_hash(num)?;
+        let mut headers = dbtx.read_headers_at(num)?;
+        headers.sort_by_key(|(key, _)| Some(key.hash) != canonical);
+        Ok(headers
+            .iter()
+            .map(|(key, header)| BlockCast(header).cast_header(key.num, key.hash))
+            .collect())
     }
 
     pub fn get_uncle_count<T: Into<BlockId> + Send + Sync>(
         &self,
         block_hash_or_number: T,
     ) -> Result<U256> {
+        let id = block_hash_or_number.into();
+        let _slow = self.slow_read("get_uncle_count", HEADER_TABLES, || format!("{:?}", id));
         let mut dbtx = self.reader()?;
-        let header_key = get_header_key(&mut dbtx, block_hash_or_number)?;
+        let header_key = get_header_key(&mut dbtx, id)?;
         let body = dbtx.read_body_for_storage(header_key)?;
         Ok(body.uncles.len().into())
     }
@@ -110,15 +950,17 @@ impl<E: EnvironmentKind> Client<E> {
         block_hash_or_number: T,
         idx: U64,
     ) -> Result<Option<Block<H256>>> {
+        let id = block_hash_or_number.into();
+        let _slow = self.slow_read("get_uncle", HEADER_TABLES, || format!("{:?} {}", id, idx));
         let mut dbtx = self.reader()?;
-        let header_key = get_header_key(&mut dbtx, block_hash_or_number)?;
+        let header_key = get_header_key(&mut dbtx, id)?;
         let body = dbtx.read_body_for_storage(header_key)?;
-        let idx = idx.as_usize();
-        if idx < body.uncles.len() {
-            self.get_block(*body.uncles[idx].number)
-        } else {
-            Ok(None)
-        }
+        // Uncles are only stored as headers in their nephew's body, and have no
+        // transactions or uncles of their own.
+        Ok(body
+            .uncles
+            .get(idx.as_usize())
+            .map(|uncle| BlockCast(uncle).cast_uncle()))
     }
 
     //TODO: should also look for non-canonical blocks?
@@ -127,104 +969,384 @@ impl<E: EnvironmentKind> Client<E> {
         &self,
         block_hash_or_number: T,
     ) -> Result<Option<Block<TxHash>>> {
+        let id = block_hash_or_number.into();
+        let _slow = self.slow_read("get_block", BLOCK_TABLES, || format!("{:?}", id));
         let mut dbtx = self.reader()?;
 
-        let header_key = get_header_key(&mut dbtx, block_hash_or_number)?;
-        let (block_num, block_hash) = header_key;
+        let header_key = get_header_key(&mut dbtx, id)?;
+        let block = BlockAssembler::<_, TxHashes>::new(self).assemble(&mut dbtx, header_key)?;
+        Ok(Some(block))
+    }
 
-        let header = dbtx.read_header(header_key)?;
-        let body = dbtx.read_body_for_storage(header_key)?;
+    /// Like `get_block`, but only reads the parts of the block in `fields`.
+    /// The others are left empty, so hot paths such as head tracking can skip
+    /// reading transactions and decoding ommers.
+    pub fn get_block_projected<T: Into<BlockId> + Send + Sync>(
+        &self,
+        block_hash_or_number: T,
+        fields: BlockFields,
+    ) -> Result<Option<Block<TxHash>>> {
+        let id = block_hash_or_number.into();
+        let _slow = self.slow_read("get_block_projected", BLOCK_TABLES, || {
+            format!("{:?} {:?}", id, fields)
+        });
+        let mut dbtx = self.reader()?;
+        let header_key = get_header_key(&mut dbtx, id)?;
+        let block = BlockAssembler::<_, TxHashes>::new(self)
+            .fields(fields)
+            .assemble(&mut dbtx, header_key)?;
+        Ok(Some(block))
+    }
 
-        let tx_amt: usize = body.tx_amount.try_into()?;
-        let txs = dbtx
-            .stream_transactions(body.base_tx_id.0)?
-            .map(|msg| Ok(msg?.hash()))
-            .take(body.tx_amount.try_into()?)
-            .collect::<Result<Vec<_>>>()?;
+    /// Like `get_block`, but without reading the block's transactions. The
+    /// returned block has an empty transaction list, and the number of
+    /// transactions is reported alongside it.
+    pub fn get_block_raw<T: Into<BlockId> + Send + Sync>(
+        &self,
+        block_hash_or_number: T,
+    ) -> Result<Option<RawBlock>> {
+        let id = block_hash_or_number.into();
+        let _slow = self.slow_read("get_block_raw", BLOCK_TABLES, || format!("{:?}", id));
+        let mut dbtx = self.reader()?;
 
-        if txs.len() != tx_amt {
-            return Err(format_err!(
-                "Failed to get some txs in block {}. Expected: {}. Got {}",
-                block_num,
-                tx_amt,
-                txs.len()
-            ));
-        }
+        let header_key = get_header_key(&mut dbtx, id)?;
+        let header = self.read_header_cached(&mut dbtx, header_key)?;
+        let body = dbtx.read_body_for_storage(header_key)?;
 
-        let ommer_hashes = body
-            .uncles
-            .iter()
-            .map(|header| dbtx.read_canonical_hash(header.number))
-            .collect::<Result<Vec<_>>>()?;
+        let tx_count = if self.options.system_txs.is_filtering() {
+            // count the txs the way get_block lists them, custom txs included
+            BlockAssembler::<_, TxHashes>::new(self)
+                .fields(BlockFields::TRANSACTIONS)
+                .assemble(&mut dbtx, header_key)?
+                .transactions
+                .len()
+        } else {
+            body.tx_amount.try_into()?
+        };
 
-        let block = BlockCast(&header).cast(txs, block_num, block_hash, ommer_hashes);
-        Ok(Some(block))
+        let ommer_hashes = body.uncles.iter().map(|header| header.hash()).collect();
+        let block = BlockCast(&header).cast(vec![], header_key.num, header_key.hash, ommer_hashes);
+        Ok(Some(RawBlock { block, tx_count }))
     }
 
     pub fn get_block_with_txs<T: Into<BlockId> + Send + Sync>(
         &self,
         block_hash_or_number: T,
     ) -> Result<Option<Block<ethers::types::Transaction>>> {
+        let id = block_hash_or_number.into();
+        let _slow = self.slow_read("get_block_with_txs", BLOCK_TABLES, || format!("{:?}", id));
         let mut dbtx = self.reader()?;
 
-        let header_key = get_header_key(&mut dbtx, block_hash_or_number)?;
-        let (block_num, block_hash) = header_key;
+        let header_key = get_header_key(&mut dbtx, id)?;
+        let block = BlockAssembler::<_, FullTxs>::new(self).assemble(&mut dbtx, header_key)?;
+        Ok(Some(block))
+    }
 
-        let header = dbtx.read_header(header_key)?;
-        let body = dbtx.read_body_for_storage(header_key)?;
+    /// Like `get_block_with_txs`, but a transaction which fails to decode is
+    /// left out of the block and reported in the returned errors instead of
+    /// failing the read, for scanning chains with the odd exotic transaction.
+    /// The remaining transactions keep their index in the block.
+    pub fn get_block_with_txs_lenient<T: Into<BlockId> + Send + Sync>(
+        &self,
+        block_hash_or_number: T,
+    ) -> Result<Option<(Block<ethers::types::Transaction>, Vec<TxDecodeError>)>> {
+        let id = block_hash_or_number.into();
+        let _slow = self.slow_read("get_block_with_txs_lenient", BLOCK_TABLES, || {
+            format!("{:?}", id)
+        });
+        let mut dbtx = self.reader()?;
+        let header_key = get_header_key(&mut dbtx, id)?;
+        let res =
+            BlockAssembler::<_, FullTxs>::new(self).assemble_lenient(&mut dbtx, header_key)?;
+        Ok(Some(res))
+    }
 
-        // We may not have all signers in the db, in which case we get zero
-        // addresses and have to recover the signatures
-        let senders = dbtx.read_senders(header_key)?;
+    /// Like `get_block_with_txs`, but only reads the parts of the block in
+    /// `fields`. See `get_block_projected`.
+    pub fn get_block_with_txs_projected<T: Into<BlockId> + Send + Sync>(
+        &self,
+        block_hash_or_number: T,
+        fields: BlockFields,
+    ) -> Result<Option<Block<ethers::types::Transaction>>> {
+        let id = block_hash_or_number.into();
+        let _slow = self.slow_read("get_block_with_txs_projected", BLOCK_TABLES, || {
+            format!("{:?} {:?}", id, fields)
+        });
+        let mut dbtx = self.reader()?;
+        let header_key = get_header_key(&mut dbtx, id)?;
+        let block = BlockAssembler::<_, FullTxs>::new(self)
+            .fields(fields)
+            .assemble(&mut dbtx, header_key)?;
+        Ok(Some(block))
+    }
 
-        // try_stream_transactions so we can cast the txs as we read them
-        let tx_amt = body.tx_amount.try_into()?;
-        let txs = dbtx
-            .try_stream_transactions(*body.base_tx_id, tx_amt)?
-            .scan(0_usize, |idx, msg| {
-                let tx = MsgCast::new(&msg)
-                    .maybe_signer(senders[*idx])
-                    .cast(block_num, block_hash, *idx);
-                *idx += 1;
-                Some(tx)
-            })
-            .collect::<Vec<_>>();
+    /// Returns a lazy iterator over the logs matching `filter`. Candidate blocks
+    /// are found with the log address and topic indices, and each block's logs
+    /// are only read once the iterator reaches it. The iterator's reads are
+    /// not timed for the slow read log, as they run after this returns.
+    pub fn stream_logs(&self, filter: &Filter) -> Result<LogStream<'_, E>> {
+        self.ensure_unpruned("stream_logs", PrunedData::Receipts)?;
+        LogStream::new(self.reader()?, filter)
+    }
 
-        // Check that no txs were discarded (e.g. if they failed to decode)
-        if txs.len() != tx_amt {
-            return Err(format_err!(
-                "Failed to get some txs in block {}. Expected: {}. Got {}",
-                block_num,
-                tx_amt,
-                txs.len()
-            )
-            .into());
+    /// Returns a page of at most `limit` of the logs matching `filter`. Pass
+    /// the previous page's cursor, with the same filter, to resume. A resumed
+    /// range filter only scans from the cursor's block onwards.
+    pub fn get_logs_page(
+        &self,
+        filter: &Filter,
+        cursor: Option<&PageCursor>,
+        limit: usize,
+    ) -> Result<Page<Log>> {
+        let _slow = self.slow_read("get_logs_page", LOG_TABLES, || format!("{:?}", filter));
+        let resume = cursor.map(|c| c.to_array::<16>()).transpose()?;
+        let mut filter = filter.clone();
+        if let (Some(key), FilterBlockOption::Range { .. }) = (resume, &filter.block_option) {
+            let num = u64::from_be_bytes(key[..8].try_into()?);
+            filter = filter.from_block(num);
         }
+        let logs = self
+            .stream_logs(&filter)?
+            .map(|res| {
+                let log = res?;
+                Ok((log_page_key(&log)?, log))
+            })
+            .filter(|res| match (res, resume) {
+                (Ok((key, _)), Some(resume)) => *key >= resume,
+                _ => true,
+            });
+        paginate(logs, limit)
+    }
 
-        let ommer_hashes = body
-            .uncles
-            .iter()
-            .map(|header| dbtx.read_canonical_hash(header.number))
-            .collect::<Result<Vec<_>>>()?;
+    /// Returns the logs matching `filter`. If the client's `log_parallelism` is
+    /// above one, wide filters are scanned by a pool of that many threads, each
+    /// shard in its own read transaction. The pool is started by the first wide
+    /// filter and reused by later calls and clones.
+    pub fn get_logs(&self, filter: &Filter) -> Result<Vec<Log>> {
+        self.ensure_unpruned("get_logs", PrunedData::Receipts)?;
+        let _permit = self.admit()?;
+        let _slow = self.slow_read("get_logs", LOG_TABLES, || format!("{:?}", filter));
+        match self.options.log_parallelism {
+            0 | 1 => self.stream_logs(filter)?.collect(),
+            threads => logs::get_logs_sharded(self, filter, threads),
+        }
+    }
 
-        let block = crate::utils::BlockCast(&header).cast(txs, block_num, block_hash, ommer_hashes);
-        Ok(Some(block))
+    /// Returns an iterator over the `Ev` events emitted in `range`, optionally
+    /// restricted to the contract at `address`, decoded along with their log
+    /// metadata. Mirrors `Event::query_with_meta` from ethers-contract.
+    pub fn stream_decoded_events<Ev, R>(
+        &self,
+        range: R,
+        address: Option<Address>,
+    ) -> Result<impl Iterator<Item = Result<(Ev, LogMeta)>> + '_>
+    where
+        Ev: EthEvent,
+        R: Into<FilterBlockOption>,
+    {
+        let mut filter = Filter::new().select(range);
+        if let Some(address) = address {
+            filter = filter.address(address);
+        }
+        if !Ev::is_anonymous() {
+            filter = filter.topic0(Ev::signature());
+        }
+        Ok(self.stream_logs(&filter)?.map(|log| {
+            let log = log?;
+            let raw = RawLog {
+                topics: log.topics.clone(),
+                data: log.data.to_vec(),
+            };
+            Ok((Ev::decode_log(&raw)?, LogMeta::from(&log)))
+        }))
     }
 
-    /// Returns the receipts for the block if they are stored in the db. If they
-    /// are not, erigon would attempt to reconstruct them. In this case, the block
-    /// number is returned so the caller can attempt to get the receipts over rpc.
-    pub fn get_block_receipts<T: Into<EthersBlockNumber> + Send + Sync>(
+    /// Collects the decoded `Ev` events emitted in `range`. See `stream_decoded_events`.
+    pub fn get_decoded_events<Ev, R>(
         &self,
-        block: T,
-    ) -> Result<Either<ak_models::BlockNumber, Vec<ethers::types::TransactionReceipt>>> {
-        let mut dbtx = self.reader()?;
-        let num = res_block_number(&mut dbtx, block)?;
+        range: R,
+        address: Option<Address>,
+    ) -> Result<Vec<(Ev, LogMeta)>>
+    where
+        Ev: EthEvent,
+        R: Into<FilterBlockOption>,
+    {
+        let range = range.into();
+        let _slow = self.slow_read("get_decoded_events", LOG_TABLES, || {
+            format!("{} {:?} at {:?}", Ev::name(), range, address)
+        });
+        self.stream_decoded_events(range, address)?.collect()
+    }
 
-        //TODO: actually try to get the receipts
-        Ok(Either::Left(num))
+    /// Returns the current head along with the safe and finalized blocks of the
+    /// last forkchoice update, read in a single transaction.
+    pub fn chain_head(&self) -> Result<ChainHead> {
+        let _slow = self.slow_read("chain_head", HEADER_TABLES, String::new);
+        let mut dbtx = self.reader()?;
+        let hash = dbtx.read_head_header_hash()?;
+        Ok(ChainHead {
+            number: dbtx.read_header_number(hash)?.into(),
+            hash,
+            safe: dbtx.read_safe_block_hash()?,
+            finalized: dbtx.read_finalized_block_hash()?,
+        })
     }
-}
+
+    /// Starts timing a read by `method`, which is logged when the returned guard
+    /// is dropped if it was slower than the client's `slow_read_threshold`.
+    pub(crate) fn slow_read<F: FnOnce() -> String>(
+        &self,
+        method: &'static str,
+        tables: &'static [&'static str],
+        context: F,
+    ) -> SlowRead<F> {
+        SlowRead::new(self.options.slow_read_threshold, method, tables, context)
+    }
+
+    /// Returns environment-wide mdbx statistics, for export as operational
+    /// metrics. No transaction is opened.
+    pub fn env_info(&self) -> Result<EnvInfo> {
+        let info = self.env.info()?;
+        let stat = self.env.stat()?;
+        let page_size = u64::from(stat.page_size());
+        Ok(EnvInfo {
+            map_size: info.map_size() as u64,
+            page_size,
+            used_bytes: (info.last_pgno() as u64 + 1) * page_size,
+            last_txn_id: info.last_txnid() as u64,
+            max_readers: info.max_readers() as u64,
+            readers_in_use: info.num_readers() as u64,
+            tree_depth: stat.depth(),
+            branch_pages: stat.branch_pages() as u64,
+            leaf_pages: stat.leaf_pages() as u64,
+            overflow_pages: stat.overflow_pages() as u64,
+        })
+    }
+
+    /// Returns the ranges of blocks whose data is present, so requested ranges can
+    /// be validated up front. Only blocks in the db are readable, so frozen blocks
+    /// are not counted in the db ranges even when they are in `snapshots`.
+    pub fn available_ranges(&self) -> Result<AvailableRanges> {
+        let _slow = self.slow_read("available_ranges", AVAILABLE_TABLES, String::new);
+        let missing_snapshots = self.snapshot_inventory()?.missing_ranges();
+        let mut dbtx = self.reader()?;
+        Ok(AvailableRanges {
+            headers: dbtx.read_block_key_range(ak_tables::Header.erased())?,
+            bodies: dbtx.read_block_key_range(ak_tables::BlockBody.erased())?,
+            receipts: dbtx.read_block_key_range(tables::Receipt.erased())?,
+            state_history: dbtx.read_block_key_range(tables::AccountChangeSet.erased())?,
+            snapshots: self
+                .snapshots
+                .as_ref()
+                .map(|s| s.ranges())
+                .unwrap_or_default(),
+            missing_snapshots,
+        })
+    }
+
+    /// Returns the snapshot segments Erigon has a torrent for in the snapshots
+    /// directory, and those present on disk. The directory is read on every
+    /// call, so segments downloaded since the client was built are included.
+    /// Empty if the client was built without snapshots.
+    pub fn snapshot_inventory(&self) -> Result<SnapshotInventory> {
+        match &self.snapshots {
+            Some(snapshots) => SnapshotInventory::open(snapshots.dir()),
+            None => Ok(Default::default()),
+        }
+    }
+}
+
+/// A Merkle-Patricia proof of a transaction's inclusion in a block, as returned
+/// by `Client::get_transaction_proof`. The proof nodes begin at the root, and
+/// the transaction is stored under the key `rlp(transaction_index)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransactionProof {
+    pub block_hash: H256,
+    pub block_number: U64,
+    pub transaction_index: U64,
+    pub transactions_root: H256,
+    pub proof: Vec<ethers::types::Bytes>,
+}
+
+/// An incarnation of a contract account, as returned by
+/// `Client::get_incarnations`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Incarnation {
+    pub incarnation: u64,
+    /// The incarnation's code hash, if it was deployed with code.
+    pub codehash: Option<H256>,
+    /// Whether this is the account's incarnation in the current state.
+    pub current: bool,
+}
+
+/// The head of the chain, as returned by `Client::chain_head`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChainHead {
+    pub number: U64,
+    pub hash: H256,
+    /// The latest safe block hash, if the consensus layer has reported one.
+    pub safe: Option<H256>,
+    /// The latest finalized block hash, if the consensus layer has reported one.
+    pub finalized: Option<H256>,
+}
+
+/// Environment-wide mdbx statistics, as returned by `Client::env_info`. The
+/// page counts are those of the main database, which holds the table names.
+/// Dirty and spilled page counts only exist for write transactions, so they
+/// are not reported for a read-only environment.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EnvInfo {
+    /// The size of the memory map, which bounds the size of the data file.
+    pub map_size: u64,
+    pub page_size: u64,
+    /// The bytes of the data file in use, up to its last allocated page.
+    pub used_bytes: u64,
+    /// The id of the last committed transaction.
+    pub last_txn_id: u64,
+    pub max_readers: u64,
+    /// The number of reader slots in use, by this and any other process.
+    pub readers_in_use: u64,
+    pub tree_depth: u32,
+    pub branch_pages: u64,
+    pub leaf_pages: u64,
+    pub overflow_pages: u64,
+}
+
+/// The ranges of blocks for which each kind of data is present.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AvailableRanges {
+    pub headers: Option<BlockRange>,
+    pub bodies: Option<BlockRange>,
+    pub receipts: Option<BlockRange>,
+    /// Blocks with account changesets, at which historical state can be read.
+    pub state_history: Option<BlockRange>,
+    /// Blocks covered by the snapshot transaction indices.
+    pub snapshots: Vec<BlockRange>,
+    /// Blocks of segments Erigon has a torrent for but which are missing
+    /// from disk.
+    pub missing_snapshots: Vec<BlockRange>,
+}
+
+/// A block returned by `Client::get_block_raw`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawBlock {
+    /// The block, with an empty transaction list.
+    pub block: Block<TxHash>,
+    /// The number of transactions `get_block` lists, so less any left out by
+    /// the client's `SystemTxs`.
+    pub tx_count: usize,
+}
+
+/// A transaction left out of a block by `Client::get_block_with_txs_lenient`
+/// because it failed to decode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TxDecodeError {
+    /// The index of the transaction in the block.
+    pub index: usize,
+    /// The hash of the transaction as stored.
+    pub hash: H256,
+    pub error: String,
+}
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Either<L, R> {
@@ -232,60 +1354,130 @@ pub enum Either<L, R> {
     Right(R),
 }
 
-/// Returns the (block number, block hash) key used to identify a block in the db
+/// Returned (wrapped in an `anyhow::Error`) by reads of a block or
+/// transaction which isn't in the db, as opposed to one which failed to read.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotFound {
+    #[error("unknown block {0:?}")]
+    Block(BlockId),
+    #[error("unknown transaction {0:?}")]
+    Tx(H256),
+}
+
+/// Reads a page of the storage of the given incarnation of `from`.
+fn read_storage_page<TX: TransactionKind, E: EnvironmentKind>(
+    dbtx: &mut Reader<'_, TX, E>,
+    from: Address,
+    incarnation: u64,
+    cursor: Option<&PageCursor>,
+    limit: usize,
+) -> Result<Page<(H256, H256)>> {
+    let page = dbtx.read_account_storage_page(from, incarnation, cursor, limit)?;
+    Ok(Page {
+        items: page
+            .items
+            .into_iter()
+            .map(|(k, v)| (k, convert::word(v)))
+            .collect(),
+        next_cursor: page.next_cursor,
+    })
+}
+
+/// Returns the resume key of a log read by a `LogStream`: its block number
+/// then its index in the block.
+fn log_page_key(log: &Log) -> Result<[u8; 16]> {
+    let (num, idx) = log
+        .block_number
+        .zip(log.log_index)
+        .ok_or_else(|| format_err!("log without a block number or index"))?;
+    let mut key = [0u8; 16];
+    key[..8].copy_from_slice(&num.as_u64().to_be_bytes());
+    key[8..].copy_from_slice(&idx.as_u64().to_be_bytes());
+    Ok(key)
+}
+
+/// Returns the (block number, block hash) key used to identify a block in the
+/// db. Fails with `NotFound` for an unknown hash or number.
 pub fn get_header_key<T: Into<BlockId> + Send + Sync, TX: TransactionKind, E: EnvironmentKind>(
     dbtx: &mut Reader<'_, TX, E>,
     id: T,
-) -> Result<ak_tables::HeaderKey> {
-    let (num, hash) = match id.into() {
-        BlockId::Hash(hash) => {
-            let num = (*dbtx.read_header_number(hash)?).into();
-            (num, hash)
-        }
-        BlockId::Number(id) => match id {
-            EthersBlockNumber::Number(n) => (n, dbtx.read_canonical_hash(n.as_u64().into())?),
+) -> Result<HeaderKey> {
+    let id = id.into();
+    let not_found = || NotFound::Block(id);
+    let (num, hash) = match id {
+        BlockId::Hash(hash) => (dbtx.find_header_number(hash)?.ok_or_else(not_found)?, hash),
+        BlockId::Number(n) => match n {
+            EthersBlockNumber::Number(n) => {
+                let num = BlockNum::from(n);
+                (num, dbtx.find_canonical_hash(num)?.ok_or_else(not_found)?)
+            }
             EthersBlockNumber::Latest | EthersBlockNumber::Pending => {
                 let hash = dbtx.read_head_header_hash()?;
-                let num = dbtx.read_header_number(hash)?;
-                (num.0.into(), hash)
+                (dbtx.read_header_number(hash)?, hash)
             }
-            EthersBlockNumber::Earliest => (0.into(), dbtx.read_canonical_hash(0.into())?),
+            EthersBlockNumber::Earliest => (BlockNum(0), dbtx.read_canonical_hash(BlockNum(0))?),
         },
     };
-    Ok((num.as_u64().into(), hash))
+    Ok(HeaderKey::new(num, hash))
 }
 
 pub fn res_block_number<T: Into<EthersBlockNumber>, TX: TransactionKind, E: EnvironmentKind>(
     dbtx: &mut Reader<'_, TX, E>,
     block: T,
-) -> Result<ak_models::BlockNumber> {
+) -> Result<BlockNum> {
     match block.into() {
-        EthersBlockNumber::Number(n) => Ok(n.as_u64().into()),
+        EthersBlockNumber::Number(n) => Ok(n.into()),
         //TODO: check this https://github.com/ledgerwatch/erigon/blob/156da607e7495d709c141aec40f66a2556d35dc0/cmd/rpcdaemon/commands/rpc_block.go#L30
         EthersBlockNumber::Latest | EthersBlockNumber::Pending => {
             let hash = dbtx.read_head_header_hash()?;
             dbtx.read_header_number(hash)
         }
-        EthersBlockNumber::Earliest => Ok(0.into()),
+        EthersBlockNumber::Earliest => Ok(BlockNum(0)),
     }
 }
 
+/// Returns how far the db has synced, which keys found missing are only
+/// trusted at.
+fn sync_progress<TX: TransactionKind, E: EnvironmentKind>(
+    dbtx: &mut Reader<'_, TX, E>,
+) -> Result<SyncProgress> {
+    Ok(SyncProgress {
+        head: dbtx.read_head_header_hash()?,
+        execution: dbtx.read_stage_progress(EXECUTION_STAGE)?,
+        tx_lookup: dbtx.read_stage_progress(TX_LOOKUP_STAGE)?,
+    })
+}
+
 #[cfg(test)]
 mod tests {
-    use akula::models::{Block, BodyForStorage, MessageWithSignature, H256};
+    use akula::models::{self as ak_models, MessageWithSignature, H256};
     use anyhow::Result;
-    use ethers::utils::keccak256;
+    use ethers::{
+        types::{Address, Filter},
+        utils::keccak256,
+    };
     use std::path::PathBuf;
 
-    use super::Client;
+    use super::{Client, Either, Incarnation};
     use crate::{
-        models::Account,
+        budget::CancelToken,
+        builder::{Features, OpenOptions, Pruned, PrunedData, PrunedProfile, SystemTxs},
+        cache::CacheConfig,
+        codec::{BlockCast, BlockFields, CustomTxDecoder, DepositTxDecoder, MsgCast},
+        logs::NotCanonical,
+        models::{Account, DepositTx, Log, Receipt, DEPOSIT_TX_TYPE},
+        readtrace::ReadRecord,
+        snapshot::BlockRange,
+        tables,
         test::{
+            chain::ChainBuilder,
             ffi::writer::Writer,
             rand::{rand_vec, Rand},
             TMP_DIR,
         },
-        utils::{BlockCast, MsgCast},
+        trie,
+        types::BlockNum,
+        warm::WarmTable,
     };
     use rand::{thread_rng, Rng};
 
@@ -301,11 +1493,11 @@ mod tests {
         let bal = <[u8; 32]>::rand(&mut rng).into();
         let acct = Account::new().balance(bal);
 
-        let mut w = Writer::open(TMP_DIR.clone())?;
-        w.put_account(who, acct)?;
-        let path = w.close()?;
+        let chain = ChainBuilder::new()
+            .block(|b| b.account(who, acct))
+            .write(TMP_DIR.clone())?;
 
-        let db = client(path)?;
+        let db = client(chain.path)?;
         let res = db.get_balance(who, None).unwrap();
         assert_eq!(res, bal);
         Ok(())
@@ -318,11 +1510,11 @@ mod tests {
         let nonce = Rand::rand(&mut rng);
         let acct = Account::new().nonce(nonce);
 
-        let mut w = Writer::open(TMP_DIR.clone())?;
-        w.put_account(who, acct)?;
-        let path = w.close()?;
+        let chain = ChainBuilder::new()
+            .block(|b| b.account(who, acct))
+            .write(TMP_DIR.clone())?;
 
-        let db = client(path)?;
+        let db = client(chain.path)?;
         let res = db.get_transaction_count(who, None)?;
         assert_eq!(res, nonce.into());
         Ok(())
@@ -335,107 +1527,343 @@ mod tests {
         let key = Rand::rand(&mut rng);
         let val = Rand::rand(&mut rng);
 
-        let mut w = Writer::open(TMP_DIR.clone())?;
-        w.put_storage(who, key, val)?;
-        let path = w.close()?;
+        let chain = ChainBuilder::new()
+            .block(|b| b.storage(who, key, val))
+            .write(TMP_DIR.clone())?;
 
-        let db = client(path)?;
+        let db = client(chain.path)?;
         let read = db.get_storage_at(who, key, None)?;
         assert_eq!(read, val);
         Ok(())
     }
 
+    #[test]
+    fn test_get_storage_history() -> Result<()> {
+        let who = Address::repeat_byte(0x42);
+        let (slot, other) = (H256::from_low_u64_be(1), H256::from_low_u64_be(2));
+        let word = H256::from_low_u64_be;
+        // the slot is set to 8 in block 2 and 9 in block 3
+        let chain = ChainBuilder::new()
+            .block(|b| {
+                b.account(who, Account::new().incarnation(1))
+                    .storage(who, slot, word(9))
+                    .storage(who, other, word(3))
+            })
+            .block(|b| b.storage_change(who, 1, slot, word(7)))
+            .block(|b| b.storage_change(who, 1, slot, word(8)))
+            .write(TMP_DIR.clone())?;
+        let db = client(chain.path.clone())?;
+
+        let at = |n: u64| Some(ethers::types::BlockNumber::Number(n.into()).into());
+        assert_eq!(db.get_storage_at(who, slot, at(1))?, word(7));
+        assert_eq!(db.get_storage_at(who, slot, at(2))?, word(8));
+        assert_eq!(db.get_storage_at(who, slot, at(3))?, word(9));
+        assert_eq!(db.get_storage_at(who, slot, None)?, word(9));
+        assert_eq!(db.get_storage_at(who, other, at(1))?, word(3));
+        Ok(())
+    }
+
     #[test]
     fn test_get_block_number() -> Result<()> {
         let mut rng = thread_rng();
-        let num = Rand::rand(&mut rng);
-        let hash = keccak256(vec![0x10]).into();
+        let num = u64::from(u32::rand(&mut rng));
 
-        let mut w = Writer::open(TMP_DIR.clone())?;
-        w.put_head_header_hash(hash)?;
-        w.put_header_number(hash, num)?;
-        let path = w.close()?;
+        let chain = ChainBuilder::new()
+            .start(num)
+            .block(|b| b)
+            .write(TMP_DIR.clone())?;
 
-        let db = client(path)?;
+        let db = client(chain.path)?;
         let res = db.get_block_number()?;
-        assert_eq!(res, (*num).into());
+        assert_eq!(res, num.into());
         Ok(())
     }
 
     #[test]
     fn test_get_transaction() -> Result<()> {
         let mut rng = thread_rng();
-        let txs = (0..)
-            .map(|_| MessageWithSignature::rand(&mut rng))
-            .take(5)
-            .collect::<Vec<_>>();
+        let txs: Vec<MessageWithSignature> = rand_vec(&mut rng, 5);
         let tx_hashes = txs.iter().map(|tx| tx.hash());
-        let block_num = Rand::rand(&mut rng);
-
-        let block_body = BodyForStorage::rand(&mut rng);
-        let base_tx_id = block_body.base_tx_id;
-        let block_hash = H256::rand(&mut rng);
 
-        let mut w = Writer::open(TMP_DIR.clone())?;
-        // tx hash -> block number
-        w.put_tx_lookup_entries(block_num, tx_hashes.clone())?;
-        // block number -> block hash
-        w.put_canonical_hash(block_hash, block_num)?;
-        // (block number, block hash) -> body_for_storage
-        w.put_body_for_storage(block_hash, block_num, block_body)?;
-        // store transactions themselves
-        w.put_transactions(txs.clone(), *base_tx_id)?;
-        let path = w.close()?;
+        let chain = ChainBuilder::new()
+            .start(u32::rand(&mut rng).into())
+            .block(|b| b.txs(txs.clone()))
+            .write(TMP_DIR.clone())?;
+        let (block_num, block_hash) = (chain.head().header.number, chain.hash(0));
 
-        let db = client(path)?;
+        let db = client(chain.path.clone())?;
         for (i, hash) in tx_hashes.enumerate() {
             let res = db.get_transaction(hash)?;
-            let expected = Some(MsgCast::new(&txs[i]).cast(block_num, block_hash, i));
+            let expected = Some(MsgCast::new(&txs[i]).cast(block_num, block_hash, i)?);
             assert_eq!(res, expected);
         }
         Ok(())
     }
 
     #[test]
-    fn test_get_block() -> Result<()> {
+    fn test_get_transaction_proof() -> Result<()> {
         let mut rng = thread_rng();
-        let mut block = Block::rand(&mut rng);
-        block.transactions = rand_vec(&mut rng, 5);
-        block.ommers = rand_vec(&mut rng, 5);
-        let body_for_storage = BodyForStorage {
-            base_tx_id: Rand::rand(&mut rng),
-            tx_amount: (block.transactions.len() + 2).try_into()?,
-            uncles: block.ommers.clone(),
-        };
-        let base_tx_id = *body_for_storage.base_tx_id;
-        let block_hash = block.header.hash();
-        let block_num = block.header.number;
+        let chain = ChainBuilder::new()
+            .block(|b| b.txs(rand_vec(&mut rng, 20)))
+            .write(TMP_DIR.clone())?;
+        let block = chain.head();
+
+        let db = client(chain.path.clone())?;
+        for (i, tx) in block.transactions.iter().enumerate() {
+            let res = db.get_transaction_proof(tx.hash())?;
+            assert_eq!(res.block_hash, block.header.hash());
+            assert_eq!(res.transaction_index, i.into());
+            assert_eq!(res.transactions_root, block.header.transactions_root);
+            let proof: Vec<_> = res.proof.iter().map(|node| node.to_vec()).collect();
+            let value = trie::verify_ordered_proof(res.transactions_root, i, &proof)?.unwrap();
+            assert_eq!(H256::from(keccak256(value)), tx.hash());
+        }
+        Ok(())
+    }
 
-        // make our stored senders list spotty, so some need to be recovered
-        let senders = block.transactions.as_slice().iter().map(|t| {
-            if rng.gen() {
-                t.recover_sender().expect("bad sig")
-            } else {
-                Default::default()
+    #[test]
+    fn test_chain_builder() -> Result<()> {
+        let mut rng = thread_rng();
+        let who = Rand::rand(&mut rng);
+        let bal = <[u8; 32]>::rand(&mut rng).into();
+        let chain = ChainBuilder::new()
+            .start(100)
+            .block(|b| b.txs(rand_vec(&mut rng, 3)))
+            .block(|b| b.account(who, Account::new().balance(bal)))
+            .block(|b| b.txs(rand_vec(&mut rng, 2)))
+            .write(TMP_DIR.clone())?;
+
+        let db = client(chain.path.clone())?;
+        assert_eq!(db.get_block_number()?, 102.into());
+        assert_eq!(db.get_balance(who, None)?, bal);
+        for (i, block) in chain.blocks.iter().enumerate() {
+            let res = db.get_block(100 + i as u64)?.unwrap();
+            assert_eq!(res.hash, Some(chain.hash(i)));
+            let hashes: Vec<_> = block.transactions.iter().map(|tx| tx.hash()).collect();
+            assert_eq!(res.transactions, hashes);
+            if i > 0 {
+                assert_eq!(res.parent_hash, chain.hash(i - 1));
             }
-        });
+            for tx in &block.transactions {
+                let res = db.get_transaction(tx.hash())?.unwrap();
+                assert_eq!(res.block_hash, Some(chain.hash(i)));
+            }
+        }
+        Ok(())
+    }
 
-        let mut w = Writer::open(TMP_DIR.clone())?;
-        w.put_header_number(block_hash, block_num)?;
-        w.put_header(block.header.clone())?;
-        w.put_body_for_storage(block_hash, block.header.number, body_for_storage)?;
-        w.put_transactions(block.transactions.clone(), base_tx_id)?;
-        w.put_senders(block_hash, block_num, senders)?;
+    #[test]
+    fn test_env_info() -> Result<()> {
+        let chain = ChainBuilder::new().block(|b| b).write(TMP_DIR.clone())?;
+        let db = client(chain.path.clone())?;
+
+        let _dbtx = db.reader()?;
+        let info = db.env_info()?;
+        assert!(info.page_size.is_power_of_two());
+        assert!(info.used_bytes <= info.map_size);
+        assert!(info.last_txn_id > 0);
+        assert!(info.readers_in_use >= 1);
+        assert!(info.readers_in_use <= info.max_readers);
+        Ok(())
+    }
 
-        // write ommer hashes to db and save them for checking the result
-        let mut ommer_hashes = vec![];
-        for ommer in block.ommers.clone() {
-            ommer_hashes.push(ommer.hash());
-            w.put_canonical_hash(ommer.hash(), ommer.number)?;
+    #[test]
+    fn test_explain_logs() -> Result<()> {
+        let chain = ChainBuilder::new()
+            .start(100)
+            .block(|b| b)
+            .block(|b| b)
+            .block(|b| b)
+            .write(TMP_DIR.clone())?;
+        let db = client(chain.path.clone())?;
+
+        let plan = db.explain_logs(&Filter::new().from_block(100).to_block(102))?;
+        assert_eq!((plan.from, plan.to), (100, 102));
+        assert!(plan.full_scan);
+        assert!(plan.indices.is_empty());
+        assert_eq!(plan.candidate_blocks, 3);
+        assert_eq!(plan.threads, 1);
+
+        // nothing has been indexed, so an address filter has no candidates
+        let filter = Filter::new()
+            .from_block(100)
+            .address(Address::repeat_byte(1))
+            .topic0(H256::repeat_byte(2));
+        let plan = db.explain_logs(&filter)?;
+        assert_eq!((plan.from, plan.to), (100, 102));
+        assert!(!plan.full_scan);
+        assert_eq!(plan.indices, vec!["LogAddressIndex", "LogTopicIndex"]);
+        assert_eq!(plan.candidate_blocks, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_logs_sharded() -> Result<()> {
+        let mut rng = thread_rng();
+        let log = |i: u64, j: u64| Log {
+            address: Address::from_low_u64_be(i % 3),
+            topics: vec![H256::from_low_u64_be(j)],
+            data: H256::from_low_u64_be(i).as_bytes().to_vec().into(),
+        };
+        // enough blocks to be split across threads, some with no logs
+        let mut builder = ChainBuilder::new();
+        for i in 0..300 {
+            builder = builder.block(|b| match i % 4 {
+                0 => b,
+                n => b
+                    .txs(rand_vec(&mut rng, 2))
+                    .receipt(Receipt::default(), (0..n).map(|j| log(i, j)).collect())
+                    .receipt(Receipt::default(), vec![log(i, 9)]),
+            });
         }
+        let chain = builder.write(TMP_DIR.clone())?;
+        let serial = client(chain.path.clone())?;
+        let sharded = Client::<mdbx::NoWriteMap>::builder()
+            .path(chain.path.clone())
+            .log_parallelism(4)
+            .build()?;
+
+        let filter = Filter::new().from_block(1).to_block(300);
+        assert_eq!(sharded.explain_logs(&filter)?.threads, 4);
+        let want = serial.get_logs(&filter)?;
+        assert_eq!(want.len(), 75 * (1 + 1) + 75 * (2 + 1) + 75 * (3 + 1));
+        assert_eq!(sharded.get_logs(&filter)?, want);
+        // the pool is reused by later calls and clones
+        assert_eq!(sharded.clone().get_logs(&filter)?, want);
+
+        // narrow filters stay on the calling thread
+        let filter = Filter::new().from_block(10).to_block(20);
+        assert_eq!(sharded.explain_logs(&filter)?.threads, 1);
+        assert_eq!(sharded.get_logs(&filter)?, serial.get_logs(&filter)?);
+        Ok(())
+    }
 
-        let path = w.close()?;
-        let db = client(path)?;
+    #[test]
+    fn test_get_logs_page() -> Result<()> {
+        let mut rng = thread_rng();
+        let log = |i: u64| Log {
+            address: Address::from_low_u64_be(i),
+            topics: vec![],
+            data: vec![].into(),
+        };
+        let mut builder = ChainBuilder::new();
+        for i in 0..4 {
+            builder = builder.block(|b| {
+                b.txs(rand_vec(&mut rng, 1))
+                    .receipt(Receipt::default(), (0..3).map(|j| log(3 * i + j)).collect())
+            });
+        }
+        let db = client(builder.write(TMP_DIR.clone())?.path)?;
+
+        let filter = Filter::new().from_block(1).to_block(4);
+        let want = db.get_logs(&filter)?;
+        let mut got = vec![];
+        let mut cursor = None;
+        loop {
+            // pages of 5 end and resume mid-block
+            let page = db.get_logs_page(&filter, cursor.as_ref(), 5)?;
+            got.extend(page.items);
+            match page.next_cursor {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+        }
+        assert_eq!(got, want);
+        assert_eq!(got.len(), 12);
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_logs_at_block_hash() -> Result<()> {
+        let mut rng = thread_rng();
+        let log = Log {
+            address: Address::repeat_byte(1),
+            topics: vec![],
+            data: vec![].into(),
+        };
+        let chain = ChainBuilder::new()
+            .block(|b| {
+                b.txs(rand_vec(&mut rng, 1))
+                    .receipt(Receipt::default(), vec![log.clone()])
+                    .side_block(vec![0xff])
+            })
+            .write(TMP_DIR.clone())?;
+        let db = client(chain.path.clone())?;
+
+        let logs = db.get_logs(&Filter::new().at_block_hash(chain.hash(0)))?;
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].block_hash, Some(chain.hash(0)));
+
+        // the logs in the db are the canonical block's, not the sibling's
+        let sibling = chain.side_blocks[0].hash();
+        let err = db
+            .get_logs(&Filter::new().at_block_hash(sibling))
+            .unwrap_err();
+        assert_eq!(err.downcast_ref(), Some(&NotCanonical(sibling)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_warm() -> Result<()> {
+        let mut rng = thread_rng();
+        let chain = ChainBuilder::new()
+            .start(100)
+            .block(|b| b.txs(rand_vec(&mut rng, 3)))
+            .block(|b| b)
+            .block(|b| b.txs(rand_vec(&mut rng, 2)))
+            .write(TMP_DIR.clone())?;
+        let db = client(chain.path.clone())?;
+
+        let report = db.warm(&WarmTable::HOT, 100.., &CancelToken::new())?;
+        assert_eq!(report.blocks, 3);
+        assert!(report.bytes > 0);
+        assert!(!report.cancelled);
+
+        let headers = db.warm(&[WarmTable::Headers], 101..=101, &CancelToken::new())?;
+        assert_eq!(headers.blocks, 1);
+        assert!(headers.bytes < report.bytes);
+
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        let report = db.warm(&WarmTable::HOT, 100.., &cancel)?;
+        assert_eq!(report.blocks, 0);
+        assert_eq!(report.bytes, 0);
+        assert!(report.cancelled);
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_block() -> Result<()> {
+        let mut rng = thread_rng();
+        let txs: Vec<MessageWithSignature> = rand_vec(&mut rng, 5);
+        let ommers: Vec<ak_models::BlockHeader> = rand_vec(&mut rng, 5);
+
+        // make our stored senders list spotty, so some need to be recovered
+        let senders = txs
+            .iter()
+            .map(|t| {
+                if rng.gen() {
+                    t.recover_sender().expect("bad sig")
+                } else {
+                    Default::default()
+                }
+            })
+            .collect();
+
+        let chain = ChainBuilder::new()
+            .start(u32::rand(&mut rng).into())
+            .block(|mut b| {
+                for ommer in ommers {
+                    b = b.ommer(ommer);
+                }
+                b.txs(txs).senders(senders)
+            })
+            .write(TMP_DIR.clone())?;
+        let block = chain.head();
+        let (block_num, block_hash) = (block.header.number, chain.hash(0));
+        let ommer_hashes = block.ommers.iter().map(|o| o.hash()).collect::<Vec<_>>();
+
+        let db = client(chain.path.clone())?;
 
         // test get_block_with_txs
         let res = db.get_block_with_txs(block_hash)?;
@@ -444,7 +1872,7 @@ mod tests {
             .iter()
             .zip(0..)
             .map(|(tx, i)| MsgCast::new(tx).cast(block_num, block_hash, i))
-            .collect();
+            .collect::<Result<_>>()?;
         let expected = BlockCast(&block.header).cast(
             expected_txs,
             block_num,
@@ -456,14 +1884,1003 @@ mod tests {
         // test get_block
         let res = db.get_block(block_hash)?;
         let expected_txs = block.transactions.iter().map(|tx| tx.hash()).collect();
+        let expected = BlockCast(&block.header).cast(
+            expected_txs,
+            block_num,
+            block_hash,
+            ommer_hashes.clone(),
+        );
+        assert_eq!(res, Some(expected));
+
+        // test get_block_raw
+        let res = db.get_block_raw(block_hash)?.unwrap();
         let expected =
-            BlockCast(&block.header).cast(expected_txs, block_num, block_hash, ommer_hashes);
+            BlockCast(&block.header).cast(vec![], block_num, block_hash, ommer_hashes.clone());
+        assert_eq!(res.block, expected);
+        assert_eq!(res.tx_count, block.transactions.len());
+
+        // test the projected reads
+        let all = db.get_block_projected(block_hash, BlockFields::ALL)?;
+        assert_eq!(all, db.get_block(block_hash)?);
+        let res = db
+            .get_block_projected(block_hash, BlockFields::NONE)?
+            .unwrap();
+        assert_eq!(
+            (res.hash, res.parent_hash),
+            (Some(block_hash), block.header.parent_hash)
+        );
+        assert!(res.transactions.is_empty() && res.uncles.is_empty());
+        assert!(res.extra_data.is_empty() && res.logs_bloom.is_none());
+        let fields = BlockFields::ALL.difference(BlockFields::OMMERS);
+        let res = db
+            .get_block_with_txs_projected(block_hash, fields)?
+            .unwrap();
+        assert_eq!(res.transactions.len(), block.transactions.len());
+        assert!(res.uncles.is_empty());
+        assert_eq!(res.logs_bloom, Some(block.header.logs_bloom));
+        let res = db
+            .get_block_projected(block_hash, BlockFields::OMMERS | BlockFields::EXTRA_DATA)?
+            .unwrap();
+        assert_eq!(res.uncles, ommer_hashes);
+        assert!(res.transactions.is_empty());
+        assert_eq!(res.extra_data.to_vec(), block.header.extra_data.to_vec());
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_storage_range() -> Result<()> {
+        let mut rng = thread_rng();
+        let who = Rand::rand(&mut rng);
+        let n = 5;
+        let mut keys: Vec<H256> = rand_vec(&mut rng, n);
+        keys.sort();
+        let vals: Vec<H256> = rand_vec(&mut rng, n);
+
+        let chain = ChainBuilder::new()
+            .block(|mut b| {
+                for (k, v) in keys.iter().zip(vals.iter()) {
+                    b = b.storage(who, *k, *v);
+                }
+                b
+            })
+            .write(TMP_DIR.clone())?;
+
+        let db = client(chain.path)?;
+        let first = db.get_storage_range(who, None, 3)?;
+        assert_eq!(first.items.len(), 3);
+        let rest = db.get_storage_range(who, first.next_cursor.as_ref(), 3)?;
+        assert!(rest.is_last());
+
+        let read = [first.items, rest.items].concat();
+        let expected = keys.into_iter().zip(vals).collect::<Vec<_>>();
+        assert_eq!(read, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_incarnations() -> Result<()> {
+        let who = Address::repeat_byte(0x44);
+        let slot = H256::from_low_u64_be(1);
+        let (old, new) = (H256::from_low_u64_be(7), H256::from_low_u64_be(8));
+
+        let chain = ChainBuilder::new()
+            .block(|b| {
+                b.account(who, Account::new().incarnation(1))
+                    .code(who, 1, vec![0x60, 0x01])
+                    .storage(who, slot, old)
+            })
+            // redeployed without code
+            .block(|b| {
+                b.destroy(who, 1)
+                    .account(who, Account::new().incarnation(2))
+                    .storage(who, slot, new)
+            })
+            .write(TMP_DIR.clone())?;
+
+        let db = client(chain.path)?;
+        let incarnations = db.get_incarnations(who)?;
+        assert_eq!(
+            incarnations,
+            vec![
+                Incarnation {
+                    incarnation: 1,
+                    codehash: Some(keccak256([0x60, 0x01]).into()),
+                    current: false,
+                },
+                Incarnation {
+                    incarnation: 2,
+                    codehash: None,
+                    current: true,
+                },
+            ]
+        );
+        let destroyed = db.get_incarnation_storage_range(who, 1, None, 10)?;
+        assert_eq!(destroyed.items, vec![(slot, old)]);
+        assert_eq!(
+            db.get_storage_range(who, None, 10)?.items,
+            vec![(slot, new)]
+        );
+        assert!(db.get_incarnations(Address::zero())?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_code_history() -> Result<()> {
+        let who = Address::repeat_byte(0x42);
+        let (code_a, code_b) = (vec![0x60, 0x00], vec![0x60, 0x01]);
+        let deployed = |incarnation, code: &[u8]| Account {
+            nonce: 1,
+            incarnation,
+            codehash: keccak256(code).into(),
+            ..Default::default()
+        };
+        // deployed in block 2, then destroyed and redeployed in block 3
+        let chain = ChainBuilder::new()
+            .block(|b| b)
+            .block(|b| b.account_change(who, None).code(who, 1, code_a.clone()))
+            .block(|b| {
+                // changesets omit the code hash
+                let before = Account {
+                    codehash: Default::default(),
+                    ..deployed(1, &code_a)
+                };
+                b.account_change(who, Some(before))
+                    .account(who, deployed(2, &code_b))
+                    .code(who, 2, code_b.clone())
+            })
+            .write(TMP_DIR.clone())?;
+        let db = client(chain.path.clone())?;
+
+        let at = |n: u64| Some(ethers::types::BlockNumber::Number(n.into()).into());
+        assert!(db.get_code(who, at(1))?.is_empty());
+        assert_eq!(db.get_code(who, at(2))?.to_vec(), code_a);
+        assert_eq!(db.get_code(who, at(3))?.to_vec(), code_b);
+        assert_eq!(db.get_code(who, None)?.to_vec(), code_b);
+        assert!(db.get_code(Address::zero(), at(2))?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_account_history() -> Result<()> {
+        let who = Address::repeat_byte(0x42);
+        // created in block 2, then spends in block 3
+        let chain = ChainBuilder::new()
+            .block(|b| b)
+            .block(|b| b.account_change(who, None))
+            .block(|b| {
+                b.account_change(who, Some(Account::new().balance(10.into())))
+                    .account(who, Account::new().balance(4.into()).nonce(1))
+            })
+            .write(TMP_DIR.clone())?;
+        let db = client(chain.path.clone())?;
+
+        let at = |n: u64| Some(ethers::types::BlockNumber::Number(n.into()).into());
+        assert_eq!(db.get_balance(who, at(1))?, 0.into());
+        assert_eq!(db.get_balance(who, at(2))?, 10.into());
+        assert_eq!(db.get_transaction_count(who, at(2))?, 0.into());
+        assert_eq!(db.get_balance(who, at(3))?, 4.into());
+        assert_eq!(db.get_transaction_count(who, at(3))?, 1.into());
+        assert_eq!(db.get_transaction_count(who, None)?, 1.into());
+        assert!(db.get_balance(who, at(4)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_storage_slot_count() -> Result<()> {
+        let mut rng = thread_rng();
+        let who = Rand::rand(&mut rng);
+        let n = 5;
+        let keys: Vec<H256> = rand_vec(&mut rng, n);
+        let vals: Vec<H256> = rand_vec(&mut rng, n);
+        let other = Rand::rand(&mut rng);
+
+        let chain = ChainBuilder::new()
+            .block(|mut b| {
+                for (k, v) in keys.iter().zip(vals.iter()) {
+                    b = b.storage(who, *k, *v);
+                }
+                // another account's storage isn't counted
+                b.storage(other, keys[0], vals[0])
+            })
+            .write(TMP_DIR.clone())?;
+
+        let db = client(chain.path)?;
+        assert_eq!(db.storage_slot_count(who)?, n as u64);
+        assert_eq!(db.storage_slot_count(Address::zero())?, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_keys() -> Result<()> {
+        let who = Address::repeat_byte(0x33);
+        let hash = H256::repeat_byte(0xab);
+        let bal = ethers::types::U256::from(7);
+
+        let mut w = Writer::open(TMP_DIR.clone())?;
+        w.put_head_header_hash(H256::repeat_byte(0x01))?;
+        let db = Client::<mdbx::NoWriteMap>::builder()
+            .path(w.path())
+            .open_options(OpenOptions::live_node())
+            .build()?;
+        assert!(db.get_transaction(hash).is_err());
+        assert_eq!(db.get_balance(who, None)?, 0.into());
+        assert_eq!(db.cache_metrics().missing.entries, 2);
+
+        // executing the head drops every missing key, though the head is unchanged
+        w.put_account(who, Account::new().balance(bal))?;
+        w.put_stage_progress(crate::reader::EXECUTION_STAGE, ak_models::BlockNumber(1))?;
+        assert_eq!(db.get_balance(who, None)?, bal);
+        assert_eq!(db.cache_metrics().missing.entries, 0);
+
+        // as does a new head
+        let other = Address::repeat_byte(0x34);
+        assert_eq!(db.get_balance(other, None)?, 0.into());
+        w.put_account(other, Account::new().balance(bal))?;
+        w.put_head_header_hash(H256::repeat_byte(0x02))?;
+        assert_eq!(db.get_balance(other, None)?, bal);
+        w.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_builder() -> Result<()> {
+        let mut rng = thread_rng();
+        let who = Rand::rand(&mut rng);
+        let code_hash = keccak256(vec![0xff]).into();
+        let acct = Account::new().codehash(code_hash);
+
+        let chain = ChainBuilder::new()
+            .block(|b| b.account(who, acct))
+            .write(TMP_DIR.clone())?;
+
+        let db = Client::<mdbx::NoWriteMap>::builder()
+            .path(chain.path)
+            .cache_config(CacheConfig::disabled())
+            .features(Features {
+                recover_senders: false,
+                ..Default::default()
+            })
+            .build()?;
+        assert!(!db.options().features.recover_senders);
+
+        // code isn't in the db, and nothing should be cached
+        assert!(db.get_code(who, None).is_err());
+        let metrics = db.cache_metrics();
+        assert_eq!(metrics.code.capacity, 0);
+        assert_eq!(metrics.code.entries, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_block_number_by_timestamp() -> Result<()> {
+        let mut builder = ChainBuilder::new().start(10);
+        for i in 0..9 {
+            builder = builder.block(|b| b.timestamp(100 + 10 * i));
+        }
+        let db = client(builder.write(TMP_DIR.clone())?.path)?;
+
+        let at = |ts| db.get_block_number_by_timestamp(ts);
+        assert_eq!(at(99)?, None);
+        assert_eq!(at(100)?, Some(10.into()));
+        assert_eq!(at(135)?, Some(13.into()));
+        assert_eq!(at(140)?, Some(14.into()));
+        assert_eq!(at(180)?, Some(18.into()));
+        assert_eq!(at(u64::MAX)?, Some(18.into()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_recover_block_senders() -> Result<()> {
+        let mut rng = thread_rng();
+        let msgs: Vec<MessageWithSignature> = rand_vec(&mut rng, 3);
+        let senders = msgs
+            .iter()
+            .map(|msg| msg.recover_sender())
+            .collect::<Result<Vec<_>>>()?;
+        let chain = ChainBuilder::new().block(|b| b).write(TMP_DIR.clone())?;
+        let db = client(chain.path.clone())?;
+        let num = BlockNum(1);
+
+        // the stored senders are kept, and the zero or missing ones recovered
+        let stored = Address::repeat_byte(0x11);
+        let known = [stored, Address::zero()];
+        assert_eq!(
+            db.recover_block_senders(num, &msgs, &known)?,
+            vec![stored, senders[1], senders[2]]
+        );
+
+        let db = Client::<mdbx::NoWriteMap>::builder()
+            .path(chain.path.clone())
+            .features(Features {
+                recover_senders: false,
+                ..Default::default()
+            })
+            .build()?;
+        assert!(db.recover_block_senders(num, &msgs, &known).is_err());
+        assert!(db.recover_block_senders(num, &msgs, &senders[..2]).is_err());
+        assert_eq!(db.recover_block_senders(num, &msgs, &senders)?, senders);
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_timeout() -> Result<()> {
+        let chain = ChainBuilder::new().block(|b| b).write(TMP_DIR.clone())?;
+        let timeout = std::time::Duration::from_millis(20);
+        let db = Client::<mdbx::NoWriteMap>::builder()
+            .path(chain.path.clone())
+            .read_timeout(timeout)
+            .build()?;
+
+        // each reader's budget starts when it's opened, not with the client
+        std::thread::sleep(timeout * 2);
+        assert_eq!(db.get_block_number()?, 1.into());
+        let dbtx = db.reader()?;
+        assert!(dbtx.budget().check().is_ok());
+        std::thread::sleep(timeout * 2);
+        assert!(dbtx.budget().check().is_err());
+        drop(dbtx);
+
+        // as does each read of an owned reader
+        let owned = db.owned_reader();
+        std::thread::sleep(timeout * 2);
+        owned.read(|dbtx| dbtx.budget().check())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_slow_read_log() -> Result<()> {
+        let mut rng = thread_rng();
+        let who = Address::repeat_byte(0x42);
+        let chain = ChainBuilder::new()
+            .block(|b| b.account(who, Account::new().balance(5.into())))
+            .block(|b| b.txs(rand_vec(&mut rng, 2)))
+            .write(TMP_DIR.clone())?;
+
+        // every read is logged, which mustn't change what it returns
+        let db = Client::<mdbx::NoWriteMap>::builder()
+            .path(chain.path.clone())
+            .slow_read_threshold(std::time::Duration::ZERO)
+            .build()?;
+        let plain = client(chain.path.clone())?;
+        assert_eq!(db.get_block_number()?, plain.get_block_number()?);
+        assert_eq!(db.chain_head()?, plain.chain_head()?);
+        assert_eq!(db.get_header(1u64)?, plain.get_header(1u64)?);
+        assert_eq!(db.get_block_raw(2u64)?, plain.get_block_raw(2u64)?);
+        assert_eq!(db.get_balance(who, None)?, 5.into());
+        assert_eq!(db.get_incarnations(who)?, plain.get_incarnations(who)?);
+        let filter = Filter::new().from_block(0).to_block(2);
+        assert_eq!(
+            db.get_logs_page(&filter, None, 10)?,
+            plain.get_logs_page(&filter, None, 10)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_trace() -> Result<()> {
+        let chain = ChainBuilder::new()
+            .start(7)
+            .block(|b| b)
+            .write(TMP_DIR.clone())?;
+        let (path, hash) = (chain.path.clone(), chain.hash(0));
+
+        let db = client(path.clone())?;
+        db.get_block_number()?;
+        assert_eq!(db.last_read_trace(), None);
+
+        let db = Client::<mdbx::NoWriteMap>::builder()
+            .path(path)
+            .read_trace(16)
+            .build()?;
+        assert_eq!(db.last_read_trace(), Some(vec![]));
+        db.get_block_number()?;
+        let trace = db.last_read_trace().unwrap();
+        let reads = trace
+            .iter()
+            .map(|r| (r.table.as_str(), r.key.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            reads,
+            vec![
+                (
+                    tables::LastHeader::const_db_name(),
+                    tables::LAST_HEADER_KEY.0.to_vec()
+                ),
+                (
+                    tables::HeaderNumber::const_db_name(),
+                    hash.as_bytes().to_vec()
+                ),
+            ]
+        );
+
+        // only the last transaction's reads are returned
+        db.get_block_number()?;
+        assert_eq!(db.last_read_trace(), Some(trace.clone()));
+        assert_eq!(db.read_trace().unwrap().all().len(), 4);
+
+        // a call traced alone, which doesn't add to the client's trace
+        let (num, reads) = db.trace_reads(|db| db.get_block_number());
+        assert_eq!(num?, 7.into());
+        assert_eq!(reads, trace);
+        assert_eq!(db.read_trace().unwrap().all().len(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_dry_run() -> Result<()> {
+        let chain = ChainBuilder::new().block(|b| b).write(TMP_DIR.clone())?;
+        let db = client(chain.path.clone())?;
+        let hash = chain.hash(0);
+        let header_reads = |reads: &[ReadRecord]| {
+            reads
+                .iter()
+                .filter(|r| r.table == tables::Header::const_db_name())
+                .count()
+        };
+
+        // the header is cached by the first read
+        let (block, reads) = db.trace_reads(|db| db.get_block(hash));
+        assert_eq!(block?.unwrap().hash, Some(hash));
+        assert!(header_reads(&reads) > 0);
+        let (_, reads) = db.trace_reads(|db| db.get_block(hash));
+        assert_eq!(header_reads(&reads), 0);
+
+        // a dry run reads it from the db, and leaves the caches alone
+        let before = db.cache_metrics();
+        let (block, reads) = db.dry_run(|db| db.get_block(hash));
+        assert_eq!(block?.unwrap().hash, Some(hash));
+        assert!(header_reads(&reads) > 0);
+        assert_eq!(db.cache_metrics(), before);
+        Ok(())
+    }
+
+    #[test]
+    fn test_pruned_profile() -> Result<()> {
+        let mut rng = thread_rng();
+        let txs: Vec<MessageWithSignature> = rand_vec(&mut rng, 1);
+        let chain = ChainBuilder::new()
+            .block(|b| b.txs(txs.clone()))
+            .write(TMP_DIR.clone())?;
+        let db = Client::<mdbx::NoWriteMap>::builder()
+            .path(chain.path.clone())
+            .pruned(PrunedProfile {
+                receipts: false,
+                ..PrunedProfile::full_node()
+            })
+            .build()?;
+
+        assert!(db.get_block(1u64)?.is_some());
+        let err = db.get_transaction(txs[0].hash()).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<Pruned>(),
+            Some(&Pruned {
+                method: "get_transaction",
+                data: PrunedData::TxLookup,
+            })
+        );
+        // receipts are kept
+        db.get_logs(&Filter::new().from_block(1).to_block(1))?;
+
+        let db = client(chain.path.clone())?;
+        assert!(db.get_transaction(txs[0].hash())?.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_decoded_events() -> Result<()> {
+        use ethers::{
+            contract::EthEvent,
+            types::{FilterBlockOption, U256},
+        };
+
+        #[derive(Debug, Clone, PartialEq, Eq, EthEvent)]
+        struct Transfer {
+            #[ethevent(indexed)]
+            from: Address,
+            #[ethevent(indexed)]
+            to: Address,
+            value: U256,
+        }
+
+        let mut rng = thread_rng();
+        let txs: Vec<MessageWithSignature> = rand_vec(&mut rng, 2);
+        let (token, other) = (Address::repeat_byte(0xaa), Address::repeat_byte(0xbb));
+        let (from, to) = (Address::repeat_byte(1), Address::repeat_byte(2));
+        let transfer = |address, value| Log {
+            address,
+            topics: vec![Transfer::signature(), from.into(), to.into()],
+            data: H256::from_low_u64_be(value).as_bytes().to_vec().into(),
+        };
+        let unrelated = Log {
+            address: token,
+            topics: vec![H256::repeat_byte(0x99)],
+            data: Default::default(),
+        };
+        let chain = ChainBuilder::new()
+            .block(|b| {
+                b.txs(txs.clone())
+                    .receipt(
+                        Receipt::default(),
+                        vec![transfer(token, 5), unrelated.clone()],
+                    )
+                    .receipt(Receipt::default(), vec![transfer(other, 7)])
+            })
+            .block(|b| b)
+            .write(TMP_DIR.clone())?;
+        let db = client(chain.path.clone())?;
+        let range = || FilterBlockOption::Range {
+            from_block: Some(1u64.into()),
+            to_block: Some(2u64.into()),
+        };
+
+        let events = db.get_decoded_events::<Transfer, _>(range(), None)?;
+        let decoded = |value: u64| Transfer {
+            from,
+            to,
+            value: value.into(),
+        };
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].0, decoded(5));
+        assert_eq!(events[1].0, decoded(7));
+        let meta = &events[1].1;
+        assert_eq!(meta.address, other);
+        assert_eq!(meta.block_number, 1u64.into());
+        assert_eq!(meta.block_hash, chain.hash(0));
+        assert_eq!(meta.transaction_hash, txs[1].hash());
+        assert_eq!(meta.log_index, 2u64.into());
+
+        let events = db.get_decoded_events::<Transfer, _>(range(), Some(token))?;
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].0, decoded(5));
+        Ok(())
+    }
+
+    #[test]
+    fn test_system_txs() -> Result<()> {
+        let mut rng = thread_rng();
+        let txs: Vec<MessageWithSignature> = rand_vec(&mut rng, 3);
+        let system = txs[1].recover_sender()?;
+        let chain = ChainBuilder::new()
+            .block(|b| b.txs(txs.clone()))
+            .write(TMP_DIR.clone())?;
+        let db = Client::<mdbx::NoWriteMap>::builder()
+            .path(chain.path.clone())
+            .system_txs(SystemTxs {
+                senders: vec![system],
+                exclude: true,
+                ..Default::default()
+            })
+            .build()?;
+
+        // the remaining txs keep their index in the block
+        let hash = chain.hash(0);
+        let block = db.get_block_with_txs(hash)?.unwrap();
+        let indices = block
+            .transactions
+            .iter()
+            .map(|tx| tx.transaction_index.unwrap().as_u64())
+            .collect::<Vec<_>>();
+        assert_eq!(indices, vec![0, 2]);
+        assert_eq!(
+            db.get_block(hash)?.unwrap().transactions,
+            vec![txs[0].hash(), txs[2].hash()]
+        );
+        assert_eq!(db.get_block_raw(hash)?.unwrap().tx_count, 2);
+        assert_eq!(db.get_transaction(txs[1].hash())?, None);
+        assert!(db.get_transaction(txs[0].hash())?.is_some());
+        Ok(())
+    }
+
+    #[derive(Debug)]
+    struct DepositDecoder;
+
+    impl CustomTxDecoder for DepositDecoder {
+        fn decode(&self, raw: &[u8]) -> Result<ethers::types::Transaction> {
+            Ok(ethers::types::Transaction {
+                from: Address::repeat_byte(0xde),
+                input: raw[1..].to_vec().into(),
+                ..Default::default()
+            })
+        }
+    }
+
+    #[test]
+    fn test_custom_tx_types() -> Result<()> {
+        let mut rng = thread_rng();
+        let tx = MessageWithSignature::rand(&mut rng);
+        let deposit = vec![0x7e, 0xc0];
+        let deposit_hash = H256(keccak256(&deposit));
+        let receipt = |cumulative_gas_used| Receipt {
+            status: 1,
+            cumulative_gas_used,
+            ..Default::default()
+        };
+        let chain = ChainBuilder::new()
+            .block(|b| {
+                b.tx(tx.clone())
+                    .raw_tx(deposit.clone())
+                    .receipt(receipt(21_000), vec![])
+                    .receipt(receipt(50_000), vec![])
+            })
+            .write(TMP_DIR.clone())?;
+        let hash = chain.hash(0);
+
+        // without a decoder, the deposit is read as a tx of an unknown type
+        let db = client(chain.path.clone())?;
+        let block = db.get_block_with_txs(hash)?.unwrap();
+        assert_eq!(block.transactions.len(), 2);
+        let unknown = &block.transactions[1];
+        assert_eq!(unknown.hash, deposit_hash);
+        assert_eq!(unknown.transaction_type, Some(0x7e.into()));
+        assert_eq!(unknown.transaction_index, Some(1.into()));
+        assert_eq!(unknown.input.as_ref(), &deposit[..]);
+        assert_eq!(db.get_transaction(deposit_hash)?.as_ref(), Some(unknown));
+
+        let db = Client::<mdbx::NoWriteMap>::builder()
+            .path(chain.path.clone())
+            .custom_tx_type(0x7e, DepositDecoder)
+            .build()?;
+        let block = db.get_block_with_txs(hash)?.unwrap();
+        assert_eq!(block.transactions.len(), 2);
+        assert_eq!(block.transactions[0].hash, tx.hash());
+        let custom = &block.transactions[1];
+        assert_eq!(custom.hash, deposit_hash);
+        assert_eq!(custom.transaction_type, Some(0x7e.into()));
+        assert_eq!(custom.transaction_index, Some(1.into()));
+        assert_eq!(custom.block_hash, Some(hash));
+        assert_eq!(
+            db.get_block(hash)?.unwrap().transactions,
+            vec![tx.hash(), deposit_hash]
+        );
+        assert_eq!(db.get_transaction(deposit_hash)?.as_ref(), Some(custom));
+        assert_eq!(db.get_transaction(tx.hash())?.unwrap().hash, tx.hash());
+
+        // receipts of the block, and of the custom tx alone
+        let receipts = match db.get_block_receipts(1u64)? {
+            Either::Right(receipts) => receipts,
+            Either::Left(_) => panic!("receipts are stored"),
+        };
+        assert_eq!(receipts.len(), 2);
+        assert_eq!(receipts[1].transaction_hash, deposit_hash);
+        assert_eq!(receipts[1].from, Address::repeat_byte(0xde));
+        assert_eq!(receipts[1].gas_used, Some(29_000.into()));
+        assert_eq!(receipts[1].transaction_type, Some(0x7e.into()));
+        match db.get_transaction_receipt(deposit_hash)? {
+            Either::Right(Some(receipt)) => assert_eq!(receipt, receipts[1]),
+            other => panic!("no receipt: {:?}", other),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_optimism_deposits() -> Result<()> {
+        let mut rng = thread_rng();
+        let tx = MessageWithSignature::rand(&mut rng);
+        let system = SystemTxs::optimism();
+        let deposit = |from, source| DepositTx {
+            source_hash: H256::repeat_byte(source),
+            from,
+            to: Some(Address::repeat_byte(0x42)),
+            gas: 1_000_000,
+            ..Default::default()
+        };
+        let attributes = deposit(system.senders[0], 1).encode();
+        let user = deposit(Address::repeat_byte(0xaa), 2).encode();
+        let chain = ChainBuilder::new()
+            .block(|b| {
+                b.raw_tx(attributes.clone())
+                    .tx(tx.clone())
+                    .raw_tx(user.clone())
+            })
+            .write(TMP_DIR.clone())?;
+        let db = Client::<mdbx::NoWriteMap>::builder()
+            .path(chain.path.clone())
+            .custom_tx_type(DEPOSIT_TX_TYPE, DepositTxDecoder)
+            .system_txs(system)
+            .build()?;
+
+        // the L1 attributes deposit is left out, the user deposit is kept
+        let hash = chain.hash(0);
+        let block = db.get_block_with_txs(hash)?.unwrap();
+        let txs = block
+            .transactions
+            .iter()
+            .map(|tx| (tx.hash, tx.transaction_index.unwrap().as_u64()))
+            .collect::<Vec<_>>();
+        let user_hash = H256(keccak256(&user));
+        assert_eq!(txs, vec![(tx.hash(), 1), (user_hash, 2)]);
+        assert_eq!(block.transactions[1].from, Address::repeat_byte(0xaa));
+        // the raw block counts the txs get_block lists
+        assert_eq!(db.get_block(hash)?.unwrap().transactions.len(), 2);
+        assert_eq!(db.get_block_raw(hash)?.unwrap().tx_count, 2);
+        assert_eq!(db.get_transaction(H256(keccak256(&attributes)))?, None);
+        assert_eq!(
+            db.get_transaction(user_hash)?.as_ref(),
+            Some(&block.transactions[1])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_block_with_txs_lenient() -> Result<()> {
+        let mut rng = thread_rng();
+        let txs: Vec<MessageWithSignature> = rand_vec(&mut rng, 2);
+        // an EIP-1559 tx with an empty payload
+        let exotic = vec![0x02, 0xc0];
+        let chain = ChainBuilder::new()
+            .block(|b| b.txs(txs.clone()).raw_tx(exotic.clone()))
+            .write(TMP_DIR.clone())?;
+        let db = client(chain.path.clone())?;
+        let hash = chain.hash(0);
+        assert!(db.get_block_with_txs(hash).is_err());
+
+        let (block, errors) = db.get_block_with_txs_lenient(hash)?.unwrap();
+        assert_eq!(
+            block
+                .transactions
+                .iter()
+                .map(|tx| tx.hash)
+                .collect::<Vec<_>>(),
+            vec![txs[0].hash(), txs[1].hash()]
+        );
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].index, 2);
+        assert_eq!(errors[0].hash, H256(keccak256(&exotic)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_live_node() -> Result<()> {
+        let mut rng = thread_rng();
+        let who = Rand::rand(&mut rng);
+        let bal = <[u8; 32]>::rand(&mut rng).into();
+
+        let mut w = Writer::open(TMP_DIR.clone())?;
+        w.put_account(who, Account::new().balance(bal))?;
+
+        // the writer still holds the environment open
+        let db = Client::<mdbx::NoWriteMap>::builder()
+            .path(w.path())
+            .open_options(OpenOptions::live_node())
+            .build()?;
+        assert_eq!(db.get_balance(who, None)?, bal);
+
+        // no-subdir: open the data file directly
+        let db = Client::<mdbx::NoWriteMap>::builder()
+            .path(w.path().join("mdbx.dat"))
+            .open_options(OpenOptions::live_node())
+            .build()?;
+        assert_eq!(db.get_balance(who, None)?, bal);
+
+        let path = w.close()?;
+        let db = Client::<mdbx::NoWriteMap>::builder()
+            .path(path)
+            .open_options(OpenOptions::offline())
+            .build()?;
+        assert_eq!(db.get_balance(who, None)?, bal);
+        Ok(())
+    }
+
+    #[test]
+    fn test_execution_safe_head() -> Result<()> {
+        let mut rng = thread_rng();
+        let executed = u64::from(u32::rand(&mut rng));
+        let head = executed + 2;
+
+        let chain = ChainBuilder::new()
+            .start(executed)
+            .stage_progress(crate::reader::EXECUTION_STAGE, executed)
+            .block(|b| b)
+            .block(|b| b)
+            .block(|b| b)
+            .write(TMP_DIR.clone())?;
+        let path = chain.path;
+
+        let db = client(path.clone())?;
+        assert_eq!(db.get_block_number()?, head.into());
+
+        let db = Client::<mdbx::NoWriteMap>::builder()
+            .path(path)
+            .features(Features {
+                execution_safe_head: true,
+                ..Default::default()
+            })
+            .build()?;
+        assert_eq!(db.get_block_number()?, executed.into());
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_block_number_fallback() -> Result<()> {
+        let mut rng = thread_rng();
+        let num = ak_models::BlockNumber(u32::rand(&mut rng).into());
+
+        let mut w = Writer::open(TMP_DIR.clone())?;
+        // the head header hash has no HeaderNumber entry
+        w.put_head_header_hash(H256::rand(&mut rng))?;
+        w.put_canonical_hash(H256::rand(&mut rng), num)?;
+        w.put_canonical_hash(H256::rand(&mut rng), ak_models::BlockNumber(num.0 + 1))?;
+        let path = w.close()?;
+
+        let db = client(path.clone())?;
+        assert_eq!(db.get_block_number()?, (num.0 + 1).into());
+
+        // without Execution progress a capped client fails instead of
+        // reporting the uncapped head
+        let capped = Client::<mdbx::NoWriteMap>::builder()
+            .path(path)
+            .features(Features {
+                execution_safe_head: true,
+                ..Default::default()
+            })
+            .build()?;
+        assert!(capped.get_block_number().is_err());
+
+        // no LastHeader at all
+        let mut w = Writer::open(TMP_DIR.clone())?;
+        w.put_canonical_hash(H256::rand(&mut rng), num)?;
+        let db = client(w.close()?)?;
+        assert_eq!(db.get_block_number()?, (*num).into());
+        Ok(())
+    }
+
+    #[test]
+    fn test_chain_head() -> Result<()> {
+        let mut rng = thread_rng();
+        let num = u64::from(u32::rand(&mut rng)) + 2;
+        let chain = ChainBuilder::new()
+            .start(num - 2)
+            .block(|b| b)
+            .block(|b| b)
+            .block(|b| b);
+        let (safe, finalized) = (H256::rand(&mut rng), H256::rand(&mut rng));
+
+        let built = chain.clone().write(TMP_DIR.clone())?;
+        let res = client(built.path.clone())?.chain_head()?;
+        assert_eq!((res.number, res.hash), (num.into(), built.hash(2)));
+        assert_eq!((res.safe, res.finalized), (None, None));
+
+        let built = chain.forkchoice(safe, finalized).write(TMP_DIR.clone())?;
+        let res = client(built.path.clone())?.chain_head()?;
+        assert_eq!((res.safe, res.finalized), (Some(safe), Some(finalized)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_blocks_at_height() -> Result<()> {
+        let mut rng = thread_rng();
+        let num = u64::from(u32::rand(&mut rng)) + 1;
+
+        // neighbouring heights aren't included
+        let chain = ChainBuilder::new()
+            .start(num - 1)
+            .block(|b| b)
+            .block(|b| b.side_block(vec![0]).side_block(vec![2]))
+            .block(|b| b)
+            .write(TMP_DIR.clone())?;
+
+        let db = client(chain.path.clone())?;
+        let res = db.get_blocks_at_height(num)?;
+        assert_eq!(res.len(), 3);
+        assert_eq!(res[0].hash, Some(chain.hash(1)));
+        let mut hashes: Vec<_> = res.iter().map(|b| b.hash.unwrap()).collect();
+        let mut expected: Vec<_> = chain.side_blocks.iter().map(|h| h.hash()).collect();
+        expected.push(chain.hash(1));
+        hashes.sort();
+        expected.sort();
+        assert_eq!(hashes, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_issuance() -> Result<()> {
+        let mut rng = thread_rng();
+        let first = u64::from(u32::rand(&mut rng)) + 1;
+        // cumulative (issued, burnt) totals as of each block
+        let chain = ChainBuilder::new()
+            .start(first)
+            .block(|b| b.issuance(100, 10))
+            .block(|b| b.issuance(150, 25))
+            .block(|b| b.issuance(190, 55))
+            .write(TMP_DIR.clone())?;
+        let hash = chain.hash(2);
+
+        let db = client(chain.path.clone())?;
+        let res = db.get_issuance(hash)?;
+        assert_eq!(res.block_number, (first + 2).into());
+        assert_eq!((res.issuance, res.burnt), (40.into(), 30.into()));
+        assert_eq!((res.total_issued, res.total_burnt), (190.into(), 55.into()));
+
+        let res = db.get_issuance_range(first + 1, first + 2)?;
+        assert_eq!((res.issuance, res.burnt), (90.into(), 45.into()));
+        assert_eq!(res.net_supply_change(), (45.into(), false));
+        // the totals before the range are needed
+        assert!(db.get_issuance_range(first, first + 2).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_available_ranges() -> Result<()> {
+        let mut rng = thread_rng();
+        let from = u64::from(u32::rand(&mut rng));
+
+        let chain = ChainBuilder::new()
+            .start(from)
+            .block(|b| b)
+            .block(|b| b)
+            .block(|b| b)
+            .write(TMP_DIR.clone())?;
+
+        let db = client(chain.path)?;
+        let ranges = db.available_ranges()?;
+        let expected = BlockRange { from, to: from + 3 };
+        assert_eq!(ranges.headers, Some(expected));
+        assert_eq!(ranges.bodies, Some(expected));
+        // no receipts or state history were written
+        assert_eq!(ranges.receipts, None);
+        assert_eq!(ranges.state_history, None);
+        assert!(ranges.snapshots.is_empty());
+        assert!(ranges.missing_snapshots.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_header() -> Result<()> {
+        let mut rng = thread_rng();
+        let chain = ChainBuilder::new()
+            .start(u32::rand(&mut rng).into())
+            .block(|b| b)
+            .write(TMP_DIR.clone())?;
+        let header = &chain.head().header;
+        let (block_num, block_hash) = (header.number, chain.hash(0));
+
+        let db = client(chain.path.clone())?;
+        let res = db.get_header(block_hash)?;
+        let expected = BlockCast(header).cast_header(block_num, block_hash);
         assert_eq!(res, Some(expected));
         Ok(())
     }
 
+    #[test]
+    fn test_get_uncle() -> Result<()> {
+        let mut rng = thread_rng();
+        let uncles: Vec<ak_models::BlockHeader> = rand_vec(&mut rng, 2);
+
+        let chain = ChainBuilder::new()
+            .block(|b| b.ommer(uncles[0].clone()).ommer(uncles[1].clone()))
+            .write(TMP_DIR.clone())?;
+        let block_hash = chain.hash(0);
+
+        let db = client(chain.path)?;
+        let res = db.get_uncle(block_hash, 1.into())?.unwrap();
+        assert_eq!(res.hash, Some(uncles[1].hash()));
+        assert_eq!(res.number, Some((*uncles[1].number).into()));
+        assert_eq!(res.parent_hash, uncles[1].parent_hash);
+        assert!(res.transactions.is_empty());
+        assert!(res.uncles.is_empty());
+        assert!(res.size.is_some());
+        assert_eq!(res.total_difficulty, None);
+
+        assert_eq!(db.get_uncle(block_hash, 2.into())?, None);
+        assert_eq!(db.get_uncle_count(block_hash)?, 2.into());
+        Ok(())
+    }
+
     #[test]
     fn test_get_header_key() -> Result<()> {
         Ok(())
     }
+
+    #[test]
+    fn test_send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Client<mdbx::NoWriteMap>>();
+        assert_send_sync::<crate::reader::OwnedReader<mdbx::NoWriteMap>>();
+        assert_send_sync::<
+            crate::middleware::DbMiddleware<
+                ethers::providers::Provider<ethers::providers::MockProvider>,
+                mdbx::NoWriteMap,
+            >,
+        >();
+    }
 }
@@ -0,0 +1,274 @@
+//! Request coalescing for `DbMiddleware`. Indexers often have many tasks ask
+//! for the same block or receipts at once; with coalescing enabled, the first
+//! request for a key reads the db and the requests for the same key arriving
+//! while it runs wait for its result instead of repeating the read. Reads run
+//! on the blocking pool, so neither the leader nor the waiters hold up an
+//! async worker.
+
+use anyhow::Result;
+use std::{collections::HashMap, hash::Hash, sync::Mutex};
+use tokio::sync::watch;
+
+/// Which `DbMiddleware` methods coalesce concurrent requests. All are off by
+/// default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CoalesceConfig {
+    pub get_block: bool,
+    pub get_block_with_txs: bool,
+    pub get_transaction_receipt: bool,
+    pub get_block_receipts: bool,
+}
+
+impl CoalesceConfig {
+    /// Coalesces every method which supports it.
+    pub fn all() -> Self {
+        Self {
+            get_block: true,
+            get_block_with_txs: true,
+            get_transaction_receipt: true,
+            get_block_receipts: true,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum State<V> {
+    Running,
+    Done(V),
+    Failed,
+}
+
+/// Shares the result of a read among the concurrent requests for the same
+/// key, as in Go's singleflight. Only reads in progress are shared: a request
+/// arriving after the read finished makes a new one. Waiting requests await
+/// the read's result rather than block their thread.
+#[derive(Debug)]
+pub struct Coalescer<K, V> {
+    flights: Mutex<HashMap<K, watch::Receiver<State<V>>>>,
+}
+
+impl<K, V> Default for Coalescer<K, V> {
+    fn default() -> Self {
+        Self {
+            flights: Default::default(),
+        }
+    }
+}
+
+impl<K: Eq + Hash + Clone, V: Clone + Send + 'static> Coalescer<K, V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the result of `read`, or of the read already in progress for
+    /// `key`. Errors aren't shared, so they keep their type for callers which
+    /// downcast them: if the read in progress fails, each waiting request
+    /// makes its own.
+    pub async fn run<F>(&self, key: K, read: F) -> Result<V>
+    where
+        F: FnOnce() -> Result<V> + Send + 'static,
+    {
+        let waiting = {
+            let mut flights = self.flights.lock().unwrap();
+            match flights.get(&key) {
+                Some(flight) => Err(flight.clone()),
+                None => {
+                    let (tx, rx) = watch::channel(State::Running);
+                    flights.insert(key.clone(), rx);
+                    Ok(tx)
+                }
+            }
+        };
+        let tx = match waiting {
+            Ok(tx) => tx,
+            Err(mut flight) => {
+                loop {
+                    let landed = match &*flight.borrow() {
+                        State::Running => None,
+                        State::Done(value) => Some(Some(value.clone())),
+                        State::Failed => Some(None),
+                    };
+                    match landed {
+                        Some(Some(value)) => return Ok(value),
+                        Some(None) => break,
+                        None => {
+                            // the leader is gone without landing
+                            if flight.changed().await.is_err() {
+                                break;
+                            }
+                        }
+                    }
+                }
+                return blocking(read).await;
+            }
+        };
+
+        // marks the flight failed if `read` panics, so waiters don't hang
+        let mut landing = Landing {
+            coalescer: self,
+            key,
+            tx,
+            state: Some(State::Failed),
+        };
+        let res = blocking(read).await;
+        if let Ok(value) = &res {
+            landing.state = Some(State::Done(value.clone()));
+        }
+        res
+    }
+
+    /// The number of reads in progress.
+    pub fn in_flight(&self) -> usize {
+        self.flights.lock().unwrap().len()
+    }
+}
+
+/// Runs `read` on the blocking pool. A panic in `read` resumes in the caller,
+/// as it would had `read` run inline.
+async fn blocking<V, F>(read: F) -> Result<V>
+where
+    V: Send + 'static,
+    F: FnOnce() -> Result<V> + Send + 'static,
+{
+    match tokio::task::spawn_blocking(read).await {
+        Ok(res) => res,
+        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Ends a flight on drop: removes it from the coalescer, so later requests
+/// make a new read, and wakes its waiters with `state`.
+struct Landing<'a, K: Eq + Hash, V> {
+    coalescer: &'a Coalescer<K, V>,
+    key: K,
+    tx: watch::Sender<State<V>>,
+    state: Option<State<V>>,
+}
+
+impl<K: Eq + Hash, V> Drop for Landing<'_, K, V> {
+    fn drop(&mut self) {
+        self.coalescer.flights.lock().unwrap().remove(&self.key);
+        if let Some(state) = self.state.take() {
+            // fails only once every waiter is gone
+            let _ = self.tx.send(state);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::format_err;
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        thread,
+        time::Duration,
+    };
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_coalescer() {
+        let coalescer = Arc::new(Coalescer::<u64, u64>::new());
+        let reads = Arc::new(AtomicUsize::new(0));
+        let tasks = (0..8)
+            .map(|_| {
+                let (coalescer, reads) = (Arc::clone(&coalescer), Arc::clone(&reads));
+                tokio::spawn(async move {
+                    coalescer
+                        .run(1, move || {
+                            reads.fetch_add(1, Ordering::SeqCst);
+                            thread::sleep(Duration::from_millis(200));
+                            Ok(42)
+                        })
+                        .await
+                })
+            })
+            .collect::<Vec<_>>();
+        for task in tasks {
+            assert_eq!(task.await.unwrap().unwrap(), 42);
+        }
+        // the tasks all start well within the first read
+        assert_eq!(reads.load(Ordering::SeqCst), 1);
+        assert_eq!(coalescer.in_flight(), 0);
+
+        // later requests read again
+        assert_eq!(coalescer.run(1, || Ok(43)).await.unwrap(), 43);
+    }
+
+    #[tokio::test]
+    async fn test_coalescer_waiters_yield() {
+        let coalescer = Arc::new(Coalescer::<u64, u64>::new());
+        let (started_tx, started_rx) = std::sync::mpsc::channel();
+        let leader = thread::spawn({
+            let coalescer = Arc::clone(&coalescer);
+            move || {
+                let rt = tokio::runtime::Builder::new_current_thread()
+                    .build()
+                    .unwrap();
+                rt.block_on(coalescer.run(1, move || {
+                    started_tx.send(()).unwrap();
+                    thread::sleep(Duration::from_millis(200));
+                    Ok(42)
+                }))
+            }
+        });
+        started_rx.recv().unwrap();
+
+        let parked = Arc::new(AtomicUsize::new(0));
+        let waiters = (0..4)
+            .map(|_| {
+                let (coalescer, parked) = (Arc::clone(&coalescer), Arc::clone(&parked));
+                tokio::spawn(async move {
+                    parked.fetch_add(1, Ordering::SeqCst);
+                    coalescer.run(1, || Ok(0)).await
+                })
+            })
+            .collect::<Vec<_>>();
+        while parked.load(Ordering::SeqCst) < 4 {
+            tokio::task::yield_now().await;
+        }
+        // the waiters await the read on the runtime's only thread instead of
+        // blocking it until the read lands
+        assert_eq!(coalescer.in_flight(), 1);
+        for waiter in waiters {
+            assert_eq!(waiter.await.unwrap().unwrap(), 42);
+        }
+        assert_eq!(leader.join().unwrap().unwrap(), 42);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_coalescer_errors() {
+        let coalescer = Arc::new(Coalescer::<u64, u64>::new());
+        let leader = {
+            let coalescer = Arc::clone(&coalescer);
+            tokio::spawn(async move {
+                coalescer
+                    .run(1, || {
+                        thread::sleep(Duration::from_millis(200));
+                        Err(format_err!("failed"))
+                    })
+                    .await
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        // waits for the leader, then makes its own read
+        assert_eq!(coalescer.run(1, || Ok(7)).await.unwrap(), 7);
+        assert!(leader.await.unwrap().is_err());
+
+        // a panicking read fails the flight rather than leaving it running
+        let res = tokio::spawn({
+            let coalescer = Arc::clone(&coalescer);
+            async move {
+                coalescer
+                    .run(2, || -> Result<u64> { panic!("read panicked") })
+                    .await
+            }
+        })
+        .await;
+        assert!(res.is_err());
+        assert_eq!(coalescer.in_flight(), 0);
+    }
+}
@@ -0,0 +1,812 @@
+//! Decoding of the values Erigon stores and their conversion into ethers
+//! types. Nothing here touches mdbx, so this module, along with the `bitmap`,
+//! `convert`, `trie` and `types` modules, is available when the crate is built
+//! without the `db` feature, and none of them spawn threads on `wasm32`.
+//!
+//! The crate doesn't build for `wasm32` yet: akula, whose models these decode
+//! into, links libmdbx whatever the features, and some of the other modules
+//! built without `db` use tokio and memory maps.
+
+use akula::models::{Address, BlockHeader, Message, MessageWithSignature};
+use anyhow::{format_err, Result};
+use ethers::{
+    types::{Transaction, H256, U256, U64},
+    utils::keccak256,
+};
+use std::{collections::HashMap, fmt, sync::Arc};
+
+pub use crate::{
+    bitmap::{decode_roaring, decode_roaring64, encode_roaring, encode_roaring64},
+    models::{
+        Account, CallTrace, DepositTx, Log, Receipt, StorageBucket, Withdrawal, DEPOSIT_TX_TYPE,
+    },
+};
+use crate::{convert, types::BlockNum};
+
+// Below this many missing senders, spawning threads costs more than it saves
+const MIN_PARALLEL_RECOVERY: usize = 16;
+
+// The typed transactions `MessageWithSignature` decodes: EIP-2930 and EIP-1559
+const BUILTIN_TX_TYPES: [u8; 2] = [0x01, 0x02];
+
+// https://github.com/akula-bft/akula/blob/a9aed09b31bb41c89832149bcad7248f7fcd70ca/src/models/account.rs#L47
+pub fn bytes_to_u64(buf: &[u8]) -> u64 {
+    let mut decoded = [0u8; 8];
+    for (i, b) in buf.iter().rev().enumerate() {
+        decoded[i] = *b;
+    }
+    u64::from_le_bytes(decoded)
+}
+
+/// Returns the sender of each message, taken from `known` where it has a nonzero
+/// entry and otherwise recovered from the signature. Recovery is split across
+/// threads when enough senders are missing, except on `wasm32`.
+pub fn recover_senders(msgs: &[MessageWithSignature], known: &[Address]) -> Result<Vec<Address>> {
+    let mut senders = (0..msgs.len())
+        .map(|i| known.get(i).copied().unwrap_or_default())
+        .collect::<Vec<_>>();
+    let missing = senders.iter().filter(|s| s.is_zero()).count();
+    if missing == 0 {
+        return Ok(senders);
+    }
+    if missing < MIN_PARALLEL_RECOVERY {
+        recover_serial(msgs, &mut senders)?;
+        return Ok(senders);
+    }
+    recover_parallel(msgs, &mut senders)?;
+    Ok(senders)
+}
+
+fn recover_serial(msgs: &[MessageWithSignature], senders: &mut [Address]) -> Result<()> {
+    for (msg, sender) in msgs.iter().zip(senders.iter_mut()) {
+        if sender.is_zero() {
+            *sender = msg.recover_sender()?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(target_arch = "wasm32")]
+fn recover_parallel(msgs: &[MessageWithSignature], senders: &mut [Address]) -> Result<()> {
+    recover_serial(msgs, senders)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn recover_parallel(msgs: &[MessageWithSignature], senders: &mut [Address]) -> Result<()> {
+    use std::thread;
+
+    let threads = thread::available_parallelism().map_or(1, usize::from);
+    if threads == 1 {
+        return recover_serial(msgs, senders);
+    }
+
+    let chunk = (msgs.len() + threads - 1) / threads;
+    thread::scope(|scope| {
+        let handles = msgs
+            .chunks(chunk)
+            .zip(senders.chunks_mut(chunk))
+            .map(|(msgs, senders)| scope.spawn(move || recover_serial(msgs, senders)))
+            .collect::<Vec<_>>();
+        handles.into_iter().try_for_each(|handle| {
+            handle
+                .join()
+                .map_err(|_| format_err!("sender recovery thread panicked"))?
+        })
+    })
+}
+
+/// Converts akula message data into ethers transaction data. The block
+/// context (sender, block, index and base fee) is optional, and is set with the
+/// builder methods before producing a full `Transaction` or a `TxSummary`:
+///
+/// ```ignore
+/// let tx = MsgCast::new(&msg)
+///     .maybe_signer(sender)
+///     .block(num, hash)
+///     .index(idx)
+///     .base_fee(base_fee)
+///     .transaction()?;
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct MsgCast<'a> {
+    pub msg: &'a MessageWithSignature,
+    pub src: Option<Address>,
+    block: Option<(BlockNum, H256)>,
+    index: Option<usize>,
+    base_fee: Option<U256>,
+}
+
+/// The fields of a transaction needed by most listings, without its input,
+/// signature or access list. Returned by `MsgCast::summary`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TxSummary {
+    pub hash: H256,
+    pub from: Address,
+    pub to: Option<Address>,
+    pub nonce: U256,
+    pub value: U256,
+    pub gas: U256,
+    /// Only known for typed fee txs when the block's base fee is given.
+    pub effective_gas_price: Option<U256>,
+    pub transaction_type: Option<U64>,
+    pub block_number: Option<U64>,
+    pub transaction_index: Option<U64>,
+}
+
+impl<'a> MsgCast<'a> {
+    pub fn new(msg: &'a MessageWithSignature) -> Self {
+        Self {
+            msg,
+            src: None,
+            block: None,
+            index: None,
+            base_fee: None,
+        }
+    }
+
+    /// Sets the sender, unless it is the zero address, in which case it is
+    /// recovered from the signature when needed.
+    pub fn maybe_signer(&mut self, src: Address) -> &mut Self {
+        if src != Default::default() {
+            self.src = Some(src)
+        }
+        self
+    }
+
+    /// Sets the block which includes the transaction.
+    pub fn block<N: Into<BlockNum>>(&mut self, num: N, hash: H256) -> &mut Self {
+        self.block = Some((num.into(), hash));
+        self
+    }
+
+    /// Sets the transaction's index in its block.
+    pub fn index(&mut self, idx: usize) -> &mut Self {
+        self.index = Some(idx);
+        self
+    }
+
+    /// Sets the base fee of the including block, if it has one.
+    pub fn base_fee(&mut self, base_fee: Option<U256>) -> &mut Self {
+        self.base_fee = base_fee;
+        self
+    }
+
+    /// Casts a mined transaction. Shorthand for setting the block and index,
+    /// then calling `transaction`.
+    pub fn cast<N: Into<BlockNum>>(
+        &self,
+        block_num: N,
+        block_hash: H256,
+        idx: usize,
+    ) -> Result<Transaction> {
+        let mut cast = *self;
+        cast.block(block_num, block_hash).index(idx).transaction()
+    }
+
+    /// Returns the sender, recovering it from the signature if it wasn't given.
+    /// Fails if the signature doesn't recover to a key.
+    pub fn sender(&self) -> Result<Address> {
+        match self.src {
+            Some(src) => Ok(src),
+            None => self.msg.recover_sender().map_err(|e| {
+                format_err!("cant recover the sender of tx {:?}: {}", self.msg.hash(), e)
+            }),
+        }
+    }
+
+    /// Casts the transaction in full. Block fields are `None` without a block.
+    pub fn transaction(&self) -> Result<Transaction> {
+        Ok(Transaction {
+            hash: self.msg.hash(),
+            nonce: self.msg.nonce().into(),
+            block_hash: self.block.map(|(_, hash)| hash),
+            block_number: self.block.map(|(num, _)| num.into()),
+            transaction_index: self.index.map(Into::into),
+            from: self.sender()?,
+            to: self.msg.action().into_address(),
+            value: convert::u256(self.msg.value()),
+            gas_price: self.gas_price(),
+            gas: self.msg.gas_limit().into(),
+            input: self.msg.input().clone().into(),
+            v: self.v(),
+            r: self.msg.r().to_fixed_bytes().into(),
+            s: self.msg.s().to_fixed_bytes().into(),
+            transaction_type: self.tx_type(),
+            access_list: self.access_list(),
+            chain_id: self.msg.chain_id().map(|id| (*id).into()),
+
+            //TODO: should these be None for legacy txs?
+            max_priority_fee_per_gas: Some(convert::u256(self.msg.max_priority_fee_per_gas())),
+            max_fee_per_gas: Some(convert::u256(self.msg.max_fee_per_gas())),
+        })
+    }
+
+    /// Casts the fields of the transaction kept by `TxSummary`.
+    pub fn summary(&self) -> Result<TxSummary> {
+        Ok(TxSummary {
+            hash: self.msg.hash(),
+            from: self.sender()?,
+            to: self.msg.action().into_address(),
+            nonce: self.msg.nonce().into(),
+            value: convert::u256(self.msg.value()),
+            gas: self.msg.gas_limit().into(),
+            effective_gas_price: self.effective_gas_price(),
+            transaction_type: self.tx_type(),
+            block_number: self.block.map(|(num, _)| num.into()),
+            transaction_index: self.index.map(Into::into),
+        })
+    }
+
+    /// Returns the price paid per unit of gas: the gas price of legacy and
+    /// access list txs, and the base fee plus the capped tip otherwise, which
+    /// needs the block's base fee.
+    pub fn effective_gas_price(&self) -> Option<U256> {
+        if let Some(gas_price) = self.gas_price() {
+            return Some(gas_price);
+        }
+        let base_fee = self.base_fee?;
+        let max_fee = convert::u256(self.msg.max_fee_per_gas());
+        let tip = convert::u256(self.msg.max_priority_fee_per_gas());
+        Some(base_fee + tip.min(max_fee.saturating_sub(base_fee)))
+    }
+
+    pub fn gas_price(&self) -> Option<U256> {
+        match self.msg.message {
+            Message::Legacy { gas_price, .. } | Message::EIP2930 { gas_price, .. } => {
+                Some(convert::u256(gas_price))
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the `v` reported over rpc: `27 + y_parity` for legacy txs from
+    /// before EIP-155, `35 + 2 * chain_id + y_parity` for later legacy txs, and
+    /// the bare `y_parity` for typed txs.
+    pub fn v(&self) -> U64 {
+        let parity = y_parity(self.msg.v());
+        match &self.msg.message {
+            Message::Legacy {
+                chain_id: Some(id), ..
+            } => {
+                id.0.checked_mul(2)
+                    .and_then(|v| v.checked_add(35 + parity))
+                    // not representable, so keep the stored value
+                    .unwrap_or_else(|| self.msg.v())
+            }
+            Message::Legacy { chain_id: None, .. } => 27 + parity,
+            _ => parity,
+        }
+        .into()
+    }
+
+    fn tx_type(&self) -> Option<U64> {
+        match self.msg.message {
+            Message::EIP2930 { .. } => Some(1.into()),
+            Message::EIP1559 { .. } => Some(2.into()),
+            _ => None,
+        }
+    }
+
+    fn access_list(&self) -> Option<ethers::types::transaction::eip2930::AccessList> {
+        match &self.msg.message {
+            Message::EIP2930 { access_list, .. } | Message::EIP1559 { access_list, .. } => Some(
+                access_list
+                    .iter()
+                    .map(|it| ethers::types::transaction::eip2930::AccessListItem {
+                        address: it.address,
+                        storage_keys: it.slots.clone(),
+                    })
+                    .collect::<Vec<_>>()
+                    .into(),
+            ),
+            _ => None,
+        }
+    }
+}
+
+/// Decodes the transactions of a custom envelope type, such as an L2's deposit
+/// transactions, which `MessageWithSignature` can't decode.
+pub trait CustomTxDecoder: fmt::Debug + Send + Sync {
+    /// Decodes `raw`, the transaction as stored: its type byte followed by
+    /// its payload. The block context and, if left zero, the hash are filled
+    /// in by the caller.
+    fn decode(&self, raw: &[u8]) -> Result<Transaction>;
+}
+
+/// The fallback for transactions of a type which is neither built in nor
+/// registered, so that they don't fail the reads of their whole block. Only
+/// what is known without decoding them is set: the hash, the type and the
+/// block context. `input` holds the stored envelope, type byte included.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UnknownTxType;
+
+impl CustomTxDecoder for UnknownTxType {
+    fn decode(&self, raw: &[u8]) -> Result<Transaction> {
+        Ok(Transaction {
+            input: raw.to_vec().into(),
+            ..Default::default()
+        })
+    }
+}
+
+/// Decodes OP-stack deposit transactions, of type `DEPOSIT_TX_TYPE`. They
+/// have no signature, nonce or gas price: `from` comes from the payload. The
+/// deposit's own fields, such as its source hash and mint, have no place in a
+/// `Transaction`; `DepositTx::decode` reads them from the raw transaction.
+///
+/// ```ignore
+/// let client = Client::builder()
+///     .custom_tx_type(DEPOSIT_TX_TYPE, DepositTxDecoder)
+///     .system_txs(SystemTxs::optimism())
+///     .build()?;
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DepositTxDecoder;
+
+impl CustomTxDecoder for DepositTxDecoder {
+    fn decode(&self, raw: &[u8]) -> Result<Transaction> {
+        let deposit =
+            DepositTx::decode(raw).map_err(|e| format_err!("deposit tx decode error: {}", e))?;
+        Ok(Transaction {
+            from: deposit.from,
+            to: deposit.to,
+            value: deposit.value,
+            gas: deposit.gas.into(),
+            gas_price: Some(0.into()),
+            input: deposit.data.into(),
+            transaction_type: Some(DEPOSIT_TX_TYPE.into()),
+            ..Default::default()
+        })
+    }
+}
+
+/// A stored transaction, decoded by `CustomTxTypes::decode`.
+#[derive(Debug, Clone)]
+pub enum StoredTx {
+    /// A legacy transaction or one of a built-in type.
+    Msg(MessageWithSignature),
+    /// A transaction of a custom type, cast by its decoder with its block
+    /// context. See `UnknownTxType` for types without one.
+    Custom(Transaction),
+}
+
+impl StoredTx {
+    pub fn hash(&self) -> H256 {
+        match self {
+            Self::Msg(msg) => msg.hash(),
+            Self::Custom(tx) => tx.hash,
+        }
+    }
+}
+
+/// The decoders registered for custom transaction types, by type byte. A
+/// registered type takes precedence over the built-in decoding, and the
+/// transactions of any other type are cast by `UnknownTxType`.
+#[derive(Debug, Clone, Default)]
+pub struct CustomTxTypes(HashMap<u8, Arc<dyn CustomTxDecoder>>);
+
+impl CustomTxTypes {
+    pub fn register<D: CustomTxDecoder + 'static>(&mut self, tx_type: u8, decoder: D) {
+        self.0.insert(tx_type, Arc::new(decoder));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns whether `raw` is of a registered or unknown type.
+    pub fn handles(&self, raw: &[u8]) -> bool {
+        self.decoder(raw).is_some()
+    }
+
+    /// Decodes `raw`, the transaction `idx` of the block `num` with hash
+    /// `hash`, whatever its type.
+    pub fn decode<N: Into<BlockNum>>(
+        &self,
+        raw: &[u8],
+        num: N,
+        hash: H256,
+        idx: usize,
+    ) -> Result<StoredTx> {
+        match self.cast(raw, num, hash, idx) {
+            Some(tx) => tx.map(StoredTx::Custom),
+            None => <MessageWithSignature as fastrlp::Decodable>::decode(&mut &*raw)
+                .map(StoredTx::Msg)
+                .map_err(|e| format_err!("tx decode error: {}", e)),
+        }
+    }
+
+    /// Decodes `raw` if it is of a registered or unknown type, and casts it
+    /// with its block context. Returns `None` for any other transaction.
+    pub fn cast<N: Into<BlockNum>>(
+        &self,
+        raw: &[u8],
+        num: N,
+        hash: H256,
+        idx: usize,
+    ) -> Option<Result<Transaction>> {
+        let (tx_type, decoder) = self.decoder(raw)?;
+        Some(decoder.decode(raw).map(|mut tx| {
+            if tx.hash.is_zero() {
+                tx.hash = keccak256(raw).into();
+            }
+            tx.block_hash = Some(hash);
+            tx.block_number = Some(num.into().into());
+            tx.transaction_index = Some(idx.into());
+            tx.transaction_type.get_or_insert(tx_type.into());
+            tx
+        }))
+    }
+
+    fn decoder(&self, raw: &[u8]) -> Option<(u8, &dyn CustomTxDecoder)> {
+        // legacy transactions begin with an rlp list header instead of a type
+        let tx_type = *raw.first().filter(|b| **b < 0x80)?;
+        match self.0.get(&tx_type) {
+            Some(decoder) => Some((tx_type, decoder.as_ref())),
+            None if BUILTIN_TX_TYPES.contains(&tx_type) => None,
+            None => Some((tx_type, &UnknownTxType)),
+        }
+    }
+}
+
+/// Extracts the y parity from a `v` in any of its encodings.
+pub fn y_parity(v: u64) -> u64 {
+    match v {
+        0 | 1 => v,
+        27 | 28 => v - 27,
+        v => v.saturating_sub(35) % 2,
+    }
+}
+
+/// Returns the length of an rlp list with a payload of `payload_len` bytes.
+pub fn rlp_list_len(payload_len: usize) -> usize {
+    fastrlp::length_of_length(payload_len) + payload_len
+}
+
+/// A set of the optional parts of a block, used to project block reads onto
+/// the parts a caller needs. The header's fixed-size fields are always read.
+///
+/// ```ignore
+/// // head tracking only needs the hash, number and parent hash
+/// let block = client.get_block_projected(BlockNumber::Latest, BlockFields::NONE)?;
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BlockFields(u8);
+
+impl BlockFields {
+    pub const NONE: Self = Self(0);
+    pub const EXTRA_DATA: Self = Self(1);
+    pub const LOGS_BLOOM: Self = Self(1 << 1);
+    /// The ommer hashes. Reading them means decoding and hashing every ommer
+    /// header in the body.
+    pub const OMMERS: Self = Self(1 << 2);
+    /// The transactions, or their hashes.
+    pub const TRANSACTIONS: Self = Self(1 << 3);
+    pub const ALL: Self = Self(0b1111);
+
+    /// Returns whether every field in `other` is in `self`.
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    pub const fn difference(self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+}
+
+impl Default for BlockFields {
+    fn default() -> Self {
+        Self::ALL
+    }
+}
+
+impl std::ops::BitOr for BlockFields {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        self.union(other)
+    }
+}
+
+/// Converts akula block data into ethers block data
+pub struct BlockCast<'a>(pub &'a BlockHeader);
+impl<'a> BlockCast<'a> {
+    pub fn cast<TX: std::default::Default, N: Into<BlockNum>>(
+        &self,
+        txs: Vec<TX>,
+        block_num: N,
+        block_hash: H256,
+        ommer_hashes: Vec<H256>,
+    ) -> ethers::types::Block<TX> {
+        self.project(BlockFields::ALL, txs, block_num, block_hash, ommer_hashes)
+    }
+
+    /// Like `cast`, but leaves out the extra data and logs bloom unless they
+    /// are in `fields`.
+    pub fn project<TX: std::default::Default, N: Into<BlockNum>>(
+        &self,
+        fields: BlockFields,
+        txs: Vec<TX>,
+        block_num: N,
+        block_hash: H256,
+        ommer_hashes: Vec<H256>,
+    ) -> ethers::types::Block<TX> {
+        let block_num: BlockNum = block_num.into();
+        let extra_data = if fields.contains(BlockFields::EXTRA_DATA) {
+            self.0.extra_data.clone().into()
+        } else {
+            Default::default()
+        };
+        ethers::types::Block {
+            hash: Some(block_hash),
+            parent_hash: self.0.parent_hash,
+            uncles_hash: self.0.ommers_hash,
+            author: self.0.beneficiary,
+            state_root: self.0.state_root,
+            transactions_root: self.0.transactions_root,
+            receipts_root: self.0.receipts_root,
+            number: Some(block_num.into()),
+            gas_used: self.0.gas_used.into(),
+            gas_limit: self.0.gas_limit.into(),
+            extra_data,
+            logs_bloom: fields
+                .contains(BlockFields::LOGS_BLOOM)
+                .then(|| self.0.logs_bloom),
+            timestamp: self.0.timestamp.into(),
+            difficulty: convert::u256(self.0.difficulty),
+            total_difficulty: None, // TODO
+            uncles: ommer_hashes,
+            transactions: txs,
+            mix_hash: Some(self.0.mix_hash),
+            nonce: Some(self.0.nonce.to_fixed_bytes().into()),
+            base_fee_per_gas: self.0.base_fee_per_gas.map(convert::u256),
+
+            // TODO:
+            // seal_fields
+            //size
+            ..Default::default()
+        }
+    }
+
+    /// Casts an uncle header into the shape returned by `eth_getUncleByBlock*`:
+    /// no transactions, no uncles, and the size of the block `[header, [], []]`.
+    pub fn cast_uncle(&self) -> ethers::types::Block<H256> {
+        let header_len = fastrlp::Encodable::length(self.0);
+        // two empty lists of one byte each
+        let size = rlp_list_len(header_len + 2);
+        ethers::types::Block {
+            size: Some(size.into()),
+            ..self.cast(vec![], self.0.number, self.0.hash(), vec![])
+        }
+    }
+
+    /// Casts the header alone, with no transactions or uncles.
+    pub fn cast_header<N: Into<BlockNum>>(
+        &self,
+        block_num: N,
+        block_hash: H256,
+    ) -> ethers::types::Block<()> {
+        self.cast(vec![], block_num, block_hash, vec![])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::rand::{rand_1559, rand_vec, Rand};
+    use akula::models::{ChainId, MessageSignature, TransactionAction};
+    use rand::thread_rng;
+
+    #[test]
+    fn test_recover_senders() -> Result<()> {
+        let mut rng = thread_rng();
+        let msgs: Vec<MessageWithSignature> = rand_vec(&mut rng, 4 * MIN_PARALLEL_RECOVERY);
+        let expected = msgs
+            .iter()
+            .map(|msg| msg.recover_sender())
+            .collect::<Result<Vec<_>>>()?;
+
+        // every other sender is missing, and the list is short
+        let known = expected
+            .iter()
+            .step_by(2)
+            .flat_map(|s| [*s, Default::default()])
+            .take(msgs.len() - 3)
+            .collect::<Vec<_>>();
+        assert_eq!(recover_senders(&msgs, &known)?, expected);
+        assert_eq!(recover_senders(&msgs[..2], &[])?, expected[..2]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_cast_v() -> Result<()> {
+        let mut rng = thread_rng();
+        let legacy = |chain_id: Option<u64>| -> MessageWithSignature {
+            let mut msg = MessageWithSignature::rand(&mut rng);
+            msg.message = Message::Legacy {
+                chain_id: chain_id.map(ChainId),
+                nonce: 1,
+                gas_price: 1_000_000_000u64.into(),
+                gas_limit: 21_000,
+                action: TransactionAction::Call(Address::repeat_byte(0x11)),
+                value: 0u64.into(),
+                input: Default::default(),
+            };
+            msg
+        };
+
+        for (chain_id, base) in [
+            (None, 27),
+            (Some(1), 37),
+            (Some(0x7fff_ffff), 0xffff_ffff + 34),
+        ] {
+            let msg = legacy(chain_id);
+            let tx = MsgCast::new(&msg).cast(1, H256::zero(), 0)?;
+            let parity = tx.v.as_u64() - base;
+            assert!(parity <= 1, "bad v {} for chain id {:?}", tx.v, chain_id);
+            assert_eq!(tx.chain_id.map(|id| id.as_u64()), chain_id);
+
+            // the stored encoding decodes to the same v and chain id
+            let mut buf = bytes::BytesMut::new();
+            fastrlp::Encodable::encode(&msg, &mut buf);
+            let decoded: MessageWithSignature = fastrlp::Decodable::decode(&mut &buf[..])?;
+            assert_eq!(MsgCast::new(&decoded).cast(1, H256::zero(), 0)?.v, tx.v);
+            assert_eq!(decoded.chain_id(), msg.chain_id());
+        }
+
+        // typed txs report the bare parity
+        let mut msg = MessageWithSignature::rand(&mut rng);
+        msg.message = rand_1559(&mut rng);
+        assert!(MsgCast::new(&msg).cast(1, H256::zero(), 0)?.v.as_u64() <= 1);
+
+        for (v, parity) in [
+            (0, 0),
+            (1, 1),
+            (27, 0),
+            (28, 1),
+            (37, 0),
+            (38, 1),
+            (2710, 1),
+        ] {
+            assert_eq!(y_parity(v), parity);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_block_projection() {
+        let mut header = BlockHeader::rand(&mut thread_rng());
+        header.extra_data = vec![0xab].into();
+        let hash = header.hash();
+        let cast = BlockCast(&header);
+
+        let full = cast.cast(vec![1u64], header.number, hash, vec![hash]);
+        assert_eq!(full.extra_data, ethers::types::Bytes::from(vec![0xab]));
+        assert_eq!(full.logs_bloom, Some(header.logs_bloom));
+        assert_eq!(
+            cast.project(
+                BlockFields::ALL,
+                vec![1u64],
+                header.number,
+                hash,
+                vec![hash]
+            ),
+            full
+        );
+
+        let bare = cast.project::<u64, _>(BlockFields::NONE, vec![], header.number, hash, vec![]);
+        assert!(bare.extra_data.is_empty());
+        assert_eq!(bare.logs_bloom, None);
+        assert_eq!((bare.hash, bare.number), (full.hash, full.number));
+        assert_eq!(bare.parent_hash, header.parent_hash);
+
+        let bloom =
+            cast.project::<u64, _>(BlockFields::LOGS_BLOOM, vec![], header.number, hash, vec![]);
+        assert_eq!(bloom.logs_bloom, Some(header.logs_bloom));
+        assert!(bloom.extra_data.is_empty());
+    }
+
+    #[test]
+    fn test_cast_context() -> Result<()> {
+        let mut rng = thread_rng();
+        let mut msg = MessageWithSignature::rand(&mut rng);
+        msg.message = Message::EIP1559 {
+            chain_id: ChainId(1),
+            nonce: 7,
+            max_priority_fee_per_gas: 10u64.into(),
+            max_fee_per_gas: 100u64.into(),
+            gas_limit: 21_000,
+            action: TransactionAction::Call(Address::repeat_byte(0x11)),
+            value: 0u64.into(),
+            input: Default::default(),
+            access_list: Default::default(),
+        };
+        let sender = Address::repeat_byte(0x22);
+
+        // no block context
+        let mut cast = MsgCast::new(&msg);
+        cast.maybe_signer(sender);
+        let summary = cast.summary()?;
+        assert_eq!(summary.from, sender);
+        assert_eq!(summary.block_number, None);
+        assert_eq!(summary.effective_gas_price, None);
+        assert_eq!(cast.transaction()?.block_hash, None);
+
+        // the tip is capped by the max fee
+        let hash = H256::repeat_byte(0x33);
+        cast.block(5, hash).index(2).base_fee(Some(95.into()));
+        assert_eq!(cast.effective_gas_price(), Some(100.into()));
+        let summary = cast.summary()?;
+        assert_eq!(summary.block_number, Some(5.into()));
+        assert_eq!(summary.transaction_index, Some(2.into()));
+        assert_eq!(
+            cast.transaction()?,
+            MsgCast::new(&msg).maybe_signer(sender).cast(5, hash, 2)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_cast_unrecoverable_sender() -> Result<()> {
+        let mut rng = thread_rng();
+        let mut msg = MessageWithSignature::rand(&mut rng);
+        // r and s are in range, but no point on the curve has x = 5, so the
+        // signature can't be recovered
+        msg.signature = MessageSignature::new(
+            false,
+            H256::from_low_u64_be(5),
+            H256(msg.s().to_fixed_bytes()),
+        )
+        .ok_or_else(|| format_err!("bad test signature"))?;
+        let cast = MsgCast::new(&msg);
+        assert!(cast.sender().is_err());
+        assert!(cast.transaction().is_err());
+        assert!(cast.summary().is_err());
+        assert!(cast.cast(1, H256::zero(), 0).is_err());
+
+        // a stored sender is used as is
+        let sender = Address::repeat_byte(0x22);
+        let mut cast = MsgCast::new(&msg);
+        cast.maybe_signer(sender);
+        assert_eq!(cast.transaction()?.from, sender);
+        Ok(())
+    }
+
+    #[test]
+    fn test_deposit_tx_decoder() -> Result<()> {
+        let deposit = DepositTx {
+            source_hash: H256::repeat_byte(0x5c),
+            from: Address::repeat_byte(0xde),
+            to: Some(Address::repeat_byte(0x42)),
+            mint: 5u64.into(),
+            value: 3u64.into(),
+            gas: 1_000_000,
+            is_system_tx: false,
+            data: bytes::Bytes::from_static(&[0xab, 0xcd]),
+        };
+        let raw = deposit.encode();
+        let mut types = CustomTxTypes::default();
+        types.register(DEPOSIT_TX_TYPE, DepositTxDecoder);
+        let hash = H256::repeat_byte(0x33);
+        let tx = match types.decode(&raw, 9, hash, 0)? {
+            StoredTx::Custom(tx) => tx,
+            StoredTx::Msg(_) => panic!("deposit decoded as a msg"),
+        };
+        assert_eq!(tx.hash, H256(keccak256(&raw)));
+        assert_eq!(tx.from, deposit.from);
+        assert_eq!(tx.to, deposit.to);
+        assert_eq!(tx.value, 3.into());
+        assert_eq!(tx.gas, 1_000_000.into());
+        assert_eq!(tx.input.as_ref(), &[0xab, 0xcd]);
+        assert_eq!(tx.transaction_type, Some(U64::from(DEPOSIT_TX_TYPE)));
+        assert_eq!(tx.block_number, Some(9.into()));
+
+        // a malformed deposit is an error, not an unknown tx
+        assert!(types.decode(&raw[..raw.len() - 1], 9, hash, 0).is_err());
+        Ok(())
+    }
+}
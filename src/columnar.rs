@@ -0,0 +1,493 @@
+//! Apache Arrow output for range scans, so blocks, transactions, logs,
+//! storage and full state dumps can be fed to DataFusion or Polars without a
+//! CSV intermediate.
+//!
+//! Each scan yields `RecordBatch`es of at most `batch_rows` rows, read lazily
+//! as the iterator is advanced. A read error ends the scan: the rows read
+//! before it are yielded as a batch, then the error. Hashes and addresses are fixed size binary
+//! columns, and 256-bit integers are 32 byte big-endian binary columns.
+//!
+//! ```ignore
+//! let batches = client.transactions_arrow(15_000_000..=15_000_999, DEFAULT_BATCH_ROWS);
+//! columnar::write_ipc(File::create("txs.arrow")?, &columnar::transaction_schema(), batches)?;
+//! ```
+
+use anyhow::{format_err, Result};
+use arrow::{
+    array::{ArrayRef, BinaryArray, FixedSizeBinaryBuilder, UInt64Array},
+    datatypes::{DataType, Field, Schema, SchemaRef},
+    ipc::writer::StreamWriter,
+    record_batch::RecordBatch,
+};
+use ethers::types::{Address, Block, BlockId, Filter, Log, Transaction, TxHash, H256};
+use mdbx::EnvironmentKind;
+use std::{io::Write, ops::RangeInclusive, sync::Arc};
+
+use crate::{client::Client, convert, dump::DumpedAccount, page::PageCursor};
+
+/// A batch size which amortizes per-batch overhead without holding much of a
+/// scan in memory.
+pub const DEFAULT_BATCH_ROWS: usize = 8192;
+
+const HASH: DataType = DataType::FixedSizeBinary(32);
+const ADDRESS: DataType = DataType::FixedSizeBinary(20);
+const WORD: DataType = DataType::FixedSizeBinary(32);
+
+pub fn block_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("number", DataType::UInt64, false),
+        Field::new("hash", HASH, false),
+        Field::new("parent_hash", HASH, false),
+        Field::new("timestamp", DataType::UInt64, false),
+        Field::new("miner", ADDRESS, false),
+        Field::new("gas_used", DataType::UInt64, false),
+        Field::new("gas_limit", DataType::UInt64, false),
+        Field::new("base_fee_per_gas", WORD, true),
+        Field::new("transaction_count", DataType::UInt64, false),
+    ]))
+}
+
+pub fn transaction_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("block_number", DataType::UInt64, false),
+        Field::new("transaction_index", DataType::UInt64, false),
+        Field::new("hash", HASH, false),
+        Field::new("from", ADDRESS, false),
+        Field::new("to", ADDRESS, true),
+        Field::new("nonce", DataType::UInt64, false),
+        Field::new("value", WORD, false),
+        Field::new("gas", DataType::UInt64, false),
+        Field::new("gas_price", WORD, true),
+        Field::new("max_fee_per_gas", WORD, true),
+        Field::new("max_priority_fee_per_gas", WORD, true),
+        Field::new("transaction_type", DataType::UInt64, true),
+        Field::new("input", DataType::Binary, false),
+    ]))
+}
+
+pub fn log_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("block_number", DataType::UInt64, false),
+        Field::new("transaction_hash", HASH, false),
+        Field::new("transaction_index", DataType::UInt64, false),
+        Field::new("log_index", DataType::UInt64, false),
+        Field::new("address", ADDRESS, false),
+        Field::new("topic0", HASH, true),
+        Field::new("topic1", HASH, true),
+        Field::new("topic2", HASH, true),
+        Field::new("topic3", HASH, true),
+        Field::new("data", DataType::Binary, false),
+    ]))
+}
+
+pub fn storage_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("address", ADDRESS, false),
+        Field::new("slot", HASH, false),
+        Field::new("value", WORD, false),
+    ]))
+}
+
+pub fn account_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("address", ADDRESS, false),
+        Field::new("balance", WORD, false),
+        Field::new("nonce", DataType::UInt64, false),
+        Field::new("incarnation", DataType::UInt64, false),
+        Field::new("codehash", HASH, false),
+        Field::new("storage_slots", DataType::UInt64, false),
+    ]))
+}
+
+impl<E: EnvironmentKind> Client<E> {
+    /// Returns the headers of the blocks in `range` as batches of `block_schema`.
+    pub fn blocks_arrow(
+        &self,
+        range: RangeInclusive<u64>,
+        batch_rows: usize,
+    ) -> impl Iterator<Item = Result<RecordBatch>> + '_ {
+        let rows = range.map(move |num| {
+            self.get_block(num)?
+                .ok_or_else(|| format_err!("block {} not found", num))
+        });
+        Batches::new(rows, batch_rows, block_batch)
+    }
+
+    /// Returns the transactions of the blocks in `range` as batches of
+    /// `transaction_schema`.
+    pub fn transactions_arrow(
+        &self,
+        range: RangeInclusive<u64>,
+        batch_rows: usize,
+    ) -> impl Iterator<Item = Result<RecordBatch>> + '_ {
+        let rows = range.flat_map(move |num| match self.get_block_with_txs(num) {
+            Ok(Some(block)) => block.transactions.into_iter().map(Ok).collect(),
+            Ok(None) => vec![Err(format_err!("block {} not found", num))],
+            Err(e) => vec![Err(e)],
+        });
+        Batches::new(rows, batch_rows, transaction_batch)
+    }
+
+    /// Returns the logs matching `filter` as batches of `log_schema`.
+    pub fn logs_arrow(
+        &self,
+        filter: &Filter,
+        batch_rows: usize,
+    ) -> Result<impl Iterator<Item = Result<RecordBatch>> + '_> {
+        Ok(Batches::new(
+            self.stream_logs(filter)?,
+            batch_rows,
+            log_batch,
+        ))
+    }
+
+    /// Returns the current storage of `address` as batches of `storage_schema`,
+    /// one storage page per batch.
+    pub fn storage_arrow(
+        &self,
+        address: Address,
+        batch_rows: usize,
+    ) -> impl Iterator<Item = Result<RecordBatch>> + '_ {
+        let mut cursor: Option<PageCursor> = None;
+        let mut done = false;
+        std::iter::from_fn(move || {
+            if done {
+                return None;
+            }
+            let page = match self.get_storage_range(address, cursor.as_ref(), batch_rows.max(1)) {
+                Ok(page) => page,
+                Err(e) => {
+                    done = true;
+                    return Some(Err(e));
+                }
+            };
+            done = page.is_last();
+            cursor = page.next_cursor;
+            if page.items.is_empty() {
+                return None;
+            }
+            let rows: Vec<_> = page
+                .items
+                .into_iter()
+                .map(|(slot, value)| (address, slot, value))
+                .collect();
+            Some(storage_batch(&rows))
+        })
+    }
+}
+
+impl<E: EnvironmentKind> Client<E> {
+    /// Returns every account as of `block` as batches of `account_schema`, in
+    /// address order. See `dump_state_at` for what the dump reads.
+    pub fn state_accounts_arrow<T: Into<BlockId> + Send + Sync>(
+        &self,
+        block: T,
+        batch_rows: usize,
+    ) -> Result<impl Iterator<Item = Result<RecordBatch>> + '_> {
+        Ok(Batches::new(
+            self.dump_state_at(block)?,
+            batch_rows,
+            account_batch,
+        ))
+    }
+
+    /// Returns the storage of every account as of `block` as batches of
+    /// `storage_schema`, ordered by address then slot.
+    pub fn state_storage_arrow<T: Into<BlockId> + Send + Sync>(
+        &self,
+        block: T,
+        batch_rows: usize,
+    ) -> Result<impl Iterator<Item = Result<RecordBatch>> + '_> {
+        let rows = self.dump_state_at(block)?.flat_map(|acct| match acct {
+            Ok(acct) => acct
+                .storage
+                .into_iter()
+                .map(|(slot, value)| Ok((acct.address, slot, value)))
+                .collect(),
+            Err(e) => vec![Err(e)],
+        });
+        Ok(Batches::new(rows, batch_rows, storage_batch))
+    }
+}
+
+/// Writes `batches` to `out` as an Arrow IPC stream, returning the number of
+/// rows written. The stream is finished even if there are no batches.
+pub fn write_ipc<W, I>(out: W, schema: &Schema, batches: I) -> Result<usize>
+where
+    W: Write,
+    I: IntoIterator<Item = Result<RecordBatch>>,
+{
+    let mut writer = StreamWriter::try_new(out, schema)?;
+    let mut rows = 0;
+    for batch in batches {
+        let batch = batch?;
+        rows += batch.num_rows();
+        writer.write(&batch)?;
+    }
+    writer.finish()?;
+    Ok(rows)
+}
+
+/// Groups the rows of a scan into record batches built by `build`. The first
+/// error ends the scan, after the rows read before it.
+struct Batches<I, T> {
+    rows: I,
+    batch_rows: usize,
+    build: fn(&[T]) -> Result<RecordBatch>,
+    /// An error to yield after the partial batch read before it.
+    error: Option<anyhow::Error>,
+    done: bool,
+}
+
+impl<I, T> Batches<I, T> {
+    fn new(rows: I, batch_rows: usize, build: fn(&[T]) -> Result<RecordBatch>) -> Self {
+        Self {
+            rows,
+            batch_rows: batch_rows.max(1),
+            build,
+            error: None,
+            done: false,
+        }
+    }
+}
+
+impl<I: Iterator<Item = Result<T>>, T> Iterator for Batches<I, T> {
+    type Item = Result<RecordBatch>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(e) = self.error.take() {
+            return Some(Err(e));
+        }
+        if self.done {
+            return None;
+        }
+        let mut rows = Vec::with_capacity(self.batch_rows);
+        for row in self.rows.by_ref().take(self.batch_rows) {
+            match row {
+                Ok(row) => rows.push(row),
+                Err(e) => {
+                    self.done = true;
+                    self.error = Some(e);
+                    break;
+                }
+            }
+        }
+        if rows.is_empty() {
+            self.done = true;
+            return self.error.take().map(Err);
+        }
+        Some((self.build)(&rows))
+    }
+}
+
+fn block_batch(blocks: &[Block<TxHash>]) -> Result<RecordBatch> {
+    Ok(RecordBatch::try_new(
+        block_schema(),
+        vec![
+            u64s(blocks, |b| b.number.unwrap_or_default().as_u64()),
+            fixed(blocks, 32, |b| Some(b.hash.unwrap_or_default().0.to_vec()))?,
+            fixed(blocks, 32, |b| Some(b.parent_hash.0.to_vec()))?,
+            u64s(blocks, |b| b.timestamp.as_u64()),
+            fixed(blocks, 20, |b| Some(b.author.0.to_vec()))?,
+            u64s(blocks, |b| b.gas_used.as_u64()),
+            u64s(blocks, |b| b.gas_limit.as_u64()),
+            fixed(blocks, 32, |b| b.base_fee_per_gas.map(convert::u256_bytes))?,
+            u64s(blocks, |b| b.transactions.len() as u64),
+        ],
+    )?)
+}
+
+fn transaction_batch(txs: &[Transaction]) -> Result<RecordBatch> {
+    Ok(RecordBatch::try_new(
+        transaction_schema(),
+        vec![
+            u64s(txs, |tx| tx.block_number.unwrap_or_default().as_u64()),
+            u64s(txs, |tx| tx.transaction_index.unwrap_or_default().as_u64()),
+            fixed(txs, 32, |tx| Some(tx.hash.0.to_vec()))?,
+            fixed(txs, 20, |tx| Some(tx.from.0.to_vec()))?,
+            fixed(txs, 20, |tx| tx.to.map(|to| to.0.to_vec()))?,
+            u64s(txs, |tx| tx.nonce.as_u64()),
+            fixed(txs, 32, |tx| Some(convert::u256_bytes(tx.value)))?,
+            u64s(txs, |tx| tx.gas.as_u64()),
+            fixed(txs, 32, |tx| tx.gas_price.map(convert::u256_bytes))?,
+            fixed(txs, 32, |tx| tx.max_fee_per_gas.map(convert::u256_bytes))?,
+            fixed(txs, 32, |tx| {
+                tx.max_priority_fee_per_gas.map(convert::u256_bytes)
+            })?,
+            Arc::new(
+                txs.iter()
+                    .map(|tx| tx.transaction_type.map(|t| t.as_u64()))
+                    .collect::<UInt64Array>(),
+            ),
+            Arc::new(
+                txs.iter()
+                    .map(|tx| Some(tx.input.as_ref()))
+                    .collect::<BinaryArray>(),
+            ),
+        ],
+    )?)
+}
+
+fn log_batch(logs: &[Log]) -> Result<RecordBatch> {
+    let topic = |i: usize| fixed(logs, 32, move |log| log.topics.get(i).map(|t| t.0.to_vec()));
+    Ok(RecordBatch::try_new(
+        log_schema(),
+        vec![
+            u64s(logs, |log| log.block_number.unwrap_or_default().as_u64()),
+            fixed(logs, 32, |log| {
+                Some(log.transaction_hash.unwrap_or_default().0.to_vec())
+            })?,
+            u64s(logs, |log| {
+                log.transaction_index.unwrap_or_default().as_u64()
+            }),
+            u64s(logs, |log| log.log_index.unwrap_or_default().as_u64()),
+            fixed(logs, 20, |log| Some(log.address.0.to_vec()))?,
+            topic(0)?,
+            topic(1)?,
+            topic(2)?,
+            topic(3)?,
+            Arc::new(
+                logs.iter()
+                    .map(|log| Some(log.data.as_ref()))
+                    .collect::<BinaryArray>(),
+            ),
+        ],
+    )?)
+}
+
+fn account_batch(accts: &[DumpedAccount]) -> Result<RecordBatch> {
+    Ok(RecordBatch::try_new(
+        account_schema(),
+        vec![
+            fixed(accts, 20, |acct| Some(acct.address.0.to_vec()))?,
+            fixed(accts, 32, |acct| Some(convert::u256_bytes(acct.balance)))?,
+            u64s(accts, |acct| acct.nonce),
+            u64s(accts, |acct| acct.incarnation),
+            fixed(accts, 32, |acct| Some(acct.codehash.0.to_vec()))?,
+            u64s(accts, |acct| acct.storage.len() as u64),
+        ],
+    )?)
+}
+
+fn storage_batch(slots: &[(Address, H256, H256)]) -> Result<RecordBatch> {
+    Ok(RecordBatch::try_new(
+        storage_schema(),
+        vec![
+            fixed(slots, 20, |(who, _, _)| Some(who.0.to_vec()))?,
+            fixed(slots, 32, |(_, slot, _)| Some(slot.0.to_vec()))?,
+            fixed(slots, 32, |(_, _, value)| Some(value.0.to_vec()))?,
+        ],
+    )?)
+}
+
+fn u64s<T>(rows: &[T], f: impl Fn(&T) -> u64) -> ArrayRef {
+    Arc::new(UInt64Array::from_iter_values(rows.iter().map(f)))
+}
+
+/// Builds a fixed size binary column of `width` byte values, null where `f`
+/// returns `None`.
+fn fixed<T>(rows: &[T], width: i32, f: impl Fn(&T) -> Option<Vec<u8>>) -> Result<ArrayRef> {
+    let mut col = FixedSizeBinaryBuilder::with_capacity(rows.len(), width);
+    for row in rows {
+        match f(row) {
+            Some(val) => col.append_value(val)?,
+            None => col.append_null(),
+        }
+    }
+    Ok(Arc::new(col.finish()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::Account,
+        test::{chain::ChainBuilder, TMP_DIR},
+    };
+    use arrow::array::Array;
+
+    #[test]
+    fn test_batches() -> Result<()> {
+        let blocks = (0..5u64).map(|num| {
+            Ok(Block {
+                number: Some(num.into()),
+                base_fee_per_gas: (num % 2 == 0).then(|| num.into()),
+                ..Default::default()
+            })
+        });
+        let batches = Batches::new(blocks, 2, block_batch).collect::<Result<Vec<_>>>()?;
+        assert_eq!(
+            batches
+                .iter()
+                .map(RecordBatch::num_rows)
+                .collect::<Vec<_>>(),
+            vec![2, 2, 1]
+        );
+        assert_eq!(batches[0].schema(), block_schema());
+        assert_eq!(batches[0].column(7).null_count(), 1);
+
+        let mut out = vec![];
+        let rows = write_ipc(&mut out, &block_schema(), batches.into_iter().map(Ok))?;
+        assert_eq!(rows, 5);
+        let read = arrow::ipc::reader::StreamReader::try_new(&out[..], None)?
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(read.iter().map(RecordBatch::num_rows).sum::<usize>(), 5);
+        Ok(())
+    }
+
+    #[test]
+    fn test_batches_error() -> Result<()> {
+        let rows = vec![
+            Ok(Block::default()),
+            Ok(Block::default()),
+            Ok(Block::default()),
+            Err(format_err!("boom")),
+            Ok(Block::default()),
+        ];
+        let mut batches = Batches::new(rows.into_iter(), 2, block_batch);
+        assert_eq!(batches.next().unwrap()?.num_rows(), 2);
+        // the row read before the error isn't dropped, and nothing is read after it
+        assert_eq!(batches.next().unwrap()?.num_rows(), 1);
+        assert!(batches.next().unwrap().is_err());
+        assert!(batches.next().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_state_arrow() -> Result<()> {
+        let (a, b) = (Address::repeat_byte(0x0a), Address::repeat_byte(0x0b));
+        let word = H256::from_low_u64_be;
+        let chain = ChainBuilder::new()
+            .block(|blk| {
+                blk.account(a, Account::new().balance(2.into()))
+                    .account(b, Account::new().balance(5.into()).incarnation(1))
+                    .storage(b, word(1), word(8))
+                    .storage(b, word(2), word(9))
+            })
+            .write(TMP_DIR.clone())?;
+        let db = Client::<mdbx::NoWriteMap>::open_new(chain.path.clone())?;
+
+        let accts = db
+            .state_accounts_arrow(1u64, 1)?
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(accts.len(), 2);
+        assert_eq!(accts[0].schema(), account_schema());
+        let slots = |batch: &RecordBatch| {
+            batch
+                .column(5)
+                .as_any()
+                .downcast_ref::<UInt64Array>()
+                .unwrap()
+                .value(0)
+        };
+        assert_eq!((slots(&accts[0]), slots(&accts[1])), (0, 2));
+
+        let storage = db
+            .state_storage_arrow(1u64, DEFAULT_BATCH_ROWS)?
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(storage.len(), 1);
+        assert_eq!(storage[0].num_rows(), 2);
+        assert_eq!(storage[0].schema(), storage_schema());
+        Ok(())
+    }
+}
@@ -0,0 +1,375 @@
+//! Compares two datadirs, for operators validating a resynced node against a
+//! trusted one. Each comparison reports where the candidate diverges from the
+//! trusted node rather than stopping at the first difference.
+//!
+//! ```ignore
+//! let diff = DatadirDiff::open(trusted_dir, resynced_dir)?;
+//! let chain = diff.canonical(15_000_000..)?;
+//! if let Some(num) = chain.first_divergence() {
+//!     let state = diff.state_at(num - 1)?;
+//! }
+//! ```
+
+use anyhow::Result;
+use ethers::types::{Address, Bytes, H256};
+use mdbx::EnvironmentKind;
+use serde::Serialize;
+use std::{
+    cmp::Ordering,
+    ops::{Bound, RangeBounds},
+    path::PathBuf,
+};
+
+use crate::{client::Client, dump::DumpedAccount, tables, types::BlockNum};
+
+/// The tables `DatadirDiff::table` can compare.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffTable {
+    CanonicalHeader,
+    Header,
+    BlockBody,
+    BlockTransaction,
+    PlainState,
+    PlainContractCode,
+    Code,
+    AccountChangeSet,
+    StorageChangeSet,
+    Receipt,
+    TransactionLog,
+}
+
+impl DiffTable {
+    /// Whether the table holds several sorted values under a key.
+    pub fn is_dupsort(self) -> bool {
+        matches!(
+            self,
+            Self::PlainState | Self::AccountChangeSet | Self::StorageChangeSet
+        )
+    }
+}
+
+/// A block whose canonical hash differs between the two datadirs. A hash is
+/// `None` if that datadir has no canonical block at the height.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HashDivergence {
+    pub number: u64,
+    pub trusted: Option<H256>,
+    pub candidate: Option<H256>,
+}
+
+/// The result of `DatadirDiff::canonical`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CanonicalDiff {
+    /// The first and last blocks compared.
+    pub from: u64,
+    pub to: u64,
+    pub divergences: Vec<HashDivergence>,
+}
+
+impl CanonicalDiff {
+    /// The lowest block at which the chains differ.
+    pub fn first_divergence(&self) -> Option<u64> {
+        self.divergences.first().map(|d| d.number)
+    }
+}
+
+/// An account which differs between the two datadirs, or is only in one.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountDivergence {
+    pub address: Address,
+    pub trusted: Option<DumpedAccount>,
+    pub candidate: Option<DumpedAccount>,
+}
+
+/// A table entry which differs between the two datadirs, or is only in one.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EntryDivergence {
+    pub key: Bytes,
+    pub trusted: Option<Bytes>,
+    pub candidate: Option<Bytes>,
+}
+
+/// The result of `DatadirDiff::table`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TableDiff {
+    pub divergences: Vec<EntryDivergence>,
+    /// The key to pass as `from` to compare the next window, if the table
+    /// wasn't compared to its end.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next: Option<Bytes>,
+}
+
+/// A trusted datadir and a candidate to compare against it.
+#[derive(Debug)]
+pub struct DatadirDiff<E: EnvironmentKind> {
+    pub trusted: Client<E>,
+    pub candidate: Client<E>,
+}
+
+impl DatadirDiff<mdbx::NoWriteMap> {
+    /// Opens the chaindata directories of both nodes.
+    pub fn open<P: Into<PathBuf>>(trusted: P, candidate: P) -> Result<Self> {
+        Ok(Self::new(
+            Client::open_new(trusted.into())?,
+            Client::open_new(candidate.into())?,
+        ))
+    }
+}
+
+impl<E: EnvironmentKind> DatadirDiff<E> {
+    pub fn new(trusted: Client<E>, candidate: Client<E>) -> Self {
+        Self { trusted, candidate }
+    }
+
+    /// Compares the canonical hashes of the blocks in `range`. An unbounded
+    /// end is the higher of the two heads, so blocks one node is missing are
+    /// reported too.
+    pub fn canonical<R: RangeBounds<u64>>(&self, range: R) -> Result<CanonicalDiff> {
+        let from = match range.start_bound() {
+            Bound::Included(n) => *n,
+            Bound::Excluded(n) => n.saturating_add(1),
+            Bound::Unbounded => 0,
+        };
+        let to = match range.end_bound() {
+            Bound::Included(n) => *n,
+            Bound::Excluded(0) => return Ok(CanonicalDiff::default()),
+            Bound::Excluded(n) => n - 1,
+            Bound::Unbounded => self
+                .trusted
+                .get_block_number()?
+                .max(self.candidate.get_block_number()?)
+                .as_u64(),
+        };
+        if from > to {
+            return Ok(CanonicalDiff::default());
+        }
+
+        let mut trusted = self.trusted.reader()?;
+        let mut candidate = self.candidate.reader()?;
+        let mut diff = CanonicalDiff {
+            from,
+            to,
+            ..Default::default()
+        };
+        for num in from..=to {
+            let a = trusted.find_canonical_hash(BlockNum(num))?;
+            let b = candidate.find_canonical_hash(BlockNum(num))?;
+            if a != b {
+                diff.divergences.push(HashDivergence {
+                    number: num,
+                    trusted: a,
+                    candidate: b,
+                });
+            }
+        }
+        Ok(diff)
+    }
+
+    /// Compares the full state of the two datadirs as of block `num`. See
+    /// `Client::dump_state_at`, which both sides are streamed from.
+    pub fn state_at(&self, num: u64) -> Result<Vec<AccountDivergence>> {
+        let mut trusted = self.trusted.dump_state_at(num)?.peekable();
+        let mut candidate = self.candidate.dump_state_at(num)?.peekable();
+        let mut out = vec![];
+        loop {
+            let order = match (trusted.peek(), candidate.peek()) {
+                (None, None) => break,
+                (Some(Ok(a)), Some(Ok(b))) => a.address.cmp(&b.address),
+                // errors are taken and returned below
+                (Some(Err(_)), _) => Ordering::Less,
+                (_, Some(Err(_))) => Ordering::Greater,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+            };
+            let (a, b) = match order {
+                Ordering::Less => (trusted.next().transpose()?, None),
+                Ordering::Greater => (None, candidate.next().transpose()?),
+                Ordering::Equal => (trusted.next().transpose()?, candidate.next().transpose()?),
+            };
+            if a != b {
+                let address = a.as_ref().or(b.as_ref()).map(|acct| acct.address);
+                out.push(AccountDivergence {
+                    address: address.unwrap_or_default(),
+                    trusted: a,
+                    candidate: b,
+                });
+            }
+        }
+        Ok(out)
+    }
+
+    /// Compares the raw entries of `table` from the first key at or after
+    /// `from`, reading at most `limit` entries of each datadir. Entries under
+    /// the same key whose values differ are reported as one divergence, except
+    /// in dupsort tables, where each value under a key is its own entry and a
+    /// value only one side has is reported alone.
+    pub fn table(&self, table: DiffTable, from: &[u8], limit: usize) -> Result<TableDiff> {
+        // one entry past the window shows where a side was cut off: only the
+        // keys before it are complete, so later ones are left to the next window
+        let a = read_entries(&self.trusted, table, from, limit + 1)?;
+        let b = read_entries(&self.candidate, table, from, limit + 1)?;
+        let end = [&a, &b]
+            .into_iter()
+            .filter(|entries| entries.len() > limit)
+            .filter_map(|entries| entries.last().map(|(k, _)| k.clone()))
+            .min();
+        if let Some(end) = &end {
+            anyhow::ensure!(
+                end.as_slice() > from,
+                "more than {} entries under key {}",
+                limit,
+                hex::encode(end)
+            );
+        }
+        let complete = |k: &Vec<u8>| end.as_ref().map_or(true, |end| k < end);
+
+        let mut a = a.into_iter().filter(|(k, _)| complete(k)).peekable();
+        let mut b = b.into_iter().filter(|(k, _)| complete(k)).peekable();
+        let mut divergences = vec![];
+        loop {
+            let order = match (a.peek(), b.peek()) {
+                (None, None) => break,
+                (Some(x), Some(y)) if table.is_dupsort() => x.cmp(y),
+                (Some(x), Some(y)) => x.0.cmp(&y.0),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+            };
+            let (x, y) = match order {
+                Ordering::Less => (a.next(), None),
+                Ordering::Greater => (None, b.next()),
+                Ordering::Equal => (a.next(), b.next()),
+            };
+            if x != y {
+                let key = x.as_ref().or(y.as_ref()).map(|(k, _)| k.clone());
+                divergences.push(EntryDivergence {
+                    key: key.unwrap_or_default().into(),
+                    trusted: x.map(|(_, v)| v.into()),
+                    candidate: y.map(|(_, v)| v.into()),
+                });
+            }
+        }
+        Ok(TableDiff {
+            divergences,
+            next: end.map(Into::into),
+        })
+    }
+}
+
+fn read_entries<E: EnvironmentKind>(
+    client: &Client<E>,
+    table: DiffTable,
+    from: &[u8],
+    limit: usize,
+) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    let mut dbtx = client.reader()?;
+    let from = from.to_vec();
+    match table {
+        DiffTable::CanonicalHeader => {
+            dbtx.read_raw_entries(tables::CanonicalHeader.erased(), from, limit)
+        }
+        DiffTable::Header => dbtx.read_raw_entries(tables::Header.erased(), from, limit),
+        DiffTable::BlockBody => dbtx.read_raw_entries(tables::BlockBody.erased(), from, limit),
+        DiffTable::BlockTransaction => {
+            dbtx.read_raw_entries(tables::BlockTransaction.erased(), from, limit)
+        }
+        DiffTable::PlainState => dbtx.read_raw_entries(tables::PlainState.erased(), from, limit),
+        DiffTable::PlainContractCode => {
+            dbtx.read_raw_entries(tables::PlainContractCode.erased(), from, limit)
+        }
+        DiffTable::Code => dbtx.read_raw_entries(tables::Code.erased(), from, limit),
+        DiffTable::AccountChangeSet => {
+            dbtx.read_raw_entries(tables::AccountChangeSet.erased(), from, limit)
+        }
+        DiffTable::StorageChangeSet => {
+            dbtx.read_raw_entries(tables::StorageChangeSet.erased(), from, limit)
+        }
+        DiffTable::Receipt => dbtx.read_raw_entries(tables::Receipt.erased(), from, limit),
+        DiffTable::TransactionLog => {
+            dbtx.read_raw_entries(tables::TransactionLog.erased(), from, limit)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::Account,
+        test::{chain::ChainBuilder, TMP_DIR},
+    };
+
+    #[test]
+    fn test_datadir_diff() -> Result<()> {
+        let who = Address::repeat_byte(0x55);
+        let trusted = ChainBuilder::new()
+            .block(|b| b.account(who, Account::new().balance(1.into())))
+            .block(|b| b)
+            .write(TMP_DIR.clone())?;
+        let candidate = ChainBuilder::new().block(|b| b).write(TMP_DIR.clone())?;
+        let diff = DatadirDiff::open(trusted.path.clone(), candidate.path.clone())?;
+
+        // the chains share no blocks, and the candidate is one block short
+        let chain = diff.canonical(..)?;
+        assert_eq!((chain.from, chain.to), (0, 2));
+        assert_eq!(chain.first_divergence(), Some(1));
+        assert_eq!(
+            chain.divergences[1],
+            HashDivergence {
+                number: 2,
+                trusted: Some(trusted.hash(1)),
+                candidate: None,
+            }
+        );
+        assert!(diff.canonical(3..)?.divergences.is_empty());
+
+        let state = diff.state_at(1)?;
+        assert_eq!(state.len(), 1);
+        assert_eq!(state[0].address, who);
+        assert_eq!(state[0].candidate, None);
+
+        let table = diff.table(DiffTable::PlainState, &[], 10)?;
+        assert_eq!(table.divergences.len(), 1);
+        assert_eq!(table.divergences[0].key.as_ref(), who.as_bytes());
+        assert_eq!(table.next, None);
+
+        // windows of one entry
+        let first = diff.table(DiffTable::CanonicalHeader, &[], 1)?;
+        assert_eq!(first.divergences.len(), 1);
+        let next = first.next.expect("trusted has a second block");
+        let rest = diff.table(DiffTable::CanonicalHeader, &next, 1)?;
+        assert_eq!(rest.divergences.len(), 1);
+        assert_eq!(rest.divergences[0].candidate, None);
+        assert_eq!(rest.next, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_dupsort_diff() -> Result<()> {
+        let who = Address::repeat_byte(0x55);
+        let (one, two) = (H256::from_low_u64_be(1), H256::from_low_u64_be(2));
+        let trusted = ChainBuilder::new()
+            .block(|b| b.storage(who, one, one).storage(who, two, two))
+            .write(TMP_DIR.clone())?;
+        let candidate = ChainBuilder::new()
+            .block(|b| b.storage(who, two, two))
+            .write(TMP_DIR.clone())?;
+        let diff = DatadirDiff::open(trusted.path.clone(), candidate.path.clone())?;
+
+        // the shared slot isn't paired with the missing one
+        let table = diff.table(DiffTable::PlainState, &[], 10)?;
+        assert_eq!(table.divergences.len(), 1);
+        let missing = &table.divergences[0];
+        assert!(missing
+            .trusted
+            .as_ref()
+            .unwrap()
+            .starts_with(one.as_bytes()));
+        assert_eq!(missing.candidate, None);
+        Ok(())
+    }
+}
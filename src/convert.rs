@@ -0,0 +1,142 @@
+//! Conversions between akula's primitive types and ethers'.
+//!
+//! Akula's hashes and addresses are the same `ethereum-types` as ethers', but
+//! its integers are `ethnum` types and its block numbers are newtypes. Values
+//! decoded from raw table bytes must also be checked for length, since
+//! `H256::from_slice` and friends panic on a mismatch. Custom readers built on
+//! `Reader` can use the same conversions as the `Client`.
+
+use akula::models as ak_models;
+use anyhow::{format_err, Result};
+use ethers::types::{Address, BlockNumber, Bloom, H256, U256, U64};
+
+/// Converts an akula `U256` to an ethers `U256`.
+pub fn u256(v: ak_models::U256) -> U256 {
+    v.to_be_bytes().into()
+}
+
+/// Converts an ethers `U256` to an akula `U256`.
+pub fn ak_u256(v: U256) -> ak_models::U256 {
+    let mut buf = [0; 32];
+    v.to_big_endian(&mut buf);
+    ak_models::U256::from_be_bytes(buf)
+}
+
+/// Encodes an ethers `U256` as 32 big endian bytes, which sort numerically,
+/// for the exporters' fixed-width columns.
+pub fn u256_bytes(v: U256) -> Vec<u8> {
+    let mut buf = vec![0; 32];
+    v.to_big_endian(&mut buf);
+    buf
+}
+
+/// Converts an ethers `U256` to a `u64`, failing if it doesn't fit.
+pub fn u64(v: U256) -> Result<u64> {
+    if v > U256::from(u64::MAX) {
+        return Err(format_err!("{} overflows a u64", v));
+    }
+    Ok(v.as_u64())
+}
+
+/// Reinterprets an akula `U256` as a 32 byte word, as the storage tables hold
+/// slot values.
+pub fn word(v: ak_models::U256) -> H256 {
+    v.to_be_bytes().into()
+}
+
+/// Reads a storage value with its leading zeros trimmed, as the hashed storage
+/// and storage changesets hold them, into a 32 byte word.
+pub fn trimmed_word(bytes: &[u8]) -> Result<H256> {
+    anyhow::ensure!(
+        bytes.len() <= H256::len_bytes(),
+        "storage value is {} bytes long",
+        bytes.len()
+    );
+    let mut word = H256::zero();
+    word.0[H256::len_bytes() - bytes.len()..].copy_from_slice(bytes);
+    Ok(word)
+}
+
+/// Reads a hash from raw bytes, which must be exactly 32 bytes long.
+pub fn h256(bytes: &[u8]) -> Result<H256> {
+    check_len(bytes, H256::len_bytes(), "hash")?;
+    Ok(H256::from_slice(bytes))
+}
+
+/// Reads an address from raw bytes, which must be exactly 20 bytes long.
+pub fn address(bytes: &[u8]) -> Result<Address> {
+    check_len(bytes, Address::len_bytes(), "address")?;
+    Ok(Address::from_slice(bytes))
+}
+
+/// Reads a logs bloom from raw bytes, which must be exactly 256 bytes long.
+pub fn bloom(bytes: &[u8]) -> Result<Bloom> {
+    check_len(bytes, Bloom::len_bytes(), "bloom")?;
+    Ok(Bloom::from_slice(bytes))
+}
+
+/// Converts an akula block number to the `U64` ethers uses in blocks,
+/// transactions and logs.
+pub fn block_number(n: ak_models::BlockNumber) -> U64 {
+    n.0.into()
+}
+
+/// Converts an ethers block number to an akula one. Tags such as `latest` need
+/// a db to resolve and are an error here.
+pub fn ak_block_number(n: BlockNumber) -> Result<ak_models::BlockNumber> {
+    match n {
+        BlockNumber::Number(n) => Ok(ak_models::BlockNumber(n.as_u64())),
+        tag => Err(format_err!("{:?} is not a block number", tag)),
+    }
+}
+
+fn check_len(bytes: &[u8], len: usize, what: &str) -> Result<()> {
+    anyhow::ensure!(
+        bytes.len() == len,
+        "bad {} length: expected {} bytes, got {}",
+        what,
+        len,
+        bytes.len()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_convert() -> Result<()> {
+        assert_eq!(u256(ak_models::U256::from(0x1234u64)), U256::from(0x1234));
+        assert_eq!(u256(ak_models::U256::MAX), U256::MAX);
+        assert_eq!(word(1u64.into()), H256::from_low_u64_be(1));
+        assert_eq!(
+            u256_bytes(U256::from(1)),
+            H256::from_low_u64_be(1).as_bytes()
+        );
+        assert_eq!(u64(U256::from(u64::MAX))?, u64::MAX);
+        assert!(u64(U256::from(u64::MAX) + 1).is_err());
+
+        assert_eq!(h256(&[0x11; 32])?, H256::repeat_byte(0x11));
+        assert_eq!(address(&[0x22; 20])?, Address::repeat_byte(0x22));
+        assert_eq!(bloom(&[0x33; 256])?, Bloom::repeat_byte(0x33));
+        let err = h256(&[0; 20]).unwrap_err().to_string();
+        assert_eq!(err, "bad hash length: expected 32 bytes, got 20");
+        assert!(address(&[0; 32]).is_err());
+        assert!(bloom(&[]).is_err());
+
+        assert_eq!(block_number(ak_models::BlockNumber(7)), U64::from(7));
+        assert_eq!(ak_block_number(7u64.into())?, ak_models::BlockNumber(7));
+        assert!(ak_block_number(BlockNumber::Latest).is_err());
+        Ok(())
+    }
+
+    proptest! {
+        #[test]
+        fn prop_u256_roundtrip(bytes in any::<[u8; 32]>()) {
+            let v = U256::from(bytes);
+            prop_assert_eq!(u256(ak_u256(v)), v);
+        }
+    }
+}
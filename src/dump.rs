@@ -0,0 +1,266 @@
+//! Dumps of the full state as of a historical block. The current accounts and
+//! storage in PlainState are walked in address order, with every account and
+//! slot changed since the block replaced by its value going into the first
+//! block which changed it, as recorded in the changesets.
+
+use anyhow::Result;
+use ethers::types::{Address, BlockId, H256, U256};
+use mdbx::EnvironmentKind;
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::{
+    builder::PrunedData,
+    client::{get_header_key, Client, STATE_TABLES},
+    convert,
+    models::Account,
+    page::{paginate, Page, PageCursor},
+    reader::Reader,
+    tables,
+    types::BlockNum,
+};
+
+/// The number of PlainState accounts read per step of a `StateDump`.
+const DUMP_BATCH: usize = 256;
+
+/// One account of a `StateDump`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DumpedAccount {
+    pub address: Address,
+    pub balance: U256,
+    pub nonce: u64,
+    pub incarnation: u64,
+    pub codehash: H256,
+    /// The account's set storage slots.
+    pub storage: BTreeMap<H256, H256>,
+}
+
+/// A lazy iterator over the accounts existing as of a block, in address
+/// order, returned by `Client::dump_state_at`.
+pub struct StateDump<'env, E: EnvironmentKind> {
+    dbtx: Reader<'env, mdbx::RO, E>,
+    /// The accounts changed since the block, as they were at the block.
+    accounts: BTreeMap<Address, Account>,
+    /// The slots changed since the block, as they were at the block.
+    storage: BTreeMap<(Address, u64), BTreeMap<H256, H256>>,
+    /// The last address read from PlainState.
+    after: Option<Address>,
+    pending: VecDeque<DumpedAccount>,
+    done: bool,
+}
+
+impl<'env, E: EnvironmentKind> StateDump<'env, E> {
+    fn new(mut dbtx: Reader<'env, mdbx::RO, E>, num: BlockNum) -> Result<Self> {
+        let mut state = dbtx.state_at(num);
+        let accounts = state.changed_accounts()?;
+        let storage = state.changed_storage()?;
+        Ok(Self {
+            accounts,
+            storage,
+            dbtx,
+            after: None,
+            pending: VecDeque::new(),
+            done: false,
+        })
+    }
+
+    /// Skips the accounts before `who`, so the dump starts at `who`.
+    fn start_at(mut self, who: Address) -> Self {
+        let n = U256::from_big_endian(who.as_bytes());
+        self.after = n.checked_sub(1.into()).map(|before| {
+            let mut word = [0u8; 32];
+            before.to_big_endian(&mut word);
+            Address::from_slice(&word[12..])
+        });
+        self
+    }
+
+    /// Reads the next batch of accounts into `pending`. Returns false once
+    /// every account has been read.
+    fn fill(&mut self) -> Result<bool> {
+        let plain = self.dbtx.read_accounts_after(self.after, DUMP_BATCH)?;
+        // a short batch reached the end of PlainState
+        let end = if plain.len() == DUMP_BATCH {
+            plain.last().map(|(who, _)| *who)
+        } else {
+            None
+        };
+        let changed = self
+            .accounts
+            .iter()
+            .filter(|(who, _)| self.after.map_or(true, |after| **who > after))
+            .take_while(|(who, _)| end.map_or(true, |end| **who <= end))
+            .map(|(who, acct)| (*who, *acct))
+            .collect::<BTreeMap<_, _>>();
+
+        let mut merged = plain.into_iter().collect::<BTreeMap<_, _>>();
+        merged.extend(changed);
+        for (who, acct) in merged {
+            // didn't exist at the block
+            if acct == Account::default() {
+                continue;
+            }
+            let storage = self.read_storage(who, acct.incarnation)?;
+            self.pending.push_back(DumpedAccount {
+                address: who,
+                balance: acct.balance,
+                nonce: acct.nonce,
+                incarnation: acct.incarnation,
+                codehash: acct.codehash,
+                storage,
+            });
+        }
+        self.after = end;
+        Ok(end.is_some())
+    }
+
+    /// Returns the storage of `who` at `incarnation` as of the block.
+    fn read_storage(&mut self, who: Address, incarnation: u64) -> Result<BTreeMap<H256, H256>> {
+        let mut storage = BTreeMap::new();
+        if incarnation == 0 {
+            return Ok(storage);
+        }
+        for res in self.dbtx.walk_account_storage(who, incarnation)? {
+            let (slot, val) = res?;
+            storage.insert(slot, convert::word(val));
+        }
+        if let Some(changed) = self.storage.get(&(who, incarnation)) {
+            for (slot, val) in changed {
+                if val.is_zero() {
+                    storage.remove(slot);
+                } else {
+                    storage.insert(*slot, *val);
+                }
+            }
+        }
+        Ok(storage)
+    }
+}
+
+impl<'env, E: EnvironmentKind> Iterator for StateDump<'env, E> {
+    type Item = Result<DumpedAccount>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(acct) = self.pending.pop_front() {
+                return Some(Ok(acct));
+            }
+            if self.done {
+                return None;
+            }
+            match self.fill() {
+                Ok(more) => self.done = !more,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+impl<E: EnvironmentKind> Client<E> {
+    /// Returns a lazy iterator over every account as of `block`, with its
+    /// storage, in address order. Everything changed since the block is read
+    /// from the changesets up front, so memory grows with the number of
+    /// changes between the block and the head. Fails if the changesets don't
+    /// reach back to the block.
+    ///
+    /// ```ignore
+    /// for acct in client.dump_state_at(15_000_000)? {
+    ///     serde_json::to_writer(&mut out, &acct?)?;
+    /// }
+    /// ```
+    pub fn dump_state_at<T: Into<BlockId> + Send + Sync>(
+        &self,
+        block: T,
+    ) -> Result<StateDump<'_, E>> {
+        self.ensure_unpruned("dump_state_at", PrunedData::History)?;
+        let mut dbtx = self.reader()?;
+        let num = get_header_key(&mut dbtx, block)?.num;
+        let head = dbtx.read_head_block_number()?;
+        anyhow::ensure!(num <= head, "block {} is past the head {}", num, head);
+        if let Some(range) = dbtx.read_block_key_range(tables::AccountChangeSet.erased())? {
+            anyhow::ensure!(
+                range.from <= *num + 1,
+                "state history starts at block {}, after block {}",
+                range.from,
+                num
+            );
+        }
+        StateDump::new(dbtx, num)
+    }
+
+    /// Returns a page of at most `limit` accounts of `dump_state_at(block)`.
+    /// Pass the previous page's cursor, with the same block, to resume.
+    pub fn dump_state_page<T: Into<BlockId> + Send + Sync>(
+        &self,
+        block: T,
+        cursor: Option<&PageCursor>,
+        limit: usize,
+    ) -> Result<Page<DumpedAccount>> {
+        let block = block.into();
+        let _slow = self.slow_read("dump_state_page", STATE_TABLES, || format!("{:?}", block));
+        let mut dump = self.dump_state_at(block)?;
+        if let Some(cursor) = cursor {
+            dump = dump.start_at(Address::from(cursor.to_array::<20>()?));
+        }
+        let accounts = dump.map(|res| res.map(|acct| (acct.address.as_bytes().to_vec(), acct)));
+        paginate(accounts, limit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::{chain::ChainBuilder, TMP_DIR};
+
+    #[test]
+    fn test_dump_state_at() -> Result<()> {
+        let (a, b, c) = (
+            Address::repeat_byte(0x0a),
+            Address::repeat_byte(0x0b),
+            Address::repeat_byte(0x0c),
+        );
+        let slot = H256::from_low_u64_be(1);
+        let word = H256::from_low_u64_be;
+        let chain = ChainBuilder::new()
+            .start(1)
+            .block(|blk| blk)
+            // block 2 raises a's balance, changes b's slot and creates c
+            .block(|blk| {
+                blk.account(a, Account::new().balance(2.into()))
+                    .account(b, Account::new().balance(5.into()).incarnation(1))
+                    .storage(b, slot, word(8))
+                    .account(c, Account::new().balance(3.into()))
+                    .account_change(a, Some(Account::new().balance(1.into())))
+                    .storage_change(b, 1, slot, word(7))
+                    .account_change(c, None)
+            })
+            .write(TMP_DIR.clone())?;
+        let db = Client::<mdbx::NoWriteMap>::open_new(chain.path.clone())?;
+
+        let dump = db.dump_state_at(1u64)?.collect::<Result<Vec<_>>>()?;
+        assert_eq!(
+            dump.iter().map(|acct| acct.address).collect::<Vec<_>>(),
+            vec![a, b]
+        );
+        assert_eq!(dump[0].balance, 1.into());
+        assert_eq!(dump[1].storage[&slot], word(7));
+
+        let head = db.dump_state_at(2u64)?.collect::<Result<Vec<_>>>()?;
+        assert_eq!(head.len(), 3);
+        assert_eq!(head[0].balance, 2.into());
+        assert_eq!(head[1].storage[&slot], word(8));
+        assert!(db.dump_state_at(3u64).is_err());
+
+        // paged dumps resume at the cursor's account
+        let first = db.dump_state_page(2u64, None, 2)?;
+        assert_eq!(first.items, head[..2]);
+        let rest = db.dump_state_page(2u64, first.next_cursor.as_ref(), 2)?;
+        assert!(rest.is_last());
+        assert_eq!(rest.items, head[2..]);
+        Ok(())
+    }
+}
@@ -0,0 +1,348 @@
+//! Chunked exports of long block ranges.
+//!
+//! An export which reads a long range in one read transaction pins that MDBX
+//! snapshot for its whole run, and the pages Erigon frees meanwhile can't be
+//! reused, so the db grows. A `ChunkedExport` instead reads each chunk of
+//! blocks in a fresh transaction, closed before the next one opens, and saves
+//! its progress after every chunk so a stopped export resumes where it left
+//! off.
+//!
+//! Chunks are stitched at a recorded boundary: the number and hash of the last
+//! exported block. Each new snapshot must still have that block on its
+//! canonical chain, so the chunks always form one chain even while the node
+//! writes.
+
+use anyhow::{format_err, Result};
+use mdbx::EnvironmentKind;
+use std::{io::Write, ops::RangeInclusive};
+
+use crate::{
+    budget::CancelToken,
+    builder::ClientOptions,
+    client::Client,
+    codec::BlockCast,
+    follow::{Checkpoint, CheckpointStore, Cursor},
+    reader::Reader,
+    types::{BlockNum, HeaderKey},
+};
+
+/// Enough blocks per chunk to amortize opening a transaction, few enough that
+/// a chunk's snapshot is released within seconds.
+pub const DEFAULT_CHUNK_BLOCKS: u64 = 10_000;
+
+/// The output of a `ChunkedExport`. Sinks writing JSON can format it as the
+/// serving layers do with `ClientOptions::format_output`, as `HeaderLines`
+/// does.
+pub trait ExportSink<E: EnvironmentKind> {
+    /// Exports the blocks in `range`, reading them from `dbtx`. The snapshot
+    /// is consistent for the chunk, and is closed once this returns.
+    fn write_chunk(
+        &mut self,
+        dbtx: &mut Reader<'_, mdbx::RO, E>,
+        range: RangeInclusive<u64>,
+    ) -> Result<()>;
+
+    /// Makes the chunks written so far durable. Called after each chunk and
+    /// before its progress is saved, so the progress never runs ahead of the
+    /// output.
+    fn commit(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// What `ChunkedExport::run` did.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExportReport {
+    pub chunks: u64,
+    pub blocks: u64,
+    /// The last block exported, by this run or an earlier one.
+    pub last: Option<Cursor>,
+    /// Whether the export stopped early because it was cancelled.
+    pub cancelled: bool,
+}
+
+/// Exports a range of blocks in chunks, each read from its own snapshot.
+///
+/// ```ignore
+/// let mut export = ChunkedExport::new(FileStore::new("export.json")).start(15_000_000);
+/// let report = export.run(&client, 16_000_000, &mut sink, &CancelToken::new())?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct ChunkedExport<S> {
+    store: S,
+    start: u64,
+    chunk_blocks: u64,
+}
+
+impl<S: CheckpointStore> ChunkedExport<S> {
+    /// Exports from block 0 in chunks of `DEFAULT_CHUNK_BLOCKS`, resuming from
+    /// the progress in `store` if it has any.
+    pub fn new(store: S) -> Self {
+        Self {
+            store,
+            start: 0,
+            chunk_blocks: DEFAULT_CHUNK_BLOCKS,
+        }
+    }
+
+    /// The first block exported when the store has no progress.
+    pub fn start(mut self, num: u64) -> Self {
+        self.start = num;
+        self
+    }
+
+    /// The number of blocks read per transaction.
+    pub fn chunk_blocks(mut self, n: u64) -> Self {
+        self.chunk_blocks = n.max(1);
+        self
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// Exports every block after the saved progress up to and including `to`.
+    /// Errors if the last exported block has since been reorged out, as the
+    /// chunks already written would no longer form one chain.
+    ///
+    /// `cancel` is checked between chunks. A cancelled run saves the progress
+    /// of the chunks it finished.
+    pub fn run<E, K>(
+        &mut self,
+        client: &Client<E>,
+        to: u64,
+        sink: &mut K,
+        cancel: &CancelToken,
+    ) -> Result<ExportReport>
+    where
+        E: EnvironmentKind,
+        K: ExportSink<E>,
+    {
+        let checkpoint = self.store.load()?.unwrap_or_default();
+        let mut report = ExportReport {
+            last: checkpoint.head(),
+            ..Default::default()
+        };
+        let mut from = report.last.map_or(self.start, |c| c.number + 1);
+
+        while from <= to {
+            if cancel.is_cancelled() {
+                report.cancelled = true;
+                break;
+            }
+            let end = to.min(from.saturating_add(self.chunk_blocks - 1));
+            let last = {
+                let _permit = client.admit()?;
+                let mut dbtx = client.reader()?;
+                check_boundary(&mut dbtx, report.last)?;
+                let head = *dbtx.read_head_block_number()?;
+                anyhow::ensure!(end <= head, "export end {} is past the head {}", end, head);
+
+                sink.write_chunk(&mut dbtx, from..=end)?;
+                Cursor {
+                    number: end,
+                    hash: dbtx.read_canonical_hash(BlockNum(end))?,
+                }
+            };
+            sink.commit()?;
+            self.store.save(&Checkpoint { recent: vec![last] })?;
+
+            report.chunks += 1;
+            report.blocks += end - from + 1;
+            report.last = Some(last);
+            from = match end.checked_add(1) {
+                Some(n) => n,
+                None => break,
+            };
+        }
+        Ok(report)
+    }
+}
+
+/// Writes the header of each exported block to `out` as a line of JSON,
+/// formatted and labelled as the serving layers format their output.
+#[derive(Debug)]
+pub struct HeaderLines<W> {
+    out: W,
+    options: ClientOptions,
+}
+
+impl<W: Write> HeaderLines<W> {
+    /// Formats the headers with the options of `client`.
+    pub fn new<E: EnvironmentKind>(client: &Client<E>, out: W) -> Self {
+        Self {
+            out,
+            options: client.options().clone(),
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+impl<W: Write, E: EnvironmentKind> ExportSink<E> for HeaderLines<W> {
+    fn write_chunk(
+        &mut self,
+        dbtx: &mut Reader<'_, mdbx::RO, E>,
+        range: RangeInclusive<u64>,
+    ) -> Result<()> {
+        for num in range {
+            let key = HeaderKey::new(num, dbtx.read_canonical_hash(BlockNum(num))?);
+            let header = dbtx.read_header(key)?;
+            let mut value =
+                serde_json::to_value(BlockCast(&header).cast_header(key.num, key.hash))?;
+            self.options.format_output(&mut value);
+            serde_json::to_writer(&mut self.out, &value)?;
+            self.out.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        Ok(self.out.flush()?)
+    }
+}
+
+/// Checks that the boundary recorded by the last chunk is still canonical in
+/// the snapshot `dbtx`.
+fn check_boundary<E: EnvironmentKind>(
+    dbtx: &mut Reader<'_, mdbx::RO, E>,
+    boundary: Option<Cursor>,
+) -> Result<()> {
+    let boundary = match boundary {
+        Some(boundary) => boundary,
+        None => return Ok(()),
+    };
+    let hash = dbtx.read_canonical_hash(BlockNum(boundary.number)).ok();
+    if hash != Some(boundary.hash) {
+        return Err(format_err!(
+            "block {} ({:?}) was reorged out after it was exported",
+            boundary.number,
+            boundary.hash
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        follow::MemoryStore,
+        output::AddressLabeler,
+        test::{chain::ChainBuilder, TMP_DIR},
+    };
+    use ethers::types::{Address, H256};
+
+    /// Records the chunks and the headers read in each.
+    #[derive(Default)]
+    struct Recorder {
+        chunks: Vec<RangeInclusive<u64>>,
+        headers: Vec<u64>,
+        commits: usize,
+    }
+
+    impl<E: EnvironmentKind> ExportSink<E> for Recorder {
+        fn write_chunk(
+            &mut self,
+            dbtx: &mut Reader<'_, mdbx::RO, E>,
+            range: RangeInclusive<u64>,
+        ) -> Result<()> {
+            for num in range.clone() {
+                let key = HeaderKey::new(num, dbtx.read_canonical_hash(BlockNum(num))?);
+                self.headers.push(dbtx.read_header(key)?.number.0);
+            }
+            self.chunks.push(range);
+            Ok(())
+        }
+
+        fn commit(&mut self) -> Result<()> {
+            self.commits += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_chunked_export() -> Result<()> {
+        let mut chain = ChainBuilder::new();
+        for _ in 0..5 {
+            chain = chain.block(|b| b);
+        }
+        let chain = chain.write(TMP_DIR.clone())?;
+        let db = Client::<mdbx::NoWriteMap>::open_new(chain.path.clone())?;
+
+        let mut export = ChunkedExport::new(MemoryStore::default())
+            .start(1)
+            .chunk_blocks(2);
+        let mut rec = Recorder::default();
+        let cancel = CancelToken::new();
+        let report = export.run(&db, 4, &mut rec, &cancel)?;
+        assert_eq!((report.chunks, report.blocks), (2, 4));
+        assert_eq!(rec.chunks, vec![1..=2, 3..=4]);
+        assert_eq!(rec.headers, vec![1, 2, 3, 4]);
+        assert_eq!(rec.commits, 2);
+        let boundary = Cursor {
+            number: 4,
+            hash: chain.hash(3),
+        };
+        assert_eq!(report.last, Some(boundary));
+
+        // resumes from the boundary
+        let report = export.run(&db, 5, &mut rec, &cancel)?;
+        assert_eq!(report.blocks, 1);
+        assert_eq!(rec.headers, vec![1, 2, 3, 4, 5]);
+        assert!(export.run(&db, 6, &mut rec, &cancel).is_err());
+
+        cancel.cancel();
+        let mut fresh = ChunkedExport::new(MemoryStore::default()).start(1);
+        let report = fresh.run(&db, 5, &mut rec, &cancel)?;
+        assert!(report.cancelled);
+        assert_eq!(report.blocks, 0);
+
+        // the boundary is no longer canonical
+        let mut forked = ChunkedExport::new(MemoryStore(Some(Checkpoint {
+            recent: vec![Cursor {
+                hash: H256::repeat_byte(0xff),
+                ..boundary
+            }],
+        })));
+        assert!(forked.run(&db, 5, &mut rec, &CancelToken::new()).is_err());
+        Ok(())
+    }
+
+    #[derive(Debug)]
+    struct Everyone;
+
+    impl AddressLabeler for Everyone {
+        fn label(&self, _: &Address) -> Option<String> {
+            Some("someone".to_string())
+        }
+    }
+
+    #[test]
+    fn test_header_lines() -> Result<()> {
+        let chain = ChainBuilder::new()
+            .block(|b| b)
+            .block(|b| b)
+            .write(TMP_DIR.clone())?;
+        let db = Client::<mdbx::NoWriteMap>::builder()
+            .path(chain.path.clone())
+            .labeler(Everyone)
+            .build()?;
+
+        let mut sink = HeaderLines::new(&db, vec![]);
+        let mut export = ChunkedExport::new(MemoryStore::default()).start(1);
+        export.run(&db, 2, &mut sink, &CancelToken::new())?;
+        let lines = String::from_utf8(sink.into_inner())?;
+        let headers = lines
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<Result<Vec<serde_json::Value>, _>>()?;
+        assert_eq!(headers.len(), 2);
+        assert_eq!(headers[1]["hash"], serde_json::to_value(chain.hash(1))?);
+        // formatted as the serving layers format it
+        assert_eq!(headers[0]["minerLabel"], "someone");
+        Ok(())
+    }
+}
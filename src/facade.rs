@@ -0,0 +1,158 @@
+//! An object-safe facade over the commonly used read methods.
+//!
+//! `Client` is generic over its MDBX environment kind and most of its methods
+//! are generic over their arguments, so it can't be used as a trait object.
+//! `EthReader` takes concrete ethers types instead, so an application can hold
+//! an `Arc<dyn EthReader>` and choose the backend at runtime: an Erigon
+//! `Client`, another node's db, or a mock in tests.
+
+use anyhow::Result;
+use ethers::types::{
+    Address, Block, BlockId, BlockNumber, Bytes, Filter, Log, Transaction, TransactionReceipt,
+    TxHash, H256, U256, U64,
+};
+use std::sync::Arc;
+
+/// The read methods of a `Client`, with concrete argument types.
+///
+/// Methods which take a `block` only serve the latest state unless the
+/// backend says otherwise.
+pub trait EthReader: Send + Sync {
+    fn get_block_number(&self) -> Result<U64>;
+
+    fn get_balance(&self, address: Address, block: Option<BlockId>) -> Result<U256>;
+
+    fn get_transaction_count(&self, address: Address, block: Option<BlockId>) -> Result<U256>;
+
+    fn get_code(&self, address: Address, block: Option<BlockId>) -> Result<Bytes>;
+
+    fn get_storage_at(&self, address: Address, slot: H256, block: Option<BlockId>) -> Result<H256>;
+
+    fn get_block(&self, id: BlockId) -> Result<Option<Block<TxHash>>>;
+
+    fn get_block_with_txs(&self, id: BlockId) -> Result<Option<Block<Transaction>>>;
+
+    fn get_transaction(&self, hash: TxHash) -> Result<Option<Transaction>>;
+
+    /// Errors if the backend has the transaction but not its block's receipts.
+    fn get_transaction_receipt(&self, hash: TxHash) -> Result<Option<TransactionReceipt>>;
+
+    /// Errors if the backend has the block but not its receipts.
+    fn get_block_receipts(&self, block: BlockNumber) -> Result<Vec<TransactionReceipt>>;
+
+    fn get_logs(&self, filter: &Filter) -> Result<Vec<Log>>;
+}
+
+/// A shared, type-erased reader.
+pub type DynClient = Arc<dyn EthReader>;
+
+#[cfg(feature = "db")]
+mod client {
+    use anyhow::format_err;
+    use mdbx::EnvironmentKind;
+
+    use super::*;
+    use crate::client::{Client, Either};
+
+    impl<E: EnvironmentKind> Client<E>
+    where
+        Self: Send + Sync,
+    {
+        /// Erases the client's environment kind, for use behind `DynClient`.
+        pub fn into_dyn(self) -> DynClient {
+            Arc::new(self)
+        }
+    }
+
+    impl<E: EnvironmentKind> EthReader for Client<E>
+    where
+        Self: Send + Sync,
+    {
+        fn get_block_number(&self) -> Result<U64> {
+            Client::get_block_number(self)
+        }
+
+        fn get_balance(&self, address: Address, block: Option<BlockId>) -> Result<U256> {
+            Client::get_balance(self, address, block)
+        }
+
+        fn get_transaction_count(&self, address: Address, block: Option<BlockId>) -> Result<U256> {
+            Client::get_transaction_count(self, address, block)
+        }
+
+        fn get_code(&self, address: Address, block: Option<BlockId>) -> Result<Bytes> {
+            Client::get_code(self, address, block)
+        }
+
+        fn get_storage_at(
+            &self,
+            address: Address,
+            slot: H256,
+            block: Option<BlockId>,
+        ) -> Result<H256> {
+            Client::get_storage_at(self, address, slot, block)
+        }
+
+        fn get_block(&self, id: BlockId) -> Result<Option<Block<TxHash>>> {
+            Client::get_block(self, id)
+        }
+
+        fn get_block_with_txs(&self, id: BlockId) -> Result<Option<Block<Transaction>>> {
+            Client::get_block_with_txs(self, id)
+        }
+
+        fn get_transaction(&self, hash: TxHash) -> Result<Option<Transaction>> {
+            Client::get_transaction(self, hash)
+        }
+
+        fn get_transaction_receipt(&self, hash: TxHash) -> Result<Option<TransactionReceipt>> {
+            match Client::get_transaction_receipt(self, hash)? {
+                Either::Right(receipt) => Ok(receipt),
+                Either::Left(num) => Err(format_err!("receipts for block {} not in db", num)),
+            }
+        }
+
+        fn get_block_receipts(&self, block: BlockNumber) -> Result<Vec<TransactionReceipt>> {
+            match Client::get_block_receipts(self, block)? {
+                Either::Right(receipts) => Ok(receipts),
+                Either::Left(num) => Err(format_err!("receipts for block {} not in db", num)),
+            }
+        }
+
+        fn get_logs(&self, filter: &Filter) -> Result<Vec<Log>> {
+            Client::get_logs(self, filter)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        client::Client,
+        test::{chain::ChainBuilder, TMP_DIR},
+    };
+
+    #[test]
+    fn test_dyn_client() -> Result<()> {
+        let chain = ChainBuilder::new()
+            .block(|b| b)
+            .block(|b| b)
+            .write(TMP_DIR.clone())?;
+        let client = Client::<mdbx::NoWriteMap>::open_new(chain.path.clone())?;
+        let db: DynClient = client.into_dyn();
+
+        assert_eq!(db.get_block_number()?, 2.into());
+        let block = db.get_block(BlockNumber::Number(1.into()).into())?.unwrap();
+        assert_eq!(block.hash, Some(chain.hash(0)));
+        assert_eq!(db.get_balance(Address::zero(), None)?, U256::zero());
+
+        // the facade can be shared across threads
+        let handle = {
+            let db = Arc::clone(&db);
+            std::thread::spawn(move || db.get_block_number())
+        };
+        assert_eq!(handle.join().unwrap()?, 2.into());
+        Ok(())
+    }
+}
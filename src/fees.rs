@@ -0,0 +1,195 @@
+use anyhow::{format_err, Result};
+use ethers::types::{BlockId, TxHash, H256, U256, U64};
+use mdbx::EnvironmentKind;
+use serde::Serialize;
+
+use crate::{
+    builder::PrunedData,
+    client::{get_header_key, Client},
+    codec::MsgCast,
+    convert,
+    receipts::{TxGasUsed, RECEIPT_TABLES},
+};
+
+/// The fees paid by one transaction, as returned in a `BlockFeeBreakdown`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TxFees {
+    pub transaction_hash: TxHash,
+    pub transaction_index: U64,
+    pub gas_used: U256,
+    pub effective_gas_price: U256,
+    /// The price per gas above the base fee, paid to the proposer.
+    pub effective_tip: U256,
+    /// `effective_tip * gas_used`.
+    pub tip: U256,
+}
+
+/// Where the fees of a block went, as returned by `Client::block_fee_breakdown`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlockFeeBreakdown {
+    pub number: U64,
+    pub hash: H256,
+    /// `None` before London.
+    pub base_fee_per_gas: Option<U256>,
+    pub gas_used: U256,
+    /// `base_fee_per_gas * gas_used`, zero before London.
+    pub burnt_fees: U256,
+    /// The tips of every transaction in `transactions`.
+    pub total_tips: U256,
+    /// The transactions' fees in block order, less any system transactions
+    /// excluded by the client's `SystemTxs` option.
+    pub transactions: Vec<TxFees>,
+}
+
+impl<E: EnvironmentKind> Client<E> {
+    /// Splits the fees paid in a block into the base fee burnt and the tips
+    /// paid to the proposer, per transaction and in total. The gas used by a
+    /// transaction is taken from the difference in cumulative gas used between
+    /// its receipt and the one before, so the block's receipts must be in the
+    /// db.
+    ///
+    /// ```ignore
+    /// let fees = client.block_fee_breakdown(BlockNumber::Latest)?;
+    /// println!("burnt {} tipped {}", fees.burnt_fees, fees.total_tips);
+    /// ```
+    pub fn block_fee_breakdown<T: Into<BlockId> + Send + Sync>(
+        &self,
+        block_hash_or_number: T,
+    ) -> Result<BlockFeeBreakdown> {
+        self.ensure_unpruned("block_fee_breakdown", PrunedData::Receipts)?;
+        let id = block_hash_or_number.into();
+        let _slow = self.slow_read("block_fee_breakdown", RECEIPT_TABLES, || {
+            format!("{:?}", id)
+        });
+        let mut dbtx = self.reader()?;
+        let key = get_header_key(&mut dbtx, id)?;
+        let header = self.read_header_cached(&mut dbtx, key)?;
+        let base_fee = header.base_fee_per_gas.map(convert::u256);
+        let txs = self.read_txs_gas_used(&mut dbtx, key)?;
+
+        let gas_used = U256::from(header.gas_used);
+        let mut breakdown = BlockFeeBreakdown {
+            number: key.num.into(),
+            hash: key.hash,
+            base_fee_per_gas: base_fee,
+            gas_used,
+            burnt_fees: base_fee.unwrap_or_default() * gas_used,
+            ..Default::default()
+        };
+        for TxGasUsed {
+            index,
+            msg,
+            gas_used,
+        } in txs
+        {
+            let effective_gas_price = MsgCast::new(&msg)
+                .base_fee(base_fee)
+                .effective_gas_price()
+                .ok_or_else(|| {
+                    format_err!("fee market tx in block {} without a base fee", key.num)
+                })?;
+            let effective_tip = effective_gas_price.saturating_sub(base_fee.unwrap_or_default());
+            let tip = effective_tip * U256::from(gas_used);
+            breakdown.total_tips += tip;
+            breakdown.transactions.push(TxFees {
+                transaction_hash: msg.hash(),
+                transaction_index: index.into(),
+                gas_used: gas_used.into(),
+                effective_gas_price,
+                effective_tip,
+                tip,
+            });
+        }
+        Ok(breakdown)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::Receipt,
+        test::{
+            chain::ChainBuilder,
+            rand::{rand_1559, rand_legacy, sign},
+            TMP_DIR,
+        },
+    };
+    use akula::models::{Message, MessageWithSignature};
+    use ethers::{core::k256::ecdsa::SigningKey, types::BlockNumber};
+    use rand::thread_rng;
+
+    #[test]
+    fn test_block_fee_breakdown() -> Result<()> {
+        let mut rng = thread_rng();
+        let mut legacy = rand_legacy(&mut rng);
+        if let Message::Legacy { gas_price, .. } = &mut legacy {
+            *gas_price = 30u64.into();
+        }
+        let mut dynamic = rand_1559(&mut rng);
+        if let Message::EIP1559 {
+            max_priority_fee_per_gas,
+            max_fee_per_gas,
+            ..
+        } = &mut dynamic
+        {
+            *max_priority_fee_per_gas = 5u64.into();
+            *max_fee_per_gas = 12u64.into();
+        }
+        let txs = [legacy, dynamic]
+            .into_iter()
+            .map(|message| {
+                let signature = sign(SigningKey::random(&mut rng), message.hash().as_bytes());
+                MessageWithSignature { message, signature }
+            })
+            .collect::<Vec<_>>();
+        let receipt = |cumulative_gas_used| Receipt {
+            status: 1,
+            cumulative_gas_used,
+            ..Default::default()
+        };
+        let chain = ChainBuilder::new()
+            .block(|b| {
+                b.txs(txs.clone())
+                    .base_fee(10u64)
+                    .receipt(receipt(21_000), vec![])
+                    .receipt(receipt(71_000), vec![])
+            })
+            .write(TMP_DIR.clone())?;
+        let db = Client::<mdbx::NoWriteMap>::open_new(chain.path.clone())?;
+
+        let fees = db.block_fee_breakdown(BlockNumber::Number(1.into()))?;
+        assert_eq!(fees.base_fee_per_gas, Some(10.into()));
+        let header_gas = U256::from(chain.blocks[0].header.gas_used);
+        assert_eq!(fees.burnt_fees, header_gas * U256::from(10));
+        assert_eq!(
+            fees.transactions,
+            vec![
+                TxFees {
+                    transaction_hash: txs[0].hash(),
+                    transaction_index: 0.into(),
+                    gas_used: 21_000.into(),
+                    effective_gas_price: 30.into(),
+                    effective_tip: 20.into(),
+                    tip: 420_000.into(),
+                },
+                // the tip is capped by the max fee
+                TxFees {
+                    transaction_hash: txs[1].hash(),
+                    transaction_index: 1.into(),
+                    gas_used: 50_000.into(),
+                    effective_gas_price: 12.into(),
+                    effective_tip: 2.into(),
+                    tip: 100_000.into(),
+                },
+            ]
+        );
+        assert_eq!(fees.total_tips, 520_000.into());
+        assert!(db
+            .block_fee_breakdown(BlockNumber::Number(2.into()))
+            .is_err());
+        Ok(())
+    }
+}
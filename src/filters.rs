@@ -0,0 +1,443 @@
+use anyhow::{format_err, Result};
+use ethers::{
+    providers::FilterKind,
+    types::{
+        Block, BlockNumber, Filter, FilterBlockOption, Log, Transaction, TransactionReceipt, H256,
+        U256,
+    },
+};
+use mdbx::EnvironmentKind;
+use serde::Serialize;
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet},
+    sync::{Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    builder::PrunedData,
+    client::{Client, BLOCK_TABLES},
+    types::{BlockNum, HeaderKey},
+    utils::{BlockAssembler, FullTxs},
+};
+
+/// How long a filter may go unpolled before it's uninstalled, as in geth.
+pub const FILTER_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// A filter installed with `Client::new_filter`. `last_polled` is the head block
+/// as of the previous poll, so each poll covers the blocks after it. Pending
+/// transaction filters instead keep the pool contents seen by the previous poll.
+#[derive(Debug, Clone)]
+enum InstalledFilter {
+    Logs { filter: Filter, last_polled: u64 },
+    NewBlocks { last_polled: u64 },
+    FullBlocks { last_polled: u64, receipts: bool },
+    PendingTransactions { seen: HashSet<H256> },
+}
+
+impl InstalledFilter {
+    fn last_polled_mut(&mut self) -> Option<&mut u64> {
+        match self {
+            Self::Logs { last_polled, .. }
+            | Self::NewBlocks { last_polled }
+            | Self::FullBlocks { last_polled, .. } => Some(last_polled),
+            Self::PendingTransactions { .. } => None,
+        }
+    }
+}
+
+/// A new canonical block, as reported by a filter installed with
+/// `Client::new_full_block_filter`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NewBlock {
+    pub block: Block<Transaction>,
+    /// The block's receipts, if the filter asked for them. `None` if it didn't
+    /// or the block's receipts are not stored in the db.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub receipts: Option<Vec<TransactionReceipt>>,
+}
+
+/// The changes seen by a filter since it was last polled.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FilterChanges {
+    /// New logs matching a log filter.
+    Logs(Vec<Log>),
+    /// Hashes of new canonical blocks or pending transactions.
+    Hashes(Vec<H256>),
+    /// New canonical blocks, for full block filters.
+    Blocks(Vec<NewBlock>),
+}
+
+#[derive(Debug)]
+struct FilterState {
+    /// Each filter and when it was installed or last polled.
+    installed: HashMap<U256, (InstalledFilter, Instant)>,
+    timeout: Duration,
+}
+
+/// The filters installed on a `Client`, shared by the client and its clones.
+/// Filters not polled for `FILTER_TIMEOUT` are uninstalled, so clients which
+/// go away without uninstalling theirs don't leak them.
+#[derive(Debug)]
+pub struct Filters(Mutex<FilterState>);
+
+impl Default for Filters {
+    fn default() -> Self {
+        Self::with_timeout(FILTER_TIMEOUT)
+    }
+}
+
+impl Filters {
+    /// A registry whose filters expire after going unpolled for `timeout`.
+    pub fn with_timeout(timeout: Duration) -> Self {
+        Self(Mutex::new(FilterState {
+            installed: HashMap::new(),
+            timeout,
+        }))
+    }
+
+    /// Locks the registry, first dropping the filters which have expired.
+    fn state(&self) -> MutexGuard<'_, FilterState> {
+        let mut state = self.0.lock().unwrap();
+        let timeout = state.timeout;
+        state
+            .installed
+            .retain(|_, (_, polled)| polled.elapsed() < timeout);
+        state
+    }
+
+    /// Returns the filter `id`, marking it as polled now.
+    fn poll(state: &mut FilterState, id: U256) -> Result<&mut InstalledFilter> {
+        let (filter, polled) = state
+            .installed
+            .get_mut(&id)
+            .ok_or_else(|| format_err!("filter not found: {}", id))?;
+        *polled = Instant::now();
+        Ok(filter)
+    }
+
+    /// Installs `filter` under a random id. Ids are random rather than counted
+    /// so they don't collide with those of other clients, or of the node a
+    /// `DbMiddleware` delegates filters to.
+    fn install(&self, filter: InstalledFilter) -> U256 {
+        let mut state = self.state();
+        loop {
+            let id = U256::from(ethers::core::rand::random::<[u8; 32]>());
+            if let Entry::Vacant(entry) = state.installed.entry(id) {
+                entry.insert((filter, Instant::now()));
+                return id;
+            }
+        }
+    }
+
+    fn uninstall(&self, id: U256) -> bool {
+        self.state().installed.remove(&id).is_some()
+    }
+
+    fn contains(&self, id: U256) -> bool {
+        self.state().installed.contains_key(&id)
+    }
+
+    fn is_pending(&self, id: U256) -> Result<bool> {
+        match self.state().installed.get(&id) {
+            Some((filter, _)) => Ok(matches!(
+                filter,
+                InstalledFilter::PendingTransactions { .. }
+            )),
+            None => Err(format_err!("filter not found: {}", id)),
+        }
+    }
+
+    /// Moves the filter's poll position to `head`, returning the filter as it
+    /// was before. Concurrent polls of one filter therefore see disjoint blocks.
+    fn advance(&self, id: U256, head: u64) -> Result<InstalledFilter> {
+        let mut state = self.state();
+        let filter = Self::poll(&mut state, id)?;
+        let prev = filter.clone();
+        if let Some(last_polled) = filter.last_polled_mut() {
+            *last_polled = head.max(*last_polled);
+        }
+        Ok(prev)
+    }
+
+    /// Records `pool` as the pool contents seen by a pending transaction filter,
+    /// returning the hashes in `pool` which the previous poll didn't see.
+    fn replace_seen(&self, id: U256, pool: Vec<H256>) -> Result<Vec<H256>> {
+        let mut state = self.state();
+        match Self::poll(&mut state, id) {
+            Ok(InstalledFilter::PendingTransactions { seen }) => {
+                let new = pool
+                    .iter()
+                    .filter(|h| !seen.contains(*h))
+                    .copied()
+                    .collect();
+                *seen = pool.into_iter().collect();
+                Ok(new)
+            }
+            _ => Err(format_err!("pending transaction filter not found: {}", id)),
+        }
+    }
+}
+
+impl<E: EnvironmentKind> Client<E> {
+    /// Installs a filter and returns its id. Changes are only reported for
+    /// blocks after the current head, or for transactions not already in the
+    /// pool. Pending transaction filters require the client's txpool db.
+    pub fn new_filter(&self, kind: FilterKind<'_>) -> Result<U256> {
+        if let FilterKind::PendingTransactions = kind {
+            let seen = self.pending_transaction_hashes()?.into_iter().collect();
+            return Ok(self
+                .filters
+                .install(InstalledFilter::PendingTransactions { seen }));
+        }
+        let last_polled = self.get_block_number()?.as_u64();
+        let filter = match kind {
+            FilterKind::Logs(filter) => {
+                self.ensure_unpruned("new_filter", PrunedData::Receipts)?;
+                anyhow::ensure!(
+                    matches!(filter.block_option, FilterBlockOption::Range { .. }),
+                    "log filters cannot select a block hash"
+                );
+                InstalledFilter::Logs {
+                    filter: filter.clone(),
+                    last_polled,
+                }
+            }
+            FilterKind::NewBlocks => InstalledFilter::NewBlocks { last_polled },
+            FilterKind::PendingTransactions => unreachable!(),
+        };
+        Ok(self.filters.install(filter))
+    }
+
+    /// Installs a filter which reports each new canonical block in full, with
+    /// its transactions and, if `receipts` is set, its receipts. Each poll
+    /// reads all of its blocks in one transaction, so indexers get the blocks
+    /// without a follow-up call per hash.
+    ///
+    /// ```ignore
+    /// let id = client.new_full_block_filter(true)?;
+    /// if let FilterChanges::Blocks(blocks) = client.get_filter_changes(id)? {
+    ///     for new in blocks {
+    ///         index(&new.block, new.receipts.as_deref());
+    ///     }
+    /// }
+    /// ```
+    pub fn new_full_block_filter(&self, receipts: bool) -> Result<U256> {
+        if receipts {
+            self.ensure_unpruned("new_full_block_filter", PrunedData::Receipts)?;
+        }
+        let last_polled = self.get_block_number()?.as_u64();
+        Ok(self.filters.install(InstalledFilter::FullBlocks {
+            last_polled,
+            receipts,
+        }))
+    }
+
+    /// Removes the filter `id`. Returns false if no such filter was installed.
+    pub fn uninstall_filter(&self, id: U256) -> bool {
+        self.filters.uninstall(id)
+    }
+
+    /// Returns true if `id` was installed by `new_filter` and not yet removed.
+    pub fn has_filter(&self, id: U256) -> bool {
+        self.filters.contains(id)
+    }
+
+    /// Returns the changes seen by the filter `id` since it was installed or
+    /// last polled.
+    pub fn get_filter_changes(&self, id: U256) -> Result<FilterChanges> {
+        if self.filters.is_pending(id)? {
+            let pool = self.pending_transaction_hashes()?;
+            return Ok(FilterChanges::Hashes(self.filters.replace_seen(id, pool)?));
+        }
+        let head = self.get_block_number()?.as_u64();
+        match self.filters.advance(id, head)? {
+            InstalledFilter::Logs {
+                filter,
+                last_polled,
+            } => {
+                let (from, to) = match filter.block_option {
+                    FilterBlockOption::Range {
+                        from_block,
+                        to_block,
+                    } => (from_block, to_block),
+                    FilterBlockOption::AtBlockHash(_) => unreachable!(),
+                };
+                let from = match from {
+                    Some(BlockNumber::Number(n)) => n.as_u64().max(last_polled + 1),
+                    _ => last_polled + 1,
+                };
+                let to = match to {
+                    Some(BlockNumber::Number(n)) => n.as_u64().min(head),
+                    _ => head,
+                };
+                if from > to {
+                    return Ok(FilterChanges::Logs(vec![]));
+                }
+                let logs = self.get_logs(&filter.from_block(from).to_block(to))?;
+                Ok(FilterChanges::Logs(logs))
+            }
+            InstalledFilter::NewBlocks { last_polled } => {
+                let mut dbtx = self.reader()?;
+                let hashes = (last_polled + 1..=head)
+                    .map(|n| dbtx.read_canonical_hash(BlockNum(n)))
+                    .collect::<Result<_>>()?;
+                Ok(FilterChanges::Hashes(hashes))
+            }
+            InstalledFilter::FullBlocks {
+                last_polled,
+                receipts,
+            } => {
+                let _slow = self.slow_read("get_filter_changes", BLOCK_TABLES, || {
+                    format!("{} blocks {}..={}", id, last_polled + 1, head)
+                });
+                let mut dbtx = self.reader()?;
+                let assembler = BlockAssembler::<_, FullTxs>::new(self);
+                let mut blocks = vec![];
+                for n in last_polled + 1..=head {
+                    let num = BlockNum(n);
+                    let key = HeaderKey::new(num, dbtx.read_canonical_hash(num)?);
+                    let block = assembler.assemble(&mut dbtx, key)?;
+                    let receipts = if receipts {
+                        self.read_receipts_cached(&mut dbtx, key)?
+                    } else {
+                        None
+                    };
+                    blocks.push(NewBlock { block, receipts });
+                }
+                Ok(FilterChanges::Blocks(blocks))
+            }
+            InstalledFilter::PendingTransactions { .. } => unreachable!(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::Receipt,
+        test::{chain::ChainBuilder, rand::Rand, TMP_DIR},
+    };
+    use akula::models::MessageWithSignature;
+    use rand::thread_rng;
+
+    #[test]
+    fn test_filter_registry() -> Result<()> {
+        let filters = Filters::default();
+        let id = filters.install(InstalledFilter::NewBlocks { last_polled: 5 });
+        assert!(filters.contains(id));
+        // ids aren't counted per registry, so separate ones don't collide
+        let other = Filters::default().install(InstalledFilter::NewBlocks { last_polled: 5 });
+        assert_ne!(other, id);
+
+        let prev = filters.advance(id, 8)?;
+        assert!(matches!(
+            prev,
+            InstalledFilter::NewBlocks { last_polled: 5 }
+        ));
+        // the head moving backwards doesn't replay blocks
+        let prev = filters.advance(id, 7)?;
+        assert!(matches!(
+            prev,
+            InstalledFilter::NewBlocks { last_polled: 8 }
+        ));
+
+        assert!(filters.uninstall(id));
+        assert!(!filters.uninstall(id));
+        assert!(filters.advance(id, 9).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter_timeout() -> Result<()> {
+        let timeout = Duration::from_millis(200);
+        let filters = Filters::with_timeout(timeout);
+        let idle = filters.install(InstalledFilter::NewBlocks { last_polled: 5 });
+        let polled = filters.install(InstalledFilter::NewBlocks { last_polled: 5 });
+
+        std::thread::sleep(timeout * 3 / 5);
+        filters.advance(polled, 6)?;
+        std::thread::sleep(timeout * 3 / 5);
+        // each poll restarts the filter's timeout
+        assert!(!filters.contains(idle));
+        assert!(filters.advance(idle, 7).is_err());
+        assert!(filters.contains(polled));
+        std::thread::sleep(timeout);
+        assert!(!filters.uninstall(polled));
+        Ok(())
+    }
+
+    #[test]
+    fn test_pending_filter() -> Result<()> {
+        let filters = Filters::default();
+        let (a, b, c) = (
+            H256::repeat_byte(1),
+            H256::repeat_byte(2),
+            H256::repeat_byte(3),
+        );
+        let seen = [a].into_iter().collect();
+        let id = filters.install(InstalledFilter::PendingTransactions { seen });
+        assert!(filters.is_pending(id)?);
+
+        assert_eq!(filters.replace_seen(id, vec![a, b])?, vec![b]);
+        // a tx leaving the pool and another arriving
+        assert_eq!(filters.replace_seen(id, vec![b, c])?, vec![c]);
+        assert_eq!(filters.replace_seen(id, vec![b, c])?, vec![]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_full_block_filter() -> Result<()> {
+        let tx = MessageWithSignature::rand(&mut thread_rng());
+        let chain = ChainBuilder::new()
+            .start(10)
+            .block(|b| {
+                b.tx(tx.clone()).receipt(
+                    Receipt {
+                        status: 1,
+                        cumulative_gas_used: 21_000,
+                        ..Default::default()
+                    },
+                    vec![],
+                )
+            })
+            .block(|b| b)
+            .write(TMP_DIR.clone())?;
+        let db = Client::<mdbx::NoWriteMap>::open_new(chain.path.clone())?;
+
+        // nothing new since the filter was installed
+        let id = db.new_full_block_filter(true)?;
+        assert_eq!(db.get_filter_changes(id)?, FilterChanges::Blocks(vec![]));
+
+        // as if installed before the last two blocks
+        let id = db.filters.install(InstalledFilter::FullBlocks {
+            last_polled: 9,
+            receipts: true,
+        });
+        let blocks = match db.get_filter_changes(id)? {
+            FilterChanges::Blocks(blocks) => blocks,
+            changes => panic!("unexpected changes {:?}", changes),
+        };
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].block.hash, Some(chain.hash(0)));
+        assert_eq!(blocks[0].block.transactions[0].hash, tx.hash());
+        let receipts = blocks[0].receipts.as_ref().unwrap();
+        assert_eq!(receipts[0].transaction_hash, tx.hash());
+        assert_eq!(blocks[1].block.hash, Some(chain.hash(1)));
+
+        let id = db.filters.install(InstalledFilter::FullBlocks {
+            last_polled: 10,
+            receipts: false,
+        });
+        match db.get_filter_changes(id)? {
+            FilterChanges::Blocks(blocks) => {
+                assert_eq!(blocks.len(), 1);
+                assert_eq!(blocks[0].receipts, None);
+            }
+            changes => panic!("unexpected changes {:?}", changes),
+        }
+        Ok(())
+    }
+}
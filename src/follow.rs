@@ -0,0 +1,451 @@
+//! Scaffolding for indexers which process each new block once, in order.
+//!
+//! A `Follower` feeds the blocks after its checkpoint, along with their logs,
+//! to a `FollowHandler`, and saves the checkpoint after each block. Delivery
+//! is at least once: a block whose handler succeeded may be delivered again if
+//! the process stops before its checkpoint is saved.
+//!
+//! The checkpoint holds the last `reorg_depth` processed blocks. If the last
+//! one is no longer canonical, or isn't the parent of the next block, the
+//! follower finds the newest one which is canonical, calls
+//! `FollowHandler::rollback` with it, and continues from there.
+
+use anyhow::{format_err, Result};
+use ethers::types::{Block, Filter, Log, Transaction, H256};
+use mdbx::EnvironmentKind;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    builder::PrunedData,
+    client::{get_header_key, Client},
+    logs::LogStream,
+    reader::Reader,
+    types::BlockNum,
+    utils::{BlockAssembler, FullTxs},
+};
+
+/// The default number of processed blocks remembered for reorg handling.
+pub const DEFAULT_REORG_DEPTH: usize = 128;
+
+/// A processed block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Cursor {
+    pub number: u64,
+    pub hash: H256,
+}
+
+/// The most recently processed blocks, oldest first.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub recent: Vec<Cursor>,
+}
+
+impl Checkpoint {
+    /// The last processed block.
+    pub fn head(&self) -> Option<Cursor> {
+        self.recent.last().copied()
+    }
+}
+
+/// Durable storage for a follower's checkpoint.
+pub trait CheckpointStore {
+    fn load(&mut self) -> Result<Option<Checkpoint>>;
+    fn save(&mut self, checkpoint: &Checkpoint) -> Result<()>;
+}
+
+/// Keeps the checkpoint in memory, for tests and for followers which rebuild
+/// their state on startup.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStore(pub Option<Checkpoint>);
+
+impl CheckpointStore for MemoryStore {
+    fn load(&mut self) -> Result<Option<Checkpoint>> {
+        Ok(self.0.clone())
+    }
+
+    fn save(&mut self, checkpoint: &Checkpoint) -> Result<()> {
+        self.0 = Some(checkpoint.clone());
+        Ok(())
+    }
+}
+
+/// Keeps the checkpoint in a JSON file. Saves write and fsync a temporary
+/// file, rename it over the old one and fsync the directory, so a crash never
+/// leaves a torn checkpoint and a save is on disk once it returns.
+#[derive(Debug, Clone)]
+pub struct FileStore {
+    path: PathBuf,
+}
+
+impl FileStore {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self { path: path.into() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl CheckpointStore for FileStore {
+    fn load(&mut self) -> Result<Option<Checkpoint>> {
+        match fs::read(&self.path) {
+            Ok(buf) => Ok(Some(serde_json::from_slice(&buf)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn save(&mut self, checkpoint: &Checkpoint) -> Result<()> {
+        let tmp = self.path.with_extension("tmp");
+        let mut file = File::create(&tmp)?;
+        file.write_all(&serde_json::to_vec(checkpoint)?)?;
+        // the contents must be on disk before the rename can expose them
+        file.sync_all()?;
+        fs::rename(&tmp, &self.path)?;
+        sync_parent(&self.path)
+    }
+}
+
+/// Flushes the directory entry of `path`, making a rename into it durable.
+#[cfg(unix)]
+fn sync_parent(path: &Path) -> Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+/// Directories can't be opened as files off unix, where the rename is left to
+/// the filesystem's own ordering.
+#[cfg(not(unix))]
+fn sync_parent(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+/// The callbacks of a `Follower`.
+pub trait FollowHandler {
+    /// Processes a new canonical block and its logs matching the follower's
+    /// filter.
+    fn block(&mut self, block: &Block<Transaction>, logs: &[Log]) -> Result<()>;
+
+    /// Undoes the processing of every block after the common ancestor, which
+    /// have been reorged out.
+    fn rollback(&mut self, _ancestor: Cursor) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// What `Follower::poll` did.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FollowReport {
+    /// The number of blocks processed.
+    pub blocks: u64,
+    /// The common ancestor rolled back to, if there was a reorg.
+    pub rolled_back_to: Option<Cursor>,
+}
+
+/// Follows the canonical chain from a durable checkpoint.
+///
+/// ```ignore
+/// let mut follower = Follower::new(FileStore::new("indexer.json"))
+///     .start(15_000_000)
+///     .logs(Filter::new().address(token));
+/// loop {
+///     follower.poll(&client, &mut indexer)?;
+///     std::thread::sleep(Duration::from_secs(1));
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Follower<S> {
+    store: S,
+    start: u64,
+    reorg_depth: usize,
+    logs: Option<Filter>,
+}
+
+impl<S: CheckpointStore> Follower<S> {
+    pub fn new(store: S) -> Self {
+        Self {
+            store,
+            start: 0,
+            reorg_depth: DEFAULT_REORG_DEPTH,
+            logs: None,
+        }
+    }
+
+    /// The first block processed when the store has no checkpoint.
+    pub fn start(mut self, num: u64) -> Self {
+        self.start = num;
+        self
+    }
+
+    /// The number of processed blocks remembered. Deeper reorgs are an error.
+    pub fn reorg_depth(mut self, depth: usize) -> Self {
+        self.reorg_depth = depth.max(1);
+        self
+    }
+
+    /// Delivers the logs matching `filter` with each block. Its block range is
+    /// ignored. Without a filter, no logs are read.
+    pub fn logs(mut self, filter: Filter) -> Self {
+        self.logs = Some(filter);
+        self
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// Processes every block up to the current head of `client`, rolling back
+    /// first if the last processed block has been reorged out.
+    pub fn poll<E, H>(&mut self, client: &Client<E>, handler: &mut H) -> Result<FollowReport>
+    where
+        E: EnvironmentKind,
+        H: FollowHandler,
+    {
+        let mut report = FollowReport::default();
+        let mut checkpoint = self.store.load()?.unwrap_or_default();
+        if self.logs.is_some() {
+            client.ensure_unpruned("Follower::poll", PrunedData::Receipts)?;
+        }
+
+        let mut dbtx = client.reader()?;
+        if !is_canonical(&mut dbtx, checkpoint.head())? {
+            let ancestor = self.rewind(&mut dbtx, &mut checkpoint, handler)?;
+            report.rolled_back_to = Some(ancestor);
+        }
+        let head = *dbtx.read_head_block_number()?;
+        drop(dbtx);
+        self.follow(client, &mut checkpoint, head, handler, &mut report)?;
+        Ok(report)
+    }
+
+    /// Processes the blocks after the checkpoint up to `head`. Each block is
+    /// read along with its logs in one read transaction, and only delivered if
+    /// its parent is the last processed block. Otherwise the chain was reorged
+    /// since the checkpoint was checked, and the follower rolls back first.
+    fn follow<E, H>(
+        &mut self,
+        client: &Client<E>,
+        checkpoint: &mut Checkpoint,
+        head: u64,
+        handler: &mut H,
+        report: &mut FollowReport,
+    ) -> Result<()>
+    where
+        E: EnvironmentKind,
+        H: FollowHandler,
+    {
+        let assembler = BlockAssembler::<_, FullTxs>::new(client);
+        let mut num = checkpoint.head().map_or(self.start, |c| c.number + 1);
+        while num <= head {
+            let mut dbtx = client.reader()?;
+            let key = get_header_key(&mut dbtx, num)?;
+            let block = assembler.assemble(&mut dbtx, key)?;
+            if let Some(parent) = checkpoint.head() {
+                if block.parent_hash != parent.hash {
+                    let ancestor = self.rewind(&mut dbtx, checkpoint, handler)?;
+                    report.rolled_back_to = Some(ancestor);
+                    num = ancestor.number + 1;
+                    continue;
+                }
+            }
+            let logs: Vec<Log> = match &self.logs {
+                Some(filter) => {
+                    LogStream::new(dbtx, &filter.clone().from_block(num).to_block(num))?
+                        .collect::<Result<_>>()?
+                }
+                None => vec![],
+            };
+            handler.block(&block, &logs)?;
+
+            checkpoint.recent.push(Cursor {
+                number: num,
+                hash: key.hash,
+            });
+            if checkpoint.recent.len() > self.reorg_depth {
+                let excess = checkpoint.recent.len() - self.reorg_depth;
+                checkpoint.recent.drain(..excess);
+            }
+            self.store.save(checkpoint)?;
+            report.blocks += 1;
+            num += 1;
+        }
+        Ok(())
+    }
+
+    /// Drops the last processed block, which has been reorged out, and any
+    /// before it which aren't canonical in `dbtx`, then rolls the handler back
+    /// to the newest one left.
+    fn rewind<E, H>(
+        &mut self,
+        dbtx: &mut Reader<'_, mdbx::RO, E>,
+        checkpoint: &mut Checkpoint,
+        handler: &mut H,
+    ) -> Result<Cursor>
+    where
+        E: EnvironmentKind,
+        H: FollowHandler,
+    {
+        let ancestor = loop {
+            checkpoint.recent.pop();
+            match checkpoint.head() {
+                Some(cursor) if is_canonical(dbtx, Some(cursor))? => break cursor,
+                Some(_) => continue,
+                None => {
+                    return Err(format_err!(
+                        "reorg deeper than the {} remembered blocks",
+                        self.reorg_depth
+                    ))
+                }
+            }
+        };
+        handler.rollback(ancestor)?;
+        self.store.save(checkpoint)?;
+        Ok(ancestor)
+    }
+}
+
+/// Returns whether `cursor` is still on the canonical chain. No cursor is
+/// trivially canonical.
+fn is_canonical<E: EnvironmentKind>(
+    dbtx: &mut Reader<'_, mdbx::RO, E>,
+    cursor: Option<Cursor>,
+) -> Result<bool> {
+    let cursor = match cursor {
+        Some(cursor) => cursor,
+        None => return Ok(true),
+    };
+    if cursor.number > *dbtx.read_head_block_number()? {
+        return Ok(false);
+    }
+    Ok(dbtx.find_canonical_hash(BlockNum(cursor.number))? == Some(cursor.hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::{chain::ChainBuilder, TMP_DIR};
+
+    #[derive(Default)]
+    struct Recorder {
+        blocks: Vec<u64>,
+        rollbacks: Vec<u64>,
+    }
+
+    impl FollowHandler for Recorder {
+        fn block(&mut self, block: &Block<Transaction>, _logs: &[Log]) -> Result<()> {
+            self.blocks.push(block.number.unwrap_or_default().as_u64());
+            Ok(())
+        }
+
+        fn rollback(&mut self, ancestor: Cursor) -> Result<()> {
+            self.rollbacks.push(ancestor.number);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_follower() -> Result<()> {
+        let chain = ChainBuilder::new()
+            .start(100)
+            .block(|b| b)
+            .block(|b| b)
+            .block(|b| b)
+            .write(TMP_DIR.clone())?;
+        let db = Client::<mdbx::NoWriteMap>::open_new(chain.path.clone())?;
+
+        let mut follower = Follower::new(MemoryStore::default())
+            .start(100)
+            .logs(Filter::new());
+        let mut rec = Recorder::default();
+        assert_eq!(follower.poll(&db, &mut rec)?.blocks, 3);
+        assert_eq!(rec.blocks, vec![100, 101, 102]);
+        assert_eq!(
+            follower.store().0.as_ref().and_then(Checkpoint::head),
+            Some(Cursor {
+                number: 102,
+                hash: chain.hash(2),
+            })
+        );
+        assert_eq!(follower.poll(&db, &mut rec)?, FollowReport::default());
+
+        // pretend the last two blocks processed were on a fork
+        let checkpoint = follower.store.0.as_mut().unwrap();
+        for cursor in &mut checkpoint.recent[1..] {
+            cursor.hash = H256::repeat_byte(0xff);
+        }
+        let report = follower.poll(&db, &mut rec)?;
+        assert_eq!(report.blocks, 2);
+        assert_eq!(report.rolled_back_to.map(|c| c.number), Some(100));
+        assert_eq!(rec.rollbacks, vec![100]);
+        assert_eq!(rec.blocks, vec![100, 101, 102, 101, 102]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_follower_parent_mismatch() -> Result<()> {
+        let chain = ChainBuilder::new()
+            .start(100)
+            .block(|b| b)
+            .block(|b| b)
+            .block(|b| b)
+            .write(TMP_DIR.clone())?;
+        let db = Client::<mdbx::NoWriteMap>::open_new(chain.path.clone())?;
+
+        // a reorg after the checkpoint was checked leaves its head off the
+        // parent of the next block
+        let mut checkpoint = Checkpoint {
+            recent: vec![
+                Cursor {
+                    number: 100,
+                    hash: chain.hash(0),
+                },
+                Cursor {
+                    number: 101,
+                    hash: H256::repeat_byte(0xff),
+                },
+            ],
+        };
+        let mut follower = Follower::new(MemoryStore::default());
+        let (mut rec, mut report) = (Recorder::default(), FollowReport::default());
+        follower.follow(&db, &mut checkpoint, 102, &mut rec, &mut report)?;
+        assert_eq!(report.rolled_back_to.map(|c| c.number), Some(100));
+        assert_eq!(rec.rollbacks, vec![100]);
+        assert_eq!(rec.blocks, vec![101, 102]);
+        assert_eq!(checkpoint.head().map(|c| c.hash), Some(chain.hash(2)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_store() -> Result<()> {
+        let dir = tempfile::tempdir_in(TMP_DIR.clone())?;
+        let mut store = FileStore::new(dir.path().join("checkpoint.json"));
+        assert_eq!(store.load()?, None);
+
+        let cursor = |number| Cursor {
+            number,
+            hash: H256::repeat_byte(number as u8),
+        };
+        let mut checkpoint = Checkpoint {
+            recent: vec![cursor(1)],
+        };
+        store.save(&checkpoint)?;
+        assert_eq!(store.load()?, Some(checkpoint.clone()));
+
+        // a save replaces the checkpoint and leaves no temporary file
+        checkpoint.recent.push(cursor(2));
+        store.save(&checkpoint)?;
+        assert_eq!(store.load()?, Some(checkpoint));
+        assert_eq!(fs::read_dir(dir.path())?.count(), 1);
+        Ok(())
+    }
+}
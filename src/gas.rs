@@ -0,0 +1,211 @@
+use anyhow::Result;
+use ethers::types::{Address, U256, U64};
+use mdbx::EnvironmentKind;
+use serde::Serialize;
+use std::{collections::HashMap, ops::RangeBounds};
+
+use crate::{
+    builder::PrunedData,
+    client::Client,
+    tables,
+    types::{BlockNum, HeaderKey},
+};
+
+// The tables read by `gas_profile`, as reported in the slow read log
+const GAS_TABLES: &[&str] = &[
+    tables::CanonicalHeader::const_db_name(),
+    tables::BlockBody::const_db_name(),
+    tables::BlockTransaction::const_db_name(),
+    tables::Receipt::const_db_name(),
+    tables::CallTraceSet::const_db_name(),
+];
+
+/// The gas used by the transactions sent to one address.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GasConsumer {
+    /// The transactions' `to` address, or `None` for contract creations.
+    pub address: Option<Address>,
+    pub gas_used: U256,
+    pub tx_count: u64,
+    /// The number of blocks in which the address received a call, internal
+    /// calls included, according to the CallTraceSet. Zero for creations.
+    pub call_blocks: u64,
+}
+
+/// The top gas consumers over a range of blocks.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GasProfile {
+    pub from: U64,
+    pub to: U64,
+    /// The gas used by every transaction in the range, not just the top ones.
+    pub total_gas_used: U256,
+    /// The consumers using the most gas, most first.
+    pub consumers: Vec<GasConsumer>,
+}
+
+impl<E: EnvironmentKind> Client<E> {
+    /// Attributes the gas used by each transaction in `range` to its `to`
+    /// address and returns the `top` consumers. The gas used by a transaction
+    /// is the difference in cumulative gas used between its receipt and the
+    /// one before, so the blocks' receipts must be in the db. An unbounded end
+    /// is the current head.
+    ///
+    /// Gas is attributed to the called address only, not to the contracts it
+    /// calls in turn, whose share the CallTraceSet doesn't record.
+    ///
+    /// ```ignore
+    /// let head = client.get_block_number()?.as_u64();
+    /// for c in client.gas_profile(head - 7200..=head, 10)?.consumers {
+    ///     println!("{:?} {} gas in {} txs", c.address, c.gas_used, c.tx_count);
+    /// }
+    /// ```
+    pub fn gas_profile<R: RangeBounds<u64>>(&self, range: R, top: usize) -> Result<GasProfile> {
+        self.ensure_unpruned("gas_profile", PrunedData::Receipts)?;
+        self.ensure_unpruned("gas_profile", PrunedData::CallTraces)?;
+        let (from, to) = match self.resolve_range(range)? {
+            Some(bounds) => bounds,
+            None => return Ok(GasProfile::default()),
+        };
+
+        let _permit = self.admit()?;
+        let _slow = self.slow_read("gas_profile", GAS_TABLES, || {
+            format!("{}..={} top {}", from, to, top)
+        });
+        let mut dbtx = self.reader()?;
+        let mut profile = GasProfile {
+            from: from.into(),
+            to: to.into(),
+            ..Default::default()
+        };
+        let mut consumers = HashMap::<Option<Address>, GasConsumer>::new();
+        let mut call_blocks = HashMap::<Address, u64>::new();
+
+        for num in from..=to {
+            let num = BlockNum(num);
+            let key = HeaderKey::new(num, dbtx.read_canonical_hash(num)?);
+            for tx in self.read_txs_gas_used(&mut dbtx, key)? {
+                let address = tx.msg.action().into_address();
+                let consumer = consumers.entry(address).or_insert_with(|| GasConsumer {
+                    address,
+                    ..Default::default()
+                });
+                consumer.gas_used += U256::from(tx.gas_used);
+                consumer.tx_count += 1;
+                profile.total_gas_used += U256::from(tx.gas_used);
+            }
+
+            for trace in dbtx.read_call_traces(num)? {
+                if trace.to {
+                    *call_blocks.entry(trace.address).or_default() += 1;
+                }
+            }
+        }
+
+        let mut consumers = consumers.into_values().collect::<Vec<_>>();
+        consumers.sort_unstable_by(|a, b| {
+            b.gas_used
+                .cmp(&a.gas_used)
+                .then_with(|| a.address.cmp(&b.address))
+        });
+        consumers.truncate(top);
+        for consumer in &mut consumers {
+            if let Some(address) = consumer.address {
+                consumer.call_blocks = call_blocks.get(&address).copied().unwrap_or_default();
+            }
+        }
+        profile.consumers = consumers;
+        Ok(profile)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::{CallTrace, Receipt},
+        test::{
+            chain::ChainBuilder,
+            rand::{rand_legacy, sign},
+            TMP_DIR,
+        },
+    };
+    use akula::models::{Message, MessageWithSignature, TransactionAction};
+    use ethers::core::k256::ecdsa::SigningKey;
+    use rand::thread_rng;
+
+    #[test]
+    fn test_gas_profile() -> Result<()> {
+        let mut rng = thread_rng();
+        let (a, b) = (Address::repeat_byte(0xaa), Address::repeat_byte(0xbb));
+        let mut tx = |action| {
+            let mut message = rand_legacy(&mut rng);
+            if let Message::Legacy { action: to, .. } = &mut message {
+                *to = action;
+            }
+            let signature = sign(SigningKey::random(&mut rng), message.hash().as_bytes());
+            MessageWithSignature { message, signature }
+        };
+        let txs = vec![
+            tx(TransactionAction::Call(a)),
+            tx(TransactionAction::Call(b)),
+            tx(TransactionAction::Create),
+            tx(TransactionAction::Call(a)),
+        ];
+        let receipt = |cumulative_gas_used| Receipt {
+            status: 1,
+            cumulative_gas_used,
+            ..Default::default()
+        };
+        let trace = |address| CallTrace {
+            address,
+            from: false,
+            to: true,
+        };
+        let chain = ChainBuilder::new()
+            .block(|blk| {
+                blk.txs(txs[..3].to_vec())
+                    .receipt(receipt(21_000), vec![])
+                    .receipt(receipt(71_000), vec![])
+                    .receipt(receipt(171_000), vec![])
+                    .call_trace(trace(a))
+                    .call_trace(trace(b))
+            })
+            .block(|blk| {
+                blk.txs(txs[3..].to_vec())
+                    .receipt(receipt(40_000), vec![])
+                    .call_trace(trace(a))
+            })
+            .write(TMP_DIR.clone())?;
+        let db = Client::<mdbx::NoWriteMap>::open_new(chain.path.clone())?;
+
+        let profile = db.gas_profile(1.., 2)?;
+        assert_eq!(profile.total_gas_used, 211_000.into());
+        assert_eq!(
+            profile.consumers,
+            vec![
+                GasConsumer {
+                    address: None,
+                    gas_used: 100_000.into(),
+                    tx_count: 1,
+                    call_blocks: 0,
+                },
+                GasConsumer {
+                    address: Some(a),
+                    gas_used: 61_000.into(),
+                    tx_count: 2,
+                    call_blocks: 2,
+                },
+            ]
+        );
+
+        let profile = db.gas_profile(1..=1, 10)?;
+        assert_eq!(profile.total_gas_used, 171_000.into());
+        assert_eq!(profile.consumers.len(), 3);
+        assert_eq!(profile.consumers[1].address, Some(b));
+        assert_eq!(profile.consumers[1].call_blocks, 1);
+        assert_eq!(profile.consumers[2].call_blocks, 1);
+        Ok(())
+    }
+}
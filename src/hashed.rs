@@ -0,0 +1,151 @@
+//! Translation between Erigon's plain and hashed state keys.
+//!
+//! PlainState is keyed by address, and storage by address ++ incarnation with
+//! each value prefixed by its slot. HashedAccount and HashedStorage hold the
+//! same state keyed by `keccak(address)` and `keccak(slot)`, in the order of
+//! the state trie. Hashing is one-way and Erigon doesn't keep preimages, so
+//! going back from a hashed key takes the plain keys it may have come from;
+//! see `PreimageIndex`.
+
+use anyhow::{format_err, Result};
+use ethers::{
+    types::{Address, H256},
+    utils::keccak256,
+};
+use std::collections::HashMap;
+
+/// The length of a HashedStorage key: `keccak(address) ++ incarnation`.
+pub const HASHED_STORAGE_KEY_LENGTH: usize = 40;
+
+/// Returns the HashedAccount key of `who`.
+pub fn hash_address(who: Address) -> H256 {
+    keccak256(who).into()
+}
+
+/// Returns the HashedStorage key of `slot`, which prefixes its value.
+pub fn hash_slot(slot: H256) -> H256 {
+    keccak256(slot).into()
+}
+
+/// Returns the HashedStorage key of the storage of an incarnation of an
+/// account, given the account's hashed address.
+pub fn hashed_storage_key(
+    hashed_address: H256,
+    incarnation: u64,
+) -> [u8; HASHED_STORAGE_KEY_LENGTH] {
+    let mut out = [0; HASHED_STORAGE_KEY_LENGTH];
+    out[..32].copy_from_slice(hashed_address.as_bytes());
+    out[32..].copy_from_slice(&incarnation.to_be_bytes());
+    out
+}
+
+/// Splits a HashedStorage key into the hashed address and incarnation.
+pub fn split_hashed_storage_key(key: &[u8]) -> Result<(H256, u64)> {
+    if key.len() != HASHED_STORAGE_KEY_LENGTH {
+        return Err(format_err!(
+            "hashed storage key should be {} bytes long. Got {} instead",
+            HASHED_STORAGE_KEY_LENGTH,
+            key.len()
+        ));
+    }
+    Ok((
+        H256::from_slice(&key[..32]),
+        u64::from_be_bytes(key[32..].try_into()?),
+    ))
+}
+
+/// Maps hashed addresses and slots back to the plain keys they were hashed
+/// from, for the keys inserted into it.
+///
+/// ```ignore
+/// let mut preimages = PreimageIndex::new();
+/// preimages.extend_addresses(watched);
+/// let who = preimages.address(hashed).ok_or_else(|| format_err!("unknown account"))?;
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct PreimageIndex {
+    addresses: HashMap<H256, Address>,
+    slots: HashMap<H256, H256>,
+}
+
+impl PreimageIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `who`, returning its hash.
+    pub fn insert_address(&mut self, who: Address) -> H256 {
+        let hashed = hash_address(who);
+        self.addresses.insert(hashed, who);
+        hashed
+    }
+
+    /// Adds `slot`, returning its hash.
+    pub fn insert_slot(&mut self, slot: H256) -> H256 {
+        let hashed = hash_slot(slot);
+        self.slots.insert(hashed, slot);
+        hashed
+    }
+
+    pub fn extend_addresses<I: IntoIterator<Item = Address>>(&mut self, addresses: I) {
+        for who in addresses {
+            self.insert_address(who);
+        }
+    }
+
+    pub fn extend_slots<I: IntoIterator<Item = H256>>(&mut self, slots: I) {
+        for slot in slots {
+            self.insert_slot(slot);
+        }
+    }
+
+    /// Returns the address hashed to `hashed`, if it was inserted.
+    pub fn address(&self, hashed: H256) -> Option<Address> {
+        self.addresses.get(&hashed).copied()
+    }
+
+    /// Returns the slot hashed to `hashed`, if it was inserted.
+    pub fn slot(&self, hashed: H256) -> Option<H256> {
+        self.slots.get(&hashed).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.addresses.len() + self.slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hashed_storage_key() -> Result<()> {
+        let who = Address::repeat_byte(0x11);
+        let key = hashed_storage_key(hash_address(who), 3);
+        assert_eq!(&key[..32], keccak256(who.as_bytes()));
+        assert_eq!(split_hashed_storage_key(&key)?, (hash_address(who), 3));
+        assert!(split_hashed_storage_key(&key[1..]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_preimage_index() {
+        let who = Address::repeat_byte(0x22);
+        let slot = H256::from_low_u64_be(1);
+        let mut preimages = PreimageIndex::new();
+        assert!(preimages.is_empty());
+        let hashed = preimages.insert_address(who);
+        preimages.extend_slots([slot, H256::zero()]);
+
+        assert_eq!(preimages.address(hashed), Some(who));
+        assert_eq!(preimages.slot(hash_slot(slot)), Some(slot));
+        // the two domains are kept apart
+        assert_eq!(preimages.slot(hashed), None);
+        assert_eq!(preimages.address(hash_slot(slot)), None);
+        assert_eq!(preimages.len(), 3);
+    }
+}
@@ -0,0 +1,193 @@
+use anyhow::Result;
+use ethers::types::{Address, BlockId, U256};
+use mdbx::EnvironmentKind;
+use serde::Serialize;
+use std::collections::VecDeque;
+
+use crate::{client::Client, reader::Reader, types::BlockNum};
+
+/// The number of addresses looked up per pass over PlainState.
+pub const HOLDERS_BATCH: usize = 4096;
+
+/// An address's balance in a `HoldersSnapshot`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Holding {
+    pub address: Address,
+    /// Zero for addresses without an account.
+    pub balance: U256,
+}
+
+/// The balances of a sorted stream of addresses, read from one snapshot of
+/// the db. Returned by `Client::holders_snapshot`.
+pub struct HoldersSnapshot<'env, E: EnvironmentKind, I> {
+    dbtx: Reader<'env, mdbx::RO, E>,
+    /// The past block the balances are read at, or `None` for the latest.
+    at: Option<BlockNum>,
+    addresses: I,
+    last: Option<Address>,
+    batch: Vec<Address>,
+    pending: VecDeque<Holding>,
+    done: bool,
+}
+
+impl<'env, E: EnvironmentKind, I: Iterator<Item = Address>> HoldersSnapshot<'env, E, I> {
+    /// Reads the balances of the next batch of addresses.
+    fn fill(&mut self) -> Result<()> {
+        self.batch.clear();
+        for address in self.addresses.by_ref().take(HOLDERS_BATCH) {
+            if let Some(last) = self.last {
+                anyhow::ensure!(
+                    address > last,
+                    "addresses must be strictly ascending: {:?} follows {:?}",
+                    address,
+                    last
+                );
+            }
+            self.last = Some(address);
+            self.batch.push(address);
+        }
+        let accounts = match self.at {
+            Some(num) => {
+                let mut state = self.dbtx.state_at(num);
+                self.batch
+                    .iter()
+                    .map(|address| state.account(*address).map(Some))
+                    .collect::<Result<Vec<_>>>()?
+            }
+            None => self.dbtx.read_accounts_sorted(&self.batch)?,
+        };
+        self.pending.extend(
+            self.batch
+                .iter()
+                .zip(accounts)
+                .map(|(address, acct)| Holding {
+                    address: *address,
+                    balance: acct.map(|a| a.balance).unwrap_or_default(),
+                }),
+        );
+        Ok(())
+    }
+}
+
+impl<'env, E: EnvironmentKind, I: Iterator<Item = Address>> Iterator
+    for HoldersSnapshot<'env, E, I>
+{
+    type Item = Result<Holding>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pending.is_empty() && !self.done {
+            if let Err(e) = self.fill() {
+                self.done = true;
+                return Some(Err(e));
+            }
+            self.done = self.batch.len() < HOLDERS_BATCH;
+        }
+        self.pending.pop_front().map(Ok)
+    }
+}
+
+impl<E: EnvironmentKind> Client<E> {
+    /// Streams the balances of `addresses`, which must be strictly ascending,
+    /// for holder snapshots and airdrops over millions of addresses. Batches of
+    /// addresses are merged against PlainState in key order rather than looked
+    /// up one by one, and every balance is read from the same snapshot.
+    ///
+    /// Balances at a `block` before the head are read through a `StateAt`
+    /// view, one address at a time.
+    ///
+    /// ```ignore
+    /// let mut holders: Vec<Address> = load_holders()?;
+    /// holders.sort_unstable();
+    /// holders.dedup();
+    /// for holding in client.holders_snapshot(holders, None)? {
+    ///     let holding = holding?;
+    ///     println!("{:?},{}", holding.address, holding.balance);
+    /// }
+    /// ```
+    pub fn holders_snapshot<I>(
+        &self,
+        addresses: I,
+        block: Option<BlockId>,
+    ) -> Result<HoldersSnapshot<'_, E, I::IntoIter>>
+    where
+        I: IntoIterator<Item = Address>,
+    {
+        let mut dbtx = self.reader()?;
+        let at = self.historical_block(&mut dbtx, block, "holders_snapshot")?;
+        Ok(HoldersSnapshot {
+            dbtx,
+            at,
+            addresses: addresses.into_iter(),
+            last: None,
+            batch: Vec::with_capacity(HOLDERS_BATCH),
+            pending: VecDeque::new(),
+            done: false,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::Account,
+        test::{chain::ChainBuilder, TMP_DIR},
+    };
+    use ethers::types::BlockNumber;
+
+    #[test]
+    fn test_holders_snapshot() -> Result<()> {
+        let holder = |b: u8| Address::repeat_byte(b);
+        let funded = |balance: u64| Account::new().balance(balance.into());
+        let chain = ChainBuilder::new()
+            .block(|b| b)
+            // block 2 creates 0x20
+            .block(|b| {
+                b.account_change(holder(0x20), None)
+                    .account(holder(0x20), funded(2))
+                    .account(holder(0x40), funded(4))
+                    .account(holder(0x41), funded(5))
+            })
+            .write(TMP_DIR.clone())?;
+        let db = Client::<mdbx::NoWriteMap>::open_new(chain.path.clone())?;
+
+        // more addresses than a batch, mostly without accounts
+        let mut addresses = (0..=HOLDERS_BATCH as u64)
+            .map(|n| Address::from_low_u64_be(n + 1))
+            .collect::<Vec<_>>();
+        addresses.extend([holder(0x20), holder(0x30), holder(0x40), holder(0x41)]);
+        let holdings = db
+            .holders_snapshot(addresses.clone(), None)?
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(holdings.len(), addresses.len());
+        let balances = holdings[holdings.len() - 4..]
+            .iter()
+            .map(|h| h.balance.as_u64())
+            .collect::<Vec<_>>();
+        assert_eq!(balances, vec![2, 0, 4, 5]);
+        assert!(holdings[..HOLDERS_BATCH]
+            .iter()
+            .all(|h| h.balance.is_zero()));
+
+        let head = Some(BlockNumber::Number(2.into()).into());
+        assert_eq!(
+            db.holders_snapshot(addresses.clone(), head)?.count(),
+            addresses.len()
+        );
+        let old = Some(BlockNumber::Number(1.into()).into());
+        let holdings = db
+            .holders_snapshot(vec![holder(0x20), holder(0x40)], old)?
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(holdings[0].balance, 0.into());
+        assert_eq!(holdings[1].balance, 4.into());
+
+        // out of order
+        let unsorted = vec![holder(0x40), holder(0x20)];
+        assert!(db
+            .holders_snapshot(unsorted, None)?
+            .collect::<Result<Vec<_>>>()
+            .is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,83 @@
+//! Local answers to the methods tooling probes before doing real work, so a
+//! `DbMiddleware` doesn't need a remote provider just to identify the chain.
+
+use anyhow::{format_err, Result};
+use ethers::types::U256;
+use mdbx::EnvironmentKind;
+
+use crate::{client::Client, tables};
+
+// The tables read by `chain_id`, as reported in the slow read log
+const CONFIG_TABLES: &[&str] = &[
+    tables::CanonicalHeader::const_db_name(),
+    tables::Config::const_db_name(),
+];
+
+/// The version of the `eth` wire protocol reported by `eth_protocolVersion`,
+/// the newest one spoken by the Erigon releases this crate reads.
+pub const ETH_PROTOCOL_VERSION: u64 = 66;
+
+/// The `web3_clientVersion` reported for the db.
+pub const CLIENT_VERSION: &str = concat!("ethers-db/v", env!("CARGO_PKG_VERSION"));
+
+impl<E: EnvironmentKind> Client<E> {
+    /// Returns the chain id from the chain config stored with the genesis
+    /// block, as in `eth_chainId`.
+    pub fn chain_id(&self) -> Result<U256> {
+        let _slow = self.slow_read("chain_id", CONFIG_TABLES, String::new);
+        let config = self
+            .reader()?
+            .read_chain_config()?
+            .ok_or_else(|| format_err!("no chain config in db"))?;
+        config["chainId"]
+            .as_u64()
+            .map(U256::from)
+            .ok_or_else(|| format_err!("chain config has no chainId"))
+    }
+
+    /// Returns the network id as a decimal string, as in `net_version`. Erigon
+    /// uses the chain id unless started with a different network id, which
+    /// isn't stored in the db.
+    pub fn net_version(&self) -> Result<String> {
+        Ok(self.chain_id()?.to_string())
+    }
+
+    /// Returns `ETH_PROTOCOL_VERSION`, as in `eth_protocolVersion`.
+    pub fn protocol_version(&self) -> u64 {
+        ETH_PROTOCOL_VERSION
+    }
+
+    /// Returns `CLIENT_VERSION`, as in `web3_clientVersion`.
+    pub fn client_version(&self) -> &'static str {
+        CLIENT_VERSION
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::{chain::ChainBuilder, TMP_DIR};
+
+    #[test]
+    fn test_chain_identity() -> Result<()> {
+        let chain = ChainBuilder::new()
+            .start(0)
+            .config(br#"{"chainId": 5, "londonBlock": 5062605}"#)
+            .block(|b| b)
+            .write(TMP_DIR.clone())?;
+
+        let db = Client::<mdbx::NoWriteMap>::open_new(chain.path)?;
+        assert_eq!(db.chain_id()?, 5.into());
+        assert_eq!(db.net_version()?, "5");
+        assert!(db.client_version().starts_with("ethers-db/v"));
+
+        // no config
+        let chain = ChainBuilder::new()
+            .start(0)
+            .block(|b| b)
+            .write(TMP_DIR.clone())?;
+        let db = Client::<mdbx::NoWriteMap>::open_new(chain.path)?;
+        assert!(db.chain_id().is_err());
+        Ok(())
+    }
+}
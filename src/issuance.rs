@@ -0,0 +1,307 @@
+use anyhow::{format_err, Result};
+use ethers::types::{Address, BlockId, BlockNumber, U256, U64};
+use mdbx::EnvironmentKind;
+
+use crate::{
+    client::{get_header_key, res_block_number, Client, Either},
+    convert,
+    reader::Reader,
+    tables,
+    types::BlockNum,
+};
+
+// The tables read by the issuance methods, as reported in the slow read log
+const ISSUANCE_TABLES: &[&str] = &[
+    tables::CanonicalHeader::const_db_name(),
+    tables::HeaderNumber::const_db_name(),
+    tables::Issuance::const_db_name(),
+];
+const REWARD_TABLES: &[&str] = &[
+    tables::CanonicalHeader::const_db_name(),
+    tables::HeaderNumber::const_db_name(),
+    tables::Header::const_db_name(),
+    tables::BlockBody::const_db_name(),
+    tables::Config::const_db_name(),
+    tables::Issuance::const_db_name(),
+    tables::Receipt::const_db_name(),
+];
+
+/// The engines of chains without ethash block rewards.
+const NO_REWARD_ENGINES: &[&str] = &["clique", "aura", "bor"];
+
+/// The ether issued and burnt in a block, as recorded by Erigon's Issuance
+/// stage. Issuance is the sum of the block and uncle rewards; burnt is the
+/// block's base fee times its gas used.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Issuance {
+    pub block_number: U64,
+    pub issuance: U256,
+    pub burnt: U256,
+    /// Ether issued in all blocks up to and including this one.
+    pub total_issued: U256,
+    /// Base fees burnt in all blocks up to and including this one.
+    pub total_burnt: U256,
+}
+
+/// The ether issued and burnt over the blocks `from..=to`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IssuanceRange {
+    pub from: U64,
+    pub to: U64,
+    pub issuance: U256,
+    pub burnt: U256,
+}
+
+/// The rewards and fees paid for a block, returned by
+/// `Client::get_block_rewards`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BlockRewards {
+    pub block_number: U64,
+    pub miner: Address,
+    /// The static reward of the block's era: 5 ether until Byzantium, 3 until
+    /// Constantinople, 2 until the merge and none after.
+    pub static_reward: U256,
+    /// Paid to the miner for including the block's uncles, 1/32 of the static
+    /// reward per uncle.
+    pub uncle_inclusion_reward: U256,
+    pub uncle_rewards: Vec<UncleReward>,
+    /// The priority fees paid to the miner, or the whole fees before London.
+    pub fees: U256,
+    /// The base fees burnt.
+    pub burnt: U256,
+}
+
+impl BlockRewards {
+    /// Returns everything paid to the block's miner.
+    pub fn miner_total(&self) -> U256 {
+        self.static_reward + self.uncle_inclusion_reward + self.fees
+    }
+}
+
+/// The reward paid to the miner of an uncle: the static reward times
+/// `(8 - depth) / 8`, where `depth` is how many blocks the uncle precedes the
+/// block including it by.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UncleReward {
+    pub miner: Address,
+    pub block_number: U64,
+    pub reward: U256,
+}
+
+impl IssuanceRange {
+    /// Returns the change in supply over the range, and whether it is negative.
+    pub fn net_supply_change(&self) -> (U256, bool) {
+        if self.issuance >= self.burnt {
+            (self.issuance - self.burnt, false)
+        } else {
+            (self.burnt - self.issuance, true)
+        }
+    }
+}
+
+impl<E: EnvironmentKind> Client<E> {
+    /// Returns the ether issued and burnt in the block. Errors if the Issuance
+    /// stage hasn't processed the block, which it only does when enabled.
+    pub fn get_issuance<T: Into<BlockId> + Send + Sync>(&self, block: T) -> Result<Issuance> {
+        let block = block.into();
+        let _slow = self.slow_read("get_issuance", ISSUANCE_TABLES, || format!("{:?}", block));
+        let mut dbtx = self.reader()?;
+        let num = get_header_key(&mut dbtx, block)?.num;
+        let (total_issued, total_burnt) = read_totals(&mut dbtx, num)?;
+        let (prev_issued, prev_burnt) = match num.checked_sub(1) {
+            Some(prev) => read_totals(&mut dbtx, BlockNum(prev))?,
+            None => Default::default(),
+        };
+        Ok(Issuance {
+            block_number: num.into(),
+            issuance: total_issued - prev_issued,
+            burnt: total_burnt - prev_burnt,
+            total_issued,
+            total_burnt,
+        })
+    }
+
+    /// Returns the ether issued and burnt over the blocks `from..=to`. Only the
+    /// cumulative totals at either end are read, so the cost is independent of
+    /// the size of the range.
+    pub fn get_issuance_range<T: Into<BlockNumber>>(
+        &self,
+        from: T,
+        to: T,
+    ) -> Result<IssuanceRange> {
+        let (from, to) = (from.into(), to.into());
+        let _slow = self.slow_read("get_issuance_range", ISSUANCE_TABLES, || {
+            format!("{:?}..={:?}", from, to)
+        });
+        let mut dbtx = self.reader()?;
+        let from = res_block_number(&mut dbtx, from)?;
+        let to = res_block_number(&mut dbtx, to)?;
+        anyhow::ensure!(from <= to, "invalid block range: {} > {}", from, to);
+
+        let (total_issued, total_burnt) = read_totals(&mut dbtx, to)?;
+        let (prev_issued, prev_burnt) = match from.checked_sub(1) {
+            Some(prev) => read_totals(&mut dbtx, BlockNum(prev))?,
+            None => Default::default(),
+        };
+        Ok(IssuanceRange {
+            from: from.into(),
+            to: to.into(),
+            issuance: total_issued - prev_issued,
+            burnt: total_burnt - prev_burnt,
+        })
+    }
+}
+
+impl<E: EnvironmentKind> Client<E> {
+    /// Returns the block and uncle rewards and the fees paid for the block.
+    /// The static reward follows the forks in the chain config, and is zero for
+    /// proof-of-stake blocks and chains sealed by clique, AuRa or Bor. Without
+    /// a chain config, it is derived from the block's issuance recorded by the
+    /// Issuance stage. Fees are read from the block's receipts.
+    pub fn get_block_rewards<T: Into<BlockId> + Send + Sync>(
+        &self,
+        block: T,
+    ) -> Result<BlockRewards> {
+        let block = block.into();
+        let _slow = self.slow_read("get_block_rewards", REWARD_TABLES, || {
+            format!("{:?}", block)
+        });
+        let mut dbtx = self.reader()?;
+        let key = get_header_key(&mut dbtx, block)?;
+        let header = self.read_header_cached(&mut dbtx, key)?;
+        let uncles = dbtx.read_body_for_storage(key)?.uncles;
+        let (_, tx_amt) = dbtx.read_body_tx_range(key)?;
+        // each uncle's reward is the static reward times (8 - depth) / 8
+        let eighths = uncles
+            .iter()
+            .map(|uncle| (uncle.number.0 + 8).saturating_sub(*key.num))
+            .collect::<Vec<_>>();
+
+        let static_reward = match dbtx.read_chain_config()? {
+            // proof of stake
+            Some(_) if convert::u256(header.difficulty).is_zero() => U256::zero(),
+            Some(config) => era_reward(&config, key.num),
+            None => {
+                // issuance = reward * (1 + uncles / 32 + sum(eighths) / 8)
+                let (issued, _) = read_totals(&mut dbtx, key.num)?;
+                let (prev, _) = match key.num.checked_sub(1) {
+                    Some(prev) => read_totals(&mut dbtx, BlockNum(prev))?,
+                    None => Default::default(),
+                };
+                let parts = 256 + 8 * uncles.len() as u64 + 32 * eighths.iter().sum::<u64>();
+                (issued - prev) * 256 / parts
+            }
+        };
+
+        let base_fee = header.base_fee_per_gas.map(convert::u256);
+        let fees = if tx_amt == 0 {
+            U256::zero()
+        } else {
+            let receipts = match self.get_block_receipts(*key.num)? {
+                Either::Right(receipts) => receipts,
+                Either::Left(num) => return Err(format_err!("no receipts for block {}", num)),
+            };
+            receipts.iter().fold(U256::zero(), |fees, receipt| {
+                let price = receipt.effective_gas_price.unwrap_or_default();
+                let tip = price.saturating_sub(base_fee.unwrap_or_default());
+                fees + tip * receipt.gas_used.unwrap_or_default()
+            })
+        };
+
+        Ok(BlockRewards {
+            block_number: key.num.into(),
+            miner: header.beneficiary,
+            static_reward,
+            uncle_inclusion_reward: static_reward / 32 * uncles.len(),
+            uncle_rewards: uncles
+                .iter()
+                .zip(eighths)
+                .map(|(uncle, eighths)| UncleReward {
+                    miner: uncle.beneficiary,
+                    block_number: uncle.number.0.into(),
+                    reward: static_reward * eighths / 8,
+                })
+                .collect(),
+            fees,
+            burnt: base_fee.unwrap_or_default() * header.gas_used,
+        })
+    }
+}
+
+/// Returns the static block reward in effect at block `num` per the forks in
+/// the chain config.
+fn era_reward(config: &serde_json::Value, num: BlockNum) -> U256 {
+    if NO_REWARD_ENGINES
+        .iter()
+        .any(|engine| config.get(engine).is_some())
+    {
+        return U256::zero();
+    }
+    let activated = |fork: &str| config[fork].as_u64().map_or(false, |at| *num >= at);
+    let ether = U256::exp10(18);
+    if activated("constantinopleBlock") {
+        ether * 2
+    } else if activated("byzantiumBlock") {
+        ether * 3
+    } else {
+        ether * 5
+    }
+}
+
+/// Reads the cumulative (issued, burnt) totals as of block `num`.
+fn read_totals<E: EnvironmentKind>(
+    dbtx: &mut Reader<'_, mdbx::RO, E>,
+    num: BlockNum,
+) -> Result<(U256, U256)> {
+    let missing = || format_err!("no issuance recorded for block {}", num);
+    let issued = dbtx.read_total_issued(num)?.ok_or_else(missing)?;
+    let burnt = dbtx.read_total_burnt(num)?.ok_or_else(missing)?;
+    Ok((issued, burnt))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::{chain::ChainBuilder, rand::Rand, TMP_DIR};
+    use akula::models::{BlockHeader, BlockNumber as AkBlockNumber};
+    use rand::thread_rng;
+
+    #[test]
+    fn test_get_block_rewards() -> Result<()> {
+        let mut rng = thread_rng();
+        let mut uncle = BlockHeader::rand(&mut rng);
+        uncle.number = AkBlockNumber(1);
+        let chain = ChainBuilder::new()
+            .start(0)
+            .config(br#"{"chainId": 1, "byzantiumBlock": 2, "constantinopleBlock": 4}"#)
+            .block(|b| b)
+            .block(|b| b.base_fee(7u64))
+            .block(|b| b.base_fee(7u64).ommer(uncle.clone()))
+            .write(TMP_DIR.clone())?;
+        let db = Client::<mdbx::NoWriteMap>::open_new(chain.path.clone())?;
+        let ether = U256::exp10(18);
+
+        let rewards = db.get_block_rewards(1u64)?;
+        assert_eq!(rewards.static_reward, ether * 5);
+        assert!(rewards.uncle_rewards.is_empty());
+
+        let rewards = db.get_block_rewards(2u64)?;
+        let header = &chain.blocks[2].header;
+        assert_eq!(rewards.miner, header.beneficiary);
+        assert_eq!(rewards.static_reward, ether * 3);
+        assert_eq!(rewards.uncle_inclusion_reward, ether * 3 / 32);
+        assert_eq!(
+            rewards.uncle_rewards,
+            vec![UncleReward {
+                miner: uncle.beneficiary,
+                block_number: 1.into(),
+                reward: ether * 3 * 7 / 8,
+            }]
+        );
+        // no txs
+        assert_eq!(rewards.fees, U256::zero());
+        assert_eq!(rewards.burnt, U256::from(header.gas_used) * 7);
+        assert_eq!(rewards.miner_total(), ether * 3 + ether * 3 / 32);
+        Ok(())
+    }
+}
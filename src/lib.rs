@@ -1,10 +1,93 @@
+pub mod admission;
+#[cfg(feature = "db")]
+pub mod audit;
+#[cfg(feature = "gnosis")]
+pub mod aura;
+#[cfg(feature = "db")]
+pub mod balances;
+pub mod bitmap;
+#[cfg(feature = "polygon")]
+pub mod bor;
+pub mod budget;
+#[cfg(feature = "db")]
+pub mod builder;
+pub mod cache;
+#[cfg(feature = "db")]
 pub mod client;
+pub mod coalesce;
+pub mod codec;
+#[cfg(feature = "arrow")]
+pub mod columnar;
+#[cfg(feature = "db")]
+pub mod compare;
+pub mod convert;
+#[cfg(feature = "db")]
+pub mod dump;
+#[cfg(feature = "db")]
+pub mod export;
+pub mod facade;
+#[cfg(feature = "db")]
+pub mod fees;
+#[cfg(feature = "db")]
+pub mod filters;
+#[cfg(feature = "db")]
+pub mod follow;
+#[cfg(feature = "db")]
+pub mod gas;
+pub mod hashed;
+#[cfg(feature = "db")]
+pub mod holders;
+#[cfg(feature = "db")]
+pub mod identity;
+#[cfg(feature = "db")]
+pub mod issuance;
+#[cfg(feature = "db")]
+pub mod logs;
+#[cfg(feature = "db")]
+pub mod matrix;
+#[cfg(feature = "db")]
 pub mod middleware;
+#[cfg(feature = "sqlite")]
+pub mod mirror;
+pub mod output;
+pub mod page;
+pub mod pool;
+#[cfg(feature = "db")]
+pub mod prefetch;
+#[cfg(feature = "db")]
 pub mod reader;
+pub mod readtrace;
+#[cfg(feature = "db")]
+pub mod receipts;
+#[cfg(feature = "rest")]
+pub mod rest;
+pub mod slowlog;
+pub mod snapshot;
+#[cfg(feature = "db")]
+pub mod state;
+#[cfg(feature = "db")]
+pub mod stats;
+#[cfg(feature = "db")]
+pub mod summary;
+#[cfg(feature = "db")]
+pub mod tables;
+#[cfg(feature = "token")]
+pub mod token;
+pub mod trie;
+#[cfg(feature = "db")]
+pub mod txpool;
+pub mod types;
+#[cfg(feature = "sqlite")]
+pub mod userdata;
+#[cfg(feature = "db")]
+pub mod warm;
+#[cfg(feature = "db")]
+pub mod withdrawals;
 
+mod cbor;
 mod models;
-mod tables;
+#[cfg(feature = "db")]
 mod utils;
 
-#[cfg(test)]
-mod test;
+#[cfg(any(test, feature = "test_utils"))]
+pub mod test;
@@ -0,0 +1,422 @@
+use anyhow::{format_err, Result};
+use ethers::types::{Address, BlockNumber, Filter, FilterBlockOption, Log, ValueOrArray, H256};
+use mdbx::EnvironmentKind;
+use std::{collections::VecDeque, sync::mpsc::sync_channel};
+use thiserror::Error;
+
+use crate::{
+    bitmap::{intersect, union},
+    client::{res_block_number, Client, LOG_TABLES},
+    models,
+    pool::BufPool,
+    prefetch::Sequential,
+    reader::Reader,
+    tables,
+    types::{BlockNum, HeaderKey, TxId},
+};
+
+static TX_HASH_BUFS: BufPool<H256> = BufPool::new(16, 1 << 12);
+
+/// Filters with fewer candidate blocks than this are scanned on one thread.
+const MIN_PARALLEL_LOG_BLOCKS: usize = 256;
+
+/// Returned (wrapped in an `anyhow::Error`) by log reads at the hash of a
+/// block which isn't canonical. Only canonical blocks have their logs in the db.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("block {0:?} is not canonical")]
+pub struct NotCanonical(pub H256);
+
+/// A log filter normalized for matching against the db. An empty set of
+/// addresses or topics matches anything.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LogFilter {
+    pub addresses: Vec<Address>,
+    pub topics: [Vec<H256>; 4],
+    /// The block of a filter at a block hash, which must be canonical.
+    pub block_hash: Option<H256>,
+}
+
+impl LogFilter {
+    pub fn new(filter: &Filter) -> Self {
+        let addresses = match &filter.address {
+            Some(ValueOrArray::Value(a)) => vec![*a],
+            Some(ValueOrArray::Array(a)) => a.clone(),
+            None => vec![],
+        };
+        let mut topics: [Vec<H256>; 4] = Default::default();
+        for (i, topic) in filter.topics.iter().enumerate() {
+            topics[i] = match topic {
+                Some(ValueOrArray::Value(Some(t))) => vec![*t],
+                // a null entry anywhere in the position is a wildcard
+                Some(ValueOrArray::Array(ts)) if ts.iter().all(Option::is_some) => {
+                    ts.iter().flatten().copied().collect()
+                }
+                _ => vec![],
+            };
+        }
+        Self {
+            addresses,
+            topics,
+            block_hash: filter.get_block_hash(),
+        }
+    }
+
+    pub fn is_wildcard(&self) -> bool {
+        self.addresses.is_empty() && self.topics.iter().all(Vec::is_empty)
+    }
+
+    pub fn matches(&self, log: &models::Log) -> bool {
+        if !self.addresses.is_empty() && !self.addresses.contains(&log.address) {
+            return false;
+        }
+        self.topics.iter().enumerate().all(|(i, wanted)| {
+            wanted.is_empty() || log.topics.get(i).map_or(false, |t| wanted.contains(t))
+        })
+    }
+
+    /// Returns the blocks in `from..=to` which may contain matching logs,
+    /// according to the log address and topic indices.
+    fn candidate_blocks<E: EnvironmentKind>(
+        &self,
+        dbtx: &mut Reader<'_, mdbx::RO, E>,
+        from: u32,
+        to: u32,
+    ) -> Result<Vec<u32>> {
+        if self.is_wildcard() {
+            return Ok((from..=to).collect());
+        }
+
+        let mut candidates: Option<Vec<u32>> = None;
+        if !self.addresses.is_empty() {
+            let mut blocks = vec![];
+            for address in &self.addresses {
+                let set =
+                    dbtx.read_bitmap_index(tables::LogAddressIndex, address.as_bytes(), from, to)?;
+                blocks = union(&blocks, &set);
+            }
+            candidates = Some(blocks);
+        }
+        // The topic index doesn't record topic positions, so this is a superset
+        for wanted in self.topics.iter().filter(|t| !t.is_empty()) {
+            let mut blocks = vec![];
+            for topic in wanted {
+                let set =
+                    dbtx.read_bitmap_index(tables::LogTopicIndex, topic.as_bytes(), from, to)?;
+                blocks = union(&blocks, &set);
+            }
+            candidates = Some(match candidates {
+                Some(c) => intersect(&c, &blocks),
+                None => blocks,
+            });
+        }
+        Ok(candidates.unwrap_or_default())
+    }
+}
+
+/// A lazy iterator over the logs matching a filter. Blocks are read one at a
+/// time as the iterator is advanced.
+pub struct LogStream<'env, E: EnvironmentKind> {
+    dbtx: Reader<'env, mdbx::RO, E>,
+    filter: LogFilter,
+    blocks: std::vec::IntoIter<u32>,
+    pending: VecDeque<Log>,
+    seq: Sequential<E>,
+    done: bool,
+}
+
+impl<'env, E: EnvironmentKind> LogStream<'env, E> {
+    pub fn new(mut dbtx: Reader<'env, mdbx::RO, E>, filter: &Filter) -> Result<Self> {
+        let (log_filter, blocks) = plan(&mut dbtx, filter)?;
+        Ok(Self::with_blocks(dbtx, log_filter, blocks))
+    }
+
+    /// Returns a stream over the logs matching `filter` in the candidate `blocks`.
+    fn with_blocks(dbtx: Reader<'env, mdbx::RO, E>, filter: LogFilter, blocks: Vec<u32>) -> Self {
+        Self {
+            seq: dbtx.sequential(),
+            dbtx,
+            filter,
+            blocks: blocks.into_iter(),
+            pending: VecDeque::new(),
+            done: false,
+        }
+    }
+
+    /// Reads the matching logs of block `num` into `pending`.
+    fn fill(&mut self, num: u32) -> Result<()> {
+        let block_num = BlockNum(num.into());
+        // wildcard and dense filters walk the logs of consecutive blocks
+        self.seq
+            .advance(num.into(), tables::TransactionLog.erased(), || {
+                block_num.to_be_bytes().to_vec()
+            });
+        let mut matched = vec![];
+        let mut log_index = 0u64;
+        for (tx_idx, logs) in self.dbtx.read_block_logs(block_num)? {
+            for (tx_log_idx, log) in logs.into_iter().enumerate() {
+                if self.filter.matches(&log) {
+                    matched.push((tx_idx, tx_log_idx, log_index, log));
+                }
+                log_index += 1;
+            }
+        }
+        if matched.is_empty() {
+            return Ok(());
+        }
+
+        let block_hash = self.dbtx.read_canonical_hash(block_num)?;
+        // the block may have been reorged out since the range was resolved
+        if let Some(hash) = self.filter.block_hash {
+            anyhow::ensure!(hash == block_hash, NotCanonical(hash));
+        }
+        let body = self
+            .dbtx
+            .read_body_for_storage(HeaderKey::new(block_num, block_hash))?;
+        let tx_amt = body.tx_amount.try_into()?;
+        let mut tx_hashes = TX_HASH_BUFS.get();
+        for msg in self
+            .dbtx
+            .stream_transactions(TxId::from(body.base_tx_id))?
+            .take(tx_amt)
+        {
+            tx_hashes.push(msg?.hash());
+        }
+        anyhow::ensure!(
+            tx_hashes.len() == tx_amt,
+            "Failed to read the txs of block {}. Expected: {}. Got {}",
+            num,
+            tx_amt,
+            tx_hashes.len()
+        );
+
+        for (tx_idx, tx_log_idx, log_index, log) in matched {
+            self.pending.push_back(Log {
+                address: log.address,
+                topics: log.topics,
+                data: log.data.into(),
+                block_hash: Some(block_hash),
+                block_number: Some(num.into()),
+                transaction_hash: tx_hashes.get(tx_idx as usize).copied(),
+                transaction_index: Some(tx_idx.into()),
+                log_index: Some(log_index.into()),
+                transaction_log_index: Some(tx_log_idx.into()),
+                removed: Some(false),
+                ..Default::default()
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Resolves the inclusive block range of `filter`.
+fn resolve_range<E: EnvironmentKind>(
+    dbtx: &mut Reader<'_, mdbx::RO, E>,
+    filter: &Filter,
+) -> Result<(u64, u64)> {
+    Ok(match filter.block_option {
+        FilterBlockOption::AtBlockHash(hash) => {
+            let num = dbtx.read_header_number(hash)?;
+            // only canonical blocks have their logs in the db
+            anyhow::ensure!(dbtx.read_canonical_hash(num)? == hash, NotCanonical(hash));
+            (*num, *num)
+        }
+        FilterBlockOption::Range {
+            from_block,
+            to_block,
+        } => (
+            *res_block_number(dbtx, from_block.unwrap_or(BlockNumber::Latest))?,
+            *res_block_number(dbtx, to_block.unwrap_or(BlockNumber::Latest))?,
+        ),
+    })
+}
+
+/// Resolves the block range of `filter` and returns its candidate blocks.
+fn plan<E: EnvironmentKind>(
+    dbtx: &mut Reader<'_, mdbx::RO, E>,
+    filter: &Filter,
+) -> Result<(LogFilter, Vec<u32>)> {
+    let (from, to) = resolve_range(dbtx, filter)?;
+    let log_filter = LogFilter::new(filter);
+    let blocks = if from > to {
+        vec![]
+    } else {
+        log_filter.candidate_blocks(dbtx, u32::try_from(from)?, u32::try_from(to)?)?
+    };
+    Ok((log_filter, blocks))
+}
+
+/// How `get_logs` would serve a filter, as returned by `Client::explain_logs`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LogQueryPlan {
+    /// The inclusive block range of the filter.
+    pub from: u64,
+    pub to: u64,
+    /// The index tables consulted to narrow the range.
+    pub indices: Vec<&'static str>,
+    /// The number of blocks whose logs would be read.
+    pub candidate_blocks: usize,
+    /// Whether no index applies, so the logs of every block in the range
+    /// would be read.
+    pub full_scan: bool,
+    /// The number of threads which would scan the candidate blocks.
+    pub threads: usize,
+}
+
+impl<E: EnvironmentKind> Client<E> {
+    /// Plans `get_logs(filter)` without reading any logs, so the cost of a
+    /// query can be checked before running it. The index bitmaps are read, so
+    /// the number of candidate blocks is exact.
+    pub fn explain_logs(&self, filter: &Filter) -> Result<LogQueryPlan> {
+        let _slow = self.slow_read("explain_logs", LOG_TABLES, || format!("{:?}", filter));
+        let mut dbtx = self.reader()?;
+        let (from, to) = resolve_range(&mut dbtx, filter)?;
+        let log_filter = LogFilter::new(filter);
+
+        let mut indices = vec![];
+        if !log_filter.addresses.is_empty() {
+            indices.push(tables::LogAddressIndex::const_db_name());
+        }
+        if log_filter.topics.iter().any(|t| !t.is_empty()) {
+            indices.push(tables::LogTopicIndex::const_db_name());
+        }
+        let blocks = if from > to {
+            vec![]
+        } else {
+            log_filter.candidate_blocks(&mut dbtx, u32::try_from(from)?, u32::try_from(to)?)?
+        };
+        let threads = match self.options().log_parallelism {
+            n if n > 1 && blocks.len() >= MIN_PARALLEL_LOG_BLOCKS => shards(&blocks, n).count(),
+            _ => 1,
+        };
+
+        Ok(LogQueryPlan {
+            from,
+            to,
+            full_scan: indices.is_empty(),
+            indices,
+            candidate_blocks: blocks.len(),
+            threads,
+        })
+    }
+}
+
+/// Returns the logs matching `filter`, splitting its candidate blocks into up
+/// to `threads` contiguous shards which are scanned in parallel on the client's
+/// log workers, each in its own read transaction, and concatenated in block
+/// order.
+pub(crate) fn get_logs_sharded<E: EnvironmentKind>(
+    client: &Client<E>,
+    filter: &Filter,
+    threads: usize,
+) -> Result<Vec<Log>> {
+    let (log_filter, blocks) = plan(&mut client.reader()?, filter)?;
+    if blocks.len() < MIN_PARALLEL_LOG_BLOCKS {
+        return LogStream::with_blocks(client.reader()?, log_filter, blocks).collect();
+    }
+
+    let workers = client.log_workers(threads)?;
+    let results = shards(&blocks, workers.threads())
+        .map(|shard| {
+            let (tx, rx) = sync_channel(1);
+            let (client, log_filter, shard) = (client.clone(), log_filter.clone(), shard.to_vec());
+            workers.execute(move || {
+                let res = client
+                    .reader()
+                    .and_then(|dbtx| LogStream::with_blocks(dbtx, log_filter, shard).collect());
+                let _ = tx.send(res);
+            });
+            rx
+        })
+        .collect::<Vec<_>>();
+    let mut logs = vec![];
+    for rx in results {
+        logs.extend(
+            rx.recv()
+                .map_err(|_| format_err!("log scan worker panicked"))??,
+        );
+    }
+    Ok(logs)
+}
+
+/// Splits `blocks` into at most `n` contiguous shards of near equal length.
+fn shards(blocks: &[u32], n: usize) -> std::slice::Chunks<'_, u32> {
+    let len = (blocks.len() + n - 1) / n;
+    blocks.chunks(len.max(1))
+}
+
+impl<'env, E: EnvironmentKind> Iterator for LogStream<'env, E> {
+    type Item = Result<Log>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(log) = self.pending.pop_front() {
+                return Some(Ok(log));
+            }
+            if self.done {
+                return None;
+            }
+            let res = self
+                .dbtx
+                .budget()
+                .check()
+                .and_then(|_| match self.blocks.next() {
+                    Some(num) => self.fill(num).map(|_| true),
+                    None => Ok(false),
+                });
+            match res {
+                Ok(true) => {}
+                Ok(false) => {
+                    self.done = true;
+                    return None;
+                }
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_filter_matches() {
+        let address = Address::repeat_byte(1);
+        let t0 = H256::repeat_byte(2);
+        let t1 = H256::repeat_byte(3);
+        let filter = Filter::new().address(address).topic1(t1);
+        let log_filter = LogFilter::new(&filter);
+
+        let mut log = models::Log {
+            address,
+            topics: vec![t0, t1],
+            data: Default::default(),
+        };
+        assert!(log_filter.matches(&log));
+
+        // topic in the wrong position
+        log.topics = vec![t1, t0];
+        assert!(!log_filter.matches(&log));
+
+        log.topics = vec![t0, t1];
+        log.address = Address::repeat_byte(9);
+        assert!(!log_filter.matches(&log));
+    }
+
+    #[test]
+    fn test_shards() {
+        let blocks: Vec<u32> = (0..10).collect();
+        let got: Vec<_> = shards(&blocks, 3).collect();
+        assert_eq!(got, vec![&blocks[..4], &blocks[4..8], &blocks[8..]]);
+        assert_eq!(shards(&blocks, 20).count(), 10);
+        assert_eq!(shards(&[], 4).count(), 0);
+        // shards concatenate back to the blocks in order
+        assert_eq!(
+            shards(&blocks, 4).flatten().copied().collect::<Vec<_>>(),
+            blocks
+        );
+    }
+}
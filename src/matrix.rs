@@ -0,0 +1,256 @@
+//! Which JSON-RPC methods a client can serve from its datadir, for health
+//! output and for integrators to assert on.
+
+use anyhow::Result;
+use mdbx::EnvironmentKind;
+use serde::Serialize;
+
+use crate::{
+    builder::{PrunedData, Route},
+    client::Client,
+};
+
+/// Where `DbMiddleware` serves a method from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum MethodStatus {
+    /// Served from the db.
+    Local,
+    /// Delegated to the inner provider.
+    Remote,
+    /// Served from the db, but the data it needs isn't there and the client's
+    /// routing doesn't fall back to the inner provider, so calls fail.
+    Unsupported,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MethodSupport {
+    pub method: &'static str,
+    pub status: MethodStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note: Option<&'static str>,
+}
+
+/// The result of `Client::feature_matrix`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FeatureMatrix {
+    pub methods: Vec<MethodSupport>,
+}
+
+impl FeatureMatrix {
+    /// Returns the status of `method`, or `None` if it isn't listed.
+    pub fn status(&self, method: &str) -> Option<MethodStatus> {
+        self.methods
+            .iter()
+            .find(|m| m.method == method)
+            .map(|m| m.status)
+    }
+
+    /// Returns the methods served from the db.
+    pub fn local(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.methods
+            .iter()
+            .filter(|m| m.status == MethodStatus::Local)
+            .map(|m| m.method)
+    }
+}
+
+const LATEST_STATE_ONLY: &str = "historical blocks are delegated to the inner provider";
+const NO_EXECUTION: &str = "requires executing transactions";
+const NO_PROOFS: &str = "state proofs are not implemented";
+
+impl<E: EnvironmentKind> Client<E> {
+    /// Lists the JSON-RPC methods `DbMiddleware` implements and how each is
+    /// served given the data in the opened datadir and the client's routing.
+    /// A method reading a kind of data the db has none of is `Remote` if the
+    /// middleware delegates it, `Unsupported` if it would fail. Methods
+    /// disabled by the client's `PrunedProfile` are always `Remote`.
+    ///
+    /// Only whether any data of each kind is present is checked, so a pruned
+    /// node may still delegate or fail reads of its oldest blocks.
+    ///
+    /// ```ignore
+    /// let matrix = client.feature_matrix()?;
+    /// assert_eq!(matrix.status("eth_getLogs"), Some(MethodStatus::Local));
+    /// ```
+    pub fn feature_matrix(&self) -> Result<FeatureMatrix> {
+        let ranges = self.available_ranges()?;
+        let fallback = self.options().routing.fallback_on_error;
+        let has_txpool = self.txpool_reader().is_ok();
+        let needs = |present: bool| match (present, fallback) {
+            (true, _) => MethodStatus::Local,
+            (false, true) => MethodStatus::Remote,
+            (false, false) => MethodStatus::Unsupported,
+        };
+        let pruned = |data| {
+            self.options()
+                .pruned
+                .map_or(false, |profile| profile.prunes(data))
+        };
+        let unless_pruned = |data, status| {
+            if pruned(data) {
+                MethodStatus::Remote
+            } else {
+                status
+            }
+        };
+        let chain_config = needs(self.reader()?.read_chain_config().ok().flatten().is_some());
+        let headers = needs(ranges.headers.is_some());
+        let bodies = needs(ranges.headers.is_some() && ranges.bodies.is_some());
+        let by_hash = unless_pruned(PrunedData::TxLookup, bodies);
+        // missing receipts are always delegated, but missing logs are not
+        let receipts = match ranges.receipts {
+            _ if pruned(PrunedData::Receipts) || pruned(PrunedData::TxLookup) => {
+                MethodStatus::Remote
+            }
+            Some(_) => MethodStatus::Local,
+            None => MethodStatus::Remote,
+        };
+        let block_receipts = match ranges.receipts {
+            Some(_) => unless_pruned(PrunedData::Receipts, MethodStatus::Local),
+            None => MethodStatus::Remote,
+        };
+        let logs = unless_pruned(PrunedData::Receipts, needs(ranges.receipts.is_some()));
+        // historical accounts and storage are read from the changesets if routed to the db
+        let historical_account = match self.options().routing.historical_state {
+            Route::Db if !pruned(PrunedData::History) => None,
+            _ => Some(LATEST_STATE_ONLY),
+        };
+
+        let method = |method, status| MethodSupport {
+            method,
+            status,
+            note: None,
+        };
+        let with_note = |method, status, note| MethodSupport {
+            method,
+            status,
+            note: Some(note),
+        };
+        let methods = vec![
+            method("eth_blockNumber", MethodStatus::Local),
+            MethodSupport {
+                method: "eth_getBalance",
+                status: MethodStatus::Local,
+                note: historical_account,
+            },
+            MethodSupport {
+                method: "eth_getCode",
+                status: MethodStatus::Local,
+                note: historical_account,
+            },
+            MethodSupport {
+                method: "eth_getStorageAt",
+                status: MethodStatus::Local,
+                note: historical_account,
+            },
+            MethodSupport {
+                method: "eth_getTransactionCount",
+                status: MethodStatus::Local,
+                note: historical_account,
+            },
+            method("eth_getBlockByHash", bodies),
+            method("eth_getBlockByNumber", bodies),
+            method("eth_getUncleByBlockHashAndIndex", bodies),
+            method("eth_getUncleByBlockNumberAndIndex", bodies),
+            method("eth_getUncleCountByBlockHash", bodies),
+            method("eth_getUncleCountByBlockNumber", bodies),
+            method("eth_getTransactionByHash", by_hash),
+            method("eth_getTransactionReceipt", receipts),
+            method("eth_getBlockReceipts", block_receipts),
+            method("eth_getLogs", logs),
+            method("eth_newFilter", logs),
+            method("eth_newBlockFilter", headers),
+            method("eth_newPendingTransactionFilter", needs(has_txpool)),
+            method("eth_getFilterChanges", MethodStatus::Local),
+            method("eth_uninstallFilter", MethodStatus::Local),
+            method("eth_chainId", chain_config),
+            method("net_version", chain_config),
+            method("web3_clientVersion", MethodStatus::Local),
+            with_note("eth_call", MethodStatus::Remote, NO_EXECUTION),
+            with_note("eth_estimateGas", MethodStatus::Remote, NO_EXECUTION),
+            with_note("eth_getProof", MethodStatus::Remote, NO_PROOFS),
+            method("eth_sendRawTransaction", MethodStatus::Remote),
+        ];
+        Ok(FeatureMatrix { methods })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        builder::{PrunedProfile, Routing},
+        test::{chain::ChainBuilder, TMP_DIR},
+    };
+
+    #[test]
+    fn test_feature_matrix() -> Result<()> {
+        let chain = ChainBuilder::new().block(|b| b).write(TMP_DIR.clone())?;
+        let db = Client::<mdbx::NoWriteMap>::open_new(chain.path.clone())?;
+
+        let matrix = db.feature_matrix()?;
+        assert_eq!(
+            matrix.status("eth_getBlockByNumber"),
+            Some(MethodStatus::Local)
+        );
+        assert_eq!(matrix.status("eth_call"), Some(MethodStatus::Remote));
+        // the chain has no config
+        assert_eq!(
+            matrix.status("eth_chainId"),
+            Some(MethodStatus::Unsupported)
+        );
+        assert_eq!(
+            matrix.status("web3_clientVersion"),
+            Some(MethodStatus::Local)
+        );
+        assert_eq!(matrix.status("eth_mining"), None);
+        // no txpool db was opened
+        assert_eq!(
+            matrix.status("eth_newPendingTransactionFilter"),
+            Some(MethodStatus::Unsupported)
+        );
+        assert!(matrix.local().any(|m| m == "eth_getBalance"));
+
+        let db = Client::<mdbx::NoWriteMap>::builder()
+            .path(chain.path.clone())
+            .routing(Routing {
+                fallback_on_error: true,
+                ..Default::default()
+            })
+            .build()?;
+        let matrix = db.feature_matrix()?;
+        assert_eq!(
+            matrix.status("eth_newPendingTransactionFilter"),
+            Some(MethodStatus::Remote)
+        );
+
+        let db = Client::<mdbx::NoWriteMap>::builder()
+            .path(chain.path.clone())
+            .pruned(PrunedProfile::full_node())
+            .build()?;
+        let pruned = db.feature_matrix()?;
+        assert_eq!(pruned.status("eth_getLogs"), Some(MethodStatus::Remote));
+        assert_eq!(
+            pruned.status("eth_getTransactionByHash"),
+            Some(MethodStatus::Remote)
+        );
+        assert_eq!(
+            pruned.status("eth_getBlockByNumber"),
+            Some(MethodStatus::Local)
+        );
+
+        let json = serde_json::to_value(&matrix)?;
+        assert_eq!(
+            json["methods"][1],
+            serde_json::json!({
+                "method": "eth_getBalance",
+                "status": "local",
+                "note": LATEST_STATE_ONLY,
+            })
+        );
+        Ok(())
+    }
+}
@@ -1,24 +1,186 @@
 use anyhow::Result;
 use async_trait::async_trait;
 use ethers::{
-    core::types::{Address, Block, BlockId, NameOrAddress, TxHash, H256, U256, U64},
-    providers::{FromErr, Middleware},
+    core::types::{
+        Address, Block, BlockId, BlockNumber, Filter, FilterBlockOption, Log, NameOrAddress,
+        Transaction, TransactionReceipt, TxHash, H256, U256, U64,
+    },
+    providers::{FilterKind, FilterWatcher, FromErr, Middleware},
 };
 use mdbx::EnvironmentKind;
-use std::sync::Arc;
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    fmt::Debug,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 use thiserror::Error;
 
-use crate::client::{Client, Either};
+use crate::{
+    builder::{Pruned, Route},
+    client::{get_header_key, Client, Either},
+    coalesce::{CoalesceConfig, Coalescer},
+    filters::FilterChanges,
+    types::{BlockNum, HeaderKey},
+};
 
+/// A `Middleware` which serves requests from an Erigon database where possible,
+/// delegating to `inner` otherwise. Both `inner` and the underlying `Client` are
+/// shared by reference, so `DbMiddleware` is `Send + Sync` whenever `M` is.
+///
+/// `watch_pending_transactions` is delegated to `inner`, because ethers'
+/// `FilterWatcher` polls the node itself. Pending transaction filters created with
+/// `new_filter` are served from the txpool db, and `Client::watch_pending_transactions`,
+/// reached through `client()`, tails it directly.
+///
+/// A db and an inner provider on different chains give inconsistent results,
+/// so prefer constructing with `checked`, which compares their chain ids.
+/// `new` doesn't compare them at all. With `recheck_chain_id`, they are
+/// compared again before the requests this type delegates, including
+/// `get_logs` and the filter methods. Methods it doesn't implement go to
+/// `inner` through ethers' defaults and are never guarded.
 #[derive(Debug, Clone)]
 pub struct DbMiddleware<M, E: EnvironmentKind> {
     inner: M,
     db: Arc<Client<E>>,
+    coalesce: CoalesceConfig,
+    flights: Arc<Flights>,
+    chain_check: Arc<ChainCheck>,
+    head_lag: Option<HeadLagPolicy>,
+    inner_head: Arc<InnerHead>,
+}
+
+/// How long the inner provider's head is reused for head-lag comparisons by
+/// default. See `DbMiddleware::head_lag_ttl`.
+pub const INNER_HEAD_TTL: Duration = Duration::from_secs(1);
+
+/// What `DbMiddleware` does with reads of the latest block or state while
+/// the db's head is behind the inner provider's. Such reads compare the db's
+/// head with the inner provider's, which is asked for it at most once per
+/// `head_lag_ttl`. If the inner provider can't be asked, the db serves the
+/// read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeadLagPolicy {
+    /// Serve the reads from whichever of the db and the inner provider is
+    /// ahead.
+    RouteToAhead,
+    /// Fail the reads with a `HeadLag` while the db is more than `max_lag`
+    /// blocks behind.
+    Fail { max_lag: u64 },
+}
+
+/// The db's head is too far behind the inner provider's. Returned under a
+/// `HeadLagPolicy::Fail` wrapped in `DbMiddlewareError::Anyhow`.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("the db's head {db} is too far behind the inner provider's head {inner}")]
+pub struct HeadLag {
+    pub db: U64,
+    pub inner: U64,
+}
+
+/// The inner provider's chain id differs from the one in the db's chain
+/// config. Returned by `DbMiddleware::check_chain_id` wrapped in
+/// `DbMiddlewareError::Anyhow`.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("chain id mismatch: the db is on chain {db} but the inner provider is on chain {inner}")]
+pub struct ChainMismatch {
+    pub db: U256,
+    pub inner: U256,
+}
+
+/// When the chain ids were last compared, and how often to compare them again.
+#[derive(Debug, Default)]
+struct ChainCheck {
+    interval: Option<Duration>,
+    last: Mutex<Option<Instant>>,
+}
+
+/// The inner provider's head as of when it was last asked for it.
+#[derive(Debug)]
+struct InnerHead {
+    ttl: Duration,
+    last: Mutex<Option<(Instant, U64)>>,
+}
+
+impl InnerHead {
+    fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            last: Mutex::new(None),
+        }
+    }
+
+    /// Returns the head, if it was asked for within the TTL.
+    fn get(&self) -> Option<U64> {
+        match *self.last.lock().unwrap() {
+            Some((at, head)) if at.elapsed() < self.ttl => Some(head),
+            _ => None,
+        }
+    }
+
+    fn set(&self, head: U64) {
+        *self.last.lock().unwrap() = Some((Instant::now(), head));
+    }
+}
+
+/// The reads in progress for each coalesced method, keyed by the block or
+/// transaction they're for.
+#[derive(Debug, Default)]
+struct Flights {
+    blocks: Coalescer<HeaderKey, Option<Block<TxHash>>>,
+    blocks_with_txs: Coalescer<HeaderKey, Option<Block<Transaction>>>,
+    receipts: Coalescer<TxHash, Either<BlockNum, Option<TransactionReceipt>>>,
+    block_receipts: Coalescer<HeaderKey, Either<BlockNum, Vec<TransactionReceipt>>>,
 }
 
 impl<M, E: EnvironmentKind> DbMiddleware<M, E> {
     pub fn new(inner: M, db: Arc<Client<E>>) -> Self {
-        Self { inner, db }
+        Self {
+            inner,
+            db,
+            coalesce: Default::default(),
+            flights: Default::default(),
+            chain_check: Default::default(),
+            head_lag: None,
+            inner_head: Arc::new(InnerHead::new(INNER_HEAD_TTL)),
+        }
+    }
+
+    /// Compares the db's head with the inner provider's on reads of the
+    /// latest block or state, handling a lagging db as `policy` says.
+    pub fn head_lag(mut self, policy: HeadLagPolicy) -> Self {
+        self.head_lag = Some(policy);
+        self
+    }
+
+    /// Reuses the inner provider's head for head-lag comparisons for `ttl`
+    /// after asking for it, rather than for `INNER_HEAD_TTL`.
+    pub fn head_lag_ttl(mut self, ttl: Duration) -> Self {
+        self.inner_head = Arc::new(InnerHead::new(ttl));
+        self
+    }
+
+    /// Compares the inner provider's chain id with the db's again before
+    /// delegating a request, once `interval` has passed since the last
+    /// comparison, failing the request on a mismatch.
+    pub fn recheck_chain_id(mut self, interval: Duration) -> Self {
+        self.chain_check = Arc::new(ChainCheck {
+            interval: Some(interval),
+            last: Mutex::new(*self.chain_check.last.lock().unwrap()),
+        });
+        self
+    }
+
+    /// Coalesces concurrent requests for the same block or receipts into one
+    /// db read for the methods enabled in `config`. Blocks are keyed by their
+    /// hash, so `latest` and the head's number share a read.
+    pub fn coalesce(mut self, config: CoalesceConfig) -> Self {
+        self.coalesce = config;
+        self
+    }
+
+    pub fn client(&self) -> &Client<E> {
+        &self.db
     }
 }
 
@@ -27,6 +189,77 @@ where
     M: Middleware,
     E: EnvironmentKind,
 {
+    /// Like `new`, but fails with a `ChainMismatch` if `inner` is on another
+    /// chain than the db.
+    pub async fn checked(inner: M, db: Arc<Client<E>>) -> Result<Self, DbMiddlewareError<M>> {
+        let mw = Self::new(inner, db);
+        mw.check_chain_id().await?;
+        Ok(mw)
+    }
+
+    /// Fails with a `ChainMismatch` if the inner provider's chain id differs
+    /// from the one in the db's chain config.
+    pub async fn check_chain_id(&self) -> Result<(), DbMiddlewareError<M>> {
+        let db = self.db.chain_id()?;
+        let inner = self.inner.get_chainid().await.map_err(FromErr::from)?;
+        if db != inner {
+            return Err(anyhow::Error::from(ChainMismatch { db, inner }).into());
+        }
+        *self.chain_check.last.lock().unwrap() = Some(Instant::now());
+        Ok(())
+    }
+
+    /// Returns true if a read at `block` is of the latest block and should be
+    /// sent to the inner provider because the db is behind it.
+    async fn lags(&self, block: Option<BlockId>) -> Result<bool, DbMiddlewareError<M>> {
+        let policy = match self.head_lag {
+            Some(policy) => policy,
+            None => return Ok(false),
+        };
+        if !matches!(block, None | Some(BlockId::Number(BlockNumber::Latest))) {
+            return Ok(false);
+        }
+        let db = self.db.get_block_number()?;
+        let inner = match self.inner_head.get() {
+            Some(head) => head,
+            None => match self.inner.get_block_number().await {
+                Ok(head) => {
+                    self.inner_head.set(head);
+                    head
+                }
+                Err(e) => {
+                    tracing::warn!(error = %e, "can't compare heads, reading from the db");
+                    return Ok(false);
+                }
+            },
+        };
+        let lag = inner.saturating_sub(db).as_u64();
+        match policy {
+            HeadLagPolicy::RouteToAhead => Ok(lag > 0),
+            HeadLagPolicy::Fail { max_lag } if lag > max_lag => {
+                Err(anyhow::Error::from(HeadLag { db, inner }).into())
+            }
+            HeadLagPolicy::Fail { .. } => Ok(false),
+        }
+    }
+
+    /// Runs `check_chain_id` if the recheck interval has passed.
+    async fn ensure_same_chain(&self) -> Result<(), DbMiddlewareError<M>> {
+        let due = match self.chain_check.interval {
+            Some(interval) => self
+                .chain_check
+                .last
+                .lock()
+                .unwrap()
+                .map_or(true, |last| last.elapsed() >= interval),
+            None => false,
+        };
+        if due {
+            self.check_chain_id().await?;
+        }
+        Ok(())
+    }
+
     async fn get_address<T: Into<NameOrAddress>>(
         &self,
         who: T,
@@ -38,6 +271,57 @@ where
     }
 }
 
+/// Serves a request from the db. If the db read fails and the client's routing
+/// allows it, or the read is disabled by the client's `PrunedProfile`, the
+/// request is retried against the inner provider.
+macro_rules! db_or_inner {
+    ($self:ident, $db:expr, $inner:expr) => {
+        match $db {
+            Ok(res) => Ok(res),
+            Err(e) if $self.delegates(&e) => {
+                $self.ensure_same_chain().await?;
+                $inner.await.map_err(FromErr::from)
+            }
+            Err(e) => Err(From::from(e)),
+        }
+    };
+}
+
+impl<M, E> DbMiddleware<M, E>
+where
+    M: Middleware,
+    E: EnvironmentKind,
+{
+    /// Returns true if a failed db read should be retried against the inner provider.
+    fn delegates(&self, err: &anyhow::Error) -> bool {
+        self.db.options().routing.fallback_on_error || err.is::<Pruned>()
+    }
+
+    /// Returns true if a state read at `block` should be sent to the inner provider.
+    fn route_to_inner(&self, block: Option<BlockId>) -> bool {
+        block.is_some() && self.db.options().routing.historical_state == Route::Inner
+    }
+
+    fn header_key<T: Into<BlockId> + Send + Sync>(&self, id: T) -> Result<HeaderKey> {
+        get_header_key(&mut self.db.reader()?, id)
+    }
+
+    /// Runs a db read which waits for an admission slot. With admission
+    /// control enabled the read runs on the blocking pool, so that a queued
+    /// query doesn't hold up an async worker while it waits.
+    async fn admitted<T, F>(&self, read: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Client<E>) -> Result<T> + Send + 'static,
+    {
+        if self.db.admission_metrics().is_none() {
+            return read(&self.db);
+        }
+        let db = Arc::clone(&self.db);
+        tokio::task::spawn_blocking(move || read(&db)).await?
+    }
+}
+
 #[async_trait]
 impl<M, E> Middleware for DbMiddleware<M, E>
 where
@@ -53,7 +337,27 @@ where
     }
 
     async fn get_block_number(&self) -> Result<U64, Self::Error> {
-        self.db.get_block_number().map_err(From::from)
+        if self.lags(None).await? {
+            self.ensure_same_chain().await?;
+            return self.inner().get_block_number().await.map_err(FromErr::from);
+        }
+        db_or_inner!(
+            self,
+            self.db.get_block_number(),
+            self.inner().get_block_number()
+        )
+    }
+
+    async fn get_chainid(&self) -> Result<U256, Self::Error> {
+        db_or_inner!(self, self.db.chain_id(), self.inner().get_chainid())
+    }
+
+    async fn get_net_version(&self) -> Result<String, Self::Error> {
+        db_or_inner!(self, self.db.net_version(), self.inner().get_net_version())
+    }
+
+    async fn client_version(&self) -> Result<String, Self::Error> {
+        Ok(self.db.client_version().to_string())
     }
 
     async fn get_balance<T: Into<NameOrAddress> + Send + Sync>(
@@ -62,7 +366,8 @@ where
         block: Option<BlockId>,
     ) -> Result<U256, Self::Error> {
         let who = self.get_address(from).await?;
-        if block.is_some() {
+        if self.route_to_inner(block) || self.lags(block).await? {
+            self.ensure_same_chain().await?;
             return self
                 .inner()
                 .get_balance(who, block)
@@ -70,7 +375,11 @@ where
                 .map_err(FromErr::from);
         }
 
-        self.db.get_balance(who, block).map_err(From::from)
+        db_or_inner!(
+            self,
+            self.db.get_balance(who, block),
+            self.inner().get_balance(who, block)
+        )
     }
 
     async fn get_code<T: Into<NameOrAddress> + Send + Sync>(
@@ -79,7 +388,8 @@ where
         block: Option<BlockId>,
     ) -> Result<ethers::types::Bytes, Self::Error> {
         let who = self.get_address(from).await?;
-        if block.is_some() {
+        if self.route_to_inner(block) || self.lags(block).await? {
+            self.ensure_same_chain().await?;
             return self
                 .inner()
                 .get_code(who, block)
@@ -87,7 +397,11 @@ where
                 .map_err(FromErr::from);
         }
 
-        self.db.get_code(who, block).map_err(From::from)
+        db_or_inner!(
+            self,
+            self.db.get_code(who, block),
+            self.inner().get_code(who, block)
+        )
     }
 
     async fn get_transaction_count<T: Into<NameOrAddress> + Send + Sync>(
@@ -96,7 +410,8 @@ where
         block: Option<BlockId>,
     ) -> Result<U256, Self::Error> {
         let who = self.get_address(from).await?;
-        if block.is_some() {
+        if self.route_to_inner(block) || self.lags(block).await? {
+            self.ensure_same_chain().await?;
             return self
                 .inner()
                 .get_transaction_count(who, block)
@@ -104,18 +419,23 @@ where
                 .map_err(FromErr::from);
         }
 
-        self.db
-            .get_transaction_count(who, block)
-            .map_err(From::from)
+        db_or_inner!(
+            self,
+            self.db.get_transaction_count(who, block),
+            self.inner().get_transaction_count(who, block)
+        )
     }
 
     async fn get_transaction<T: Send + Sync + Into<TxHash>>(
         &self,
         transaction_hash: T,
     ) -> Result<Option<ethers::types::Transaction>, Self::Error> {
-        self.db
-            .get_transaction(transaction_hash)
-            .map_err(From::from)
+        let hash = transaction_hash.into();
+        db_or_inner!(
+            self,
+            self.db.get_transaction(hash),
+            self.inner().get_transaction(hash)
+        )
     }
 
     async fn get_storage_at<T: Into<NameOrAddress> + Send + Sync>(
@@ -125,7 +445,8 @@ where
         block: Option<BlockId>,
     ) -> Result<H256, Self::Error> {
         let who = self.get_address(from).await?;
-        if block.is_some() {
+        if self.route_to_inner(block) || self.lags(block).await? {
+            self.ensure_same_chain().await?;
             return self
                 .inner()
                 .get_storage_at(who, location, block)
@@ -133,18 +454,23 @@ where
                 .map_err(FromErr::from);
         }
 
-        self.db
-            .get_storage_at(who, location, block)
-            .map_err(From::from)
+        db_or_inner!(
+            self,
+            self.db.get_storage_at(who, location, block),
+            self.inner().get_storage_at(who, location, block)
+        )
     }
 
     async fn get_uncle_count<T: Into<BlockId> + Send + Sync>(
         &self,
         block_hash_or_number: T,
     ) -> Result<U256, Self::Error> {
-        self.db
-            .get_uncle_count(block_hash_or_number)
-            .map_err(From::from)
+        let id = block_hash_or_number.into();
+        db_or_inner!(
+            self,
+            self.db.get_uncle_count(id),
+            self.inner().get_uncle_count(id)
+        )
     }
 
     async fn get_uncle<T: Into<BlockId> + Send + Sync>(
@@ -152,40 +478,240 @@ where
         block_hash_or_number: T,
         idx: U64,
     ) -> Result<Option<Block<H256>>, Self::Error> {
-        self.db
-            .get_uncle(block_hash_or_number, idx)
-            .map_err(From::from)
+        let id = block_hash_or_number.into();
+        db_or_inner!(
+            self,
+            self.db.get_uncle(id, idx),
+            self.inner().get_uncle(id, idx)
+        )
     }
 
     async fn get_block<T: Into<BlockId> + Send + Sync>(
         &self,
         block_hash_or_number: T,
     ) -> Result<Option<Block<TxHash>>, Self::Error> {
-        self.db.get_block(block_hash_or_number).map_err(From::from)
+        let id = block_hash_or_number.into();
+        if self.lags(Some(id)).await? {
+            self.ensure_same_chain().await?;
+            return self.inner().get_block(id).await.map_err(FromErr::from);
+        }
+        let res = if self.coalesce.get_block {
+            match self.header_key(id) {
+                Ok(key) => {
+                    let db = Arc::clone(&self.db);
+                    self.flights
+                        .blocks
+                        .run(key, move || db.get_block(key.hash))
+                        .await
+                }
+                Err(e) => Err(e),
+            }
+        } else {
+            self.db.get_block(id)
+        };
+        db_or_inner!(self, res, self.inner().get_block(id))
     }
 
     async fn get_block_with_txs<T: Into<BlockId> + Send + Sync>(
         &self,
         block_hash_or_number: T,
     ) -> Result<Option<Block<ethers::types::Transaction>>, Self::Error> {
-        self.db
-            .get_block_with_txs(block_hash_or_number)
-            .map_err(From::from)
+        let id = block_hash_or_number.into();
+        if self.lags(Some(id)).await? {
+            self.ensure_same_chain().await?;
+            return self
+                .inner()
+                .get_block_with_txs(id)
+                .await
+                .map_err(FromErr::from);
+        }
+        let res = if self.coalesce.get_block_with_txs {
+            match self.header_key(id) {
+                Ok(key) => {
+                    let db = Arc::clone(&self.db);
+                    self.flights
+                        .blocks_with_txs
+                        .run(key, move || db.get_block_with_txs(key.hash))
+                        .await
+                }
+                Err(e) => Err(e),
+            }
+        } else {
+            self.db.get_block_with_txs(id)
+        };
+        db_or_inner!(self, res, self.inner().get_block_with_txs(id))
+    }
+
+    async fn get_logs(&self, filter: &Filter) -> Result<Vec<Log>, Self::Error> {
+        let to_latest = match filter.block_option {
+            FilterBlockOption::Range { to_block, .. } => {
+                matches!(to_block, None | Some(BlockNumber::Latest))
+            }
+            FilterBlockOption::AtBlockHash(_) => false,
+        };
+        if to_latest && self.lags(None).await? {
+            self.ensure_same_chain().await?;
+            return self.inner().get_logs(filter).await.map_err(FromErr::from);
+        }
+        let owned = filter.clone();
+        db_or_inner!(
+            self,
+            self.admitted(move |db| db.get_logs(&owned)).await,
+            self.inner().get_logs(filter)
+        )
+    }
+
+    async fn new_filter(&self, filter: FilterKind<'_>) -> Result<U256, Self::Error> {
+        db_or_inner!(
+            self,
+            self.db.new_filter(filter.clone()),
+            self.inner().new_filter(filter)
+        )
+    }
+
+    async fn uninstall_filter<T: Into<U256> + Send + Sync>(
+        &self,
+        id: T,
+    ) -> Result<bool, Self::Error> {
+        let id = id.into();
+        if self.db.has_filter(id) {
+            return Ok(self.db.uninstall_filter(id));
+        }
+        self.ensure_same_chain().await?;
+        self.inner()
+            .uninstall_filter(id)
+            .await
+            .map_err(FromErr::from)
+    }
+
+    async fn get_filter_changes<T, R>(&self, id: T) -> Result<Vec<R>, Self::Error>
+    where
+        T: Into<U256> + Send + Sync,
+        R: Serialize + DeserializeOwned + Send + Sync + Debug,
+    {
+        // Filters installed on the inner provider are unknown to the db
+        let id = id.into();
+        if !self.db.has_filter(id) {
+            self.ensure_same_chain().await?;
+            return self
+                .inner()
+                .get_filter_changes(id)
+                .await
+                .map_err(FromErr::from);
+        }
+        let changes = match self.admitted(move |db| db.get_filter_changes(id)).await? {
+            FilterChanges::Logs(logs) => serde_json::to_value(logs),
+            FilterChanges::Hashes(hashes) => serde_json::to_value(hashes),
+            FilterChanges::Blocks(blocks) => serde_json::to_value(blocks),
+        };
+        changes
+            .and_then(serde_json::from_value)
+            .map_err(|e| anyhow::Error::from(e).into())
+    }
+
+    // The watcher polls its filter through `Self::Provider`, which doesn't
+    // know the filters installed in the db
+    async fn watch_pending_transactions(
+        &self,
+    ) -> Result<FilterWatcher<'_, Self::Provider, H256>, Self::Error> {
+        self.ensure_same_chain().await?;
+        self.inner()
+            .watch_pending_transactions()
+            .await
+            .map_err(FromErr::from)
+    }
+
+    async fn get_transaction_receipt<T: Send + Sync + Into<TxHash>>(
+        &self,
+        transaction_hash: T,
+    ) -> Result<Option<ethers::types::TransactionReceipt>, Self::Error> {
+        let hash = transaction_hash.into();
+        let res = if self.coalesce.get_transaction_receipt {
+            let db = Arc::clone(&self.db);
+            self.flights
+                .receipts
+                .run(hash, move || db.get_transaction_receipt(hash))
+                .await
+        } else {
+            self.db.get_transaction_receipt(hash)
+        };
+        match res {
+            // A tx the db doesn't know may be in a block it hasn't synced yet
+            Ok(Either::Right(None)) if self.lags(None).await? => {
+                self.ensure_same_chain().await?;
+                self.inner()
+                    .get_transaction_receipt(hash)
+                    .await
+                    .map_err(FromErr::from)
+            }
+            Ok(Either::Right(receipt)) => Ok(receipt),
+            // Receipts not in the db, delegate to inner
+            Ok(Either::Left(_)) => {
+                self.ensure_same_chain().await?;
+                self.inner()
+                    .get_transaction_receipt(hash)
+                    .await
+                    .map_err(FromErr::from)
+            }
+            Err(e) if self.delegates(&e) => {
+                self.ensure_same_chain().await?;
+                self.inner()
+                    .get_transaction_receipt(hash)
+                    .await
+                    .map_err(FromErr::from)
+            }
+            Err(e) => Err(From::from(e)),
+        }
     }
 
     async fn get_block_receipts<T: Into<ethers::types::BlockNumber> + Send + Sync>(
         &self,
         block: T,
     ) -> Result<Vec<ethers::types::TransactionReceipt>, Self::Error> {
-        match self.db.get_block_receipts(block)? {
-            // Receipts not in cache, delegate to inner
-            Either::Left(num) => self
+        let block = block.into();
+        if self.lags(Some(block.into())).await? {
+            self.ensure_same_chain().await?;
+            return self
                 .inner()
-                .get_block_receipts(*num)
+                .get_block_receipts(block)
                 .await
-                .map_err(FromErr::from),
+                .map_err(FromErr::from);
+        }
+        let res = if self.coalesce.get_block_receipts {
+            // read by hash, so a reorg during the flight can't hand its
+            // waiters the receipts of another block
+            match self.header_key(block) {
+                Ok(key) => {
+                    let db = Arc::clone(&self.db);
+                    self.flights
+                        .block_receipts
+                        .run(key, move || db.get_block_receipts_by_key(key))
+                        .await
+                }
+                Err(e) => Err(e),
+            }
+        } else {
+            self.db.get_block_receipts(block)
+        };
+        match res {
+            // Receipts not in cache, delegate to inner
+            Ok(Either::Left(num)) => {
+                self.ensure_same_chain().await?;
+                self.inner()
+                    .get_block_receipts(*num)
+                    .await
+                    .map_err(FromErr::from)
+            }
             // Got the receipts from the db, so return them
-            Either::Right(receipts) => Ok(receipts),
+            Ok(Either::Right(receipts)) => Ok(receipts),
+            Err(e) if e.is::<Pruned>() => {
+                self.ensure_same_chain().await?;
+                self.inner()
+                    .get_block_receipts(block)
+                    .await
+                    .map_err(FromErr::from)
+            }
+            Err(e) => Err(From::from(e)),
         }
     }
 }
@@ -213,3 +739,205 @@ impl<M: Middleware> From<anyhow::Error> for DbMiddlewareError<M> {
         DbMiddlewareError::Anyhow(src)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        admission::AdmissionConfig,
+        test::{chain::ChainBuilder, TMP_DIR},
+    };
+    use ethers::providers::Provider;
+
+    fn mismatch<M: Middleware>(err: DbMiddlewareError<M>) -> Option<ChainMismatch> {
+        match err {
+            DbMiddlewareError::Anyhow(e) => e.downcast_ref().copied(),
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_check_chain_id() -> Result<()> {
+        let chain = ChainBuilder::new()
+            .start(0)
+            .config(br#"{"chainId": 5}"#)
+            .block(|b| b)
+            .write(TMP_DIR.clone())?;
+        let db = Arc::new(Client::<mdbx::NoWriteMap>::open_new(chain.path)?);
+
+        let (inner, mock) = Provider::mocked();
+        mock.push(U256::from(5))?;
+        let mw = DbMiddleware::checked(inner, Arc::clone(&db))
+            .await
+            .expect("same chain")
+            .recheck_chain_id(Duration::ZERO);
+
+        // the inner provider has since moved to another chain
+        mock.push(U256::from(1))?;
+        let err = mw
+            .get_balance(Address::zero(), Some(0u64.into()))
+            .await
+            .unwrap_err();
+        let expected = ChainMismatch {
+            db: 5.into(),
+            inner: 1.into(),
+        };
+        assert_eq!(mismatch(err), Some(expected));
+
+        // filters unknown to the db are delegated behind the same check
+        mock.push(U256::from(1))?;
+        let err = mw
+            .get_filter_changes::<_, Log>(U256::from(7))
+            .await
+            .unwrap_err();
+        assert_eq!(mismatch(err), Some(expected));
+        mock.push(U256::from(1))?;
+        let err = mw.uninstall_filter(U256::from(7)).await.unwrap_err();
+        assert_eq!(mismatch(err), Some(expected));
+        mock.push(U256::from(1))?;
+        match mw.watch_pending_transactions().await {
+            Err(err) => assert_eq!(mismatch(err), Some(expected)),
+            Ok(_) => panic!("watched pending txs on another chain"),
+        }
+
+        let (inner, mock) = Provider::mocked();
+        mock.push(U256::from(1))?;
+        let err = DbMiddleware::checked(inner, db).await.unwrap_err();
+        assert_eq!(mismatch(err), Some(expected));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_watch_pending_transactions() -> Result<()> {
+        let chain = ChainBuilder::new().block(|b| b).write(TMP_DIR.clone())?;
+        let db = Arc::new(Client::<mdbx::NoWriteMap>::open_new(chain.path.clone())?);
+        let (inner, mock) = Provider::mocked();
+        let mw = DbMiddleware::new(inner, db);
+
+        // the filter is installed on the inner provider, which the watcher polls
+        mock.push(U256::from(9))?;
+        let watcher = mw.watch_pending_transactions().await.unwrap();
+        assert_eq!(watcher.id, U256::from(9));
+        assert!(!mw.client().has_filter(watcher.id));
+        // the db's pool is tailed through the client, which has no txpool db here
+        assert!(mw.client().watch_pending_transactions().is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_head_lag() -> Result<()> {
+        let chain = ChainBuilder::new()
+            .block(|b| b)
+            .block(|b| b)
+            .write(TMP_DIR.clone())?;
+        let db = Arc::new(Client::<mdbx::NoWriteMap>::open_new(chain.path.clone())?);
+
+        let (inner, mock) = Provider::mocked();
+        let mw = DbMiddleware::new(inner, Arc::clone(&db))
+            .head_lag(HeadLagPolicy::RouteToAhead)
+            .head_lag_ttl(Duration::ZERO);
+        // one response for the head comparison, one for the delegated read
+        mock.push(U64::from(10))?;
+        mock.push(U64::from(10))?;
+        assert_eq!(mw.get_block_number().await.unwrap(), 10.into());
+        // a behind inner provider is only asked for its head
+        mock.push(U64::from(1))?;
+        assert_eq!(mw.get_block_number().await.unwrap(), 2.into());
+        // reads at a number aren't compared
+        assert!(mw.get_block(1u64).await.unwrap().is_some());
+
+        let (inner, mock) = Provider::mocked();
+        let mw = DbMiddleware::new(inner, db)
+            .head_lag(HeadLagPolicy::Fail { max_lag: 5 })
+            .head_lag_ttl(Duration::ZERO);
+        mock.push(U64::from(7))?;
+        assert_eq!(mw.get_block_number().await.unwrap(), 2.into());
+        mock.push(U64::from(8))?;
+        let err = match mw
+            .get_block(ethers::types::BlockNumber::Latest)
+            .await
+            .unwrap_err()
+        {
+            DbMiddlewareError::Anyhow(e) => e.downcast_ref::<HeadLag>().copied(),
+            _ => None,
+        };
+        assert_eq!(
+            err,
+            Some(HeadLag {
+                db: 2.into(),
+                inner: 8.into()
+            })
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_head_lag_cached() -> Result<()> {
+        let chain = ChainBuilder::new()
+            .block(|b| b)
+            .block(|b| b)
+            .write(TMP_DIR.clone())?;
+        let db = Arc::new(Client::<mdbx::NoWriteMap>::open_new(chain.path.clone())?);
+        let (inner, mock) = Provider::mocked();
+        let mw = DbMiddleware::new(inner, db)
+            .head_lag(HeadLagPolicy::RouteToAhead)
+            .head_lag_ttl(Duration::from_secs(60));
+
+        // an inner provider which can't be asked for its head leaves the read to the db
+        assert_eq!(mw.get_block_number().await.unwrap(), 2.into());
+
+        // the head is asked for once, then reused
+        mock.push(U64::from(10))?;
+        mock.push(U64::from(10))?;
+        assert_eq!(mw.get_block_number().await.unwrap(), 10.into());
+        mock.push(U64::from(11))?;
+        assert_eq!(mw.get_block_number().await.unwrap(), 11.into());
+
+        // logs up to the latest block, and receipts of txs the db doesn't know
+        mock.push(vec![Log::default()])?;
+        let logs = mw.get_logs(&Filter::new().from_block(0u64)).await.unwrap();
+        assert_eq!(logs.len(), 1);
+        mock.push(TransactionReceipt::default())?;
+        let receipt = mw
+            .get_transaction_receipt(H256::repeat_byte(0xab))
+            .await
+            .unwrap();
+        assert!(receipt.is_some());
+
+        // logs of a closed range are still read from the db
+        let logs = mw
+            .get_logs(&Filter::new().from_block(0u64).to_block(2u64))
+            .await
+            .unwrap();
+        assert!(logs.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_admission_wait_off_worker() -> Result<()> {
+        let chain = ChainBuilder::new().block(|b| b).write(TMP_DIR.clone())?;
+        let db = Client::<mdbx::NoWriteMap>::open_new(chain.path.clone())?.with_admission(
+            AdmissionConfig {
+                max_running: 1,
+                max_queued: None,
+                queue_timeout: Some(Duration::from_secs(60)),
+            },
+        );
+        let db = Arc::new(db);
+        let (inner, _mock) = Provider::mocked();
+        let mw = DbMiddleware::new(inner, Arc::clone(&db));
+
+        // the test runtime has a single worker, which a queued get_logs
+        // waiting in place would hold, so the permit would never be dropped
+        let permit = db.admit()?;
+        let filter = Filter::new().from_block(0u64).to_block(1u64);
+        let logs =
+            tokio::spawn(async move { mw.get_logs(&filter).await.map_err(|e| e.to_string()) });
+        while db.admission_metrics().map_or(0, |m| m.queued) == 0 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        drop(permit);
+        assert_eq!(logs.await?, Ok(vec![]));
+        Ok(())
+    }
+}
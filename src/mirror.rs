@@ -0,0 +1,449 @@
+//! Mirrors selected tables into a SQLite file for ad-hoc SQL, behind the
+//! `sqlite` feature.
+//!
+//! Every table is keyed and indexed for the usual lookups:
+//!
+//! | Table          | Key                 | Indexed by                                  |
+//! |----------------|---------------------|---------------------------------------------|
+//! | `headers`      | `number`            | `hash`, `timestamp`                         |
+//! | `transactions` | `hash`              | `(block_number, transaction_index)`, `from_address`, `to_address` |
+//! | `receipts`     | `transaction_hash`  | `block_number`                              |
+//!
+//! The receipts table doesn't copy Erigon's Receipt table. It summarizes each
+//! receipt by where its transaction is and how many logs it emitted, counted
+//! from the logs, which a node pruning receipts (`--prune r`) doesn't keep
+//! for old blocks either.
+//!
+//! The mirror records the last block it copied, so `refresh` only copies the
+//! blocks added since. If that block is no longer canonical, the last
+//! `REORG_REWIND` blocks are dropped and copied again. If the mirrored header
+//! before them isn't canonical either, or headers aren't mirrored so it can't
+//! be checked, the reorg may be deeper and the whole mirror is copied again.
+
+use anyhow::Result;
+use ethers::types::H256;
+use mdbx::EnvironmentKind;
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use std::{collections::HashMap, path::Path};
+
+use crate::{
+    builder::PrunedData,
+    client::Client,
+    convert,
+    types::{BlockNum, HeaderKey},
+    utils::{BlockAssembler, FullTxs},
+};
+
+/// Blocks copied per SQLite transaction.
+const CHUNK_BLOCKS: u64 = 1000;
+/// How far a refresh rewinds when the last mirrored block has been reorged out.
+pub const REORG_REWIND: u64 = 64;
+
+pub(crate) const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS meta (
+    key TEXT PRIMARY KEY,
+    value BLOB NOT NULL
+);
+CREATE TABLE IF NOT EXISTS headers (
+    number INTEGER PRIMARY KEY,
+    hash BLOB NOT NULL,
+    parent_hash BLOB NOT NULL,
+    timestamp INTEGER NOT NULL,
+    miner BLOB NOT NULL,
+    gas_used INTEGER NOT NULL,
+    gas_limit INTEGER NOT NULL,
+    base_fee_per_gas BLOB,
+    transaction_count INTEGER NOT NULL
+);
+CREATE UNIQUE INDEX IF NOT EXISTS headers_hash ON headers (hash);
+CREATE INDEX IF NOT EXISTS headers_timestamp ON headers (timestamp);
+CREATE TABLE IF NOT EXISTS transactions (
+    hash BLOB PRIMARY KEY,
+    block_number INTEGER NOT NULL,
+    transaction_index INTEGER NOT NULL,
+    from_address BLOB NOT NULL,
+    to_address BLOB,
+    nonce INTEGER NOT NULL,
+    value BLOB NOT NULL
+);
+CREATE INDEX IF NOT EXISTS transactions_position ON transactions (block_number, transaction_index);
+CREATE INDEX IF NOT EXISTS transactions_from ON transactions (from_address);
+CREATE INDEX IF NOT EXISTS transactions_to ON transactions (to_address);
+CREATE TABLE IF NOT EXISTS receipts (
+    transaction_hash BLOB PRIMARY KEY,
+    block_number INTEGER NOT NULL,
+    transaction_index INTEGER NOT NULL,
+    log_count INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS receipts_block ON receipts (block_number);
+";
+
+/// The tables created by `SCHEMA`.
+pub(crate) const TABLE_NAMES: [&str; 4] = ["meta", "headers", "transactions", "receipts"];
+
+const WATERMARK_KEY: &str = "watermark";
+const WATERMARK_HASH_KEY: &str = "watermark_hash";
+
+/// The tables a `Mirror` can fill.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MirrorTable {
+    Headers,
+    Transactions,
+    Receipts,
+}
+
+/// What `Mirror::refresh` copied.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MirrorReport {
+    /// The first and last blocks copied, if any were.
+    pub range: Option<(u64, u64)>,
+    /// Whether previously mirrored blocks were dropped because of a reorg.
+    pub rewound: bool,
+    /// Whether every mirrored block was dropped because the reorg may have
+    /// been deeper than `REORG_REWIND` blocks.
+    pub resynced: bool,
+}
+
+/// A SQLite file mirroring some of the tables of a `Client`.
+#[derive(Debug)]
+pub struct Mirror {
+    conn: Connection,
+    tables: Vec<MirrorTable>,
+    start: u64,
+}
+
+impl Mirror {
+    /// Opens or creates the mirror at `path`, which fills every table from the
+    /// genesis block by default.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self {
+            conn,
+            tables: vec![
+                MirrorTable::Headers,
+                MirrorTable::Transactions,
+                MirrorTable::Receipts,
+            ],
+            start: 0,
+        })
+    }
+
+    /// Only fills `tables`. The others are created but left empty.
+    pub fn tables(mut self, tables: &[MirrorTable]) -> Self {
+        self.tables = tables.to_vec();
+        self
+    }
+
+    /// The block the first refresh of an empty mirror starts from.
+    pub fn start(mut self, num: u64) -> Self {
+        self.start = num;
+        self
+    }
+
+    /// Returns the number and hash of the last mirrored block.
+    pub fn watermark(&self) -> Result<Option<(u64, H256)>> {
+        let num = self.meta(WATERMARK_KEY)?;
+        let hash = self.meta(WATERMARK_HASH_KEY)?;
+        Ok(match (num, hash) {
+            (Some(num), Some(hash)) => Some((
+                u64::from_be_bytes(num.as_slice().try_into()?),
+                convert::h256(&hash)?,
+            )),
+            _ => None,
+        })
+    }
+
+    /// Copies the blocks after the watermark, up to the head of `client`.
+    pub fn refresh<E: EnvironmentKind>(&mut self, client: &Client<E>) -> Result<MirrorReport> {
+        let mut report = MirrorReport::default();
+        let head = client.get_block_number()?.as_u64();
+        let mut from = match self.watermark()? {
+            None => self.start,
+            Some((num, hash)) if num <= head && canonical_hash(client, num)? == Some(hash) => {
+                num + 1
+            }
+            Some((num, _)) => {
+                report.rewound = true;
+                let keep = num.min(head).saturating_sub(REORG_REWIND);
+                let keep_hash = canonical_hash(client, keep)?;
+                // the rows up to `keep` are only kept if it's still canonical
+                report.resynced = keep >= self.start
+                    && (keep_hash.is_none() || self.mirrored_hash(keep)? != keep_hash);
+                let tx = self.conn.transaction()?;
+                // an empty mirror starts from `start`, so keep nothing before it
+                let from = if keep < self.start || report.resynced {
+                    rewind(&tx, self.start.checked_sub(1), None)?;
+                    self.start
+                } else {
+                    rewind(&tx, Some(keep), keep_hash)?;
+                    keep + 1
+                };
+                tx.commit()?;
+                from
+            }
+        };
+
+        while from <= head {
+            let to = head.min(from + CHUNK_BLOCKS - 1);
+            let tx = self.conn.transaction()?;
+            let last_hash = copy_blocks(&tx, client, &self.tables, from, to)?;
+            set_meta(&tx, WATERMARK_KEY, &to.to_be_bytes())?;
+            set_meta(&tx, WATERMARK_HASH_KEY, last_hash.as_bytes())?;
+            tx.commit()?;
+
+            report.range = Some((report.range.map_or(from, |(start, _)| start), to));
+            from = to + 1;
+        }
+        Ok(report)
+    }
+
+    /// Returns the hash of block `num` in the headers table, if it's there.
+    fn mirrored_hash(&self, num: u64) -> Result<Option<H256>> {
+        let hash: Option<Vec<u8>> = self
+            .conn
+            .query_row(
+                "SELECT hash FROM headers WHERE number = ?1",
+                [i64::try_from(num)?],
+                |row| row.get(0),
+            )
+            .optional()?;
+        hash.map(|hash| convert::h256(&hash)).transpose()
+    }
+
+    fn meta(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self
+            .conn
+            .query_row("SELECT value FROM meta WHERE key = ?1", [key], |row| {
+                row.get(0)
+            })
+            .optional()?)
+    }
+}
+
+fn set_meta(tx: &Transaction<'_>, key: &str, value: &[u8]) -> Result<()> {
+    tx.execute(
+        "INSERT OR REPLACE INTO meta (key, value) VALUES (?1, ?2)",
+        params![key, value],
+    )?;
+    Ok(())
+}
+
+fn canonical_hash<E: EnvironmentKind>(client: &Client<E>, num: u64) -> Result<Option<H256>> {
+    Ok(client.get_header(num)?.and_then(|header| header.hash))
+}
+
+/// Deletes the rows of the blocks after `keep`, or every row if it's `None`, and
+/// moves the watermark back to it.
+fn rewind(tx: &Transaction<'_>, keep: Option<u64>, keep_hash: Option<H256>) -> Result<()> {
+    let after = match keep {
+        Some(keep) => i64::try_from(keep)?,
+        None => -1,
+    };
+    tx.execute("DELETE FROM headers WHERE number > ?1", [after])?;
+    tx.execute("DELETE FROM transactions WHERE block_number > ?1", [after])?;
+    tx.execute("DELETE FROM receipts WHERE block_number > ?1", [after])?;
+    match (keep, keep_hash) {
+        (Some(keep), Some(hash)) => {
+            set_meta(tx, WATERMARK_KEY, &keep.to_be_bytes())?;
+            set_meta(tx, WATERMARK_HASH_KEY, hash.as_bytes())?;
+        }
+        _ => {
+            tx.execute(
+                "DELETE FROM meta WHERE key IN (?1, ?2)",
+                [WATERMARK_KEY, WATERMARK_HASH_KEY],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Copies the blocks `from..=to` into the `tables`, returning the hash of `to`.
+/// The chunk is read in one read transaction, so its blocks and logs are all
+/// from the same chain.
+fn copy_blocks<E: EnvironmentKind>(
+    tx: &Transaction<'_>,
+    client: &Client<E>,
+    tables: &[MirrorTable],
+    from: u64,
+    to: u64,
+) -> Result<H256> {
+    let receipts = tables.contains(&MirrorTable::Receipts);
+    if receipts {
+        client.ensure_unpruned("Mirror::refresh", PrunedData::Receipts)?;
+    }
+    let mut dbtx = client.reader()?;
+    let assembler = BlockAssembler::<_, FullTxs>::new(client);
+
+    let mut last_hash = H256::zero();
+    for num in from..=to {
+        let key = HeaderKey::new(num, dbtx.read_canonical_hash(BlockNum(num))?);
+        let block = assembler.assemble(&mut dbtx, key)?;
+        let number = i64::try_from(num)?;
+        last_hash = key.hash;
+        // the number of logs emitted by each tx, by its index in the block
+        let log_counts = if receipts {
+            dbtx.read_block_logs(key.num)?
+                .into_iter()
+                .map(|(idx, logs)| Ok((u64::from(idx), i64::try_from(logs.len())?)))
+                .collect::<Result<HashMap<_, _>>>()?
+        } else {
+            HashMap::new()
+        };
+
+        if tables.contains(&MirrorTable::Headers) {
+            tx.execute(
+                "INSERT OR REPLACE INTO headers (number, hash, parent_hash, timestamp, miner,
+                    gas_used, gas_limit, base_fee_per_gas, transaction_count)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                params![
+                    number,
+                    last_hash.as_bytes(),
+                    block.parent_hash.as_bytes(),
+                    i64::try_from(block.timestamp.as_u64())?,
+                    block.author.as_bytes(),
+                    i64::try_from(block.gas_used.as_u64())?,
+                    i64::try_from(block.gas_limit.as_u64())?,
+                    block.base_fee_per_gas.map(convert::u256_bytes),
+                    i64::try_from(block.transactions.len())?,
+                ],
+            )?;
+        }
+        for (idx, t) in block.transactions.iter().enumerate() {
+            let idx = i64::try_from(idx)?;
+            if tables.contains(&MirrorTable::Transactions) {
+                tx.execute(
+                    "INSERT OR REPLACE INTO transactions (hash, block_number, transaction_index,
+                        from_address, to_address, nonce, value)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                    params![
+                        t.hash.as_bytes(),
+                        number,
+                        idx,
+                        t.from.as_bytes(),
+                        t.to.as_ref().map(|to| to.as_bytes()),
+                        i64::try_from(t.nonce.as_u64())?,
+                        convert::u256_bytes(t.value),
+                    ],
+                )?;
+            }
+            if tables.contains(&MirrorTable::Receipts) {
+                tx.execute(
+                    "INSERT OR REPLACE INTO receipts (transaction_hash, block_number,
+                        transaction_index, log_count)
+                     VALUES (?1, ?2, ?3, ?4)",
+                    params![
+                        t.hash.as_bytes(),
+                        number,
+                        idx,
+                        t.transaction_index
+                            .and_then(|i| log_counts.get(&i.as_u64()))
+                            .copied()
+                            .unwrap_or_default(),
+                    ],
+                )?;
+            }
+        }
+    }
+    Ok(last_hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::{Log, Receipt},
+        test::{chain::ChainBuilder, rand::rand_vec, TMP_DIR},
+    };
+    use ethers::types::Address;
+    use rand::thread_rng;
+
+    #[test]
+    fn test_mirror_refresh() -> Result<()> {
+        let chain = ChainBuilder::new()
+            .start(100)
+            .block(|b| b)
+            .block(|b| b)
+            .block(|b| b)
+            .write(TMP_DIR.clone())?;
+        let db = Client::<mdbx::NoWriteMap>::open_new(chain.path.clone())?;
+
+        let mut mirror = Mirror::open(":memory:")?.start(100);
+        let report = mirror.refresh(&db)?;
+        assert_eq!(report.range, Some((100, 102)));
+        assert!(!report.rewound);
+        assert_eq!(mirror.watermark()?, Some((102, chain.hash(2))));
+
+        let count: i64 = mirror
+            .conn
+            .query_row("SELECT COUNT(*) FROM headers", [], |row| row.get(0))?;
+        assert_eq!(count, 3);
+        let hash: Vec<u8> =
+            mirror
+                .conn
+                .query_row("SELECT hash FROM headers WHERE number = 101", [], |row| {
+                    row.get(0)
+                })?;
+        assert_eq!(H256::from_slice(&hash), chain.hash(1));
+
+        // nothing new to copy
+        assert_eq!(mirror.refresh(&db)?, MirrorReport::default());
+        Ok(())
+    }
+
+    #[test]
+    fn test_mirror_receipts() -> Result<()> {
+        let mut rng = thread_rng();
+        let log = Log {
+            address: Address::repeat_byte(1),
+            topics: vec![],
+            data: vec![].into(),
+        };
+        let chain = ChainBuilder::new()
+            .block(|b| {
+                b.txs(rand_vec(&mut rng, 2))
+                    .receipt(Receipt::default(), vec![log.clone(), log.clone()])
+                    .receipt(Receipt::default(), vec![])
+            })
+            .write(TMP_DIR.clone())?;
+        let db = Client::<mdbx::NoWriteMap>::open_new(chain.path.clone())?;
+
+        let mut mirror = Mirror::open(":memory:")?.start(1);
+        mirror.refresh(&db)?;
+        let counts = mirror
+            .conn
+            .prepare("SELECT log_count FROM receipts ORDER BY transaction_index")?
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<Vec<i64>, _>>()?;
+        assert_eq!(counts, vec![2, 0]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mirror_deep_reorg() -> Result<()> {
+        // two chains of the same length sharing no blocks, so the reorg from
+        // one to the other is deeper than the rewind
+        let write = || {
+            let mut chain = ChainBuilder::new().start(100);
+            for _ in 0..REORG_REWIND + 6 {
+                chain = chain.block(|b| b);
+            }
+            chain.write(TMP_DIR.clone())
+        };
+        let (old, new) = (write()?, write()?);
+        let last = 100 + REORG_REWIND + 5;
+
+        let mut mirror = Mirror::open(":memory:")?.start(100);
+        mirror.refresh(&Client::<mdbx::NoWriteMap>::open_new(old.path.clone())?)?;
+        let report = mirror.refresh(&Client::<mdbx::NoWriteMap>::open_new(new.path.clone())?)?;
+        assert!(report.rewound && report.resynced);
+        assert_eq!(report.range, Some((100, last)));
+        assert_eq!(
+            mirror.watermark()?,
+            Some((last, new.hash(REORG_REWIND as usize + 5)))
+        );
+        // no rows of the old chain are left behind
+        assert_eq!(mirror.mirrored_hash(100)?, Some(new.hash(0)));
+        Ok(())
+    }
+}
@@ -62,17 +62,55 @@ impl ak_traits::TableDecode for Account {
         Ok(acct)
     }
 }
-//TODO: dummy impl as we only need to decode for now, but need the trait bound
 impl ak_traits::TableEncode for Account {
     type Encoded = Vec<u8>;
+
+    /// Encodes the account in Erigon's storage format, omitting zero fields.
     fn encode(self) -> Self::Encoded {
-        Self::Encoded::default()
+        let mut fieldset = 0;
+        let mut out = vec![0];
+
+        if self.nonce > 0 {
+            fieldset |= 1;
+            put_bytes_with_len(&mut out, &self.nonce.to_be_bytes());
+        }
+
+        if !self.balance.is_zero() {
+            fieldset |= 2;
+            let mut bal = [0; 32];
+            self.balance.to_big_endian(&mut bal);
+            put_bytes_with_len(&mut out, &bal);
+        }
+
+        if self.incarnation > 0 {
+            fieldset |= 4;
+            put_bytes_with_len(&mut out, &self.incarnation.to_be_bytes());
+        }
+
+        if !self.codehash.is_zero() {
+            fieldset |= 8;
+            out.push(KECCAK_LENGTH as u8);
+            out.extend_from_slice(self.codehash.as_bytes());
+        }
+
+        if fieldset == 0 {
+            return vec![];
+        }
+        out[0] = fieldset;
+        out
     }
 }
 
+/// Writes a big-endian integer without its leading zeros, prefixed by its length.
+fn put_bytes_with_len(out: &mut Vec<u8>, be: &[u8]) {
+    let start = be.iter().position(|b| *b != 0).unwrap_or(be.len());
+    out.push((be.len() - start) as u8);
+    out.extend_from_slice(&be[start..]);
+}
+
 pub fn parse_u64_with_len(enc: &mut &[u8]) -> u64 {
     let len = enc.get_u8().into();
-    let val = crate::utils::bytes_to_u64(&enc[..len]);
+    let val = crate::codec::bytes_to_u64(&enc[..len]);
     enc.advance(len);
     val
 }
@@ -98,3 +136,34 @@ impl Account {
         self
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::strategy;
+    use ak_traits::{TableDecode, TableEncode};
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_account_encoding() -> anyhow::Result<()> {
+        assert!(Account::new().encode().is_empty());
+        // nonce 1, balance 256
+        let acct = Account::new().nonce(1).balance(256.into());
+        assert_eq!(acct.encode(), vec![3, 1, 1, 2, 1, 0]);
+        assert_eq!(Account::decode(&acct.encode())?, acct);
+        Ok(())
+    }
+
+    proptest! {
+        #[test]
+        fn prop_account_roundtrip(
+            nonce in any::<u64>(),
+            incarnation in any::<u64>(),
+            balance in strategy::u256(),
+            codehash in prop_oneof![Just(H256::zero()), strategy::h256()],
+        ) {
+            let acct = Account { nonce, incarnation, balance, codehash };
+            prop_assert_eq!(Account::decode(&acct.encode()).unwrap(), acct);
+        }
+    }
+}
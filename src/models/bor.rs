@@ -0,0 +1,126 @@
+use bytes::BufMut;
+use ethers::types::{Address, H256};
+use fastrlp::{Decodable, DecodeError, Encodable, Header};
+
+use super::{list_payload, Log};
+
+/// The receipt of a Polygon state-sync transaction, as stored rlp encoded in
+/// Erigon's BorReceipt table: the status, the cumulative gas used and the logs
+/// of the block's state syncs. Unlike the Receipt table, which is cbor encoded
+/// without logs, this is the `ReceiptForStorage` rlp written by Erigon's
+/// `rawdb.WriteBorReceipt`, keyed by the block number.
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+pub struct BorReceipt {
+    pub status: u64,
+    pub cumulative_gas_used: u64,
+    pub logs: Vec<Log>,
+}
+
+impl BorReceipt {
+    pub fn decode(mut enc: &[u8]) -> Result<Self, DecodeError> {
+        let mut payload = list_payload(&mut enc)?;
+        // the status is 0x01 on success, empty on failure
+        let status = u64::from(bytes::Bytes::decode(&mut payload)?.as_ref() == [1]);
+        let cumulative_gas_used = u64::decode(&mut payload)?;
+        let mut list = list_payload(&mut payload)?;
+        let mut logs = vec![];
+        while !list.is_empty() {
+            let mut log = list_payload(&mut list)?;
+            let address = Address::from(<[u8; 20]>::decode(&mut log)?);
+            let mut topic_list = list_payload(&mut log)?;
+            let mut topics = vec![];
+            while !topic_list.is_empty() {
+                topics.push(H256::from(<[u8; 32]>::decode(&mut topic_list)?));
+            }
+            let data = bytes::Bytes::decode(&mut log)?;
+            logs.push(Log {
+                address,
+                topics,
+                data,
+            });
+        }
+        if !payload.is_empty() {
+            return Err(DecodeError::Custom("bor receipt has trailing fields"));
+        }
+        Ok(Self {
+            status,
+            cumulative_gas_used,
+            logs,
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let logs = self
+            .logs
+            .iter()
+            .map(|log| {
+                let mut topics = vec![];
+                for topic in &log.topics {
+                    topic.0.encode(&mut topics);
+                }
+                let mut out = vec![];
+                log.address.0.encode(&mut out);
+                list_header(topics.len()).encode(&mut out);
+                out.extend(topics);
+                log.data.encode(&mut out);
+                out
+            })
+            .collect::<Vec<_>>();
+        let logs_length = logs.iter().map(Vec::len).sum::<usize>();
+
+        let mut payload = vec![];
+        let status = if self.status == 1 {
+            bytes::Bytes::from_static(&[1])
+        } else {
+            bytes::Bytes::new()
+        };
+        status.encode(&mut payload);
+        self.cumulative_gas_used.encode(&mut payload);
+        list_header(logs_length).encode(&mut payload);
+        for log in logs {
+            list_header(log.len()).encode(&mut payload);
+            payload.extend(log);
+        }
+        let mut out = vec![];
+        list_header(payload.len()).encode(&mut out);
+        out.put_slice(&payload);
+        out
+    }
+}
+
+fn list_header(payload_length: usize) -> Header {
+    Header {
+        list: true,
+        payload_length,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bor_receipt_roundtrip() -> Result<(), DecodeError> {
+        let receipt = BorReceipt {
+            status: 1,
+            cumulative_gas_used: 90_000,
+            logs: vec![
+                Log {
+                    address: Address::repeat_byte(0x11),
+                    topics: vec![H256::repeat_byte(0x22), H256::repeat_byte(0x33)],
+                    data: vec![0xab; 40].into(),
+                },
+                Log::default(),
+            ],
+        };
+        let enc = receipt.encode();
+        assert_eq!(BorReceipt::decode(&enc)?, receipt);
+        assert!(BorReceipt::decode(&enc[..enc.len() - 1]).is_err());
+
+        let failed = BorReceipt::default();
+        // an empty status, no gas and no logs
+        assert_eq!(failed.encode(), vec![0xc3, 0x80, 0x80, 0xc0]);
+        assert_eq!(BorReceipt::decode(&failed.encode())?, failed);
+        Ok(())
+    }
+}
@@ -0,0 +1,138 @@
+use bytes::Bytes;
+use ethers::types::{Address, H256, U256};
+use fastrlp::{Decodable, DecodeError, Encodable, Header};
+
+use super::list_payload;
+
+/// The envelope type of OP-stack deposit transactions.
+pub const DEPOSIT_TX_TYPE: u8 = 0x7e;
+
+/// An OP-stack deposit transaction, stored as `0x7e` followed by the rlp list
+/// of its fields. Deposits are unsigned: the sender is part of the payload.
+/// See the deposits spec in the optimism specs repo.
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+pub struct DepositTx {
+    pub source_hash: H256,
+    pub from: Address,
+    /// `None` for a contract creation.
+    pub to: Option<Address>,
+    /// The ether minted on L2, in wei.
+    pub mint: U256,
+    pub value: U256,
+    pub gas: u64,
+    pub is_system_tx: bool,
+    pub data: Bytes,
+}
+
+impl DepositTx {
+    /// Decodes a deposit from its stored envelope, type byte included.
+    pub fn decode(enc: &[u8]) -> Result<Self, DecodeError> {
+        let mut enc = match enc.split_first() {
+            Some((&DEPOSIT_TX_TYPE, rest)) => rest,
+            _ => return Err(DecodeError::Custom("not a deposit tx")),
+        };
+        let mut payload = list_payload(&mut enc)?;
+        let this = Self {
+            source_hash: H256::from(<[u8; 32]>::decode(&mut payload)?),
+            from: Address::from(<[u8; 20]>::decode(&mut payload)?),
+            to: match Bytes::decode(&mut payload)? {
+                to if to.is_empty() => None,
+                to if to.len() == 20 => Some(Address::from_slice(&to)),
+                _ => return Err(DecodeError::Custom("bad deposit recipient")),
+            },
+            mint: decode_u256(&mut payload)?,
+            value: decode_u256(&mut payload)?,
+            gas: u64::decode(&mut payload)?,
+            is_system_tx: match u64::decode(&mut payload)? {
+                0 => false,
+                1 => true,
+                _ => return Err(DecodeError::Custom("bad deposit system flag")),
+            },
+            data: Bytes::decode(&mut payload)?,
+        };
+        if !payload.is_empty() || !enc.is_empty() {
+            return Err(DecodeError::Custom("deposit tx has trailing bytes"));
+        }
+        Ok(this)
+    }
+
+    /// Encodes the deposit as it is stored, type byte included.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut payload = vec![];
+        self.source_hash.0.encode(&mut payload);
+        self.from.0.encode(&mut payload);
+        match self.to {
+            Some(to) => to.0.encode(&mut payload),
+            None => Bytes::new().encode(&mut payload),
+        }
+        encode_u256(self.mint, &mut payload);
+        encode_u256(self.value, &mut payload);
+        self.gas.encode(&mut payload);
+        u64::from(self.is_system_tx).encode(&mut payload);
+        self.data.encode(&mut payload);
+
+        let mut out = vec![DEPOSIT_TX_TYPE];
+        Header {
+            list: true,
+            payload_length: payload.len(),
+        }
+        .encode(&mut out);
+        out.extend(payload);
+        out
+    }
+}
+
+fn decode_u256(buf: &mut &[u8]) -> Result<U256, DecodeError> {
+    let enc = Bytes::decode(buf)?;
+    if enc.len() > 32 {
+        return Err(DecodeError::Overflow);
+    }
+    if enc.first() == Some(&0) {
+        return Err(DecodeError::LeadingZero);
+    }
+    Ok(U256::from_big_endian(&enc))
+}
+
+fn encode_u256(v: U256, out: &mut Vec<u8>) {
+    let mut word = [0u8; 32];
+    v.to_big_endian(&mut word);
+    let start = word.iter().position(|b| *b != 0).unwrap_or(32);
+    Bytes::copy_from_slice(&word[start..]).encode(out);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deposit_roundtrip() -> Result<(), DecodeError> {
+        let deposit = DepositTx {
+            source_hash: H256::repeat_byte(0x5c),
+            from: Address::repeat_byte(0xde),
+            to: Some(Address::repeat_byte(0x42)),
+            mint: U256::exp10(18),
+            value: 1_000.into(),
+            gas: 1_000_000,
+            is_system_tx: true,
+            data: Bytes::from_static(&[0x01, 0x5d, 0x8e, 0xb9]),
+        };
+        let enc = deposit.encode();
+        assert_eq!(enc[0], DEPOSIT_TX_TYPE);
+        assert_eq!(DepositTx::decode(&enc)?, deposit);
+
+        // contract creations have an empty recipient
+        let creation = DepositTx {
+            to: None,
+            is_system_tx: false,
+            ..deposit
+        };
+        assert_eq!(DepositTx::decode(&creation.encode())?, creation);
+
+        // other envelopes and trailing bytes
+        assert!(DepositTx::decode(&enc[1..]).is_err());
+        let mut trailing = enc;
+        trailing.push(0x80);
+        assert!(DepositTx::decode(&trailing).is_err());
+        Ok(())
+    }
+}
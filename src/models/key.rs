@@ -0,0 +1,131 @@
+use akula::kv::traits::{self as ak_traits, TableDecode};
+use anyhow::format_err;
+use ethers::types::H256;
+
+use crate::types::{BlockNum, HeaderKey, TxId};
+
+const U64_LENGTH: usize = std::mem::size_of::<u64>();
+pub const HEADER_KEY_LENGTH: usize = U64_LENGTH + H256::len_bytes();
+
+/// A table key of exactly `N` bytes. Unlike the `Vec<u8>` keys of erased tables,
+/// encoding a `FixedKey` doesn't allocate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct FixedKey<const N: usize>(pub [u8; N]);
+
+impl<const N: usize> ak_traits::TableEncode for FixedKey<N> {
+    type Encoded = [u8; N];
+
+    fn encode(self) -> Self::Encoded {
+        self.0
+    }
+}
+
+impl<const N: usize> ak_traits::TableDecode for FixedKey<N> {
+    fn decode(enc: &[u8]) -> anyhow::Result<Self> {
+        enc.try_into()
+            .map(Self)
+            .map_err(|_| format_err!("expected a {} byte key, got {}", N, enc.len()))
+    }
+}
+
+impl<const N: usize> From<FixedKey<N>> for Vec<u8> {
+    fn from(key: FixedKey<N>) -> Self {
+        key.0.to_vec()
+    }
+}
+
+impl From<TxId> for FixedKey<U64_LENGTH> {
+    fn from(id: TxId) -> Self {
+        Self(id.0.to_be_bytes())
+    }
+}
+
+impl From<HeaderKey> for FixedKey<HEADER_KEY_LENGTH> {
+    fn from(key: HeaderKey) -> Self {
+        let mut out = [0; HEADER_KEY_LENGTH];
+        out[..U64_LENGTH].copy_from_slice(&key.num.to_be_bytes());
+        out[U64_LENGTH..].copy_from_slice(key.hash.as_bytes());
+        Self(out)
+    }
+}
+
+impl From<FixedKey<HEADER_KEY_LENGTH>> for HeaderKey {
+    fn from(key: FixedKey<HEADER_KEY_LENGTH>) -> Self {
+        let (num, hash) = key.0.split_at(U64_LENGTH);
+        Self::new(
+            u64::from_be_bytes(num.try_into().unwrap()),
+            H256::from_slice(hash),
+        )
+    }
+}
+
+impl ak_traits::TableEncode for BlockNum {
+    type Encoded = [u8; U64_LENGTH];
+
+    fn encode(self) -> Self::Encoded {
+        self.0.to_be_bytes()
+    }
+}
+
+impl ak_traits::TableDecode for BlockNum {
+    fn decode(enc: &[u8]) -> anyhow::Result<Self> {
+        FixedKey::<U64_LENGTH>::decode(enc).map(|k| Self(u64::from_be_bytes(k.0)))
+    }
+}
+
+impl ak_traits::TableEncode for HeaderKey {
+    type Encoded = [u8; HEADER_KEY_LENGTH];
+
+    fn encode(self) -> Self::Encoded {
+        FixedKey::from(self).0
+    }
+}
+
+impl ak_traits::TableDecode for HeaderKey {
+    fn decode(enc: &[u8]) -> anyhow::Result<Self> {
+        FixedKey::<HEADER_KEY_LENGTH>::decode(enc).map(From::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::strategy;
+    use akula::kv::{
+        tables as ak_tables,
+        traits::{TableDecode, TableEncode},
+    };
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_header_key() -> anyhow::Result<()> {
+        let key = HeaderKey::new(7, H256::repeat_byte(0xab));
+        let fixed = FixedKey::from(key);
+        assert_eq!(
+            fixed.encode()[..],
+            ak_tables::HeaderKey::from(key).encode()[..]
+        );
+        assert_eq!(FixedKey::<HEADER_KEY_LENGTH>::decode(&fixed.0)?, fixed);
+        assert!(FixedKey::<HEADER_KEY_LENGTH>::decode(&fixed.0[1..]).is_err());
+
+        assert_eq!(key.encode(), fixed.0);
+        assert_eq!(HeaderKey::decode(&key.encode())?, key);
+        assert_eq!(HeaderKey::from(fixed), key);
+        assert_eq!(BlockNum::decode(&BlockNum(7).encode())?, key.num);
+        Ok(())
+    }
+
+    proptest! {
+        #[test]
+        fn prop_key_roundtrip(num in any::<u64>(), hash in strategy::h256()) {
+            let key = HeaderKey::new(num, hash);
+            prop_assert_eq!(HeaderKey::decode(&key.encode()).unwrap(), key);
+            prop_assert_eq!(BlockNum::decode(&key.num.encode()).unwrap(), key.num);
+            let fixed = FixedKey::from(key);
+            prop_assert_eq!(FixedKey::<HEADER_KEY_LENGTH>::decode(&fixed.encode()).unwrap(), fixed);
+            // big-endian keys sort by block number
+            let next = HeaderKey::new(num.saturating_add(1), hash);
+            prop_assert!(key.encode() <= next.encode());
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use anyhow::{format_err, Result};
+use ethers::types::{Address, H256};
+
+use crate::{cbor, convert};
+
+/// A log as stored in Erigon's TransactionLog table, without any block or
+/// transaction context.
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+pub struct Log {
+    pub address: Address,
+    pub topics: Vec<H256>,
+    pub data: bytes::Bytes,
+}
+
+impl Log {
+    /// Decodes the CBOR encoded list of logs emitted by one transaction.
+    pub fn decode_list(mut enc: &[u8]) -> Result<Vec<Self>> {
+        cbor::decode(&mut enc)?
+            .as_array()?
+            .iter()
+            .map(Self::from_cbor)
+            .collect()
+    }
+
+    /// Encodes the logs emitted by one transaction as a CBOR list, with each log
+    /// in the `toarray` form.
+    pub fn encode_list(logs: &[Self]) -> Vec<u8> {
+        let mut out = vec![];
+        let val = cbor::Value::Array(logs.iter().map(Self::to_cbor).collect());
+        cbor::encode(&val, &mut out);
+        out
+    }
+
+    fn to_cbor(&self) -> cbor::Value {
+        cbor::Value::Array(vec![
+            cbor::Value::Bytes(self.address.as_bytes().to_vec()),
+            cbor::Value::Array(
+                self.topics
+                    .iter()
+                    .map(|t| cbor::Value::Bytes(t.as_bytes().to_vec()))
+                    .collect(),
+            ),
+            cbor::Value::Bytes(self.data.to_vec()),
+        ])
+    }
+
+    fn from_cbor(val: &cbor::Value) -> Result<Self> {
+        let field = |idx, tag| {
+            val.field(idx, tag)
+                .ok_or_else(|| format_err!("log missing field {}", tag))
+        };
+        let address = convert::address(field(0, "1")?.as_bytes()?)?;
+        let topics = field(1, "2")?
+            .as_array()?
+            .iter()
+            .map(|t| convert::h256(t.as_bytes()?))
+            .collect::<Result<Vec<_>>>()?;
+        let data = field(2, "3")?.as_bytes()?;
+
+        Ok(Self {
+            address,
+            topics,
+            data: bytes::Bytes::copy_from_slice(data),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::strategy;
+    use proptest::prelude::*;
+
+    fn log() -> impl Strategy<Value = Log> {
+        (
+            strategy::address(),
+            prop::collection::vec(strategy::h256(), 0..=4),
+            any::<Vec<u8>>(),
+        )
+            .prop_map(|(address, topics, data)| Log {
+                address,
+                topics,
+                data: data.into(),
+            })
+    }
+
+    proptest! {
+        #[test]
+        fn prop_log_roundtrip(logs in prop::collection::vec(log(), 0..8)) {
+            prop_assert_eq!(Log::decode_list(&Log::encode_list(&logs)).unwrap(), logs);
+        }
+    }
+}
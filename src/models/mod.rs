@@ -1,4 +1,21 @@
 mod account;
+#[cfg(feature = "polygon")]
+mod bor;
+mod deposit;
+mod key;
+mod log;
+mod receipt;
 mod storage;
+mod trace;
+mod withdrawal;
 pub use account::*;
+#[cfg(feature = "polygon")]
+pub use bor::*;
+pub use deposit::*;
+pub use key::*;
+pub use log::*;
+pub use receipt::*;
 pub use storage::*;
+pub use trace::*;
+use withdrawal::list_payload;
+pub use withdrawal::*;
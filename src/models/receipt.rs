@@ -0,0 +1,122 @@
+use anyhow::{format_err, Result};
+use ethers::types::H256;
+
+use crate::cbor;
+
+/// A receipt as stored in Erigon's Receipt table. The logs are stored apart,
+/// in the TransactionLog table, and the remaining fields are derived from the
+/// block and its transactions.
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+pub struct Receipt {
+    /// The post-transaction state root of pre-Byzantium receipts. Empty for
+    /// later receipts, which carry a status instead.
+    pub post_state: Vec<u8>,
+    pub status: u64,
+    pub cumulative_gas_used: u64,
+}
+
+impl Receipt {
+    /// The post-transaction state root, for receipts from before Byzantium
+    /// (EIP-658). Their status is meaningless.
+    pub fn root(&self) -> Option<H256> {
+        (self.post_state.len() == H256::len_bytes()).then(|| H256::from_slice(&self.post_state))
+    }
+
+    /// Decodes the CBOR encoded list of receipts of one block.
+    pub fn decode_list(mut enc: &[u8]) -> Result<Vec<Self>> {
+        cbor::decode(&mut enc)?
+            .as_array()?
+            .iter()
+            .map(Self::from_cbor)
+            .collect()
+    }
+
+    /// Decodes a single CBOR encoded receipt, as split from a block's list.
+    pub fn decode(mut enc: &[u8]) -> Result<Self> {
+        Self::from_cbor(&cbor::decode(&mut enc)?)
+    }
+
+    /// Encodes the receipts of one block as a CBOR list, with each receipt in
+    /// the `toarray` form.
+    pub fn encode_list(receipts: &[Self]) -> Vec<u8> {
+        let mut out = vec![];
+        let val = cbor::Value::Array(receipts.iter().map(Self::to_cbor).collect());
+        cbor::encode(&val, &mut out);
+        out
+    }
+
+    fn to_cbor(&self) -> cbor::Value {
+        cbor::Value::Array(vec![
+            cbor::Value::Bytes(self.post_state.clone()),
+            cbor::Value::Uint(self.status),
+            cbor::Value::Uint(self.cumulative_gas_used),
+        ])
+    }
+
+    fn from_cbor(val: &cbor::Value) -> Result<Self> {
+        let field = |idx, tag| {
+            val.field(idx, tag)
+                .ok_or_else(|| format_err!("receipt missing field {}", tag))
+        };
+        // a 32 byte root before Byzantium, and empty or null after it
+        let post_state = field(0, "1")?.as_bytes()?;
+        if !post_state.is_empty() && post_state.len() != H256::len_bytes() {
+            return Err(format_err!(
+                "bad receipt post state length: {}",
+                post_state.len()
+            ));
+        }
+        Ok(Self {
+            post_state: post_state.to_vec(),
+            status: field(1, "2")?.as_u64()?,
+            cumulative_gas_used: field(2, "3")?.as_u64()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn receipt() -> impl Strategy<Value = Receipt> {
+        (
+            prop_oneof![Just(vec![]), prop::collection::vec(any::<u8>(), 32)],
+            0..=1u64,
+            any::<u64>(),
+        )
+            .prop_map(|(post_state, status, cumulative_gas_used)| Receipt {
+                post_state,
+                status,
+                cumulative_gas_used,
+            })
+    }
+
+    #[test]
+    fn test_decode_post_state() -> Result<()> {
+        // [{"1": h'11..11', "2": 0, "3": 21000}, {"1": null, "2": 1, "3": 42000}]
+        let mut enc = hex::decode("82a361315820")?;
+        enc.extend([0x11; 32]);
+        enc.extend(hex::decode(
+            "61320061331952 08a361 31f6613201613319a410".replace(' ', ""),
+        )?);
+        let receipts = Receipt::decode_list(&enc)?;
+        assert_eq!(receipts[0].root(), Some(H256::repeat_byte(0x11)));
+        assert_eq!(receipts[0].cumulative_gas_used, 21_000);
+        assert_eq!(receipts[1].root(), None);
+        assert_eq!(receipts[1].status, 1);
+        assert_eq!(receipts[1].cumulative_gas_used, 42_000);
+
+        // a post state that is neither a root nor empty
+        let bad = hex::decode("818341aa0100")?;
+        assert!(Receipt::decode_list(&bad).is_err());
+        Ok(())
+    }
+
+    proptest! {
+        #[test]
+        fn prop_receipt_roundtrip(receipts in prop::collection::vec(receipt(), 0..8)) {
+            prop_assert_eq!(Receipt::decode_list(&Receipt::encode_list(&receipts)).unwrap(), receipts);
+        }
+    }
+}
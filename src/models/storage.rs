@@ -1,3 +1,4 @@
+use akula::kv::traits::{TableDecode, TableEncode};
 use ethers::types::Address;
 
 const ADDRESS_LENGTH: usize = Address::len_bytes();
@@ -17,7 +18,7 @@ impl StorageBucket {
     }
 }
 
-impl akula::kv::TableEncode for StorageBucket {
+impl TableEncode for StorageBucket {
     type Encoded = [u8; ADDRESS_LENGTH + U64_LENGTH];
 
     fn encode(self) -> Self::Encoded {
@@ -27,9 +28,42 @@ impl akula::kv::TableEncode for StorageBucket {
         out
     }
 }
-//TODO: dummy impl as we only need to encode for now, but need the trait bound
-impl akula::kv::TableDecode for StorageBucket {
-    fn decode(_enc: &[u8]) -> anyhow::Result<Self> {
-        Ok(Default::default())
+impl TableDecode for StorageBucket {
+    fn decode(enc: &[u8]) -> anyhow::Result<Self> {
+        if enc.len() != ADDRESS_LENGTH + U64_LENGTH {
+            return Err(anyhow::format_err!(
+                "storage bucket should be {} bytes long. Got {} instead",
+                ADDRESS_LENGTH + U64_LENGTH,
+                enc.len()
+            ));
+        }
+        Ok(Self {
+            address: Address::decode(&enc[..ADDRESS_LENGTH])?,
+            incarnation: u64::decode(&enc[ADDRESS_LENGTH..])?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::strategy;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_storage_bucket_codec() -> anyhow::Result<()> {
+        let bucket = StorageBucket::new(Address::repeat_byte(0x11), 3);
+        let enc = bucket.encode();
+        assert_eq!(StorageBucket::decode(&enc)?, bucket);
+        assert!(StorageBucket::decode(&enc[1..]).is_err());
+        Ok(())
+    }
+
+    proptest! {
+        #[test]
+        fn prop_storage_bucket_roundtrip(address in strategy::address(), incarnation in any::<u64>()) {
+            let bucket = StorageBucket::new(address, incarnation);
+            prop_assert_eq!(StorageBucket::decode(&bucket.encode()).unwrap(), bucket);
+        }
     }
 }
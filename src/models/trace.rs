@@ -0,0 +1,71 @@
+use anyhow::Result;
+use ethers::types::Address;
+
+use crate::convert;
+
+const FLAG_FROM: u8 = 1;
+const FLAG_TO: u8 = 2;
+
+/// An entry of Erigon's CallTraceSet table: an address which appears in the
+/// block's call traces, and whether it was a sender, a recipient, or both.
+/// Internal calls are included.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct CallTrace {
+    pub address: Address,
+    pub from: bool,
+    pub to: bool,
+}
+
+impl CallTrace {
+    /// Decodes a value of the table: the address followed by a flags byte.
+    pub fn decode(enc: &[u8]) -> Result<Self> {
+        anyhow::ensure!(
+            enc.len() == Address::len_bytes() + 1,
+            "bad call trace length: {}",
+            enc.len()
+        );
+        let (address, flags) = enc.split_at(Address::len_bytes());
+        Ok(Self {
+            address: convert::address(address)?,
+            from: flags[0] & FLAG_FROM != 0,
+            to: flags[0] & FLAG_TO != 0,
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut flags = 0;
+        if self.from {
+            flags |= FLAG_FROM;
+        }
+        if self.to {
+            flags |= FLAG_TO;
+        }
+        [self.address.as_bytes(), &[flags]].concat()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_call_trace() -> Result<()> {
+        let mut enc = vec![0x11; 20];
+        enc.push(3);
+        let trace = CallTrace::decode(&enc)?;
+        assert_eq!(
+            trace,
+            CallTrace {
+                address: Address::repeat_byte(0x11),
+                from: true,
+                to: true,
+            }
+        );
+        assert_eq!(trace.encode(), enc);
+
+        enc[20] = FLAG_TO;
+        assert!(!CallTrace::decode(&enc)?.from);
+        assert!(CallTrace::decode(&enc[..20]).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,171 @@
+use bytes::{Buf, BufMut};
+use ethers::types::Address;
+use fastrlp::{Decodable, DecodeError, Encodable, Header};
+
+/// A beacon chain withdrawal, as included in the body of a post-Shanghai block.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct Withdrawal {
+    pub index: u64,
+    pub validator_index: u64,
+    pub address: Address,
+    /// The amount credited to `address`, in gwei.
+    pub amount: u64,
+}
+
+impl Withdrawal {
+    /// Decodes the withdrawals of an rlp encoded BodyForStorage. Bodies written
+    /// before Shanghai have no withdrawals field.
+    pub fn decode_from_body(mut enc: &[u8]) -> Result<Vec<Self>, DecodeError> {
+        let mut payload = list_payload(&mut enc)?;
+        // base tx id, tx amount and uncles
+        for _ in 0..3 {
+            skip_item(&mut payload)?;
+        }
+        if payload.is_empty() {
+            return Ok(vec![]);
+        }
+        let mut list = list_payload(&mut payload)?;
+        let mut out = vec![];
+        while !list.is_empty() {
+            out.push(Self::decode(&mut list)?);
+        }
+        Ok(out)
+    }
+
+    fn payload_length(&self) -> usize {
+        self.index.length()
+            + self.validator_index.length()
+            + self.address.0.length()
+            + self.amount.length()
+    }
+}
+
+impl Decodable for Withdrawal {
+    fn decode(buf: &mut &[u8]) -> Result<Self, DecodeError> {
+        let mut payload = list_payload(buf)?;
+        let this = Self {
+            index: u64::decode(&mut payload)?,
+            validator_index: u64::decode(&mut payload)?,
+            address: <[u8; 20]>::decode(&mut payload)?.into(),
+            amount: u64::decode(&mut payload)?,
+        };
+        if !payload.is_empty() {
+            return Err(DecodeError::Custom("withdrawal has trailing fields"));
+        }
+        Ok(this)
+    }
+}
+
+impl Encodable for Withdrawal {
+    fn length(&self) -> usize {
+        let payload_length = self.payload_length();
+        payload_length + fastrlp::length_of_length(payload_length)
+    }
+
+    fn encode(&self, out: &mut dyn BufMut) {
+        Header {
+            list: true,
+            payload_length: self.payload_length(),
+        }
+        .encode(out);
+        self.index.encode(out);
+        self.validator_index.encode(out);
+        self.address.0.encode(out);
+        self.amount.encode(out);
+    }
+}
+
+/// Splits the payload of the list at the front of `buf` off of `buf`.
+pub(super) fn list_payload<'a>(buf: &mut &'a [u8]) -> Result<&'a [u8], DecodeError> {
+    let h = Header::decode(buf)?;
+    if !h.list {
+        return Err(DecodeError::UnexpectedString);
+    }
+    take_payload(buf, h.payload_length)
+}
+
+/// Advances `buf` past the item at its front.
+fn skip_item(buf: &mut &[u8]) -> Result<(), DecodeError> {
+    let h = Header::decode(buf)?;
+    take_payload(buf, h.payload_length).map(|_| ())
+}
+
+fn take_payload<'a>(buf: &mut &'a [u8], len: usize) -> Result<&'a [u8], DecodeError> {
+    if buf.len() < len {
+        return Err(DecodeError::InputTooShort);
+    }
+    let payload = &buf[..len];
+    buf.advance(len);
+    Ok(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::strategy;
+    use proptest::prelude::*;
+
+    fn encode_body(withdrawals: Option<&[Withdrawal]>) -> Vec<u8> {
+        let mut payload = vec![];
+        7u64.encode(&mut payload);
+        2u32.encode(&mut payload);
+        // no uncles
+        Header {
+            list: true,
+            payload_length: 0,
+        }
+        .encode(&mut payload);
+        if let Some(ws) = withdrawals {
+            fastrlp::encode_list::<Withdrawal, _>(ws, &mut payload);
+        }
+        let mut out = vec![];
+        Header {
+            list: true,
+            payload_length: payload.len(),
+        }
+        .encode(&mut out);
+        out.extend(payload);
+        out
+    }
+
+    #[test]
+    fn test_decode_withdrawals() -> Result<(), DecodeError> {
+        let ws = [
+            Withdrawal {
+                index: 1,
+                validator_index: 1000,
+                address: Address::repeat_byte(0xaa),
+                amount: 32_000_000_000,
+            },
+            Withdrawal {
+                index: 2,
+                validator_index: 0,
+                address: Address::repeat_byte(0xbb),
+                amount: 0,
+            },
+        ];
+        let body = encode_body(Some(&ws));
+        assert_eq!(Withdrawal::decode_from_body(&body)?, ws);
+
+        let body = encode_body(None);
+        assert_eq!(Withdrawal::decode_from_body(&body)?, vec![]);
+        assert!(Withdrawal::decode_from_body(&body[..body.len() - 1]).is_err());
+        Ok(())
+    }
+
+    proptest! {
+        #[test]
+        fn prop_withdrawal_roundtrip(
+            index in any::<u64>(),
+            validator_index in any::<u64>(),
+            address in strategy::address(),
+            amount in any::<u64>(),
+        ) {
+            let w = Withdrawal { index, validator_index, address, amount };
+            let mut enc = vec![];
+            w.encode(&mut enc);
+            prop_assert_eq!(enc.len(), w.length());
+            prop_assert_eq!(Withdrawal::decode(&mut &enc[..]).unwrap(), w);
+        }
+    }
+}
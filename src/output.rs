@@ -0,0 +1,226 @@
+//! Adjustments to the JSON returned by the serving layers, so their output can
+//! match a particular node's RPC bit for bit. The `Client` methods return
+//! ethers types, whose serialization differs from both geth and erigon in a
+//! few places; an `OutputConfig` rewrites the serialized value.
+
+use ethers::{types::Address, utils::to_checksum};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fmt;
+
+use crate::codec::y_parity;
+
+// The fields holding addresses, across blocks, transactions, receipts and logs
+const ADDRESS_FIELDS: &[&str] = &["address", "contractAddress", "from", "miner", "to"];
+
+/// How addresses are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AddressCase {
+    /// All lowercase, as geth and erigon write them.
+    Lower,
+    /// EIP-55 mixed-case checksums.
+    Eip55,
+    /// EIP-1191 checksums, which include the chain id.
+    Eip1191(u8),
+}
+
+impl Default for AddressCase {
+    fn default() -> Self {
+        Self::Lower
+    }
+}
+
+/// Output options for the serving layers. The default leaves the ethers
+/// serialization untouched.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutputConfig {
+    /// Adds `yParity` alongside `v` to typed transactions.
+    pub y_parity: bool,
+    /// Writes `"type": "0x0"` on legacy transactions and receipts instead of
+    /// omitting it.
+    pub legacy_type: bool,
+    pub addresses: AddressCase,
+}
+
+impl OutputConfig {
+    /// Matches geth's RPC output.
+    pub fn geth() -> Self {
+        Self {
+            y_parity: true,
+            legacy_type: true,
+            addresses: AddressCase::Lower,
+        }
+    }
+
+    /// Matches erigon's RPC output.
+    pub fn erigon() -> Self {
+        Self {
+            y_parity: false,
+            legacy_type: true,
+            addresses: AddressCase::Lower,
+        }
+    }
+
+    /// Rewrites every transaction, receipt and log in `value`, however deeply
+    /// nested.
+    pub fn apply(&self, value: &mut Value) {
+        if *self == Self::default() {
+            return;
+        }
+        match value {
+            Value::Array(items) => items.iter_mut().for_each(|item| self.apply(item)),
+            Value::Object(obj) => {
+                self.apply_object(obj);
+                obj.values_mut().for_each(|item| self.apply(item));
+            }
+            _ => (),
+        }
+    }
+
+    fn apply_object(&self, obj: &mut Map<String, Value>) {
+        let is_tx = obj.contains_key("nonce") && obj.contains_key("v");
+        let is_receipt =
+            obj.contains_key("transactionHash") && obj.contains_key("cumulativeGasUsed");
+
+        if self.legacy_type && (is_tx || is_receipt) && !obj.contains_key("type") {
+            obj.insert("type".into(), "0x0".into());
+        }
+        if self.y_parity && is_tx && obj.get("type").map_or(false, |t| t != "0x0") {
+            let parity = obj.get("v").and_then(quantity).map(y_parity);
+            if let Some(parity) = parity {
+                obj.insert("yParity".into(), format!("{:#x}", parity).into());
+            }
+        }
+        if self.addresses != AddressCase::Lower {
+            for field in ADDRESS_FIELDS {
+                if let Some(val) = obj.get_mut(*field) {
+                    self.checksum(val);
+                }
+            }
+        }
+    }
+
+    fn checksum(&self, val: &mut Value) {
+        let addr = match val.as_str().and_then(|s| s.parse::<Address>().ok()) {
+            Some(addr) => addr,
+            None => return,
+        };
+        *val = match self.addresses {
+            AddressCase::Lower => return,
+            AddressCase::Eip55 => to_checksum(&addr, None),
+            AddressCase::Eip1191(chain_id) => to_checksum(&addr, Some(chain_id)),
+        }
+        .into();
+    }
+}
+
+/// Looks up human-readable labels for addresses, which the serving layers
+/// attach to their output. `UserData` implements it over the labels set with
+/// `UserData::set_label`.
+pub trait AddressLabeler: fmt::Debug + Send + Sync {
+    fn label(&self, address: &Address) -> Option<String>;
+}
+
+/// Adds a `<field>Label` beside every address field in `value` whose address
+/// `labeler` has a label for, however deeply nested.
+pub fn label_addresses(labeler: &dyn AddressLabeler, value: &mut Value) {
+    match value {
+        Value::Array(items) => items
+            .iter_mut()
+            .for_each(|item| label_addresses(labeler, item)),
+        Value::Object(obj) => {
+            let labels = ADDRESS_FIELDS
+                .iter()
+                .filter_map(|field| {
+                    let addr = obj.get(*field)?.as_str()?.parse::<Address>().ok()?;
+                    Some((format!("{}Label", field), labeler.label(&addr)?))
+                })
+                .collect::<Vec<_>>();
+            obj.values_mut()
+                .for_each(|item| label_addresses(labeler, item));
+            for (field, label) in labels {
+                obj.insert(field, label.into());
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Parses a `0x` prefixed hex quantity.
+fn quantity(val: &Value) -> Option<u64> {
+    u64::from_str_radix(val.as_str()?.strip_prefix("0x")?, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::types::{Transaction, TransactionReceipt, U64};
+
+    #[test]
+    fn test_output_config() -> anyhow::Result<()> {
+        let from: Address = "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed".parse()?;
+        let legacy = Transaction {
+            from,
+            v: 37.into(),
+            ..Default::default()
+        };
+        let typed = Transaction {
+            v: 1.into(),
+            transaction_type: Some(U64::from(2)),
+            ..Default::default()
+        };
+        let receipt = TransactionReceipt::default();
+        let orig = serde_json::to_value((&legacy, &typed, &receipt))?;
+
+        let mut val = orig.clone();
+        OutputConfig::default().apply(&mut val);
+        assert_eq!(val, orig);
+
+        OutputConfig::geth().apply(&mut val);
+        assert_eq!(val[0]["type"], "0x0");
+        assert_eq!(val[0].get("yParity"), None);
+        assert_eq!(val[1]["type"], "0x2");
+        assert_eq!(val[1]["yParity"], "0x1");
+        assert_eq!(val[2]["type"], "0x0");
+        assert_eq!(val[0]["from"], "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed");
+
+        let mut val = orig;
+        let config = OutputConfig {
+            addresses: AddressCase::Eip55,
+            ..Default::default()
+        };
+        config.apply(&mut val);
+        assert_eq!(val[0]["from"], "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed");
+        assert_eq!(val[0].get("type"), None);
+        Ok(())
+    }
+
+    #[derive(Debug)]
+    struct Labels(Address);
+
+    impl AddressLabeler for Labels {
+        fn label(&self, address: &Address) -> Option<String> {
+            (*address == self.0).then(|| "treasury".to_string())
+        }
+    }
+
+    #[test]
+    fn test_label_addresses() -> anyhow::Result<()> {
+        let treasury = Address::repeat_byte(0x11);
+        let receipt = TransactionReceipt {
+            from: treasury,
+            to: Some(Address::repeat_byte(0x22)),
+            logs: vec![ethers::types::Log {
+                address: treasury,
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let mut val = serde_json::to_value(&receipt)?;
+        label_addresses(&Labels(treasury), &mut val);
+        assert_eq!(val["fromLabel"], "treasury");
+        assert_eq!(val.get("toLabel"), None);
+        assert_eq!(val["logs"][0]["addressLabel"], "treasury");
+        Ok(())
+    }
+}
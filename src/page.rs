@@ -0,0 +1,123 @@
+use anyhow::{format_err, Result};
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+/// One page of results from an unbounded query. If `next_cursor` is `Some`, more
+/// results are available and can be fetched by passing the cursor back to the
+/// same query.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<PageCursor>,
+}
+
+impl<T> Page<T> {
+    pub fn is_last(&self) -> bool {
+        self.next_cursor.is_none()
+    }
+}
+
+impl<T> Default for Page<T> {
+    fn default() -> Self {
+        Self {
+            items: Vec::new(),
+            next_cursor: None,
+        }
+    }
+}
+
+/// An opaque resume key. The contents are only meaningful to the query that
+/// produced it. Cursors are hex encoded when displayed or serialized so they can
+/// be passed through json-rpc.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct PageCursor(Vec<u8>);
+
+impl PageCursor {
+    pub(crate) fn new<T: Into<Vec<u8>>>(key: T) -> Self {
+        Self(key.into())
+    }
+
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Interprets the cursor as a fixed size key.
+    pub(crate) fn to_array<const N: usize>(&self) -> Result<[u8; N]> {
+        self.0
+            .as_slice()
+            .try_into()
+            .map_err(|_| format_err!("bad page cursor length: {}", self.0.len()))
+    }
+}
+
+impl fmt::Debug for PageCursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PageCursor({})", self)
+    }
+}
+
+impl fmt::Display for PageCursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{}", hex::encode(&self.0))
+    }
+}
+
+impl FromStr for PageCursor {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(Self(hex::decode(s.trim_start_matches("0x"))?))
+    }
+}
+
+impl Serialize for PageCursor {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for PageCursor {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Collects at most `limit` items from `iter`, which yields (resume key, item)
+/// pairs. The key of the first item that doesn't fit becomes the next cursor.
+pub(crate) fn paginate<K, T, I>(iter: I, limit: usize) -> Result<Page<T>>
+where
+    K: Into<Vec<u8>>,
+    I: IntoIterator<Item = Result<(K, T)>>,
+{
+    let mut page = Page::default();
+    for res in iter {
+        let (key, item) = res?;
+        if page.items.len() == limit {
+            page.next_cursor = Some(PageCursor::new(key));
+            break;
+        }
+        page.items.push(item);
+    }
+    Ok(page)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paginate() -> Result<()> {
+        let iter = (0u64..5).map(|i| Ok((i.to_be_bytes().to_vec(), i)));
+        let page = paginate(iter, 3)?;
+        assert_eq!(page.items, vec![0, 1, 2]);
+        let cursor = page.next_cursor.unwrap();
+        assert_eq!(u64::from_be_bytes(cursor.to_array()?), 3);
+
+        let roundtrip: PageCursor = cursor.to_string().parse()?;
+        assert_eq!(roundtrip, cursor);
+
+        let iter = (0u64..3).map(|i| Ok((i.to_be_bytes().to_vec(), i)));
+        assert!(paginate(iter, 3)?.is_last());
+        Ok(())
+    }
+}
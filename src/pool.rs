@@ -0,0 +1,201 @@
+//! A small pool of reusable buffers for the scratch space needed by scanning
+//! reads, so that walking many shards or blocks doesn't allocate per item, and
+//! a pool of worker threads for reads which are split across threads.
+
+use anyhow::Result;
+use std::{
+    ops::{Deref, DerefMut},
+    panic::{catch_unwind, AssertUnwindSafe},
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+/// A bounded pool of cleared `Vec<T>` buffers. Buffers which have grown past
+/// `max_capacity` are dropped rather than returned, so one large read can't pin
+/// its memory in the pool.
+#[derive(Debug)]
+pub struct BufPool<T> {
+    bufs: Mutex<Vec<Vec<T>>>,
+    max_bufs: usize,
+    max_capacity: usize,
+}
+
+impl<T> BufPool<T> {
+    pub const fn new(max_bufs: usize, max_capacity: usize) -> Self {
+        Self {
+            bufs: Mutex::new(Vec::new()),
+            max_bufs,
+            max_capacity,
+        }
+    }
+
+    /// Takes an empty buffer from the pool, allocating one if the pool is empty.
+    /// The buffer goes back to the pool when the guard is dropped.
+    pub fn get(&self) -> PooledBuf<'_, T> {
+        let buf = self.bufs.lock().unwrap().pop().unwrap_or_default();
+        PooledBuf { buf, pool: self }
+    }
+
+    /// Returns the number of idle buffers in the pool.
+    pub fn len(&self) -> usize {
+        self.bufs.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn put(&self, mut buf: Vec<T>) {
+        if buf.capacity() == 0 || buf.capacity() > self.max_capacity {
+            return;
+        }
+        buf.clear();
+        let mut bufs = self.bufs.lock().unwrap();
+        if bufs.len() < self.max_bufs {
+            bufs.push(buf);
+        }
+    }
+}
+
+/// A buffer borrowed from a `BufPool`.
+#[derive(Debug)]
+pub struct PooledBuf<'a, T> {
+    buf: Vec<T>,
+    pool: &'a BufPool<T>,
+}
+
+impl<T> Deref for PooledBuf<'_, T> {
+    type Target = Vec<T>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.buf
+    }
+}
+
+impl<T> DerefMut for PooledBuf<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.buf
+    }
+}
+
+impl<T> Drop for PooledBuf<'_, T> {
+    fn drop(&mut self) {
+        self.pool.put(std::mem::take(&mut self.buf));
+    }
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// A fixed set of named threads which run jobs in the order they are queued.
+/// The threads exit once the pool has been dropped and the queue drained.
+#[derive(Debug)]
+pub struct WorkerPool {
+    jobs: Mutex<Sender<Job>>,
+    threads: usize,
+}
+
+impl WorkerPool {
+    pub fn spawn(name: &str, threads: usize) -> Result<Self> {
+        let (jobs, rx) = channel::<Job>();
+        let rx = Arc::new(Mutex::new(rx));
+        for i in 0..threads {
+            let rx = Arc::clone(&rx);
+            thread::Builder::new()
+                .name(format!("{}-{}", name, i))
+                .spawn(move || Self::work(&rx))?;
+        }
+        Ok(Self {
+            jobs: Mutex::new(jobs),
+            threads,
+        })
+    }
+
+    /// The number of threads in the pool.
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Queues `job` to run on the next idle thread. A job which panics is
+    /// dropped without taking its thread down; callers waiting on its result
+    /// see the job's end of their channel hang up.
+    pub fn execute<F: FnOnce() + Send + 'static>(&self, job: F) {
+        // the receiver lives as long as any thread, so this only fails if all
+        // of them are gone, in which case the dropped job reports the same way
+        let _ = self.jobs.lock().unwrap().send(Box::new(job));
+    }
+
+    fn work(rx: &Mutex<Receiver<Job>>) {
+        loop {
+            // the guard is dropped before the job runs
+            let job = match rx.lock().unwrap().recv() {
+                Ok(job) => job,
+                Err(_) => return,
+            };
+            let _ = catch_unwind(AssertUnwindSafe(job));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buf_pool() {
+        let pool = BufPool::<u8>::new(1, 16);
+        {
+            let mut a = pool.get();
+            a.extend_from_slice(&[1, 2, 3]);
+            let mut b = pool.get();
+            b.push(4);
+        }
+        // only one buffer is kept, and it comes back cleared
+        assert_eq!(pool.len(), 1);
+        let a = pool.get();
+        assert!(a.is_empty());
+        assert!(a.capacity() > 0);
+        drop(a);
+
+        // oversized buffers are dropped
+        pool.get().extend_from_slice(&[0; 32]);
+        pool.get().extend_from_slice(&[0; 32]);
+        assert!(pool.is_empty());
+    }
+
+    #[test]
+    fn test_worker_pool() -> Result<()> {
+        let pool = WorkerPool::spawn("test-worker", 2)?;
+        assert_eq!(pool.threads(), 2);
+        let (tx, rx) = channel();
+        for i in 0..8 {
+            let tx = tx.clone();
+            pool.execute(move || {
+                let name = thread::current().name().unwrap_or_default().to_string();
+                tx.send((i, name)).unwrap()
+            });
+        }
+        // a panicking job doesn't shrink the pool
+        pool.execute(|| panic!("job panicked"));
+        pool.execute(|| panic!("job panicked"));
+        for i in 8..10 {
+            let tx = tx.clone();
+            pool.execute(move || {
+                let name = thread::current().name().unwrap_or_default().to_string();
+                tx.send((i, name)).unwrap()
+            });
+        }
+        drop(tx);
+
+        let mut got: Vec<_> = rx.iter().collect();
+        got.sort();
+        assert_eq!(
+            got.iter().map(|(i, _)| *i).collect::<Vec<_>>(),
+            (0..10).collect::<Vec<_>>()
+        );
+        assert!(got.iter().all(|(_, name)| name.starts_with("test-worker-")));
+        Ok(())
+    }
+}
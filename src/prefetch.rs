@@ -0,0 +1,205 @@
+//! Readahead for sequential scans. Once a block or transaction iterator has
+//! read enough consecutive keys, a background thread walks the entries just
+//! ahead of it in its own read transaction, so the pages they live on are
+//! faulted in before the iterator reaches them. This hides most of the page
+//! fault latency of scanning a cold datadir.
+
+use akula::kv::{mdbx::MdbxEnvironment, Table};
+use anyhow::Result;
+use mdbx::EnvironmentKind;
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{sync_channel, SyncSender},
+        Arc,
+    },
+    thread,
+};
+
+use crate::reader::Reader;
+
+type Job<E> = Box<dyn for<'env> FnOnce(&mut Reader<'env, mdbx::RO, E>) -> Result<u64> + Send>;
+
+/// When readahead starts and how far it reaches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PrefetchConfig {
+    /// The number of consecutive keys an iterator reads before readahead starts.
+    pub trigger: usize,
+    /// The number of entries touched ahead of the iterator. Readahead is
+    /// requested again each time the iterator is half way through the window.
+    pub window: usize,
+}
+
+impl Default for PrefetchConfig {
+    fn default() -> Self {
+        Self {
+            trigger: 32,
+            window: 1024,
+        }
+    }
+}
+
+/// A handle to the readahead thread, shared by a client and its readers. The
+/// thread exits once every handle has been dropped.
+pub struct Prefetch<E: EnvironmentKind> {
+    jobs: SyncSender<Job<E>>,
+    config: PrefetchConfig,
+    touched: Arc<AtomicU64>,
+}
+
+impl<E: EnvironmentKind> Clone for Prefetch<E> {
+    fn clone(&self) -> Self {
+        Self {
+            jobs: self.jobs.clone(),
+            config: self.config,
+            touched: Arc::clone(&self.touched),
+        }
+    }
+}
+
+impl<E: EnvironmentKind> fmt::Debug for Prefetch<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Prefetch")
+            .field("config", &self.config)
+            .field("touched", &self.touched_bytes())
+            .finish()
+    }
+}
+
+impl<E: EnvironmentKind> Prefetch<E> {
+    pub fn spawn(env: Arc<MdbxEnvironment<E>>, config: PrefetchConfig) -> Result<Self> {
+        // one pending request is enough to stay ahead of a single scan, and
+        // requests made while the thread is busy are dropped
+        let (jobs, rx) = sync_channel::<Job<E>>(1);
+        let touched = Arc::new(AtomicU64::new(0));
+        let counter = Arc::clone(&touched);
+        thread::Builder::new()
+            .name("ethers-db-prefetch".into())
+            .spawn(move || {
+                for job in rx {
+                    let res = env.begin().map_err(From::from).and_then(|tx| {
+                        let mut reader = Reader::new(tx);
+                        job(&mut reader)
+                    });
+                    // readahead is best effort, the scan itself reports errors
+                    if let Ok(n) = res {
+                        counter.fetch_add(n, Ordering::Relaxed);
+                    }
+                }
+            })?;
+        Ok(Self {
+            jobs,
+            config,
+            touched,
+        })
+    }
+
+    pub fn config(&self) -> PrefetchConfig {
+        self.config
+    }
+
+    /// The number of bytes read ahead so far.
+    pub fn touched_bytes(&self) -> u64 {
+        self.touched.load(Ordering::Relaxed)
+    }
+
+    /// Asks the thread to touch `n` entries of `table` from `from`, unless it
+    /// is still busy with a previous request.
+    fn request<T>(&self, table: T, from: Vec<u8>, n: usize)
+    where
+        T: Table<Key = Vec<u8>, SeekKey = Vec<u8>, Value = Vec<u8>> + Send + 'static,
+    {
+        let job: Job<E> =
+            Box::new(move |reader: &mut Reader<'_, mdbx::RO, E>| reader.touch(table, from, n));
+        let _ = self.jobs.try_send(job);
+    }
+}
+
+/// Tracks a run of consecutive reads by one iterator, requesting readahead as
+/// the run grows.
+#[derive(Debug)]
+pub(crate) struct Sequential<E: EnvironmentKind> {
+    prefetch: Option<Prefetch<E>>,
+    run: Run,
+}
+
+impl<E: EnvironmentKind> Sequential<E> {
+    pub(crate) fn new(prefetch: Option<Prefetch<E>>) -> Self {
+        Self {
+            prefetch,
+            run: Run::default(),
+        }
+    }
+
+    /// Records a read at position `pos` of `table`, whose key is `key`.
+    pub(crate) fn advance<T, F>(&mut self, pos: u64, table: T, key: F)
+    where
+        T: Table<Key = Vec<u8>, SeekKey = Vec<u8>, Value = Vec<u8>> + Send + 'static,
+        F: FnOnce() -> Vec<u8>,
+    {
+        if let Some(prefetch) = &self.prefetch {
+            if self.run.advance(pos, prefetch.config) {
+                prefetch.request(table, key(), prefetch.config.window);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Run {
+    last: Option<u64>,
+    len: usize,
+    requested_at: Option<usize>,
+}
+
+impl Run {
+    /// Extends or restarts the run with `pos`, returning true if readahead is due.
+    fn advance(&mut self, pos: u64, config: PrefetchConfig) -> bool {
+        if self
+            .last
+            .map_or(false, |last| last.checked_add(1) == Some(pos))
+        {
+            self.len += 1;
+        } else {
+            self.len = 1;
+            self.requested_at = None;
+        }
+        self.last = Some(pos);
+
+        let due = match self.requested_at {
+            None => self.len >= config.trigger,
+            Some(at) => self.len - at >= (config.window / 2).max(1),
+        };
+        if due {
+            self.requested_at = Some(self.len);
+        }
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run() {
+        let config = PrefetchConfig {
+            trigger: 3,
+            window: 4,
+        };
+        let mut run = Run::default();
+        let due: Vec<_> = (10..18).map(|pos| run.advance(pos, config)).collect();
+        // triggered at the third read, then every half window
+        assert_eq!(
+            due,
+            vec![false, false, true, false, true, false, true, false]
+        );
+        // a jump restarts the run
+        assert!(!run.advance(100, config));
+        assert!(!run.advance(101, config));
+        assert!(run.advance(102, config));
+        // as does going backwards
+        assert!(!run.advance(101, config));
+    }
+}
@@ -1,77 +1,379 @@
 #![allow(dead_code)]
 
 use akula::{
-    kv::{mdbx::MdbxTransaction, tables as ak_tables, traits::TableEncode},
+    kv::{
+        mdbx::{MdbxCursor, MdbxEnvironment, MdbxTransaction},
+        tables as ak_tables,
+        traits::{DupSort, Table, TableDecode, TableEncode},
+    },
     models as ak_models,
 };
 use anyhow::{format_err, Result};
-use ethers::core::types::{Address, H256};
+use ethers::core::types::{Address, H256, U256};
 use fastrlp::Decodable;
 use mdbx::{EnvironmentKind, TransactionKind};
 use once_cell::sync::Lazy;
+use std::{
+    collections::{btree_map::Entry, BTreeMap},
+    sync::Arc,
+    time::Duration,
+};
 
-use crate::{models::Account, tables};
+use crate::{
+    bitmap::{decode_roaring64, decode_roaring_into},
+    budget::{Budgeted, ReadBudget},
+    convert, hashed,
+    models::{Account, CallTrace, Log, Receipt, Withdrawal},
+    page::{paginate, Page, PageCursor},
+    pool::BufPool,
+    prefetch::{Prefetch, Sequential},
+    readtrace::{ReadTrace, TxTrace},
+    snapshot::BlockRange,
+    state::StateAt,
+    tables,
+    types::{BlockNum, BlockNumKey, HeaderKey, TxId},
+};
 
 pub static EMPTY_CODEHASH: Lazy<H256> = Lazy::new(|| ethers::utils::keccak256(vec![]).into());
 
+// Scratch space for decoding index shards, which hold up to a few thousand blocks each
+static SHARD_BUFS: BufPool<u32> = BufPool::new(16, 1 << 16);
+
+/// The SyncStage key recording the last block whose state has been executed.
+pub const EXECUTION_STAGE: &str = "Execution";
+
+/// The SyncStage key recording the last block whose transactions are in TxLookup.
+pub const TX_LOOKUP_STAGE: &str = "TxLookup";
+
+// Issuance keys of the cumulative burnt fees are prefixed to keep them apart
+// from the cumulative issuance, which is keyed by block number alone
+const BURNT_PREFIX: &[u8] = b"burnt";
+
 /// A Reader wraps an MdbxTransaction and provides Erigon-specific access methods.
-pub struct Reader<'env, K: TransactionKind, E: EnvironmentKind>(MdbxTransaction<'env, K, E>);
+/// Iterators returned by the reader are bounded by its `ReadBudget`, and read
+/// ahead of themselves if it has a `Prefetch`. If capped at execution, the head
+/// is never past the Execution stage progress. If traced, every key it looks
+/// up and the key of every entry its cursors read is recorded.
+pub struct Reader<'env, K: TransactionKind, E: EnvironmentKind>(
+    MdbxTransaction<'env, K, E>,
+    ReadBudget,
+    bool,
+    Option<Prefetch<E>>,
+    Option<TxTrace>,
+);
 
 // Most of these methods are ported from erigon/core/rawdb/accesssors_*.go
 impl<'env, K: TransactionKind, E: EnvironmentKind> Reader<'env, K, E> {
     pub fn new(tx: MdbxTransaction<'env, K, E>) -> Self {
-        Self(tx)
+        Self(tx, ReadBudget::default(), false, None, None)
+    }
+
+    /// Bounds every iterator subsequently returned by this reader by `budget`.
+    pub fn with_budget(mut self, budget: ReadBudget) -> Self {
+        self.1 = budget;
+        self
+    }
+
+    pub fn budget(&self) -> &ReadBudget {
+        &self.1
+    }
+
+    /// Lets sequential scans by this reader's iterators request readahead.
+    pub fn with_prefetch(mut self, prefetch: Option<Prefetch<E>>) -> Self {
+        self.3 = prefetch;
+        self
+    }
+
+    pub(crate) fn sequential(&self) -> Sequential<E> {
+        Sequential::new(self.3.clone())
     }
 
-    /// Returns the hash of the current canonical head header.
+    /// Caps the head returned by this reader at the Execution stage progress, so
+    /// "latest" never refers to a block whose state hasn't been executed yet.
+    pub fn with_execution_cap(mut self, cap: bool) -> Self {
+        self.2 = cap;
+        self
+    }
+
+    /// Records the reads of this reader's transaction in `trace`.
+    pub fn with_read_trace(mut self, trace: Option<&ReadTrace>) -> Self {
+        self.4 = trace.map(ReadTrace::begin);
+        self
+    }
+
+    fn get<T>(&self, table: T, key: T::Key) -> Result<Option<T::Value>>
+    where
+        T: akula::kv::Table,
+        T::Key: Clone,
+    {
+        if let Some(trace) = &self.4 {
+            trace.record(&table.db_name(), key.clone().encode().as_ref());
+        }
+        self.0.get(table, key)
+    }
+
+    fn cursor<'tx, T: Table>(&'tx self, table: T) -> Result<TracedCursor<'tx, K, T>> {
+        let trace = self
+            .4
+            .as_ref()
+            .map(|trace| (trace.clone(), table.db_name().to_string()));
+        Ok(TracedCursor {
+            inner: self.0.cursor(table)?,
+            trace,
+        })
+    }
+
+    /// Returns the hash of the current canonical head header, or of the last
+    /// executed block if that is behind the head and the reader is capped.
     pub fn read_head_header_hash(&mut self) -> Result<H256> {
-        self.0
-            .get(tables::LastHeader, String::from("LastHeader").into_bytes())?
-            .ok_or_else(|| format_err!("read_head_header_hash"))
+        let hash = self
+            .get(tables::LastHeader, tables::LAST_HEADER_KEY)?
+            .ok_or_else(|| format_err!("read_head_header_hash"))?;
+        if !self.2 {
+            return Ok(hash);
+        }
+        let executed = self
+            .read_stage_progress(EXECUTION_STAGE)?
+            .ok_or_else(|| format_err!("no Execution stage progress"))?;
+        if self.read_header_number(hash)? > executed {
+            return self.read_canonical_hash(executed);
+        }
+        Ok(hash)
+    }
+
+    /// Returns the highest block processed by the sync stage `stage`, or `None`
+    /// if the stage has not recorded any progress.
+    pub fn read_stage_progress(&mut self, stage: &str) -> Result<Option<BlockNum>> {
+        self.get(tables::SyncStage, stage.as_bytes().to_vec())
+    }
+
+    /// Returns the total ether issued in blocks `0..=num`, or `None` if the
+    /// Issuance stage hasn't processed the block.
+    pub fn read_total_issued(&mut self, num: BlockNum) -> Result<Option<U256>> {
+        let total = self.get(tables::Issuance, num.to_be_bytes().to_vec())?;
+        Ok(total.map(convert::u256))
+    }
+
+    /// Returns the total base fees burnt in blocks `0..=num`, or `None` if the
+    /// Issuance stage hasn't processed the block.
+    pub fn read_total_burnt(&mut self, num: BlockNum) -> Result<Option<U256>> {
+        let key = [BURNT_PREFIX, &num.to_be_bytes()].concat();
+        let total = self.get(tables::Issuance, key)?;
+        Ok(total.map(convert::u256))
     }
 
     /// Returns the hash of the current canonical head block.
     pub fn read_head_block_hash(&mut self) -> Result<H256> {
-        self.0
-            .get(tables::LastBlock, String::from("LastBlock").into_bytes())?
+        self.get(tables::LastBlock, tables::LAST_BLOCK_KEY)?
             .ok_or_else(|| format_err!("read_head_block_hash"))
     }
 
+    /// Returns the hash of the latest finalized block, as of the last forkchoice
+    /// update. Returns `None` before the merge or before any update.
+    pub fn read_finalized_block_hash(&mut self) -> Result<Option<H256>> {
+        self.read_forkchoice_hash(tables::FINALIZED_BLOCK_KEY)
+    }
+
+    /// Returns the hash of the latest safe block, as of the last forkchoice
+    /// update. Returns `None` before the merge or before any update.
+    pub fn read_safe_block_hash(&mut self) -> Result<Option<H256>> {
+        self.read_forkchoice_hash(tables::SAFE_BLOCK_KEY)
+    }
+
+    fn read_forkchoice_hash(&mut self, key: &[u8]) -> Result<Option<H256>> {
+        let hash = self.get(tables::LastForkchoice, key.to_vec())?;
+        Ok(hash.filter(|h| !h.is_zero()))
+    }
+
     /// Returns the header number assigned to a hash
-    pub fn read_header_number(&mut self, hash: H256) -> Result<ak_models::BlockNumber> {
-        self.0
-            .get(ak_tables::HeaderNumber, hash)?
+    pub fn read_header_number(&mut self, hash: H256) -> Result<BlockNum> {
+        self.find_header_number(hash)?
             .ok_or_else(|| format_err!("read_header_number"))
     }
 
-    /// Returns the number of the current canonical block header
-    pub fn read_head_block_number(&mut self) -> Result<ak_models::BlockNumber> {
-        let hash = self.read_head_header_hash()?;
-        self.read_header_number(hash)
+    /// Returns the number of the header `hash`, or `None` if it isn't stored.
+    pub fn find_header_number(&mut self, hash: H256) -> Result<Option<BlockNum>> {
+        self.get(tables::HeaderNumber, hash)
+    }
+
+    /// Returns the number of the current canonical block header. During sync,
+    /// LastHeader can briefly point at a header which isn't indexed yet, in
+    /// which case the number is taken from LastBlock or, failing that, from the
+    /// highest entry in CanonicalHeader.
+    pub fn read_head_block_number(&mut self) -> Result<BlockNum> {
+        let head = match self.get(tables::LastHeader, tables::LAST_HEADER_KEY)? {
+            Some(hash) => self.get(tables::HeaderNumber, hash)?,
+            None => None,
+        };
+        let num = match head {
+            Some(num) => num,
+            None => self.read_fallback_head_number()?,
+        };
+        if !self.2 {
+            return Ok(num);
+        }
+        let executed = self
+            .read_stage_progress(EXECUTION_STAGE)?
+            .ok_or_else(|| format_err!("no Execution stage progress"))?;
+        Ok(num.min(executed))
+    }
+
+    fn read_fallback_head_number(&mut self) -> Result<BlockNum> {
+        if let Some(hash) = self.get(tables::LastBlock, tables::LAST_BLOCK_KEY)? {
+            if let Some(num) = self.get(tables::HeaderNumber, hash)? {
+                return Ok(num);
+            }
+        }
+        self.read_last_canonical_number()?
+            .ok_or_else(|| format_err!("read_head_block_number"))
+    }
+
+    /// Returns the highest block number with a canonical hash, if any.
+    pub fn read_last_canonical_number(&mut self) -> Result<Option<BlockNum>> {
+        Ok(self
+            .seek_floor(tables::CanonicalHeader, BlockNum(u64::MAX))?
+            .map(|(num, _)| num))
+    }
+
+    /// Returns the first entry of `table` at or after block `num`.
+    pub fn seek_ceil<T, Key>(&mut self, table: T, num: BlockNum) -> Result<Option<(Key, T::Value)>>
+    where
+        T: akula::kv::Table<Key = Key, SeekKey = Key>,
+        Key: BlockNumKey + Clone,
+    {
+        self.cursor(table)?.seek(Key::first_at(num))
+    }
+
+    /// Returns the last entry of `table` at or before block `num`. Entries
+    /// whose keys don't begin with a block number are skipped.
+    pub fn seek_floor<T, Key>(&mut self, table: T, num: BlockNum) -> Result<Option<(Key, T::Value)>>
+    where
+        T: akula::kv::Table<Key = Key, SeekKey = Key>,
+        Key: BlockNumKey + Clone,
+    {
+        let mut cur = self.cursor(table)?;
+        let mut entry = match num.checked_add(1) {
+            Some(next) => match cur.seek(Key::first_at(BlockNum(next)))? {
+                Some(_) => cur.prev()?,
+                None => cur.last()?,
+            },
+            None => cur.last()?,
+        };
+        while let Some((k, v)) = entry {
+            match k.block_num() {
+                Some(n) if n <= num => return Ok(Some((k, v))),
+                _ => {
+                    self.1.check()?;
+                    entry = cur.prev()?;
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns the last canonical block whose timestamp is at or before
+    /// `timestamp`, found by binary search over the canonical headers. `None`
+    /// if there are none, or if every one of them is later.
+    pub fn read_block_number_at_time(&mut self, timestamp: u64) -> Result<Option<BlockNum>> {
+        let (mut lo, mut hi) = match (
+            self.seek_ceil(tables::CanonicalHeader, BlockNum(0))?,
+            self.seek_floor(tables::CanonicalHeader, BlockNum(u64::MAX))?,
+        ) {
+            (Some((lo, hash)), Some((hi, _))) => {
+                if self.read_header(HeaderKey::new(lo, hash))?.timestamp > timestamp {
+                    return Ok(None);
+                }
+                (lo.0, hi.0)
+            }
+            _ => return Ok(None),
+        };
+        // `lo` is always a canonical block at or before `timestamp`. Pruned
+        // blocks leave holes, so the probe is the first block at or after `mid`
+        while lo < hi {
+            self.1.check()?;
+            let mid = lo + (hi - lo + 1) / 2;
+            match self.seek_ceil(tables::CanonicalHeader, BlockNum(mid))? {
+                Some((num, hash)) if num.0 <= hi => {
+                    if self.read_header(HeaderKey::new(num, hash))?.timestamp <= timestamp {
+                        lo = num.0;
+                    } else {
+                        hi = mid - 1;
+                    }
+                }
+                // no canonical block in [mid, hi]
+                _ => hi = mid - 1,
+            }
+        }
+        Ok(Some(BlockNum(lo)))
     }
 
     /// Returns the block header identified by the (block number, block hash) key
-    pub fn read_header(&mut self, key: ak_tables::HeaderKey) -> Result<ak_models::BlockHeader> {
+    pub fn read_header(&mut self, key: HeaderKey) -> Result<ak_models::BlockHeader> {
         let raw_header = self.read_header_rlp(key)?;
         <ak_models::BlockHeader as Decodable>::decode(&mut &*raw_header)
             .map_err(|e| format_err!("cant decode header: {}", e))
     }
 
+    /// Returns an iterator over at most `n` headers, beginning with the header of
+    /// the block `hash` and continuing through its ancestors. Parent hashes are
+    /// followed through HeaderNumber, so the walk works for non-canonical blocks
+    /// too. The iterator ends after genesis or the first error.
+    pub fn iter_ancestors(
+        &mut self,
+        hash: H256,
+        n: usize,
+    ) -> impl Iterator<Item = Result<(HeaderKey, ak_models::BlockHeader)>> + '_ {
+        let mut next = Some(hash);
+        std::iter::from_fn(move || {
+            let hash = next.take()?;
+            let res = self.1.check().and_then(|_| {
+                let key = HeaderKey::new(self.read_header_number(hash)?, hash);
+                Ok((key, self.read_header(key)?))
+            });
+            if let Ok((key, header)) = &res {
+                if *key.num > 0 {
+                    next = Some(header.parent_hash);
+                }
+            }
+            Some(res)
+        })
+        .take(n)
+    }
+
+    /// Returns every header stored at height `num`, canonical or not, ordered
+    /// by hash.
+    pub fn read_headers_at(
+        &mut self,
+        num: BlockNum,
+    ) -> Result<Vec<(HeaderKey, ak_models::BlockHeader)>> {
+        let start = HeaderKey::new(num, H256::zero()).into();
+        let mut out = vec![];
+        for res in self.cursor(tables::Header)?.walk(Some(start)) {
+            self.1.check()?;
+            let (k, v) = res?;
+            let key = HeaderKey::from(k);
+            if key.num != num {
+                break;
+            }
+            let header = <ak_models::BlockHeader as Decodable>::decode(&mut &*v)
+                .map_err(|e| format_err!("cant decode header: {}", e))?;
+            out.push((key, header));
+        }
+        Ok(out)
+    }
+
     /// Returns the raw RLP encoded block header identified by the (block number, block hash) key
-    pub fn read_header_rlp(&mut self, key: ak_tables::HeaderKey) -> Result<Vec<u8>> {
-        self.0
-            .get(ak_tables::Header.erased(), key.encode().to_vec())?
+    pub fn read_header_rlp(&mut self, key: HeaderKey) -> Result<Vec<u8>> {
+        self.get(tables::Header, key.into())?
             .ok_or_else(|| format_err!("read_header_rlp"))
     }
 
-    /// Returns the decoding of the body as stored in the BlockBody table
-    pub fn read_body_for_storage(
-        &mut self,
-        key: ak_tables::HeaderKey,
-    ) -> Result<ak_models::BodyForStorage> {
+    /// Returns the decoding of the body as stored in the BlockBody table, with
+    /// the system transactions skipped. The `base_tx_id` of the returned body is
+    /// the id of the block's first transaction.
+    pub fn read_body_for_storage(&mut self, key: HeaderKey) -> Result<ak_models::BodyForStorage> {
         let raw_body = self
-            .0
-            .get(ak_tables::BlockBody.erased(), key.encode().to_vec())?
+            .get(tables::BlockBody, key.into())?
             .ok_or_else(|| format_err!("cant find body"))?;
 
         let mut body = <ak_models::BodyForStorage as Decodable>::decode(&mut &*raw_body)
@@ -91,14 +393,46 @@ impl<'env, K: TransactionKind, E: EnvironmentKind> Reader<'env, K, E> {
         Ok(body)
     }
 
+    /// Like `read_body_for_storage`, but only decodes the id of the block's
+    /// first transaction and the number of transactions, leaving the ommer
+    /// headers undecoded.
+    pub fn read_body_tx_range(&mut self, key: HeaderKey) -> Result<(TxId, usize)> {
+        let raw_body = self
+            .get(tables::BlockBody, key.into())?
+            .ok_or_else(|| format_err!("cant find body"))?;
+
+        let buf = &mut &*raw_body;
+        let decode_err =
+            |e: fastrlp::DecodeError| format_err!("BodyForStorage decode error: {}", e);
+        let header = fastrlp::Header::decode(buf).map_err(decode_err)?;
+        anyhow::ensure!(header.list, "BodyForStorage is not a list");
+        let base_tx_id = u64::decode(buf).map_err(decode_err)?;
+        let tx_amount = u32::decode(buf).map_err(decode_err)?;
+
+        // Skip the system txs, as in read_body_for_storage
+        let tx_amount = tx_amount.checked_sub(2).ok_or_else(|| {
+            format_err!(
+                "Block body has too few txs: {}. HeaderKey: {:?}",
+                tx_amount,
+                key,
+            )
+        })?;
+        Ok((TxId(base_tx_id + 1), tx_amount.try_into()?))
+    }
+
     /// Returns the number of the block containing the specified transaction.
-    pub fn read_transaction_block_number(&mut self, hash: H256) -> Result<ak_models::BlockNumber> {
-        let num = self
-            .0
-            .get(tables::BlockTransactionLookup, hash)?
-            .ok_or_else(|| format_err!("cant find tx"))?;
+    pub fn read_transaction_block_number(&mut self, hash: H256) -> Result<BlockNum> {
+        self.find_transaction_block_number(hash)?
+            .ok_or_else(|| format_err!("cant find tx"))
+    }
 
-        Ok(u64::try_from(num)?.into())
+    /// Like `read_transaction_block_number`, but returns `None` if the
+    /// transaction isn't in TxLookup.
+    pub fn find_transaction_block_number(&mut self, hash: H256) -> Result<Option<BlockNum>> {
+        match self.get(tables::BlockTransactionLookup, hash)? {
+            Some(num) => Ok(Some(BlockNum(u64::try_from(num)?))),
+            None => Ok(None),
+        }
     }
 
     /// Returns a vector of `n` transactions beginning at `start_key`, propogating
@@ -107,7 +441,7 @@ impl<'env, K: TransactionKind, E: EnvironmentKind> Reader<'env, K, E> {
     /// expected in the db), an error is returned.
     pub fn read_transactions(
         &mut self,
-        start_key: u64,
+        start_key: TxId,
         n: usize,
     ) -> Result<Vec<ak_models::MessageWithSignature>> {
         let res = self
@@ -118,7 +452,7 @@ impl<'env, K: TransactionKind, E: EnvironmentKind> Reader<'env, K, E> {
             anyhow::bail!(
                 "Could not read {} transactions from start key {:x}. Got {}",
                 n,
-                start_key,
+                *start_key,
                 res.len()
             )
         }
@@ -128,19 +462,67 @@ impl<'env, K: TransactionKind, E: EnvironmentKind> Reader<'env, K, E> {
     /// Returns an iterator over transaction reads beginning at `start_key`
     pub fn stream_transactions(
         &mut self,
-        start_key: u64,
+        start_key: TxId,
     ) -> Result<impl Iterator<Item = Result<ak_models::MessageWithSignature>>> {
         // BlockTransaction is Erigon's "EthTx" table
-        Ok(self
-            .0
-            .cursor(ak_tables::BlockTransaction.erased())?
-            .walk(Some(start_key.encode().to_vec()))
-            .map(|res| {
-                res.and_then(|(_, tx)| {
+        let mut seq = self.sequential();
+        let walk = self
+            .cursor(tables::BlockTransaction)?
+            .walk(Some(start_key.into()))
+            .map(move |res| {
+                res.and_then(|(key, tx)| {
+                    seq.advance(
+                        u64::from_be_bytes(key.0),
+                        tables::BlockTransaction.erased(),
+                        || key.0.to_vec(),
+                    );
                     <ak_models::MessageWithSignature as Decodable>::decode(&mut &*tx)
                         .map_err(From::from)
                 })
-            }))
+            });
+        Ok(Budgeted::new(walk, self.1.clone()))
+    }
+
+    /// Returns `n` transactions beginning at `start_key` in their canonical
+    /// encoding, as hashed and committed to in the transactions trie.
+    pub fn read_raw_transactions(&mut self, start_key: TxId, n: usize) -> Result<Vec<Vec<u8>>> {
+        let txs = self
+            .cursor(tables::BlockTransaction)?
+            .walk(Some(start_key.into()))
+            .take(n)
+            .map(|res| res.map(|(_, tx)| tx))
+            .collect::<Result<Vec<_>>>()?;
+        anyhow::ensure!(
+            txs.len() == n,
+            "Failed to read some txs. Expected: {}. Got {}",
+            n,
+            txs.len()
+        );
+        Ok(txs)
+    }
+
+    /// Returns a page of at most `limit` transactions beginning at `start_key`, or
+    /// at `cursor` if resuming from a previous page.
+    pub fn read_transactions_page(
+        &mut self,
+        start_key: TxId,
+        cursor: Option<&PageCursor>,
+        limit: usize,
+    ) -> Result<Page<ak_models::MessageWithSignature>> {
+        let start_key = match cursor {
+            Some(c) => TxId(u64::from_be_bytes(c.to_array()?)),
+            None => start_key,
+        };
+        let walk = self
+            .cursor(tables::BlockTransaction)?
+            .walk(Some(start_key.into()))
+            .map(|res| {
+                res.and_then(|(k, tx)| {
+                    let msg = <ak_models::MessageWithSignature as Decodable>::decode(&mut &*tx)?;
+                    Ok((k, msg))
+                })
+            });
+        paginate(Budgeted::new(walk, self.1.clone()), limit)
     }
 
     /// Returns an iterator over transactions beginning at `start_key`. Any errors
@@ -149,7 +531,7 @@ impl<'env, K: TransactionKind, E: EnvironmentKind> Reader<'env, K, E> {
     /// if exactly `n` transactions are needed.
     pub fn try_stream_transactions(
         &mut self,
-        start_key: u64,
+        start_key: TxId,
         n: usize,
     ) -> Result<impl Iterator<Item = ak_models::MessageWithSignature>> {
         Ok(self
@@ -158,21 +540,80 @@ impl<'env, K: TransactionKind, E: EnvironmentKind> Reader<'env, K, E> {
             .flatten())
     }
 
+    /// Returns the beacon chain withdrawals included in the block. Blocks from
+    /// before Shanghai have none.
+    pub fn read_withdrawals(&mut self, key: HeaderKey) -> Result<Vec<Withdrawal>> {
+        let raw_body = self
+            .get(tables::BlockBody, key.into())?
+            .ok_or_else(|| format_err!("cant find body"))?;
+        Withdrawal::decode_from_body(&raw_body)
+            .map_err(|e| format_err!("withdrawals decode error: {}", e))
+    }
+
     /// Returns the signers of each transaction in the block.
     /// If the block or the signers are not in the db, returns zero addresses.
-    pub fn read_senders(&mut self, key: ak_tables::HeaderKey) -> Result<Vec<Address>> {
-        self.0
-            .get(ak_tables::TxSender, key)
+    pub fn read_senders(&mut self, key: HeaderKey) -> Result<Vec<Address>> {
+        self.get(tables::TxSender, key)
             .map(|res| res.unwrap_or_default())
     }
 
     /// Returns the hash assigned to a canonical block number.
-    pub fn read_canonical_hash(&mut self, num: ak_models::BlockNumber) -> Result<H256> {
-        self.0
-            .get(ak_tables::CanonicalHeader, num)?
+    pub fn read_canonical_hash(&mut self, num: BlockNum) -> Result<H256> {
+        self.find_canonical_hash(num)?
             .ok_or(format_err!("read_canonical_hash"))
     }
 
+    /// Returns the hash assigned to a canonical block number, or `None` if
+    /// there is no canonical block at that height.
+    pub fn find_canonical_hash(&mut self, num: BlockNum) -> Result<Option<H256>> {
+        self.get(tables::CanonicalHeader, num)
+    }
+
+    /// Returns the AuRa epoch transition proof of the block `key`, or `None` if
+    /// the block isn't a transition.
+    #[cfg(feature = "gnosis")]
+    pub fn read_epoch(&mut self, key: HeaderKey) -> Result<Option<Vec<u8>>> {
+        self.get(tables::Epoch, key)
+    }
+
+    /// Returns the proof of the epoch transition signalled in the block `key`
+    /// which isn't yet final, if any.
+    #[cfg(feature = "gnosis")]
+    pub fn read_pending_epoch(&mut self, key: HeaderKey) -> Result<Option<Vec<u8>>> {
+        self.get(tables::PendingEpoch, key)
+    }
+
+    /// Returns the last canonical AuRa epoch transition at or before block
+    /// `num`, with its proof. Transitions of non-canonical blocks are skipped.
+    #[cfg(feature = "gnosis")]
+    pub fn read_epoch_transition_at(
+        &mut self,
+        num: BlockNum,
+    ) -> Result<Option<(HeaderKey, Vec<u8>)>> {
+        let mut num = num;
+        while let Some((key, proof)) = self.seek_floor(tables::Epoch, num)? {
+            if self.get(tables::CanonicalHeader, key.num)? == Some(key.hash) {
+                return Ok(Some((key, proof)));
+            }
+            num = match key.num.checked_sub(1) {
+                Some(prev) => BlockNum(prev),
+                None => break,
+            };
+            self.1.check()?;
+        }
+        Ok(None)
+    }
+
+    /// Returns the json chain config stored under the genesis hash, or `None`
+    /// if the db has no config.
+    pub fn read_chain_config(&mut self) -> Result<Option<serde_json::Value>> {
+        let genesis = self.read_canonical_hash(BlockNum(0))?;
+        match self.get(tables::Config, genesis)? {
+            Some(raw) => Ok(Some(serde_json::from_slice(&raw)?)),
+            None => Ok(None),
+        }
+    }
+
     /// Determines whether a header with the given hash is on the canonical chain.
     pub fn is_canonical_hash(&mut self, hash: H256) -> Result<bool> {
         let num = self.read_header_number(hash)?;
@@ -183,14 +624,217 @@ impl<'env, K: TransactionKind, E: EnvironmentKind> Reader<'env, K, E> {
     /// Returns the decoded account data as stored in the PlainState table.
     /// If the account is not in the db, the empty account is returned.
     pub fn read_account_data(&mut self, who: Address) -> Result<Account> {
-        self.0
-            .get(tables::PlainState, who)
-            .map(|res| res.unwrap_or_default())
+        self.read_account(who).map(|res| res.unwrap_or_default())
+    }
+
+    /// Returns the current account of `who`, or `None` if it has none.
+    pub fn read_account(&mut self, who: Address) -> Result<Option<Account>> {
+        self.get(tables::PlainState, who)
+    }
+
+    /// Returns the accounts of `addresses`, which must be sorted, in one pass
+    /// over PlainState. A seek is skipped when the last one already landed at
+    /// or past the address, so runs of addresses without accounts are cheap.
+    pub fn read_accounts_sorted(&mut self, addresses: &[Address]) -> Result<Vec<Option<Account>>> {
+        let mut cur = self.cursor(tables::PlainState.erased())?;
+        // the entry the cursor is on, and whether it has run off the end
+        let mut pos: Option<(Vec<u8>, Vec<u8>)> = None;
+        let mut exhausted = false;
+        let mut out = Vec::with_capacity(addresses.len());
+        for who in addresses {
+            self.1.check()?;
+            let key = who.as_bytes();
+            let behind = match &pos {
+                Some((k, _)) => k.as_slice() < key,
+                None => !exhausted,
+            };
+            if behind {
+                pos = cur.seek(key.to_vec())?;
+                exhausted = pos.is_none();
+            }
+            out.push(match &pos {
+                Some((k, v)) if k.as_slice() == key => Some(<Account as TableDecode>::decode(v)?),
+                _ => None,
+            });
+        }
+        Ok(out)
+    }
+
+    /// Returns the account as it was before block `num` changed it, according to
+    /// the AccountChangeSet, or `None` if the block didn't change it. An account
+    /// which didn't exist before the block is returned empty.
+    pub fn read_account_before(&mut self, who: Address, num: BlockNum) -> Result<Option<Account>> {
+        let mut cur = self.cursor(tables::AccountChangeSet)?;
+        match cur.seek_both_range(num, who)? {
+            Some(val) if val.starts_with(who.as_bytes()) => Ok(Some(
+                <Account as TableDecode>::decode(&val[Address::len_bytes()..])?,
+            )),
+            _ => Ok(None),
+        }
+    }
+
+    /// Returns the value `slot` of `who` at `incarnation` had before block
+    /// `num` changed it, according to the StorageChangeSet, or `None` if the
+    /// block didn't change it. A slot which was unset is zero.
+    pub fn read_storage_before(
+        &mut self,
+        who: Address,
+        incarnation: u64,
+        slot: H256,
+        num: BlockNum,
+    ) -> Result<Option<H256>> {
+        let mut key = num.to_be_bytes().to_vec();
+        key.extend_from_slice(who.as_bytes());
+        key.extend_from_slice(&incarnation.to_be_bytes());
+        let mut cur = self.cursor(tables::StorageChangeSet)?;
+        match cur.seek_both_range(key, slot)? {
+            Some(val) if val.starts_with(slot.as_bytes()) => {
+                convert::trimmed_word(&val[H256::len_bytes()..]).map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Returns a view of the state as of block `num`. See `StateAt`.
+    pub fn state_at(&mut self, num: BlockNum) -> StateAt<'_, 'env, K, E> {
+        StateAt::new(self, num)
+    }
+
+    /// Sets the code hash of a contract account read from a changeset, which
+    /// omits it, from PlainContractCode by the account's incarnation.
+    pub fn fill_codehash(&mut self, who: Address, acct: &mut Account) -> Result<()> {
+        if acct.incarnation > 0 && acct.codehash.is_zero() {
+            if let Some(codehash) = self.read_contract_codehash(who, acct.incarnation)? {
+                acct.codehash = codehash;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the code hash of an incarnation of the contract `who` from
+    /// PlainContractCode, which keeps the code of destroyed incarnations.
+    pub fn read_contract_codehash(
+        &mut self,
+        who: Address,
+        incarnation: u64,
+    ) -> Result<Option<H256>> {
+        let bucket = crate::models::StorageBucket::new(who, incarnation);
+        self.get(tables::PlainContractCode, bucket)
+    }
+
+    /// Returns at most `limit` accounts from PlainState with addresses after
+    /// `after`, or from the first if `None`, in address order. Storage entries,
+    /// which share the table, are skipped over with a seek per account.
+    pub fn read_accounts_after(
+        &mut self,
+        after: Option<Address>,
+        limit: usize,
+    ) -> Result<Vec<(Address, Account)>> {
+        // sorts after every key beginning with the address
+        let past = |who: &[u8]| [who, &[0xff; 9][..]].concat();
+        let mut cur = self.cursor(tables::PlainState.erased())?;
+        let mut next = match after {
+            Some(who) => cur.seek(past(who.as_bytes()))?,
+            None => cur.first()?,
+        };
+        let mut out = vec![];
+        while let Some((k, v)) = next {
+            self.1.check()?;
+            if out.len() == limit {
+                break;
+            }
+            if k.len() == Address::len_bytes() {
+                out.push((convert::address(&k)?, <Account as TableDecode>::decode(&v)?));
+                next = cur.next()?;
+            } else {
+                let who = k
+                    .get(..Address::len_bytes())
+                    .ok_or_else(|| format_err!("plain state key too short"))?;
+                next = cur.seek(past(who))?;
+            }
+        }
+        Ok(out)
+    }
+
+    /// Returns each account changed after block `num` as it was going into the
+    /// first block which changed it, according to the AccountChangeSet. An
+    /// account which didn't exist then is returned empty. Changesets omit code
+    /// hashes, see `fill_codehash`.
+    pub fn read_account_changes_since(
+        &mut self,
+        num: BlockNum,
+    ) -> Result<BTreeMap<Address, Account>> {
+        let mut out = BTreeMap::new();
+        let walk = self
+            .cursor(tables::AccountChangeSet.erased())?
+            .walk(Some((*num + 1).to_be_bytes().to_vec()));
+        for res in Budgeted::new(walk, self.1.clone()) {
+            let (_, v) = res?;
+            let (who, acct) = decode_account_change(&v)?;
+            out.entry(who).or_insert(acct);
+        }
+        Ok(out)
+    }
+
+    /// Returns each storage slot changed after block `num`, by address and
+    /// incarnation, with its value going into the first block which changed it,
+    /// according to the StorageChangeSet. A slot which was unset is zero.
+    pub fn read_storage_changes_since(
+        &mut self,
+        num: BlockNum,
+    ) -> Result<BTreeMap<(Address, u64), BTreeMap<H256, H256>>> {
+        let mut out = BTreeMap::<_, BTreeMap<_, _>>::new();
+        let walk = self
+            .cursor(tables::StorageChangeSet.erased())?
+            .walk(Some((*num + 1).to_be_bytes().to_vec()));
+        for res in Budgeted::new(walk, self.1.clone()) {
+            let (k, v) = res?;
+            let (who, incarnation, slot, val) = decode_storage_change(&k, &v)?;
+            let slots = out.entry((who, incarnation)).or_default();
+            if let Entry::Vacant(entry) = slots.entry(slot) {
+                entry.insert(val);
+            }
+        }
+        Ok(out)
+    }
+
+    /// Returns an iterator over the accounts block `num` changed, in address
+    /// order, each as it was going into the block according to the
+    /// AccountChangeSet. An account the block created is returned empty.
+    /// Changesets omit code hashes, see `fill_codehash`.
+    pub fn walk_account_changes(
+        &mut self,
+        num: BlockNum,
+    ) -> Result<impl Iterator<Item = Result<(Address, Account)>>> {
+        let walk = self
+            .cursor(tables::AccountChangeSet)?
+            .walk_dup(num)
+            .map(|res| decode_account_change(&res?));
+        Ok(Budgeted::new(walk, self.1.clone()))
+    }
+
+    /// Returns an iterator over the storage slots block `num` changed, as
+    /// (address, incarnation, slot, value going into the block) according to
+    /// the StorageChangeSet, in address, incarnation and slot order. A slot
+    /// which was unset is zero.
+    pub fn walk_storage_changes(
+        &mut self,
+        num: BlockNum,
+    ) -> Result<impl Iterator<Item = Result<(Address, u64, H256, H256)>>> {
+        let prefix = num.to_be_bytes();
+        let walk = self
+            .cursor(tables::StorageChangeSet.erased())?
+            .walk(Some(prefix.to_vec()))
+            .take_while(move |res| res.as_ref().map_or(true, |(k, _)| k.starts_with(&prefix)))
+            .map(|res| {
+                let (k, v) = res?;
+                decode_storage_change(&k, &v)
+            });
+        Ok(Budgeted::new(walk, self.1.clone()))
     }
 
     pub fn read_account_data_raw(&mut self, who: Address) -> Result<Vec<u8>> {
-        self.0
-            .get(tables::PlainState.erased(), who.encode().to_vec())?
+        self.get(tables::PlainState.erased(), who.encode().to_vec())?
             .ok_or_else(|| format_err!("read_account_data_raw"))
     }
 
@@ -203,17 +847,42 @@ impl<'env, K: TransactionKind, E: EnvironmentKind> Reader<'env, K, E> {
         key: H256,
     ) -> Result<H256> {
         let bucket = crate::models::StorageBucket::new(who, incarnation);
-        let mut cur = self.0.cursor(tables::Storage)?;
+        let mut cur = self.cursor(tables::Storage)?;
 
         if let Some((k, v)) = cur.seek_both_range(bucket, key)? {
             if k == key {
-                return Ok(v.to_be_bytes().into());
+                return Ok(convert::word(v));
             }
         }
 
         Ok(Default::default())
     }
 
+    /// Returns the account stored in HashedAccount under `hashed_address`, the
+    /// keccak of its address. See `hashed::hash_address`.
+    pub fn read_hashed_account(&mut self, hashed_address: H256) -> Result<Option<Account>> {
+        self.get(tables::HashedAccount, hashed_address)
+    }
+
+    /// Returns the value of the storage slot hashed to `hashed_slot` from
+    /// HashedStorage. If the account or storage slot is not in the db, returns
+    /// 0x0.
+    pub fn read_hashed_storage(
+        &mut self,
+        hashed_address: H256,
+        incarnation: u64,
+        hashed_slot: H256,
+    ) -> Result<H256> {
+        let key = hashed::hashed_storage_key(hashed_address, incarnation).to_vec();
+        let mut cur = self.cursor(tables::HashedStorage)?;
+        match cur.seek_both_range(key, hashed_slot)? {
+            Some(val) if val.starts_with(hashed_slot.as_bytes()) => {
+                convert::trimmed_word(&val[H256::len_bytes()..])
+            }
+            _ => Ok(Default::default()),
+        }
+    }
+
     /// Returns an iterator over all of the storage (key, value) pairs for the
     /// given address and account incarnation.
     pub fn walk_account_storage(
@@ -222,25 +891,72 @@ impl<'env, K: TransactionKind, E: EnvironmentKind> Reader<'env, K, E> {
         incarnation: u64,
     ) -> Result<impl Iterator<Item = Result<(ak_models::H256, ak_models::U256)>>> {
         let start_key = crate::models::StorageBucket::new(who, incarnation);
-        Ok(self.0.cursor(tables::Storage)?.walk_dup(start_key))
+        let walk = self.cursor(tables::Storage)?.walk_dup(start_key);
+        Ok(Budgeted::new(walk, self.1.clone()))
+    }
+
+    /// Returns a page of at most `limit` storage (key, value) pairs for the given
+    /// address and account incarnation, ordered by key. Pass the previous page's
+    /// cursor to resume.
+    pub fn read_account_storage_page(
+        &mut self,
+        who: Address,
+        incarnation: u64,
+        cursor: Option<&PageCursor>,
+        limit: usize,
+    ) -> Result<Page<(ak_models::H256, ak_models::U256)>> {
+        let bucket = crate::models::StorageBucket::new(who, incarnation);
+        let mut cur = self.cursor(tables::Storage)?;
+
+        let mut next = match cursor {
+            Some(c) => cur.seek_both_range(bucket, H256(c.to_array()?))?,
+            None => cur.seek_exact(bucket)?.map(|(_, v)| v),
+        };
+
+        // read one past the limit so paginate can set the next cursor
+        let mut entries = vec![];
+        while let Some((k, v)) = next {
+            self.1.check()?;
+            entries.push(Ok((k.as_bytes().to_vec(), (k, v))));
+            if entries.len() > limit {
+                break;
+            }
+            next = cur.next_dup()?.map(|(_, v)| v);
+        }
+        paginate(entries, limit)
+    }
+
+    /// Returns the number of storage slots set for the given address and
+    /// account incarnation. akula's cursors don't expose MDBX's dup count, so
+    /// this steps through the bucket's entries without collecting them.
+    pub fn count_account_storage(&mut self, who: Address, incarnation: u64) -> Result<u64> {
+        let bucket = crate::models::StorageBucket::new(who, incarnation);
+        let mut cur = self.cursor(tables::Storage)?;
+        let mut count = 0;
+        let mut next = cur.seek_exact(bucket)?.map(|_| ());
+        while next.is_some() {
+            self.1.check()?;
+            count += 1;
+            next = cur.next_dup()?.map(|_| ());
+        }
+        Ok(count)
     }
 
     /// Returns the incarnation of the account when it was last deleted.
     /// If the account is not in the db, returns 0.
     pub fn read_last_incarnation(&mut self, who: Address) -> Result<u64> {
-        self.0
-            .get(tables::IncarnationMap, who)
+        self.get(tables::IncarnationMap, who)
             .map(|res| res.unwrap_or_default())
     }
 
-    /// Returns the code associated with the given codehash.
+    /// Returns the code associated with the given codehash, empty for the
+    /// empty code hash or a zero one, which is how Erigon stores it.
     /// If the codehash is not in the db, returns an error.
     pub fn read_code(&mut self, codehash: H256) -> Result<bytes::Bytes> {
-        if codehash == *EMPTY_CODEHASH {
+        if codehash.is_zero() || codehash == *EMPTY_CODEHASH {
             return Ok(bytes::Bytes::new());
         }
-        self.0
-            .get(ak_tables::Code, codehash)?
+        self.get(tables::Code, codehash)?
             .ok_or_else(|| format_err!("read_account_data_raw"))
     }
 
@@ -251,6 +967,326 @@ impl<'env, K: TransactionKind, E: EnvironmentKind> Reader<'env, K, E> {
         Ok(code.len())
     }
 
+    /// Returns the block numbers in `from..=to` recorded for `key` in one of the
+    /// sharded bitmap index tables (e.g. LogAddressIndex). Each shard is keyed by
+    /// `key ++ shard_max`, where `shard_max` is the highest block in the shard.
+    pub fn read_bitmap_index<T>(
+        &mut self,
+        table: T,
+        key: &[u8],
+        from: u32,
+        to: u32,
+    ) -> Result<Vec<u32>>
+    where
+        T: akula::kv::Table<Key = Vec<u8>, SeekKey = Vec<u8>, Value = Vec<u8>>,
+    {
+        let mut start = key.to_vec();
+        start.extend_from_slice(&from.to_be_bytes());
+
+        let mut out = vec![];
+        let mut shard = SHARD_BUFS.get();
+        for res in self.cursor(table)?.walk(Some(start)) {
+            let (k, v) = res?;
+            if k.len() != key.len() + 4 || !k.starts_with(key) {
+                break;
+            }
+            // shards are disjoint and ordered, so the output stays sorted
+            decode_roaring_into(&v, &mut shard)?;
+            out.extend(shard.drain(..).filter(|n| (from..=to).contains(n)));
+            let shard_max = u32::from_be_bytes(k[key.len()..].try_into()?);
+            if shard_max >= to {
+                break;
+            }
+        }
+        Ok(out)
+    }
+
+    /// Returns every block number recorded for `key` in one of the sharded
+    /// roaring64 index tables (e.g. AccountHistory). Shards are keyed by
+    /// `key ++ shard_max`, with an 8-byte `shard_max`.
+    pub fn read_bitmap_index64<T>(&mut self, table: T, key: &[u8]) -> Result<Vec<u64>>
+    where
+        T: akula::kv::Table<Key = Vec<u8>, SeekKey = Vec<u8>, Value = Vec<u8>>,
+    {
+        let mut out = vec![];
+        for res in self.cursor(table)?.walk(Some(key.to_vec())) {
+            self.1.check()?;
+            let (k, v) = res?;
+            if k.len() != key.len() + 8 || !k.starts_with(key) {
+                break;
+            }
+            out.extend(decode_roaring64(&v)?);
+        }
+        Ok(out)
+    }
+
+    /// Returns the first block at or after `num` recorded for `key` in one of
+    /// the sharded roaring64 index tables. Only the shards from the first with
+    /// a `shard_max` of at least `num` are decoded.
+    pub fn read_bitmap_index64_from<T>(
+        &mut self,
+        table: T,
+        key: &[u8],
+        num: u64,
+    ) -> Result<Option<u64>>
+    where
+        T: akula::kv::Table<Key = Vec<u8>, SeekKey = Vec<u8>, Value = Vec<u8>>,
+    {
+        let start = [key, &num.to_be_bytes()].concat();
+        for res in self.cursor(table)?.walk(Some(start)) {
+            self.1.check()?;
+            let (k, v) = res?;
+            if k.len() != key.len() + 8 || !k.starts_with(key) {
+                break;
+            }
+            if let Some(found) = decode_roaring64(&v)?.into_iter().find(|n| *n >= num) {
+                return Ok(Some(found));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns the first block at or after `num` which changed the account
+    /// `who`, according to the AccountHistory. Its changeset holds the account
+    /// going into the block, see `read_account_before`.
+    pub fn read_account_change_from(
+        &mut self,
+        who: Address,
+        num: BlockNum,
+    ) -> Result<Option<BlockNum>> {
+        Ok(self
+            .read_bitmap_index64_from(tables::AccountHistory, who.as_bytes(), *num)?
+            .map(BlockNum))
+    }
+
+    /// Returns the first block at or after `num` which changed `slot` of `who`,
+    /// according to the StorageHistory. The index doesn't key by incarnation,
+    /// so the block may have changed the slot at any incarnation of `who`.
+    pub fn read_storage_change_from(
+        &mut self,
+        who: Address,
+        slot: H256,
+        num: BlockNum,
+    ) -> Result<Option<BlockNum>> {
+        let key = [who.as_bytes(), slot.as_bytes()].concat();
+        Ok(self
+            .read_bitmap_index64_from(tables::StorageHistory, &key, *num)?
+            .map(BlockNum))
+    }
+
+    /// Returns the range of blocks keyed in `table`, whose keys must begin with a
+    /// big-endian block number. Erigon only prunes or freezes the oldest blocks
+    /// of a table, so the range between the first and last keys has no holes.
+    pub fn read_block_key_range<T>(&mut self, table: T) -> Result<Option<BlockRange>>
+    where
+        T: akula::kv::Table<Key = Vec<u8>, SeekKey = Vec<u8>, Value = Vec<u8>> + Clone,
+    {
+        let (first, last) = match (
+            self.seek_ceil(table.clone(), BlockNum(0))?,
+            self.seek_floor(table, BlockNum(u64::MAX))?,
+        ) {
+            (Some((first, _)), Some((last, _))) => (first, last),
+            _ => return Ok(None),
+        };
+        let block_num = |k: &Vec<u8>| -> Result<u64> {
+            k.block_num()
+                .map(|num| num.0)
+                .ok_or_else(|| format_err!("key too short for a block number: {}", hex::encode(k)))
+        };
+        Ok(Some(BlockRange {
+            from: block_num(&first)?,
+            to: block_num(&last)? + 1,
+        }))
+    }
+
+    /// Returns at most `limit` raw (key, value) entries of `table` from the
+    /// first key at or after `from`, every value of a dupsort key included.
+    pub fn read_raw_entries<T>(
+        &mut self,
+        table: T,
+        from: Vec<u8>,
+        limit: usize,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>>
+    where
+        T: akula::kv::Table<Key = Vec<u8>, SeekKey = Vec<u8>, Value = Vec<u8>>,
+    {
+        let walk = self.cursor(table)?.walk(Some(from)).take(limit);
+        Budgeted::new(walk, self.1.clone()).collect()
+    }
+
+    /// Walks `n` entries of `table` from `from`, returning the number of bytes
+    /// read. Used to fault in the pages ahead of a sequential scan.
+    pub(crate) fn touch<T>(&mut self, table: T, from: Vec<u8>, n: usize) -> Result<u64>
+    where
+        T: akula::kv::Table<Key = Vec<u8>, SeekKey = Vec<u8>, Value = Vec<u8>>,
+    {
+        let mut bytes = 0;
+        for res in self.cursor(table)?.walk(Some(from)).take(n) {
+            let (k, v) = res?;
+            bytes += (k.len() + v.len()) as u64;
+        }
+        Ok(bytes)
+    }
+
+    /// Walks the entries of `table` from `from` up to, but not including, `to`,
+    /// returning the number of bytes read.
+    pub(crate) fn touch_range<T>(&mut self, table: T, from: Vec<u8>, to: &[u8]) -> Result<u64>
+    where
+        T: akula::kv::Table<Key = Vec<u8>, SeekKey = Vec<u8>, Value = Vec<u8>>,
+    {
+        let mut bytes = 0;
+        for res in self.cursor(table)?.walk(Some(from)) {
+            let (k, v) = res?;
+            if k.as_slice() >= to {
+                break;
+            }
+            bytes += (k.len() + v.len()) as u64;
+        }
+        Ok(bytes)
+    }
+
+    /// Reads the entry of `table` at `key`, if any, returning its size.
+    pub(crate) fn touch_key<T>(&mut self, table: T, key: Vec<u8>) -> Result<u64>
+    where
+        T: akula::kv::Table<Key = Vec<u8>, SeekKey = Vec<u8>, Value = Vec<u8>>,
+    {
+        Ok(self.get(table, key)?.map_or(0, |v| v.len() as u64))
+    }
+
+    /// Returns the sorted addresses of the accounts changed in the blocks
+    /// `from..=to`, according to the account changesets.
+    pub fn read_changed_accounts(&mut self, from: BlockNum, to: BlockNum) -> Result<Vec<Address>> {
+        let mut out = vec![];
+        let walk = self
+            .cursor(tables::AccountChangeSet.erased())?
+            .walk(Some(from.to_be_bytes().to_vec()));
+        for res in Budgeted::new(walk, self.1.clone()) {
+            let (k, v) = res?;
+            if k.get(..8).map_or(true, |num| num > &to.to_be_bytes()[..]) {
+                break;
+            }
+            // each value is the address followed by the account before the change
+            let address = v
+                .get(..Address::len_bytes())
+                .ok_or_else(|| format_err!("account changeset value too short"))?;
+            out.push(convert::address(address)?);
+        }
+        out.sort_unstable();
+        out.dedup();
+        Ok(out)
+    }
+
+    /// Returns the addresses in the call traces of the block, with whether each
+    /// sent or received a call, according to the CallTraceSet.
+    pub fn read_call_traces(&mut self, num: BlockNum) -> Result<Vec<CallTrace>> {
+        let key = num.to_be_bytes();
+        let walk = self
+            .cursor(tables::CallTraceSet.erased())?
+            .walk(Some(key.to_vec()));
+        let mut out = vec![];
+        for res in Budgeted::new(walk, self.1.clone()) {
+            let (k, v) = res?;
+            if k != key {
+                break;
+            }
+            out.push(CallTrace::decode(&v)?);
+        }
+        Ok(out)
+    }
+
+    /// Returns the logs of every transaction in the block which emitted any,
+    /// paired with the transaction's index in the block.
+    pub fn read_block_logs(&mut self, num: BlockNum) -> Result<Vec<(u32, Vec<Log>)>> {
+        self.read_block_logs_upto(num, u32::MAX)
+    }
+
+    /// Returns the logs of the transactions up to and including `last_tx`, as
+    /// `read_block_logs` does.
+    pub fn read_block_logs_upto(
+        &mut self,
+        num: BlockNum,
+        last_tx: u32,
+    ) -> Result<Vec<(u32, Vec<Log>)>> {
+        self.walk_block_logs(num, last_tx)?
+            .into_iter()
+            .map(|(tx_idx, v)| Ok((tx_idx, Log::decode_list(&v)?)))
+            .collect()
+    }
+
+    /// Returns the CBOR encoded logs of every transaction in the block which
+    /// emitted any, exactly as stored.
+    pub fn read_raw_block_logs(&mut self, num: BlockNum) -> Result<Vec<(u32, Vec<u8>)>> {
+        self.walk_block_logs(num, u32::MAX)
+    }
+
+    fn walk_block_logs(&mut self, num: BlockNum, last_tx: u32) -> Result<Vec<(u32, Vec<u8>)>> {
+        let prefix = num.to_be_bytes();
+        let mut out = vec![];
+        for res in self
+            .cursor(tables::TransactionLog)?
+            .walk(Some(prefix.to_vec()))
+        {
+            let (k, v) = res?;
+            if k.len() != prefix.len() + 4 || !k.starts_with(&prefix) {
+                break;
+            }
+            let tx_idx = u32::from_be_bytes(k[prefix.len()..].try_into()?);
+            if tx_idx > last_tx {
+                break;
+            }
+            out.push((tx_idx, v));
+        }
+        Ok(out)
+    }
+
+    /// Returns the stored receipts of the block, without their logs, or `None`
+    /// if the block's receipts are not in the db.
+    pub fn read_receipts(&mut self, num: BlockNum) -> Result<Option<Vec<Receipt>>> {
+        self.read_raw_receipts(num)?
+            .map(|enc| Receipt::decode_list(&enc))
+            .transpose()
+    }
+
+    /// Returns the CBOR encoded receipts of the block, exactly as stored.
+    pub fn read_raw_receipts(&mut self, num: BlockNum) -> Result<Option<Vec<u8>>> {
+        self.get(tables::Receipt, num)
+    }
+
+    /// Returns the number of the block of the Polygon state-sync transaction
+    /// `hash`, if it is in BorTxLookup.
+    #[cfg(feature = "polygon")]
+    pub fn find_state_sync_block_number(&mut self, hash: H256) -> Result<Option<BlockNum>> {
+        match self.get(tables::BorTxLookup, hash)? {
+            Some(num) => Ok(Some(BlockNum(u64::try_from(num)?))),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the receipt of the block's state-sync transaction, or `None` if
+    /// the block had no state syncs.
+    #[cfg(feature = "polygon")]
+    pub fn read_bor_receipt(&mut self, num: BlockNum) -> Result<Option<crate::models::BorReceipt>> {
+        self.get(tables::BorReceipt, num)?
+            .map(|enc| {
+                crate::models::BorReceipt::decode(&enc)
+                    .map_err(|e| format_err!("bad bor receipt in block {}: {}", num, e))
+            })
+            .transpose()
+    }
+
+    /// Returns the hashes of the transactions in Erigon's txpool db, as of the
+    /// pool's last flush. Only meaningful for a reader over the txpool environment.
+    pub fn read_pool_transaction_hashes(&mut self) -> Result<Vec<H256>> {
+        let walk = self.cursor(tables::PoolTransaction)?.walk(None);
+        Budgeted::new(walk, self.1.clone())
+            .map(|res| {
+                let (k, _) = res?;
+                convert::h256(&k)
+                    .map_err(|e| format_err!("bad PoolTransaction key {}: {}", hex::encode(&k), e))
+            })
+            .collect()
+    }
+
     /// Helper fn to walk a db table and print key, value pairs
     #[cfg(test)]
     pub fn walk_table_debug<T: akula::kv::Table>(
@@ -258,7 +1294,7 @@ impl<'env, K: TransactionKind, E: EnvironmentKind> Reader<'env, K, E> {
         table: ak_tables::ErasedTable<T>,
     ) -> Result<()> {
         println!("\nWalking table: {:?}", table.0);
-        let mut cur = self.0.cursor(table).unwrap();
+        let mut cur = self.cursor(table).unwrap();
         while let Some((k, v)) = cur.next().unwrap() {
             let k = hex::encode(k);
             let v = hex::encode(v);
@@ -268,9 +1304,274 @@ impl<'env, K: TransactionKind, E: EnvironmentKind> Reader<'env, K, E> {
     }
 }
 
+/// Decodes an AccountChangeSet value: the address, then the account going
+/// into the block, empty if it didn't exist.
+fn decode_account_change(v: &[u8]) -> Result<(Address, Account)> {
+    if v.len() < Address::len_bytes() {
+        return Err(format_err!("account changeset value too short"));
+    }
+    let (who, acct) = v.split_at(Address::len_bytes());
+    Ok((
+        convert::address(who)?,
+        <Account as TableDecode>::decode(acct)?,
+    ))
+}
+
+/// Decodes a StorageChangeSet entry into the address, incarnation, slot and
+/// value going into the block, which is zero if the slot was unset.
+fn decode_storage_change(k: &[u8], v: &[u8]) -> Result<(Address, u64, H256, H256)> {
+    // block number ++ address ++ incarnation => slot ++ value
+    if k.len() != 8 + Address::len_bytes() + 8 || v.len() < H256::len_bytes() {
+        return Err(format_err!("malformed storage changeset entry"));
+    }
+    let who = convert::address(&k[8..8 + Address::len_bytes()])?;
+    let incarnation = u64::from_be_bytes(k[8 + Address::len_bytes()..].try_into()?);
+    let (slot, val) = v.split_at(H256::len_bytes());
+    Ok((
+        who,
+        incarnation,
+        H256::from_slice(slot),
+        convert::trimmed_word(val)?,
+    ))
+}
+
+/// A cursor of a `Reader`, which records the key of every entry it reads if
+/// the reader is traced. Entries of dupsort tables are recorded by their key.
+pub(crate) struct TracedCursor<'tx, K: TransactionKind, T: Table> {
+    inner: MdbxCursor<'tx, K, T>,
+    trace: Option<(TxTrace, String)>,
+}
+
+impl<'tx, K, T> TracedCursor<'tx, K, T>
+where
+    K: TransactionKind,
+    T: Table,
+    T::Key: Clone,
+{
+    fn record(&self, key: &T::Key) {
+        if let Some((trace, table)) = &self.trace {
+            trace.record(table, key.clone().encode().as_ref());
+        }
+    }
+
+    fn seen<V>(&self, entry: Result<Option<(T::Key, V)>>) -> Result<Option<(T::Key, V)>> {
+        if let Ok(Some((key, _))) = &entry {
+            self.record(key);
+        }
+        entry
+    }
+
+    pub fn first(&mut self) -> Result<Option<(T::Key, T::Value)>> {
+        let entry = self.inner.first();
+        self.seen(entry)
+    }
+
+    pub fn last(&mut self) -> Result<Option<(T::Key, T::Value)>> {
+        let entry = self.inner.last();
+        self.seen(entry)
+    }
+
+    pub fn next(&mut self) -> Result<Option<(T::Key, T::Value)>> {
+        let entry = self.inner.next();
+        self.seen(entry)
+    }
+
+    pub fn prev(&mut self) -> Result<Option<(T::Key, T::Value)>> {
+        let entry = self.inner.prev();
+        self.seen(entry)
+    }
+
+    pub fn seek(&mut self, key: T::SeekKey) -> Result<Option<(T::Key, T::Value)>> {
+        let entry = self.inner.seek(key);
+        self.seen(entry)
+    }
+
+    pub fn seek_exact(&mut self, key: T::Key) -> Result<Option<(T::Key, T::Value)>> {
+        let entry = self.inner.seek_exact(key);
+        self.seen(entry)
+    }
+
+    /// Iterates over the entries from `start`, or from the first one.
+    pub fn walk(self, start: Option<T::SeekKey>) -> Walk<'tx, K, T> {
+        Walk {
+            cursor: self,
+            start: Some(start),
+            done: false,
+        }
+    }
+}
+
+impl<'tx, K, T> TracedCursor<'tx, K, T>
+where
+    K: TransactionKind,
+    T: DupSort,
+    T::Key: Clone,
+{
+    pub fn seek_both_range(
+        &mut self,
+        key: T::Key,
+        subkey: T::SeekBothKey,
+    ) -> Result<Option<T::Value>> {
+        let value = self.inner.seek_both_range(key.clone(), subkey)?;
+        if value.is_some() {
+            self.record(&key);
+        }
+        Ok(value)
+    }
+
+    pub fn next_dup(&mut self) -> Result<Option<(T::Key, T::Value)>> {
+        let entry = self.inner.next_dup();
+        self.seen(entry)
+    }
+
+    /// Iterates over the values of `key`.
+    pub fn walk_dup(self, key: T::Key) -> WalkDup<'tx, K, T> {
+        WalkDup {
+            cursor: self,
+            start: Some(key),
+            done: false,
+        }
+    }
+}
+
+/// The entries of a table from a start key, returned by `TracedCursor::walk`.
+pub(crate) struct Walk<'tx, K: TransactionKind, T: Table> {
+    cursor: TracedCursor<'tx, K, T>,
+    start: Option<Option<T::SeekKey>>,
+    done: bool,
+}
+
+impl<'tx, K, T> Iterator for Walk<'tx, K, T>
+where
+    K: TransactionKind,
+    T: Table,
+    T::Key: Clone,
+{
+    type Item = Result<(T::Key, T::Value)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let entry = match self.start.take() {
+            Some(Some(key)) => self.cursor.seek(key),
+            Some(None) => self.cursor.first(),
+            None => self.cursor.next(),
+        };
+        let entry = entry.transpose();
+        self.done = !matches!(entry, Some(Ok(_)));
+        entry
+    }
+}
+
+/// The values of a dupsort key, returned by `TracedCursor::walk_dup`.
+pub(crate) struct WalkDup<'tx, K: TransactionKind, T: DupSort> {
+    cursor: TracedCursor<'tx, K, T>,
+    start: Option<T::Key>,
+    done: bool,
+}
+
+impl<'tx, K, T> Iterator for WalkDup<'tx, K, T>
+where
+    K: TransactionKind,
+    T: DupSort,
+    T::Key: Clone,
+{
+    type Item = Result<T::Value>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let entry = match self.start.take() {
+            Some(key) => self.cursor.seek_exact(key),
+            None => self.cursor.next_dup(),
+        };
+        let entry = entry.map(|e| e.map(|(_, v)| v)).transpose();
+        self.done = !matches!(entry, Some(Ok(_)));
+        entry
+    }
+}
+
+/// An owned, `'static` counterpart to `Reader`.
+///
+/// An `OwnedReader` keeps the environment alive and begins a fresh read-only
+/// transaction for every call to `read`, so it can be moved across threads and
+/// stored in long-lived server state without borrowing from a `Client`.
+#[derive(Debug)]
+pub struct OwnedReader<E: EnvironmentKind>(
+    Arc<MdbxEnvironment<E>>,
+    ReadBudget,
+    bool,
+    Option<ReadTrace>,
+    Option<Duration>,
+);
+
+impl<E: EnvironmentKind> Clone for OwnedReader<E> {
+    fn clone(&self) -> Self {
+        Self(
+            Arc::clone(&self.0),
+            self.1.clone(),
+            self.2,
+            self.3.clone(),
+            self.4,
+        )
+    }
+}
+
+impl<E: EnvironmentKind> OwnedReader<E> {
+    pub fn new(env: Arc<MdbxEnvironment<E>>) -> Self {
+        Self(env, ReadBudget::default(), false, None, None)
+    }
+
+    /// Bounds the readers handed out by `read` by `budget`.
+    pub fn with_budget(mut self, budget: ReadBudget) -> Self {
+        self.1 = budget;
+        self
+    }
+
+    /// Bounds each reader handed out by `read` to `timeout` from when it's
+    /// opened, unless a budget is set. See `ReadBudget::or_timeout`.
+    pub fn with_read_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.4 = timeout;
+        self
+    }
+
+    /// Caps the head seen by the readers handed out by `read`. See `Reader::with_execution_cap`.
+    pub fn with_execution_cap(mut self, cap: bool) -> Self {
+        self.2 = cap;
+        self
+    }
+
+    /// Records the reads of the readers handed out by `read` in `trace`.
+    pub fn with_read_trace(mut self, trace: Option<ReadTrace>) -> Self {
+        self.3 = trace;
+        self
+    }
+
+    /// Runs `f` against a new read-only transaction, which is closed when `f` returns.
+    pub fn read<T, F>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut Reader<'_, mdbx::RO, E>) -> Result<T>,
+    {
+        let mut reader = Reader::new(self.0.begin()?)
+            .with_budget(self.1.or_timeout(self.4))
+            .with_execution_cap(self.2)
+            .with_read_trace(self.3.as_ref());
+        f(&mut reader)
+    }
+}
+
+// Shared handles must be usable from multi-threaded servers.
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<OwnedReader<mdbx::NoWriteMap>>();
+    assert_send_sync::<OwnedReader<mdbx::WriteMap>>();
+};
+
 #[cfg(test)]
 mod tests {
-    use akula::models::{self as ak_models, BodyForStorage, MessageWithSignature, H256};
+    use akula::models::{self as ak_models, MessageWithSignature, H256};
     use anyhow::Result;
     use ethers::{core::types::Address, utils::keccak256};
     use rand::thread_rng;
@@ -278,8 +1579,12 @@ mod tests {
 
     use crate::{
         client::Client,
+        hashed,
         models::Account,
-        test::{ffi::writer::Writer, rand::Rand, TMP_DIR},
+        readtrace::ReadTrace,
+        tables,
+        test::{chain::ChainBuilder, ffi::writer::Writer, rand::Rand, TMP_DIR},
+        types::{BlockNum, HeaderKey, TxId},
     };
 
     // helper for type inference
@@ -288,65 +1593,253 @@ mod tests {
     }
 
     #[test]
-    fn test_read_head_header_hash() -> Result<()> {
-        let hash = keccak256(vec![0xab]).into();
+    fn test_walk_changes() -> Result<()> {
+        let (a, b) = (Address::repeat_byte(0x0a), Address::repeat_byte(0x0b));
+        let slot = |n| H256::from_low_u64_be(n);
+        let chain = ChainBuilder::new()
+            .block(|blk| blk.account_change(b, None))
+            .block(|blk| {
+                blk.account_change(b, Some(Account::new().balance(3.into())))
+                    .account_change(a, Some(Account::new().nonce(1)))
+                    .storage_change(b, 1, slot(2), slot(5))
+                    .storage_change(b, 1, slot(1), H256::zero())
+            })
+            .block(|blk| blk)
+            .write(TMP_DIR.clone())?;
+        let db = client(chain.path.clone())?;
+        let mut dbtx = db.reader()?;
 
-        let mut w = Writer::open(TMP_DIR.clone())?;
-        w.put_head_header_hash(hash)?;
-        let path = w.close()?;
+        let accounts = dbtx
+            .walk_account_changes(BlockNum(2))?
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(
+            accounts,
+            vec![
+                (a, Account::new().nonce(1)),
+                (b, Account::new().balance(3.into()))
+            ]
+        );
+        let created = dbtx
+            .walk_account_changes(BlockNum(1))?
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(created, vec![(b, Account::default())]);
 
-        let db = client(path)?;
+        let slots = dbtx
+            .walk_storage_changes(BlockNum(2))?
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(
+            slots,
+            vec![(b, 1, slot(1), H256::zero()), (b, 1, slot(2), slot(5))]
+        );
+        assert_eq!(dbtx.walk_storage_changes(BlockNum(3))?.count(), 0);
+        assert_eq!(dbtx.walk_account_changes(BlockNum(3))?.count(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_change_from() -> Result<()> {
+        let who = Address::repeat_byte(0x0a);
+        let slot = H256::from_low_u64_be(1);
+        let chain = ChainBuilder::new()
+            .block(|blk| blk.account_change(who, None))
+            .block(|blk| blk)
+            .block(|blk| {
+                blk.account_change(who, Some(Account::new().nonce(1)))
+                    .storage_change(who, 1, slot, H256::zero())
+            })
+            .block(|blk| blk)
+            .write(TMP_DIR.clone())?;
+        let db = client(chain.path.clone())?;
+        let mut dbtx = db.reader()?;
+
+        assert_eq!(
+            dbtx.read_account_change_from(who, BlockNum(0))?,
+            Some(BlockNum(1))
+        );
+        assert_eq!(
+            dbtx.read_account_change_from(who, BlockNum(1))?,
+            Some(BlockNum(1))
+        );
+        assert_eq!(
+            dbtx.read_account_change_from(who, BlockNum(2))?,
+            Some(BlockNum(3))
+        );
+        assert_eq!(dbtx.read_account_change_from(who, BlockNum(4))?, None);
+        assert_eq!(
+            dbtx.read_storage_change_from(who, slot, BlockNum(2))?,
+            Some(BlockNum(3))
+        );
+        assert_eq!(
+            dbtx.read_storage_change_from(who, H256::zero(), BlockNum(0))?,
+            None
+        );
+
+        // a key past the last shard of another
+        let (x, y) = (Address::repeat_byte(0x01), Address::repeat_byte(0x02));
+        let chain = ChainBuilder::new()
+            .start(5)
+            .block(|blk| blk.account_change(x, None))
+            .block(|blk| blk)
+            .block(|blk| blk.account_change(x, Some(Account::new())))
+            .block(|blk| blk)
+            .block(|blk| blk.account_change(y, None))
+            .write(TMP_DIR.clone())?;
+        let db = client(chain.path.clone())?;
+        let mut dbtx = db.reader()?;
+        assert_eq!(
+            dbtx.read_account_change_from(x, BlockNum(6))?,
+            Some(BlockNum(7))
+        );
+        assert_eq!(dbtx.read_account_change_from(x, BlockNum(8))?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_head_header_hash() -> Result<()> {
+        let chain = ChainBuilder::new()
+            .block(|blk| blk)
+            .block(|blk| blk)
+            .write(TMP_DIR.clone())?;
+
+        let db = client(chain.path.clone())?;
         let read = db.reader()?.read_head_header_hash()?;
-        assert_eq!(read, hash);
+        assert_eq!(read, chain.hash(1));
         Ok(())
     }
 
     #[test]
     fn test_read_header() -> Result<()> {
+        let chain = ChainBuilder::new()
+            .block(|blk| blk)
+            .write(TMP_DIR.clone())?;
+        let header = &chain.head().header;
+        let key = HeaderKey::new(header.number, chain.hash(0));
+
+        let db = client(chain.path.clone())?;
+        let read = db.reader()?.read_header(key)?;
+        assert_eq!(&read, header);
+        Ok(())
+    }
+
+    #[test]
+    fn test_iter_ancestors() -> Result<()> {
         let mut rng = thread_rng();
-        let header = ak_models::BlockHeader::rand(&mut rng);
-        let key = (header.number, header.hash());
 
-        let mut w = Writer::open(TMP_DIR.clone())?;
-        w.put_header(header.clone())?;
-        let path = w.close()?;
+        // a chain of 4 headers, and a sibling of the last
+        let built = ChainBuilder::new()
+            .start(u64::from(u32::rand(&mut rng)) + 1)
+            .block(|blk| blk)
+            .block(|blk| blk)
+            .block(|blk| blk)
+            .block(|blk| blk.side_block(vec![0xff]))
+            .write(TMP_DIR.clone())?;
+        let chain = built
+            .blocks
+            .iter()
+            .map(|block| block.header.clone())
+            .collect::<Vec<_>>();
+        let sibling = built.side_blocks[0].clone();
 
-        let db = client(path)?;
-        let read = db.reader()?.read_header(key)?;
-        assert_eq!(read, header);
+        let db = client(built.path.clone())?;
+        let mut dbtx = db.reader()?;
+        let read = dbtx
+            .iter_ancestors(sibling.hash(), 3)
+            .map(|res| res.map(|(_, header)| header))
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(read, vec![sibling, chain[2].clone(), chain[1].clone()]);
+
+        // the walk stops at the first header missing from the db
+        let read = dbtx.iter_ancestors(chain[3].hash(), 10).collect::<Vec<_>>();
+        assert_eq!(read.len(), 5);
+        assert!(read[4].is_err());
         Ok(())
     }
 
     #[test]
-    fn test_read_header_number() -> Result<()> {
+    fn test_seek_floor_ceil() -> Result<()> {
         let mut rng = thread_rng();
-        let num = Rand::rand(&mut rng);
-        let hash = keccak256(vec![0x10]).into();
+        let hashes: Vec<H256> = (0..3).map(|_| H256::rand(&mut rng)).collect();
 
         let mut w = Writer::open(TMP_DIR.clone())?;
-        w.put_header_number(hash, num)?;
+        for (i, hash) in hashes.iter().enumerate() {
+            let num = ak_models::BlockNumber(10 * (i as u64 + 1));
+            w.put_canonical_hash(*hash, num)?;
+            w.put_issuance(num, 1.into(), 2.into())?;
+        }
         let path = w.close()?;
 
         let db = client(path)?;
-        let read = db.reader()?.read_header_number(hash)?;
-        assert_eq!(read, num);
+        let mut dbtx = db.reader()?;
+        let table = tables::CanonicalHeader;
+        assert_eq!(
+            dbtx.seek_ceil(table, BlockNum(15))?,
+            Some((BlockNum(20), hashes[1]))
+        );
+        assert_eq!(
+            dbtx.seek_ceil(table, BlockNum(20))?,
+            Some((BlockNum(20), hashes[1]))
+        );
+        assert_eq!(dbtx.seek_ceil(table, BlockNum(31))?, None);
+        assert_eq!(
+            dbtx.seek_floor(table, BlockNum(15))?,
+            Some((BlockNum(10), hashes[0]))
+        );
+        assert_eq!(
+            dbtx.seek_floor(table, BlockNum(u64::MAX))?,
+            Some((BlockNum(30), hashes[2]))
+        );
+        assert_eq!(dbtx.seek_floor(table, BlockNum(5))?, None);
+
+        // raw keys are read as beginning with a block number
+        let (key, _) = dbtx.seek_floor(tables::Issuance, BlockNum(25))?.unwrap();
+        assert_eq!(key, BlockNum(20).to_be_bytes().to_vec());
+
+        // a traced cursor records every entry it reads
+        let trace = ReadTrace::new(16);
+        let mut dbtx = db.reader()?.with_read_trace(Some(&trace));
+        dbtx.seek_floor(table, BlockNum(15))?;
+        let reads = trace
+            .all()
+            .into_iter()
+            .map(|r| (r.table, r.key))
+            .collect::<Vec<_>>();
+        let name = tables::CanonicalHeader::const_db_name().to_string();
+        assert_eq!(
+            reads,
+            vec![
+                (name.clone(), BlockNum(20).to_be_bytes().to_vec()),
+                (name, BlockNum(10).to_be_bytes().to_vec()),
+            ]
+        );
         Ok(())
     }
 
     #[test]
-    fn test_is_canonical_hash() -> Result<()> {
+    fn test_read_header_number() -> Result<()> {
         let mut rng = thread_rng();
-        let num = Rand::rand(&mut rng);
-        let hash = keccak256(vec![0x10]).into();
+        let num = u64::from(u32::rand(&mut rng));
 
-        let mut w = Writer::open(TMP_DIR.clone())?;
-        w.put_header_number(hash, num)?;
-        w.put_canonical_hash(hash, num)?;
-        let path = w.close()?;
+        let chain = ChainBuilder::new()
+            .start(num)
+            .block(|blk| blk)
+            .write(TMP_DIR.clone())?;
 
-        let db = client(path)?;
-        let read = db.reader()?.is_canonical_hash(hash)?;
-        assert!(read);
+        let db = client(chain.path.clone())?;
+        let read = db.reader()?.read_header_number(chain.hash(0))?;
+        assert_eq!(read, BlockNum(num));
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_canonical_hash() -> Result<()> {
+        let chain = ChainBuilder::new()
+            .block(|blk| blk.side_block(vec![0xff]))
+            .write(TMP_DIR.clone())?;
+
+        let db = client(chain.path.clone())?;
+        let mut dbtx = db.reader()?;
+        assert!(dbtx.is_canonical_hash(chain.hash(0))?);
+        assert!(!dbtx.is_canonical_hash(chain.side_blocks[0].hash())?);
         Ok(())
     }
 
@@ -360,34 +1853,68 @@ mod tests {
             codehash: keccak256(vec![0xff]).into(),
         };
 
-        let mut w = Writer::open(TMP_DIR.clone())?;
-        w.put_account(who, acct)?;
-        let path = w.close()?;
+        let chain = ChainBuilder::new()
+            .block(|blk| blk.account(who, acct))
+            .write(TMP_DIR.clone())?;
 
-        let db = client(path)?;
+        let db = client(chain.path)?;
         let mut dbtx = db.reader().unwrap();
         let read = dbtx.read_account_data(who).unwrap();
         assert_eq!(acct, read);
         Ok(())
     }
 
+    #[test]
+    fn test_read_hashed_state() -> Result<()> {
+        let who = Address::repeat_byte(0x33);
+        let acct = Account::new().balance(7u64.into());
+        let (slot, val) = (H256::from_low_u64_be(1), H256::from_low_u64_be(0x0100));
+
+        let mut w = Writer::open(TMP_DIR.clone())?;
+        w.put_hashed_account(who, acct)?;
+        w.put_hashed_storage(who, 1, slot, val)?;
+        w.put_hashed_storage(who, 1, H256::from_low_u64_be(2), H256::repeat_byte(0xff))?;
+        let path = w.close()?;
+
+        let db = client(path)?;
+        let mut dbtx = db.reader()?;
+        let hashed_who = hashed::hash_address(who);
+        assert_eq!(dbtx.read_hashed_account(hashed_who)?, Some(acct));
+        // stored under the hash, not the address
+        assert_eq!(dbtx.read_hashed_account(H256::from(who))?, None);
+        assert_eq!(
+            dbtx.read_hashed_storage(hashed_who, 1, hashed::hash_slot(slot))?,
+            val
+        );
+        assert_eq!(
+            dbtx.read_hashed_storage(hashed_who, 1, hashed::hash_slot(H256::from_low_u64_be(2)))?,
+            H256::repeat_byte(0xff)
+        );
+        assert!(dbtx
+            .read_hashed_storage(hashed_who, 2, hashed::hash_slot(slot))?
+            .is_zero());
+        assert!(dbtx.read_hashed_storage(hashed_who, 1, slot)?.is_zero());
+        Ok(())
+    }
+
     #[test]
     fn test_read_transactions() -> Result<()> {
         let mut rng = thread_rng();
-        let base_id = u64::rand(&mut rng);
         let n = 3;
 
         let txs = (0..n)
             .map(|_| MessageWithSignature::rand(&mut rng))
             .collect::<Vec<_>>();
 
-        let mut w = Writer::open(TMP_DIR.clone())?;
-        w.put_transactions(txs.clone(), base_id)?;
-        let path = w.close()?;
+        let chain = ChainBuilder::new()
+            .block(|blk| blk.txs(txs.clone()))
+            .write(TMP_DIR.clone())?;
 
-        let db = client(path)?;
+        let db = client(chain.path.clone())?;
         let mut dbtx = db.reader().unwrap();
-        let read = dbtx.read_transactions(base_id, n).unwrap();
+        let key = HeaderKey::new(chain.head().header.number, chain.hash(0));
+        let base_id = dbtx.read_body_for_storage(key)?.base_tx_id;
+        let read = dbtx.read_transactions(TxId(*base_id), n).unwrap();
 
         for (i, t) in read.into_iter().enumerate() {
             assert_eq!(t, txs[i]);
@@ -398,40 +1925,44 @@ mod tests {
     #[test]
     fn test_read_body_for_storage() -> Result<()> {
         let mut rng = thread_rng();
-        let hash = H256::rand(&mut rng);
-        let num = u64::rand(&mut rng);
-        let body = BodyForStorage::rand(&mut rng);
+        let txs: Vec<MessageWithSignature> = crate::test::rand::rand_vec(&mut rng, 3);
+        let ommer = ak_models::BlockHeader::rand(&mut rng);
 
-        let mut w = Writer::open(TMP_DIR.clone())?;
-        w.put_body_for_storage(hash, num.into(), body.clone())?;
-        let path = w.close()?;
+        let chain = ChainBuilder::new()
+            .block(|blk| blk.txs(txs).ommer(ommer.clone()))
+            .block(|blk| blk)
+            .write(TMP_DIR.clone())?;
 
-        let db = client(path)?;
+        let db = client(chain.path.clone())?;
         let mut dbtx = db.reader().unwrap();
-        let key = (num.into(), hash);
-        let read = dbtx.read_body_for_storage(key).unwrap();
-
-        assert_eq!(read.base_tx_id, body.base_tx_id + 1);
-        assert_eq!(read.tx_amount + 2, body.tx_amount);
-        assert_eq!(read.uncles, body.uncles);
+        let key = |i: usize| HeaderKey::new(chain.blocks[i].header.number, chain.hash(i));
+        let first = dbtx.read_body_for_storage(key(0)).unwrap();
+        let second = dbtx.read_body_for_storage(key(1)).unwrap();
+
+        // the system txs on either side of a block's txs are left out
+        assert_eq!(first.tx_amount, 3);
+        assert_eq!(first.uncles, vec![ommer]);
+        assert_eq!(second.base_tx_id, first.base_tx_id + 3 + 2);
+        assert_eq!(second.tx_amount, 0);
         Ok(())
     }
 
     #[test]
     fn test_read_transaction_block_number() -> Result<()> {
         let mut rng = thread_rng();
-        let block_num = ak_models::BlockNumber::rand(&mut rng);
-        let tx_hashes = (0..5).map(|_| H256::rand(&mut rng)).collect::<Vec<_>>();
+        let block_num = u64::from(u32::rand(&mut rng));
+        let txs: Vec<MessageWithSignature> = crate::test::rand::rand_vec(&mut rng, 5);
 
-        let mut w = Writer::open(TMP_DIR.clone())?;
-        w.put_tx_lookup_entries(block_num, tx_hashes.clone())?;
-        let path = w.close()?;
+        let chain = ChainBuilder::new()
+            .start(block_num)
+            .block(|blk| blk.txs(txs.clone()))
+            .write(TMP_DIR.clone())?;
 
-        let db = client(path)?;
+        let db = client(chain.path)?;
         let mut dbtx = db.reader().unwrap();
-        for hash in tx_hashes {
-            let read = dbtx.read_transaction_block_number(hash).unwrap();
-            assert_eq!(read, block_num);
+        for tx in txs {
+            let read = dbtx.read_transaction_block_number(tx.hash()).unwrap();
+            assert_eq!(read, BlockNum(block_num));
         }
         Ok(())
     }
@@ -446,19 +1977,23 @@ mod tests {
         let vals = crate::test::rand::rand_vec(&mut rng, n);
         let mut kv = keys.into_iter().zip(vals);
 
-        let mut w = Writer::open(TMP_DIR.clone())?;
-        for (k, v) in kv.clone() {
-            w.put_storage(who, k, v)?;
-        }
-        // shouldn't get storage value from a different account
-        w.put_storage(
+        let other = (
             Rand::rand(&mut rng),
             Rand::rand(&mut rng),
             Rand::rand(&mut rng),
-        )?;
-        let path = w.close()?;
-
-        let db = client(path)?;
+        );
+
+        let chain = ChainBuilder::new()
+            .block(|mut blk| {
+                for (k, v) in kv.clone() {
+                    blk = blk.storage(who, k, v);
+                }
+                // shouldn't get storage value from a different account
+                blk.storage(other.0, other.1, other.2)
+            })
+            .write(TMP_DIR.clone())?;
+
+        let db = client(chain.path)?;
         let mut dbtx = db.reader()?;
         let read = dbtx.walk_account_storage(who, 0)?;
 
@@ -0,0 +1,134 @@
+//! An audit log of the table reads made by a client, for precise bug
+//! reproductions: the records of a call name every key it looked up and the
+//! key of every entry its cursors read, which is enough to extract the db
+//! entries behind it.
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+/// A read recorded by a `ReadTrace`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReadRecord {
+    pub table: String,
+    /// The key looked up, or of the entry a cursor read.
+    pub key: Vec<u8>,
+}
+
+#[derive(Debug)]
+struct Records {
+    // (transaction id, read), oldest first
+    reads: VecDeque<(u64, ReadRecord)>,
+    capacity: usize,
+    next_tx: u64,
+}
+
+/// A bounded ring buffer of the reads made by the transactions of a client
+/// and its clones. Once full, the oldest reads are dropped.
+#[derive(Debug, Clone)]
+pub struct ReadTrace(Arc<Mutex<Records>>);
+
+impl ReadTrace {
+    pub fn new(capacity: usize) -> Self {
+        Self(Arc::new(Mutex::new(Records {
+            reads: VecDeque::with_capacity(capacity.min(1 << 16)),
+            capacity,
+            next_tx: 0,
+        })))
+    }
+
+    /// Returns a handle which tags the reads of a new transaction.
+    pub(crate) fn begin(&self) -> TxTrace {
+        let mut records = self.0.lock().unwrap();
+        let tx = records.next_tx;
+        records.next_tx += 1;
+        TxTrace {
+            trace: self.clone(),
+            tx,
+        }
+    }
+
+    /// Returns the reads made by the most recently begun transaction, oldest
+    /// first. Empty if it made none, or if they have all been dropped.
+    pub fn last(&self) -> Vec<ReadRecord> {
+        let records = self.0.lock().unwrap();
+        let last = match records.next_tx.checked_sub(1) {
+            Some(tx) => tx,
+            None => return vec![],
+        };
+        records
+            .reads
+            .iter()
+            .filter(|(tx, _)| *tx == last)
+            .map(|(_, read)| read.clone())
+            .collect()
+    }
+
+    /// Returns every read still in the buffer, oldest first.
+    pub fn all(&self) -> Vec<ReadRecord> {
+        let records = self.0.lock().unwrap();
+        records.reads.iter().map(|(_, read)| read.clone()).collect()
+    }
+
+    pub fn clear(&self) {
+        self.0.lock().unwrap().reads.clear();
+    }
+}
+
+/// The trace of one transaction's reads.
+#[derive(Debug, Clone)]
+pub(crate) struct TxTrace {
+    trace: ReadTrace,
+    tx: u64,
+}
+
+impl TxTrace {
+    pub(crate) fn record(&self, table: &str, key: &[u8]) {
+        let mut records = self.trace.0.lock().unwrap();
+        if records.capacity == 0 {
+            return;
+        }
+        if records.reads.len() == records.capacity {
+            records.reads.pop_front();
+        }
+        let read = ReadRecord {
+            table: table.to_string(),
+            key: key.to_vec(),
+        };
+        records.reads.push_back((self.tx, read));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_trace() {
+        let trace = ReadTrace::new(3);
+        assert!(trace.last().is_empty());
+
+        let first = trace.begin();
+        first.record("Header", &[1]);
+        first.record("Header", &[2]);
+        let second = trace.begin();
+        second.record("Receipt", &[9]);
+        assert_eq!(
+            trace.last(),
+            vec![ReadRecord {
+                table: "Receipt".to_string(),
+                key: vec![9],
+            }]
+        );
+
+        // the oldest read is dropped
+        first.record("Header", &[3]);
+        let keys = trace.all().into_iter().map(|r| r.key).collect::<Vec<_>>();
+        assert_eq!(keys, vec![vec![2], vec![9], vec![3]]);
+
+        trace.clear();
+        assert!(trace.all().is_empty());
+        ReadTrace::new(0).begin().record("Header", &[]);
+    }
+}
@@ -0,0 +1,725 @@
+use akula::models::MessageWithSignature;
+use anyhow::{format_err, Result};
+use ethers::{
+    types::{
+        BlockId, BlockNumber as EthersBlockNumber, Bytes, Log, Transaction, TransactionReceipt,
+        TxHash, H256, U64,
+    },
+    utils::{get_contract_address, keccak256},
+};
+use mdbx::{EnvironmentKind, TransactionKind};
+use std::{fmt, mem, sync::Arc};
+
+use crate::{
+    builder::PrunedData,
+    cache::MissingKey,
+    cbor,
+    client::{get_header_key, res_block_number, Client, Either},
+    codec::{self, MsgCast, StoredTx},
+    convert,
+    reader::Reader,
+    tables, trie,
+    types::{BlockNum, HeaderKey},
+};
+
+pub(crate) const RECEIPT_TABLES: &[&str] = &[
+    tables::Receipt::const_db_name(),
+    tables::TransactionLog::const_db_name(),
+    tables::CanonicalHeader::const_db_name(),
+    tables::BlockBody::const_db_name(),
+    tables::BlockTransaction::const_db_name(),
+    tables::TxSender::const_db_name(),
+];
+
+/// Derives the status of a pre-Byzantium receipt, which commits to the
+/// post-transaction state root instead (EIP-658). Only re-executing the
+/// transaction against its parent state tells the status for certain, which
+/// this crate leaves to the implementor.
+pub trait StatusBackfill: fmt::Debug + Send + Sync {
+    /// Returns 1 if `tx` succeeded and 0 if it failed, or `None` if it can't
+    /// be told. `receipt` is complete apart from its status.
+    fn status(&self, tx: &Transaction, receipt: &TransactionReceipt) -> Result<Option<U64>>;
+}
+
+/// A stored value and the result of decoding it. Decode errors are kept as
+/// their message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decoded<T> {
+    pub raw: Bytes,
+    pub decoded: Result<T, String>,
+}
+
+impl<T> Decoded<T> {
+    fn new<F: FnOnce(&[u8]) -> Result<T>>(raw: &[u8], decode: F) -> Self {
+        Self {
+            raw: raw.to_vec().into(),
+            decoded: decode(raw).map_err(|e| e.to_string()),
+        }
+    }
+}
+
+/// The stored receipt of one transaction and the logs it emitted, as returned
+/// by `Client::get_raw_receipts`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawReceipt {
+    pub transaction_index: u32,
+    /// The receipt's item of the block's CBOR encoded receipt list.
+    pub receipt: Decoded<codec::Receipt>,
+    /// The CBOR encoded logs, if the transaction emitted any.
+    pub logs: Option<Decoded<Vec<codec::Log>>>,
+}
+
+/// A transaction and the gas it used, as returned by `Client::read_txs_gas_used`.
+pub(crate) struct TxGasUsed {
+    pub index: usize,
+    pub msg: MessageWithSignature,
+    pub gas_used: u64,
+}
+
+impl<E: EnvironmentKind> Client<E> {
+    /// Returns the receipts for the block if they are stored in the db. If they
+    /// are not, erigon would attempt to reconstruct them. In this case, the block
+    /// number is returned so the caller can attempt to get the receipts over rpc.
+    pub fn get_block_receipts<T: Into<EthersBlockNumber> + Send + Sync>(
+        &self,
+        block: T,
+    ) -> Result<Either<BlockNum, Vec<TransactionReceipt>>> {
+        let block = block.into();
+        self.ensure_unpruned("get_block_receipts", PrunedData::Receipts)?;
+        let _slow = self.slow_read("get_block_receipts", RECEIPT_TABLES, || {
+            format!("{:?}", block)
+        });
+        let mut dbtx = self.reader()?;
+        let num = res_block_number(&mut dbtx, block)?;
+        let key = HeaderKey::new(num, dbtx.read_canonical_hash(num)?);
+        match self.read_receipts_cached(&mut dbtx, key)? {
+            Some(receipts) => Ok(Either::Right(receipts)),
+            None => Ok(Either::Left(num)),
+        }
+    }
+
+    /// Like `get_block_receipts`, for the block `key`. Receipts are stored by
+    /// block number, so this fails if the block is no longer canonical.
+    pub fn get_block_receipts_by_key(
+        &self,
+        key: HeaderKey,
+    ) -> Result<Either<BlockNum, Vec<TransactionReceipt>>> {
+        self.ensure_unpruned("get_block_receipts", PrunedData::Receipts)?;
+        let _slow = self.slow_read("get_block_receipts", RECEIPT_TABLES, || {
+            format!("{:?}", key)
+        });
+        let mut dbtx = self.reader()?;
+        let canonical = dbtx.read_canonical_hash(key.num)?;
+        anyhow::ensure!(
+            canonical == key.hash,
+            "block {:?} is not canonical at {}, {:?} is",
+            key.hash,
+            key.num,
+            canonical
+        );
+        match self.read_receipts_cached(&mut dbtx, key)? {
+            Some(receipts) => Ok(Either::Right(receipts)),
+            None => Ok(Either::Left(key.num)),
+        }
+    }
+
+    /// Returns the receipts of the block `key` from the cache, or builds and
+    /// caches them. `None` if the block's receipts are not stored.
+    pub(crate) fn read_receipts_cached<TX: TransactionKind>(
+        &self,
+        dbtx: &mut Reader<'_, TX, E>,
+        key: HeaderKey,
+    ) -> Result<Option<Vec<TransactionReceipt>>> {
+        if let Some(receipts) = self.caches.receipts.lock().unwrap().get(&key) {
+            return Ok(Some(receipts.to_vec()));
+        }
+        let receipts = self.build_receipts(dbtx, key, None)?;
+        if let Some(receipts) = &receipts {
+            let weight = receipts_weight(receipts);
+            self.caches
+                .receipts
+                .lock()
+                .unwrap()
+                .insert(key, Arc::new(receipts.clone()), weight);
+        }
+        Ok(receipts)
+    }
+
+    /// Returns the receipt of the transaction `hash`. If its block's receipts
+    /// are cached, the receipt is taken from there. Otherwise only the
+    /// transactions and logs up to `hash` are read, or the whole block if the
+    /// client verifies reads, and nothing is cached. As
+    /// with `get_block_receipts`, the block number is returned if the block's
+    /// receipts are not stored in the db. `None` if the transaction is unknown.
+    pub fn get_transaction_receipt<T: Send + Sync + Into<TxHash>>(
+        &self,
+        transaction_hash: T,
+    ) -> Result<Either<BlockNum, Option<TransactionReceipt>>> {
+        let hash = transaction_hash.into();
+        self.ensure_unpruned("get_transaction_receipt", PrunedData::Receipts)?;
+        self.ensure_unpruned("get_transaction_receipt", PrunedData::TxLookup)?;
+        let _slow = self.slow_read("get_transaction_receipt", RECEIPT_TABLES, || {
+            format!("{:?}", hash)
+        });
+        let mut dbtx = self.reader()?;
+        let num = match self.find_transaction_block_number_cached(&mut dbtx, hash)? {
+            Some(num) => num,
+            None => {
+                #[cfg(feature = "polygon")]
+                if let Some(key) = self.find_state_sync_block(&mut dbtx, hash)? {
+                    if self.options().system_txs.excludes_state_syncs() {
+                        return Ok(Either::Right(None));
+                    }
+                    return Ok(Either::Right(self.state_sync_receipt(&mut dbtx, key)?));
+                }
+                // unknown, as eth_getTransactionReceipt's null
+                self.record_missing(&mut dbtx, MissingKey::Tx(hash));
+                return Ok(Either::Right(None));
+            }
+        };
+        let key = HeaderKey::new(num, dbtx.read_canonical_hash(num)?);
+
+        if let Some(receipts) = self.caches.receipts.lock().unwrap().get(&key) {
+            let receipt = receipts.iter().find(|r| r.transaction_hash == hash);
+            return Ok(Either::Right(receipt.cloned()));
+        }
+        // receipts can only be checked against the root all together
+        let until = if self.options().features.verify {
+            None
+        } else {
+            Some(hash)
+        };
+        match self.build_receipts(&mut dbtx, key, until)? {
+            // missing if the tx is an excluded system tx
+            Some(receipts) => Ok(Either::Right(
+                receipts
+                    .into_iter()
+                    .rev()
+                    .find(|r| r.transaction_hash == hash),
+            )),
+            None => Ok(Either::Left(num)),
+        }
+    }
+
+    /// Returns the receipts of the block exactly as stored, each alongside the
+    /// result of decoding it, or `None` if the block's receipts are not in the
+    /// db. Meant for reporting decode failures, so a receipt which fails to
+    /// decode is not an error.
+    pub fn get_raw_receipts<T: Into<BlockId> + Send + Sync>(
+        &self,
+        block_hash_or_number: T,
+    ) -> Result<Option<Vec<RawReceipt>>> {
+        self.ensure_unpruned("get_raw_receipts", PrunedData::Receipts)?;
+        let id = block_hash_or_number.into();
+        let _slow = self.slow_read("get_raw_receipts", RECEIPT_TABLES, || format!("{:?}", id));
+        let mut dbtx = self.reader()?;
+        let key = get_header_key(&mut dbtx, id)?;
+        // receipts are stored by number, so only the canonical block has any
+        if dbtx.read_canonical_hash(key.num)? != key.hash {
+            return Ok(None);
+        }
+        let raw = match dbtx.read_raw_receipts(key.num)? {
+            Some(raw) => raw,
+            None => return Ok(None),
+        };
+        let items = cbor::split_array(&raw).map_err(|e| {
+            format_err!(
+                "bad receipt list in block {}: {}. Raw: 0x{}",
+                key.num,
+                e,
+                hex::encode(&raw)
+            )
+        })?;
+
+        let mut logs = dbtx.read_raw_block_logs(key.num)?.into_iter().peekable();
+        let receipts = (0..)
+            .zip(items)
+            .map(|(tx_idx, item)| {
+                let tx_logs = match logs.peek() {
+                    Some((idx, _)) if *idx == tx_idx => logs.next().map(|(_, raw)| raw),
+                    _ => None,
+                };
+                RawReceipt {
+                    transaction_index: tx_idx,
+                    receipt: Decoded::new(item, codec::Receipt::decode),
+                    logs: tx_logs.map(|raw| Decoded::new(&raw, codec::Log::decode_list)),
+                }
+            })
+            .collect();
+        Ok(Some(receipts))
+    }
+
+    /// Returns the transactions of the block `key` with the gas each used: the
+    /// difference between the cumulative gas of its receipt and the previous
+    /// one's. Transactions left out by the client's `SystemTxs` are skipped,
+    /// and the rest keep their index in the block.
+    pub(crate) fn read_txs_gas_used<TX: TransactionKind>(
+        &self,
+        dbtx: &mut Reader<'_, TX, E>,
+        key: HeaderKey,
+    ) -> Result<Vec<TxGasUsed>> {
+        let body = dbtx.read_body_for_storage(key)?;
+        let msgs = dbtx.read_transactions(body.base_tx_id.into(), body.tx_amount.try_into()?)?;
+        let receipts = dbtx
+            .read_receipts(key.num)?
+            .ok_or_else(|| format_err!("receipts for block {} not in db", key.num))?;
+        anyhow::ensure!(
+            receipts.len() == msgs.len(),
+            "block {} has {} receipts for {} txs",
+            key.num,
+            receipts.len(),
+            msgs.len()
+        );
+        let excluded = self.excluded_txs(dbtx, key, &msgs)?;
+
+        let mut txs = Vec::with_capacity(msgs.len());
+        let mut prev_gas_used = 0;
+        for (index, ((msg, receipt), excluded)) in
+            msgs.into_iter().zip(&receipts).zip(excluded).enumerate()
+        {
+            let gas_used = receipt
+                .cumulative_gas_used
+                .checked_sub(prev_gas_used)
+                .ok_or_else(|| format_err!("decreasing cumulative gas in block {}", key.num))?;
+            prev_gas_used = receipt.cumulative_gas_used;
+            if !excluded {
+                txs.push(TxGasUsed {
+                    index,
+                    msg,
+                    gas_used,
+                });
+            }
+        }
+        Ok(txs)
+    }
+
+    /// Assembles the receipts of the block `key` from its stored receipts, its
+    /// transactions and their logs. If `until` is given, stops after that
+    /// transaction, which must be in the block. Returns `None` if the block's
+    /// receipts are not stored.
+    fn build_receipts<TX: TransactionKind>(
+        &self,
+        dbtx: &mut Reader<'_, TX, E>,
+        key: HeaderKey,
+        until: Option<H256>,
+    ) -> Result<Option<Vec<TransactionReceipt>>> {
+        let stored = match dbtx.read_receipts(key.num)? {
+            Some(stored) => stored,
+            None => return Ok(None),
+        };
+        let header = self.read_header_cached(dbtx, key)?;
+        let body = dbtx.read_body_for_storage(key)?;
+        let tx_amt: usize = body.tx_amount.try_into()?;
+
+        if until.is_none() {
+            self.verify_transactions(dbtx, key, &header, body.base_tx_id.into(), tx_amt)?;
+        }
+        // txs cast by a custom decoder keep their own sender, or the stored one
+        let stored_senders = dbtx.read_senders(key)?;
+        let sender = |idx: usize| stored_senders.get(idx).copied().unwrap_or_default();
+        let (mut msgs, mut known, mut txs) = (vec![], vec![], vec![]);
+        for (idx, raw) in dbtx
+            .read_raw_transactions(body.base_tx_id.into(), tx_amt)?
+            .iter()
+            .enumerate()
+        {
+            let tx = self
+                .options()
+                .custom_txs
+                .decode(raw, key.num, key.hash, idx)?;
+            let done = until == Some(tx.hash());
+            match tx {
+                StoredTx::Msg(msg) => {
+                    msgs.push(msg);
+                    known.push(sender(idx));
+                    txs.push(Either::Left(idx));
+                }
+                StoredTx::Custom(mut tx) => {
+                    if tx.from.is_zero() {
+                        tx.from = sender(idx);
+                    }
+                    txs.push(Either::Right(tx));
+                }
+            }
+            if done {
+                break;
+            }
+        }
+        if let Some(hash) = until {
+            let last = match txs.last() {
+                Some(Either::Left(_)) => msgs.last().map(|msg| msg.hash()),
+                Some(Either::Right(tx)) => Some(tx.hash),
+                None => None,
+            };
+            if last != Some(hash) {
+                return Err(format_err!(
+                    "No transaction hash {} in block {}",
+                    hash,
+                    key.num
+                ));
+            }
+        }
+        anyhow::ensure!(
+            stored.len() >= txs.len(),
+            "block {} has {} receipts for {} transactions",
+            key.num,
+            stored.len(),
+            txs.len()
+        );
+        if txs.is_empty() {
+            return Ok(Some(vec![]));
+        }
+
+        let senders = self.recover_block_senders(key.num, &msgs, &known)?;
+        let mut msgs = msgs.iter().zip(senders);
+        let mut logs = dbtx
+            .read_block_logs_upto(key.num, (txs.len() - 1).try_into()?)?
+            .into_iter()
+            .peekable();
+
+        let base_fee = header.base_fee_per_gas.map(convert::u256);
+        let verify = self.options().features.verify && until.is_none();
+        let mut encodings = vec![];
+        let mut receipts = Vec::with_capacity(txs.len());
+        let mut log_index = 0u64;
+        let mut prev_gas_used = 0u64;
+        for (idx, (tx, stored)) in txs.into_iter().zip(stored).enumerate() {
+            let (tx, effective_gas_price) = match tx {
+                Either::Left(_) => {
+                    let (msg, sender) = msgs.next().expect("a msg per built-in tx");
+                    let mut cast = MsgCast::new(msg);
+                    cast.maybe_signer(sender)
+                        .block(key.num, key.hash)
+                        .index(idx)
+                        .base_fee(base_fee);
+                    (cast.transaction()?, cast.effective_gas_price())
+                }
+                Either::Right(tx) => {
+                    let price = tx.gas_price;
+                    (tx, price)
+                }
+            };
+            let tx_logs = match logs.peek() {
+                Some((tx_idx, _)) if *tx_idx as usize == idx => logs.next().unwrap().1,
+                _ => vec![],
+            };
+
+            let mut receipt = TransactionReceipt {
+                transaction_hash: tx.hash,
+                transaction_index: idx.into(),
+                block_hash: Some(key.hash),
+                block_number: Some(key.num.into()),
+                from: tx.from,
+                to: tx.to,
+                cumulative_gas_used: stored.cumulative_gas_used.into(),
+                gas_used: Some(
+                    stored
+                        .cumulative_gas_used
+                        .saturating_sub(prev_gas_used)
+                        .into(),
+                ),
+                contract_address: match tx.to {
+                    Some(_) => None,
+                    None => Some(get_contract_address(tx.from, tx.nonce)),
+                },
+                transaction_type: tx.transaction_type,
+                effective_gas_price,
+                ..Default::default()
+            };
+            // pre-Byzantium receipts commit to the state root instead of a status
+            match stored.root() {
+                Some(root) => receipt.root = Some(root),
+                None => receipt.status = Some(stored.status.into()),
+            }
+            for (tx_log_idx, log) in tx_logs.into_iter().enumerate() {
+                accrue(&mut receipt.logs_bloom.0, log.address.as_bytes());
+                for topic in &log.topics {
+                    accrue(&mut receipt.logs_bloom.0, topic.as_bytes());
+                }
+                receipt.logs.push(Log {
+                    address: log.address,
+                    topics: log.topics,
+                    data: log.data.into(),
+                    block_hash: Some(key.hash),
+                    block_number: Some(key.num.into()),
+                    transaction_hash: Some(tx.hash),
+                    transaction_index: Some(idx.into()),
+                    log_index: Some(log_index.into()),
+                    transaction_log_index: Some(tx_log_idx.into()),
+                    removed: Some(false),
+                    ..Default::default()
+                });
+                log_index += 1;
+            }
+
+            prev_gas_used = stored.cumulative_gas_used;
+            if verify {
+                encodings.push(trie::receipt_encoding(&receipt));
+            }
+            if let (Some(_), Some(backfill)) = (receipt.root, &self.options().status_backfill) {
+                receipt.status = backfill.status(&tx, &receipt)?;
+            }
+            if !self.options().system_txs.excludes(&tx.from) {
+                receipts.push(receipt);
+            }
+        }
+        if verify {
+            let root = trie::ordered_trie_root(&encodings);
+            anyhow::ensure!(
+                root == header.receipts_root,
+                "receipts root mismatch in block {}: {:?} != {:?}",
+                key.num,
+                root,
+                header.receipts_root
+            );
+        }
+        Ok(Some(receipts))
+    }
+}
+
+/// Adds `input` to a logs bloom, setting the three bits picked by its hash.
+pub(crate) fn accrue(bloom: &mut [u8; 256], input: &[u8]) {
+    let hash = keccak256(input);
+    for i in [0, 2, 4] {
+        let bit = (usize::from(hash[i]) << 8 | usize::from(hash[i + 1])) & 2047;
+        bloom[255 - bit / 8] |= 1 << (bit % 8);
+    }
+}
+
+/// Approximates the bytes held by `receipts`, for the receipt cache.
+fn receipts_weight(receipts: &[TransactionReceipt]) -> usize {
+    receipts
+        .iter()
+        .map(|r| {
+            mem::size_of::<TransactionReceipt>()
+                + r.logs
+                    .iter()
+                    .map(|log| {
+                        mem::size_of::<Log>()
+                            + log.topics.len() * H256::len_bytes()
+                            + log.data.len()
+                    })
+                    .sum::<usize>()
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        builder::Features,
+        cache::CacheConfig,
+        models::{Log as DbLog, Receipt},
+        test::{chain::ChainBuilder, rand::rand_vec, TMP_DIR},
+    };
+    use akula::models::MessageWithSignature;
+    use ethers::types::Address;
+    use rand::thread_rng;
+
+    fn receipt(cumulative_gas_used: u64, status: u64) -> Receipt {
+        Receipt {
+            status,
+            cumulative_gas_used,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_raw_receipts() -> Result<()> {
+        let mut rng = thread_rng();
+        let txs: Vec<MessageWithSignature> = rand_vec(&mut rng, 2);
+        let log = DbLog {
+            address: Address::repeat_byte(0x11),
+            topics: vec![],
+            data: vec![0xab; 4].into(),
+        };
+        let chain = ChainBuilder::new()
+            .block(|b| {
+                b.txs(txs)
+                    .receipt(receipt(21_000, 1), vec![])
+                    .receipt(receipt(42_000, 1), vec![log.clone()])
+            })
+            .block(|b| b)
+            .write(TMP_DIR.clone())?;
+        let db = Client::<mdbx::NoWriteMap>::open_new(chain.path.clone())?;
+
+        let raw = db.get_raw_receipts(1u64)?.unwrap();
+        assert_eq!(raw.len(), 2);
+        assert_eq!(raw[0].receipt.decoded, Ok(receipt(21_000, 1)));
+        assert_eq!(
+            Receipt::encode_list(&[receipt(21_000, 1), receipt(42_000, 1)]),
+            [
+                &[0x82u8][..],
+                raw[0].receipt.raw.as_ref(),
+                raw[1].receipt.raw.as_ref()
+            ]
+            .concat()
+        );
+        assert!(raw[0].logs.is_none());
+        let logs = raw[1].logs.as_ref().unwrap();
+        assert_eq!(logs.raw.to_vec(), DbLog::encode_list(&[log.clone()]));
+        assert_eq!(logs.decoded, Ok(vec![log]));
+        assert_eq!(db.get_raw_receipts(2u64)?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_receipts() -> Result<()> {
+        let mut rng = thread_rng();
+        let txs: Vec<MessageWithSignature> = rand_vec(&mut rng, 4);
+        let log = DbLog {
+            address: Address::repeat_byte(0x11),
+            topics: vec![H256::repeat_byte(0x22)],
+            data: vec![0xab; 4].into(),
+        };
+        let chain = ChainBuilder::new()
+            .block(|b| {
+                b.tx(txs[0].clone())
+                    .receipt(receipt(21_000, 1), vec![log.clone()])
+                    .tx(txs[1].clone())
+                    .receipt(receipt(50_000, 0), vec![])
+                    .tx(txs[2].clone())
+                    .receipt(receipt(90_000, 1), vec![log.clone(), log.clone()])
+            })
+            .block(|b| b)
+            .block(|b| {
+                let pre_byzantium = Receipt {
+                    post_state: vec![0x33; 32],
+                    ..receipt(21_000, 0)
+                };
+                b.tx(txs[3].clone()).receipt(pre_byzantium, vec![])
+            })
+            .write(TMP_DIR.clone())?;
+        let db = Client::<mdbx::NoWriteMap>::open_new(chain.path.clone())?;
+
+        let receipts = match db.get_block_receipts(1u64)? {
+            Either::Right(receipts) => receipts,
+            Either::Left(num) => panic!("no receipts for block {}", num),
+        };
+        assert_eq!(receipts.len(), 3);
+        let gas_used = receipts.iter().map(|r| r.gas_used.unwrap().as_u64());
+        assert_eq!(gas_used.collect::<Vec<_>>(), vec![21_000, 29_000, 40_000]);
+        assert_eq!(receipts[1].status, Some(0.into()));
+        assert_eq!(receipts[2].logs[1].log_index, Some(2.into()));
+        assert_eq!(receipts[2].logs[1].transaction_log_index, Some(1.into()));
+        assert!(receipts[1].logs.is_empty());
+        for (receipt, tx) in receipts.iter().zip(&txs[..3]) {
+            assert_eq!(receipt.transaction_hash, tx.hash());
+            assert_eq!(receipt.from, tx.recover_sender()?);
+        }
+        assert_eq!(receipts[1].root, None);
+        // no receipts stored for the second block
+        assert_eq!(db.get_block_receipts(2u64)?, Either::Left(BlockNum(2)));
+        // by key, only while the block is canonical
+        let key = HeaderKey::new(BlockNum(1), chain.hash(0));
+        assert_eq!(
+            db.get_block_receipts_by_key(key)?,
+            Either::Right(receipts.clone())
+        );
+        let stale = HeaderKey::new(BlockNum(1), H256::repeat_byte(0xee));
+        assert!(db.get_block_receipts_by_key(stale).is_err());
+
+        // the root replaces the status
+        let receipt = match db.get_transaction_receipt(txs[3].hash())? {
+            Either::Right(receipt) => receipt.unwrap(),
+            Either::Left(num) => panic!("no receipts for block {}", num),
+        };
+        assert_eq!(receipt.root, Some(H256::repeat_byte(0x33)));
+        assert_eq!(receipt.status, None);
+        assert_eq!(
+            db.get_transaction_receipt(H256::repeat_byte(0xee))?,
+            Either::Right(None)
+        );
+
+        // served from the cached block receipts
+        let hits = db.cache_metrics().receipts.hits;
+        assert_eq!(
+            db.get_transaction_receipt(txs[1].hash())?,
+            Either::Right(Some(receipts[1].clone()))
+        );
+        assert_eq!(db.cache_metrics().receipts.hits, hits + 1);
+
+        // built from the prefix of the block
+        let db = db.with_cache_config(CacheConfig::disabled());
+        assert_eq!(
+            db.get_transaction_receipt(txs[2].hash())?,
+            Either::Right(Some(receipts[2].clone()))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_receipts() -> Result<()> {
+        let mut rng = thread_rng();
+        let txs: Vec<MessageWithSignature> = rand_vec(&mut rng, 2);
+        let chain = ChainBuilder::new()
+            .block(|b| {
+                b.txs(txs.clone())
+                    .receipt(receipt(21_000, 1), vec![])
+                    .receipt(receipt(42_000, 1), vec![])
+            })
+            .write(TMP_DIR.clone())?;
+        let verifying = Client::<mdbx::NoWriteMap>::builder()
+            .path(chain.path.clone())
+            .features(Features {
+                verify: true,
+                ..Default::default()
+            })
+            .build()?;
+
+        // the header and txs match their commitments
+        let block = verifying.get_block_with_txs(1u64)?.unwrap();
+        assert_eq!(block.transactions.len(), 2);
+        assert!(verifying.get_transaction(txs[1].hash())?.is_some());
+        // but the built chain's receipts root is random
+        assert!(verifying.get_block_receipts(1u64).is_err());
+        assert!(verifying.get_transaction_receipt(txs[0].hash()).is_err());
+
+        let db = Client::<mdbx::NoWriteMap>::open_new(chain.path.clone())?;
+        assert!(matches!(db.get_block_receipts(1u64)?, Either::Right(r) if r.len() == 2));
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_known_receipts_root() -> Result<()> {
+        // block 3 of the dev chain in ethers-core's block tests: one successful
+        // legacy transfer, committed to by the node that mined it
+        let raw = hex::decode(
+            "f865028504a817c80083015f9094dca8ce283150ab773bcbeb8d38289bdb5661de1e808025a019f2694eb9\
+             113656dbea0b925e2e7ceb43df83e601c4116aee9c0dd99130be88a073e5764b324a4f7679d890a198ba65\
+             8ba1c8cd36983ff9797e10b1b89dbb448e",
+        )?;
+        let receipts_root: H256 =
+            "0x056b23fbba480696b65fe5a59b8f2148a1299103c4f57df839233af2cf4ca2d2".parse()?;
+        let chain = ChainBuilder::new()
+            .block(|b| {
+                b.raw_tx(raw)
+                    .receipt(receipt(21_000, 1), vec![])
+                    .receipts_root(receipts_root)
+            })
+            .write(TMP_DIR.clone())?;
+        let db = Client::<mdbx::NoWriteMap>::builder()
+            .path(chain.path.clone())
+            .features(Features {
+                verify: true,
+                ..Default::default()
+            })
+            .build()?;
+
+        let receipts = match db.get_block_receipts(1u64)? {
+            Either::Right(receipts) => receipts,
+            Either::Left(num) => panic!("no receipts for block {}", num),
+        };
+        assert_eq!(receipts.len(), 1);
+        assert_eq!(
+            receipts[0].transaction_hash,
+            "0xc3c5f700243de37ae986082fd2af88d2a7c2752a0c0f7b9d6ac47c729d45e067".parse::<H256>()?
+        );
+        assert_eq!(
+            receipts[0].from,
+            "0xfdcedc3bfca10ecb0890337fbdd1977aba84807a".parse::<Address>()?
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,205 @@
+//! A read-only REST facade over a `Client`, for consumers which don't speak
+//! JSON-RPC. Every route is a `GET` returning the JSON serialization of the
+//! equivalent `Client` method:
+//!
+//! | Route             | Method            |
+//! |-------------------|-------------------|
+//! | `/block/{id}`     | `get_block`       |
+//! | `/tx/{hash}`      | `get_transaction` |
+//! | `/account/{addr}` | `address_summary` |
+//! | `/features`       | `feature_matrix`  |
+//!
+//! A block `id` is a block hash, a decimal or `0x` prefixed number, `latest`
+//! or `earliest`. Unknown blocks and transactions are `404`s, malformed ids are
+//! `400`s and queries rejected by admission control are `503`s. Errors are
+//! returned as `{"error": "..."}`. Responses are formatted by the client's
+//! `OutputConfig` and labelled by its `AddressLabeler`, if any.
+
+use anyhow::Result;
+use ethers::types::{Address, BlockId, BlockNumber, H256, U64};
+use hyper::{
+    header::CONTENT_TYPE,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use mdbx::EnvironmentKind;
+use serde::Serialize;
+use std::{convert::Infallible, net::SocketAddr, str::FromStr};
+
+use crate::{
+    admission::Rejected,
+    builder::ClientOptions,
+    client::{Client, NotFound},
+};
+
+/// Serves the REST routes for `client` on `addr` until the server fails.
+pub async fn serve<E: EnvironmentKind>(client: Client<E>, addr: SocketAddr) -> Result<()> {
+    let make_svc = make_service_fn(move |_conn| {
+        let client = client.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let client = client.clone();
+                async move { Ok::<_, Infallible>(handle(client, req).await) }
+            }))
+        }
+    });
+    Server::try_bind(&addr)?.serve(make_svc).await?;
+    Ok(())
+}
+
+async fn handle<E: EnvironmentKind>(client: Client<E>, req: Request<Body>) -> Response<Body> {
+    if req.method() != Method::GET {
+        return error(StatusCode::METHOD_NOT_ALLOWED, "only GET is supported");
+    }
+    let path = req.uri().path().to_string();
+    // reads block on mdbx, so they are kept off the async workers
+    match tokio::task::spawn_blocking(move || route(&client, &path)).await {
+        Ok(res) => res,
+        Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
+
+/// Returns the response to a `GET` of `path`.
+pub fn route<E: EnvironmentKind>(client: &Client<E>, path: &str) -> Response<Body> {
+    let out = client.options();
+    let segments: Vec<_> = path.trim_matches('/').split('/').collect();
+    let res = match segments.as_slice() {
+        ["block", id] => match parse_block_id(id) {
+            Some(id) => client
+                .get_block(id)
+                .and_then(|b| json_or_404(out, b, "block")),
+            None => return error(StatusCode::BAD_REQUEST, "invalid block id"),
+        },
+        ["tx", hash] => match parse_hex::<H256>(hash) {
+            Some(hash) => client
+                .get_transaction(hash)
+                .and_then(|tx| json_or_404(out, tx, "transaction")),
+            None => return error(StatusCode::BAD_REQUEST, "invalid transaction hash"),
+        },
+        ["account", addr] => match parse_hex::<Address>(addr) {
+            Some(addr) => client
+                .address_summary(addr)
+                .and_then(|s| json(out, StatusCode::OK, &s)),
+            None => return error(StatusCode::BAD_REQUEST, "invalid address"),
+        },
+        ["features"] => client
+            .feature_matrix()
+            .and_then(|m| json(out, StatusCode::OK, &m)),
+        _ => return error(StatusCode::NOT_FOUND, "no such route"),
+    };
+    res.unwrap_or_else(|e| {
+        let status = if e.is::<NotFound>() {
+            StatusCode::NOT_FOUND
+        } else if e.is::<Rejected>() {
+            StatusCode::SERVICE_UNAVAILABLE
+        } else {
+            StatusCode::INTERNAL_SERVER_ERROR
+        };
+        error(status, &e.to_string())
+    })
+}
+
+fn parse_block_id(id: &str) -> Option<BlockId> {
+    match id {
+        "latest" => Some(BlockNumber::Latest.into()),
+        "earliest" => Some(BlockNumber::Earliest.into()),
+        _ => match id.strip_prefix("0x") {
+            Some(hex) if hex.len() == 64 => parse_hex::<H256>(id).map(Into::into),
+            Some(hex) => U64::from_str_radix(hex, 16).ok().map(Into::into),
+            None => id.parse::<u64>().ok().map(Into::into),
+        },
+    }
+}
+
+/// Parses a `0x` prefixed hash or address.
+fn parse_hex<T: FromStr>(s: &str) -> Option<T> {
+    s.strip_prefix("0x")?.parse().ok()
+}
+
+fn json<T: Serialize>(
+    out: &ClientOptions,
+    status: StatusCode,
+    value: &T,
+) -> Result<Response<Body>> {
+    let mut value = serde_json::to_value(value)?;
+    out.format_output(&mut value);
+    Ok(Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(serde_json::to_vec(&value)?.into())?)
+}
+
+fn json_or_404<T: Serialize>(
+    out: &ClientOptions,
+    value: Option<T>,
+    what: &str,
+) -> Result<Response<Body>> {
+    match value {
+        Some(value) => json(out, StatusCode::OK, &value),
+        None => Ok(error(StatusCode::NOT_FOUND, &format!("{} not found", what))),
+    }
+}
+
+fn error(status: StatusCode, msg: &str) -> Response<Body> {
+    let mut res = Response::new(serde_json::json!({ "error": msg }).to_string().into());
+    *res.status_mut() = status;
+    res.headers_mut()
+        .insert(CONTENT_TYPE, "application/json".parse().unwrap());
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::{chain::ChainBuilder, TMP_DIR};
+
+    #[test]
+    fn test_parse_block_id() {
+        let hash = H256::repeat_byte(0xab);
+        assert_eq!(
+            parse_block_id(&format!("{:?}", hash)),
+            Some(BlockId::Hash(hash))
+        );
+        assert_eq!(parse_block_id("0x10"), Some(BlockId::from(16u64)));
+        assert_eq!(parse_block_id("16"), Some(BlockId::from(16u64)));
+        assert_eq!(
+            parse_block_id("latest"),
+            Some(BlockId::from(BlockNumber::Latest))
+        );
+        assert_eq!(parse_block_id("0xzz"), None);
+        assert_eq!(parse_block_id("pending"), None);
+        assert_eq!(parse_hex::<Address>("ab"), None);
+    }
+
+    #[test]
+    fn test_route_status() -> Result<()> {
+        let chain = ChainBuilder::new()
+            .block(|b| b)
+            .block(|b| b)
+            .write(TMP_DIR.clone())?;
+        let db = Client::<mdbx::NoWriteMap>::open_new(chain.path.clone())?;
+        let status = |path: &str| route(&db, path).status();
+
+        assert_eq!(status("/block/1"), StatusCode::OK);
+        assert_eq!(
+            status(&format!("/block/{:?}", chain.hash(1))),
+            StatusCode::OK
+        );
+        assert_eq!(status("/block/latest"), StatusCode::OK);
+        // unknown blocks and transactions
+        assert_eq!(status("/block/100"), StatusCode::NOT_FOUND);
+        assert_eq!(
+            status(&format!("/block/{:?}", H256::repeat_byte(0xab))),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            status(&format!("/tx/{:?}", H256::repeat_byte(0xab))),
+            StatusCode::NOT_FOUND
+        );
+        // malformed ids and unknown routes
+        assert_eq!(status("/block/0xzz"), StatusCode::BAD_REQUEST);
+        assert_eq!(status("/tx/ab"), StatusCode::BAD_REQUEST);
+        assert_eq!(status("/blocks"), StatusCode::NOT_FOUND);
+        Ok(())
+    }
+}
@@ -0,0 +1,71 @@
+//! Logging of reads slower than the client's `slow_read_threshold`.
+
+use std::time::{Duration, Instant};
+
+/// The `tracing` target of slow read events.
+pub const SLOW_READ_TARGET: &str = "ethers_db::slow_read";
+
+/// A guard which logs the read it spans on drop, if the read took at least
+/// `threshold`. The context is only formatted for slow reads.
+pub(crate) struct SlowRead<F: FnOnce() -> String> {
+    method: &'static str,
+    tables: &'static [&'static str],
+    context: Option<F>,
+    threshold: Option<Duration>,
+    start: Instant,
+}
+
+impl<F: FnOnce() -> String> SlowRead<F> {
+    pub(crate) fn new(
+        threshold: Option<Duration>,
+        method: &'static str,
+        tables: &'static [&'static str],
+        context: F,
+    ) -> Self {
+        Self {
+            method,
+            tables,
+            context: Some(context),
+            threshold,
+            start: Instant::now(),
+        }
+    }
+
+    fn is_slow(&self) -> Option<Duration> {
+        let elapsed = self.start.elapsed();
+        self.threshold.filter(|t| elapsed >= *t).map(|_| elapsed)
+    }
+}
+
+impl<F: FnOnce() -> String> Drop for SlowRead<F> {
+    fn drop(&mut self) {
+        let elapsed = match self.is_slow() {
+            Some(elapsed) => elapsed,
+            None => return,
+        };
+        let context = self.context.take().map(|f| f()).unwrap_or_default();
+        tracing::warn!(
+            target: SLOW_READ_TARGET,
+            method = self.method,
+            context = %context,
+            tables = ?self.tables,
+            elapsed_ms = elapsed.as_millis() as u64,
+            "slow read"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_slow() {
+        let read = SlowRead::new(None, "m", &[], String::new);
+        assert_eq!(read.is_slow(), None);
+        let read = SlowRead::new(Some(Duration::ZERO), "m", &[], String::new);
+        assert!(read.is_slow().is_some());
+        let read = SlowRead::new(Some(Duration::from_secs(60)), "m", &[], String::new);
+        assert_eq!(read.is_slow(), None);
+    }
+}
@@ -0,0 +1,1773 @@
+//! Readers for Erigon's frozen snapshot files.
+//!
+//! Blocks older than the snapshot boundary live in `<datadir>/snapshots`
+//! segments rather than the db, and their transactions are no longer indexed by
+//! the TxLookup table. Each `*-transactions-to-block.idx` file is a recsplit
+//! minimal perfect hash index mapping transaction hashes to block numbers,
+//! ported here from erigon-lib/recsplit.
+//!
+//! The headers, bodies and transactions themselves are words in compressed
+//! `.seg` files (erigon-lib/compress), each with an enumerated recsplit index
+//! beside it mapping the block or transaction number to the word's offset.
+//! Files are memory mapped rather than read, as segments run to gigabytes.
+//!
+//! Erigon's downloader writes a `<segment>.torrent` file for every segment it
+//! means to fetch, before the data arrives. `SnapshotInventory` compares them
+//! against the segments present.
+
+use akula::models::{BodyForStorage, MessageWithSignature};
+use anyhow::{format_err, Result};
+use ethers::{
+    types::{Address, H256},
+    utils::keccak256,
+};
+use memmap2::Mmap;
+use sha1::{Digest, Sha1};
+use std::{
+    collections::BTreeMap,
+    fs,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+const TX_TO_BLOCK_KIND: &str = "transactions-to-block";
+const HEADERS_KIND: &str = "headers";
+const BODIES_KIND: &str = "bodies";
+const TRANSACTIONS_KIND: &str = "transactions";
+const SEGMENT_EXT: &str = "seg";
+const INDEX_EXT: &str = "idx";
+const TORRENT_EXT: &str = "torrent";
+// Deeper bencoded values than this are taken for a malformed torrent
+const MAX_BENCODE_DEPTH: usize = 64;
+
+// erigon-lib/recsplit/eliasfano16 constants, shared with eliasfano32
+const LOG2Q: u64 = 8;
+const Q: u64 = 1 << LOG2Q;
+const Q_MASK: u64 = Q - 1;
+const SUPER_Q: u64 = 1 << 14;
+const Q_PER_SUPER_Q: u64 = SUPER_Q / Q;
+const SUPER_Q_SIZE: u64 = 1 + Q_PER_SUPER_Q / 2;
+
+// Optimal bijection code lengths for leaves of size 0..=24
+const BIJ_MEMO: [u32; 25] = [
+    0, 0, 0, 1, 3, 4, 5, 7, 8, 10, 11, 12, 14, 15, 16, 18, 19, 21, 22, 23, 25, 26, 28, 29, 30,
+];
+
+/// The transaction hash indices found in a snapshots directory, and the
+/// segments holding the frozen blocks they point into.
+#[derive(Debug, Default)]
+pub struct SnapshotTxIndex {
+    dir: PathBuf,
+    indices: Vec<(BlockRange, RecSplitIndex)>,
+    /// Keyed by kind and first block.
+    segments: BTreeMap<(&'static str, u64), (BlockRange, IndexedSegment)>,
+}
+
+/// The half-open range of blocks covered by a snapshot file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockRange {
+    pub from: u64,
+    pub to: u64,
+}
+
+impl SnapshotTxIndex {
+    /// Loads every `*-transactions-to-block.idx` file in `dir`, and every
+    /// headers, bodies and transactions segment with an index beside it.
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        let mut indices = vec![];
+        let mut segments = BTreeMap::new();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if let Some(range) = tx_to_block_range(&path) {
+                indices.push((range, RecSplitIndex::open(&path)?));
+            } else if let Some((range, kind)) = frozen_segment(&path) {
+                if !path.with_extension(INDEX_EXT).is_file() {
+                    // Erigon builds the index after downloading the segment
+                    tracing::warn!(segment = %path.display(), "snapshot segment has no index");
+                    continue;
+                }
+                segments.insert((kind, range.from), (range, IndexedSegment::open(&path)?));
+            }
+        }
+        indices.sort_by_key(|(range, _)| range.from);
+        Ok(Self {
+            dir,
+            indices,
+            segments,
+        })
+    }
+
+    /// The snapshots directory the indices were loaded from.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+
+    /// Returns the highest block covered by the snapshots, if any.
+    pub fn max_block(&self) -> Option<u64> {
+        self.indices
+            .last()
+            .map(|(range, _)| range.to.saturating_sub(1))
+    }
+
+    /// Returns the ranges of blocks covered by the snapshots, merging adjacent files.
+    pub fn ranges(&self) -> Vec<BlockRange> {
+        merge_ranges(self.indices.iter().map(|(range, _)| *range))
+    }
+
+    /// Returns candidate block numbers for the transaction with hash `hash`.
+    /// A perfect hash maps keys outside its key set to arbitrary values, so the
+    /// caller must verify the candidates against the block's transactions.
+    pub fn candidate_blocks(&self, hash: &[u8]) -> Result<Vec<u64>> {
+        let mut out = vec![];
+        for (range, idx) in &self.indices {
+            match idx.lookup(hash)? {
+                Some(num) if range.from <= num && num < range.to => out.push(num),
+                _ => {}
+            }
+        }
+        Ok(out)
+    }
+
+    /// Returns the hash of the frozen block `num`, or `None` if no headers
+    /// segment covers it.
+    pub fn read_block_hash(&self, num: u64) -> Result<Option<H256>> {
+        let word = match self.word(HEADERS_KIND, num, num)? {
+            Some(word) => word,
+            None => return Ok(None),
+        };
+        // [first byte of the hash][rlp header]
+        anyhow::ensure!(!word.is_empty(), "empty header word for block {}", num);
+        Ok(Some(keccak256(&word[1..]).into()))
+    }
+
+    /// Returns the transactions of the frozen block `num` with their senders,
+    /// or `None` if no bodies or transactions segment covers it.
+    pub fn read_block_transactions(
+        &self,
+        num: u64,
+    ) -> Result<Option<Vec<(MessageWithSignature, Address)>>> {
+        let body = match self.word(BODIES_KIND, num, num)? {
+            Some(word) => <BodyForStorage as fastrlp::Decodable>::decode(&mut &*word)
+                .map_err(|e| format_err!("BodyForStorage decode error: {}", e))?,
+            None => return Ok(None),
+        };
+        if self.segment(TRANSACTIONS_KIND, num).is_none() {
+            return Ok(None);
+        }
+        // Skip the system txs at the beginning and end of the block, which
+        // are written as empty words
+        let first = (*body.base_tx_id)
+            .checked_add(1)
+            .ok_or_else(|| format_err!("bad base tx id in block {}", num))?;
+        let amount = body
+            .tx_amount
+            .checked_sub(2)
+            .ok_or_else(|| format_err!("block body {} has too few txs", num))?;
+        let mut txs = vec![];
+        for id in first..first.saturating_add(amount) {
+            let word = self
+                .word(TRANSACTIONS_KIND, num, id)?
+                .ok_or_else(|| format_err!("tx {} of block {} not in its segment", id, num))?;
+            txs.push(decode_frozen_tx(&word)?);
+        }
+        Ok(Some(txs))
+    }
+
+    fn segment(&self, kind: &'static str, num: u64) -> Option<&IndexedSegment> {
+        let ((k, _), (range, seg)) = self.segments.range(..=(kind, num)).next_back()?;
+        (*k == kind && range.from <= num && num < range.to).then_some(seg)
+    }
+
+    /// Returns the word `id` of the segment of `kind` covering block `num`.
+    fn word(&self, kind: &'static str, num: u64, id: u64) -> Result<Option<Vec<u8>>> {
+        match self.segment(kind, num) {
+            Some(seg) => seg.word(id),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Decodes a transactions segment word:
+/// `[first byte of the hash][sender][rlp tx]`.
+fn decode_frozen_tx(word: &[u8]) -> Result<(MessageWithSignature, Address)> {
+    anyhow::ensure!(
+        word.len() > 21,
+        "short transaction word: {} bytes",
+        word.len()
+    );
+    let sender = Address::from_slice(&word[1..21]);
+    let msg = <MessageWithSignature as fastrlp::Decodable>::decode(&mut &word[21..])
+        .map_err(|e| format_err!("frozen tx decode error: {}", e))?;
+    Ok((msg, sender))
+}
+
+/// A `.seg` file and the enumerated index of its word offsets.
+#[derive(Debug)]
+struct IndexedSegment {
+    seg: Segment,
+    idx: RecSplitIndex,
+}
+
+impl IndexedSegment {
+    fn open(path: &Path) -> Result<Self> {
+        Ok(Self {
+            seg: Segment::open(path)?,
+            idx: RecSplitIndex::open(path.with_extension(INDEX_EXT))?,
+        })
+    }
+
+    /// Returns the word numbered `id`, a block number for headers and bodies
+    /// and a tx number for transactions, or `None` if it isn't in the segment.
+    fn word(&self, id: u64) -> Result<Option<Vec<u8>>> {
+        let ordinal = match id.checked_sub(self.idx.base_data_id()) {
+            Some(i) if i < self.idx.key_count() => i,
+            _ => return Ok(None),
+        };
+        let offset = self.idx.ordinal_lookup(ordinal)?;
+        self.seg.word_at(offset).map(Some)
+    }
+}
+
+/// A snapshot segment with a torrent or present in the snapshots directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotSegment {
+    /// The file name, e.g. `v1-000000-000500-bodies.seg`.
+    pub name: String,
+    /// The kind of data in the segment: `headers`, `bodies` or `transactions`.
+    pub kind: String,
+    pub range: BlockRange,
+    /// The info hash of the segment's torrent, hex encoded.
+    pub hash: Option<String>,
+    /// Whether the segment has a torrent, i.e. Erigon expects it.
+    pub listed: bool,
+    /// Whether the file is in the snapshots directory.
+    pub on_disk: bool,
+}
+
+/// The snapshot segments Erigon expects and those it has, as returned by
+/// `Client::snapshot_inventory`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SnapshotInventory {
+    /// The segments in order of their first block, then kind.
+    pub segments: Vec<SnapshotSegment>,
+}
+
+impl SnapshotInventory {
+    /// Lists the `.seg` files in `dir` and the segments with a `.seg.torrent`
+    /// file. Torrents which can't be read are logged and skipped.
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let mut segments = BTreeMap::new();
+        for dirent in fs::read_dir(dir)? {
+            let path = dirent?.path();
+            let name = match path.file_name().and_then(|name| name.to_str()) {
+                Some(name) => name,
+                None => continue,
+            };
+            match path.extension().and_then(|ext| ext.to_str()) {
+                Some(TORRENT_EXT) => {
+                    let seg_name = &name[..name.len() - TORRENT_EXT.len() - 1];
+                    let hash = match fs::read(&path)
+                        .map_err(From::from)
+                        .and_then(|data| torrent_info_hash(&data))
+                    {
+                        Ok(hash) => hash,
+                        Err(e) => {
+                            tracing::warn!(torrent = %path.display(), error = %e, "skipping unreadable torrent");
+                            continue;
+                        }
+                    };
+                    if let Some(seg) = segment_entry(&mut segments, seg_name) {
+                        seg.hash = Some(hash);
+                        seg.listed = true;
+                    }
+                }
+                Some(SEGMENT_EXT) => {
+                    if let Some(seg) = segment_entry(&mut segments, name) {
+                        seg.on_disk = true;
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(Self {
+            segments: segments.into_values().collect(),
+        })
+    }
+
+    /// Returns the segments with a torrent which aren't on disk.
+    pub fn missing(&self) -> impl Iterator<Item = &SnapshotSegment> {
+        self.segments
+            .iter()
+            .filter(|seg| seg.listed && !seg.on_disk)
+    }
+
+    /// Returns the ranges of blocks covered by the segments of `kind` on disk,
+    /// merging adjacent files.
+    pub fn ranges(&self, kind: &str) -> Vec<BlockRange> {
+        merge_ranges(
+            self.segments
+                .iter()
+                .filter(|seg| seg.on_disk && seg.kind == kind)
+                .map(|seg| seg.range),
+        )
+    }
+
+    /// Returns the ranges of blocks covered by segments missing from disk,
+    /// merging adjacent files.
+    pub fn missing_ranges(&self) -> Vec<BlockRange> {
+        let mut ranges = self.missing().map(|seg| seg.range).collect::<Vec<_>>();
+        ranges.sort_by_key(|range| range.from);
+        merge_ranges(ranges)
+    }
+}
+
+type SegmentKey = (u64, String, String);
+
+/// Returns the entry of the segment file `name`, or `None` if it isn't the
+/// name of a segment.
+fn segment_entry<'a>(
+    segments: &'a mut BTreeMap<SegmentKey, SnapshotSegment>,
+    name: &str,
+) -> Option<&'a mut SnapshotSegment> {
+    let (range, kind, ext) = parse_segment_name(name)?;
+    if ext != SEGMENT_EXT {
+        return None;
+    }
+    let key = (range.from, kind.to_string(), name.to_string());
+    Some(segments.entry(key).or_insert_with(|| SnapshotSegment {
+        name: name.to_string(),
+        kind: kind.to_string(),
+        range,
+        hash: None,
+        listed: false,
+        on_disk: false,
+    }))
+}
+
+/// Merges overlapping and adjacent ranges, which must be sorted by `from`.
+fn merge_ranges<I: IntoIterator<Item = BlockRange>>(ranges: I) -> Vec<BlockRange> {
+    let mut out: Vec<BlockRange> = vec![];
+    for range in ranges {
+        match out.last_mut() {
+            Some(last) if range.from <= last.to => last.to = last.to.max(range.to),
+            _ => out.push(range),
+        }
+    }
+    out
+}
+
+/// Returns the info hash of a `.torrent` file: the SHA-1 of the bencoded
+/// value of its `info` key.
+fn torrent_info_hash(data: &[u8]) -> Result<String> {
+    anyhow::ensure!(data.first() == Some(&b'd'), "torrent is not a dictionary");
+    let mut pos = 1;
+    while data.get(pos) != Some(&b'e') {
+        let key_end = bencode_end(data, pos, 0)?;
+        let value_end = bencode_end(data, key_end, 0)?;
+        if &data[pos..key_end] == b"4:info" {
+            return Ok(hex::encode(Sha1::digest(&data[key_end..value_end])));
+        }
+        pos = value_end;
+    }
+    Err(format_err!("torrent has no info dictionary"))
+}
+
+/// Returns the end of the bencoded value at `pos`.
+fn bencode_end(data: &[u8], pos: usize, depth: usize) -> Result<usize> {
+    anyhow::ensure!(depth < MAX_BENCODE_DEPTH, "bencoding nested too deep");
+    let eof = || format_err!("truncated bencoding at byte {}", pos);
+    match *data.get(pos).ok_or_else(eof)? {
+        b'i' => {
+            let len = data[pos..]
+                .iter()
+                .position(|b| *b == b'e')
+                .ok_or_else(eof)?;
+            Ok(pos + len + 1)
+        }
+        b'l' | b'd' => {
+            let mut pos = pos + 1;
+            while *data.get(pos).ok_or_else(eof)? != b'e' {
+                pos = bencode_end(data, pos, depth + 1)?;
+            }
+            Ok(pos + 1)
+        }
+        b'0'..=b'9' => {
+            let colon = data[pos..]
+                .iter()
+                .position(|b| *b == b':')
+                .ok_or_else(eof)?;
+            let len: usize = std::str::from_utf8(&data[pos..pos + colon])?.parse()?;
+            let end = (pos + colon + 1)
+                .checked_add(len)
+                .filter(|end| *end <= data.len())
+                .ok_or_else(eof)?;
+            Ok(end)
+        }
+        b => Err(format_err!("bad bencoding byte {:#x} at byte {}", b, pos)),
+    }
+}
+
+/// Parses the block range, kind and extension from names like
+/// `v1-000000-000500-bodies.seg`, where the bounds are in thousands of blocks.
+fn parse_segment_name(name: &str) -> Option<(BlockRange, &str, &str)> {
+    let (stem, ext) = name.rsplit_once('.')?;
+    let mut parts = stem.splitn(4, '-');
+    let _version = parts.next()?;
+    let from: u64 = parts.next()?.parse().ok()?;
+    let to: u64 = parts.next()?.parse().ok()?;
+    let kind = parts.next()?;
+    let range = BlockRange {
+        from: from * 1000,
+        to: to * 1000,
+    };
+    Some((range, kind, ext))
+}
+
+/// Parses the block range from names like `v1-000000-000500-transactions-to-block.idx`.
+fn tx_to_block_range(path: &Path) -> Option<BlockRange> {
+    match parse_segment_name(path.file_name()?.to_str()?)? {
+        (range, TX_TO_BLOCK_KIND, "idx") => Some(range),
+        _ => None,
+    }
+}
+
+/// Parses the block range and kind from names like `v1-000000-000500-bodies.seg`,
+/// for the segments frozen blocks are read from.
+fn frozen_segment(path: &Path) -> Option<(BlockRange, &'static str)> {
+    match parse_segment_name(path.file_name()?.to_str()?)? {
+        (range, kind, SEGMENT_EXT) => [HEADERS_KIND, BODIES_KIND, TRANSACTIONS_KIND]
+            .into_iter()
+            .find(|k| *k == kind)
+            .map(|kind| (range, kind)),
+        _ => None,
+    }
+}
+
+/// Maps the file at `path` read only.
+fn map_file(path: &Path) -> Result<Mmap> {
+    let file = fs::File::open(path)?;
+    // Safety: Erigon never modifies a snapshot file once it is written, it
+    // only deletes it after merging, which leaves existing maps valid
+    unsafe { Mmap::map(&file) }.map_err(|e| format_err!("{}: {}", path.display(), e))
+}
+
+/// A recsplit index file. See erigon-lib/recsplit/index.go.
+#[derive(Debug)]
+pub struct RecSplitIndex {
+    path: PathBuf,
+    data: Mmap,
+    base_data_id: u64,
+    key_count: u64,
+    bytes_per_rec: usize,
+    rec_mask: u64,
+    bucket_count: u64,
+    leaf_size: u16,
+    primary_aggr_bound: u16,
+    secondary_aggr_bound: u16,
+    salt: u32,
+    start_seed: Vec<u64>,
+    /// The word offsets of enumerated indices, by ordinal.
+    offsets: Option<EliasFano32>,
+    golomb_rice: Vec<u32>,
+    gr_data: Words,
+    ef: DoubleEliasFano,
+}
+
+impl RecSplitIndex {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let data = map_file(&path)?;
+        Self::parse(path, data)
+    }
+
+    fn parse(path: PathBuf, data: Mmap) -> Result<Self> {
+        let mut r = ByteReader::new(&data);
+        let base_data_id = r.u64()?;
+        let key_count = r.u64()?;
+        let bytes_per_rec = r.u8()? as usize;
+        if bytes_per_rec == 0 || bytes_per_rec > 8 {
+            return Err(format_err!("bad bytes per record: {}", bytes_per_rec));
+        }
+        let rec_mask = if bytes_per_rec == 8 {
+            u64::MAX
+        } else {
+            (1u64 << (8 * bytes_per_rec)) - 1
+        };
+        let records_len = usize::try_from(key_count)?
+            .checked_mul(bytes_per_rec)
+            .ok_or_else(|| format_err!("bad key count: {}", key_count))?;
+        r.skip(records_len)?;
+
+        let bucket_count = r.u64()?;
+        let _bucket_size = r.u16()?;
+        let leaf_size = r.u16()?;
+        if leaf_size == 0 || leaf_size as usize >= BIJ_MEMO.len() {
+            return Err(format_err!("unsupported leaf size: {}", leaf_size));
+        }
+        let primary_aggr_bound =
+            leaf_size * std::cmp::max(2, (0.35 * leaf_size as f64 + 0.5).ceil() as u16);
+        let secondary_aggr_bound = if leaf_size < 7 {
+            primary_aggr_bound * 2
+        } else {
+            primary_aggr_bound * (0.21 * leaf_size as f64 + 0.9).ceil() as u16
+        };
+
+        let salt = r.u32()?;
+        let start_seed_len = r.u8()? as usize;
+        let start_seed = (0..start_seed_len)
+            .map(|_| r.u64())
+            .collect::<Result<Vec<_>>>()?;
+
+        let enums = r.u8()? != 0;
+        let offsets = if enums && key_count > 0 {
+            Some(EliasFano32::read(&mut r)?)
+        } else {
+            None
+        };
+
+        // written as a u16 padded to 4 bytes
+        let golomb_param_size = r.u16()?;
+        r.skip(2)?;
+        let mut golomb_rice = vec![0u32; golomb_param_size as usize];
+        for i in 0..golomb_param_size {
+            let m = i as usize;
+            if i == 0 {
+                golomb_rice[m] = (BIJ_MEMO[m] << 27) | BIJ_MEMO[m];
+            } else if i <= leaf_size {
+                golomb_rice[m] = (BIJ_MEMO[m] << 27) | (1 << 16) | BIJ_MEMO[m];
+            } else {
+                compute_golomb_rice(
+                    i,
+                    &mut golomb_rice,
+                    leaf_size,
+                    primary_aggr_bound,
+                    secondary_aggr_bound,
+                )?;
+            }
+        }
+
+        let gr_len = usize::try_from(r.u64()?)?;
+        let gr_data = r.words_le(gr_len)?;
+        let ef = DoubleEliasFano::read(&mut r)?;
+
+        Ok(Self {
+            path,
+            base_data_id,
+            key_count,
+            bytes_per_rec,
+            rec_mask,
+            bucket_count,
+            leaf_size,
+            primary_aggr_bound,
+            secondary_aggr_bound,
+            salt,
+            start_seed,
+            offsets,
+            golomb_rice,
+            gr_data,
+            ef,
+            data,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn key_count(&self) -> u64 {
+        self.key_count
+    }
+
+    pub fn base_data_id(&self) -> u64 {
+        self.base_data_id
+    }
+
+    /// Returns the value stored for `key`. Keys that were not in the indexed set
+    /// map to an arbitrary value. Returns `None` if the index is empty, and an
+    /// error if it is malformed.
+    pub fn lookup(&self, key: &[u8]) -> Result<Option<u64>> {
+        if self.key_count == 0 {
+            return Ok(None);
+        }
+        let (bucket_hash, fingerprint) = murmur3_x64_128(key, self.salt);
+        self.lookup_hash(bucket_hash, fingerprint)
+            .map(Some)
+            .ok_or_else(|| format_err!("{}: malformed recsplit index", self.path.display()))
+    }
+
+    /// Returns the value at position `i` of an enumerated index, the offset
+    /// of the `i`th word of its segment.
+    pub fn ordinal_lookup(&self, i: u64) -> Result<u64> {
+        let offsets = self
+            .offsets
+            .as_ref()
+            .ok_or_else(|| format_err!("{}: index is not enumerated", self.path.display()))?;
+        offsets.get(&self.data, i).ok_or_else(|| {
+            format_err!(
+                "{}: no ordinal {} in malformed index",
+                self.path.display(),
+                i
+            )
+        })
+    }
+
+    fn lookup_hash(&self, bucket_hash: u64, fingerprint: u64) -> Option<u64> {
+        if self.key_count == 1 {
+            return self.record(0);
+        }
+        let bucket = remap(bucket_hash, self.bucket_count);
+        let (mut cum_keys, cum_keys_next, bit_pos) = self.ef.get3(&self.data, bucket)?;
+        let mut m = u16::try_from(cum_keys_next.checked_sub(cum_keys)?).ok()?;
+        let mut gr = GolombRiceReader::new(&self.data, self.gr_data);
+        gr.read_reset(bit_pos as usize, self.skip_bits(m)?)?;
+
+        let mut level = 0;
+        while m > self.secondary_aggr_bound {
+            let d = gr.read_next(self.golomb_param(m)?)?;
+            let hmod = remap16(
+                remix(fingerprint.wrapping_add(self.seed(level)?).wrapping_add(d)),
+                m,
+            );
+            let split = (m / 2 + m % 2)
+                .checked_add(self.secondary_aggr_bound - 1)?
+                .checked_div(self.secondary_aggr_bound)?
+                .checked_mul(self.secondary_aggr_bound)?;
+            if hmod < split {
+                m = split;
+            } else {
+                gr.skip_subtree(self.skip_nodes(split)?, self.skip_bits(split)?)?;
+                m = m.checked_sub(split)?;
+                cum_keys += split as u64;
+            }
+            level += 1;
+        }
+        if m > self.primary_aggr_bound {
+            let d = gr.read_next(self.golomb_param(m)?)?;
+            let hmod = remap16(
+                remix(fingerprint.wrapping_add(self.seed(level)?).wrapping_add(d)),
+                m,
+            );
+            let part = hmod / self.primary_aggr_bound;
+            m = std::cmp::min(
+                self.primary_aggr_bound,
+                m.checked_sub(part * self.primary_aggr_bound)?,
+            );
+            cum_keys += (self.primary_aggr_bound * part) as u64;
+            if part != 0 {
+                let bound = self.primary_aggr_bound;
+                gr.skip_subtree(
+                    self.skip_nodes(bound)? * part as usize,
+                    self.skip_bits(bound)? * part as usize,
+                )?;
+            }
+            level += 1;
+        }
+        if m > self.leaf_size {
+            let d = gr.read_next(self.golomb_param(m)?)?;
+            let hmod = remap16(
+                remix(fingerprint.wrapping_add(self.seed(level)?).wrapping_add(d)),
+                m,
+            );
+            let part = hmod / self.leaf_size;
+            m = std::cmp::min(self.leaf_size, m.checked_sub(part * self.leaf_size)?);
+            cum_keys += (self.leaf_size * part) as u64;
+            if part != 0 {
+                gr.skip_subtree(
+                    part as usize,
+                    self.skip_bits(self.leaf_size)? * part as usize,
+                )?;
+            }
+            level += 1;
+        }
+        let b = gr.read_next(self.golomb_param(m)?)?;
+        let rec = cum_keys.checked_add(remap16(
+            remix(fingerprint.wrapping_add(self.seed(level)?).wrapping_add(b)),
+            m,
+        ) as u64)?;
+        self.record(rec)
+    }
+
+    fn record(&self, rec: u64) -> Option<u64> {
+        if rec >= self.key_count {
+            return None;
+        }
+        // records are right-aligned in the 8 bytes ending at the end of the record
+        let pos = usize::try_from(rec)
+            .ok()?
+            .checked_add(1)?
+            .checked_mul(self.bytes_per_rec)?
+            .checked_add(1 + 8)?;
+        let buf: [u8; 8] = self.data.get(pos..pos.checked_add(8)?)?.try_into().ok()?;
+        Some(u64::from_be_bytes(buf) & self.rec_mask)
+    }
+
+    fn seed(&self, level: usize) -> Option<u64> {
+        self.start_seed.get(level).copied()
+    }
+
+    fn params(&self, m: u16) -> Option<u32> {
+        self.golomb_rice.get(m as usize).copied()
+    }
+
+    fn golomb_param(&self, m: u16) -> Option<usize> {
+        Some((self.params(m)? >> 27) as usize)
+    }
+
+    fn skip_bits(&self, m: u16) -> Option<usize> {
+        Some((self.params(m)? & 0xffff) as usize)
+    }
+
+    fn skip_nodes(&self, m: u16) -> Option<usize> {
+        Some(((self.params(m)? >> 16) & 0x7ff) as usize)
+    }
+}
+
+fn compute_golomb_rice(
+    m: u16,
+    table: &mut [u32],
+    leaf_size: u16,
+    primary_aggr_bound: u16,
+    secondary_aggr_bound: u16,
+) -> Result<()> {
+    let (unit, fanout) = if m > secondary_aggr_bound {
+        let unit = secondary_aggr_bound
+            * (((m + 1) / 2 + secondary_aggr_bound - 1) / secondary_aggr_bound);
+        (unit, 2)
+    } else if m > primary_aggr_bound {
+        (
+            primary_aggr_bound,
+            (m + primary_aggr_bound - 1) / primary_aggr_bound,
+        )
+    } else {
+        (leaf_size, (m + leaf_size - 1) / leaf_size)
+    };
+
+    let mut k = vec![unit; fanout as usize];
+    k[fanout as usize - 1] = m - unit * (fanout - 1);
+
+    let sqrt_prod: f64 = k.iter().map(|&ki| (ki as f64).sqrt()).product();
+    let p = (m as f64).sqrt()
+        / ((2.0 * std::f64::consts::PI).powf((fanout as f64 - 1.0) / 2.0) * sqrt_prod);
+    let mut length = ((-((5f64.sqrt() + 1.0) / 2.0).ln() / (-p).ln_1p()).log2()).ceil() as u32;
+    if length > 0x1f {
+        return Err(format_err!("golomb rice length overflow for m = {}", m));
+    }
+    table[m as usize] = length << 27;
+    for &ki in &k {
+        length += table[ki as usize] & 0xffff;
+    }
+    if length > 0xffff {
+        return Err(format_err!(
+            "golomb rice subtree length overflow for m = {}",
+            m
+        ));
+    }
+    table[m as usize] |= length;
+    let mut nodes = 1;
+    for &ki in &k {
+        nodes += (table[ki as usize] >> 16) & 0x7ff;
+    }
+    if leaf_size >= 3 && nodes > 0x7ff {
+        return Err(format_err!("golomb rice node count overflow for m = {}", m));
+    }
+    table[m as usize] |= nodes << 16;
+    Ok(())
+}
+
+/// Reads the Golomb-Rice coded bijection seeds. See erigon-lib/recsplit/golomb_rice.go.
+struct GolombRiceReader<'a> {
+    data: &'a [u8],
+    words: Words,
+    curr_fixed_offset: usize,
+    curr_window_unary: u64,
+    curr_ptr_unary: usize,
+    valid_lower_bits_unary: usize,
+}
+
+impl<'a> GolombRiceReader<'a> {
+    fn new(data: &'a [u8], words: Words) -> Self {
+        Self {
+            data,
+            words,
+            curr_fixed_offset: 0,
+            curr_window_unary: 0,
+            curr_ptr_unary: 0,
+            valid_lower_bits_unary: 0,
+        }
+    }
+
+    fn word(&self, i: usize) -> Option<u64> {
+        self.words.get(self.data, i)
+    }
+
+    fn read_reset(&mut self, bit_pos: usize, unary_offset: usize) -> Option<()> {
+        self.curr_fixed_offset = bit_pos;
+        let unary_pos = bit_pos.checked_add(unary_offset)?;
+        self.curr_ptr_unary = unary_pos / 64;
+        self.curr_window_unary = self.word(self.curr_ptr_unary)? >> (unary_pos & 63);
+        self.curr_ptr_unary += 1;
+        self.valid_lower_bits_unary = 64 - (unary_pos & 63);
+        Some(())
+    }
+
+    fn skip_subtree(&mut self, nodes: usize, fixed_len: usize) -> Option<()> {
+        let mut missing = nodes;
+        loop {
+            let cnt = self.curr_window_unary.count_ones() as usize;
+            if cnt >= missing {
+                break;
+            }
+            self.curr_window_unary = self.word(self.curr_ptr_unary)?;
+            self.curr_ptr_unary += 1;
+            missing -= cnt;
+            self.valid_lower_bits_unary = 64;
+        }
+        let cnt = select64(self.curr_window_unary, missing.checked_sub(1)?);
+        self.curr_window_unary = (self.curr_window_unary >> cnt) >> 1;
+        self.valid_lower_bits_unary = self.valid_lower_bits_unary.checked_sub(cnt + 1)?;
+        self.curr_fixed_offset = self.curr_fixed_offset.checked_add(fixed_len)?;
+        Some(())
+    }
+
+    fn read_next(&mut self, log2golomb: usize) -> Option<u64> {
+        let mut result = 0u64;
+        if self.curr_window_unary == 0 {
+            result += self.valid_lower_bits_unary as u64;
+            self.curr_window_unary = self.word(self.curr_ptr_unary)?;
+            self.curr_ptr_unary += 1;
+            self.valid_lower_bits_unary = 64;
+            while self.curr_window_unary == 0 {
+                result += 64;
+                self.curr_window_unary = self.word(self.curr_ptr_unary)?;
+                self.curr_ptr_unary += 1;
+            }
+        }
+        let pos = self.curr_window_unary.trailing_zeros() as usize;
+        self.curr_window_unary = (self.curr_window_unary >> pos) >> 1;
+        self.valid_lower_bits_unary = self.valid_lower_bits_unary.checked_sub(pos + 1)?;
+        result += pos as u64;
+        result <<= log2golomb;
+
+        let idx64 = self.curr_fixed_offset >> 6;
+        let shift = self.curr_fixed_offset & 63;
+        let mut fixed = self.word(idx64)? >> shift;
+        if shift + log2golomb > 64 {
+            fixed |= self.word(idx64 + 1)? << (64 - shift);
+        }
+        result |= fixed & ((1u64 << log2golomb) - 1);
+        self.curr_fixed_offset += log2golomb;
+        Some(result)
+    }
+}
+
+/// Elias-Fano encoding of the cumulative key counts and bit positions of the
+/// buckets. See erigon-lib/recsplit/eliasfano16.
+#[derive(Debug)]
+struct DoubleEliasFano {
+    lower_bits: Words,
+    upper_bits_cum_keys: Words,
+    upper_bits_position: Words,
+    jump: Words,
+    lower_bits_mask_cum_keys: u64,
+    lower_bits_mask_position: u64,
+    l_cum_keys: u64,
+    l_position: u64,
+    cum_keys_min_delta: u64,
+    min_diff: u64,
+}
+
+impl DoubleEliasFano {
+    fn read(r: &mut ByteReader<'_>) -> Result<Self> {
+        let num_buckets = r.u64()?;
+        let u_cum_keys = r.u64()?;
+        let u_position = r.u64()?;
+        let cum_keys_min_delta = r.u64()?;
+        let min_diff = r.u64()?;
+
+        let n = num_buckets
+            .checked_add(1)
+            .ok_or_else(|| format_err!("bad bucket count: {}", num_buckets))?;
+        let l_position = log2(u_position / n);
+        let l_cum_keys = log2(u_cum_keys / n);
+        if l_cum_keys * 2 + l_position > 56 {
+            return Err(format_err!("bad elias-fano parameters"));
+        }
+
+        let overflow = || format_err!("elias-fano size overflow for {} buckets", num_buckets);
+        let words_lower_bits = n
+            .checked_mul(l_cum_keys + l_position)
+            .and_then(|bits| bits.checked_add(63))
+            .ok_or_else(overflow)?
+            / 64
+            + 1;
+        let words_cum_keys = upper_words(n, u_cum_keys >> l_cum_keys).ok_or_else(overflow)?;
+        let words_position = upper_words(n, u_position >> l_position).ok_or_else(overflow)?;
+        let mut jump_words = n / SUPER_Q * SUPER_Q_SIZE * 2;
+        if n % SUPER_Q != 0 {
+            jump_words += (1 + ((n % SUPER_Q + Q - 1) / Q + 3) / 4) * 2;
+        }
+
+        Ok(Self {
+            lower_bits: r.words_le(usize::try_from(words_lower_bits)?)?,
+            upper_bits_cum_keys: r.words_le(usize::try_from(words_cum_keys)?)?,
+            upper_bits_position: r.words_le(usize::try_from(words_position)?)?,
+            jump: r.words_le(usize::try_from(jump_words)?)?,
+            lower_bits_mask_cum_keys: (1 << l_cum_keys) - 1,
+            lower_bits_mask_position: (1 << l_position) - 1,
+            l_cum_keys,
+            l_position,
+            cum_keys_min_delta,
+            min_diff,
+        })
+    }
+
+    /// Returns the cumulative key count of bucket `i` and `i + 1`, and the bit
+    /// position of bucket `i`.
+    fn get3(&self, data: &[u8], i: u64) -> Option<(u64, u64, u64)> {
+        let pos_lower = i.checked_mul(self.l_cum_keys + self.l_position)?;
+        let idx64 = (pos_lower / 64) as usize;
+        let shift = pos_lower % 64;
+        let mut lower = self.lower_bits.get(data, idx64)? >> shift;
+        if shift > 0 {
+            lower |= self.lower_bits.get(data, idx64 + 1)? << (64 - shift);
+        }
+
+        let jump_super_q = (i / SUPER_Q * SUPER_Q_SIZE * 2) as usize;
+        let jump_inside_super_q = ((i % SUPER_Q) / Q) as usize;
+        let idx16 = 4 * (jump_super_q + 2) + 2 * jump_inside_super_q;
+        let jump_cum_keys = self
+            .jump
+            .get(data, jump_super_q)?
+            .checked_add(self.jump_offset(data, idx16)?)?;
+        let jump_position = self
+            .jump
+            .get(data, jump_super_q + 1)?
+            .checked_add(self.jump_offset(data, idx16 + 1)?)?;
+
+        let delta = i & Q_MASK;
+        let (mut word_cum_keys, mut window_cum_keys, cum_delta) =
+            Self::skip(data, self.upper_bits_cum_keys, jump_cum_keys, delta)?;
+        let (word_position, window_position, position_delta) =
+            Self::skip(data, self.upper_bits_position, jump_position, delta)?;
+
+        let select_position = select64(window_position, position_delta as usize) as u64;
+        let position = ((word_position as u64 * 64 + select_position).checked_sub(i)?
+            << self.l_position
+            | ((lower >> self.l_cum_keys) & self.lower_bits_mask_position))
+            .checked_add(i.checked_mul(self.min_diff)?)?;
+
+        let select_cum_keys = select64(window_cum_keys, cum_delta as usize) as u64;
+        let cum_keys = ((word_cum_keys as u64 * 64 + select_cum_keys).checked_sub(i)?
+            << self.l_cum_keys
+            | (lower & self.lower_bits_mask_cum_keys))
+            .checked_add(i.checked_mul(self.cum_keys_min_delta)?)?;
+
+        // the next set bit in the upper bits is bucket i + 1
+        window_cum_keys &= (u64::MAX << select_cum_keys) << 1;
+        while window_cum_keys == 0 {
+            word_cum_keys += 1;
+            window_cum_keys = self.upper_bits_cum_keys.get(data, word_cum_keys)?;
+        }
+        let lower_next = lower >> (self.l_cum_keys + self.l_position);
+        let cum_keys_next = ((word_cum_keys as u64 * 64 + window_cum_keys.trailing_zeros() as u64)
+            .checked_sub(i + 1)?
+            << self.l_cum_keys
+            | (lower_next & self.lower_bits_mask_cum_keys))
+            .checked_add((i + 1).checked_mul(self.cum_keys_min_delta)?)?;
+
+        Some((cum_keys, cum_keys_next, position))
+    }
+
+    fn jump_offset(&self, data: &[u8], idx16: usize) -> Option<u64> {
+        let shift = 16 * (idx16 % 4);
+        Some((self.jump.get(data, idx16 / 4)? >> shift) & 0xffff)
+    }
+
+    /// Advances from bit `start` to the word holding the `delta`th following set bit.
+    fn skip(data: &[u8], upper: Words, start: u64, mut delta: u64) -> Option<(usize, u64, u64)> {
+        let mut word = (start / 64) as usize;
+        let mut window = upper.get(data, word)? & (u64::MAX << (start % 64));
+        loop {
+            let bit_count = window.count_ones() as u64;
+            if bit_count > delta {
+                return Some((word, window, delta));
+            }
+            word += 1;
+            window = upper.get(data, word)?;
+            delta -= bit_count;
+        }
+    }
+}
+
+/// Elias-Fano encoding of a monotone sequence, used for the word offsets of
+/// enumerated indices. See erigon-lib/recsplit/eliasfano32.
+#[derive(Debug)]
+struct EliasFano32 {
+    /// The number of values.
+    count: u64,
+    lower_bits: Words,
+    upper_bits: Words,
+    jump: Words,
+    lower_bits_mask: u64,
+    l: u64,
+}
+
+impl EliasFano32 {
+    fn read(r: &mut ByteReader<'_>) -> Result<Self> {
+        // written as the index of the last value and one past the largest value
+        let count = r
+            .u64()?
+            .checked_add(1)
+            .ok_or_else(|| format_err!("bad elias-fano count"))?;
+        let max_offset = r
+            .u64()?
+            .checked_sub(1)
+            .ok_or_else(|| format_err!("bad elias-fano bound"))?;
+        let l = log2(max_offset / count);
+
+        let overflow = || format_err!("elias-fano size overflow for {} values", count);
+        let words_lower_bits = count
+            .checked_mul(l)
+            .and_then(|bits| bits.checked_add(63))
+            .ok_or_else(overflow)?
+            / 64
+            + 1;
+        let words_upper_bits = upper_words(count, max_offset >> l).ok_or_else(overflow)?;
+        let mut jump_words = count / SUPER_Q * SUPER_Q_SIZE;
+        if count % SUPER_Q != 0 {
+            jump_words += 1 + ((count % SUPER_Q + Q - 1) / Q + 3) / 2;
+        }
+
+        Ok(Self {
+            count,
+            lower_bits: r.words_le(usize::try_from(words_lower_bits)?)?,
+            upper_bits: r.words_le(usize::try_from(words_upper_bits)?)?,
+            jump: r.words_le(usize::try_from(jump_words)?)?,
+            lower_bits_mask: (1 << l) - 1,
+            l,
+        })
+    }
+
+    /// Returns the `i`th value, or `None` if there is none or the encoding
+    /// is malformed.
+    fn get(&self, data: &[u8], i: u64) -> Option<u64> {
+        if i >= self.count {
+            return None;
+        }
+        let pos_lower = i.checked_mul(self.l)?;
+        let idx64 = usize::try_from(pos_lower / 64).ok()?;
+        let shift = pos_lower % 64;
+        let mut lower = self.lower_bits.get(data, idx64)? >> shift;
+        if shift > 0 {
+            lower |= self.lower_bits.get(data, idx64 + 1)? << (64 - shift);
+        }
+
+        // 32 bit offsets of each block of Q values, after the offset of their super block
+        let jump_super_q = usize::try_from(i / SUPER_Q * SUPER_Q_SIZE).ok()?;
+        let jump_inside_super_q = ((i % SUPER_Q) / Q) as usize;
+        let idx64 = jump_super_q + 1 + (jump_inside_super_q >> 1);
+        let shift = 32 * (jump_inside_super_q % 2);
+        let jump = self
+            .jump
+            .get(data, jump_super_q)?
+            .checked_add((self.jump.get(data, idx64)? >> shift) & 0xffff_ffff)?;
+
+        let (word, window, delta) = DoubleEliasFano::skip(data, self.upper_bits, jump, i & Q_MASK)?;
+        let sel = select64(window, delta as usize) as u64;
+        let upper = (word as u64 * 64 + sel).checked_sub(i)?;
+        Some(upper << self.l | (lower & self.lower_bits_mask))
+    }
+}
+
+fn log2(x: u64) -> u64 {
+    if x == 0 {
+        0
+    } else {
+        63 - x.leading_zeros() as u64
+    }
+}
+
+/// Returns the number of words of upper bits for `n` values whose upper
+/// parts are at most `max_upper`.
+fn upper_words(n: u64, max_upper: u64) -> Option<u64> {
+    Some(n.checked_add(max_upper)?.checked_add(63)? / 64)
+}
+
+/// Returns the position of the `k`th (0-indexed) set bit of `x`, or 64 if
+/// `x` has fewer set bits.
+fn select64(mut x: u64, k: usize) -> usize {
+    for _ in 0..k {
+        x &= x.wrapping_sub(1);
+    }
+    x.trailing_zeros() as usize
+}
+
+/// A compressed segment file of words. See erigon-lib/compress/decompress.go.
+#[derive(Debug)]
+pub struct Segment {
+    path: PathBuf,
+    data: Mmap,
+    words_count: u64,
+    /// Patterns, as ranges of `data`.
+    patterns: HuffmanTree<Range<usize>>,
+    positions: HuffmanTree<u64>,
+    /// The offset of the first word. Word offsets are relative to it.
+    words_start: usize,
+    /// Bounds the length of a word: each pattern takes at least a bit.
+    max_pattern_len: usize,
+}
+
+impl Segment {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let data = map_file(&path)?;
+        let mut r = ByteReader::new(&data);
+        let words_count = r.u64()?;
+        let _empty_words_count = r.u64()?;
+
+        let dict_size = usize::try_from(r.u64()?)?;
+        let dict_start = r.pos;
+        let mut dict = ByteReader::new(r.take(dict_size)?);
+        let mut patterns = vec![];
+        let mut max_pattern_len = 1;
+        while !dict.is_empty() {
+            let depth = dict.uvarint()?;
+            let len = usize::try_from(dict.uvarint()?)?;
+            let start = dict_start + dict.pos;
+            dict.skip(len)?;
+            max_pattern_len = max_pattern_len.max(len);
+            patterns.push((depth, start..start + len));
+        }
+
+        let dict_size = usize::try_from(r.u64()?)?;
+        let mut dict = ByteReader::new(r.take(dict_size)?);
+        let mut positions = vec![];
+        while !dict.is_empty() {
+            let depth = dict.uvarint()?;
+            positions.push((depth, dict.uvarint()?));
+        }
+
+        let words_start = r.pos;
+        Ok(Self {
+            patterns: HuffmanTree::new(patterns)
+                .map_err(|e| format_err!("{}: patterns: {}", path.display(), e))?,
+            positions: HuffmanTree::new(positions)
+                .map_err(|e| format_err!("{}: positions: {}", path.display(), e))?,
+            path,
+            data,
+            words_count,
+            words_start,
+            max_pattern_len,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn words_count(&self) -> u64 {
+        self.words_count
+    }
+
+    /// Decodes the word at `offset`, as given by the segment's index.
+    pub fn word_at(&self, offset: u64) -> Result<Vec<u8>> {
+        self.decode(offset).map_err(|e| {
+            format_err!(
+                "{}: bad word at offset {}: {}",
+                self.path.display(),
+                offset,
+                e
+            )
+        })
+    }
+
+    fn decode(&self, offset: u64) -> Result<Vec<u8>> {
+        let data = &self.data[self.words_start..];
+        let start = usize::try_from(offset)?;
+        anyhow::ensure!(start <= data.len(), "past the end of the segment");
+
+        // A word is its length + 1, the positions and patterns covering it,
+        // which end with a 0 position, then the uncovered bytes. Patterns are
+        // read twice: once to place them, then again to find the gaps
+        // between them which the uncovered bytes fill.
+        let mut bits = BitReader::new(data, start);
+        let word_len = self
+            .positions
+            .decode(&mut bits)?
+            .checked_sub(1)
+            .ok_or_else(|| format_err!("zero word length"))?;
+        let word_len = usize::try_from(word_len)?;
+        let max_len = (data.len() - start).saturating_mul(8 * self.max_pattern_len);
+        anyhow::ensure!(word_len <= max_len, "word longer than the segment");
+        let mut word = vec![0u8; word_len];
+        if word_len == 0 {
+            return Ok(word);
+        }
+        let mut covered = vec![];
+        let mut at = 0usize;
+        loop {
+            let pos = usize::try_from(*self.positions.decode(&mut bits)?)?;
+            if pos == 0 {
+                break;
+            }
+            at = at
+                .checked_add(pos - 1)
+                .ok_or_else(|| format_err!("pattern position overflow"))?;
+            let pattern = &self.data[self.patterns.decode(&mut bits)?.clone()];
+            let end = at
+                .checked_add(pattern.len())
+                .filter(|end| *end <= word_len)
+                .ok_or_else(|| format_err!("pattern past the end of the word"))?;
+            word[at..end].copy_from_slice(pattern);
+            covered.push(at..end);
+        }
+        bits.align();
+
+        let mut uncovered = bits.pos;
+        let mut fill = |from: usize, to: usize| -> Result<()> {
+            let len = to - from;
+            let src = data
+                .get(uncovered..uncovered.saturating_add(len))
+                .ok_or_else(|| format_err!("uncovered bytes past the end of the segment"))?;
+            word[from..to].copy_from_slice(src);
+            uncovered += len;
+            Ok(())
+        };
+        let mut last = 0;
+        for range in covered {
+            if range.start > last {
+                fill(last, range.start)?;
+            }
+            last = range.end;
+        }
+        if word_len > last {
+            fill(last, word_len)?;
+        }
+        Ok(word)
+    }
+}
+
+/// A Huffman code, as written by erigon-lib/compress: the (depth, value)
+/// entries are the leaves in depth-first order, 0 branch first.
+#[derive(Debug)]
+struct HuffmanTree<T> {
+    nodes: Vec<HuffmanNode<T>>,
+}
+
+#[derive(Debug)]
+enum HuffmanNode<T> {
+    Leaf(T),
+    Branch(usize, usize),
+}
+
+impl<T> HuffmanTree<T> {
+    fn new(entries: Vec<(u64, T)>) -> Result<Self> {
+        let mut tree = Self { nodes: vec![] };
+        let n = entries.len();
+        let mut entries = entries.into_iter().peekable();
+        if n > 0 {
+            tree.build(&mut entries, 0)?;
+        }
+        anyhow::ensure!(entries.next().is_none(), "unreachable code entries");
+        Ok(tree)
+    }
+
+    fn build<I: Iterator<Item = (u64, T)>>(
+        &mut self,
+        entries: &mut std::iter::Peekable<I>,
+        depth: u64,
+    ) -> Result<usize> {
+        let next_depth = entries
+            .peek()
+            .map(|(d, _)| *d)
+            .ok_or_else(|| format_err!("incomplete code"))?;
+        let idx = self.nodes.len();
+        if next_depth == depth {
+            let (_, value) = entries.next().unwrap();
+            self.nodes.push(HuffmanNode::Leaf(value));
+            return Ok(idx);
+        }
+        anyhow::ensure!(
+            next_depth > depth && depth < 64,
+            "bad code depth {}",
+            next_depth
+        );
+        self.nodes.push(HuffmanNode::Branch(0, 0));
+        let zero = self.build(entries, depth + 1)?;
+        let one = self.build(entries, depth + 1)?;
+        self.nodes[idx] = HuffmanNode::Branch(zero, one);
+        Ok(idx)
+    }
+
+    fn decode(&self, bits: &mut BitReader<'_>) -> Result<&T> {
+        let mut node = 0;
+        loop {
+            match self.nodes.get(node) {
+                Some(HuffmanNode::Leaf(value)) => return Ok(value),
+                Some(HuffmanNode::Branch(zero, one)) => {
+                    node = if bits.next()? { *one } else { *zero }
+                }
+                None => return Err(format_err!("empty code")),
+            }
+        }
+    }
+}
+
+/// Reads the bits of a byte slice, least significant bit first.
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit: u8,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8], pos: usize) -> Self {
+        Self { data, pos, bit: 0 }
+    }
+
+    fn next(&mut self) -> Result<bool> {
+        let byte = self
+            .data
+            .get(self.pos)
+            .ok_or_else(|| format_err!("unexpected end of segment"))?;
+        let set = (byte >> self.bit) & 1 == 1;
+        self.bit += 1;
+        if self.bit == 8 {
+            self.align();
+        }
+        Ok(set)
+    }
+
+    /// Skips to the start of the next byte, unless at the start of one.
+    fn align(&mut self) {
+        if self.bit > 0 {
+            self.pos += 1;
+            self.bit = 0;
+        }
+    }
+}
+
+/// Maps `x` uniformly onto `0..n`.
+fn remap(x: u64, n: u64) -> u64 {
+    ((x as u128 * n as u128) >> 64) as u64
+}
+
+fn remap16(x: u64, n: u16) -> u16 {
+    (((x & 0xffff) * n as u64) >> 16) as u16
+}
+
+fn remix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+/// 128-bit x64 murmur3, returning (h1, h2) as github.com/spaolacci/murmur3's Sum128.
+pub(crate) fn murmur3_x64_128(data: &[u8], seed: u32) -> (u64, u64) {
+    const C1: u64 = 0x87c37b91114253d5;
+    const C2: u64 = 0x4cf5ad432745937f;
+
+    fn fmix(mut k: u64) -> u64 {
+        k ^= k >> 33;
+        k = k.wrapping_mul(0xff51afd7ed558ccd);
+        k ^= k >> 33;
+        k = k.wrapping_mul(0xc4ceb9fe1a85ec53);
+        k ^ (k >> 33)
+    }
+
+    let (mut h1, mut h2) = (seed as u64, seed as u64);
+    let mut blocks = data.chunks_exact(16);
+    for block in &mut blocks {
+        let mut k1 = u64::from_le_bytes(block[..8].try_into().unwrap());
+        let mut k2 = u64::from_le_bytes(block[8..].try_into().unwrap());
+
+        k1 = k1.wrapping_mul(C1).rotate_left(31).wrapping_mul(C2);
+        h1 ^= k1;
+        h1 = h1
+            .rotate_left(27)
+            .wrapping_add(h2)
+            .wrapping_mul(5)
+            .wrapping_add(0x52dce729);
+
+        k2 = k2.wrapping_mul(C2).rotate_left(33).wrapping_mul(C1);
+        h2 ^= k2;
+        h2 = h2
+            .rotate_left(31)
+            .wrapping_add(h1)
+            .wrapping_mul(5)
+            .wrapping_add(0x38495ab5);
+    }
+
+    let tail = blocks.remainder();
+    let (mut k1, mut k2) = (0u64, 0u64);
+    for (i, b) in tail.iter().enumerate() {
+        if i < 8 {
+            k1 |= (*b as u64) << (8 * i);
+        } else {
+            k2 |= (*b as u64) << (8 * (i - 8));
+        }
+    }
+    if tail.len() > 8 {
+        h2 ^= k2.wrapping_mul(C2).rotate_left(33).wrapping_mul(C1);
+    }
+    if !tail.is_empty() {
+        h1 ^= k1.wrapping_mul(C1).rotate_left(31).wrapping_mul(C2);
+    }
+
+    h1 ^= data.len() as u64;
+    h2 ^= data.len() as u64;
+    h1 = h1.wrapping_add(h2);
+    h2 = h2.wrapping_add(h1);
+    h1 = fmix(h1);
+    h2 = fmix(h2);
+    h1 = h1.wrapping_add(h2);
+    h2 = h2.wrapping_add(h1);
+    (h1, h2)
+}
+
+/// A run of little endian words in a mapped file, kept as offsets into it
+/// rather than copied out.
+#[derive(Debug, Clone, Copy, Default)]
+struct Words {
+    start: usize,
+    len: usize,
+}
+
+impl Words {
+    fn get(&self, data: &[u8], i: usize) -> Option<u64> {
+        if i >= self.len {
+            return None;
+        }
+        let pos = self.start.checked_add(i.checked_mul(8)?)?;
+        let buf: [u8; 8] = data.get(pos..pos.checked_add(8)?)?.try_into().ok()?;
+        Some(u64::from_le_bytes(buf))
+    }
+}
+
+/// Big endian cursor over an index or segment file.
+struct ByteReader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos >= self.buf.len()
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        let end = self
+            .pos
+            .checked_add(n)
+            .filter(|end| *end <= self.buf.len())
+            .ok_or_else(|| format_err!("unexpected end of file at byte {}", self.pos))?;
+        let out = &self.buf[self.pos..end];
+        self.pos = end;
+        Ok(out)
+    }
+
+    fn skip(&mut self, n: usize) -> Result<()> {
+        self.take(n).map(|_| ())
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into()?))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into()?))
+    }
+
+    /// Reads a varint as written by Go's `binary.PutUvarint`.
+    fn uvarint(&mut self) -> Result<u64> {
+        let mut out = 0u64;
+        for shift in (0..64u32).step_by(7) {
+            let b = self.u8()?;
+            out |= ((b & 0x7f) as u64)
+                .checked_shl(shift)
+                .filter(|v| v >> shift == (b & 0x7f) as u64)
+                .ok_or_else(|| format_err!("uvarint overflow at byte {}", self.pos))?;
+            if b & 0x80 == 0 {
+                return Ok(out);
+            }
+        }
+        Err(format_err!("uvarint overflow at byte {}", self.pos))
+    }
+
+    /// Skips `n` words which were written in native (little endian) byte
+    /// order, returning their offsets.
+    fn words_le(&mut self, n: usize) -> Result<Words> {
+        let len = n
+            .checked_mul(8)
+            .ok_or_else(|| format_err!("bad word count: {}", n))?;
+        let start = self.pos;
+        self.skip(len)?;
+        Ok(Words { start, len: n })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_murmur3() {
+        assert_eq!(murmur3_x64_128(b"", 0), (0, 0));
+        assert_eq!(
+            murmur3_x64_128(b"hello", 0),
+            (0xcbd8a7b341bd9b02, 0x5b1e906a48ae1d19)
+        );
+    }
+
+    #[test]
+    fn test_select64() {
+        let x = 0b1011_0100;
+        assert_eq!(select64(x, 0), 2);
+        assert_eq!(select64(x, 1), 4);
+        assert_eq!(select64(x, 3), 7);
+    }
+
+    #[test]
+    fn test_elias_fano32() -> Result<()> {
+        // [0, 5]: count - 1, max + 1, lower bits, upper bits, jump table
+        let mut data = vec![];
+        data.extend_from_slice(&1u64.to_be_bytes());
+        data.extend_from_slice(&6u64.to_be_bytes());
+        for word in [2u64, 0, 9, 0, 0, 0] {
+            data.extend_from_slice(&word.to_le_bytes());
+        }
+        let mut r = ByteReader::new(&data);
+        let ef = EliasFano32::read(&mut r)?;
+        assert!(r.is_empty());
+        assert_eq!(ef.get(&data, 0), Some(0));
+        assert_eq!(ef.get(&data, 1), Some(5));
+        assert_eq!(ef.get(&data, 2), None);
+
+        // truncated
+        let mut r = ByteReader::new(&data[..data.len() - 8]);
+        assert!(EliasFano32::read(&mut r).is_err());
+        Ok(())
+    }
+
+    /// Writes a segment of one word with the given dictionaries and words.
+    fn segment(patterns: &[u8], positions: &[u8], words: &[u8]) -> Result<Segment> {
+        let dir = tempfile::tempdir_in(crate::test::TMP_DIR.clone())?;
+        let mut data = vec![];
+        data.extend_from_slice(&1u64.to_be_bytes());
+        data.extend_from_slice(&0u64.to_be_bytes());
+        data.extend_from_slice(&(patterns.len() as u64).to_be_bytes());
+        data.extend_from_slice(patterns);
+        data.extend_from_slice(&(positions.len() as u64).to_be_bytes());
+        data.extend_from_slice(positions);
+        data.extend_from_slice(words);
+        let path = dir.path().join("v1-000000-000500-headers.seg");
+        fs::write(&path, data)?;
+        // the map outlives the deleted file
+        Segment::open(path)
+    }
+
+    #[test]
+    fn test_segment_word() -> Result<()> {
+        // no patterns. Positions 0 and 4 (the length + 1) are coded 0 and 1:
+        // the length, then the terminator, then the uncovered bytes
+        let seg = segment(&[], &[1, 0, 1, 4], &[0b01, b'a', b'b', b'c'])?;
+        assert_eq!(seg.word_at(0)?, b"abc");
+        assert!(seg.word_at(5).is_err());
+        // the uncovered bytes are cut short
+        let seg = segment(&[], &[1, 0, 1, 4], &[0b01, b'a', b'b'])?;
+        assert!(seg.word_at(0).is_err());
+
+        // one pattern, coded with no bits. Positions 0, 1 and 6 are coded 0,
+        // 01 and 11: the length, the pattern at position 0, the terminator
+        let seg = segment(
+            &[0, 3, b'x', b'y', b'z'],
+            &[1, 0, 2, 1, 2, 6],
+            &[0b00111, b'a', b'b'],
+        )?;
+        assert_eq!(seg.word_at(0)?, b"xyzab");
+        Ok(())
+    }
+
+    /// Reads a headers segment and a transactions-to-block index written by
+    /// Erigon's own compressor and recsplit builder.
+    #[test]
+    #[cfg(feature = "db")]
+    fn test_erigon_fixture() -> Result<()> {
+        use crate::test::{ffi::snapshot::*, rand::Rand};
+        use akula::models::{BlockHeader, BlockNumber};
+        use fastrlp::Encodable;
+
+        let mut rng = rand::thread_rng();
+        let dir = tempfile::tempdir_in(crate::test::TMP_DIR.clone())?;
+        let headers = (0..3)
+            .map(|num| BlockHeader {
+                number: BlockNumber(num),
+                ..BlockHeader::rand(&mut rng)
+            })
+            .collect::<Vec<_>>();
+        let hashes = headers.iter().map(BlockHeader::hash).collect::<Vec<_>>();
+        // [first byte of the hash][rlp header]
+        let words = headers
+            .iter()
+            .zip(&hashes)
+            .map(|(header, hash)| {
+                let mut word = vec![hash[0]];
+                header.encode(&mut word);
+                word
+            })
+            .collect::<Vec<_>>();
+        let seg = dir.path().join("v1-000000-000500-headers.seg");
+        write_segment(&seg, &words, &hashes, 0)?;
+        let txs = [
+            (H256::rand(&mut rng), 0),
+            (H256::rand(&mut rng), 2),
+            (H256::rand(&mut rng), 2),
+        ];
+        let idx = dir
+            .path()
+            .join("v1-000000-000500-transactions-to-block.idx");
+        write_tx_to_block_index(&idx, &txs, 0)?;
+
+        let index = SnapshotTxIndex::open(dir.path())?;
+        for (num, hash) in hashes.iter().enumerate() {
+            assert_eq!(index.read_block_hash(num as u64)?, Some(*hash));
+        }
+        assert_eq!(index.read_block_hash(3)?, None);
+        for (hash, num) in txs {
+            assert!(index.candidate_blocks(hash.as_bytes())?.contains(&num));
+        }
+        assert_eq!(index.max_block(), Some(499_999));
+        Ok(())
+    }
+
+    #[test]
+    fn test_huffman_tree() -> Result<()> {
+        // codes 0, 01 and 11, read lsb first
+        let tree = HuffmanTree::new(vec![(1, 'a'), (2, 'b'), (2, 'c')])?;
+        let mut bits = BitReader::new(&[0b1101_0], 0);
+        assert_eq!(*tree.decode(&mut bits)?, 'a');
+        assert_eq!(*tree.decode(&mut bits)?, 'b');
+        assert_eq!(*tree.decode(&mut bits)?, 'c');
+        // incomplete and overfull codes
+        assert!(HuffmanTree::new(vec![(1, 'a'), (2, 'b')]).is_err());
+        assert!(HuffmanTree::new(vec![(1, 'a'), (1, 'b'), (1, 'c')]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_uvarint() -> Result<()> {
+        let mut r = ByteReader::new(&[0x05, 0xac, 0x02]);
+        assert_eq!(r.uvarint()?, 5);
+        assert_eq!(r.uvarint()?, 300);
+        assert!(r.is_empty());
+        assert!(ByteReader::new(&[0xff; 11]).uvarint().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_tx_to_block_range() {
+        let path = Path::new("/snapshots/v1-000500-001000-transactions-to-block.idx");
+        assert_eq!(
+            tx_to_block_range(path),
+            Some(BlockRange {
+                from: 500_000,
+                to: 1_000_000
+            })
+        );
+        assert_eq!(
+            tx_to_block_range(Path::new("v1-000500-001000-transactions.idx")),
+            None
+        );
+    }
+
+    /// A single file torrent of the segment `name`.
+    fn torrent(name: &str) -> Vec<u8> {
+        let info = format!("d6:lengthi5e4:name{}:{}e", name.len(), name);
+        format!("d8:announce3:foo4:info{}e", info).into_bytes()
+    }
+
+    #[test]
+    fn test_torrent_info_hash() -> Result<()> {
+        // sha1 of the info dictionary
+        assert_eq!(
+            torrent_info_hash(&torrent("v1-000000-000500-bodies.seg"))?,
+            "fdc0d7330dc4b341fd25f4a8c402f86382811cc3"
+        );
+        assert!(torrent_info_hash(b"d8:announce3:fooe").is_err());
+        assert!(torrent_info_hash(b"d4:infod6:lengthi5e").is_err());
+        assert!(torrent_info_hash(b"d4:info99:abce").is_err());
+        assert!(torrent_info_hash(&[b'l'; 100]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_snapshot_inventory() -> Result<()> {
+        let dir = tempfile::tempdir_in(crate::test::TMP_DIR.clone())?;
+        for name in [
+            "v1-000000-000500-bodies.seg",
+            "v1-000000-000500-headers.seg",
+            "v1-000500-001000-headers.seg",
+        ] {
+            fs::write(dir.path().join(format!("{}.torrent", name)), torrent(name))?;
+        }
+        // unreadable torrents are skipped
+        fs::write(
+            dir.path().join("v1-001500-002000-headers.seg.torrent"),
+            b"x",
+        )?;
+        for name in [
+            "v1-000000-000500-headers.seg",
+            "v1-000500-001000-headers.seg",
+            "v1-001000-001500-headers.seg",
+            "v1-000000-000500-headers.idx",
+        ] {
+            fs::write(dir.path().join(name), b"")?;
+        }
+
+        let inventory = SnapshotInventory::open(dir.path())?;
+        assert_eq!(inventory.segments.len(), 4);
+        let missing = inventory.missing().collect::<Vec<_>>();
+        assert_eq!(missing.len(), 1);
+        assert_eq!(missing[0].name, "v1-000000-000500-bodies.seg");
+        assert_eq!(
+            missing[0].hash.as_deref(),
+            Some("fdc0d7330dc4b341fd25f4a8c402f86382811cc3")
+        );
+        assert_eq!(
+            inventory.missing_ranges(),
+            vec![BlockRange {
+                from: 0,
+                to: 500_000
+            }]
+        );
+        assert_eq!(
+            inventory.ranges("headers"),
+            vec![BlockRange {
+                from: 0,
+                to: 1_500_000
+            }]
+        );
+        // on disk but unlisted
+        let unlisted = &inventory.segments[3];
+        assert_eq!(unlisted.range.from, 1_000_000);
+        assert!(unlisted.on_disk && !unlisted.listed);
+        Ok(())
+    }
+}
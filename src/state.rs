@@ -0,0 +1,146 @@
+//! Reads of the state as of a past block. The history indices record the
+//! blocks which changed each account and storage slot, and the changeset of
+//! the first block after the one read holds the value going into it; anything
+//! changed by no later block is read from the current state.
+
+use anyhow::{format_err, Result};
+use ethers::types::{Address, H256};
+use mdbx::{EnvironmentKind, TransactionKind};
+use std::collections::BTreeMap;
+
+use crate::{models::Account, reader::Reader, types::BlockNum};
+
+/// A view of the state as it was after block `num`, returned by
+/// `Reader::state_at`. Every `Client` method taking a `block` reads through
+/// it for blocks before the head.
+pub struct StateAt<'r, 'env, K: TransactionKind, E: EnvironmentKind> {
+    dbtx: &'r mut Reader<'env, K, E>,
+    num: BlockNum,
+}
+
+impl<'r, 'env, K: TransactionKind, E: EnvironmentKind> StateAt<'r, 'env, K, E> {
+    pub(crate) fn new(dbtx: &'r mut Reader<'env, K, E>, num: BlockNum) -> Self {
+        Self { dbtx, num }
+    }
+
+    /// The block the state is read at.
+    pub fn block(&self) -> BlockNum {
+        self.num
+    }
+
+    /// Returns the account `who`, empty if it didn't exist. Changesets omit
+    /// code hashes, so a contract's is read from PlainContractCode by its
+    /// incarnation at the time.
+    pub fn account(&mut self, who: Address) -> Result<Account> {
+        let changed = match self.dbtx.read_account_change_from(who, self.next())? {
+            Some(changed) => changed,
+            None => return self.dbtx.read_account_data(who),
+        };
+        let mut acct = self
+            .dbtx
+            .read_account_before(who, changed)?
+            .ok_or_else(|| format_err!("no changeset for {:?} in block {}", who, changed))?;
+        self.dbtx.fill_codehash(who, &mut acct)?;
+        Ok(acct)
+    }
+
+    /// Returns the value of `slot` of `who`, read at the account's incarnation
+    /// as of the block.
+    pub fn storage(&mut self, who: Address, slot: H256) -> Result<H256> {
+        let incarnation = self.account(who)?.incarnation;
+        self.storage_at_incarnation(who, incarnation, slot)
+    }
+
+    /// Returns the value of `slot` of `who` at `incarnation`. StorageHistory
+    /// doesn't key by incarnation, so the changes to the slot at other
+    /// incarnations are passed over.
+    pub fn storage_at_incarnation(
+        &mut self,
+        who: Address,
+        incarnation: u64,
+        slot: H256,
+    ) -> Result<H256> {
+        let mut from = self.next();
+        while let Some(changed) = self.dbtx.read_storage_change_from(who, slot, from)? {
+            if let Some(val) = self
+                .dbtx
+                .read_storage_before(who, incarnation, slot, changed)?
+            {
+                return Ok(val);
+            }
+            from = BlockNum(*changed + 1);
+        }
+        self.dbtx.read_account_storage(who, incarnation, slot)
+    }
+
+    /// Returns the code of `who`, empty for accounts without code.
+    pub fn code(&mut self, who: Address) -> Result<bytes::Bytes> {
+        let acct = self.account(who)?;
+        self.dbtx.read_code(acct.codehash)
+    }
+
+    /// Returns every account changed after the block as it was at the block,
+    /// empty if it didn't exist then.
+    pub fn changed_accounts(&mut self) -> Result<BTreeMap<Address, Account>> {
+        let mut accounts = self.dbtx.read_account_changes_since(self.num)?;
+        for (who, acct) in accounts.iter_mut() {
+            self.dbtx.fill_codehash(*who, acct)?;
+        }
+        Ok(accounts)
+    }
+
+    /// Returns every storage slot changed after the block, by address and
+    /// incarnation, with its value at the block. A slot which was unset is
+    /// zero.
+    pub fn changed_storage(&mut self) -> Result<BTreeMap<(Address, u64), BTreeMap<H256, H256>>> {
+        self.dbtx.read_storage_changes_since(self.num)
+    }
+
+    /// The first block after the one read, whose changesets hold the state
+    /// going into it.
+    fn next(&self) -> BlockNum {
+        BlockNum(*self.num + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        client::Client,
+        test::{chain::ChainBuilder, TMP_DIR},
+    };
+
+    #[test]
+    fn test_state_at() -> Result<()> {
+        let who = Address::repeat_byte(0x42);
+        let slot = H256::from_low_u64_be(1);
+        let word = H256::from_low_u64_be;
+        // block 2 destroys the first incarnation and creates the second
+        let chain = ChainBuilder::new()
+            .block(|b| b)
+            .block(|b| {
+                b.account_change(who, Some(Account::new().incarnation(1)))
+                    .storage_change(who, 1, slot, word(5))
+                    .account(who, Account::new().incarnation(2))
+                    .storage(who, slot, word(9))
+            })
+            .block(|b| b)
+            .write(TMP_DIR.clone())?;
+        let db = Client::<mdbx::NoWriteMap>::open_new(chain.path.clone())?;
+        let mut dbtx = db.reader()?;
+
+        let mut state = dbtx.state_at(BlockNum(1));
+        assert_eq!(state.account(who)?.incarnation, 1);
+        assert_eq!(state.storage(who, slot)?, word(5));
+        // the change in block 2 was to the first incarnation
+        assert_eq!(state.storage_at_incarnation(who, 2, slot)?, word(9));
+        assert!(state.code(who)?.is_empty());
+
+        let mut state = dbtx.state_at(BlockNum(2));
+        assert_eq!(state.block(), BlockNum(2));
+        assert_eq!(state.account(who)?.incarnation, 2);
+        assert_eq!(state.storage(who, slot)?, word(9));
+        Ok(())
+    }
+}
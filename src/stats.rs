@@ -0,0 +1,161 @@
+use anyhow::Result;
+use ethers::types::{Address, U256, U64};
+use mdbx::EnvironmentKind;
+use serde::Serialize;
+use std::{collections::HashSet, ops::RangeBounds};
+
+use crate::{
+    client::{Client, BLOCK_TABLES},
+    convert,
+    types::{BlockNum, HeaderKey},
+};
+
+/// Aggregates over a range of blocks, as shown on chain dashboards.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChainStats {
+    pub from: U64,
+    pub to: U64,
+    pub blocks: u64,
+    /// The number of transactions, less any system transactions excluded by
+    /// the client's `SystemTxs` option.
+    pub tx_count: u64,
+    /// The gas used by every block, as recorded in their headers.
+    pub gas_used: U256,
+    /// The mean base fee of the blocks which have one, i.e. those from London
+    /// on. `None` if none do.
+    pub avg_base_fee: Option<U256>,
+    /// The number of distinct transaction senders.
+    pub unique_senders: u64,
+    /// The number of transactions which create a contract. Contracts created
+    /// by other contracts aren't counted.
+    pub contract_creations: u64,
+}
+
+impl<E: EnvironmentKind> Client<E> {
+    /// Returns the aggregates of the canonical blocks in `range`, read in a
+    /// single transaction. The headers, bodies, transactions and senders of
+    /// the range are each read once, in order, rather than assembling every
+    /// block. An unbounded end is the current head.
+    ///
+    /// The senders seen are held in memory to count the distinct ones, so very
+    /// long ranges are best split, at the cost of counting senders active in
+    /// several parts more than once.
+    ///
+    /// ```ignore
+    /// let head = client.get_block_number()?.as_u64();
+    /// let day = client.chain_stats(head - 7200..=head)?;
+    /// ```
+    pub fn chain_stats<R: RangeBounds<u64>>(&self, range: R) -> Result<ChainStats> {
+        let (from, to) = match self.resolve_range(range)? {
+            Some(bounds) => bounds,
+            None => return Ok(ChainStats::default()),
+        };
+
+        let _permit = self.admit()?;
+        let _slow = self.slow_read("chain_stats", BLOCK_TABLES, || format!("{}..={}", from, to));
+        let options = self.options();
+        let mut dbtx = self.reader()?;
+        let mut stats = ChainStats {
+            from: from.into(),
+            to: to.into(),
+            ..Default::default()
+        };
+        let mut senders = HashSet::<Address>::new();
+        let mut base_fees = (U256::zero(), 0u64);
+
+        for num in from..=to {
+            let key = HeaderKey::new(num, dbtx.read_canonical_hash(BlockNum(num))?);
+            let header = dbtx.read_header(key)?;
+            stats.blocks += 1;
+            stats.gas_used += U256::from(header.gas_used);
+            if let Some(base_fee) = header.base_fee_per_gas {
+                base_fees.0 = base_fees.0.saturating_add(convert::u256(base_fee));
+                base_fees.1 += 1;
+            }
+
+            let body = dbtx.read_body_for_storage(key)?;
+            let tx_amt = body.tx_amount.try_into()?;
+            let msgs = dbtx.read_transactions(body.base_tx_id.into(), tx_amt)?;
+            let senders = self.read_block_senders(&mut dbtx, key, &msgs)?;
+            for (msg, sender) in msgs.iter().zip(senders) {
+                if options.system_txs.excludes(&sender) {
+                    continue;
+                }
+                stats.tx_count += 1;
+                if msg.action().into_address().is_none() {
+                    stats.contract_creations += 1;
+                }
+                senders.insert(sender);
+            }
+        }
+
+        stats.unique_senders = senders.len().try_into()?;
+        stats.avg_base_fee = (base_fees.1 > 0).then(|| base_fees.0 / base_fees.1);
+        Ok(stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::{
+        chain::ChainBuilder,
+        rand::{rand_1559, rand_vec, sign},
+        TMP_DIR,
+    };
+    use akula::models::{MessageWithSignature, TransactionAction};
+    use ethers::core::k256::ecdsa::SigningKey;
+    use rand::thread_rng;
+
+    #[test]
+    fn test_chain_stats() -> Result<()> {
+        let mut rng = thread_rng();
+        let mut txs: Vec<MessageWithSignature> = rand_vec(&mut rng, 3);
+        // two txs from the same sender
+        let key = SigningKey::random(&mut rng);
+        for _ in 0..2 {
+            let message = rand_1559(&mut rng);
+            let signature = sign(key.clone(), message.hash().as_bytes());
+            txs.push(MessageWithSignature { message, signature });
+        }
+        let chain = ChainBuilder::new()
+            .start(10)
+            .block(|b| b.txs(txs[..2].to_vec()).base_fee(10u64))
+            .block(|b| b.base_fee(20u64))
+            .block(|b| b.txs(txs[2..].to_vec()).base_fee(60u64))
+            .write(TMP_DIR.clone())?;
+        let db = Client::<mdbx::NoWriteMap>::open_new(chain.path.clone())?;
+
+        let stats = db.chain_stats(10..)?;
+        let gas_used = chain
+            .blocks
+            .iter()
+            .map(|b| U256::from(b.header.gas_used))
+            .fold(U256::zero(), |a, b| a + b);
+        let creations = txs
+            .iter()
+            .filter(|tx| matches!(tx.action(), TransactionAction::Create))
+            .count();
+        assert_eq!(
+            stats,
+            ChainStats {
+                from: 10.into(),
+                to: 12.into(),
+                blocks: 3,
+                tx_count: 5,
+                gas_used,
+                avg_base_fee: Some(30.into()),
+                unique_senders: 4,
+                contract_creations: creations as u64,
+            }
+        );
+
+        let stats = db.chain_stats(11..12)?;
+        assert_eq!((stats.blocks, stats.tx_count), (1, 0));
+        assert_eq!(stats.avg_base_fee, Some(20.into()));
+        assert_eq!(db.chain_stats(..0)?, ChainStats::default());
+        assert!(db.chain_stats(10..=13).is_err());
+        Ok(())
+    }
+}
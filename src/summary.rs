@@ -0,0 +1,81 @@
+use anyhow::Result;
+use ethers::types::{Address, H256, U256, U64};
+use mdbx::EnvironmentKind;
+use serde::Serialize;
+
+use crate::{
+    bitmap::union,
+    client::Client,
+    reader::{Reader, EMPTY_CODEHASH},
+    tables,
+};
+
+// The tables read by `address_summary`, as reported in the slow read log
+const SUMMARY_TABLES: &[&str] = &[
+    tables::PlainState::const_db_name(),
+    tables::AccountHistory::const_db_name(),
+    tables::CallFromIndex::const_db_name(),
+    tables::CallToIndex::const_db_name(),
+];
+
+/// The "address page" view of an account: its current state along with the
+/// activity recorded for it in the history and call trace indices.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AddressSummary {
+    pub address: Address,
+    pub balance: U256,
+    pub incarnation: u64,
+    pub codehash: H256,
+    /// The account nonce, which is the number of transactions sent by an EOA.
+    pub sent_tx_count: U256,
+    /// The first block in which the account changed, if it ever has.
+    pub first_seen: Option<U64>,
+    /// The last block in which the account changed, if it ever has.
+    pub last_seen: Option<U64>,
+    /// The number of blocks in which the address appears in the CallTraceSet,
+    /// either as a sender or as a recipient.
+    pub appearances: u64,
+}
+
+impl AddressSummary {
+    pub fn is_contract(&self) -> bool {
+        !self.codehash.is_zero() && self.codehash != *EMPTY_CODEHASH
+    }
+}
+
+impl<E: EnvironmentKind> Client<E> {
+    /// Returns the current state and activity summary of `address`, read in a
+    /// single transaction.
+    pub fn address_summary(&self, address: Address) -> Result<AddressSummary> {
+        let _slow = self.slow_read("address_summary", SUMMARY_TABLES, || {
+            format!("{:?}", address)
+        });
+        let mut dbtx = self.reader()?;
+        let acct = dbtx.read_account_data(address)?;
+
+        let history = dbtx.read_bitmap_index64(tables::AccountHistory, address.as_bytes())?;
+
+        Ok(AddressSummary {
+            address,
+            balance: acct.balance,
+            incarnation: acct.incarnation,
+            codehash: acct.codehash,
+            sent_tx_count: acct.nonce.into(),
+            first_seen: history.first().copied().map(From::from),
+            last_seen: history.last().copied().map(From::from),
+            appearances: read_appearances(&mut dbtx, address)?,
+        })
+    }
+}
+
+/// Counts the blocks in which `address` is a trace sender or recipient. The
+/// call indices are built from the CallTraceSet, so this avoids scanning it.
+fn read_appearances<E: EnvironmentKind>(
+    dbtx: &mut Reader<'_, mdbx::RO, E>,
+    address: Address,
+) -> Result<u64> {
+    let from = dbtx.read_bitmap_index64(tables::CallFromIndex, address.as_bytes())?;
+    let to = dbtx.read_bitmap_index64(tables::CallToIndex, address.as_bytes())?;
+    Ok(union(&from, &to).len().try_into()?)
+}
@@ -1,29 +1,168 @@
-use crate::models::{Account, StorageBucket};
-use akula::decl_table;
+use crate::{
+    models::{Account, FixedKey, StorageBucket, HEADER_KEY_LENGTH},
+    types::{BlockNum, HeaderKey},
+};
 use ethers::types::{Address, H256};
 
-// pub use crate::models::Storage;
+/// Declares a table as akula's `decl_table!` does, with two extensions:
+/// - `Name("DbName") => ...` stores the table under a different db name, so
+///   several views with their own key/value codecs can share one table.
+/// - a trailing `, dupsort => SeekBothKey` makes the table `DupSort`.
+///
+/// A fourth type after the value sets the seek key, which defaults to the key.
+macro_rules! decl_table {
+    ($name:ident => $($rest:tt)+) => {
+        decl_table!($name(stringify!($name)) => $($rest)+);
+    };
+    ($name:ident($db_name:expr) => $key:ty => $value:ty $(, dupsort => $seek_both:ty)?) => {
+        decl_table!($name($db_name) => $key => $value => $key $(, dupsort => $seek_both)?);
+    };
+    ($name:ident($db_name:expr) => $key:ty => $value:ty => $seek_key:ty $(, dupsort => $seek_both:ty)?) => {
+        #[derive(Clone, Copy, Debug, Default)]
+        pub struct $name;
 
-decl_table!(LastHeader => Vec<u8> => H256);
-decl_table!(LastBlock => Vec<u8> => H256);
-decl_table!(IncarnationMap => Address => u64);
+        impl akula::kv::Table for $name {
+            type Key = $key;
+            type SeekKey = $seek_key;
+            type Value = $value;
+
+            fn db_name(&self) -> string::String<bytes::Bytes> {
+                unsafe {
+                    string::String::from_utf8_unchecked(bytes::Bytes::from_static(
+                        Self::const_db_name().as_bytes(),
+                    ))
+                }
+            }
+        }
+
+        $(
+            impl akula::kv::DupSort for $name {
+                type SeekBothKey = $seek_both;
+            }
+        )?
+
+        impl $name {
+            pub const fn const_db_name() -> &'static str {
+                $db_name
+            }
+
+            pub const fn erased(self) -> akula::kv::tables::ErasedTable<Self> {
+                akula::kv::tables::ErasedTable(self)
+            }
+        }
+
+        impl std::fmt::Display for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "{}", Self::const_db_name())
+            }
+        }
+    };
+}
+
+pub const LAST_HEADER_KEY: FixedKey<10> = FixedKey(*b"LastHeader");
+pub const LAST_BLOCK_KEY: FixedKey<9> = FixedKey(*b"LastBlock");
+pub const SAFE_BLOCK_KEY: &[u8] = b"safeBlockHash";
+pub const FINALIZED_BLOCK_KEY: &[u8] = b"finalizedBlockHash";
+
+decl_table!(LastHeader => FixedKey<10> => H256);
+decl_table!(LastBlock => FixedKey<9> => H256);
+// The hashes in the last forkchoice update from the consensus layer
+decl_table!(LastForkchoice => Vec<u8> => H256);
+
+// Headers and bodies
+// Raw views of akula's tables with fixed-size keys, so lookups don't allocate
+// block number ++ block hash => rlp encoded header
+decl_table!(Header => FixedKey<HEADER_KEY_LENGTH> => Vec<u8>);
+// block number ++ block hash => rlp encoded BodyForStorage
+decl_table!(BlockBody => FixedKey<HEADER_KEY_LENGTH> => Vec<u8>);
+// block number => hash of the canonical header
+decl_table!(CanonicalHeader => BlockNum => H256);
+// header hash => block number
+decl_table!(HeaderNumber => H256 => BlockNum);
+// block number ++ block hash => rlp encoded total difficulty
+decl_table!(HeaderTD("HeadersTotalDifficulty") => HeaderKey => Vec<u8>);
+// block number ++ block hash => senders of the block's txs
+decl_table!(TxSender => HeaderKey => Vec<Address>);
+// Erigon's EthTx table: tx id => rlp encoded tx
+decl_table!(BlockTransaction => FixedKey<8> => Vec<u8>);
 // Erigon's TxLookup table
 decl_table!(BlockTransactionLookup => H256 => akula::models::U256);
+
+// State
 decl_table!(PlainState => Address => Account);
+// Account storage overlaps with PlainState: address ++ incarnation => slot ++ value (dupsort)
+decl_table!(Storage("PlainState") => StorageBucket => (H256, akula::models::U256), dupsort => H256);
+decl_table!(IncarnationMap => Address => u64);
+// address ++ incarnation => code hash
+decl_table!(PlainContractCode => StorageBucket => H256);
+// code hash => bytecode
+decl_table!(Code => H256 => bytes::Bytes);
+// keccak(address) => account
+decl_table!(HashedAccount => H256 => Account);
+// keccak(address) ++ incarnation => keccak(slot) ++ value without leading zeros (dupsort)
+decl_table!(HashedStorage => Vec<u8> => Vec<u8>, dupsort => H256);
+
+// History
+// block number => address ++ account before the block (dupsort)
+decl_table!(AccountChangeSet => BlockNum => Vec<u8>, dupsort => Address);
+// block number ++ address ++ incarnation => slot ++ value before the block (dupsort)
+decl_table!(StorageChangeSet => Vec<u8> => Vec<u8>, dupsort => H256);
+// address ++ shard => roaring64 bitmap of blocks in which the account changed
+decl_table!(AccountHistory => Vec<u8> => Vec<u8>);
+// address ++ slot ++ shard => roaring64 bitmap of blocks in which the slot changed
+decl_table!(StorageHistory => Vec<u8> => Vec<u8>);
+
+// Receipts and logs
+// block number => cbor encoded receipts
+decl_table!(Receipt => BlockNum => Vec<u8>);
+// block number ++ tx index => cbor encoded logs
+decl_table!(TransactionLog => Vec<u8> => Vec<u8>);
+// address ++ shard => roaring bitmap of block numbers
+decl_table!(LogAddressIndex => Vec<u8> => Vec<u8>);
+// topic ++ shard => roaring bitmap of block numbers
+decl_table!(LogTopicIndex => Vec<u8> => Vec<u8>);
 
-// Custom table for account storage because it overlaps with PlainState
-#[derive(Clone, Copy, Debug, Default)]
-pub struct Storage;
+// Call traces
+// block number => address ++ flags, for each address in the block's traces (dupsort)
+decl_table!(CallTraceSet => BlockNum => Vec<u8>, dupsort => Address);
+// address ++ shard => roaring64 bitmap of blocks in which the address was a trace sender
+decl_table!(CallFromIndex => Vec<u8> => Vec<u8>);
+// address ++ shard => roaring64 bitmap of blocks in which the address was a trace recipient
+decl_table!(CallToIndex => Vec<u8> => Vec<u8>);
 
-impl akula::kv::Table for Storage {
-    type Key = StorageBucket;
-    type SeekKey = StorageBucket;
-    type Value = (H256, akula::models::U256);
+// Chain metadata
+// genesis hash => json encoded chain config
+decl_table!(Config => H256 => Vec<u8>);
+// stage name => highest block processed by the stage
+decl_table!(SyncStage => Vec<u8> => BlockNum);
+// block number => total ether issued; "burnt" ++ block number => total ether burnt
+decl_table!(Issuance => Vec<u8> => akula::models::U256);
+// block number ++ block hash => epoch transition proof, for clique and aura
+decl_table!(Epoch("DevEpoch") => HeaderKey => Vec<u8>);
+decl_table!(PendingEpoch("DevPendingEpoch") => HeaderKey => Vec<u8>);
 
-    fn db_name(&self) -> string::String<bytes::Bytes> {
-        string::String::from_str("PlainState")
+// Polygon
+// block number => rlp encoded receipt of the block's state-sync tx
+#[cfg(feature = "polygon")]
+decl_table!(BorReceipt => BlockNum => Vec<u8>);
+// state-sync tx hash => block number
+#[cfg(feature = "polygon")]
+decl_table!(BorTxLookup("BlockBorTransactionLookup") => H256 => akula::models::U256);
+
+// Stored in the txpool db rather than chaindata.
+// tx hash => sender ++ rlp encoded tx
+decl_table!(PoolTransaction => Vec<u8> => Vec<u8>);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use akula::kv::Table;
+
+    #[test]
+    fn test_decl_table_db_name() {
+        assert_eq!(Storage::const_db_name(), PlainState::const_db_name());
+        assert_eq!(Storage.db_name(), PlainState.db_name());
+        assert_eq!(Header.to_string(), "Header");
+        assert_eq!(Header.erased().db_name(), Header.db_name());
     }
 }
-impl akula::kv::DupSort for Storage {
-    type SeekBothKey = H256;
-}
@@ -0,0 +1,461 @@
+use akula::models::{
+    self as ak_models, BlockHeader, BlockNumber, BodyForStorage, MessageWithSignature, H256,
+};
+use anyhow::Result;
+use ethers::types::Address;
+use rand::thread_rng;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use super::{ffi::writer::Writer, rand::Rand};
+use crate::{
+    client::Client,
+    models::{Account, CallTrace, Log, Receipt},
+    trie,
+    types::TxId,
+};
+
+/// Builds a small chain of blocks and writes it to a new db, along with the
+/// indices needed to read it back: header numbers, canonical hashes, tx lookup
+/// entries, senders, the log address and topic indices and the head header
+/// hash.
+///
+/// Headers are random apart from their number, parent hash and transactions
+/// root, so each block links to the previous one and commits to its txs.
+///
+/// ```ignore
+/// let chain = ChainBuilder::new()
+///     .block(|b| b.tx(tx).account(who, Account::new().balance(bal)))
+///     .block(|b| b)
+///     .write(TMP_DIR.clone())?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct ChainBuilder {
+    start: u64,
+    blocks: Vec<BlockBuilder>,
+    config: Option<Vec<u8>>,
+    forkchoice: Option<(H256, H256)>,
+    stages: Vec<(&'static str, u64)>,
+}
+
+impl Default for ChainBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The contents of one block of a `ChainBuilder` chain.
+#[derive(Debug, Clone, Default)]
+pub struct BlockBuilder {
+    txs: Vec<MessageWithSignature>,
+    raw_txs: Vec<Vec<u8>>,
+    ommers: Vec<BlockHeader>,
+    senders: Option<Vec<ak_models::Address>>,
+    side_blocks: Vec<Vec<u8>>,
+    destroyed: Vec<(Address, u64)>,
+    accounts: Vec<(Address, Account)>,
+    storage: Vec<(Address, H256, H256)>,
+    code: Vec<(Address, u64, Vec<u8>)>,
+    receipts: Vec<(Receipt, Vec<Log>)>,
+    base_fee: Option<ak_models::U256>,
+    timestamp: Option<u64>,
+    receipts_root: Option<H256>,
+    issuance: Option<(u64, u64)>,
+    traces: Vec<CallTrace>,
+    changes: Vec<(Address, Option<Account>)>,
+    storage_changes: Vec<(Address, u64, H256, H256)>,
+    #[cfg(feature = "polygon")]
+    state_sync: Option<Vec<Log>>,
+    #[cfg(feature = "gnosis")]
+    epochs: Vec<(Vec<u8>, bool)>,
+}
+
+impl BlockBuilder {
+    pub fn tx(mut self, tx: MessageWithSignature) -> Self {
+        self.txs.push(tx);
+        self
+    }
+
+    pub fn txs<T: IntoIterator<Item = MessageWithSignature>>(mut self, txs: T) -> Self {
+        self.txs.extend(txs);
+        self
+    }
+
+    /// Appends a tx stored as the given bytes, after the txs added by `tx`.
+    /// No sender is written for it.
+    pub fn raw_tx(mut self, raw: Vec<u8>) -> Self {
+        self.raw_txs.push(raw);
+        self
+    }
+
+    pub fn ommer(mut self, header: BlockHeader) -> Self {
+        self.ommers.push(header);
+        self
+    }
+
+    /// Stores `senders` in place of the ones recovered from the txs, so some
+    /// can be left zero for the client to recover.
+    pub fn senders(mut self, senders: Vec<ak_models::Address>) -> Self {
+        self.senders = Some(senders);
+        self
+    }
+
+    /// Writes a side-chain header at the block's height, a copy of the block's
+    /// header with `extra_data`, so it shares the block's parent. Only the
+    /// header is written.
+    pub fn side_block(mut self, extra_data: Vec<u8>) -> Self {
+        self.side_blocks.push(extra_data);
+        self
+    }
+
+    /// Deletes the account of `who` as SELFDESTRUCT does, recording
+    /// `incarnation` as its last, before the block's other state is written.
+    pub fn destroy(mut self, who: Address, incarnation: u64) -> Self {
+        self.destroyed.push((who, incarnation));
+        self
+    }
+
+    /// Writes `acct` to the plain state. Only the latest state is written, so
+    /// the account reads the same at every block.
+    pub fn account(mut self, who: Address, acct: Account) -> Self {
+        self.accounts.push((who, acct));
+        self
+    }
+
+    /// Writes a storage slot to the plain state. See `account`.
+    pub fn storage(mut self, who: Address, key: H256, val: H256) -> Self {
+        self.storage.push((who, key, val));
+        self
+    }
+
+    /// Writes `code` as the code of `who` at `incarnation`. The account's code
+    /// hash is not set, see `account`.
+    pub fn code(mut self, who: Address, incarnation: u64, code: Vec<u8>) -> Self {
+        self.code.push((who, incarnation, code));
+        self
+    }
+
+    /// Records that the block changed `who`, which was `before` going into the
+    /// block, or didn't exist if `None`. The account's history index is built
+    /// from the blocks which change it.
+    pub fn account_change(mut self, who: Address, before: Option<Account>) -> Self {
+        self.changes.push((who, before));
+        self
+    }
+
+    /// Records that the block changed `slot` of `who` at `incarnation`, which
+    /// was `before` going into the block, or unset if zero. The slot's history
+    /// index is built from the blocks which change it.
+    pub fn storage_change(
+        mut self,
+        who: Address,
+        incarnation: u64,
+        slot: H256,
+        before: H256,
+    ) -> Self {
+        self.storage_changes.push((who, incarnation, slot, before));
+        self
+    }
+
+    /// Appends a CallTraceSet entry. Blocks with none have no traces stored.
+    pub fn call_trace(mut self, trace: CallTrace) -> Self {
+        self.traces.push(trace);
+        self
+    }
+
+    /// Sets the header's base fee, which is otherwise random.
+    pub fn base_fee<T: Into<ak_models::U256>>(mut self, base_fee: T) -> Self {
+        self.base_fee = Some(base_fee.into());
+        self
+    }
+
+    /// Sets the header's timestamp, which is otherwise random.
+    pub fn timestamp(mut self, timestamp: u64) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    /// Sets the header's receipts root, which is otherwise random.
+    pub fn receipts_root(mut self, root: H256) -> Self {
+        self.receipts_root = Some(root);
+        self
+    }
+
+    /// Records the cumulative ether issued and burnt as of the block, as the
+    /// Issuance stage does.
+    pub fn issuance(mut self, issued: u64, burnt: u64) -> Self {
+        self.issuance = Some((issued, burnt));
+        self
+    }
+
+    /// Appends the receipt of the next transaction and the logs it emitted.
+    /// Blocks with no receipts have none stored.
+    pub fn receipt(mut self, receipt: Receipt, logs: Vec<Log>) -> Self {
+        self.receipts.push((receipt, logs));
+        self
+    }
+
+    /// Makes the block an AuRa epoch transition with `proof`, or records it as
+    /// signalling one if `pending`.
+    #[cfg(feature = "gnosis")]
+    pub fn epoch(mut self, proof: Vec<u8>, pending: bool) -> Self {
+        self.epochs.push((proof, pending));
+        self
+    }
+
+    /// Gives the block a Polygon state-sync tx emitting `logs`, storing its
+    /// receipt and indexing its hash.
+    #[cfg(feature = "polygon")]
+    pub fn state_sync(mut self, logs: Vec<Log>) -> Self {
+        self.state_sync = Some(logs);
+        self
+    }
+}
+
+/// A chain written by `ChainBuilder::write`.
+#[derive(Debug, Clone)]
+pub struct BuiltChain {
+    /// The path of the closed db.
+    pub path: PathBuf,
+    pub blocks: Vec<ak_models::Block>,
+    /// The headers written by `BlockBuilder::side_block`, in order.
+    pub side_blocks: Vec<BlockHeader>,
+}
+
+impl BuiltChain {
+    /// Returns the hash of the `i`th block of the chain.
+    pub fn hash(&self, i: usize) -> H256 {
+        self.blocks[i].header.hash()
+    }
+
+    pub fn head(&self) -> &ak_models::Block {
+        self.blocks.last().expect("empty chain")
+    }
+}
+
+impl ChainBuilder {
+    pub fn new() -> Self {
+        Self {
+            start: 1,
+            blocks: vec![],
+            config: None,
+            forkchoice: None,
+            stages: vec![],
+        }
+    }
+
+    /// Sets the number of the first block, which defaults to 1.
+    pub fn start(mut self, num: u64) -> Self {
+        self.start = num;
+        self
+    }
+
+    /// Writes the json encoded chain `config` under the hash of the first
+    /// block, which is only read as the genesis if the chain starts at 0.
+    pub fn config(mut self, config: &[u8]) -> Self {
+        self.config = Some(config.to_vec());
+        self
+    }
+
+    /// Writes the safe and finalized block hashes of the last forkchoice update.
+    pub fn forkchoice(mut self, safe: H256, finalized: H256) -> Self {
+        self.forkchoice = Some((safe, finalized));
+        self
+    }
+
+    /// Records the progress of the sync `stage`, such as
+    /// `reader::EXECUTION_STAGE`, as block `num`.
+    pub fn stage_progress(mut self, stage: &'static str, num: u64) -> Self {
+        self.stages.push((stage, num));
+        self
+    }
+
+    /// Appends a block built by `f`.
+    pub fn block<F: FnOnce(BlockBuilder) -> BlockBuilder>(mut self, f: F) -> Self {
+        self.blocks.push(f(BlockBuilder::default()));
+        self
+    }
+
+    /// Writes the chain to a new db in `dir`.
+    pub fn write<P: AsRef<Path>>(self, dir: P) -> Result<BuiltChain> {
+        let mut rng = thread_rng();
+        let mut w = Writer::open(dir)?;
+        let mut parent_hash = H256::rand(&mut rng);
+        let mut base_tx_id = u64::from(u32::rand(&mut rng));
+
+        let mut blocks = vec![];
+        let mut side_blocks = vec![];
+        let mut history = BTreeMap::<Address, Vec<u64>>::new();
+        let mut storage_history = BTreeMap::<(Address, H256), Vec<u64>>::new();
+        let mut log_addresses = BTreeMap::<Address, Vec<u32>>::new();
+        let mut log_topics = BTreeMap::<H256, Vec<u32>>::new();
+        for (num, b) in (self.start..).zip(self.blocks) {
+            let num = BlockNumber(num);
+            let mut header = BlockHeader::rand(&mut rng);
+            header.number = num;
+            header.parent_hash = parent_hash;
+            header.transactions_root = trie::EMPTY_ROOT;
+            if let Some(base_fee) = b.base_fee {
+                header.base_fee_per_gas = Some(base_fee);
+            }
+            if let Some(timestamp) = b.timestamp {
+                header.timestamp = timestamp;
+            }
+            if let Some(root) = b.receipts_root {
+                header.receipts_root = root;
+            }
+            let tx_count = b.txs.len() + b.raw_txs.len();
+            if tx_count > 0 {
+                w.put_transactions(b.txs.clone(), base_tx_id)?;
+                w.put_encoded_transactions(&b.raw_txs, base_tx_id + b.txs.len() as u64)?;
+                // commit to the txs as they are stored
+                let raw = Client::<mdbx::NoWriteMap>::open_new(w.path().to_path_buf())?
+                    .reader()?
+                    .read_raw_transactions(TxId(base_tx_id + 1), tx_count)?;
+                header.transactions_root = trie::ordered_trie_root(&raw);
+            }
+            let hash = header.hash();
+
+            w.put_header(header.clone())?;
+            w.put_canonical_hash(hash, num)?;
+            for extra_data in b.side_blocks {
+                let mut side = header.clone();
+                side.extra_data = extra_data.into();
+                w.put_header(side.clone())?;
+                side_blocks.push(side);
+            }
+            let body = BodyForStorage {
+                base_tx_id: ak_models::TxIndex(base_tx_id),
+                // the txs are surrounded by a system tx on either side
+                tx_amount: (tx_count + 2).try_into()?,
+                uncles: b.ommers.clone(),
+            };
+            w.put_body_for_storage(hash, num, body)?;
+            w.put_tx_lookup_entries(
+                num,
+                b.txs.iter().map(|tx| tx.hash()).chain(
+                    b.raw_txs
+                        .iter()
+                        .map(|raw| H256(ethers::utils::keccak256(raw))),
+                ),
+            )?;
+            let senders = match b.senders {
+                Some(senders) => senders,
+                None => b
+                    .txs
+                    .iter()
+                    .map(|tx| tx.recover_sender())
+                    .collect::<Result<Vec<_>, _>>()?,
+            };
+            w.put_senders(hash, num, senders)?;
+            if let Some((issued, burnt)) = b.issuance {
+                w.put_issuance(num, issued.into(), burnt.into())?;
+            }
+            for (who, incarnation) in b.destroyed {
+                w.delete_account(who, incarnation)?;
+            }
+            for (who, acct) in b.accounts {
+                w.put_account(who, acct)?;
+            }
+            for (who, key, val) in b.storage {
+                w.put_storage(who, key, val)?;
+            }
+            for (who, incarnation, code) in b.code {
+                w.put_code(who, incarnation, &code)?;
+            }
+            #[cfg(feature = "gnosis")]
+            for (proof, pending) in b.epochs {
+                w.put_epoch(num, hash, &proof, pending)?;
+            }
+            #[cfg(feature = "polygon")]
+            if let Some(logs) = b.state_sync {
+                let receipt = crate::models::BorReceipt {
+                    status: 1,
+                    cumulative_gas_used: b
+                        .receipts
+                        .last()
+                        .map_or(0, |(r, _)| r.cumulative_gas_used),
+                    logs,
+                };
+                let tx_hash =
+                    crate::bor::state_sync_tx_hash(crate::types::HeaderKey::new(num, hash));
+                w.put_bor_receipt(num, tx_hash, &receipt)?;
+            }
+            if !b.receipts.is_empty() {
+                let (receipts, logs): (Vec<_>, Vec<_>) = b.receipts.into_iter().unzip();
+                w.put_receipts(num, &receipts)?;
+                for (tx_idx, logs) in (0..).zip(logs) {
+                    if !logs.is_empty() {
+                        w.put_logs(num, tx_idx, &logs)?;
+                    }
+                    for log in &logs {
+                        log_addresses
+                            .entry(log.address)
+                            .or_default()
+                            .push(num.0.try_into()?);
+                        for topic in &log.topics {
+                            log_topics
+                                .entry(*topic)
+                                .or_default()
+                                .push(num.0.try_into()?);
+                        }
+                    }
+                }
+            }
+
+            for (who, before) in b.changes {
+                w.put_account_change(num, who, before)?;
+                history.entry(who).or_default().push(*num);
+            }
+            for (who, incarnation, slot, before) in b.storage_changes {
+                w.put_storage_change(num, who, incarnation, slot, before)?;
+                storage_history.entry((who, slot)).or_default().push(*num);
+            }
+            if !b.traces.is_empty() {
+                w.put_call_traces(num, &b.traces)?;
+            }
+
+            base_tx_id += tx_count as u64 + 2;
+            parent_hash = hash;
+            blocks.push(ak_models::Block {
+                header,
+                transactions: b.txs,
+                ommers: b.ommers,
+            });
+        }
+        for (who, nums) in history {
+            w.put_account_history(who, &nums)?;
+        }
+        for ((who, slot), mut nums) in storage_history {
+            nums.dedup();
+            w.put_storage_history(who, slot, &nums)?;
+        }
+        for (who, mut nums) in log_addresses {
+            nums.dedup();
+            w.put_log_address_index(who, &nums)?;
+        }
+        for (topic, mut nums) in log_topics {
+            nums.dedup();
+            w.put_log_topic_index(topic, &nums)?;
+        }
+        if let Some(head) = blocks.last() {
+            w.put_head_header_hash(head.header.hash())?;
+        }
+        if let (Some(config), Some(genesis)) = (self.config, blocks.first()) {
+            w.put_chain_config(genesis.header.hash(), &config)?;
+        }
+        if let Some((safe, finalized)) = self.forkchoice {
+            w.put_forkchoice(safe, finalized)?;
+        }
+        for (stage, num) in self.stages {
+            w.put_stage_progress(stage, BlockNumber(num))?;
+        }
+        Ok(BuiltChain {
+            path: w.close()?,
+            blocks,
+            side_blocks,
+        })
+    }
+}
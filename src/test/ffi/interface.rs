@@ -12,6 +12,67 @@ extern "C" {
     pub(crate) fn PutHeadHeaderHash(db: GoPtr, hash: GoU256) -> GoExit;
     pub(crate) fn PutHeaderNumber(db: GoPtr, hash: GoU256, num: u64) -> GoExit;
     pub(crate) fn PutCanonicalHash(db: GoPtr, hash: GoU256, num: u64) -> GoExit;
+    pub(crate) fn PutChainConfig(db: GoPtr, genesis: GoU256, config: GoSlice) -> GoExit;
+    pub(crate) fn PutStageProgress(db: GoPtr, stage: GoPath, num: u64) -> GoExit;
+    pub(crate) fn PutForkchoice(db: GoPtr, safe: GoU256, finalized: GoU256) -> GoExit;
+    pub(crate) fn PutIssuance(
+        db: GoPtr,
+        num: u64,
+        total_issued: GoU256,
+        total_burnt: GoU256,
+    ) -> GoExit;
+    // receipts, logs: cbor
+    pub(crate) fn PutReceipts(db: GoPtr, num: u64, receipts: GoSlice) -> GoExit;
+    pub(crate) fn PutLogs(db: GoPtr, num: u64, tx_idx: u32, logs: GoSlice) -> GoExit;
+    #[cfg(feature = "gnosis")]
+    pub(crate) fn PutEpoch(
+        db: GoPtr,
+        num: u64,
+        hash: GoU256,
+        proof: GoSlice,
+        pending: bool,
+    ) -> GoExit;
+    #[cfg(feature = "polygon")]
+    pub(crate) fn PutBorReceipt(db: GoPtr, num: u64, tx_hash: GoSlice, receipt: GoSlice) -> GoExit;
+    // traces: [][]byte
+    pub(crate) fn PutCallTraces(db: GoPtr, num: u64, traces: GoSlice) -> GoExit;
+    // acct: erigon's storage encoding; bitmap: roaring64
+    pub(crate) fn PutAccountChange(
+        db: GoPtr,
+        num: u64,
+        address: GoAddress,
+        acct: GoSlice,
+    ) -> GoExit;
+    // val: the slot's value before the block
+    pub(crate) fn PutStorageChange(
+        db: GoPtr,
+        num: u64,
+        address: GoAddress,
+        incarnation: u64,
+        slot: GoU256,
+        val: GoU256,
+    ) -> GoExit;
+    pub(crate) fn PutAccountHistory(db: GoPtr, address: GoAddress, bitmap: GoSlice) -> GoExit;
+    pub(crate) fn PutStorageHistory(
+        db: GoPtr,
+        address: GoAddress,
+        slot: GoU256,
+        bitmap: GoSlice,
+    ) -> GoExit;
+    pub(crate) fn PutLogAddressIndex(db: GoPtr, address: GoAddress, bitmap: GoSlice) -> GoExit;
+    pub(crate) fn PutLogTopicIndex(db: GoPtr, topic: GoU256, bitmap: GoSlice) -> GoExit;
+    // acct: erigon's storage encoding
+    pub(crate) fn PutHashedAccount(db: GoPtr, address: GoAddress, acct: GoSlice) -> GoExit;
+    pub(crate) fn PutHashedStorage(
+        db: GoPtr,
+        address: GoAddress,
+        incarnation: u64,
+        key: GoU256,
+        val: GoU256,
+    ) -> GoExit;
+    pub(crate) fn PutCode(db: GoPtr, address: GoAddress, incarnation: u64, code: GoSlice)
+        -> GoExit;
+    pub(crate) fn DeleteAccount(db: GoPtr, address: GoAddress, incarnation: u64) -> GoExit;
     pub(crate) fn PutStorage(db: GoPtr, address: GoAddress, key: GoU256, val: GoU256) -> GoExit;
     #[allow(unused)]
     pub(crate) fn PutRawTransactions(db: GoPtr, txs: GoSlice, baseId: u64) -> GoExit;
@@ -33,6 +94,16 @@ extern "C" {
         rlpAccount: GoRlp,
         incarnation: u64,
     ) -> GoExit;
+    // words, keys: [][]byte
+    pub(crate) fn WriteSegment(path: GoPath, words: GoSlice, keys: GoSlice, base_id: u64)
+        -> GoExit;
+    // tx_hashes: [][]byte; block_nums: []uint64
+    pub(crate) fn WriteTxToBlockIndex(
+        path: GoPath,
+        tx_hashes: GoSlice,
+        block_nums: GoSlice,
+        base_id: u64,
+    ) -> GoExit;
 }
 
 #[repr(transparent)]
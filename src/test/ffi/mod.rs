@@ -1,2 +1,3 @@
 pub mod interface;
+pub mod snapshot;
 pub mod writer;
@@ -0,0 +1,58 @@
+//! Writes snapshot files with Erigon's own compressor and recsplit index
+//! builder, for testing the readers in `crate::snapshot`.
+
+use anyhow::Result;
+use bytes::BytesMut;
+use ethers::types::H256;
+use std::path::Path;
+
+use super::interface::*;
+
+/// Writes the segment `path` of `words` and its enumerated index beside it,
+/// keyed by `keys` and numbered from `base_id`.
+pub fn write_segment(path: &Path, words: &[Vec<u8>], keys: &[H256], base_id: u64) -> Result<()> {
+    let path = null_term(path.to_str().unwrap());
+    let mut words = words
+        .iter()
+        .map(|word| BytesMut::from(&word[..]))
+        .collect::<Vec<_>>();
+    let mut words = words.iter_mut().map(GoSlice::from).collect::<Vec<_>>();
+    let mut keys = keys.to_vec();
+    let mut keys = keys
+        .iter_mut()
+        .map(|key| GoSlice::from(&mut key.0[..]))
+        .collect::<Vec<_>>();
+
+    let exit = unsafe {
+        WriteSegment(
+            GoPath::from(path.as_ref()),
+            GoSlice::from(&mut words[..]),
+            GoSlice::from(&mut keys[..]),
+            base_id,
+        )
+    };
+    exit.ok_or_fmt("WriteSegment")?;
+    Ok(())
+}
+
+/// Writes the transactions-to-block index `path`, mapping each of `txs` to
+/// its block.
+pub fn write_tx_to_block_index(path: &Path, txs: &[(H256, u64)], base_id: u64) -> Result<()> {
+    let path = null_term(path.to_str().unwrap());
+    let (mut hashes, mut nums): (Vec<_>, Vec<_>) = txs.iter().copied().unzip();
+    let mut hashes = hashes
+        .iter_mut()
+        .map(|hash| GoSlice::from(&mut hash.0[..]))
+        .collect::<Vec<_>>();
+
+    let exit = unsafe {
+        WriteTxToBlockIndex(
+            GoPath::from(path.as_ref()),
+            GoSlice::from(&mut hashes[..]),
+            GoSlice::from(&mut nums[..]),
+            base_id,
+        )
+    };
+    exit.ok_or_fmt("WriteTxToBlockIndex")?;
+    Ok(())
+}
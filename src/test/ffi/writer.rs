@@ -1,8 +1,15 @@
-use crate::models::Account;
-use akula::models::{self as ak_models, BlockHeader, BlockNumber, BodyForStorage, RlpAccount};
+use crate::{
+    bitmap::{encode_roaring, encode_roaring64},
+    models::{Account, CallTrace, Log, Receipt},
+    pool::BufPool,
+};
+use akula::{
+    kv::traits::TableEncode,
+    models::{self as ak_models, BlockHeader, BlockNumber, BodyForStorage, RlpAccount},
+};
 use anyhow::Result;
 use bytes::BytesMut;
-use ethers::types::{Address, Transaction, H256};
+use ethers::types::{Address, Transaction, H256, U256};
 use fastrlp::*;
 use std::{
     mem,
@@ -11,6 +18,8 @@ use std::{
 
 use super::interface::*;
 
+static RLP_BUFS: BufPool<u8> = BufPool::new(4, 1 << 16);
+
 pub struct Writer {
     path: PathBuf,
     db_ptr: GoPtr,
@@ -31,6 +40,11 @@ impl Writer {
         })
     }
 
+    /// Returns the path of the db, which stays open until `close` is called.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
     pub fn close(mut self) -> Result<PathBuf> {
         unsafe { MdbxClose(self.db_ptr) }
         // consume without running drop()
@@ -57,10 +71,204 @@ impl Writer {
         Ok(())
     }
 
+    /// Writes the json encoded chain config under the genesis hash.
+    pub fn put_chain_config(&mut self, mut genesis: H256, config: &[u8]) -> Result<()> {
+        let mut buf = config.to_vec();
+        let exit =
+            unsafe { PutChainConfig(self.db_ptr, (&mut genesis).into(), (&mut buf[..]).into()) };
+        exit.ok_or_fmt("PutChainConfig")?;
+        Ok(())
+    }
+
+    pub fn put_stage_progress(&mut self, stage: &str, num: BlockNumber) -> Result<()> {
+        let stage = null_term(stage);
+        let exit = unsafe { PutStageProgress(self.db_ptr, GoPath::from(stage.as_ref()), *num) };
+        exit.ok_or_fmt("PutStageProgress")?;
+        Ok(())
+    }
+
+    pub fn put_forkchoice(&mut self, mut safe: H256, mut finalized: H256) -> Result<()> {
+        let exit =
+            unsafe { PutForkchoice(self.db_ptr, (&mut safe).into(), (&mut finalized).into()) };
+        exit.ok_or_fmt("PutForkchoice")?;
+        Ok(())
+    }
+
+    /// Writes the cumulative issuance and burnt fees as of block `num`.
+    pub fn put_issuance(&mut self, num: BlockNumber, issued: U256, burnt: U256) -> Result<()> {
+        let (mut issued, mut burnt) = (H256::from(issued), H256::from(burnt));
+        let exit =
+            unsafe { PutIssuance(self.db_ptr, *num, (&mut issued).into(), (&mut burnt).into()) };
+        exit.ok_or_fmt("PutIssuance")?;
+        Ok(())
+    }
+
+    /// Writes the receipts of block `num`, without their logs.
+    pub fn put_receipts(&mut self, num: BlockNumber, receipts: &[Receipt]) -> Result<()> {
+        let mut buf = Receipt::encode_list(receipts);
+        let exit = unsafe { PutReceipts(self.db_ptr, *num, (&mut buf[..]).into()) };
+        exit.ok_or_fmt("PutReceipts")?;
+        Ok(())
+    }
+
+    /// Writes the AuRa epoch transition proof of the block, to the PendingEpoch
+    /// table if `pending`.
+    #[cfg(feature = "gnosis")]
+    pub fn put_epoch(
+        &mut self,
+        num: BlockNumber,
+        mut hash: H256,
+        proof: &[u8],
+        pending: bool,
+    ) -> Result<()> {
+        let mut buf = proof.to_vec();
+        let exit = unsafe {
+            PutEpoch(
+                self.db_ptr,
+                *num,
+                GoU256::from(&mut hash),
+                (&mut buf[..]).into(),
+                pending,
+            )
+        };
+        exit.ok_or_fmt("PutEpoch")?;
+        Ok(())
+    }
+
+    /// Writes the receipt of the state-sync tx of block `num`, and indexes the
+    /// tx under `tx_hash`.
+    #[cfg(feature = "polygon")]
+    pub fn put_bor_receipt(
+        &mut self,
+        num: BlockNumber,
+        tx_hash: H256,
+        receipt: &crate::models::BorReceipt,
+    ) -> Result<()> {
+        let mut hash = tx_hash.0;
+        let mut buf = receipt.encode();
+        let exit = unsafe {
+            PutBorReceipt(
+                self.db_ptr,
+                *num,
+                (&mut hash[..]).into(),
+                (&mut buf[..]).into(),
+            )
+        };
+        exit.ok_or_fmt("PutBorReceipt")?;
+        Ok(())
+    }
+
+    /// Writes the logs emitted by the transaction at `tx_idx` in block `num`.
+    pub fn put_logs(&mut self, num: BlockNumber, tx_idx: u32, logs: &[Log]) -> Result<()> {
+        let mut buf = Log::encode_list(logs);
+        let exit = unsafe { PutLogs(self.db_ptr, *num, tx_idx, (&mut buf[..]).into()) };
+        exit.ok_or_fmt("PutLogs")?;
+        Ok(())
+    }
+
+    /// Writes the CallTraceSet entries of block `num`.
+    pub fn put_call_traces(&mut self, num: BlockNumber, traces: &[CallTrace]) -> Result<()> {
+        let mut bufs = traces.iter().map(CallTrace::encode).collect::<Vec<_>>();
+        let mut go_slices = bufs
+            .iter_mut()
+            .map(|buf| GoSlice::from(&mut buf[..]))
+            .collect::<Vec<_>>();
+        let exit = unsafe { PutCallTraces(self.db_ptr, *num, GoSlice::from(&mut go_slices[..])) };
+        exit.ok_or_fmt("PutCallTraces")?;
+        Ok(())
+    }
+
+    /// Writes the AccountChangeSet entry of `who` at block `num`: the account
+    /// before the block, or `None` if it didn't exist.
+    pub fn put_account_change(
+        &mut self,
+        num: BlockNumber,
+        mut who: Address,
+        before: Option<Account>,
+    ) -> Result<()> {
+        let mut buf = before.map(TableEncode::encode).unwrap_or_default();
+        let exit = unsafe {
+            PutAccountChange(self.db_ptr, *num, (&mut who).into(), (&mut buf[..]).into())
+        };
+        exit.ok_or_fmt("PutAccountChange")?;
+        Ok(())
+    }
+
+    /// Writes the StorageChangeSet entry of `slot` of `who` at `incarnation`
+    /// at block `num`: the slot's value before the block, zero if it was unset.
+    pub fn put_storage_change(
+        &mut self,
+        num: BlockNumber,
+        mut who: Address,
+        incarnation: u64,
+        mut slot: H256,
+        mut before: H256,
+    ) -> Result<()> {
+        let exit = unsafe {
+            PutStorageChange(
+                self.db_ptr,
+                *num,
+                (&mut who).into(),
+                incarnation,
+                (&mut slot).into(),
+                (&mut before).into(),
+            )
+        };
+        exit.ok_or_fmt("PutStorageChange")?;
+        Ok(())
+    }
+
+    /// Writes the LogAddressIndex of `who` as a single shard.
+    pub fn put_log_address_index(&mut self, mut who: Address, blocks: &[u32]) -> Result<()> {
+        let mut buf = encode_roaring(blocks);
+        let exit =
+            unsafe { PutLogAddressIndex(self.db_ptr, (&mut who).into(), (&mut buf[..]).into()) };
+        exit.ok_or_fmt("PutLogAddressIndex")?;
+        Ok(())
+    }
+
+    /// Writes the LogTopicIndex of `topic` as a single shard.
+    pub fn put_log_topic_index(&mut self, mut topic: H256, blocks: &[u32]) -> Result<()> {
+        let mut buf = encode_roaring(blocks);
+        let exit =
+            unsafe { PutLogTopicIndex(self.db_ptr, (&mut topic).into(), (&mut buf[..]).into()) };
+        exit.ok_or_fmt("PutLogTopicIndex")?;
+        Ok(())
+    }
+
+    /// Writes the AccountHistory index of `who` as a single shard.
+    pub fn put_account_history(&mut self, mut who: Address, blocks: &[u64]) -> Result<()> {
+        let mut buf = encode_roaring64(blocks);
+        let exit =
+            unsafe { PutAccountHistory(self.db_ptr, (&mut who).into(), (&mut buf[..]).into()) };
+        exit.ok_or_fmt("PutAccountHistory")?;
+        Ok(())
+    }
+
+    /// Writes the StorageHistory index of `slot` of `who` as a single shard.
+    pub fn put_storage_history(
+        &mut self,
+        mut who: Address,
+        mut slot: H256,
+        blocks: &[u64],
+    ) -> Result<()> {
+        let mut buf = encode_roaring64(blocks);
+        let exit = unsafe {
+            PutStorageHistory(
+                self.db_ptr,
+                (&mut who).into(),
+                (&mut slot).into(),
+                (&mut buf[..]).into(),
+            )
+        };
+        exit.ok_or_fmt("PutStorageHistory")?;
+        Ok(())
+    }
+
     pub fn put_account(&mut self, mut who: Address, acct: Account) -> Result<()> {
         let rlp_acct: RlpAccount = acct.into();
-        let mut buf = vec![];
-        rlp_acct.encode(&mut buf);
+        let mut buf = RLP_BUFS.get();
+        rlp_acct.encode(&mut *buf);
 
         let exit = unsafe {
             PutAccount(
@@ -75,8 +283,8 @@ impl Writer {
     }
 
     pub fn put_header(&mut self, header: BlockHeader) -> Result<()> {
-        let mut buf = vec![];
-        header.encode(&mut buf);
+        let mut buf = RLP_BUFS.get();
+        header.encode(&mut *buf);
 
         let exit = unsafe { PutHeader(self.db_ptr, GoRlp((&mut buf[..]).into())) };
         exit.ok_or_fmt("PutAccount")?;
@@ -96,6 +304,60 @@ impl Writer {
         Ok(())
     }
 
+    /// Deletes the account of `who` as SELFDESTRUCT does, recording
+    /// `incarnation` as its last. Its storage is left in place.
+    pub fn delete_account(&mut self, mut who: Address, incarnation: u64) -> Result<()> {
+        let exit = unsafe { DeleteAccount(self.db_ptr, (&mut who).into(), incarnation) };
+        exit.ok_or_fmt("DeleteAccount")?;
+        Ok(())
+    }
+
+    /// Writes `code` to the Code table, and its hash to PlainContractCode as
+    /// the code of `who` at `incarnation`.
+    pub fn put_code(&mut self, mut who: Address, incarnation: u64, code: &[u8]) -> Result<()> {
+        let mut buf = code.to_vec();
+        let exit = unsafe {
+            PutCode(
+                self.db_ptr,
+                (&mut who).into(),
+                incarnation,
+                (&mut buf[..]).into(),
+            )
+        };
+        exit.ok_or_fmt("PutCode")?;
+        Ok(())
+    }
+
+    /// Writes `acct` to HashedAccount under the keccak of `who`.
+    pub fn put_hashed_account(&mut self, mut who: Address, acct: Account) -> Result<()> {
+        let mut buf = acct.encode();
+        let exit =
+            unsafe { PutHashedAccount(self.db_ptr, (&mut who).into(), (&mut buf[..]).into()) };
+        exit.ok_or_fmt("PutHashedAccount")?;
+        Ok(())
+    }
+
+    /// Writes a slot to HashedStorage, hashing `who` and `key`.
+    pub fn put_hashed_storage(
+        &mut self,
+        mut who: Address,
+        incarnation: u64,
+        mut key: H256,
+        mut val: H256,
+    ) -> Result<()> {
+        let exit = unsafe {
+            PutHashedStorage(
+                self.db_ptr,
+                (&mut who).into(),
+                incarnation,
+                (&mut key).into(),
+                (&mut val).into(),
+            )
+        };
+        exit.ok_or_fmt("PutHashedStorage")?;
+        Ok(())
+    }
+
     //TODO: encoding is broken
     #[allow(unused)]
     pub fn put_raw_transactions<T: IntoIterator<Item = Transaction>>(
@@ -110,6 +372,22 @@ impl Writer {
         Ok(())
     }
 
+    /// Writes already encoded txs as they are, without decoding them.
+    pub fn put_encoded_transactions(&mut self, txs: &[Vec<u8>], base_id: u64) -> Result<()> {
+        if txs.is_empty() {
+            return Ok(());
+        }
+        let mut bufs = txs
+            .iter()
+            .map(|tx| BytesMut::from(&tx[..]))
+            .collect::<Vec<_>>();
+        let mut go_slices = bufs.iter_mut().map(GoSlice::from).collect::<Vec<_>>();
+        let exit =
+            unsafe { PutRawTransactions(self.db_ptr, GoSlice::from(&mut go_slices[..]), base_id) };
+        exit.ok_or_fmt("PutRawTransactions")?;
+        Ok(())
+    }
+
     pub fn put_transactions<T: IntoIterator<Item = ak_models::MessageWithSignature>>(
         &mut self,
         txs: T,
@@ -169,8 +447,8 @@ impl Writer {
         num: ak_models::BlockNumber,
         body: BodyForStorage,
     ) -> Result<()> {
-        let mut buf = vec![];
-        body.encode(&mut buf);
+        let mut buf = RLP_BUFS.get();
+        body.encode(&mut *buf);
 
         let exit = unsafe {
             PutBodyForStorage(
@@ -0,0 +1,224 @@
+//! Golden-file regression tests against recorded slices of real chaindata.
+//!
+//! A fixture is a directory holding `chaindata/`, an mdbx environment copied
+//! from an Erigon node, and `golden.json`, a list of JSON-RPC calls recorded
+//! from a node synced over the same blocks:
+//!
+//! ```json
+//! [{
+//!     "method": "eth_getBlockByNumber",
+//!     "params": ["0xe4e1c0", false],
+//!     "result": { ... },
+//!     "ignore": ["totalDifficulty"]
+//! }]
+//! ```
+//!
+//! Each call is replayed against a `Client` on the fixture, and its output must
+//! serialize to exactly the recorded result, apart from the top-level fields in
+//! `ignore`. Fixtures are read from the directories in `GOLDEN_FIXTURES_DIR`, or
+//! from `tests/fixtures` if it is unset. None are checked in, as chaindata is
+//! too large for the repo, so the test is ignored by default and fails when
+//! it finds no fixtures:
+//!
+//! ```text
+//! GOLDEN_FIXTURES_DIR=/data/golden cargo test golden -- --ignored
+//! ```
+
+use anyhow::{format_err, Result};
+use ethers::types::{BlockId, BlockNumber, H256};
+use mdbx::EnvironmentKind;
+use serde::Deserialize;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+use crate::client::{Client, Either};
+
+const FIXTURES_DIR_ENV_LABEL: &str = "GOLDEN_FIXTURES_DIR";
+const DEFAULT_FIXTURES_DIR: &str = "tests/fixtures";
+const GOLDEN_FILE: &str = "golden.json";
+const CHAINDATA_DIR: &str = "chaindata";
+
+/// A recorded JSON-RPC call and its result.
+#[derive(Debug, Clone, Deserialize)]
+pub struct GoldenCase {
+    pub method: String,
+    #[serde(default)]
+    pub params: Vec<Value>,
+    pub result: Value,
+    /// Top-level result fields which the db can't reproduce, e.g. because they
+    /// depend on blocks outside of the fixture.
+    #[serde(default)]
+    pub ignore: Vec<String>,
+}
+
+/// A fixture directory with its chaindata and recorded calls.
+#[derive(Debug, Clone)]
+pub struct Fixture {
+    pub dir: PathBuf,
+    pub cases: Vec<GoldenCase>,
+}
+
+impl Fixture {
+    pub fn load<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        let golden = std::fs::read(dir.join(GOLDEN_FILE))?;
+        let cases: Vec<GoldenCase> = serde_json::from_slice(&golden)
+            .map_err(|e| format_err!("bad {} in {}: {}", GOLDEN_FILE, dir.display(), e))?;
+        if cases.is_empty() {
+            return Err(format_err!("no calls in {}", dir.display()));
+        }
+        Ok(Self { dir, cases })
+    }
+
+    pub fn chaindata(&self) -> PathBuf {
+        self.dir.join(CHAINDATA_DIR)
+    }
+
+    /// Returns every fixture in the fixtures directory. Fails if the
+    /// directory is missing or holds no fixtures.
+    pub fn load_all() -> Result<Vec<Self>> {
+        let root = match std::env::var(FIXTURES_DIR_ENV_LABEL) {
+            Ok(dir) => PathBuf::from(dir),
+            Err(_) => Path::new(env!("CARGO_MANIFEST_DIR")).join(DEFAULT_FIXTURES_DIR),
+        };
+        Self::load_dir(root)
+    }
+
+    /// Returns every fixture in `root`, failing if there are none.
+    pub fn load_dir<P: AsRef<Path>>(root: P) -> Result<Vec<Self>> {
+        let root = root.as_ref();
+        let entries = std::fs::read_dir(root).map_err(|e| {
+            format_err!(
+                "Err: {}: {}\nExport {} to run the golden tests.",
+                root.display(),
+                e,
+                FIXTURES_DIR_ENV_LABEL
+            )
+        })?;
+        let mut fixtures = vec![];
+        for entry in entries {
+            let path = entry?.path();
+            if path.join(GOLDEN_FILE).exists() {
+                fixtures.push(Self::load(path)?);
+            }
+        }
+        if fixtures.is_empty() {
+            return Err(format_err!("no golden fixtures in {}", root.display()));
+        }
+        fixtures.sort_by(|a, b| a.dir.cmp(&b.dir));
+        Ok(fixtures)
+    }
+}
+
+/// Replays `case` against `db`, returning the serialized result. Fails if the
+/// client has no equivalent of the method or can't produce its result, so a
+/// fixture can't pass by recording calls which are never compared.
+pub fn replay<E: EnvironmentKind>(db: &Client<E>, case: &GoldenCase) -> Result<Value> {
+    let param = |i: usize| {
+        case.params
+            .get(i)
+            .cloned()
+            .ok_or_else(|| format_err!("{} is missing param {}", case.method, i))
+    };
+    let block_id = |id: Value| -> Result<BlockId> {
+        Ok(match case.method.as_str() {
+            "eth_getBlockByHash" => serde_json::from_value::<H256>(id)?.into(),
+            _ => serde_json::from_value::<BlockNumber>(id)?.into(),
+        })
+    };
+    let res = match case.method.as_str() {
+        "eth_getBlockByNumber" | "eth_getBlockByHash" => {
+            let id = block_id(param(0)?)?;
+            if serde_json::from_value(param(1)?)? {
+                serde_json::to_value(db.get_block_with_txs(id)?)?
+            } else {
+                serde_json::to_value(db.get_block(id)?)?
+            }
+        }
+        "eth_getTransactionByHash" => {
+            let hash: H256 = serde_json::from_value(param(0)?)?;
+            serde_json::to_value(db.get_transaction(hash)?)?
+        }
+        "eth_getBlockReceipts" => {
+            let num: BlockNumber = serde_json::from_value(param(0)?)?;
+            match db.get_block_receipts(num)? {
+                Either::Right(receipts) => serde_json::to_value(receipts)?,
+                Either::Left(_) => return Err(format_err!("receipts of {:?} aren't stored", num)),
+            }
+        }
+        "eth_getTransactionReceipt" => {
+            let hash: H256 = serde_json::from_value(param(0)?)?;
+            match db.get_transaction_receipt(hash)? {
+                Either::Right(receipt) => serde_json::to_value(receipt)?,
+                Either::Left(_) => return Err(format_err!("receipt of {:?} isn't stored", hash)),
+            }
+        }
+        method => return Err(format_err!("unsupported golden call {}", method)),
+    };
+    Ok(res)
+}
+
+/// Removes the `fields` from a result object.
+pub fn strip(value: &mut Value, fields: &[String]) {
+    if let Value::Object(obj) = value {
+        for field in fields {
+            obj.remove(field);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[ignore]
+    fn test_golden_fixtures() -> Result<()> {
+        for fixture in Fixture::load_all()? {
+            let db = Client::<mdbx::NoWriteMap>::open_new(fixture.chaindata())?;
+            for case in &fixture.cases {
+                let mut res = replay(&db, case).map_err(|e| {
+                    format_err!(
+                        "{} {:?} in {}: {}",
+                        case.method,
+                        case.params,
+                        fixture.dir.display(),
+                        e
+                    )
+                })?;
+                let mut expected = case.result.clone();
+                strip(&mut res, &case.ignore);
+                strip(&mut expected, &case.ignore);
+                assert_eq!(
+                    res,
+                    expected,
+                    "{} {:?} in {}",
+                    case.method,
+                    case.params,
+                    fixture.dir.display()
+                );
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_fixtures() -> Result<()> {
+        let dir = tempfile::tempdir_in(crate::test::TMP_DIR.clone())?;
+        assert!(Fixture::load_dir(dir.path().join("missing")).is_err());
+        assert!(Fixture::load_dir(dir.path()).is_err());
+        // a fixture without calls
+        let fixture = dir.path().join("empty");
+        std::fs::create_dir(&fixture)?;
+        std::fs::write(fixture.join(GOLDEN_FILE), "[]")?;
+        assert!(Fixture::load_dir(dir.path()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_strip() {
+        let mut v = serde_json::json!({"a": 1, "b": 2});
+        strip(&mut v, &["a".to_string()]);
+        assert_eq!(v, serde_json::json!({"b": 2}));
+    }
+}
@@ -2,8 +2,19 @@ use anyhow::{format_err, Result};
 use once_cell::sync::Lazy;
 use std::path::PathBuf;
 
+#[cfg(feature = "db")]
+pub mod chain;
+#[cfg(feature = "db")]
 pub mod ffi;
+#[cfg(feature = "db")]
+pub mod golden;
 pub mod rand;
+#[cfg(all(test, feature = "db"))]
+mod soak;
+#[cfg(test)]
+pub mod strategy;
+#[cfg(all(test, feature = "stress"))]
+mod stress;
 
 const TMP_DIR_ENV_LABEL: &str = "CHAINDATA_TMP_DIR";
 const LINK_TEST_BIN: &str = "LINK_TEST_BIN";
@@ -24,3 +35,12 @@ fn tmp_dir() -> Result<PathBuf> {
     })?;
     Ok(PathBuf::from(path))
 }
+
+/// Reads a numeric setting from the environment.
+#[cfg(test)]
+pub(crate) fn env_or(label: &str, default: u64) -> u64 {
+    std::env::var(label)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
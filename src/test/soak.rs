@@ -0,0 +1,251 @@
+//! A soak test which issues the full middleware surface against a live node's
+//! datadir for hours, reporting a taxonomy of the errors seen.
+//!
+//! ```text
+//! SOAK_DATADIR=/data/erigon cargo test soak -- --ignored --nocapture
+//! ```
+//!
+//! If `SOAK_RPC_URL` is set, the middleware wraps a provider for it and falls
+//! back to it on db errors; otherwise every call is served by the db alone.
+//! `SOAK_SECS`, `SOAK_REPORT_SECS` and `SOAK_TASKS` tune the run.
+
+use anyhow::{format_err, Result};
+use ethers::{
+    providers::{FilterKind, Http, Middleware, Provider},
+    types::{BlockId, BlockNumber, Filter, Log, H256, U256},
+};
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use super::env_or;
+use crate::{
+    builder::{Route, Routing},
+    client::Client,
+    middleware::{DbMiddleware, DbMiddlewareError},
+};
+
+const DATADIR_ENV_LABEL: &str = "SOAK_DATADIR";
+const RPC_ENV_LABEL: &str = "SOAK_RPC_URL";
+const SECS_ENV_LABEL: &str = "SOAK_SECS";
+const REPORT_SECS_ENV_LABEL: &str = "SOAK_REPORT_SECS";
+const TASKS_ENV_LABEL: &str = "SOAK_TASKS";
+
+/// Blocks are picked from this many blocks below the head, where a syncing
+/// node is still writing.
+const RECENT_BLOCKS: u64 = 1024;
+const TXS_PER_BLOCK: usize = 4;
+
+type Db<M> = DbMiddleware<M, mdbx::NoWriteMap>;
+
+/// Counts of calls and errors per method.
+#[derive(Debug, Default)]
+struct Stats {
+    calls: Mutex<BTreeMap<&'static str, (u64, u64)>>,
+    errors: Mutex<BTreeMap<(&'static str, String), u64>>,
+}
+
+impl Stats {
+    fn record(&self, method: &'static str, err: Option<String>) {
+        let mut calls = self.calls.lock().unwrap();
+        let (total, failed) = calls.entry(method).or_default();
+        *total += 1;
+        if let Some(kind) = err {
+            *failed += 1;
+            *self
+                .errors
+                .lock()
+                .unwrap()
+                .entry((method, kind))
+                .or_default() += 1;
+        }
+    }
+
+    fn report(&self, elapsed: Duration) {
+        eprintln!("--- soak report after {}s ---", elapsed.as_secs());
+        for (method, (total, failed)) in self.calls.lock().unwrap().iter() {
+            eprintln!("{:<32} {:>10} calls {:>8} errors", method, total, failed);
+        }
+        for ((method, kind), n) in self.errors.lock().unwrap().iter() {
+            eprintln!("{:<32} {:>10} x {}", method, n, kind);
+        }
+    }
+}
+
+/// Reduces an error to its kind, dropping the hashes and numbers which make
+/// each message unique.
+fn error_kind<M: Middleware>(e: &DbMiddlewareError<M>) -> String {
+    let (source, msg) = match e {
+        DbMiddlewareError::MiddlewareError(e) => ("inner", e.to_string()),
+        DbMiddlewareError::Anyhow(e) => ("db", e.to_string()),
+        DbMiddlewareError::BadError => ("db", e.to_string()),
+    };
+    let msg = msg
+        .split_whitespace()
+        .filter(|w| !w.chars().any(|c| c.is_ascii_digit()))
+        .collect::<Vec<_>>()
+        .join(" ");
+    format!("{}: {}", source, msg)
+}
+
+/// Awaits a middleware call, recording its outcome under `$method`.
+macro_rules! call {
+    ($stats:expr, $method:literal, $fut:expr) => {{
+        let res = $fut.await;
+        $stats.record($method, res.as_ref().err().map(error_kind));
+        res.ok()
+    }};
+}
+
+/// Issues every middleware method once, against a random recent block.
+async fn exercise<M: Middleware>(mw: &Db<M>, stats: &Stats) {
+    let head = match call!(stats, "eth_blockNumber", mw.get_block_number()) {
+        Some(head) => head.as_u64(),
+        None => return,
+    };
+    let num = head.saturating_sub(rand::random::<u64>() % RECENT_BLOCKS);
+    let id = BlockId::Number(BlockNumber::Number(num.into()));
+
+    if let Some(Some(block)) = call!(stats, "eth_getBlockByNumber", mw.get_block(id)) {
+        if let Some(hash) = block.hash {
+            call!(
+                stats,
+                "eth_getUncleCountByBlockHash",
+                mw.get_uncle_count(hash)
+            );
+            if !block.uncles.is_empty() {
+                call!(
+                    stats,
+                    "eth_getUncleByBlockHashAndIndex",
+                    mw.get_uncle(hash, 0.into())
+                );
+            }
+        }
+    }
+
+    let block = call!(
+        stats,
+        "eth_getBlockByNumber(full)",
+        mw.get_block_with_txs(id)
+    );
+    for tx in block
+        .flatten()
+        .iter()
+        .flat_map(|b| &b.transactions)
+        .take(TXS_PER_BLOCK)
+    {
+        call!(
+            stats,
+            "eth_getTransactionByHash",
+            mw.get_transaction(tx.hash)
+        );
+        call!(stats, "eth_getBalance", mw.get_balance(tx.from, None));
+        call!(
+            stats,
+            "eth_getBalance(historical)",
+            mw.get_balance(tx.from, Some(id))
+        );
+        call!(
+            stats,
+            "eth_getTransactionCount",
+            mw.get_transaction_count(tx.from, None)
+        );
+        if let Some(to) = tx.to {
+            call!(stats, "eth_getCode", mw.get_code(to, None));
+            call!(
+                stats,
+                "eth_getStorageAt",
+                mw.get_storage_at(to, H256::zero(), None)
+            );
+        }
+    }
+
+    let filter = Filter::new().from_block(num).to_block(num);
+    call!(stats, "eth_getLogs", mw.get_logs(&filter));
+    if let Some(id) = call!(
+        stats,
+        "eth_newFilter",
+        mw.new_filter(FilterKind::Logs(&filter))
+    ) {
+        call!(
+            stats,
+            "eth_getFilterChanges",
+            mw.get_filter_changes::<U256, Log>(id)
+        );
+        call!(stats, "eth_uninstallFilter", mw.uninstall_filter(id));
+    }
+    call!(stats, "eth_getBlockReceipts", mw.get_block_receipts(num));
+}
+
+async fn soak<M: Middleware + 'static>(mw: Db<M>) -> Result<()> {
+    let run = Duration::from_secs(env_or(SECS_ENV_LABEL, 4 * 60 * 60));
+    let report = Duration::from_secs(env_or(REPORT_SECS_ENV_LABEL, 60));
+    let tasks = env_or(TASKS_ENV_LABEL, 8);
+
+    let mw = Arc::new(mw);
+    let stats = Arc::new(Stats::default());
+    let start = Instant::now();
+    let deadline = start + run;
+
+    let workers = (0..tasks)
+        .map(|_| {
+            let (mw, stats) = (Arc::clone(&mw), Arc::clone(&stats));
+            tokio::spawn(async move {
+                while Instant::now() < deadline {
+                    exercise(&mw, &stats).await;
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let mut ticks = tokio::time::interval(report);
+    // the first tick completes immediately
+    ticks.tick().await;
+    while Instant::now() + report < deadline {
+        ticks.tick().await;
+        stats.report(start.elapsed());
+    }
+    for worker in workers {
+        worker.await?;
+    }
+    stats.report(start.elapsed());
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore]
+async fn soak_live_node() -> Result<()> {
+    let datadir = std::env::var(DATADIR_ENV_LABEL).map_err(|e| {
+        format_err!(
+            "Err: {}\nExport {} to run the soak test.",
+            e,
+            DATADIR_ENV_LABEL
+        )
+    })?;
+    let builder = Client::<mdbx::NoWriteMap>::builder().datadir(datadir);
+
+    match std::env::var(RPC_ENV_LABEL) {
+        Ok(url) => {
+            let inner = Provider::<Http>::try_from(url.as_str())?;
+            let db = builder
+                .routing(Routing {
+                    historical_state: Route::Db,
+                    fallback_on_error: true,
+                })
+                .build()?;
+            soak(DbMiddleware::new(inner, Arc::new(db))).await
+        }
+        Err(_) => {
+            let (inner, _) = Provider::mocked();
+            let db = builder
+                .routing(Routing {
+                    historical_state: Route::Db,
+                    fallback_on_error: false,
+                })
+                .build()?;
+            soak(DbMiddleware::new(inner, Arc::new(db))).await
+        }
+    }
+}
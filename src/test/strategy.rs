@@ -0,0 +1,17 @@
+//! Proptest strategies for the primitive types stored in the db.
+
+use ethers::types::{Address, H256, U256};
+use proptest::prelude::*;
+
+pub fn address() -> impl Strategy<Value = Address> {
+    any::<[u8; 20]>().prop_map(Address::from)
+}
+
+pub fn h256() -> impl Strategy<Value = H256> {
+    any::<[u8; 32]>().prop_map(H256::from)
+}
+
+/// A U256 of any byte length, so that small values are as likely as large ones.
+pub fn u256() -> impl Strategy<Value = U256> {
+    (any::<[u8; 32]>(), 0..=32usize).prop_map(|(b, len)| U256::from_big_endian(&b[32 - len..]))
+}
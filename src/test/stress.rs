@@ -0,0 +1,131 @@
+//! A long-running stress test of one `Client` shared by many reader threads
+//! while blocks are appended to its db. Run it with `--features stress`, and
+//! tune it with `STRESS_SECS` and `STRESS_THREADS`.
+
+use akula::models::{BlockHeader, BlockNumber, BodyForStorage, TxIndex, H256};
+use anyhow::{format_err, Result};
+use rand::thread_rng;
+use std::{
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+use super::{env_or, ffi::writer::Writer, rand::Rand, TMP_DIR};
+use crate::{
+    client::Client,
+    types::{BlockNum, HeaderKey},
+};
+
+const SECS_ENV_LABEL: &str = "STRESS_SECS";
+const THREADS_ENV_LABEL: &str = "STRESS_THREADS";
+/// Readers are restarted this many times over the run, so that reader slots
+/// leaked by exited threads would exhaust the reader table.
+const WAVES: u32 = 16;
+
+type Db = Client<mdbx::NoWriteMap>;
+
+/// Appends block `num` on top of `parent`. Each write is its own transaction,
+/// and the head hash is written last, so readers never see a head without its
+/// header, canonical hash and body.
+fn append(w: &mut Writer, num: u64, parent: H256) -> Result<H256> {
+    let mut header = BlockHeader::rand(&mut thread_rng());
+    header.number = BlockNumber(num);
+    header.parent_hash = parent;
+    let hash = header.hash();
+
+    w.put_header(header)?;
+    w.put_canonical_hash(hash, BlockNumber(num))?;
+    let body = BodyForStorage {
+        base_tx_id: TxIndex(0),
+        tx_amount: 2,
+        uncles: vec![],
+    };
+    w.put_body_for_storage(hash, BlockNumber(num), body)?;
+    w.put_head_header_hash(hash)?;
+    Ok(hash)
+}
+
+/// Reads the head until `done`, checking that it never moves backwards and
+/// that each transaction sees all of the head's data.
+fn read_loop(db: &Db, done: &AtomicBool, reads: &AtomicU64) -> Result<()> {
+    let mut last = 0;
+    while !done.load(Ordering::Relaxed) {
+        let mut dbtx = db.reader()?;
+        let head = dbtx.read_head_block_number()?;
+        if *head < last {
+            return Err(format_err!("head went backwards: {} < {}", *head, last));
+        }
+        last = *head;
+
+        let hash = dbtx.read_canonical_hash(head)?;
+        let key = HeaderKey::new(head, hash);
+        let header = dbtx.read_header(key)?;
+        if header.number.0 != *head || header.hash() != hash {
+            return Err(format_err!("torn header read at block {}", *head));
+        }
+        if *head > 1 && dbtx.read_canonical_hash(BlockNum(*head - 1))? != header.parent_hash {
+            return Err(format_err!("torn parent read at block {}", *head));
+        }
+        dbtx.read_body_for_storage(key)?;
+        drop(dbtx);
+
+        // and through the client's own short-lived transactions
+        let num = db.get_block_number()?.as_u64();
+        if num < last {
+            return Err(format_err!("head went backwards: {} < {}", num, last));
+        }
+        last = num;
+        reads.fetch_add(1, Ordering::Relaxed);
+    }
+    Ok(())
+}
+
+#[test]
+fn stress_concurrent_reads() -> Result<()> {
+    let secs = env_or(SECS_ENV_LABEL, 60);
+    let threads = env_or(THREADS_ENV_LABEL, 32);
+    let wave_len = Duration::from_secs(secs) / WAVES;
+
+    let mut w = Writer::open(TMP_DIR.clone())?;
+    let mut num = 1;
+    let mut parent = append(&mut w, num, H256::zero())?;
+    let db = Db::open_new(w.path().to_path_buf())?;
+    let reads = AtomicU64::new(0);
+
+    for _ in 0..WAVES {
+        let done = AtomicBool::new(false);
+        thread::scope(|scope| {
+            let handles = (0..threads)
+                .map(|_| scope.spawn(|| read_loop(&db, &done, &reads)))
+                .collect::<Vec<_>>();
+
+            let wave_end = Instant::now() + wave_len;
+            let mut written = Ok(());
+            while written.is_ok() && Instant::now() < wave_end {
+                num += 1;
+                written = append(&mut w, num, parent).map(|hash| parent = hash);
+            }
+            // stop the readers even if the writer failed
+            done.store(true, Ordering::Relaxed);
+
+            handles.into_iter().try_for_each(|handle| {
+                handle
+                    .join()
+                    .map_err(|_| format_err!("reader thread panicked"))?
+            })?;
+            written
+        })?;
+    }
+    w.close()?;
+
+    assert_eq!(*db.reader()?.read_head_block_number()?, num);
+    assert!(reads.load(Ordering::Relaxed) > 0);
+    eprintln!(
+        "{} reads by {} threads over {} blocks",
+        reads.load(Ordering::Relaxed),
+        threads * u64::from(WAVES),
+        num
+    );
+    Ok(())
+}
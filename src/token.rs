@@ -0,0 +1,146 @@
+use anyhow::Result;
+use ethers::{
+    contract::LogMeta,
+    types::{Address, FilterBlockOption, Log, ValueOrArray, H256, U256},
+    utils::keccak256,
+};
+use mdbx::EnvironmentKind;
+use once_cell::sync::Lazy;
+
+use crate::client::Client;
+
+pub static TRANSFER_TOPIC: Lazy<H256> =
+    Lazy::new(|| keccak256("Transfer(address,address,uint256)").into());
+pub static APPROVAL_TOPIC: Lazy<H256> =
+    Lazy::new(|| keccak256("Approval(address,address,uint256)").into());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenEventKind {
+    Transfer,
+    Approval,
+}
+
+impl TokenEventKind {
+    pub fn topic(&self) -> H256 {
+        match self {
+            Self::Transfer => *TRANSFER_TOPIC,
+            Self::Approval => *APPROVAL_TOPIC,
+        }
+    }
+}
+
+/// ERC-20 and ERC-721 share event signatures and differ only in whether the
+/// last argument is indexed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenValue {
+    /// An ERC-20 amount, from the log data.
+    Amount(U256),
+    /// An ERC-721 token id, from the fourth topic.
+    Id(U256),
+}
+
+/// A Transfer or Approval event emitted by a token contract. For approvals,
+/// `from` is the owner and `to` the approved spender.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenEvent {
+    pub kind: TokenEventKind,
+    pub token: Address,
+    pub from: Address,
+    pub to: Address,
+    pub value: TokenValue,
+    pub meta: LogMeta,
+}
+
+impl TokenEvent {
+    /// Parses a standard Transfer or Approval log. Returns `None` for any other log,
+    /// including logs with the right signature but a non-standard layout.
+    pub fn from_log(log: &Log) -> Option<Self> {
+        let kind = match log.topics.first()? {
+            t if *t == *TRANSFER_TOPIC => TokenEventKind::Transfer,
+            t if *t == *APPROVAL_TOPIC => TokenEventKind::Approval,
+            _ => return None,
+        };
+        let value = match (log.topics.len(), log.data.len()) {
+            (3, 32) => TokenValue::Amount(U256::from_big_endian(&log.data)),
+            (4, 0) => TokenValue::Id(U256::from_big_endian(log.topics[3].as_bytes())),
+            _ => return None,
+        };
+        Some(Self {
+            kind,
+            token: log.address,
+            from: Address::from(log.topics[1]),
+            to: Address::from(log.topics[2]),
+            value,
+            meta: LogMeta::from(log),
+        })
+    }
+}
+
+impl<E: EnvironmentKind> Client<E> {
+    /// Returns an iterator over the token events of the given kinds emitted in
+    /// `range`, optionally restricted to a single token contract.
+    pub fn stream_token_events<R: Into<FilterBlockOption>>(
+        &self,
+        range: R,
+        token: Option<Address>,
+        kinds: &[TokenEventKind],
+    ) -> Result<impl Iterator<Item = Result<TokenEvent>> + '_> {
+        let topics = kinds.iter().map(|k| Some(k.topic())).collect();
+        let mut filter = ethers::types::Filter::new()
+            .select(range)
+            .topic0(ValueOrArray::Array(topics));
+        if let Some(token) = token {
+            filter = filter.address(token);
+        }
+        Ok(self.stream_logs(&filter)?.filter_map(|log| match log {
+            Ok(log) => TokenEvent::from_log(&log).map(Ok),
+            Err(e) => Some(Err(e)),
+        }))
+    }
+
+    /// Returns an iterator over the token transfers emitted in `range`.
+    pub fn stream_token_transfers<R: Into<FilterBlockOption>>(
+        &self,
+        range: R,
+        token: Option<Address>,
+    ) -> Result<impl Iterator<Item = Result<TokenEvent>> + '_> {
+        self.stream_token_events(range, token, &[TokenEventKind::Transfer])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log(topics: Vec<H256>, data: Vec<u8>) -> Log {
+        Log {
+            address: Address::repeat_byte(0xaa),
+            topics,
+            data: data.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_from_log() {
+        let from = H256::from(Address::repeat_byte(1));
+        let to = H256::from(Address::repeat_byte(2));
+        let amount = H256::from_low_u64_be(1000);
+
+        let erc20 = log(vec![*TRANSFER_TOPIC, from, to], amount.as_bytes().to_vec());
+        let ev = TokenEvent::from_log(&erc20).unwrap();
+        assert_eq!(ev.kind, TokenEventKind::Transfer);
+        assert_eq!(ev.token, Address::repeat_byte(0xaa));
+        assert_eq!(ev.from, Address::repeat_byte(1));
+        assert_eq!(ev.to, Address::repeat_byte(2));
+        assert_eq!(ev.value, TokenValue::Amount(1000.into()));
+
+        let erc721 = log(vec![*APPROVAL_TOPIC, from, to, amount], vec![]);
+        let ev = TokenEvent::from_log(&erc721).unwrap();
+        assert_eq!(ev.kind, TokenEventKind::Approval);
+        assert_eq!(ev.value, TokenValue::Id(1000.into()));
+
+        let other = log(vec![H256::zero(), from, to], amount.as_bytes().to_vec());
+        assert!(TokenEvent::from_log(&other).is_none());
+    }
+}
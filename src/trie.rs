@@ -0,0 +1,401 @@
+//! A minimal Merkle-Patricia trie over the ordered lists committed to in block
+//! headers, such as the transactions and receipts roots.
+
+use anyhow::{format_err, Result};
+use ethers::{
+    types::{TransactionReceipt, H256},
+    utils::keccak256,
+};
+use fastrlp::{Encodable, Header};
+
+/// The root of a trie with no entries.
+pub const EMPTY_ROOT: H256 = H256([
+    0x56, 0xe8, 0x1f, 0x17, 0x1b, 0xcc, 0x55, 0xa6, 0xff, 0x83, 0x45, 0xe6, 0x92, 0xc0, 0xf8, 0x6e,
+    0x5b, 0x48, 0xe0, 0x1b, 0x99, 0x6c, 0xad, 0xc0, 0x01, 0x62, 0x2f, 0xb5, 0xe3, 0x63, 0xb4, 0x21,
+]);
+
+/// Returns the root of the trie mapping `rlp(i)` to `items[i]`.
+pub fn ordered_trie_root<T: AsRef<[u8]>>(items: &[T]) -> H256 {
+    ordered_trie_proof(items, None).0
+}
+
+/// Returns the root of the trie mapping `rlp(i)` to `items[i]`, along with the
+/// proof of `items[index]`: the nodes on the path to it, beginning at the root.
+/// Nodes short enough to be embedded in their parent are not listed separately.
+pub fn ordered_trie_proof<T: AsRef<[u8]>>(
+    items: &[T],
+    index: Option<usize>,
+) -> (H256, Vec<Vec<u8>>) {
+    if items.is_empty() {
+        return (EMPTY_ROOT, vec![]);
+    }
+    let mut entries: Vec<_> = items
+        .iter()
+        .enumerate()
+        .map(|(i, item)| (to_nibbles(&index_key(i)), item.as_ref()))
+        .collect();
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let target = index.map(|i| to_nibbles(&index_key(i)));
+    let mut proof = vec![];
+    let root = encode_node(&entries, 0, target.as_deref(), &mut proof);
+    // nodes are pushed as the recursion unwinds, so the root comes last
+    proof.push(root.clone());
+    proof.reverse();
+    (keccak256(&root).into(), proof)
+}
+
+/// Returns the consensus encoding of `receipt`, as committed to in the
+/// receipts trie: the rlp of its status, or post-state root before Byzantium,
+/// cumulative gas used, logs bloom and logs, prefixed by the transaction type
+/// for typed transactions.
+pub fn receipt_encoding(receipt: &TransactionReceipt) -> Vec<u8> {
+    let outcome = match (receipt.root, receipt.status) {
+        (Some(root), _) => rlp_bytes(root.as_bytes()),
+        (None, status) => rlp_u64(status.unwrap_or_default().as_u64()),
+    };
+    let logs = receipt
+        .logs
+        .iter()
+        .map(|log| {
+            let topics = log
+                .topics
+                .iter()
+                .map(|t| rlp_bytes(t.as_bytes()))
+                .collect::<Vec<_>>();
+            rlp_list(&[
+                rlp_bytes(log.address.as_bytes()),
+                rlp_list(&topics),
+                rlp_bytes(&log.data),
+            ])
+        })
+        .collect::<Vec<_>>();
+    let body = rlp_list(&[
+        outcome,
+        rlp_u64(receipt.cumulative_gas_used.low_u64()),
+        rlp_bytes(receipt.logs_bloom.as_bytes()),
+        rlp_list(&logs),
+    ]);
+    match receipt.transaction_type.map(|t| t.as_u64()) {
+        Some(ty) if ty > 0 => [&[ty as u8][..], &body].concat(),
+        _ => body,
+    }
+}
+
+/// Checks `proof` against `root`, returning the value stored at `rlp(index)`,
+/// or `None` if the proof shows that there is no such entry.
+pub fn verify_ordered_proof(
+    root: H256,
+    index: usize,
+    proof: &[Vec<u8>],
+) -> Result<Option<Vec<u8>>> {
+    let key = to_nibbles(&index_key(index));
+    let mut path = &key[..];
+    let mut nodes = proof.iter();
+    let mut node = match nodes.next() {
+        Some(node) => node.clone(),
+        None if root == EMPTY_ROOT => return Ok(None),
+        None => return Err(format_err!("empty proof for a non-empty trie")),
+    };
+    if H256::from(keccak256(&node)) != root {
+        return Err(format_err!("proof root mismatch"));
+    }
+    loop {
+        let items = decode_list(&node)?;
+        let child = match items.len() {
+            17 => match path.split_first() {
+                Some((nibble, rest)) => {
+                    path = rest;
+                    items[*nibble as usize]
+                }
+                None => return Ok(non_empty(decode_bytes(items[16])?)),
+            },
+            2 => {
+                let (shared, is_leaf) = decode_hex_prefix(decode_bytes(items[0])?)?;
+                if is_leaf {
+                    if shared != path {
+                        return Ok(None);
+                    }
+                    return Ok(Some(decode_bytes(items[1])?.to_vec()));
+                }
+                if !path.starts_with(&shared) {
+                    return Ok(None);
+                }
+                path = &path[shared.len()..];
+                items[1]
+            }
+            n => return Err(format_err!("bad trie node with {} items", n)),
+        };
+        node = match Header::decode(&mut &*child).map_err(|e| format_err!("{}", e))? {
+            // an embedded node
+            h if h.list => child.to_vec(),
+            h if h.payload_length == 0 => return Ok(None),
+            _ => {
+                let hash = decode_bytes(child)?;
+                let next = nodes
+                    .next()
+                    .ok_or_else(|| format_err!("proof is missing nodes"))?;
+                if keccak256(next)[..] != *hash {
+                    return Err(format_err!("proof node hash mismatch"));
+                }
+                next.clone()
+            }
+        };
+    }
+}
+
+/// Encodes the node holding `entries`, which share their first `depth` nibbles.
+/// If the node is on the path to `target`, it and its hashed descendants on
+/// the path are appended to `proof`, deepest first.
+fn encode_node(
+    entries: &[(Vec<u8>, &[u8])],
+    depth: usize,
+    target: Option<&[u8]>,
+    proof: &mut Vec<Vec<u8>>,
+) -> Vec<u8> {
+    let on_path = |prefix: &[u8]| target.map_or(false, |t| t.starts_with(prefix));
+
+    if let [(key, value)] = entries {
+        rlp_list(&[
+            rlp_bytes(&hex_prefix(&key[depth..], true)),
+            rlp_bytes(value),
+        ])
+    } else {
+        let first = &entries[0].0;
+        let last = &entries[entries.len() - 1].0;
+        // entries are sorted, so the first and last share the common prefix of all
+        let shared = first[depth..]
+            .iter()
+            .zip(&last[depth..])
+            .take_while(|(a, b)| a == b)
+            .count();
+        if shared > 0 {
+            let child = encode_node(entries, depth + shared, target, proof);
+            let child_ref = node_ref(child, on_path(&first[..depth + shared]), proof);
+            rlp_list(&[
+                rlp_bytes(&hex_prefix(&first[depth..depth + shared], false)),
+                child_ref,
+            ])
+        } else {
+            let mut items = Vec::with_capacity(17);
+            let mut value = rlp_bytes(&[]);
+            let mut rest = entries;
+            if rest[0].0.len() == depth {
+                value = rlp_bytes(rest[0].1);
+                rest = &rest[1..];
+            }
+            for nibble in 0..16u8 {
+                let n = rest.iter().take_while(|(k, _)| k[depth] == nibble).count();
+                let (group, tail) = rest.split_at(n);
+                rest = tail;
+                if group.is_empty() {
+                    items.push(rlp_bytes(&[]));
+                    continue;
+                }
+                let child = encode_node(group, depth + 1, target, proof);
+                items.push(node_ref(child, on_path(&group[0].0[..depth + 1]), proof));
+            }
+            items.push(value);
+            rlp_list(&items)
+        }
+    }
+}
+
+/// Returns the reference to `node` held by its parent: the node itself if it is
+/// shorter than a hash, and otherwise its hash.
+fn node_ref(node: Vec<u8>, on_path: bool, proof: &mut Vec<Vec<u8>>) -> Vec<u8> {
+    if node.len() < 32 {
+        return node;
+    }
+    let hash = rlp_bytes(&keccak256(&node));
+    if on_path {
+        proof.push(node);
+    }
+    hash
+}
+
+fn index_key(i: usize) -> Vec<u8> {
+    let mut out = vec![];
+    (i as u64).encode(&mut out);
+    out
+}
+
+fn to_nibbles(key: &[u8]) -> Vec<u8> {
+    key.iter().flat_map(|b| [b >> 4, b & 0x0f]).collect()
+}
+
+/// Compact encoding of a nibble path, flagging leaves and odd lengths.
+fn hex_prefix(nibbles: &[u8], is_leaf: bool) -> Vec<u8> {
+    let flag = if is_leaf { 2 } else { 0 };
+    let mut out = Vec::with_capacity(nibbles.len() / 2 + 1);
+    let rest = if nibbles.len() % 2 == 1 {
+        out.push(((flag + 1) << 4) | nibbles[0]);
+        &nibbles[1..]
+    } else {
+        out.push(flag << 4);
+        nibbles
+    };
+    out.extend(rest.chunks(2).map(|c| (c[0] << 4) | c[1]));
+    out
+}
+
+fn decode_hex_prefix(enc: &[u8]) -> Result<(Vec<u8>, bool)> {
+    let first = *enc.first().ok_or_else(|| format_err!("empty trie path"))?;
+    let flag = first >> 4;
+    if flag > 3 {
+        return Err(format_err!("bad trie path flag: {}", flag));
+    }
+    let mut nibbles = to_nibbles(&enc[1..]);
+    if flag & 1 == 1 {
+        nibbles.insert(0, first & 0x0f);
+    }
+    Ok((nibbles, flag & 2 == 2))
+}
+
+fn rlp_bytes(b: &[u8]) -> Vec<u8> {
+    let mut out = vec![];
+    b.encode(&mut out);
+    out
+}
+
+fn rlp_u64(n: u64) -> Vec<u8> {
+    let mut out = vec![];
+    n.encode(&mut out);
+    out
+}
+
+/// Wraps already encoded items in a list.
+fn rlp_list(items: &[Vec<u8>]) -> Vec<u8> {
+    let payload_length = items.iter().map(Vec::len).sum();
+    let mut out = Vec::with_capacity(payload_length + 9);
+    Header {
+        list: true,
+        payload_length,
+    }
+    .encode(&mut out);
+    for item in items {
+        out.extend_from_slice(item);
+    }
+    out
+}
+
+/// Splits an encoded list into its encoded items.
+fn decode_list(mut enc: &[u8]) -> Result<Vec<&[u8]>> {
+    let h = Header::decode(&mut enc).map_err(|e| format_err!("{}", e))?;
+    if !h.list || enc.len() != h.payload_length {
+        return Err(format_err!("bad trie node"));
+    }
+    let mut items = vec![];
+    while !enc.is_empty() {
+        let start = enc;
+        let h = Header::decode(&mut enc).map_err(|e| format_err!("{}", e))?;
+        let len = start.len() - enc.len() + h.payload_length;
+        if start.len() < len {
+            return Err(format_err!("truncated trie node"));
+        }
+        items.push(&start[..len]);
+        enc = &start[len..];
+    }
+    Ok(items)
+}
+
+fn decode_bytes(mut enc: &[u8]) -> Result<&[u8]> {
+    let h = Header::decode(&mut enc).map_err(|e| format_err!("{}", e))?;
+    if h.list || enc.len() < h.payload_length {
+        return Err(format_err!("expected a byte string"));
+    }
+    Ok(&enc[..h.payload_length])
+}
+
+fn non_empty(b: &[u8]) -> Option<Vec<u8>> {
+    (!b.is_empty()).then(|| b.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_empty_root() {
+        assert_eq!(EMPTY_ROOT, H256::from(keccak256([0x80])));
+        assert_eq!(ordered_trie_root::<Vec<u8>>(&[]), EMPTY_ROOT);
+    }
+
+    #[test]
+    fn test_receipt_encoding() {
+        let mut receipt = TransactionReceipt {
+            status: Some(1.into()),
+            cumulative_gas_used: 21_000.into(),
+            ..Default::default()
+        };
+        let enc = receipt_encoding(&receipt);
+        // a 264 byte list of the status, gas, bloom and no logs
+        assert_eq!(enc[..7], [0xf9, 0x01, 0x08, 0x01, 0x82, 0x52, 0x08]);
+        assert_eq!(enc[7..10], [0xb9, 0x01, 0x00]);
+        assert_eq!(enc[10..], [&[0u8; 256][..], &[0xc0]].concat()[..]);
+
+        receipt.transaction_type = Some(2.into());
+        assert_eq!(receipt_encoding(&receipt), [&[2u8][..], &enc].concat());
+
+        // the receipts root of block 3 of the dev chain in ethers-core's block
+        // tests, holding this one successful legacy transfer
+        receipt.transaction_type = Some(0.into());
+        assert_eq!(
+            ordered_trie_root(&[receipt_encoding(&receipt)]),
+            "0x056b23fbba480696b65fe5a59b8f2148a1299103c4f57df839233af2cf4ca2d2"
+                .parse::<H256>()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_transactions_root() -> Result<()> {
+        // the one transaction of block 3 of the same dev chain, and the
+        // block's transactions root
+        let tx = hex::decode(
+            "f865028504a817c80083015f9094dca8ce283150ab773bcbeb8d38289bdb5661de1e808025a0\
+             19f2694eb9113656dbea0b925e2e7ceb43df83e601c4116aee9c0dd99130be88a073e5764b32\
+             4a4f7679d890a198ba658ba1c8cd36983ff9797e10b1b89dbb448e",
+        )?;
+        assert_eq!(
+            H256(keccak256(&tx)),
+            "0xc3c5f700243de37ae986082fd2af88d2a7c2752a0c0f7b9d6ac47c729d45e067".parse()?
+        );
+        assert_eq!(
+            ordered_trie_root(&[tx]),
+            "0x7270c1c4440180f2bd5215809ee3d545df042b67329499e1ab97eb759d31610d".parse()?
+        );
+
+        // a root computed by alloy-trie over enough entries for branches,
+        // extensions, and both short and long values
+        let items: Vec<Vec<u8>> = (0..300u32)
+            .map(|i| i.to_be_bytes().repeat(i as usize % 20 + 1))
+            .collect();
+        assert_eq!(
+            ordered_trie_root(&items),
+            "0x6c26c66c2ba88a69ba99519a2e0ad1ca87ba9e007b7773a0e1e76241dbbbc625".parse()?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_ordered_trie_proof() -> Result<()> {
+        // enough entries for branches, extensions, and both short and long values
+        let items: Vec<Vec<u8>> = (0..300u32)
+            .map(|i| i.to_be_bytes().repeat(i as usize % 20 + 1))
+            .collect();
+        let root = ordered_trie_root(&items);
+        for i in [0, 1, 15, 127, 128, 129, 299] {
+            let (proof_root, proof) = ordered_trie_proof(&items, Some(i));
+            assert_eq!(proof_root, root);
+            let value = verify_ordered_proof(root, i, &proof)?;
+            assert_eq!(value, Some(items[i].clone()));
+        }
+        // the proof of one entry doesn't prove an entry in another subtrie
+        let (_, proof) = ordered_trie_proof(&items, Some(5));
+        assert!(verify_ordered_proof(root, 200, &proof).is_err());
+        // nor does it prove anything under another root
+        assert!(verify_ordered_proof(EMPTY_ROOT, 5, &proof).is_err());
+        Ok(())
+    }
+}
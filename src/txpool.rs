@@ -0,0 +1,70 @@
+use anyhow::Result;
+use ethers::types::H256;
+use mdbx::EnvironmentKind;
+use std::{collections::HashSet, time::Duration};
+
+use crate::client::Client;
+
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+impl<E: EnvironmentKind> Client<E> {
+    /// Returns the hashes of the transactions in the txpool db. Erigon flushes
+    /// its pool to the db periodically, so this can lag the in-memory pool.
+    pub fn pending_transaction_hashes(&self) -> Result<Vec<H256>> {
+        self.txpool_reader()?.read_pool_transaction_hashes()
+    }
+
+    /// Returns a watcher which tails the txpool db for new transactions.
+    /// Transactions already in the pool are not reported.
+    pub fn watch_pending_transactions(&self) -> Result<PendingTxWatcher<E>> {
+        let seen = self.pending_transaction_hashes()?.into_iter().collect();
+        Ok(PendingTxWatcher {
+            client: self.clone(),
+            seen,
+            interval: DEFAULT_POLL_INTERVAL,
+        })
+    }
+}
+
+/// Polls the txpool db, yielding the hashes of transactions as they appear.
+///
+/// ethers' `FilterWatcher` polls the node directly, so this is the db-backed
+/// counterpart to `Middleware::watch_pending_transactions`.
+#[derive(Debug, Clone)]
+pub struct PendingTxWatcher<E: EnvironmentKind> {
+    client: Client<E>,
+    seen: HashSet<H256>,
+    interval: Duration,
+}
+
+impl<E: EnvironmentKind> PendingTxWatcher<E> {
+    /// Sets the delay between polls of the txpool db.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Returns the hashes of the transactions added to the pool since the
+    /// previous poll, without waiting.
+    pub fn poll(&mut self) -> Result<Vec<H256>> {
+        let pool = self.client.pending_transaction_hashes()?;
+        let new = pool
+            .iter()
+            .filter(|hash| !self.seen.contains(*hash))
+            .copied()
+            .collect();
+        self.seen = pool.into_iter().collect();
+        Ok(new)
+    }
+
+    /// Waits until new transactions appear in the pool and returns their hashes.
+    pub async fn next_batch(&mut self) -> Result<Vec<H256>> {
+        loop {
+            let new = self.poll()?;
+            if !new.is_empty() {
+                return Ok(new);
+            }
+            tokio::time::sleep(self.interval).await;
+        }
+    }
+}
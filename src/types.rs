@@ -0,0 +1,164 @@
+//! Typed keys for the values which identify blocks and transactions in the db.
+
+use akula::{kv::tables as ak_tables, models as ak_models};
+use ethers::types::{H256, U64};
+use std::{fmt, ops::Deref};
+
+use crate::models::{FixedKey, HEADER_KEY_LENGTH};
+
+/// A block number.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BlockNum(pub u64);
+
+impl Deref for BlockNum {
+    type Target = u64;
+
+    fn deref(&self) -> &u64 {
+        &self.0
+    }
+}
+
+impl fmt::Display for BlockNum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl From<u64> for BlockNum {
+    fn from(n: u64) -> Self {
+        Self(n)
+    }
+}
+
+impl From<U64> for BlockNum {
+    fn from(n: U64) -> Self {
+        Self(n.as_u64())
+    }
+}
+
+impl From<ak_models::BlockNumber> for BlockNum {
+    fn from(n: ak_models::BlockNumber) -> Self {
+        Self(n.0)
+    }
+}
+
+impl From<BlockNum> for ak_models::BlockNumber {
+    fn from(n: BlockNum) -> Self {
+        Self(n.0)
+    }
+}
+
+impl From<BlockNum> for U64 {
+    fn from(n: BlockNum) -> Self {
+        n.0.into()
+    }
+}
+
+/// The (block number, block hash) pair which identifies a block in the Header,
+/// BlockBody and TxSender tables.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct HeaderKey {
+    pub num: BlockNum,
+    pub hash: H256,
+}
+
+impl HeaderKey {
+    pub fn new<N: Into<BlockNum>>(num: N, hash: H256) -> Self {
+        Self {
+            num: num.into(),
+            hash,
+        }
+    }
+}
+
+impl From<ak_tables::HeaderKey> for HeaderKey {
+    fn from((num, hash): ak_tables::HeaderKey) -> Self {
+        Self::new(num, hash)
+    }
+}
+
+impl From<HeaderKey> for ak_tables::HeaderKey {
+    fn from(key: HeaderKey) -> Self {
+        (key.num.into(), key.hash)
+    }
+}
+
+/// The id of a transaction in the BlockTransaction table. Ids are assigned
+/// sequentially across blocks, and each stored body begins and ends with a
+/// system transaction. `Reader::read_body_for_storage` skips these, so the
+/// `base_tx_id` of a body it returns is the id of the block's first transaction.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TxId(pub u64);
+
+impl Deref for TxId {
+    type Target = u64;
+
+    fn deref(&self) -> &u64 {
+        &self.0
+    }
+}
+
+impl fmt::Display for TxId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl From<ak_models::TxIndex> for TxId {
+    fn from(id: ak_models::TxIndex) -> Self {
+        Self(id.0)
+    }
+}
+
+/// A table key which begins with a block number, so that the table is ordered
+/// by block number. See `Reader::seek_floor` and `Reader::seek_ceil`.
+pub trait BlockNumKey: Sized {
+    /// Returns the smallest key at height `num`.
+    fn first_at(num: BlockNum) -> Self;
+
+    /// Returns the block number the key begins with, if it begins with one.
+    fn block_num(&self) -> Option<BlockNum>;
+}
+
+impl BlockNumKey for BlockNum {
+    fn first_at(num: BlockNum) -> Self {
+        num
+    }
+
+    fn block_num(&self) -> Option<BlockNum> {
+        Some(*self)
+    }
+}
+
+impl BlockNumKey for HeaderKey {
+    fn first_at(num: BlockNum) -> Self {
+        Self::new(num, H256::zero())
+    }
+
+    fn block_num(&self) -> Option<BlockNum> {
+        Some(self.num)
+    }
+}
+
+impl BlockNumKey for FixedKey<HEADER_KEY_LENGTH> {
+    fn first_at(num: BlockNum) -> Self {
+        HeaderKey::first_at(num).into()
+    }
+
+    fn block_num(&self) -> Option<BlockNum> {
+        Some(HeaderKey::from(*self).num)
+    }
+}
+
+/// Raw keys are assumed to begin with a big endian block number. Keys too
+/// short to hold one have no block number.
+impl BlockNumKey for Vec<u8> {
+    fn first_at(num: BlockNum) -> Self {
+        num.to_be_bytes().to_vec()
+    }
+
+    fn block_num(&self) -> Option<BlockNum> {
+        let prefix = self.get(..8)?;
+        Some(BlockNum(u64::from_be_bytes(prefix.try_into().ok()?)))
+    }
+}
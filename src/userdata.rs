@@ -0,0 +1,408 @@
+//! A small key-value store for applications' own bookkeeping, such as sync
+//! cursors and labels, kept in a SQLite file beside the datadir behind the
+//! `sqlite` feature. The chaindata is opened read-only, so nothing is written
+//! to it; the file may be shared with a `Mirror`.
+//!
+//! The file can be exported with `Client::export_userdata` and its entries
+//! imported into the store of a client on another machine with
+//! `Client::import_userdata`. Exports are stamped with the genesis hash of the
+//! datadir and refused by clients on another chain. The tables of a `Mirror`
+//! sharing the file are exported and imported with its watermark, replacing
+//! the importing store's mirror, so its next refresh carries on from there.
+
+use anyhow::{format_err, Result};
+use ethers::types::{Address, H256};
+use mdbx::EnvironmentKind;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use crate::{client::Client, mirror, output::AddressLabeler, types::BlockNum};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS userdata (
+    key BLOB PRIMARY KEY,
+    value BLOB NOT NULL
+);
+";
+
+/// The table created by `SCHEMA`.
+const USERDATA_TABLE: &str = "userdata";
+
+/// The table of an exported file recording what it was exported from.
+const EXPORT_META: &str = "export_meta";
+
+/// The prefix of the keys holding address labels, followed by the address.
+const LABEL_PREFIX: &[u8] = b"label/";
+
+/// A handle to the userdata store, returned by `Client::userdata`. Clones
+/// share the same connection.
+#[derive(Debug, Clone)]
+pub struct UserData {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl UserData {
+    /// Opens or creates the store at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self
+            .conn
+            .lock()
+            .unwrap()
+            .query_row("SELECT value FROM userdata WHERE key = ?1", [key], |row| {
+                row.get(0)
+            })
+            .optional()?)
+    }
+
+    /// Sets `key` to `value`, replacing any previous value.
+    pub fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.conn.lock().unwrap().execute(
+            "INSERT OR REPLACE INTO userdata (key, value) VALUES (?1, ?2)",
+            params![key, value],
+        )?;
+        Ok(())
+    }
+
+    /// Removes `key`, returning whether it was set.
+    pub fn delete(&self, key: &[u8]) -> Result<bool> {
+        let n = self
+            .conn
+            .lock()
+            .unwrap()
+            .execute("DELETE FROM userdata WHERE key = ?1", [key])?;
+        Ok(n > 0)
+    }
+
+    /// Returns the entries whose keys begin with `prefix`, in key order.
+    pub fn scan(&self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let conn = self.conn.lock().unwrap();
+        let rows = match prefix_end(prefix) {
+            Some(end) => conn
+                .prepare(
+                    "SELECT key, value FROM userdata WHERE key >= ?1 AND key < ?2 ORDER BY key",
+                )?
+                .query_map(params![prefix, end], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<Result<Vec<_>, _>>()?,
+            None => conn
+                .prepare("SELECT key, value FROM userdata WHERE key >= ?1 ORDER BY key")?
+                .query_map([prefix], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<Result<Vec<_>, _>>()?,
+        };
+        Ok(rows)
+    }
+}
+
+impl UserData {
+    /// Writes a consistent copy of the whole file, including any tables shared
+    /// with a `Mirror`, to a new file at `path`, stamped with `genesis`.
+    pub fn export<P: AsRef<Path>>(&self, path: P, genesis: H256) -> Result<()> {
+        let path = sql_path(path.as_ref())?;
+        self.conn
+            .lock()
+            .unwrap()
+            .execute("VACUUM INTO ?1", [path])?;
+        let export = Connection::open(path)?;
+        export.execute_batch(&format!(
+            "CREATE TABLE {} (key TEXT PRIMARY KEY, value BLOB NOT NULL);",
+            EXPORT_META
+        ))?;
+        export.execute(
+            &format!(
+                "INSERT INTO {} (key, value) VALUES ('genesis', ?1)",
+                EXPORT_META
+            ),
+            [genesis.as_bytes()],
+        )?;
+        Ok(())
+    }
+
+    /// Copies the entries of the file exported to `path` into the store,
+    /// replacing entries with the same keys. Fails, copying nothing, if the
+    /// file wasn't exported from a datadir with the genesis `genesis` or holds
+    /// tables other than the store's and a mirror's. A mirror in the file
+    /// replaces the store's, rows and watermark together.
+    pub fn import<P: AsRef<Path>>(&self, path: P, genesis: H256) -> Result<()> {
+        let path = sql_path(path.as_ref())?;
+        let mut conn = self.conn.lock().unwrap();
+        conn.execute("ATTACH DATABASE ?1 AS import", [path])?;
+        let res = import_attached(&mut conn, genesis);
+        conn.execute("DETACH DATABASE import", [])?;
+        res
+    }
+
+    /// Labels `address` in the serving layers' output, if the store is the
+    /// client's labeler.
+    pub fn set_label(&self, address: Address, label: &str) -> Result<()> {
+        self.put(&label_key(address), label.as_bytes())
+    }
+}
+
+impl AddressLabeler for UserData {
+    fn label(&self, address: &Address) -> Option<String> {
+        // labels are cosmetic, so a failed read leaves the address unlabelled
+        let label = self.get(&label_key(*address)).ok()??;
+        String::from_utf8(label).ok()
+    }
+}
+
+/// Copies the file attached as `import` into the main database, in one
+/// transaction.
+fn import_attached(conn: &mut Connection, genesis: H256) -> Result<()> {
+    let exported: Option<Vec<u8>> = conn
+        .query_row(
+            &format!(
+                "SELECT value FROM import.{} WHERE key = 'genesis'",
+                EXPORT_META
+            ),
+            [],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(|_| format_err!("not an exported userdata file"))?;
+    match exported {
+        Some(exported) if exported == genesis.as_bytes() => {}
+        Some(exported) => {
+            return Err(format_err!(
+                "userdata was exported from a datadir with genesis 0x{}, not {:?}",
+                hex::encode(exported),
+                genesis
+            ))
+        }
+        None => return Err(format_err!("exported userdata has no genesis")),
+    }
+
+    let tx = conn.transaction()?;
+    let tables: Vec<String> = tx
+        .prepare(
+            "SELECT name FROM import.sqlite_master
+             WHERE type = 'table' AND name NOT LIKE 'sqlite_%'",
+        )?
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<Result<_, _>>()?;
+    // a mirror's rows only hold alongside its own watermark, so the store's
+    // are dropped rather than merged with the imported ones
+    if tables
+        .iter()
+        .any(|name| mirror::TABLE_NAMES.contains(&name.as_str()))
+    {
+        tx.execute_batch(mirror::SCHEMA)?;
+        for name in mirror::TABLE_NAMES {
+            tx.execute(&format!("DELETE FROM main.{}", name), [])?;
+        }
+    }
+    for name in tables {
+        match name.as_str() {
+            USERDATA_TABLE => {
+                tx.execute(
+                    "INSERT OR REPLACE INTO main.userdata (key, value)
+                     SELECT key, value FROM import.userdata",
+                    [],
+                )?;
+            }
+            EXPORT_META => {}
+            name if mirror::TABLE_NAMES.contains(&name) => {
+                tx.execute(
+                    &format!("INSERT INTO main.{0} SELECT * FROM import.{0}", name),
+                    [],
+                )?;
+            }
+            name => {
+                return Err(format_err!(
+                    "unknown table in exported userdata: {:?}",
+                    name
+                ))
+            }
+        }
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+fn sql_path(path: &Path) -> Result<&str> {
+    path.to_str()
+        .ok_or_else(|| format_err!("path is not valid utf-8: {:?}", path))
+}
+
+fn label_key(address: Address) -> Vec<u8> {
+    [LABEL_PREFIX, address.as_bytes()].concat()
+}
+
+/// Returns the least key greater than every key beginning with `prefix`, or
+/// `None` if there is none.
+fn prefix_end(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < u8::MAX {
+            end.push(last + 1);
+            return Some(end);
+        }
+    }
+    None
+}
+
+impl<E: EnvironmentKind> Client<E> {
+    /// Returns the client's userdata store, set with `ClientBuilder::userdata`.
+    ///
+    /// ```ignore
+    /// let userdata = client.userdata()?;
+    /// userdata.put(b"cursor/transfers", &num.to_be_bytes())?;
+    /// ```
+    pub fn userdata(&self) -> Result<&UserData> {
+        self.userdata
+            .as_ref()
+            .ok_or_else(|| format_err!("client was opened without a userdata store"))
+    }
+
+    /// Exports the client's userdata store to a new file at `path`. See
+    /// `UserData::export`.
+    pub fn export_userdata<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let genesis = self.reader()?.read_canonical_hash(BlockNum(0))?;
+        self.userdata()?.export(path, genesis)
+    }
+
+    /// Imports the file at `path`, exported from a client on the same chain,
+    /// into the client's userdata store. See `UserData::import`.
+    pub fn import_userdata<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let genesis = self.reader()?.read_canonical_hash(BlockNum(0))?;
+        self.userdata()?.import(path, genesis)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        mirror::{Mirror, MirrorReport},
+        test::{chain::ChainBuilder, TMP_DIR},
+    };
+
+    #[test]
+    fn test_userdata() -> Result<()> {
+        let userdata = UserData::open(":memory:")?;
+        assert_eq!(userdata.get(b"cursor")?, None);
+        userdata.put(b"cursor", &[1])?;
+        userdata.put(b"cursor", &[2])?;
+        assert_eq!(userdata.get(b"cursor")?, Some(vec![2]));
+
+        userdata.put(b"label/b", b"bob")?;
+        userdata.put(b"label/a", b"alice")?;
+        userdata.put(b"labels", b"")?;
+        assert_eq!(
+            userdata.scan(b"label/")?,
+            vec![
+                (b"label/a".to_vec(), b"alice".to_vec()),
+                (b"label/b".to_vec(), b"bob".to_vec()),
+            ]
+        );
+        assert_eq!(userdata.scan(b"")?.len(), 4);
+
+        assert!(userdata.delete(b"cursor")?);
+        assert!(!userdata.delete(b"cursor")?);
+        assert_eq!(prefix_end(&[0x01, 0xff]), Some(vec![0x02]));
+        assert_eq!(prefix_end(&[0xff]), None);
+
+        let who = Address::repeat_byte(0x11);
+        assert_eq!(userdata.label(&who), None);
+        userdata.set_label(who, "treasury")?;
+        assert_eq!(userdata.label(&who), Some("treasury".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_client_userdata() -> Result<()> {
+        let chain = ChainBuilder::new().block(|b| b).write(TMP_DIR.clone())?;
+        let db = Client::<mdbx::NoWriteMap>::open_new(chain.path.clone())?;
+        assert!(db.userdata().is_err());
+
+        let path = tempfile::Builder::new()
+            .suffix(".sqlite")
+            .tempfile_in(TMP_DIR.clone())?
+            .into_temp_path();
+        let db = Client::<mdbx::NoWriteMap>::builder()
+            .path(chain.path.clone())
+            .userdata(path.to_path_buf())
+            .build()?;
+        db.clone().userdata()?.put(b"cursor", &[7])?;
+        assert_eq!(db.userdata()?.get(b"cursor")?, Some(vec![7]));
+        // persisted for the next client
+        assert_eq!(UserData::open(&path)?.get(b"cursor")?, Some(vec![7]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_export_import() -> Result<()> {
+        let tmp = || -> Result<_> {
+            Ok(tempfile::Builder::new()
+                .suffix(".sqlite")
+                .tempfile_in(TMP_DIR.clone())?
+                .into_temp_path())
+        };
+        let write = |blocks| {
+            let mut chain = ChainBuilder::new();
+            for _ in 0..blocks {
+                chain = chain.block(|b| b);
+            }
+            chain.write(TMP_DIR.clone())
+        };
+        let (chain, other) = (write(2)?, write(3)?);
+        let db = Client::<mdbx::NoWriteMap>::open_new(chain.path.clone())?;
+        let genesis = H256::repeat_byte(0x01);
+        let (source, dest) = (tmp()?, tmp()?);
+        let userdata = UserData::open(&source)?;
+        userdata.put(b"cursor", &[7])?;
+        userdata.put(b"label/a", b"alice")?;
+        // a Mirror sharing the file is exported too
+        Mirror::open(&source)?.start(1).refresh(&db)?;
+        let exported = tmp()?;
+        userdata.export(&exported, genesis)?;
+
+        let imported = UserData::open(&dest)?;
+        // the mirror already in the store is replaced, not merged
+        let mut mirror = Mirror::open(&dest)?.start(1);
+        mirror.refresh(&Client::<mdbx::NoWriteMap>::open_new(other.path.clone())?)?;
+        imported.put(b"cursor", &[1])?;
+        imported.put(b"label/b", b"bob")?;
+        assert!(imported.import(&exported, H256::repeat_byte(0x02)).is_err());
+        assert_eq!(imported.get(b"cursor")?, Some(vec![1]));
+
+        imported.import(&exported, genesis)?;
+        assert_eq!(imported.get(b"cursor")?, Some(vec![7]));
+        assert_eq!(imported.scan(b"label/")?.len(), 2);
+        // the mirror comes along with its watermark, so it's up to date
+        assert_eq!(mirror.watermark()?, Some((2, chain.hash(1))));
+        let headers: i64 =
+            imported
+                .conn
+                .lock()
+                .unwrap()
+                .query_row("SELECT COUNT(*) FROM headers", [], |row| row.get(0))?;
+        assert_eq!(headers, 2);
+        assert_eq!(mirror.refresh(&db)?, MirrorReport::default());
+        // importing again replaces the same entries
+        imported.import(&exported, genesis)?;
+        assert_eq!(imported.scan(b"")?.len(), 3);
+        // only exports are accepted
+        assert!(imported.import(&tmp()?, genesis).is_err());
+
+        // and only with the tables an export can hold
+        Connection::open(&exported)?.execute_batch(
+            "CREATE TABLE evil (x);
+             CREATE TRIGGER evil_insert AFTER INSERT ON userdata BEGIN DELETE FROM userdata; END;",
+        )?;
+        imported.put(b"cursor", &[1])?;
+        assert!(imported.import(&exported, genesis).is_err());
+        assert_eq!(imported.get(b"cursor")?, Some(vec![1]));
+        Ok(())
+    }
+}
@@ -1,14 +1,50 @@
-use akula::{
-    kv::mdbx::MdbxEnvironment,
-    models::{Address, BlockHeader, Message, MessageWithSignature},
+use akula::{kv::mdbx::MdbxEnvironment, models::BlockHeader};
+use anyhow::{format_err, Result};
+use ethers::{
+    types::{Address, Block, Transaction, H256},
+    utils::keccak256,
 };
-use anyhow::Result;
-use ethers::types::H256;
-use std::path::PathBuf;
+use mdbx::{EnvironmentKind, TransactionKind};
+use std::{
+    fs::OpenOptions as FileOptions,
+    marker::PhantomData,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    builder::{OpenMode, OpenOptions},
+    client::{Client, Either, TxDecodeError},
+    codec::{BlockCast, BlockFields, MsgCast, StoredTx},
+    convert,
+    reader::Reader,
+    types::{HeaderKey, TxId},
+};
+
+const MDBX_DAT: &str = "mdbx.dat";
+const MDBX_LCK: &str = "mdbx.lck";
+// appended to the data file's name in no-subdir mode
+const MDBX_LCK_SUFFIX: &str = "-lck";
 
-pub fn open_db<E: mdbx::EnvironmentKind>(chaindata_dir: PathBuf) -> Result<MdbxEnvironment<E>> {
+pub fn open_db<E: mdbx::EnvironmentKind>(
+    chaindata_dir: PathBuf,
+    opts: &OpenOptions,
+) -> Result<MdbxEnvironment<E>> {
+    // Allow pointing directly at the data file rather than its directory
+    let no_sub_dir = chaindata_dir.is_file();
+    check_open_mode(&chaindata_dir, no_sub_dir, opts.mode)?;
+
+    let mut builder = mdbx::Environment::new();
+    builder.set_flags(mdbx::EnvironmentFlags {
+        mode: mdbx::Mode::ReadOnly,
+        no_sub_dir,
+        accede: opts.mode == OpenMode::LiveNode,
+        ..Default::default()
+    });
+    if let Some(max_readers) = opts.max_readers {
+        builder.set_max_readers(max_readers);
+    }
     MdbxEnvironment::<E>::open_ro(
-        mdbx::Environment::new(),
+        builder,
         &chaindata_dir,
         // opening read-only, so the size of the DatabaseChart determines max_dbs,
         // but the contents are discarded
@@ -16,140 +52,334 @@ pub fn open_db<E: mdbx::EnvironmentKind>(chaindata_dir: PathBuf) -> Result<MdbxE
     )
 }
 
-// https://github.com/akula-bft/akula/blob/a9aed09b31bb41c89832149bcad7248f7fcd70ca/src/models/account.rs#L47
-pub fn bytes_to_u64(buf: &[u8]) -> u64 {
-    let mut decoded = [0u8; 8];
-    for (i, b) in buf.iter().rev().enumerate() {
-        decoded[i] = *b;
+/// Checks that the environment at `path` exists and, if another process has
+/// it open, that its lock file can be shared.
+fn check_open_mode(path: &Path, no_sub_dir: bool, mode: OpenMode) -> Result<()> {
+    let (dat, lck) = if no_sub_dir {
+        let mut lck = path.as_os_str().to_owned();
+        lck.push(MDBX_LCK_SUFFIX);
+        (path.to_path_buf(), PathBuf::from(lck))
+    } else {
+        (path.join(MDBX_DAT), path.join(MDBX_LCK))
+    };
+    if !dat.exists() {
+        return Err(format_err!("no mdbx environment at {}", path.display()));
+    }
+    if !lck.exists() {
+        return Ok(());
     }
-    u64::from_le_bytes(decoded)
+    // Readers register themselves in the lock file, so it must be writable even
+    // when the environment is opened read-only.
+    FileOptions::new()
+        .read(true)
+        .write(true)
+        .open(&lck)
+        .map(|_| ())
+        .map_err(|e| {
+            format_err!(
+                "can't share lock file {} in {:?} mode: {}",
+                lck.display(),
+                mode,
+                e
+            )
+        })
 }
 
-/// Converts akula block and message data into ethers transaction data
-pub struct MsgCast<'a> {
-    pub msg: &'a MessageWithSignature,
-    pub src: Option<Address>,
+/// Projects a stored transaction, cast with its block context, into the
+/// transaction list of a block.
+pub(crate) trait TxProjection {
+    type Tx: Default;
+    /// Whether `project` needs the sender of each transaction.
+    const NEEDS_SENDERS: bool;
+
+    fn project(cast: &MsgCast<'_>) -> Result<Self::Tx>;
+
+    /// Projects a transaction decoded by a `CustomTxDecoder`.
+    fn project_custom(tx: Transaction) -> Self::Tx;
 }
-impl<'a> MsgCast<'a> {
-    pub fn new(msg: &'a MessageWithSignature) -> Self {
-        Self { msg, src: None }
+
+/// Projects each transaction to its hash, as in `eth_getBlockByNumber(n, false)`.
+pub(crate) struct TxHashes;
+
+impl TxProjection for TxHashes {
+    type Tx = H256;
+    const NEEDS_SENDERS: bool = false;
+
+    fn project(cast: &MsgCast<'_>) -> Result<H256> {
+        Ok(cast.msg.hash())
     }
 
-    pub fn maybe_signer(&mut self, src: Address) -> &mut Self {
-        if src != Default::default() {
-            self.src = Some(src)
+    fn project_custom(tx: Transaction) -> H256 {
+        tx.hash
+    }
+}
+
+/// Projects each transaction in full, as in `eth_getBlockByNumber(n, true)`.
+pub(crate) struct FullTxs;
+
+impl TxProjection for FullTxs {
+    type Tx = Transaction;
+    const NEEDS_SENDERS: bool = true;
+
+    fn project(cast: &MsgCast<'_>) -> Result<Transaction> {
+        cast.transaction()
+    }
+
+    fn project_custom(tx: Transaction) -> Transaction {
+        tx
+    }
+}
+
+/// Assembles a block from its header, body, transactions and senders. Every
+/// block read goes through here, so the tx count checks, sender recovery and
+/// system tx filtering are the same whichever projection is used.
+pub(crate) struct BlockAssembler<'c, E: EnvironmentKind, P> {
+    client: &'c Client<E>,
+    fields: BlockFields,
+    projection: PhantomData<P>,
+}
+
+impl<'c, E: EnvironmentKind, P: TxProjection> BlockAssembler<'c, E, P> {
+    pub fn new(client: &'c Client<E>) -> Self {
+        Self {
+            client,
+            fields: BlockFields::ALL,
+            projection: PhantomData,
         }
+    }
+
+    /// Only reads the parts of the block in `fields`.
+    pub fn fields(mut self, fields: BlockFields) -> Self {
+        self.fields = fields;
         self
     }
 
-    pub fn cast(
+    pub fn assemble<TX: TransactionKind>(
         &self,
-        block_num: akula::models::BlockNumber,
-        block_hash: H256,
-        idx: usize,
-    ) -> ethers::types::Transaction {
-        let from = if let Some(src) = self.src {
-            src
+        dbtx: &mut Reader<'_, TX, E>,
+        key: HeaderKey,
+    ) -> Result<Block<P::Tx>> {
+        self.assemble_with(dbtx, key, None)
+    }
+
+    /// Like `assemble`, but txs which fail to decode are left out of the block
+    /// and returned alongside it instead of failing the read.
+    pub fn assemble_lenient<TX: TransactionKind>(
+        &self,
+        dbtx: &mut Reader<'_, TX, E>,
+        key: HeaderKey,
+    ) -> Result<(Block<P::Tx>, Vec<TxDecodeError>)> {
+        let mut errors = vec![];
+        let block = self.assemble_with(dbtx, key, Some(&mut errors))?;
+        Ok((block, errors))
+    }
+
+    fn assemble_with<TX: TransactionKind>(
+        &self,
+        dbtx: &mut Reader<'_, TX, E>,
+        key: HeaderKey,
+        errors: Option<&mut Vec<TxDecodeError>>,
+    ) -> Result<Block<P::Tx>> {
+        let header = self.client.read_header_cached(dbtx, key)?;
+        let with_txs = self.fields.contains(BlockFields::TRANSACTIONS);
+        let (txs, ommer_hashes) = if self.fields.contains(BlockFields::OMMERS) {
+            let body = dbtx.read_body_for_storage(key)?;
+            let txs = if with_txs {
+                let tx_amt = body.tx_amount.try_into()?;
+                self.read_txs(dbtx, key, &header, body.base_tx_id.into(), tx_amt, errors)?
+            } else {
+                vec![]
+            };
+            (
+                txs,
+                body.uncles.iter().map(|header| header.hash()).collect(),
+            )
+        } else if with_txs {
+            let (base_tx_id, tx_amt) = dbtx.read_body_tx_range(key)?;
+            (
+                self.read_txs(dbtx, key, &header, base_tx_id, tx_amt, errors)?,
+                vec![],
+            )
         } else {
-            self.msg.recover_sender().expect("bad sig")
+            (vec![], vec![])
         };
-        ethers::types::Transaction {
-            hash: self.msg.hash(),
-            nonce: self.msg.nonce().into(),
-            block_hash: Some(block_hash),
-            block_number: Some((*block_num).into()),
-            transaction_index: Some(idx.into()),
-            from,
-            to: self.msg.action().into_address(),
-            value: self.msg.value().to_be_bytes().into(),
-            gas_price: self.gas_price(),
-            gas: self.msg.gas_limit().into(),
-            input: self.msg.input().clone().into(),
-            v: self.msg.v().into(),
-            r: self.msg.r().to_fixed_bytes().into(),
-            s: self.msg.s().to_fixed_bytes().into(),
-            transaction_type: self.tx_type(),
-            access_list: self.access_list(),
-            chain_id: self.msg.chain_id().map(|id| (*id).into()),
-
-            //TODO: should these be None for legacy txs?
-            max_priority_fee_per_gas: Some(
-                self.msg.max_priority_fee_per_gas().to_be_bytes().into(),
-            ),
-            max_fee_per_gas: Some(self.msg.max_fee_per_gas().to_be_bytes().into()),
-        }
+        Ok(BlockCast(&header).project(self.fields, txs, key.num, key.hash, ommer_hashes))
     }
 
-    pub fn gas_price(&self) -> Option<ethers::types::U256> {
-        match self.msg.message {
-            Message::Legacy { gas_price, .. } | Message::EIP2930 { gas_price, .. } => {
-                Some(gas_price.to_be_bytes().into())
-            }
-            _ => None,
+    fn read_txs<TX: TransactionKind>(
+        &self,
+        dbtx: &mut Reader<'_, TX, E>,
+        key: HeaderKey,
+        header: &BlockHeader,
+        base_tx_id: TxId,
+        tx_amt: usize,
+        errors: Option<&mut Vec<TxDecodeError>>,
+    ) -> Result<Vec<P::Tx>> {
+        let options = self.client.options();
+        self.client
+            .verify_transactions(dbtx, key, header, base_tx_id, tx_amt)?;
+        if errors.is_some() || !options.custom_txs.is_empty() {
+            return self.read_txs_each(dbtx, key, header, base_tx_id, tx_amt, errors);
+        }
+        let msgs = match dbtx
+            .stream_transactions(base_tx_id)?
+            .take(tx_amt)
+            .collect::<Result<Vec<_>>>()
+        {
+            Ok(msgs) => msgs,
+            // a tx of a type only the custom decoding handles
+            Err(_) => return self.read_txs_each(dbtx, key, header, base_tx_id, tx_amt, errors),
+        };
+        if msgs.len() != tx_amt {
+            return Err(format_err!(
+                "Failed to get some txs in block {}. Expected: {}. Got {}",
+                key.num,
+                tx_amt,
+                msgs.len()
+            ));
         }
+
+        let senders = if P::NEEDS_SENDERS || options.system_txs.is_filtering() {
+            // We may not have all signers in the db, in which case we get zero
+            // addresses and have to recover the signatures
+            self.client.read_block_senders(dbtx, key, &msgs)?
+        } else {
+            vec![Address::zero(); tx_amt]
+        };
+
+        let base_fee = header.base_fee_per_gas.map(convert::u256);
+        let txs = msgs
+            .iter()
+            .zip(senders)
+            .enumerate()
+            .filter(|(_, (_, sender))| !options.system_txs.excludes(sender))
+            .map(|(idx, (msg, sender))| {
+                P::project(
+                    MsgCast::new(msg)
+                        .maybe_signer(sender)
+                        .block(key.num, key.hash)
+                        .index(idx)
+                        .base_fee(base_fee),
+                )
+            })
+            .collect::<Result<_>>()?;
+        Ok(txs)
     }
 
-    fn tx_type(&self) -> Option<ethers::types::U64> {
-        match self.msg.message {
-            Message::EIP2930 { .. } => Some(1.into()),
-            Message::EIP1559 { .. } => Some(2.into()),
-            _ => None,
+    /// Like `read_txs`, but decodes each tx on its own: the txs of a custom
+    /// or unknown type are cast by their decoder, and the rest as usual. With
+    /// `errors`, txs which fail to decode are recorded there and skipped.
+    fn read_txs_each<TX: TransactionKind>(
+        &self,
+        dbtx: &mut Reader<'_, TX, E>,
+        key: HeaderKey,
+        header: &BlockHeader,
+        base_tx_id: TxId,
+        tx_amt: usize,
+        mut errors: Option<&mut Vec<TxDecodeError>>,
+    ) -> Result<Vec<P::Tx>> {
+        let options = self.client.options();
+        let mut txs = Vec::with_capacity(tx_amt);
+        let mut msgs = vec![];
+        for (idx, raw) in dbtx
+            .read_raw_transactions(base_tx_id, tx_amt)?
+            .iter()
+            .enumerate()
+        {
+            match options.custom_txs.decode(raw, key.num, key.hash, idx) {
+                Ok(StoredTx::Msg(msg)) => {
+                    msgs.push(msg);
+                    txs.push(Either::Left(idx));
+                }
+                Ok(StoredTx::Custom(tx)) => txs.push(Either::Right(tx)),
+                Err(e) => match errors.as_deref_mut() {
+                    Some(errors) => errors.push(TxDecodeError {
+                        index: idx,
+                        hash: keccak256(raw).into(),
+                        error: e.to_string(),
+                    }),
+                    None => {
+                        return Err(format_err!(
+                            "Failed to decode tx {} in block {}: {}",
+                            idx,
+                            key.num,
+                            e
+                        ))
+                    }
+                },
+            }
         }
-    }
 
-    fn access_list(&self) -> Option<ethers::types::transaction::eip2930::AccessList> {
-        match &self.msg.message {
-            Message::EIP2930 { access_list, .. } | Message::EIP1559 { access_list, .. } => Some(
-                access_list
-                    .iter()
-                    .map(|it| ethers::types::transaction::eip2930::AccessListItem {
-                        address: it.address,
-                        storage_keys: it.slots.clone(),
-                    })
-                    .collect::<Vec<_>>()
-                    .into(),
-            ),
-            _ => None,
+        let senders = if P::NEEDS_SENDERS || options.system_txs.is_filtering() {
+            let stored = dbtx.read_senders(key)?;
+            // a decoder may leave the sender to the one stored
+            for tx in txs.iter_mut() {
+                if let Either::Right(tx) = tx {
+                    let idx = tx.transaction_index.unwrap_or_default().as_usize();
+                    if tx.from.is_zero() {
+                        tx.from = stored.get(idx).copied().unwrap_or_default();
+                    }
+                }
+            }
+            // the stored senders of the txs cast as usual
+            let known = txs
+                .iter()
+                .filter_map(|tx| match tx {
+                    Either::Left(idx) => Some(stored.get(*idx).copied().unwrap_or_default()),
+                    Either::Right(_) => None,
+                })
+                .collect::<Vec<_>>();
+            self.client.recover_block_senders(key.num, &msgs, &known)?
+        } else {
+            vec![Address::zero(); msgs.len()]
+        };
+
+        let base_fee = header.base_fee_per_gas.map(convert::u256);
+        let mut msgs = msgs.iter().zip(senders);
+        let mut out = Vec::with_capacity(tx_amt);
+        for tx in txs {
+            match tx {
+                Either::Left(idx) => {
+                    let (msg, sender) = msgs.next().expect("a msg per known tx");
+                    if !options.system_txs.excludes(&sender) {
+                        out.push(P::project(
+                            MsgCast::new(msg)
+                                .maybe_signer(sender)
+                                .block(key.num, key.hash)
+                                .index(idx)
+                                .base_fee(base_fee),
+                        )?);
+                    }
+                }
+                Either::Right(tx) => {
+                    if !options.system_txs.excludes(&tx.from) {
+                        out.push(P::project_custom(tx));
+                    }
+                }
+            }
         }
+        Ok(out)
     }
 }
 
-/// Converts akula block data into ethers block data
-pub struct BlockCast<'a>(pub &'a BlockHeader);
-impl<'a> BlockCast<'a> {
-    pub fn cast<TX: std::default::Default>(
-        &self,
-        txs: Vec<TX>,
-        block_num: akula::models::BlockNumber,
-        block_hash: H256,
-        ommer_hashes: Vec<H256>,
-    ) -> ethers::types::Block<TX> {
-        ethers::types::Block {
-            hash: Some(block_hash),
-            parent_hash: self.0.parent_hash,
-            uncles_hash: self.0.ommers_hash,
-            author: self.0.beneficiary,
-            state_root: self.0.state_root,
-            transactions_root: self.0.transactions_root,
-            receipts_root: self.0.receipts_root,
-            number: Some(block_num.0.into()),
-            gas_used: self.0.gas_used.into(),
-            gas_limit: self.0.gas_limit.into(),
-            extra_data: self.0.extra_data.clone().into(),
-            logs_bloom: Some(self.0.logs_bloom),
-            timestamp: self.0.timestamp.into(),
-            difficulty: self.0.difficulty.to_be_bytes().into(),
-            total_difficulty: None, // TODO
-            uncles: ommer_hashes,
-            transactions: txs,
-            mix_hash: Some(self.0.mix_hash),
-            nonce: Some(self.0.nonce.to_fixed_bytes().into()),
-            base_fee_per_gas: self.0.base_fee_per_gas.map(|f| f.to_be_bytes().into()),
-
-            // TODO:
-            // seal_fields
-            //size
-            ..Default::default()
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::TMP_DIR;
+    use std::fs;
+
+    #[test]
+    fn test_check_open_mode_no_sub_dir() -> Result<()> {
+        let dir = tempfile::tempdir_in(TMP_DIR.clone())?;
+        let dat = dir.path().join("chaindata.db");
+        assert!(check_open_mode(&dat, true, OpenMode::LiveNode).is_err());
+        fs::write(&dat, b"")?;
+        check_open_mode(&dat, true, OpenMode::LiveNode)?;
+
+        // a lock file which can't be opened for writing, even by root
+        fs::create_dir(dir.path().join("chaindata.db-lck"))?;
+        let err = check_open_mode(&dat, true, OpenMode::LiveNode).unwrap_err();
+        assert!(err.to_string().contains("chaindata.db-lck"));
+        Ok(())
     }
 }
@@ -0,0 +1,111 @@
+use anyhow::Result;
+use mdbx::EnvironmentKind;
+use std::ops::RangeBounds;
+
+use crate::{budget::CancelToken, client::Client, tables, types::BlockNum};
+
+/// Blocks warmed per read transaction, so that warming a long range doesn't
+/// hold one transaction open against a live node for the whole run.
+const CHUNK_BLOCKS: u64 = 1024;
+
+/// The tables `Client::warm` can pre-touch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WarmTable {
+    Headers,
+    CanonicalHashes,
+    Bodies,
+    /// The current state of the accounts changed in the range.
+    State,
+}
+
+impl WarmTable {
+    /// The tables read by most requests near the head.
+    pub const HOT: [Self; 4] = [
+        Self::Headers,
+        Self::CanonicalHashes,
+        Self::Bodies,
+        Self::State,
+    ];
+}
+
+/// What `Client::warm` touched.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WarmReport {
+    /// The number of blocks of the range warmed in every table.
+    pub blocks: u64,
+    /// The number of bytes of keys and values read.
+    pub bytes: u64,
+    /// Whether warming stopped early because it was cancelled.
+    pub cancelled: bool,
+}
+
+impl<E: EnvironmentKind> Client<E> {
+    /// Reads the `which` tables over the blocks in `range` so that their pages are
+    /// resident before serving traffic, rather than being faulted in by the
+    /// first requests. An unbounded end is the current head.
+    ///
+    /// ```ignore
+    /// let head = client.get_block_number()?.as_u64();
+    /// let report = client.warm(&WarmTable::HOT, head.saturating_sub(10_000).., &CancelToken::new())?;
+    /// ```
+    ///
+    /// `cancel` is checked between tables and between chunks of blocks. A
+    /// cancelled warm returns what it touched so far.
+    pub fn warm<R: RangeBounds<u64>>(
+        &self,
+        which: &[WarmTable],
+        range: R,
+        cancel: &CancelToken,
+    ) -> Result<WarmReport> {
+        let (from, to) = match self.resolve_range(range)? {
+            Some(bounds) => bounds,
+            None => return Ok(WarmReport::default()),
+        };
+
+        let _permit = self.admit()?;
+        let mut report = WarmReport::default();
+        let mut start = from;
+        while start <= to {
+            let end = to.min(start.saturating_add(CHUNK_BLOCKS - 1));
+            let lo = start.to_be_bytes().to_vec();
+            // the exclusive upper bound of the chunk's block number keys
+            let hi = match end.checked_add(1) {
+                Some(n) => n.to_be_bytes().to_vec(),
+                None => vec![0xff; 9],
+            };
+
+            let mut dbtx = self.reader()?;
+            for table in which {
+                if cancel.is_cancelled() {
+                    report.cancelled = true;
+                    return Ok(report);
+                }
+                report.bytes += match table {
+                    WarmTable::Headers => {
+                        dbtx.touch_range(tables::Header.erased(), lo.clone(), &hi)?
+                    }
+                    WarmTable::CanonicalHashes => {
+                        dbtx.touch_range(tables::CanonicalHeader.erased(), lo.clone(), &hi)?
+                    }
+                    WarmTable::Bodies => {
+                        dbtx.touch_range(tables::BlockBody.erased(), lo.clone(), &hi)?
+                    }
+                    WarmTable::State => {
+                        let mut bytes = 0;
+                        for who in dbtx.read_changed_accounts(BlockNum(start), BlockNum(end))? {
+                            bytes += dbtx
+                                .touch_key(tables::PlainState.erased(), who.as_bytes().to_vec())?;
+                        }
+                        bytes
+                    }
+                };
+            }
+            report.blocks += end - start + 1;
+            match end.checked_add(1) {
+                Some(next) => start = next,
+                None => break,
+            }
+        }
+        Ok(report)
+    }
+}
@@ -0,0 +1,71 @@
+use anyhow::Result;
+use ethers::types::{Address, BlockNumber, U256, U64};
+use mdbx::EnvironmentKind;
+
+use crate::{
+    client::{res_block_number, Client},
+    tables,
+    types::{BlockNum, HeaderKey},
+};
+
+const WEI_PER_GWEI: u64 = 1_000_000_000;
+const WITHDRAWAL_TABLES: &[&str] = &[
+    tables::CanonicalHeader::const_db_name(),
+    tables::BlockBody::const_db_name(),
+];
+
+/// The beacon chain withdrawals credited to an address over the blocks
+/// `from..=to`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WithdrawalTotals {
+    pub address: Address,
+    pub from: U64,
+    pub to: U64,
+    /// The number of withdrawals to the address.
+    pub count: u64,
+    /// The total amount withdrawn to the address, in wei.
+    pub amount: U256,
+}
+
+impl<E: EnvironmentKind> Client<E> {
+    /// Sums the withdrawals to `address` included in the blocks `from..=to`.
+    /// Every block body in the range is read, so the scan is bounded by the
+    /// client's read budget.
+    pub fn get_withdrawal_totals<T: Into<BlockNumber>>(
+        &self,
+        address: Address,
+        from: T,
+        to: T,
+    ) -> Result<WithdrawalTotals> {
+        let (from, to): (BlockNumber, BlockNumber) = (from.into(), to.into());
+        let _permit = self.admit()?;
+        let _slow = self.slow_read("get_withdrawal_totals", WITHDRAWAL_TABLES, || {
+            format!("{:?} {:?}..={:?}", address, from, to)
+        });
+        let mut dbtx = self.reader()?;
+        let from = res_block_number(&mut dbtx, from)?;
+        let to = res_block_number(&mut dbtx, to)?;
+        anyhow::ensure!(from <= to, "invalid block range: {} > {}", from, to);
+
+        let mut totals = WithdrawalTotals {
+            address,
+            from: from.into(),
+            to: to.into(),
+            ..Default::default()
+        };
+        let mut gwei = U256::zero();
+        for num in *from..=*to {
+            dbtx.budget().check()?;
+            let num = BlockNum(num);
+            let hash = dbtx.read_canonical_hash(num)?;
+            for w in dbtx.read_withdrawals(HeaderKey::new(num, hash))? {
+                if w.address == address {
+                    totals.count += 1;
+                    gwei += w.amount.into();
+                }
+            }
+        }
+        totals.amount = gwei * WEI_PER_GWEI;
+        Ok(totals)
+    }
+}